@@ -0,0 +1,35 @@
+use std::str::FromStr;
+use strum_macros::Display;
+
+/// The minimum security level of the packets accepted from the collectd network plugin
+#[derive(
+    Debug, Display, Clone, Copy, Eq, PartialEq, doku::Document, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum CollectdSecurityLevel {
+    None,
+    Sign,
+    Encrypt,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Failed to parse flag: {input}. Supported values are: 'none', 'sign' or 'encrypt'")]
+pub struct InvalidCollectdSecurityLevel {
+    input: String,
+}
+
+impl FromStr for CollectdSecurityLevel {
+    type Err = InvalidCollectdSecurityLevel;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "none" => Ok(CollectdSecurityLevel::None),
+            "sign" => Ok(CollectdSecurityLevel::Sign),
+            "encrypt" => Ok(CollectdSecurityLevel::Encrypt),
+            _ => Err(InvalidCollectdSecurityLevel {
+                input: input.to_string(),
+            }),
+        }
+    }
+}
@@ -98,6 +98,13 @@ fn connect_url_from_string_should_return_err_provided_address_with_port_and_http
     assert!(ConnectUrl::try_from(input).is_err());
 }
 
+#[test]
+fn connect_url_from_string_should_return_err_provided_websocket_url() {
+    for input in ["wss://test.address.com/mqtt", "ws://test.address.com"] {
+        assert!(ConnectUrl::try_from(input).is_err());
+    }
+}
+
 #[test]
 fn connect_url_from_string_should_return_string() -> Result<(), crate::TEdgeConfigError> {
     let input = "test.address.com";
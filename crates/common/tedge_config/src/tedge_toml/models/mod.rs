@@ -1,8 +1,10 @@
 pub mod apt_config;
 pub mod auth_method;
 pub mod auto;
+pub mod broker_url;
 pub mod c8y;
 pub mod c8y_software_management;
+pub mod collectd;
 pub mod connect_url;
 pub mod count_per_group;
 pub mod cryptoki;
@@ -29,8 +31,10 @@ pub const MQTT_TLS_PORT: u16 = 8883;
 
 pub use self::apt_config::*;
 pub use self::auto::*;
+pub use self::broker_url::BrokerUrl;
 pub use self::c8y::*;
 pub use self::c8y_software_management::*;
+pub use self::collectd::*;
 pub use self::connect_url::*;
 pub use self::cryptoki::Cryptoki;
 pub use self::flag::*;
@@ -16,6 +16,7 @@ use super::models::AptConfig;
 use super::models::AutoFlag;
 use super::models::AutoLogUpload;
 use super::models::CloudType;
+use super::models::CollectdSecurityLevel;
 use super::models::ConnectUrl;
 use super::models::Cryptoki;
 use super::models::HostPort;
@@ -786,6 +787,11 @@ define_tedge_config! {
             #[tedge_config(example = "60s", default(from_str = "60s"))]
             keepalive_interval: SecondsOrHumanTime,
 
+            /// Tunnel the bridge MQTT connection through a secure WebSocket to `wss://<c8y.mqtt host>:443/mqtt`,
+            /// for networks where only outbound HTTPS is allowed
+            #[tedge_config(note = "This is only supported by the built-in bridge, and not together with `c8y.mqtt_service.enabled`.")]
+            #[tedge_config(example = "true", default(value = false))]
+            websocket: bool,
         },
 
         entity_store: {
@@ -915,6 +921,11 @@ define_tedge_config! {
             #[tedge_config(default(variable = "TimeFormat::Unix"))]
             timestamp_format: TimeFormat,
 
+            /// The local MQTT topic on which the cloud-to-device messages received from Azure IoT Hub are republished
+            #[tedge_config(note = "When not set, the cloud-to-device messages are only published on `az/messages/devicebound/#`.")]
+            #[tedge_config(example = "c2d/messages")]
+            c2d_topic: String,
+
             mqtt: {
                 /// The maximum message payload size that can be mapped to the cloud via MQTT
                 #[tedge_config(example = "262144", default(function = "az_mqtt_payload_limit"))]
@@ -932,6 +943,33 @@ define_tedge_config! {
             /// The amount of time after which the bridge should send a ping if no other traffic has occurred
             #[tedge_config(example = "60s", default(from_str = "60s"))]
             keepalive_interval: SecondsOrHumanTime,
+
+            /// Tunnel the bridge MQTT connection through a secure WebSocket to `wss://<az.url>:443/$iothub/websocket`,
+            /// for networks where only outbound HTTPS is allowed
+            #[tedge_config(note = "This is only supported by the built-in bridge.")]
+            #[tedge_config(example = "true", default(value = false))]
+            websocket: bool,
+        },
+
+        dps: {
+            /// Endpoint of the Azure IoT Hub Device Provisioning Service, with optional port
+            #[tedge_config(example = "global.azure-devices-provisioning.net")]
+            #[tedge_config(default(from_str = "global.azure-devices-provisioning.net"))]
+            url: HostPort<MQTT_TLS_PORT>,
+
+            /// The ID scope of the Device Provisioning Service instance used by `tedge connect az --provision`
+            #[tedge_config(example = "0ne00ABC123")]
+            id_scope: String,
+
+            /// The registration ID of the device in the Device Provisioning Service
+            #[tedge_config(note = "When not set, the device id is used.")]
+            #[tedge_config(example = "Raspberrypi-4d18303a-6d3a-11eb-b1a6-175f6bb72665")]
+            registration_id: String,
+
+            /// The base64-encoded symmetric key of the device, for an enrollment using symmetric key attestation
+            #[tedge_config(note = "When not set, the device is registered using its X.509 certificate.")]
+            #[tedge_config(example = "czZkMXlNUjV6b3NSaWJrNlk2S1NmMnBoVG9HMThXVUo=")]
+            symmetric_key: String,
         },
 
         /// Set of MQTT topics the Azure IoT mapper should subscribe to
@@ -957,6 +995,7 @@ define_tedge_config! {
         cloud_type: CloudType,
 
         /// Endpoint URL of AWS IoT tenant
+        #[tedge_config(note = "MQTT over WebSocket is not supported for AWS IoT, which only accepts device certificates over MQTT/TLS, so `wss://` URLs are rejected.")]
         #[tedge_config(example = "your-endpoint.amazonaws.com")]
         url: ConnectUrl,
 
@@ -1035,6 +1074,20 @@ define_tedge_config! {
             keepalive_interval: SecondsOrHumanTime,
         },
 
+        provisioning: {
+            /// The name of the AWS IoT fleet provisioning template used by `tedge connect aws --provision`
+            #[tedge_config(example = "tedge-fleet-template")]
+            template: String,
+
+            /// Path where the claim certificate used to request the device certificate is stored
+            #[tedge_config(example = "/etc/tedge/device-certs/aws-claim-certificate.pem")]
+            claim_cert_path: AbsolutePath,
+
+            /// Path where the private key of the claim certificate is stored
+            #[tedge_config(example = "/etc/tedge/device-certs/aws-claim-private-key.pem")]
+            claim_key_path: AbsolutePath,
+        },
+
         /// Set of MQTT topics the AWS IoT mapper should subscribe to
         #[tedge_config(example = "te/+/+/+/+/a/+,te/+/+/+/+/m/+,te/+/+/+/+/e/+")]
         #[tedge_config(default(value = "te/+/+/+/+/m/+,te/+/+/+/+/e/+,te/+/+/+/+/a/+,te/+/+/+/+/status/health"))]
@@ -1169,6 +1222,27 @@ define_tedge_config! {
                 #[tedge_config(example = "5m", default(from_str = "5m"))]
                 reset_window: SecondsOrHumanTime,
             },
+
+            queue: {
+                /// Persist bridged messages to disk while the destination broker is unreachable,
+                /// and replay them in order once the connection is re-established
+                #[tedge_config(example = "true", default(value = false))]
+                #[tedge_config(note = "The queues are stored under `data.path`. After changing this value, restart the mapper to apply the changes")]
+                enable: bool,
+
+                /// The maximum number of messages held in the queue of each bridge direction
+                #[tedge_config(example = "10000", default(value = 10000u32))]
+                #[tedge_config(note = "When set to 0, no message is queued and the messages received while the destination broker is unreachable are dropped")]
+                max_messages: u32,
+
+                /// The maximum size in bytes of the messages held in the queue of each bridge direction
+                #[tedge_config(example = "10485760", default(value = 10485760u32))]
+                max_size: u32,
+
+                /// How long a queued message is kept before being discarded without being forwarded
+                #[tedge_config(example = "1h", default(from_str = "1d"))]
+                max_age: SecondsOrHumanTime,
+            },
         },
     },
 
@@ -1217,6 +1291,16 @@ define_tedge_config! {
         /// trusted when checking incoming client certificates for the File Transfer Service
         #[tedge_config(example = "/etc/ssl/certs")]
         ca_path: AbsolutePath,
+
+        access: {
+            /// Restrict the clients of the agent HTTP API to the entities and files granted by the access policies
+            #[tedge_config(example = "true", default(value = false))]
+            enable: bool,
+
+            /// The file defining the access policies of the agent HTTP API clients, identified by certificate or token
+            #[tedge_config(example = "/etc/tedge/http-access.toml", default(function = "default_http_access_policy_file"))]
+            policy_file: AbsolutePath,
+        },
     },
 
     agent: {
@@ -1251,9 +1335,50 @@ define_tedge_config! {
             /// When set to `true`, the entity store is re-created from the retained entity registration MQTT messages.
             #[tedge_config(example = "true", default(value = false), deprecated_key = "c8y.entity_store.clean_start")]
             clean_start: bool,
+
+            child_device: {
+                /// The delay without any activity after which a child device is marked stale
+                #[tedge_config(note = "When not set, child devices are never marked stale.")]
+                #[tedge_config(example = "7d")]
+                stale_after: SecondsOrHumanTime,
+
+                /// The delay after which a stale child device is deregistered
+                #[tedge_config(note = "When not set, stale child devices are never deregistered.")]
+                #[tedge_config(example = "30d")]
+                remove_after: SecondsOrHumanTime,
+            },
+
+            service: {
+                /// The delay without any activity after which a service is marked stale
+                #[tedge_config(note = "When not set, services are never marked stale.")]
+                #[tedge_config(example = "7d")]
+                stale_after: SecondsOrHumanTime,
+
+                /// The delay after which a stale service is deregistered
+                #[tedge_config(note = "When not set, stale services are never deregistered.")]
+                #[tedge_config(example = "30d")]
+                remove_after: SecondsOrHumanTime,
+            },
         },
 
+        maintenance: {
+            /// The maintenance window, out of which the execution of the maintenance operations is deferred.
+            /// This is a cron-like expression (minute hour day-of-month month day-of-week), using the local time zone,
+            /// that matches all the minutes of the window.
+            #[tedge_config(note = "When not set, the operations are executed straight away, unless given a `scheduled_at` time.")]
+            #[tedge_config(example = "* 2-4 * * 6,0")]
+            window: String,
+
+            /// The operations which execution is deferred till the maintenance window
+            #[tedge_config(example = "software_update,firmware_update,restart", default(from_str = "software_update,firmware_update,restart"))]
+            operations: TemplatesSet,
+        },
 
+        command_history: {
+            /// The maximum number of completed commands kept in the command history of the agent
+            #[tedge_config(example = "1000", default(value = 1000u32))]
+            max_commands: u32,
+        },
     },
 
     software: {
@@ -1273,7 +1398,24 @@ define_tedge_config! {
             /// The filtering criterion, in form of regex, that is used to filter out packages from the output list
             #[tedge_config(example = "^(glibc|lib|kernel-|iptables-module).*")]
             exclude: String,
-        }
+        },
+
+        rollback: {
+            /// Enable the automatic rollback of software updates.
+            /// When enabled, the software modules and the configuration files impacted by an update
+            /// are captured before the update and restored if the update fails.
+            #[tedge_config(example = "true", default(value = false))]
+            enable: bool,
+
+            /// Command run after a software update to check the health of the device.
+            /// The update is rolled back if this command fails.
+            #[tedge_config(example = "/usr/bin/check-device-health.sh")]
+            health_check: String,
+
+            /// Configuration files to be restored along the software modules on rollback
+            #[tedge_config(example = "/etc/tedge/tedge.toml,/etc/tedge/mosquitto-conf/tedge-mosquitto.conf", default(function = "TemplatesSet::default"))]
+            files: TemplatesSet,
+        },
     },
 
     run: {
@@ -1382,6 +1524,34 @@ define_tedge_config! {
         no_proxy: String,
     },
 
+    collectd: {
+        network: {
+            /// Enable the reception by tedge-mapper-collectd of the measurements sent over UDP by the collectd network plugin
+            #[tedge_config(example = "true", default(value = false))]
+            enable: bool,
+
+            bind: {
+                /// The address on which tedge-mapper-collectd listens for collectd network packets
+                #[tedge_config(default(variable = "Ipv4Addr::LOCALHOST"))]
+                #[tedge_config(example = "127.0.0.1", example = "0.0.0.0")]
+                address: IpAddr,
+
+                /// The UDP port on which tedge-mapper-collectd listens for collectd network packets
+                #[tedge_config(example = "25826", default(value = 25826u16))]
+                port: u16,
+            },
+
+            /// The minimum security level of the collectd network packets accepted by tedge-mapper-collectd
+            #[tedge_config(note = "With `sign`, only signed or encrypted measurements are accepted. With `encrypt`, only encrypted measurements are accepted.")]
+            #[tedge_config(example = "none", example = "sign", example = "encrypt", default(variable = "CollectdSecurityLevel::None"))]
+            security_level: CollectdSecurityLevel,
+
+            /// The file listing the users allowed to sign or encrypt collectd network packets, with one `user: password` line per user
+            #[tedge_config(example = "/etc/tedge/collectd-passwd")]
+            auth_file: AbsolutePath,
+        },
+    },
+
     diag: {
         /// The directories where diagnostic plugins are stored
         #[tedge_config(example = "/usr/share/diag-plugins,/etc/tedge/diag-plugins", default(value = "/usr/share/tedge/diag-plugins"))]
@@ -1411,6 +1581,17 @@ define_tedge_config! {
             stack_size: u32,
         },
 
+        wasm: {
+            /// The maximum number of bytes of linear memory allocated to each WebAssembly step
+            #[tedge_config(example = "16777216", default(value = 16777216u32))]
+            memory_size: u32,
+
+            /// The amount of fuel given to a WebAssembly step for each call, bounding the number of instructions executed
+            #[tedge_config(example = "100000000", default(value = 100000000u32))]
+            #[tedge_config(note = "WebAssembly steps are only supported when thin-edge is built with the `wasm` feature.")]
+            fuel: u32,
+        },
+
         stats: {
             /// The interval in seconds between flow statistics dumps
             #[tedge_config(example = "1h", default(from_str = "1h"))]
@@ -1430,6 +1611,24 @@ define_tedge_config! {
             on_startup: bool,
         },
 
+        context: {
+            /// The interval between two snapshots of the flow context values persisted across mapper restarts
+            #[tedge_config(example = "1m", default(from_str = "1m"))]
+            #[tedge_config(note = "The values are stored under `data.path`.")]
+            snapshot_interval: SecondsOrHumanTime,
+
+            /// The maximum number of flow context values persisted by a mapper, the least recently updated values being dropped first
+            #[tedge_config(example = "10000", default(value = 10000u32))]
+            max_entries: u32,
+        },
+
+        errors: {
+            /// The maximum number of failed messages kept by a mapper for replay, the oldest being dropped first
+            #[tedge_config(example = "1000", default(value = 1000u32))]
+            #[tedge_config(note = "The messages are stored under `data.path`. Set to 0 to not keep any failed message.")]
+            max_entries: u32,
+        },
+
         params: {
             /// If set and params.toml exists in a flow, keeps the params.toml when removing a flow; otherwise, the entire flow directory is deleted
             #[tedge_config(default(value = false))]
@@ -1624,6 +1823,9 @@ fn az_device_id(
     }
 }
 
+/// The common name of the certificates created by AWS IoT, as with fleet provisioning
+const AWS_IOT_CERTIFICATE_CN: &str = "AWS IoT Certificate";
+
 fn aws_device_id(
     aws_device: &TEdgeConfigReaderAwsDevice,
     dto_value: &OptionalConfig<String>,
@@ -1632,6 +1834,10 @@ fn aws_device_id(
         device_id_from_cert(&aws_device.cert_path),
         dto_value.or_none(),
     ) {
+        // A certificate created by AWS IoT is not named after the device
+        (Ok(common_name), Some(dto_value)) if common_name == AWS_IOT_CERTIFICATE_CN => {
+            Ok(dto_value.to_string())
+        }
         (Ok(common_name), _) => Ok(common_name),
         (Err(_), Some(dto_value)) => Ok(dto_value.to_string()),
         (Err(err), None) => Err(err),
@@ -1664,6 +1870,14 @@ fn default_root_cert_path(_location: &TEdgeConfigLocation) -> AbsolutePath {
     AbsolutePath::try_new(DEFAULT_ROOT_CERT_PATH).unwrap()
 }
 
+fn default_http_access_policy_file(location: &TEdgeConfigLocation) -> AbsolutePath {
+    location
+        .tedge_config_root_path()
+        .join("http-access.toml")
+        .try_into()
+        .unwrap()
+}
+
 fn default_device_key(location: &TEdgeConfigLocation) -> AbsolutePath {
     location
         .tedge_config_root_path()
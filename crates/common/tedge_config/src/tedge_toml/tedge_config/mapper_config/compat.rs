@@ -139,6 +139,7 @@ impl FromCloudConfig for C8yMapperSpecificConfig {
                 include: C8yBridgeIncludeConfig {
                     local_cleansession: c8y.bridge.include.local_cleansession,
                 },
+                websocket: c8y.bridge.websocket,
             },
         }
     }
@@ -176,6 +177,16 @@ impl FromCloudConfig for AzMapperSpecificConfig {
             mapper: AzCloudMapperConfig {
                 timestamp: az.mapper.timestamp,
                 timestamp_format: az.mapper.timestamp_format,
+                c2d_topic: az.mapper.c2d_topic.or_none().cloned(),
+            },
+            bridge: AzBridgeConfig {
+                websocket: az.bridge.websocket,
+            },
+            dps: AzDpsConfig {
+                url: az.dps.url.clone(),
+                id_scope: az.dps.id_scope.clone(),
+                registration_id: az.dps.registration_id.clone(),
+                symmetric_key: az.dps.symmetric_key.clone(),
             },
         }
     }
@@ -214,6 +225,11 @@ impl FromCloudConfig for AwsMapperSpecificConfig {
                 timestamp: aws.mapper.timestamp,
                 timestamp_format: aws.mapper.timestamp_format,
             },
+            provisioning: AwsProvisioningConfig {
+                template: aws.provisioning.template.clone(),
+                claim_cert_path: aws.provisioning.claim_cert_path.clone(),
+                claim_key_path: aws.provisioning.claim_key_path.clone(),
+            },
         }
     }
 }
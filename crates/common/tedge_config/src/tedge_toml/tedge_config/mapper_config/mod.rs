@@ -93,6 +93,14 @@ pub struct BridgeConfig {
 
 pub struct C8yBridgeConfig {
     pub include: C8yBridgeIncludeConfig,
+
+    /// Whether to tunnel the bridge connection through a secure WebSocket
+    pub websocket: bool,
+}
+
+pub struct AzBridgeConfig {
+    /// Whether to tunnel the bridge connection through a secure WebSocket
+    pub websocket: bool,
 }
 
 /// Trait linking cloud-specific config to its DTO and config reader
@@ -260,6 +268,36 @@ pub struct AzCloudMapperConfig {
 
     /// The timestamp format to use
     pub timestamp_format: TimeFormat,
+
+    /// The local topic where to republish cloud-to-device messages, if any
+    pub c2d_topic: Option<String>,
+}
+
+/// Azure IoT Hub Device Provisioning Service configuration
+pub struct AzDpsConfig {
+    /// Endpoint of the Device Provisioning Service
+    pub url: HostPort<MQTT_TLS_PORT>,
+
+    /// ID scope of the Device Provisioning Service instance
+    pub id_scope: OptionalConfig<String>,
+
+    /// Registration ID of the device (optional, the device id being used if not set)
+    pub registration_id: OptionalConfig<String>,
+
+    /// Symmetric key of the device (optional, the device certificate being used if not set)
+    pub symmetric_key: OptionalConfig<String>,
+}
+
+/// AWS IoT fleet provisioning configuration
+pub struct AwsProvisioningConfig {
+    /// Name of the fleet provisioning template
+    pub template: OptionalConfig<String>,
+
+    /// Path to the claim certificate
+    pub claim_cert_path: OptionalConfig<AbsolutePath>,
+
+    /// Path to the private key of the claim certificate
+    pub claim_key_path: OptionalConfig<AbsolutePath>,
 }
 
 pub struct CommonMapperConfig {
@@ -488,11 +526,17 @@ pub struct C8yMapperSpecificConfig {
 /// Azure IoT-specific mapper configuration fields
 pub struct AzMapperSpecificConfig {
     pub mapper: AzCloudMapperConfig,
+
+    pub bridge: AzBridgeConfig,
+
+    pub dps: AzDpsConfig,
 }
 
 /// AWS IoT-specific mapper configuration fields
 pub struct AwsMapperSpecificConfig {
     pub mapper: AwsCloudMapperConfig,
+
+    pub provisioning: AwsProvisioningConfig,
 }
 
 /// CloudConfig implementation for C8y
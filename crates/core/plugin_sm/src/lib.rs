@@ -1,2 +1,3 @@
 pub mod plugin;
 pub mod plugin_manager;
+pub mod snapshot;
@@ -368,6 +368,26 @@ impl ExternalPluginCommand {
             None => Ok(()), // A software module without a type can be handled by any plugin that's configured as default plugin
         }
     }
+
+    /// List all the installed modules, ignoring the include/exclude filters and the maximum number of packages
+    ///
+    /// In contrast to [Plugin::list], which output is reported to the cloud,
+    /// this list is used to capture the exact state of the modules before an update.
+    pub async fn list_all(
+        &self,
+        command_log: Option<&mut CommandLog>,
+    ) -> Result<Vec<SoftwareModule>, SoftwareError> {
+        let command = self.command(LIST, None)?;
+        let output = self.execute(command, command_log).await?;
+        if output.status.success() {
+            deserialize_module_info(self.name.clone(), &output.stdout[..])
+        } else {
+            Err(SoftwareError::Plugin {
+                software_type: self.name.clone(),
+                reason: self.content(output.stderr)?,
+            })
+        }
+    }
 }
 
 const PREPARE: &str = "prepare";
@@ -1,6 +1,7 @@
 use crate::plugin::ExternalPluginCommand;
 use crate::plugin::Plugin;
 use crate::plugin::LIST;
+use crate::snapshot::SoftwareSnapshot;
 use camino::Utf8PathBuf;
 use std::borrow::Cow;
 use std::collections::BTreeMap;
@@ -267,6 +268,29 @@ impl ExternalPlugins {
         }
     }
 
+    /// Capture the current state of the modules impacted by a software update request
+    pub async fn snapshot(
+        &self,
+        request: &SoftwareUpdateCommand,
+        mut command_log: Option<&mut CommandLog>,
+    ) -> Result<SoftwareSnapshot, SoftwareError> {
+        let mut snapshot = SoftwareSnapshot::default();
+
+        for software_type in request.modules_types() {
+            let updates = request.updates_for(&software_type);
+            let Some(plugin) = self.by_software_type(&software_type) else {
+                return Err(SoftwareError::UnknownSoftwareType {
+                    software_type,
+                    updates,
+                });
+            };
+            let installed = plugin.list_all(command_log.as_deref_mut()).await?;
+            snapshot.capture(&software_type, &installed, &updates);
+        }
+
+        Ok(snapshot)
+    }
+
     fn error_message(errors: Vec<String>, command_log: Option<CommandLog>) -> Option<String> {
         if !errors.is_empty() {
             let reason = match &errors[..] {
@@ -0,0 +1,230 @@
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tedge_api::commands::SoftwareModuleAction;
+use tedge_api::commands::SoftwareModuleItem;
+use tedge_api::commands::SoftwareRequestResponseSoftwareList;
+use tedge_api::SoftwareModule;
+use tedge_api::SoftwareModuleUpdate;
+use tedge_api::SoftwareName;
+use tedge_api::SoftwareType;
+use tedge_api::SoftwareVersion;
+
+/// The state of the software modules impacted by software updates, as captured before these updates
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SoftwareSnapshot {
+    /// For each software type, the previous state of the impacted modules
+    pub modules: BTreeMap<SoftwareType, BTreeMap<SoftwareName, PreviousModule>>,
+}
+
+/// The state of a software module before an update
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum PreviousModule {
+    Absent,
+    Installed {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<SoftwareVersion>,
+    },
+}
+
+impl SoftwareSnapshot {
+    /// Capture the state of the modules impacted by the given updates
+    ///
+    /// A module already captured by this snapshot is left unchanged,
+    /// so the snapshot always reflects the state before the first update.
+    pub fn capture(
+        &mut self,
+        software_type: &str,
+        installed: &[SoftwareModule],
+        updates: &[SoftwareModuleUpdate],
+    ) {
+        let modules = self.modules.entry(software_type.to_string()).or_default();
+        for update in updates {
+            let name = &update.module().name;
+            let previous = match installed.iter().find(|module| &module.name == name) {
+                Some(module) => PreviousModule::Installed {
+                    version: module.version.clone(),
+                },
+                None if update.action() == SoftwareModuleAction::Remove => {
+                    // Nothing to restore
+                    continue;
+                }
+                None => PreviousModule::Absent,
+            };
+            modules.entry(name.clone()).or_insert(previous);
+        }
+    }
+
+    /// Merge a snapshot captured after this one, keeping the earliest state of each module
+    pub fn merge(&mut self, later: SoftwareSnapshot) {
+        for (software_type, later_modules) in later.modules {
+            let modules = self.modules.entry(software_type).or_default();
+            for (name, previous) in later_modules {
+                modules.entry(name).or_insert(previous);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.values().all(|modules| modules.is_empty())
+    }
+
+    /// The updates to be applied to restore the modules as captured by this snapshot
+    pub fn restore_list(&self) -> Vec<SoftwareRequestResponseSoftwareList> {
+        self.modules
+            .iter()
+            .filter(|(_, modules)| !modules.is_empty())
+            .map(
+                |(software_type, modules)| SoftwareRequestResponseSoftwareList {
+                    plugin_type: software_type.clone(),
+                    modules: modules
+                        .iter()
+                        .map(|(name, previous)| {
+                            let (version, action) = match previous {
+                                PreviousModule::Installed { version } => {
+                                    (version.clone(), SoftwareModuleAction::Install)
+                                }
+                                PreviousModule::Absent => (None, SoftwareModuleAction::Remove),
+                            };
+                            SoftwareModuleItem {
+                                name: name.clone(),
+                                version,
+                                url: None,
+                                action: Some(action),
+                                reason: None,
+                            }
+                        })
+                        .collect(),
+                    errors: vec![],
+                },
+            )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(name: &str, version: Option<&str>) -> SoftwareModule {
+        SoftwareModule::new(
+            None,
+            name.to_string(),
+            version.map(str::to_string),
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn capture_the_previous_state_of_updated_modules() {
+        let installed = vec![module("nginx", Some("1.21.0")), module("vim", Some("9.0"))];
+        let updates = vec![
+            SoftwareModuleUpdate::install(module("nginx", Some("1.25.0"))),
+            SoftwareModuleUpdate::install(module("mosquitto", Some("2.0"))),
+            SoftwareModuleUpdate::remove(module("vim", None)),
+            SoftwareModuleUpdate::remove(module("unknown", None)),
+        ];
+
+        let mut snapshot = SoftwareSnapshot::default();
+        snapshot.capture("apt", &installed, &updates);
+
+        let apt = snapshot.modules.get("apt").unwrap();
+        assert_eq!(apt.len(), 3);
+        assert_eq!(
+            apt.get("nginx"),
+            Some(&PreviousModule::Installed {
+                version: Some("1.21.0".to_string())
+            })
+        );
+        assert_eq!(apt.get("mosquitto"), Some(&PreviousModule::Absent));
+        assert_eq!(
+            apt.get("vim"),
+            Some(&PreviousModule::Installed {
+                version: Some("9.0".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn merging_snapshots_keeps_the_earliest_state() {
+        let mut first = SoftwareSnapshot::default();
+        first.capture(
+            "apt",
+            &[module("nginx", Some("1.21.0"))],
+            &[SoftwareModuleUpdate::install(module(
+                "nginx",
+                Some("1.25.0"),
+            ))],
+        );
+
+        let mut second = SoftwareSnapshot::default();
+        second.capture(
+            "apt",
+            &[module("nginx", Some("1.25.0"))],
+            &[
+                SoftwareModuleUpdate::install(module("nginx", Some("1.27.0"))),
+                SoftwareModuleUpdate::install(module("curl", None)),
+            ],
+        );
+
+        first.merge(second);
+
+        let apt = first.modules.get("apt").unwrap();
+        assert_eq!(
+            apt.get("nginx"),
+            Some(&PreviousModule::Installed {
+                version: Some("1.21.0".to_string())
+            })
+        );
+        assert_eq!(apt.get("curl"), Some(&PreviousModule::Absent));
+    }
+
+    #[test]
+    fn restore_list_reinstalls_previous_versions_and_removes_new_modules() {
+        let mut snapshot = SoftwareSnapshot::default();
+        snapshot.capture(
+            "apt",
+            &[module("nginx", Some("1.21.0"))],
+            &[
+                SoftwareModuleUpdate::install(module("nginx", Some("1.25.0"))),
+                SoftwareModuleUpdate::install(module("curl", None)),
+            ],
+        );
+        snapshot.capture("docker", &[], &[]);
+
+        let restore_list = snapshot.restore_list();
+        assert_eq!(
+            restore_list,
+            vec![SoftwareRequestResponseSoftwareList {
+                plugin_type: "apt".to_string(),
+                modules: vec![
+                    SoftwareModuleItem {
+                        name: "curl".to_string(),
+                        version: None,
+                        url: None,
+                        action: Some(SoftwareModuleAction::Remove),
+                        reason: None,
+                    },
+                    SoftwareModuleItem {
+                        name: "nginx".to_string(),
+                        version: Some("1.21.0".to_string()),
+                        url: None,
+                        action: Some(SoftwareModuleAction::Install),
+                        reason: None,
+                    },
+                ],
+                errors: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn snapshot_without_impacted_modules_is_empty() {
+        let mut snapshot = SoftwareSnapshot::default();
+        snapshot.capture("apt", &[module("nginx", Some("1.21.0"))], &[]);
+        assert!(snapshot.is_empty());
+        assert!(snapshot.restore_list().is_empty());
+    }
+}
@@ -239,6 +239,8 @@ fn print_bidirectional_rules(w: &mut impl Write, rules: &[ExpandedBridgeRule]) {
 mod tests {
     use crate::cli::bridge::common::render;
     use crate::cli::bridge::common::strip_ansi;
+    use tedge_mqtt_bridge::config_toml::OverflowPolicy;
+    use tedge_mqtt_bridge::config_toml::RetainPolicy;
 
     use super::*;
 
@@ -813,6 +815,11 @@ topic = "data"
             local_prefix: local_prefix.into(),
             remote_prefix: remote_prefix.into(),
             topic: topic.into(),
+            on_overflow: OverflowPolicy::default(),
+            transform: vec![],
+            qos: None,
+            retain: RetainPolicy::default(),
+            max_rate: None,
         }
     }
 
@@ -0,0 +1,418 @@
+//! Register the device with the Azure IoT Hub Device Provisioning Service (DPS).
+//!
+//! The device connects to the DPS endpoint and requests its registration,
+//! authenticating either with its X.509 certificate or with a SAS token derived from its symmetric key.
+//! The registration being asynchronous, its status is then polled until the device is assigned to an IoT Hub,
+//! the hostname of this hub being saved as `az.url`.
+use crate::cli::connect::provisioning::provision;
+use crate::cli::connect::provisioning::ProvisioningProtocol;
+use crate::cli::connect::provisioning::Request;
+use crate::cli::connect::provisioning::Step;
+use anyhow::bail;
+use anyhow::Context as _;
+use base64::prelude::*;
+use certificate::parse_root_certificate::create_tls_config_without_client_cert;
+use rumqttc::MqttOptions;
+use rumqttc::Transport;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use std::time::SystemTime;
+use tedge_config::tedge_toml::mapper_config::AzMapperSpecificConfig;
+use tedge_config::tedge_toml::ProfileName;
+use tedge_config::tedge_toml::WritableKey;
+use tedge_config::TEdgeConfig;
+
+/// The version of the DPS API used by the device
+const API_VERSION: &str = "2019-03-31";
+
+/// How long the SAS token used to register the device is valid
+const SAS_TOKEN_VALIDITY: Duration = Duration::from_secs(3600);
+
+/// How long to wait before polling the registration status, when not told by DPS
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(3);
+
+/// Register the device with DPS and save the hostname of the IoT Hub it has been assigned to
+pub async fn provision_device_azure(
+    tedge_config: &TEdgeConfig,
+    profile: Option<&ProfileName>,
+) -> anyhow::Result<()> {
+    let az_config = tedge_config.mapper_config::<AzMapperSpecificConfig>(&profile)?;
+    let dps = &az_config.cloud_specific.dps;
+    let id_scope = dps.id_scope.or_config_not_set()?;
+    let registration_id = match dps.registration_id.or_none() {
+        Some(registration_id) => registration_id.clone(),
+        None => az_config.device.id()?,
+    };
+
+    let mut mqtt_options = MqttOptions::new(
+        registration_id.clone(),
+        dps.url.host().to_string(),
+        dps.url.port().into(),
+    );
+    let username = format!("{id_scope}/registrations/{registration_id}/api-version={API_VERSION}");
+    let tls_config = match dps.symmetric_key.or_none() {
+        Some(symmetric_key) => {
+            let expiry = SystemTime::now() + SAS_TOKEN_VALIDITY;
+            let token = sas_token(id_scope, &registration_id, symmetric_key, expiry)?;
+            mqtt_options.set_credentials(username, token);
+            create_tls_config_without_client_cert(&*az_config.root_cert_path)?
+        }
+        None => {
+            mqtt_options.set_credentials(username, "");
+            tedge_config
+                .mqtt_auth_config_cloud_broker(&az_config)?
+                .to_rustls_client_config()?
+        }
+    };
+    mqtt_options.set_transport(Transport::tls_with_config(tls_config.into()));
+
+    let assignment = provision(mqtt_options, DpsRegistration::new(registration_id)).await?;
+
+    let key = match profile {
+        None => "az.url".to_string(),
+        Some(profile) => format!("az.profiles.{profile}.url"),
+    };
+    let key = key.parse::<WritableKey>()?;
+    TEdgeConfig::load(tedge_config.root_dir())
+        .await?
+        .update_toml(&|dto, _reader| {
+            dto.try_update_str(&key, &assignment.assigned_hub)
+                .map_err(|e| e.into())
+        })
+        .await?;
+    Ok(())
+}
+
+/// Build a SAS token to authenticate a registration with a symmetric key
+fn sas_token(
+    id_scope: &str,
+    registration_id: &str,
+    symmetric_key: &str,
+    expiry: SystemTime,
+) -> anyhow::Result<String> {
+    let key = BASE64_STANDARD
+        .decode(symmetric_key)
+        .context("The DPS symmetric key is not base64-encoded")?;
+    let expiry = expiry
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let resource = url_encode(&format!("{id_scope}/registrations/{registration_id}"));
+
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &key);
+    let signature = ring::hmac::sign(&key, format!("{resource}\n{expiry}").as_bytes());
+    let signature = url_encode(&BASE64_STANDARD.encode(signature.as_ref()));
+
+    Ok(format!(
+        "SharedAccessSignature sr={resource}&sig={signature}&se={expiry}&skn=registration"
+    ))
+}
+
+fn url_encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// The IoT Hub a device has been assigned to
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct DpsAssignment {
+    pub assigned_hub: String,
+}
+
+/// The registration of a device with DPS, over the `$dps/registrations/#` topics
+pub(crate) struct DpsRegistration {
+    registration_id: String,
+    /// The request id of the pending request
+    request_id: u64,
+    /// The id of the registration operation, once accepted by DPS
+    operation_id: Option<String>,
+}
+
+/// A registration operation, as returned by DPS
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegistrationOperation {
+    operation_id: String,
+    status: String,
+    #[serde(default)]
+    registration_state: Option<RegistrationState>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RegistrationState {
+    #[serde(default)]
+    assigned_hub: Option<String>,
+    #[serde(default)]
+    error_message: Option<String>,
+}
+
+/// An error, as returned by DPS
+#[derive(Debug, Deserialize)]
+struct DpsError {
+    #[serde(default)]
+    message: Option<String>,
+}
+
+impl DpsRegistration {
+    pub fn new(registration_id: String) -> Self {
+        DpsRegistration {
+            registration_id,
+            request_id: 0,
+            operation_id: None,
+        }
+    }
+
+    /// The request to be sent next: the registration itself or a poll of the registration status
+    fn next_request(&mut self, delay: Duration) -> Request {
+        self.request_id += 1;
+        let rid = self.request_id;
+        match &self.operation_id {
+            None => Request {
+                topic: format!("$dps/registrations/PUT/iotdps-register/?$rid={rid}"),
+                payload: json!({ "registrationId": self.registration_id }).to_string(),
+                delay,
+            },
+            Some(operation_id) => Request {
+                topic: format!(
+                    "$dps/registrations/GET/iotdps-get-operationstatus/?$rid={rid}&operationId={operation_id}"
+                ),
+                payload: String::new(),
+                delay,
+            },
+        }
+    }
+}
+
+impl ProvisioningProtocol for DpsRegistration {
+    type Outcome = DpsAssignment;
+
+    fn response_topics(&self) -> Vec<String> {
+        vec!["$dps/registrations/res/#".to_string()]
+    }
+
+    fn start(&mut self) -> Request {
+        self.next_request(Duration::ZERO)
+    }
+
+    fn on_response(&mut self, topic: &str, payload: &[u8]) -> anyhow::Result<Step<DpsAssignment>> {
+        let Some((status, query)) = topic
+            .strip_prefix("$dps/registrations/res/")
+            .and_then(|response| response.split_once("/?"))
+        else {
+            return Ok(Step::Ignore);
+        };
+        if query_param(query, "$rid") != Some(self.request_id.to_string().as_str()) {
+            return Ok(Step::Ignore);
+        }
+        let retry_after = query_param(query, "retry-after")
+            .and_then(|secs| secs.parse().ok())
+            .map_or(DEFAULT_RETRY_AFTER, Duration::from_secs);
+
+        match status {
+            "200" | "202" => {
+                let operation: RegistrationOperation = serde_json::from_slice(payload)
+                    .context("Invalid response from the Device Provisioning Service")?;
+                let state = operation.registration_state;
+                match operation.status.as_str() {
+                    "assigned" => {
+                        let Some(state) = state else {
+                            bail!("The Device Provisioning Service returned no registration state")
+                        };
+                        let Some(assigned_hub) = state.assigned_hub else {
+                            bail!("The Device Provisioning Service assigned no IoT Hub")
+                        };
+                        Ok(Step::Done(DpsAssignment { assigned_hub }))
+                    }
+                    "unassigned" | "assigning" => {
+                        self.operation_id = Some(operation.operation_id);
+                        Ok(Step::Send(self.next_request(retry_after)))
+                    }
+                    status => {
+                        let reason = state
+                            .and_then(|state| state.error_message)
+                            .unwrap_or_else(|| "no reason given".to_string());
+                        bail!("The device registration has {status}: {reason}")
+                    }
+                }
+            }
+            "429" => Ok(Step::Send(self.next_request(retry_after))),
+            status => {
+                let reason = serde_json::from_slice::<DpsError>(payload)
+                    .ok()
+                    .and_then(|error| error.message)
+                    .unwrap_or_else(|| String::from_utf8_lossy(payload).to_string());
+                bail!("The Device Provisioning Service rejected the registration ({status}): {reason}")
+            }
+        }
+    }
+}
+
+/// Extract the value of a parameter from a `key=value&...` query string
+fn query_param<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .find_map(|(k, v)| (k == key).then_some(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registration_is_polled_until_the_device_is_assigned() {
+        let mut registration = DpsRegistration::new("my-device".to_string());
+
+        let request = registration.start();
+        assert_eq!(
+            request.topic,
+            "$dps/registrations/PUT/iotdps-register/?$rid=1"
+        );
+        assert_eq!(request.payload, r#"{"registrationId":"my-device"}"#);
+
+        let step = registration
+            .on_response(
+                "$dps/registrations/res/202/?$rid=1&retry-after=2",
+                br#"{"operationId":"4.a1b2","status":"assigning"}"#,
+            )
+            .unwrap();
+        assert_eq!(
+            step,
+            Step::Send(Request {
+                topic:
+                    "$dps/registrations/GET/iotdps-get-operationstatus/?$rid=2&operationId=4.a1b2"
+                        .to_string(),
+                payload: String::new(),
+                delay: Duration::from_secs(2),
+            })
+        );
+
+        let step = registration
+            .on_response(
+                "$dps/registrations/res/200/?$rid=2",
+                br#"{
+                    "operationId":"4.a1b2",
+                    "status":"assigned",
+                    "registrationState":{
+                        "registrationId":"my-device",
+                        "assignedHub":"my-hub.azure-devices.net",
+                        "deviceId":"my-device",
+                        "status":"assigned"
+                    }
+                }"#,
+            )
+            .unwrap();
+        assert_eq!(
+            step,
+            Step::Done(DpsAssignment {
+                assigned_hub: "my-hub.azure-devices.net".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn throttled_requests_are_resent() {
+        let mut registration = DpsRegistration::new("my-device".to_string());
+        registration.start();
+
+        let step = registration
+            .on_response("$dps/registrations/res/429/?$rid=1&retry-after=5", b"")
+            .unwrap();
+        assert_eq!(
+            step,
+            Step::Send(Request {
+                topic: "$dps/registrations/PUT/iotdps-register/?$rid=2".to_string(),
+                payload: r#"{"registrationId":"my-device"}"#.to_string(),
+                delay: Duration::from_secs(5),
+            })
+        );
+    }
+
+    #[test]
+    fn responses_to_other_requests_are_ignored() {
+        let mut registration = DpsRegistration::new("my-device".to_string());
+        registration.start();
+
+        let step = registration
+            .on_response(
+                "$dps/registrations/res/200/?$rid=42",
+                br#"{"operationId":"4.a1b2","status":"assigned"}"#,
+            )
+            .unwrap();
+        assert_eq!(step, Step::Ignore);
+    }
+
+    #[test]
+    fn failed_registrations_are_reported() {
+        let mut registration = DpsRegistration::new("my-device".to_string());
+        registration.start();
+
+        let err = registration
+            .on_response(
+                "$dps/registrations/res/200/?$rid=1",
+                br#"{
+                    "operationId":"4.a1b2",
+                    "status":"failed",
+                    "registrationState":{"status":"failed","errorMessage":"Custom allocation failed"}
+                }"#,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The device registration has failed: Custom allocation failed"
+        );
+
+        let err = registration
+            .on_response(
+                "$dps/registrations/res/401/?$rid=1",
+                br#"{"errorCode":401002,"message":"Unauthorized"}"#,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The Device Provisioning Service rejected the registration (401): Unauthorized"
+        );
+    }
+
+    #[test]
+    fn invalid_assignments_are_reported() {
+        let mut registration = DpsRegistration::new("my-device".to_string());
+        registration.start();
+
+        let err = registration
+            .on_response(
+                "$dps/registrations/res/200/?$rid=1",
+                br#"{"operationId":"4.a1b2","status":"assigned","registrationState":{}}"#,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The Device Provisioning Service assigned no IoT Hub"
+        );
+
+        let err = registration
+            .on_response("$dps/registrations/res/202/?$rid=1", b"not json")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid response from the Device Provisioning Service"
+        );
+    }
+
+    #[test]
+    fn sas_token_is_signed_with_the_symmetric_key() {
+        let expiry = SystemTime::UNIX_EPOCH + Duration::from_secs(1700000000);
+        let token = sas_token(
+            "0ne00ABC123",
+            "my-device",
+            "c2VjcmV0LWtleS1mb3ItdGVzdHM=",
+            expiry,
+        )
+        .unwrap();
+        assert_eq!(
+            token,
+            "SharedAccessSignature sr=0ne00ABC123%2Fregistrations%2Fmy-device&sig=27WasggLp%2B2VIILc1H2s6ABhhZnnkVf6dmBQBzE7ZfE%3D&se=1700000000&skn=registration"
+        );
+    }
+}
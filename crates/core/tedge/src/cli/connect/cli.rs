@@ -19,6 +19,13 @@ pub struct TEdgeConnectOpt {
     #[clap(long = "offline")]
     offline_mode: bool,
 
+    /// Register the device with the cloud provisioning service before connecting
+    ///
+    /// This is supported by Azure IoT (Device Provisioning Service)
+    /// and AWS IoT (fleet provisioning by claim).
+    #[clap(long = "provision", conflicts_with_all = ["is_test_connection", "offline_mode"])]
+    provision: bool,
+
     /// The cloud or custom mapper to connect to (e.g. c8y, aws, az, or a custom mapper name)
     #[arg(add(ArgValueCandidates::new(mapper_name_completions)))]
     cloud: String,
@@ -40,6 +47,7 @@ impl BuildCommand for TEdgeConnectOpt {
         let Self {
             is_test_connection,
             offline_mode,
+            provision,
             cloud,
             profile,
         } = self;
@@ -53,6 +61,7 @@ impl BuildCommand for TEdgeConnectOpt {
             cloud,
             is_test_connection,
             offline_mode,
+            provision,
             is_reconnect: false,
         }))
     }
@@ -78,6 +78,7 @@ pub struct ConnectCommand {
     pub cloud: Cloud,
     pub is_test_connection: bool,
     pub offline_mode: bool,
+    pub provision: bool,
     pub service_manager: Arc<dyn SystemServiceManager>,
     pub is_reconnect: bool,
 }
@@ -99,7 +100,10 @@ impl Command for ConnectCommand {
         }
     }
 
-    async fn execute(&self, tedge_config: TEdgeConfig) -> Result<(), MaybeFancy<anyhow::Error>> {
+    async fn execute(
+        &self,
+        mut tedge_config: TEdgeConfig,
+    ) -> Result<(), MaybeFancy<anyhow::Error>> {
         if let Cloud::Custom(ref name) = self.cloud {
             if self.is_test_connection {
                 return wait_for_custom_mapper_health(&tedge_config, name)
@@ -113,6 +117,14 @@ impl Command for ConnectCommand {
             }
         }
 
+        if self.provision {
+            self.provision_device(&tedge_config).await?;
+            // Reload the configuration updated with the outcome of the provisioning
+            tedge_config = TEdgeConfig::load(tedge_config.root_dir())
+                .await
+                .map_err(anyhow::Error::new)?;
+        }
+
         let bridge_config = bridge_config(&tedge_config, &self.cloud)
             .await
             .map_err(anyhow::Error::new)?;
@@ -191,6 +203,30 @@ impl Command for ConnectCommand {
 }
 
 impl ConnectCommand {
+    async fn provision_device(
+        &self,
+        tedge_config: &TEdgeConfig,
+    ) -> Result<(), MaybeFancy<anyhow::Error>> {
+        match &self.cloud {
+            #[cfg(feature = "azure")]
+            Cloud::Azure(profile) => {
+                let spinner =
+                    Spinner::start("Registering device with Azure IoT Device Provisioning Service");
+                let res = azure_dps::provision_device_azure(tedge_config, profile.as_deref()).await;
+                Ok(spinner.finish(res)?)
+            }
+            #[cfg(feature = "aws")]
+            Cloud::Aws(profile) => {
+                let spinner = Spinner::start("Registering device with AWS IoT fleet provisioning");
+                let res = aws_fleet::provision_device_aws(tedge_config, profile.as_deref()).await;
+                Ok(spinner.finish(res)?)
+            }
+            cloud => {
+                Err(anyhow!("'tedge connect --provision' is not supported for {cloud}").into())
+            }
+        }
+    }
+
     async fn check_bridge(
         &self,
         tedge_config: &TEdgeConfig,
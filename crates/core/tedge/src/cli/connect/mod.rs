@@ -4,10 +4,16 @@ pub use self::error::*;
 
 #[cfg(feature = "aws")]
 pub(crate) mod aws;
+#[cfg(feature = "aws")]
+pub(crate) mod aws_fleet;
 #[cfg(feature = "azure")]
 pub(crate) mod azure;
+#[cfg(feature = "azure")]
+pub(crate) mod azure_dps;
 #[cfg(feature = "c8y")]
 pub(crate) mod c8y;
 mod cli;
 mod command;
 mod error;
+#[cfg(any(feature = "aws", feature = "azure"))]
+pub(crate) mod provisioning;
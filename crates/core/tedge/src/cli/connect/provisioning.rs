@@ -0,0 +1,433 @@
+//! Register a device with a cloud provisioning service over MQTT.
+//!
+//! A provisioning exchange is a sequence of requests published by the device,
+//! each being answered by the provisioning service on a response topic.
+//! The protocol specific to each service is given by a [ProvisioningProtocol],
+//! the MQTT connection being handled by [provision].
+use anyhow::bail;
+use anyhow::Context as _;
+use rumqttc::AsyncClient;
+use rumqttc::Event;
+use rumqttc::Incoming;
+use rumqttc::MqttOptions;
+use rumqttc::Outgoing;
+use rumqttc::Packet;
+use rumqttc::QoS::AtLeastOnce;
+use std::time::Duration;
+use tracing::debug;
+use tracing::warn;
+
+/// The maximum time given to a provisioning service to register a device
+const PROVISIONING_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A request to be published to a provisioning service
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Request {
+    pub topic: String,
+    pub payload: String,
+    /// How long to wait before publishing the request
+    pub delay: Duration,
+}
+
+/// What to do on a response of a provisioning service
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Step<T> {
+    /// Publish a new request
+    Send(Request),
+    /// The response is unrelated to the pending request
+    Ignore,
+    /// The device has been provisioned
+    Done(T),
+}
+
+/// The requests and responses exchanged with a provisioning service
+pub(crate) trait ProvisioningProtocol {
+    /// What the provisioning service returns on success
+    type Outcome;
+
+    /// The topics on which the provisioning service publishes its responses
+    fn response_topics(&self) -> Vec<String>;
+
+    /// The request starting the exchange
+    fn start(&mut self) -> Request;
+
+    /// Process a response of the provisioning service
+    fn on_response(&mut self, topic: &str, payload: &[u8]) -> anyhow::Result<Step<Self::Outcome>>;
+}
+
+/// Connect to a provisioning service and run a provisioning exchange till completion
+pub(crate) async fn provision<P: ProvisioningProtocol>(
+    mqtt_options: MqttOptions,
+    protocol: P,
+) -> anyhow::Result<P::Outcome> {
+    match tokio::time::timeout(PROVISIONING_TIMEOUT, run(mqtt_options, protocol)).await {
+        Ok(outcome) => outcome,
+        Err(_) => bail!("Timed-out waiting for the provisioning service to register the device"),
+    }
+}
+
+async fn run<P: ProvisioningProtocol>(
+    mqtt_options: MqttOptions,
+    mut protocol: P,
+) -> anyhow::Result<P::Outcome> {
+    let (client, mut event_loop) = AsyncClient::new(mqtt_options, 10);
+    let topics = protocol.response_topics();
+    let mut pending_subscriptions = topics.len();
+
+    let outcome = loop {
+        match event_loop.poll().await {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                for topic in &topics {
+                    client.subscribe(topic, AtLeastOnce).await?;
+                }
+            }
+            Ok(Event::Incoming(Packet::SubAck(_))) => {
+                pending_subscriptions = pending_subscriptions.saturating_sub(1);
+                if pending_subscriptions == 0 {
+                    // We are ready to get the responses, hence start the exchange
+                    publish_later(&client, protocol.start());
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(response))) => {
+                match protocol.on_response(&response.topic, &response.payload)? {
+                    Step::Send(request) => publish_later(&client, request),
+                    Step::Ignore => debug!("Ignoring response on {}", response.topic),
+                    Step::Done(outcome) => break outcome,
+                }
+            }
+            Ok(Event::Incoming(Incoming::Disconnect)) => {
+                bail!("Unexpectedly disconnected by the provisioning service")
+            }
+            Err(err) => {
+                return Err(err).context("Connection error with the provisioning service");
+            }
+            _ => {}
+        }
+    };
+
+    // Cleanly disconnect client
+    client.disconnect().await?;
+    loop {
+        match event_loop.poll().await {
+            Ok(Event::Outgoing(Outgoing::Disconnect)) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Publish a request once its delay is elapsed, without blocking the event loop meanwhile
+fn publish_later(client: &AsyncClient, request: Request) {
+    let client = client.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(request.delay).await;
+        if let Err(err) = client
+            .publish(&request.topic, AtLeastOnce, false, request.payload)
+            .await
+        {
+            warn!(
+                "Failed to publish provisioning request on {}: {err}",
+                request.topic
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::EventLoop;
+    use tokio::sync::mpsc;
+
+    #[cfg(feature = "azure")]
+    #[tokio::test]
+    async fn dps_registration_is_polled_till_assigned() {
+        use crate::cli::connect::azure_dps::DpsAssignment;
+        use crate::cli::connect::azure_dps::DpsRegistration;
+
+        let mut requests = fake_service(
+            "dps-service",
+            &["dps-test/registrations/PUT/#", "dps-test/registrations/GET/#"],
+            |topic, _| match topic {
+                "dps-test/registrations/PUT/iotdps-register/?$rid=1" => vec![
+                    // A response to another request is ignored
+                    (
+                        "dps-test/registrations/res/202/?$rid=42",
+                        r#"{"operationId":"other","status":"assigning"}"#,
+                    ),
+                    (
+                        "dps-test/registrations/res/202/?$rid=1&retry-after=0",
+                        r#"{"operationId":"op-1","status":"assigning"}"#,
+                    ),
+                ],
+                "dps-test/registrations/GET/iotdps-get-operationstatus/?$rid=2&operationId=op-1" => {
+                    vec![(
+                        "dps-test/registrations/res/202/?$rid=2&retry-after=0",
+                        r#"{"operationId":"op-1","status":"assigning"}"#,
+                    )]
+                }
+                "dps-test/registrations/GET/iotdps-get-operationstatus/?$rid=3&operationId=op-1" => {
+                    vec![(
+                        "dps-test/registrations/res/200/?$rid=3",
+                        r#"{"operationId":"op-1","status":"assigned","registrationState":{"assignedHub":"my-hub.azure-devices.net"}}"#,
+                    )]
+                }
+                _ => vec![],
+            },
+        )
+        .await;
+
+        let assignment = provision_with_timeout(
+            device_options("dps-device"),
+            Relocated::new("dps-test", DpsRegistration::new("my-device".to_string())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            assignment,
+            DpsAssignment {
+                assigned_hub: "my-hub.azure-devices.net".to_string()
+            }
+        );
+
+        assert_eq!(
+            requests.recv().await.unwrap(),
+            (
+                "dps-test/registrations/PUT/iotdps-register/?$rid=1".to_string(),
+                r#"{"registrationId":"my-device"}"#.to_string()
+            )
+        );
+        for rid in 2..=3 {
+            let (topic, payload) = requests.recv().await.unwrap();
+            assert_eq!(
+                topic,
+                format!(
+                    "dps-test/registrations/GET/iotdps-get-operationstatus/?$rid={rid}&operationId=op-1"
+                )
+            );
+            assert_eq!(payload, "");
+        }
+    }
+
+    #[cfg(feature = "aws")]
+    #[tokio::test]
+    async fn fleet_provisioning_fails_when_the_template_is_rejected() {
+        use crate::cli::connect::aws_fleet::FleetProvisioning;
+
+        let mut requests = fake_service(
+            "fleet-service",
+            &[
+                "fleet-test/certificates/create/json",
+                "fleet-test/provisioning-templates/+/provision/json",
+            ],
+            |topic, _| match topic {
+                "fleet-test/certificates/create/json" => vec![(
+                    "fleet-test/certificates/create/json/accepted",
+                    r#"{"certificateId":"0123","certificatePem":"cert","privateKey":"key","certificateOwnershipToken":"token"}"#,
+                )],
+                "fleet-test/provisioning-templates/unknown-template/provision/json" => vec![(
+                    "fleet-test/provisioning-templates/unknown-template/provision/json/rejected",
+                    r#"{"statusCode":404,"errorCode":"NotFound","errorMessage":"Template unknown-template not found"}"#,
+                )],
+                _ => vec![],
+            },
+        )
+        .await;
+
+        let err = provision_with_timeout(
+            device_options("fleet-device"),
+            Relocated::new(
+                "fleet-test",
+                FleetProvisioning::new(
+                    "unknown-template".to_string(),
+                    serde_json::json!({"SerialNumber": "my-device"}),
+                ),
+            ),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "AWS IoT rejected RegisterThing (NotFound): Template unknown-template not found"
+        );
+
+        assert_eq!(
+            requests.recv().await.unwrap(),
+            (
+                "fleet-test/certificates/create/json".to_string(),
+                "{}".to_string()
+            )
+        );
+        let (topic, payload) = requests.recv().await.unwrap();
+        assert_eq!(
+            topic,
+            "fleet-test/provisioning-templates/unknown-template/provision/json"
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&payload).unwrap(),
+            serde_json::json!({
+                "certificateOwnershipToken": "token",
+                "parameters": {"SerialNumber": "my-device"}
+            })
+        );
+    }
+
+    #[cfg(feature = "azure")]
+    #[tokio::test]
+    async fn invalid_responses_abort_the_provisioning() {
+        use crate::cli::connect::azure_dps::DpsRegistration;
+
+        let _requests = fake_service(
+            "dps-invalid-service",
+            &["dps-invalid-test/registrations/PUT/#"],
+            |topic, _| match topic {
+                "dps-invalid-test/registrations/PUT/iotdps-register/?$rid=1" => vec![(
+                    "dps-invalid-test/registrations/res/200/?$rid=1",
+                    r#"{"status":"assigned"}"#,
+                )],
+                _ => vec![],
+            },
+        )
+        .await;
+
+        let err = provision_with_timeout(
+            device_options("dps-invalid-device"),
+            Relocated::new(
+                "dps-invalid-test",
+                DpsRegistration::new("my-device".to_string()),
+            ),
+        )
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Invalid response from the Device Provisioning Service"
+        );
+    }
+
+    /// A provisioning protocol which `$` topics are moved under a prefix,
+    /// as `$` topics cannot be subscribed to on the test broker
+    struct Relocated<P> {
+        prefix: &'static str,
+        /// The root of the topics used by the protocol, e.g. `$dps`
+        root: String,
+        protocol: P,
+    }
+
+    impl<P: ProvisioningProtocol> Relocated<P> {
+        fn new(prefix: &'static str, protocol: P) -> Self {
+            let topic = protocol.response_topics().remove(0);
+            let (root, _) = topic.split_once('/').unwrap();
+            Relocated {
+                prefix,
+                root: root.to_string(),
+                protocol,
+            }
+        }
+
+        fn relocate(&self, topic: String) -> String {
+            let (_, topic) = topic.split_once('/').unwrap();
+            format!("{}/{topic}", self.prefix)
+        }
+
+        fn relocate_step<T>(&self, step: Step<T>) -> Step<T> {
+            match step {
+                Step::Send(request) => Step::Send(self.relocate_request(request)),
+                step => step,
+            }
+        }
+
+        fn relocate_request(&self, request: Request) -> Request {
+            Request {
+                topic: self.relocate(request.topic),
+                ..request
+            }
+        }
+    }
+
+    impl<P: ProvisioningProtocol> ProvisioningProtocol for Relocated<P> {
+        type Outcome = P::Outcome;
+
+        fn response_topics(&self) -> Vec<String> {
+            let topics = self.protocol.response_topics();
+            topics.into_iter().map(|t| self.relocate(t)).collect()
+        }
+
+        fn start(&mut self) -> Request {
+            let request = self.protocol.start();
+            self.relocate_request(request)
+        }
+
+        fn on_response(
+            &mut self,
+            topic: &str,
+            payload: &[u8],
+        ) -> anyhow::Result<Step<Self::Outcome>> {
+            let Some(topic) = topic.strip_prefix(self.prefix) else {
+                return Ok(Step::Ignore);
+            };
+            let topic = format!("{}{topic}", self.root);
+            let step = self.protocol.on_response(&topic, payload)?;
+            Ok(self.relocate_step(step))
+        }
+    }
+
+    async fn provision_with_timeout<P: ProvisioningProtocol>(
+        options: MqttOptions,
+        protocol: P,
+    ) -> anyhow::Result<P::Outcome> {
+        tokio::time::timeout(Duration::from_secs(10), provision(options, protocol))
+            .await
+            .expect("provisioning to complete")
+    }
+
+    fn device_options(client_id: &str) -> MqttOptions {
+        let port = mqtt_tests::test_mqtt_broker().port;
+        MqttOptions::new(client_id, "127.0.0.1", port)
+    }
+
+    /// Start a fake provisioning service, answering the requests with the responses returned by `respond`
+    ///
+    /// Return the requests received by the service.
+    async fn fake_service(
+        client_id: &str,
+        subscriptions: &[&str],
+        respond: impl Fn(&str, &str) -> Vec<(&'static str, &'static str)> + Send + 'static,
+    ) -> mpsc::UnboundedReceiver<(String, String)> {
+        let port = mqtt_tests::test_mqtt_broker().port;
+        let (client, mut event_loop) =
+            AsyncClient::new(MqttOptions::new(client_id, "127.0.0.1", port), 10);
+        for subscription in subscriptions {
+            client.subscribe(*subscription, AtLeastOnce).await.unwrap();
+        }
+        wait_for_suback(&mut event_loop, subscriptions.len()).await;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok(event) = event_loop.poll().await {
+                if let Event::Incoming(Packet::Publish(request)) = event {
+                    let payload = String::from_utf8(request.payload.to_vec()).unwrap();
+                    for (topic, response) in respond(&request.topic, &payload) {
+                        client
+                            .publish(topic, AtLeastOnce, false, response)
+                            .await
+                            .unwrap();
+                    }
+                    let _ = sender.send((request.topic, payload));
+                }
+            }
+        });
+        receiver
+    }
+
+    async fn wait_for_suback(event_loop: &mut EventLoop, mut count: usize) {
+        while count > 0 {
+            if let Event::Incoming(Packet::SubAck(_)) = event_loop.poll().await.unwrap() {
+                count -= 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::cli::entities::command::EntitiesAction;
+use crate::cli::entities::command::EntitiesCommand;
+use crate::cli::http::tedge_http_client;
+use crate::command::BuildCommand;
+use crate::command::Command;
+use crate::ConfigError;
+use camino::Utf8PathBuf;
+use tedge_config::TEdgeConfig;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum TEdgeEntitiesCli {
+    /// Export all the registered entities, along with their twin data
+    ///
+    /// The exported file can be imported on another device using `tedge entities import`,
+    /// or used as an entity manifest in the `/etc/tedge/entities` directory.
+    ///
+    /// Examples:
+    ///   # Export the entities as JSON on stdout
+    ///   tedge entities export
+    ///
+    ///   # Export the entities as TOML
+    ///   tedge entities export --output entities.toml
+    #[clap(verbatim_doc_comment)]
+    Export {
+        /// File where the entities are saved, in TOML if the file has a .toml extension and JSON otherwise
+        #[clap(long, short)]
+        output: Option<Utf8PathBuf>,
+    },
+
+    /// Import the entities of a file, along with their twin data
+    ///
+    /// The entities that are already registered are not registered again,
+    /// but their twin data are updated with the imported values.
+    ///
+    /// Examples:
+    ///   # Restore the entities exported from another device
+    ///   tedge entities import entities.json
+    #[clap(verbatim_doc_comment)]
+    Import {
+        /// The TOML or JSON file to import
+        file: Utf8PathBuf,
+    },
+}
+
+#[async_trait::async_trait]
+impl BuildCommand for TEdgeEntitiesCli {
+    async fn build_command(self, config: &TEdgeConfig) -> Result<Box<dyn Command>, ConfigError> {
+        let (client, base_url) = tedge_http_client(config).await?;
+        let action = match self {
+            TEdgeEntitiesCli::Export { output } => EntitiesAction::Export { output },
+            TEdgeEntitiesCli::Import { file } => EntitiesAction::Import { file },
+        };
+
+        Ok(EntitiesCommand {
+            client,
+            base_url,
+            action,
+        }
+        .into_boxed())
+    }
+}
@@ -0,0 +1,148 @@
+use crate::command::Command;
+use crate::log::MaybeFancy;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Error;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use reqwest::Client;
+use reqwest::Response;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde_json::Map;
+use serde_json::Value;
+use tedge_api::entity::EntityMetadata;
+use tedge_api::entity_manifest::EntityManifest;
+use tedge_config::TEdgeConfig;
+
+pub struct EntitiesCommand {
+    /// HTTP client
+    pub client: Client,
+
+    /// Base url of the local thin-edge HTTP server
+    pub base_url: String,
+
+    /// Action
+    pub action: EntitiesAction,
+}
+
+pub enum EntitiesAction {
+    Export { output: Option<Utf8PathBuf> },
+    Import { file: Utf8PathBuf },
+}
+
+#[async_trait::async_trait]
+impl Command for EntitiesCommand {
+    fn description(&self) -> String {
+        match &self.action {
+            EntitiesAction::Export { .. } => "export the registered entities".to_string(),
+            EntitiesAction::Import { file } => format!("import the entities of {file}"),
+        }
+    }
+
+    async fn execute(&self, _: TEdgeConfig) -> Result<(), MaybeFancy<Error>> {
+        self.run().await?;
+        Ok(())
+    }
+}
+
+impl EntitiesCommand {
+    async fn run(&self) -> Result<(), Error> {
+        match &self.action {
+            EntitiesAction::Export { output } => {
+                let manifest = self.export().await?;
+                match output {
+                    Some(file) => {
+                        let content = serialize(&manifest, file)?;
+                        tokio::fs::write(file, content)
+                            .await
+                            .with_context(|| format!("Failed to write {file}"))?;
+                    }
+                    None => println!("{}", serde_json::to_string_pretty(&manifest)?),
+                }
+            }
+            EntitiesAction::Import { file } => {
+                let manifest = EntityManifest::load_file(file)?;
+                self.import(manifest).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Retrieve all the registered entities with their twin data
+    async fn export(&self) -> Result<EntityManifest, Error> {
+        let entities: Vec<EntityMetadata> = self.get("/te/v1/entities").await?;
+        let mut manifest = EntityManifest::default();
+        for mut entity in entities {
+            let twin_data: Map<String, Value> = self
+                .get(&format!("/te/v1/entities/{}/twin", entity.topic_id))
+                .await?;
+            entity.twin_data = twin_data;
+            manifest.entities.push(entity.into());
+        }
+        Ok(manifest)
+    }
+
+    /// Register the entities, parents first, and set their twin data
+    ///
+    /// The twin data of the entities already registered are updated fragment per fragment,
+    /// leaving untouched the fragments not listed in the manifest.
+    async fn import(&self, manifest: EntityManifest) -> Result<(), Error> {
+        for entity in manifest.parents_first().entities {
+            let topic_id = entity.topic_id.clone();
+            let response = self
+                .client
+                .post(self.url("/te/v1/entities"))
+                .json(&entity)
+                .send()
+                .await?;
+            if response.status() == StatusCode::CONFLICT {
+                for (fragment_key, fragment_value) in entity.payload.twin_data {
+                    let response = self
+                        .client
+                        .put(self.url(&format!("/te/v1/entities/{topic_id}/twin/{fragment_key}")))
+                        .json(&fragment_value)
+                        .send()
+                        .await?;
+                    error_for_status(response).await?;
+                }
+                println!("Updated {topic_id}");
+            } else {
+                error_for_status(response).await?;
+                println!("Registered {topic_id}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, Error> {
+        let response = self.client.get(self.url(path)).send().await?;
+        let response = error_for_status(response).await?;
+        Ok(response.json().await?)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+}
+
+async fn error_for_status(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    Err(anyhow!(
+        "HTTP error: {} {}\n{}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or(""),
+        response.text().await.unwrap_or_default()
+    ))
+}
+
+/// Serialize the entities in TOML or JSON, depending on the file extension
+fn serialize(manifest: &EntityManifest, file: &Utf8Path) -> Result<String, Error> {
+    match file.extension() {
+        Some("toml") => Ok(toml::to_string(manifest)?),
+        _ => Ok(serde_json::to_string_pretty(manifest)?),
+    }
+}
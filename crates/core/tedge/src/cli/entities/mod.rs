@@ -0,0 +1,4 @@
+mod cli;
+mod command;
+
+pub use cli::TEdgeEntitiesCli;
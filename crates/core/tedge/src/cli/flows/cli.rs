@@ -1,3 +1,7 @@
+use crate::cli::flows::context::ContextAction;
+use crate::cli::flows::context::ContextCommand;
+use crate::cli::flows::errors::ErrorsAction;
+use crate::cli::flows::errors::ErrorsCommand;
 use crate::cli::flows::list::ListCommand;
 use crate::cli::flows::test::TestCommand;
 use crate::command::BuildCommand;
@@ -17,6 +21,7 @@ use tedge_flows::FlowContextHandle;
 use tedge_flows::JsRuntimeConfig;
 use tedge_flows::Message;
 use tedge_flows::MessageProcessor;
+use tedge_flows::WasmConfig;
 
 #[derive(clap::Subcommand, Debug)]
 pub enum TEdgeFlowsCli {
@@ -100,6 +105,50 @@ pub enum TEdgeFlowsCli {
         payload: Option<String>,
     },
 
+    /// Inspect or clear the flow context values persisted by a mapper
+    Context {
+        /// Mapper name
+        #[clap(long, default_value = "local", global = true)]
+        mapper: String,
+
+        /// Mapper profile
+        #[clap(long, global = true)]
+        profile: Option<String>,
+
+        /// Only the values of this flow, given by the path to its TOML definition
+        #[clap(long, value_hint = ValueHint::FilePath, global = true)]
+        flow: Option<Utf8PathBuf>,
+
+        /// Only the values with a key starting with this prefix
+        #[clap(long, global = true)]
+        key: Option<String>,
+
+        #[clap(subcommand)]
+        action: ContextAction,
+    },
+
+    /// Inspect, replay or purge the messages that the flows of a mapper failed to process
+    Errors {
+        /// Mapper name
+        #[clap(long, default_value = "local", global = true)]
+        mapper: String,
+
+        /// Mapper profile
+        #[clap(long, global = true)]
+        profile: Option<String>,
+
+        /// Only the messages of this flow, given by the path to its TOML definition, possibly relative to the flows directory
+        #[clap(long, value_hint = ValueHint::FilePath, global = true)]
+        flow: Option<Utf8PathBuf>,
+
+        /// Only the message with this identifier
+        #[clap(long, global = true)]
+        id: Option<u64>,
+
+        #[clap(subcommand)]
+        action: ErrorsAction,
+    },
+
     /// Display the path to the directory of flows and steps
     ConfigDir {
         /// Mapper name
@@ -171,6 +220,44 @@ impl BuildCommand for TEdgeFlowsCli {
                 .into_boxed())
             }
 
+            TEdgeFlowsCli::Context {
+                mapper,
+                profile,
+                flow,
+                key,
+                action,
+            } => {
+                let mapper_dir = Self::mapper_dir(config, &mapper, profile.as_deref());
+                let path = tedge_mapper::flows_context_path(config, &mapper_dir);
+                Ok(ContextCommand {
+                    path,
+                    flow,
+                    key,
+                    action,
+                }
+                .into_boxed())
+            }
+
+            TEdgeFlowsCli::Errors {
+                mapper,
+                profile,
+                flow,
+                id,
+                action,
+            } => {
+                let mapper_dir = Self::mapper_dir(config, &mapper, profile.as_deref());
+                let path = tedge_mapper::flows_errors_path(config, &mapper_dir);
+                let flows_dir = tedge_flows::flows_dir(&mapper_dir);
+                let flow = flow.map(|flow| ErrorsCommand::canonical_flow_path(&flows_dir, flow));
+                Ok(ErrorsCommand {
+                    path,
+                    flow,
+                    id,
+                    action,
+                }
+                .into_boxed())
+            }
+
             TEdgeFlowsCli::ConfigDir { mapper, profile } => {
                 let mapper_dir = Self::mapper_dir(config, &mapper, profile.as_deref());
                 let flows_dir = tedge_flows::flows_dir(&mapper_dir);
@@ -196,6 +283,14 @@ impl TEdgeFlowsCli {
         }
     }
 
+    fn wasm_config(config: &TEdgeConfig) -> WasmConfig {
+        let wasm = &config.flows.wasm;
+        WasmConfig {
+            memory_size: wasm.memory_size as usize,
+            fuel: wasm.fuel as u64,
+        }
+    }
+
     async fn init_processor(
         config: &TEdgeConfig,
         mapper_dir: &Utf8PathBuf,
@@ -204,7 +299,9 @@ impl TEdgeFlowsCli {
     ) -> Result<MessageProcessor<BaseFlowRegistry>, Error> {
         let registry = tedge_mapper::test_cli_flow_registry(config, mapper_dir, flows_dir).await?;
         let context = FlowContextHandle::default();
-        let processor = MessageProcessor::with_context(registry, js_config, context).await?;
+        let processor = MessageProcessor::with_context(registry, js_config, context)
+            .await?
+            .with_wasm_config(Self::wasm_config(config));
         Ok(processor)
     }
 
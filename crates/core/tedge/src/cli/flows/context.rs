@@ -0,0 +1,118 @@
+use crate::command::Command;
+use crate::log::MaybeFancy;
+use anyhow::Error;
+use camino::Utf8PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use tedge_config::TEdgeConfig;
+use tedge_flows::read_context_snapshot;
+use tedge_flows::write_context_snapshot;
+use tedge_flows::ContextEntry;
+use tedge_flows::ContextScope;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ContextAction {
+    /// List the persisted values
+    List,
+
+    /// Remove the persisted values
+    ///
+    /// A running mapper drops the removed values on its next context snapshot
+    Clear,
+}
+
+pub struct ContextCommand {
+    /// The snapshot file of the mapper
+    pub path: Utf8PathBuf,
+    pub flow: Option<Utf8PathBuf>,
+    pub key: Option<String>,
+    pub action: ContextAction,
+}
+
+#[async_trait::async_trait]
+impl Command for ContextCommand {
+    fn description(&self) -> String {
+        match self.action {
+            ContextAction::List => {
+                format!("list the flow context values persisted in {}", self.path)
+            }
+            ContextAction::Clear => {
+                format!("clear the flow context values persisted in {}", self.path)
+            }
+        }
+    }
+
+    async fn execute(&self, _config: TEdgeConfig) -> Result<(), MaybeFancy<Error>> {
+        let entries = read_context_snapshot(&self.path)
+            .await
+            .map_err(Error::from)?;
+        let flow = self.flow.as_ref().map(|flow| {
+            flow.canonicalize_utf8()
+                .unwrap_or_else(|_| flow.clone())
+                .to_string()
+        });
+        let (selected, kept): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .partition(|entry| self.is_selected(flow.as_deref(), entry));
+
+        match self.action {
+            ContextAction::List => {
+                let now = SystemTime::now();
+                selected
+                    .iter()
+                    .filter(|entry| !entry.is_expired(now))
+                    .for_each(Self::display);
+            }
+            ContextAction::Clear => {
+                if !selected.is_empty() {
+                    write_context_snapshot(&self.path, &kept)
+                        .await
+                        .map_err(Error::from)?;
+                }
+                eprintln!("Removed {} context values", selected.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ContextCommand {
+    fn is_selected(&self, flow: Option<&str>, entry: &ContextEntry) -> bool {
+        let flow_matches = match (flow, &entry.context) {
+            (None, _) => true,
+            (Some(_), ContextScope::Mapper) => false,
+            (Some(flow), ContextScope::Flow(path)) => path.ends_with(flow),
+            (Some(flow), ContextScope::Script(script)) => script
+                .split('|')
+                .next()
+                .is_some_and(|path| path.ends_with(flow)),
+        };
+        let key_matches = self
+            .key
+            .as_ref()
+            .is_none_or(|prefix| entry.key.starts_with(prefix));
+        flow_matches && key_matches
+    }
+
+    fn display(entry: &ContextEntry) {
+        let context = match &entry.context {
+            ContextScope::Mapper => "mapper".to_string(),
+            ContextScope::Flow(flow) => format!("flow {flow}"),
+            ContextScope::Script(script) => match script.splitn(3, '|').collect::<Vec<_>>()[..] {
+                [flow, index, path] => format!("flow {flow} step {index} {path}"),
+                _ => format!("step {script}"),
+            },
+        };
+        let expiry = entry
+            .expires_at
+            .map(|expires_at| {
+                let expires_at = SystemTime::UNIX_EPOCH + Duration::from_secs(expires_at);
+                format!(
+                    " (expires at {})",
+                    humantime::format_rfc3339_seconds(expires_at)
+                )
+            })
+            .unwrap_or_default();
+        println!("[{context}] {} = {}{expiry}", entry.key, entry.value);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::command::Command;
+use crate::log::MaybeFancy;
+use anyhow::Error;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use tedge_config::TEdgeConfig;
+use tedge_flows::read_dead_letters;
+use tedge_flows::update_dead_letters;
+use tedge_flows::DeadLetter;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ErrorsAction {
+    /// List the failed messages
+    List,
+
+    /// Have the failed messages processed again by their flow
+    ///
+    /// The messages are replayed by the running mapper within a few seconds,
+    /// or when next started if not running.
+    Replay,
+
+    /// Remove the failed messages
+    Purge,
+}
+
+pub struct ErrorsCommand {
+    /// The dead-letter file of the mapper
+    pub path: Utf8PathBuf,
+    /// The canonical path to the flow definition, if only the messages of this flow are selected
+    pub flow: Option<Utf8PathBuf>,
+    pub id: Option<u64>,
+    pub action: ErrorsAction,
+}
+
+#[async_trait::async_trait]
+impl Command for ErrorsCommand {
+    fn description(&self) -> String {
+        match self.action {
+            ErrorsAction::List => format!("list the failed flow messages kept in {}", self.path),
+            ErrorsAction::Replay => {
+                format!("replay the failed flow messages kept in {}", self.path)
+            }
+            ErrorsAction::Purge => format!("purge the failed flow messages kept in {}", self.path),
+        }
+    }
+
+    async fn execute(&self, _config: TEdgeConfig) -> Result<(), MaybeFancy<Error>> {
+        match self.action {
+            ErrorsAction::List => {
+                read_dead_letters(&self.path)
+                    .await
+                    .map_err(Error::from)?
+                    .iter()
+                    .filter(|letter| self.is_selected(letter))
+                    .for_each(Self::display);
+            }
+            ErrorsAction::Replay => {
+                let count = update_dead_letters(&self.path, |letters| {
+                    let mut count = 0;
+                    for letter in letters.iter_mut() {
+                        if self.is_selected(letter) && !letter.replay {
+                            letter.replay = true;
+                            count += 1;
+                        }
+                    }
+                    count
+                })
+                .await
+                .map_err(Error::from)?;
+                eprintln!("Marked {count} failed messages for replay");
+            }
+            ErrorsAction::Purge => {
+                let count = update_dead_letters(&self.path, |letters| {
+                    let count = letters.len();
+                    letters.retain(|letter| !self.is_selected(letter));
+                    count - letters.len()
+                })
+                .await
+                .map_err(Error::from)?;
+                eprintln!("Removed {count} failed messages");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ErrorsCommand {
+    /// Resolve the path to a flow definition, relative to the flows directory if not found as is
+    pub fn canonical_flow_path(flows_dir: &Utf8Path, flow: Utf8PathBuf) -> Utf8PathBuf {
+        flow.canonicalize_utf8()
+            .or_else(|_| flows_dir.join(&flow).canonicalize_utf8())
+            .unwrap_or(flow)
+    }
+
+    fn is_selected(&self, letter: &DeadLetter) -> bool {
+        let flow_matches = self.flow.as_ref().is_none_or(|flow| {
+            let letter_flow = Utf8Path::new(&letter.flow);
+            letter_flow
+                .canonicalize_utf8()
+                .is_ok_and(|letter_flow| &letter_flow == flow)
+                || letter_flow == flow
+        });
+        let id_matches = self.id.is_none_or(|id| letter.id == id);
+        flow_matches && id_matches
+    }
+
+    fn display(letter: &DeadLetter) {
+        let failed_at = SystemTime::UNIX_EPOCH + Duration::from_secs(letter.failed_at);
+        let replay = if letter.replay {
+            " (pending replay)"
+        } else {
+            ""
+        };
+        println!(
+            "#{} {} flow {} step {}{replay}\n  {}\n  {}",
+            letter.id,
+            humantime::format_rfc3339_seconds(failed_at),
+            letter.flow,
+            letter.step,
+            letter.error,
+            letter.message
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tedge_flows::Message;
+    use tedge_test_utils::fs::TempTedgeDir;
+
+    #[test]
+    fn flows_are_selected_by_their_canonical_path() {
+        let ttd = TempTedgeDir::new();
+        ttd.file("a.toml");
+        ttd.file("data.toml");
+        let flows_dir = Utf8Path::from_path(ttd.path()).unwrap();
+
+        let command = ErrorsCommand {
+            path: flows_dir.join("errors.jsonl"),
+            flow: Some(ErrorsCommand::canonical_flow_path(
+                flows_dir,
+                "a.toml".into(),
+            )),
+            id: None,
+            action: ErrorsAction::List,
+        };
+
+        assert!(command.is_selected(&letter(flows_dir.join("a.toml"))));
+        assert!(!command.is_selected(&letter(flows_dir.join("data.toml"))));
+    }
+
+    fn letter(flow: Utf8PathBuf) -> DeadLetter {
+        DeadLetter {
+            id: 1,
+            flow: flow.to_string(),
+            step: "step.js".to_string(),
+            error: "error".to_string(),
+            failed_at: 0,
+            message: Message::new("topic", "payload"),
+            replay: false,
+        }
+    }
+}
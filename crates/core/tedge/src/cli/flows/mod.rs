@@ -1,4 +1,6 @@
 mod cli;
+mod context;
+mod errors;
 mod list;
 mod test;
 
@@ -1,5 +1,6 @@
 use crate::cli::http::command::HttpAction;
 use crate::cli::http::command::HttpCommand;
+use crate::cli::http::token::TokenCli;
 use crate::command::BuildCommand;
 use crate::command::Command;
 use crate::ConfigError;
@@ -19,6 +20,16 @@ use tokio::fs::File;
 
 #[derive(clap::Subcommand, Debug)]
 pub enum TEdgeHttpCli {
+    #[command(flatten)]
+    Request(HttpRequestCli),
+
+    /// Manage the bearer tokens of the agent HTTP API clients
+    #[clap(subcommand)]
+    Token(TokenCli),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum HttpRequestCli {
     /// GET content from thin-edge local HTTP servers
     ///
     /// Examples:
@@ -196,6 +207,16 @@ impl Content {
 
 #[async_trait::async_trait]
 impl BuildCommand for TEdgeHttpCli {
+    async fn build_command(self, config: &TEdgeConfig) -> Result<Box<dyn Command>, ConfigError> {
+        match self {
+            TEdgeHttpCli::Request(request) => request.build_command(config).await,
+            TEdgeHttpCli::Token(token) => token.build_command(config).await,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl BuildCommand for HttpRequestCli {
     async fn build_command(self, config: &TEdgeConfig) -> Result<Box<dyn Command>, ConfigError> {
         let uri = self.uri();
 
@@ -227,10 +248,10 @@ impl BuildCommand for TEdgeHttpCli {
     }
 }
 
-impl From<TEdgeHttpCli> for HttpAction {
-    fn from(value: TEdgeHttpCli) -> Self {
+impl From<HttpRequestCli> for HttpAction {
+    fn from(value: HttpRequestCli) -> Self {
         match value {
-            TEdgeHttpCli::Post {
+            HttpRequestCli::Post {
                 content,
                 content_type,
                 accept_type,
@@ -240,7 +261,7 @@ impl From<TEdgeHttpCli> for HttpAction {
                 content_type,
                 accept_type,
             },
-            TEdgeHttpCli::Put {
+            HttpRequestCli::Put {
                 content,
                 content_type,
                 accept_type,
@@ -250,7 +271,7 @@ impl From<TEdgeHttpCli> for HttpAction {
                 content_type,
                 accept_type,
             },
-            TEdgeHttpCli::Patch {
+            HttpRequestCli::Patch {
                 content,
                 content_type,
                 accept_type,
@@ -260,34 +281,46 @@ impl From<TEdgeHttpCli> for HttpAction {
                 content_type,
                 accept_type,
             },
-            TEdgeHttpCli::Get { accept_type, .. } => HttpAction::Get { accept_type },
-            TEdgeHttpCli::Delete { .. } => HttpAction::Delete,
+            HttpRequestCli::Get { accept_type, .. } => HttpAction::Get { accept_type },
+            HttpRequestCli::Delete { .. } => HttpAction::Delete,
         }
     }
 }
 
-impl TEdgeHttpCli {
+impl HttpRequestCli {
     fn uri(&self) -> &str {
         match self {
-            TEdgeHttpCli::Post { uri, .. }
-            | TEdgeHttpCli::Put { uri, .. }
-            | TEdgeHttpCli::Get { uri, .. }
-            | TEdgeHttpCli::Patch { uri, .. }
-            | TEdgeHttpCli::Delete { uri, .. } => uri.as_ref(),
+            HttpRequestCli::Post { uri, .. }
+            | HttpRequestCli::Put { uri, .. }
+            | HttpRequestCli::Get { uri, .. }
+            | HttpRequestCli::Patch { uri, .. }
+            | HttpRequestCli::Delete { uri, .. } => uri.as_ref(),
         }
     }
 
     fn c8y_profile(&self) -> Option<&ProfileName> {
         match self {
-            TEdgeHttpCli::Post { profile, .. }
-            | TEdgeHttpCli::Put { profile, .. }
-            | TEdgeHttpCli::Get { profile, .. }
-            | TEdgeHttpCli::Patch { profile, .. }
-            | TEdgeHttpCli::Delete { profile, .. } => profile.as_ref(),
+            HttpRequestCli::Post { profile, .. }
+            | HttpRequestCli::Put { profile, .. }
+            | HttpRequestCli::Get { profile, .. }
+            | HttpRequestCli::Patch { profile, .. }
+            | HttpRequestCli::Delete { profile, .. } => profile.as_ref(),
         }
     }
 }
 
+/// Build an HTTP client for the local thin-edge HTTP server, along the base url of this server
+pub(crate) async fn tedge_http_client(
+    config: &TEdgeConfig,
+) -> Result<(Client, String), ConfigError> {
+    let client = &config.http.client;
+    let protocol = https_if_some(&config.http.cert_path);
+    let base_url = format!("{protocol}://{}:{}", client.host, client.port);
+    let identity = config.http.client.auth.identity()?;
+    let client = http_client(config.cloud_root_certs().await?, identity.as_ref())?;
+    Ok((client, base_url))
+}
+
 fn https_if_some<T>(cert_path: &OptionalConfig<T>) -> &'static str {
     cert_path.or_none().map_or("http", |_| "https")
 }
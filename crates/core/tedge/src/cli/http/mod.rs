@@ -1,4 +1,6 @@
 mod cli;
 mod command;
+mod token;
 
+pub(crate) use cli::tedge_http_client;
 pub use cli::TEdgeHttpCli;
@@ -0,0 +1,188 @@
+use crate::command::BuildCommand;
+use crate::command::Command;
+use crate::log::MaybeFancy;
+use crate::ConfigError;
+use anyhow::anyhow;
+use anyhow::Error;
+use base64::prelude::*;
+use camino::Utf8PathBuf;
+use ring::rand::SecureRandom;
+use ring::rand::SystemRandom;
+use tedge_agent::http_access::token_digest;
+use tedge_agent::http_access::Access;
+use tedge_agent::http_access::AccessPolicies;
+use tedge_agent::http_access::ClientPolicy;
+use tedge_agent::http_access::EntityGrant;
+use tedge_agent::http_access::FileGrant;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_config::TEdgeConfig;
+
+/// The number of random bytes of a token
+const TOKEN_SIZE: usize = 32;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum TokenCli {
+    /// Create a bearer token granting a client access to the agent HTTP API
+    ///
+    /// The token is printed once and only its SHA-256 digest is stored in the access policy file,
+    /// along the entity subtrees and file transfer paths granted to the client.
+    /// Creating a token for a client name already used replaces the previous token and grants of this client,
+    /// keeping its certificate, if any.
+    ///
+    /// Examples:
+    ///   # Grant child1 full access to its own entities and files
+    ///   tedge http token create child1 --write-entity device/child1// --write-files child1
+    ///
+    ///   # Grant a client read-only access to all the entities
+    ///   tedge http token create dashboard --read-entity device/main//
+    #[clap(verbatim_doc_comment)]
+    Create {
+        /// The name of the client
+        name: String,
+
+        /// Topic id of an entity, which subtree the client is granted read access to
+        #[clap(long)]
+        read_entity: Vec<EntityTopicId>,
+
+        /// Topic id of an entity, which subtree the client is granted read and write access to
+        #[clap(long)]
+        write_entity: Vec<EntityTopicId>,
+
+        /// File transfer path prefix the client is granted read access to
+        #[clap(long)]
+        read_files: Vec<Utf8PathBuf>,
+
+        /// File transfer path prefix the client is granted read and write access to
+        #[clap(long)]
+        write_files: Vec<Utf8PathBuf>,
+    },
+}
+
+#[async_trait::async_trait]
+impl BuildCommand for TokenCli {
+    async fn build_command(self, config: &TEdgeConfig) -> Result<Box<dyn Command>, ConfigError> {
+        let policy_file = config.http.access.policy_file.clone().into();
+        match self {
+            TokenCli::Create {
+                name,
+                read_entity,
+                write_entity,
+                read_files,
+                write_files,
+            } => {
+                let entities = grants(read_entity, write_entity, |topic_id, access| EntityGrant {
+                    topic_id,
+                    access,
+                });
+                let files = grants(read_files, write_files, |prefix, access| FileGrant {
+                    prefix,
+                    access,
+                });
+                Ok(CreateTokenCommand {
+                    policy_file,
+                    name,
+                    entities,
+                    files,
+                }
+                .into_boxed())
+            }
+        }
+    }
+}
+
+fn grants<T, G>(read: Vec<T>, write: Vec<T>, grant: impl Fn(T, Access) -> G) -> Vec<G> {
+    let read = read.into_iter().map(|t| grant(t, Access::Read));
+    let write = write.into_iter().map(|t| grant(t, Access::Write));
+    read.chain(write).collect()
+}
+
+pub struct CreateTokenCommand {
+    policy_file: Utf8PathBuf,
+    name: String,
+    entities: Vec<EntityGrant>,
+    files: Vec<FileGrant>,
+}
+
+#[async_trait::async_trait]
+impl Command for CreateTokenCommand {
+    fn description(&self) -> String {
+        format!("create an HTTP access token for {}", self.name)
+    }
+
+    async fn execute(&self, config: TEdgeConfig) -> Result<(), MaybeFancy<Error>> {
+        let token = self.run()?;
+        if !config.http.access.enable {
+            eprintln!(
+                "The agent HTTP API access control is not enabled: set http.access.enable to true"
+            );
+        }
+        println!("{token}");
+        Ok(())
+    }
+}
+
+impl CreateTokenCommand {
+    fn run(&self) -> Result<String, Error> {
+        let token = random_token()?;
+        let mut policies = AccessPolicies::load(&self.policy_file)?;
+        let certificate = policies
+            .clients
+            .iter()
+            .find(|client| client.name == self.name)
+            .and_then(|client| client.certificate.clone());
+        policies.insert(ClientPolicy {
+            name: self.name.clone(),
+            certificate,
+            token_sha256: Some(token_digest(&token)),
+            entities: self.entities.clone(),
+            files: self.files.clone(),
+        });
+        policies.store(&self.policy_file)?;
+        Ok(token)
+    }
+}
+
+fn random_token() -> Result<String, Error> {
+    let mut bytes = [0u8; TOKEN_SIZE];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| anyhow!("Failed to generate a random token"))?;
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tedge_test_utils::fs::TempTedgeDir;
+
+    #[test]
+    fn recreating_a_token_keeps_the_client_certificate() {
+        let ttd = TempTedgeDir::new();
+        let policy_file = Utf8PathBuf::from_path_buf(ttd.path().join("http-access.toml")).unwrap();
+        let mut policies = AccessPolicies::default();
+        policies.insert(ClientPolicy {
+            name: "child1".to_string(),
+            certificate: Some("child1-cn".to_string()),
+            token_sha256: None,
+            entities: vec![],
+            files: vec![],
+        });
+        policies.store(&policy_file).unwrap();
+
+        let command = CreateTokenCommand {
+            policy_file: policy_file.clone(),
+            name: "child1".to_string(),
+            entities: vec![],
+            files: vec![],
+        };
+        let token = command.run().unwrap();
+
+        let policies = AccessPolicies::load(&policy_file).unwrap();
+        assert_eq!(policies.clients.len(), 1);
+        assert_eq!(
+            policies.clients[0].certificate.as_deref(),
+            Some("child1-cn")
+        );
+        assert_eq!(policies.clients[0].token_sha256, Some(token_digest(&token)));
+    }
+}
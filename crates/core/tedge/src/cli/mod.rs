@@ -26,6 +26,7 @@ pub mod config;
 mod connect;
 mod diag;
 mod disconnect;
+mod entities;
 mod flows;
 mod http;
 mod init;
@@ -35,6 +36,7 @@ mod mqtt;
 mod reconnect;
 mod refresh_bridges;
 mod upload;
+mod workflow;
 
 #[derive(clap::Parser, Debug)]
 #[clap(
@@ -157,6 +159,10 @@ pub enum TEdgeOpt {
     #[clap(subcommand)]
     Http(http::TEdgeHttpCli),
 
+    /// Export and import the entities registered on the device
+    #[clap(subcommand)]
+    Entities(entities::TEdgeEntitiesCli),
+
     /// Monitor and test flows
     #[clap(subcommand)]
     Flows(flows::TEdgeFlowsCli),
@@ -165,6 +171,10 @@ pub enum TEdgeOpt {
     #[clap(subcommand)]
     Mapper(mapper::MapperCli),
 
+    /// Check and visualize operation workflows
+    #[clap(subcommand)]
+    Workflow(workflow::TEdgeWorkflowCli),
+
     /// Run thin-edge services and plugins
     Run(ComponentOpt),
 
@@ -242,9 +252,11 @@ impl BuildCommand for TEdgeOpt {
             TEdgeOpt::RefreshBridges => RefreshBridgesCmd::new(config).map(Command::into_boxed),
             TEdgeOpt::Mqtt(opt) => opt.build_command(config).await,
             TEdgeOpt::Http(opt) => opt.build_command(config).await,
+            TEdgeOpt::Entities(opt) => opt.build_command(config).await,
             TEdgeOpt::Reconnect(opt) => opt.build_command(config).await,
             TEdgeOpt::Flows(opt) => opt.build_command(config).await,
             TEdgeOpt::Mapper(opt) => opt.build_command(config).await,
+            TEdgeOpt::Workflow(opt) => opt.build_command(config).await,
             TEdgeOpt::Bridge(opt) => opt.build_command(config).await,
             TEdgeOpt::Run(_) => {
                 // This method has to be kept in sync with tedge::redirect_if_multicall()
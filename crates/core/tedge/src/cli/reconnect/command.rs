@@ -52,6 +52,7 @@ impl From<&ReconnectBridgeCommand> for ConnectCommand {
             cloud: reconnect_cmd.cloud.clone(),
             is_test_connection: false,
             offline_mode: reconnect_cmd.offline_mode,
+            provision: false,
             service_manager: reconnect_cmd.service_manager.clone(),
             is_reconnect: true,
         }
@@ -0,0 +1,309 @@
+use std::io::Write;
+use std::ops::Range;
+
+use anyhow::Context;
+use ariadne::Color;
+use ariadne::Config;
+use ariadne::Label;
+use ariadne::Report;
+use ariadne::ReportKind;
+use ariadne::Source;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use serde_json::Map;
+use serde_json::Value;
+use tedge_api::workflow::check_workflow;
+use tedge_api::workflow::IssueSeverity;
+use tedge_api::workflow::WorkflowIssue;
+use tedge_config::TEdgeConfig;
+use yansi::Paint as _;
+
+use crate::command::Command;
+use crate::log::MaybeFancy;
+
+/// Checks operation workflow definitions
+#[derive(clap::Args, Debug, Eq, PartialEq)]
+pub struct WorkflowCheckCmd {
+    /// The workflow definition files to check
+    #[clap(required = true)]
+    files: Vec<Utf8PathBuf>,
+}
+
+#[async_trait::async_trait]
+impl Command for WorkflowCheckCmd {
+    fn description(&self) -> String {
+        "check operation workflow definitions".to_string()
+    }
+
+    #[mutants::skip]
+    async fn execute(&self, _config: TEdgeConfig) -> Result<(), MaybeFancy<anyhow::Error>> {
+        let mut invalid_workflows = 0;
+        for file in &self.files {
+            let source = tokio::fs::read_to_string(file)
+                .await
+                .with_context(|| format!("Failed to read {file}"))?;
+            let issues = check_source(&source);
+            print_issues(&mut std::io::stdout(), file, &source, &issues);
+            if issues.iter().any(|(issue, _)| issue.is_error()) {
+                invalid_workflows += 1;
+            }
+        }
+
+        if invalid_workflows > 0 {
+            Err(anyhow::anyhow!("Some invalid workflows"))?
+        }
+        Ok(())
+    }
+}
+
+/// Check a workflow definition, returning the issues along with their location in the source
+fn check_source(source: &str) -> Vec<(WorkflowIssue, Option<Range<usize>>)> {
+    let definition = match toml::from_str::<toml::Table>(source) {
+        Ok(definition) => definition,
+        Err(err) => {
+            let issue = WorkflowIssue {
+                severity: IssueSeverity::Error,
+                message: err.message().to_string(),
+                state: None,
+                key: None,
+                excerpt: None,
+            };
+            return vec![(issue, err.span())];
+        }
+    };
+    let definition = match serde_json::to_value(definition) {
+        Ok(Value::Object(definition)) => definition,
+        _ => Map::new(),
+    };
+
+    let mut issues: Vec<_> = check_workflow(&definition)
+        .into_iter()
+        .map(|issue| {
+            let span = locate(source, &issue);
+            (issue, span)
+        })
+        .collect();
+    issues.sort_by_key(|(_, span)| span.as_ref().map_or(usize::MAX, |span| span.start));
+    issues
+}
+
+fn print_issues(
+    w: &mut impl Write,
+    path: &Utf8Path,
+    source: &str,
+    issues: &[(WorkflowIssue, Option<Range<usize>>)],
+) {
+    for (issue, span) in issues {
+        let (kind, color) = match issue.severity {
+            IssueSeverity::Error => (ReportKind::Error, Color::Red),
+            IssueSeverity::Warning => (ReportKind::Warning, Color::Yellow),
+        };
+        let message = match (&issue.state, span) {
+            (Some(state), None) => format!("`{state}`: {}", issue.message),
+            _ => issue.message.clone(),
+        };
+
+        let mut report = Report::build(kind, (path.as_str(), span.clone().unwrap_or(0..0)))
+            .with_config(Config::default().with_compact(false))
+            .with_message(message);
+        if let Some(span) = span {
+            let label = match &issue.state {
+                Some(state) => format!("in the `{state}` state"),
+                None => "here".to_string(),
+            };
+            report = report.with_label(
+                Label::new((path.as_str(), span.clone()))
+                    .with_message(label)
+                    .with_color(color),
+            );
+        }
+
+        report
+            .finish()
+            .write((path.as_str(), Source::from(source)), &mut *w)
+            .unwrap();
+    }
+
+    let errors = issues.iter().filter(|(issue, _)| issue.is_error()).count();
+    let warnings = issues.len() - errors;
+    let _ = if errors > 0 {
+        writeln!(
+            w,
+            "{} {path}: {errors} error(s), {warnings} warning(s)",
+            "Invalid workflow:".red().bold()
+        )
+    } else if warnings > 0 {
+        writeln!(
+            w,
+            "{} {path}: {warnings} warning(s)",
+            "Valid workflow:".yellow().bold()
+        )
+    } else {
+        writeln!(w, "{} {path}", "Valid workflow:".green().bold())
+    };
+}
+
+/// Locate in the source the text related to an issue
+///
+/// This is the faulty text if known, otherwise the faulty key or the header of the faulty state.
+fn locate(source: &str, issue: &WorkflowIssue) -> Option<Range<usize>> {
+    let state = issue.state.as_deref();
+    let (header, region) = match state {
+        Some(state) => {
+            let (header, region) = state_section(source, state)?;
+            (Some(header), region)
+        }
+        None => (None, 0..source.len()),
+    };
+
+    let key_line = issue
+        .key
+        .as_deref()
+        .and_then(|key| find_key(source, region.clone(), state, key));
+
+    if let Some(excerpt) = issue.excerpt.as_deref() {
+        let from = key_line.as_ref().map_or(region.start, |line| line.start);
+        if let Some(pos) = source[from..region.end].find(excerpt) {
+            let start = from + pos;
+            return Some(start..start + excerpt.len());
+        }
+    }
+
+    key_line.or(header)
+}
+
+/// Find the header of a state table and the region of the source where this state is defined
+fn state_section(source: &str, state: &str) -> Option<(Range<usize>, Range<usize>)> {
+    let header = format!("[{state}]");
+    let sub_header = format!("[{state}.");
+    let mut section: Option<(Range<usize>, Range<usize>)> = None;
+    for (range, line) in lines(source, 0..source.len()) {
+        match &mut section {
+            None if line.starts_with(&header) => section = Some((range.clone(), range)),
+            None => {}
+            Some(_) if line.starts_with('[') && !line.starts_with(&sub_header) => break,
+            Some((_, region)) => region.end = range.end,
+        }
+    }
+    section
+}
+
+/// Find the line where a key is defined, either as `key = value`, `key.field = value` or `[state.key]`
+fn find_key(
+    source: &str,
+    region: Range<usize>,
+    state: Option<&str>,
+    key: &str,
+) -> Option<Range<usize>> {
+    let sub_header = state.map(|state| format!("[{state}.{key}"));
+    lines(source, region)
+        .find(|(_, line)| {
+            let is_key = line
+                .strip_prefix(key)
+                .and_then(|rest| rest.chars().next())
+                .is_some_and(|c| c == '=' || c == '.' || c.is_whitespace());
+            let is_sub_header = sub_header
+                .as_deref()
+                .is_some_and(|sub_header| line.starts_with(sub_header));
+            is_key || is_sub_header
+        })
+        .map(|(range, _)| range)
+}
+
+/// The trimmed lines of a region of the source, along with their position in the source
+fn lines(source: &str, region: Range<usize>) -> impl Iterator<Item = (Range<usize>, &str)> {
+    let mut offset = region.start;
+    source[region].split_inclusive('\n').map(move |line| {
+        let start = offset + line.len() - line.trim_start().len();
+        offset += line.len();
+        let line = line.trim();
+        (start..start + line.len(), line)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKFLOW: &str = r#"
+operation = "check"
+
+[init]
+action = "proceed"
+on_success = "next"
+
+[next]
+script = "/some/script.sh ${.payload.x} ${.topic.unknown}"
+on_success = "unknown"
+
+[next.on_error]
+status = "failed"
+reason = "script failed"
+
+[orphan]
+action = "proceed"
+on_success = "successful"
+
+[broken]
+action = "do-something"
+on_success = "successful"
+
+[successful]
+action = "cleanup"
+
+[failed]
+action = "cleanup"
+"#;
+
+    fn located_issues(source: &str) -> Vec<(Option<String>, &str)> {
+        check_source(source)
+            .into_iter()
+            .filter_map(|(issue, span)| Some((issue.state, &source[span?])))
+            .collect()
+    }
+
+    #[test]
+    fn locate_faulty_texts() {
+        let issues = located_issues(WORKFLOW);
+        assert!(issues.contains(&(Some("broken".to_string()), "do-something")));
+        assert!(issues.contains(&(Some("next".to_string()), "${.topic.unknown}")));
+        assert!(issues.contains(&(Some("next".to_string()), "unknown")));
+    }
+
+    #[test]
+    fn locate_faulty_states() {
+        let issues = located_issues(WORKFLOW);
+        assert!(issues.contains(&(Some("orphan".to_string()), "[orphan]")));
+    }
+
+    #[test]
+    fn issues_are_sorted_by_location() {
+        let starts: Vec<_> = check_source(WORKFLOW)
+            .into_iter()
+            .filter_map(|(_, span)| span)
+            .map(|span| span.start)
+            .collect();
+        let mut sorted = starts.clone();
+        sorted.sort();
+        assert_eq!(starts, sorted);
+    }
+
+    #[test]
+    fn locate_toml_syntax_errors() {
+        let source = "operation = \"check\"\n[init\naction = \"proceed\"\n";
+        let issues = check_source(source);
+        assert_eq!(issues.len(), 1);
+        let (issue, span) = &issues[0];
+        assert!(issue.is_error());
+        assert!(span.is_some());
+    }
+
+    #[test]
+    fn find_keys_defined_as_sub_tables() {
+        let source = "[init]\naction = \"proceed\"\n\n[init.input]\nx = \"${.payload.x}\"\n";
+        let (_, region) = state_section(source, "init").unwrap();
+        assert_eq!(region, 0..source.len() - 1);
+        let key = find_key(source, region, Some("init"), "input").unwrap();
+        assert_eq!(&source[key], "[init.input]");
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use tedge_api::workflow::OperationAction;
+use tedge_api::workflow::OperationWorkflow;
+use tedge_api::workflow::StateName;
+use tedge_config::TEdgeConfig;
+
+use crate::command::Command;
+use crate::log::MaybeFancy;
+
+/// Exports the state machine of an operation workflow as a graph
+#[derive(clap::Args, Debug, Eq, PartialEq)]
+pub struct WorkflowGraphCmd {
+    /// The workflow definition file
+    file: Utf8PathBuf,
+
+    /// The output format
+    #[clap(long, default_value = "dot")]
+    format: GraphFormat,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GraphFormat {
+    /// Graphviz DOT language
+    Dot,
+
+    /// Mermaid state diagram
+    Mermaid,
+}
+
+#[async_trait::async_trait]
+impl Command for WorkflowGraphCmd {
+    fn description(&self) -> String {
+        format!("export the state machine of the workflow {}", self.file)
+    }
+
+    #[mutants::skip]
+    async fn execute(&self, _config: TEdgeConfig) -> Result<(), MaybeFancy<anyhow::Error>> {
+        let file = &self.file;
+        let source = tokio::fs::read_to_string(file)
+            .await
+            .with_context(|| format!("Failed to read {file}"))?;
+        let workflow: OperationWorkflow = toml::from_str(&source).with_context(|| {
+            format!("Invalid workflow {file}: run `tedge workflow check {file}` for details")
+        })?;
+
+        let graph = StateGraph::new(&workflow);
+        let output = match self.format {
+            GraphFormat::Dot => graph.to_dot(),
+            GraphFormat::Mermaid => graph.to_mermaid(),
+        };
+        print!("{output}");
+        Ok(())
+    }
+}
+
+/// The states and transitions of a workflow, in a deterministic order
+struct StateGraph {
+    operation: String,
+
+    /// All the states, starting with those reachable from `init`
+    states: Vec<StateName>,
+
+    /// The terminal states, i.e. those where the command is cleared
+    terminals: BTreeSet<StateName>,
+
+    /// The transitions between states, labeled with the handlers triggering them
+    transitions: Vec<(StateName, StateName, String)>,
+}
+
+impl StateGraph {
+    fn new(workflow: &OperationWorkflow) -> Self {
+        let next_states = |state: &str| {
+            workflow
+                .states
+                .get(state)
+                .map(OperationAction::next_states)
+                .unwrap_or_default()
+        };
+
+        // Breadth-first from init, then the unreachable states in alphabetical order
+        let mut states = Vec::new();
+        let mut queue = VecDeque::from(["init".to_string()]);
+        while let Some(state) = queue.pop_front() {
+            if states.contains(&state) {
+                continue;
+            }
+            queue.extend(next_states(&state).into_iter().map(|(_, target)| target));
+            states.push(state);
+        }
+        let mut unreachable: Vec<_> = workflow
+            .states
+            .keys()
+            .filter(|state| !states.contains(state))
+            .cloned()
+            .collect();
+        unreachable.sort();
+        states.extend(unreachable);
+
+        let terminals = states
+            .iter()
+            .filter(|state| {
+                workflow
+                    .states
+                    .get(state.as_str())
+                    .is_some_and(|action| action == &OperationAction::Clear)
+            })
+            .cloned()
+            .collect();
+
+        // Transitions between the same states are merged
+        let mut transitions: Vec<(StateName, StateName, String)> = Vec::new();
+        for state in states.iter() {
+            for (handler, target) in next_states(state) {
+                match transitions
+                    .iter_mut()
+                    .find(|(from, to, _)| from == state && to == &target)
+                {
+                    Some((_, _, label)) => {
+                        label.push_str(", ");
+                        label.push_str(&handler);
+                    }
+                    None => transitions.push((state.clone(), target, handler)),
+                }
+            }
+        }
+
+        StateGraph {
+            operation: workflow.operation.to_string(),
+            states,
+            terminals,
+            transitions,
+        }
+    }
+
+    fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph \"{}\" {{", self.operation);
+        let _ = writeln!(dot, "    node [shape=box, style=rounded];");
+        let _ = writeln!(dot, "    \"[*]\" [shape=point];");
+        for state in self.states.iter() {
+            if self.terminals.contains(state) {
+                let _ = writeln!(dot, "    \"{state}\" [shape=doublecircle];");
+            } else {
+                let _ = writeln!(dot, "    \"{state}\";");
+            }
+        }
+        let _ = writeln!(dot, "    \"[*]\" -> \"init\";");
+        for (from, to, label) in self.transitions.iter() {
+            let _ = writeln!(dot, "    \"{from}\" -> \"{to}\" [label=\"{label}\"];");
+        }
+        let _ = writeln!(dot, "}}");
+        dot
+    }
+
+    fn to_mermaid(&self) -> String {
+        // Mermaid state ids are restricted to alphanumeric characters and underscores
+        let id = |state: &str| {
+            if state.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                state.to_string()
+            } else {
+                let index = self.states.iter().position(|s| s == state);
+                format!("state_{}", index.unwrap_or(self.states.len()))
+            }
+        };
+
+        let mut mermaid = String::new();
+        let _ = writeln!(mermaid, "---");
+        let _ = writeln!(mermaid, "title: {}", self.operation);
+        let _ = writeln!(mermaid, "---");
+        let _ = writeln!(mermaid, "stateDiagram-v2");
+        for state in self.states.iter() {
+            if id(state) != *state {
+                let _ = writeln!(mermaid, "    state \"{state}\" as {}", id(state));
+            }
+        }
+        let _ = writeln!(mermaid, "    [*] --> init");
+        for (from, to, label) in self.transitions.iter() {
+            let _ = writeln!(mermaid, "    {} --> {} : {label}", id(from), id(to));
+        }
+        for state in self.terminals.iter() {
+            let _ = writeln!(mermaid, "    {} --> [*]", id(state));
+        }
+        mermaid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORKFLOW: &str = r#"
+operation = "restart"
+
+[init]
+action = "proceed"
+on_success = "executing"
+
+[executing]
+script = "/some/script.sh"
+on_success = "successful"
+on_error = "failed"
+on_kill = "failed"
+
+[successful]
+action = "cleanup"
+
+[failed]
+action = "cleanup"
+"#;
+
+    #[test]
+    fn export_workflow_as_dot_graph() {
+        let workflow: OperationWorkflow = toml::from_str(WORKFLOW).unwrap();
+        let dot = StateGraph::new(&workflow).to_dot();
+        assert_eq!(
+            dot,
+            r#"digraph "restart" {
+    node [shape=box, style=rounded];
+    "[*]" [shape=point];
+    "init";
+    "executing";
+    "successful" [shape=doublecircle];
+    "failed" [shape=doublecircle];
+    "[*]" -> "init";
+    "init" -> "executing" [label="on_success"];
+    "executing" -> "successful" [label="on_success"];
+    "executing" -> "failed" [label="on_error, on_kill"];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn export_workflow_as_mermaid_diagram() {
+        let workflow: OperationWorkflow = toml::from_str(WORKFLOW).unwrap();
+        let mermaid = StateGraph::new(&workflow).to_mermaid();
+        assert_eq!(
+            mermaid,
+            r#"---
+title: restart
+---
+stateDiagram-v2
+    [*] --> init
+    init --> executing : on_success
+    executing --> successful : on_success
+    executing --> failed : on_error, on_kill
+    failed --> [*]
+    successful --> [*]
+"#
+        );
+    }
+
+    #[test]
+    fn unreachable_states_are_listed_last() {
+        let workflow: OperationWorkflow = toml::from_str(&format!(
+            "{WORKFLOW}\n[orphan-state]\naction = \"proceed\"\non_success = \"successful\"\n"
+        ))
+        .unwrap();
+        let graph = StateGraph::new(&workflow);
+        assert_eq!(
+            graph.states.last().map(String::as_str),
+            Some("orphan-state")
+        );
+
+        let mermaid = graph.to_mermaid();
+        assert!(mermaid.contains("    state \"orphan-state\" as state_4\n"));
+        assert!(mermaid.contains("    state_4 --> successful : on_success\n"));
+    }
+}
@@ -0,0 +1,38 @@
+use tedge_config::TEdgeConfig;
+
+use crate::command::BuildCommand;
+use crate::command::Command;
+use crate::ConfigError;
+
+mod check;
+mod graph;
+mod simulate;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum TEdgeWorkflowCli {
+    /// Check operation workflow definitions
+    ///
+    /// Report ill-defined states, unknown actions, transitions to undefined states,
+    /// unreachable states, dead ends, missing terminal states and invalid `${...}` path expressions.
+    Check(check::WorkflowCheckCmd),
+
+    /// Export the state machine of an operation workflow as a graph
+    Graph(graph::WorkflowGraphCmd),
+
+    /// Simulate the execution of a command along an operation workflow
+    ///
+    /// Print each state transition and the final payload of the command,
+    /// using mocked outcomes for the scripts, downloads, builtin actions and sub-operations.
+    Simulate(simulate::WorkflowSimulateCmd),
+}
+
+#[async_trait::async_trait]
+impl BuildCommand for TEdgeWorkflowCli {
+    async fn build_command(self, _config: &TEdgeConfig) -> Result<Box<dyn Command>, ConfigError> {
+        match self {
+            Self::Check(cmd) => Ok(cmd.into_boxed()),
+            Self::Graph(cmd) => Ok(cmd.into_boxed()),
+            Self::Simulate(cmd) => Ok(cmd.into_boxed()),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::Write;
+
+use anyhow::Context;
+use camino::Utf8PathBuf;
+use serde_json::Value;
+use tedge_api::workflow::simulation::MockedOutcomes;
+use tedge_api::workflow::simulation::SimulatedTransition;
+use tedge_api::workflow::simulation::WorkflowSimulation;
+use tedge_api::workflow::GenericCommandState;
+use tedge_api::workflow::OperationWorkflow;
+use tedge_config::TEdgeConfig;
+use yansi::Paint as _;
+
+use crate::command::Command;
+use crate::log::MaybeFancy;
+
+/// Simulates the execution of a command along an operation workflow
+///
+/// No scripts are run and nothing is published over MQTT:
+/// the outcomes of the scripts, downloads, builtin actions and sub-operations are mocked.
+#[derive(clap::Args, Debug, Eq, PartialEq)]
+pub struct WorkflowSimulateCmd {
+    /// The workflow definition file
+    file: Utf8PathBuf,
+
+    /// The init payload of the command, as a JSON object
+    #[clap(long, default_value = "{}", value_parser = parse_payload)]
+    payload: Value,
+
+    /// A TOML file defining, state by state, the mocked outcomes of the workflow steps
+    ///
+    /// A step with no mocked outcome is successful.
+    #[clap(long)]
+    outcomes: Option<Utf8PathBuf>,
+
+    /// The id of the simulated command
+    #[clap(long, default_value = "simulation")]
+    cmd_id: String,
+
+    /// The number of steps after which the simulation is aborted
+    #[clap(long, default_value_t = 100)]
+    max_steps: usize,
+}
+
+fn parse_payload(payload: &str) -> Result<Value, String> {
+    match serde_json::from_str(payload) {
+        Ok(payload @ Value::Object(_)) => Ok(payload),
+        Ok(_) => Err("expecting a JSON object".to_string()),
+        Err(err) => Err(format!("invalid JSON: {err}")),
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for WorkflowSimulateCmd {
+    fn description(&self) -> String {
+        format!("simulate a command along the workflow {}", self.file)
+    }
+
+    #[mutants::skip]
+    async fn execute(&self, _config: TEdgeConfig) -> Result<(), MaybeFancy<anyhow::Error>> {
+        let file = &self.file;
+        let source = tokio::fs::read_to_string(file)
+            .await
+            .with_context(|| format!("Failed to read {file}"))?;
+        let workflow: OperationWorkflow = toml::from_str(&source).with_context(|| {
+            format!("Invalid workflow {file}: run `tedge workflow check {file}` for details")
+        })?;
+
+        let outcomes: MockedOutcomes = match &self.outcomes {
+            None => MockedOutcomes::default(),
+            Some(outcomes) => {
+                let source = tokio::fs::read_to_string(outcomes)
+                    .await
+                    .with_context(|| format!("Failed to read {outcomes}"))?;
+                toml::from_str(&source)
+                    .with_context(|| format!("Invalid mocked outcomes {outcomes}"))?
+            }
+        };
+
+        let mut simulation =
+            WorkflowSimulation::try_new(workflow, outcomes).map_err(anyhow::Error::from)?;
+        let init_state = simulation.init_state(&self.cmd_id, self.payload.clone());
+        let final_state = simulation
+            .run(init_state, self.max_steps)
+            .map_err(anyhow::Error::from);
+
+        let w = &mut std::io::stdout();
+        print_transitions(w, simulation.transitions());
+        print_final_state(w, &final_state?);
+        Ok(())
+    }
+}
+
+fn print_transitions(w: &mut impl Write, transitions: &[SimulatedTransition]) {
+    for transition in transitions {
+        let _ = writeln!(
+            w,
+            "{} -> {}: {}",
+            transition.from.bold(),
+            transition.to.bold(),
+            transition.action
+        );
+        for detail in transition.details.iter() {
+            let _ = writeln!(w, "    {}", detail.dim());
+        }
+    }
+}
+
+fn print_final_state(w: &mut impl Write, state: &GenericCommandState) {
+    let status = if state.is_successful() {
+        state.status.green()
+    } else if state.is_failed() {
+        state.status.red()
+    } else {
+        state.status.yellow()
+    };
+    let _ = writeln!(w, "\n{} {}", "Final state:".bold(), status.bold());
+    let payload = serde_json::to_string_pretty(&state.payload).unwrap_or_default();
+    let _ = writeln!(w, "{payload}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn payload_must_be_a_json_object() {
+        assert_eq!(parse_payload(r#"{"x": 1}"#), Ok(json!({"x": 1})));
+        assert!(parse_payload("[1, 2]").is_err());
+        assert!(parse_payload("{").is_err());
+    }
+
+    #[test]
+    fn print_transitions_with_details() {
+        yansi::disable();
+        let transitions = vec![
+            SimulatedTransition {
+                from: "init".to_string(),
+                to: "executing".to_string(),
+                action: "move to executing state".to_string(),
+                details: vec![],
+            },
+            SimulatedTransition {
+                from: "executing".to_string(),
+                to: "successful".to_string(),
+                action: "/some/script.sh".to_string(),
+                details: vec!["script exit code 0".to_string()],
+            },
+        ];
+        let mut output = Vec::new();
+        print_transitions(&mut output, &transitions);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "init -> executing: move to executing state\n\
+             executing -> successful: /some/script.sh\n    script exit code 0\n"
+        );
+    }
+}
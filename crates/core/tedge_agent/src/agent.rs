@@ -1,8 +1,14 @@
+use crate::command_history::server::CommandHistoryServer;
 use crate::device_profile_manager::DeviceProfileManagerBuilder;
 use crate::entity_manager;
+use crate::entity_manager::lifecycle::EntityLifecycleBuilder;
+use crate::entity_manager::lifecycle::EntityLifecyclePolicies;
+use crate::entity_manager::lifecycle::EntityLifecyclePolicy;
+use crate::entity_manager::manifest::EntityManifestBuilder;
 use crate::entity_manager::server::EntityStoreRequest;
 use crate::entity_manager::server::EntityStoreServer;
 use crate::entity_manager::server::EntityStoreServerConfig;
+use crate::http_server::access::HttpAccessControl;
 use crate::http_server::actor::HttpServerBuilder;
 use crate::http_server::actor::HttpServerConfig;
 use crate::operation_workflows::OperationConfig;
@@ -78,6 +84,7 @@ pub(crate) struct AgentConfig {
     pub data_dir: DataDir,
     pub state_dir: TedgePaths,
     pub operations_dir: ManagedDir,
+    pub entities_dir: ManagedDir,
     pub mqtt_device_topic_id: EntityTopicId,
     pub service_topic_id: ServiceTopicId,
     pub mqtt_topic_root: Arc<str>,
@@ -91,6 +98,8 @@ pub(crate) struct AgentConfig {
     pub config_plugin_dirs: Vec<Utf8PathBuf>,
     entity_auto_register: bool,
     entity_store_clean_start: bool,
+    entity_lifecycle_policies: EntityLifecyclePolicies,
+    command_history_size: usize,
 }
 
 impl AgentConfig {
@@ -135,6 +144,12 @@ impl AgentConfig {
             key_path: tedge_config.http.key_path.clone().map(Utf8PathBuf::from),
             ca_path: tedge_config.http.ca_path.clone().map(Utf8PathBuf::from),
             bind_addr: SocketAddr::from((http_bind_address, http_port)),
+            access_control: tedge_config.http.access.enable.then(|| {
+                HttpAccessControl::new(
+                    tedge_config.http.access.policy_file.clone().into(),
+                    mqtt_device_topic_id.clone(),
+                )
+            }),
         };
 
         // Restart config
@@ -157,6 +172,7 @@ impl AgentConfig {
         let log_dir = tedge_config.logs_root();
         let agent_log_dir = log_dir.dir("agent")?;
         let operations_dir = config_dir.dir("operations")?;
+        let entities_dir = config_dir.dir("entities")?;
 
         let identity = tedge_config.http.client.auth.identity()?;
         let cloud_root_certs = tedge_config.cloud_root_certs().await?;
@@ -171,6 +187,18 @@ impl AgentConfig {
 
         let entity_auto_register = tedge_config.agent.entity_store.auto_register;
         let entity_store_clean_start = tedge_config.agent.entity_store.clean_start;
+        let entity_store_config = &tedge_config.agent.entity_store;
+        let entity_lifecycle_policies = EntityLifecyclePolicies {
+            child_device: EntityLifecyclePolicy::from_config(
+                entity_store_config.child_device.stale_after.or_none(),
+                entity_store_config.child_device.remove_after.or_none(),
+            ),
+            service: EntityLifecyclePolicy::from_config(
+                entity_store_config.service.stale_after.or_none(),
+                entity_store_config.service.remove_after.or_none(),
+            ),
+        };
+        let command_history_size = tedge_config.agent.command_history.max_commands as usize;
         let log_plugin_dirs = tedge_config
             .log
             .plugin_paths
@@ -198,6 +226,7 @@ impl AgentConfig {
             log_dir,
             agent_log_dir,
             operations_dir,
+            entities_dir,
             state_dir,
             mqtt_topic_root,
             mqtt_device_topic_id,
@@ -212,6 +241,8 @@ impl AgentConfig {
             config_plugin_dirs,
             entity_auto_register,
             entity_store_clean_start,
+            entity_lifecycle_policies,
+            command_history_size,
         })
     }
 }
@@ -231,6 +262,7 @@ impl Agent {
         // under config_dir (/etc/tedge)
         self.config.config_dir.dir("device")?.ensure().await?;
         self.config.operations_dir.ensure().await?;
+        self.config.entities_dir.ensure().await?;
         let default_state_dir = agent_default_state_dir(&self.config.config_dir);
         default_state_dir.ensure().await?;
 
@@ -293,6 +325,15 @@ impl Agent {
         workflow_actor_builder.register_builtin_operation(&mut restart_actor_builder);
         workflow_actor_builder.register_builtin_operation(&mut software_update_builder);
 
+        // Command history actor
+        let state_dir = agent_state_dir(&self.config.state_dir, &self.config.config_dir);
+        let command_history_server =
+            CommandHistoryServer::load(state_dir.path().into(), self.config.command_history_size)
+                .await;
+        let mut command_history_actor_builder =
+            ServerActorBuilder::new(command_history_server, &ServerConfig::default(), Sequential);
+        workflow_actor_builder.register_command_history(&mut command_history_actor_builder);
+
         // Health actor
         // TODO: take a user-configurable service topic id
         let device_topic_id = self.config.mqtt_device_topic_id.clone();
@@ -406,7 +447,8 @@ impl Agent {
                 clean_start,
             )?;
             let entity_store_server_config =
-                EntityStoreServerConfig::new(mqtt_schema.clone(), self.config.entity_auto_register);
+                EntityStoreServerConfig::new(mqtt_schema.clone(), self.config.entity_auto_register)
+                    .with_lifecycle_policies(self.config.entity_lifecycle_policies.clone());
             let entity_store_server = EntityStoreServer::new(
                 entity_store_server_config,
                 entity_store,
@@ -428,9 +470,26 @@ impl Agent {
             let file_transfer_server_builder = HttpServerBuilder::try_bind(
                 self.config.http_config,
                 &mut entity_store_actor_builder,
+                &mut command_history_actor_builder,
             )
             .await?;
 
+            if self.config.entity_lifecycle_policies.is_enabled() {
+                let entity_lifecycle_builder = EntityLifecycleBuilder::new(
+                    &self.config.entity_lifecycle_policies,
+                    &mut entity_store_actor_builder,
+                );
+                runtime.spawn(entity_lifecycle_builder).await?;
+            }
+
+            let entity_manifest_builder = EntityManifestBuilder::new(
+                self.config.entities_dir.path().to_owned(),
+                state_dir.to_owned(),
+                &mut fs_watch_actor_builder,
+                &mut entity_store_actor_builder,
+            );
+            runtime.spawn(entity_manifest_builder).await?;
+
             runtime.spawn(file_transfer_server_builder).await?;
             runtime.spawn(entity_store_actor_builder).await?;
         } else {
@@ -453,6 +512,7 @@ impl Agent {
         runtime.spawn(software_update_builder).await?;
         runtime.spawn(script_runner).await?;
         runtime.spawn(workflow_actor_builder).await?;
+        runtime.spawn(command_history_actor_builder).await?;
         runtime.spawn(health_actor).await?;
 
         Ok(runtime)
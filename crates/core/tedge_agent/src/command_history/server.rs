@@ -0,0 +1,342 @@
+use crate::state_repository::state::AgentStateRepository;
+use async_trait::async_trait;
+use camino::Utf8PathBuf;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::VecDeque;
+use tedge_actors::Server;
+use tedge_api::mqtt_topics::EntityTopicId;
+use time::OffsetDateTime;
+use tracing::error;
+
+/// A command that reached a terminal state
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandRecord {
+    /// The command topic
+    pub topic: String,
+    pub entity: EntityTopicId,
+    pub operation: String,
+    pub cmd_id: String,
+
+    /// The terminal state of the command, e.g. `successful` or `failed`
+    pub status: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    /// When the command was received by the agent, if known
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub started_at: Option<OffsetDateTime>,
+
+    #[serde(with = "time::serde::rfc3339")]
+    pub finished_at: OffsetDateTime,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_path: Option<Utf8PathBuf>,
+}
+
+/// Criteria to select commands from the history
+///
+/// The time range applies to the time the commands finished.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CommandFilters {
+    pub entity: Option<EntityTopicId>,
+    pub operation: Option<String>,
+    pub status: Option<String>,
+    pub from: Option<OffsetDateTime>,
+    pub to: Option<OffsetDateTime>,
+}
+
+impl CommandFilters {
+    pub fn matches(&self, record: &CommandRecord) -> bool {
+        self.entity.as_ref().is_none_or(|e| e == &record.entity)
+            && self
+                .operation
+                .as_ref()
+                .is_none_or(|op| op == &record.operation)
+            && self.status.as_ref().is_none_or(|s| s == &record.status)
+            && self.from.is_none_or(|from| from <= record.finished_at)
+            && self.to.is_none_or(|to| record.finished_at <= to)
+    }
+}
+
+/// The most recent completed commands, from the oldest to the newest
+#[derive(Debug)]
+pub struct CommandHistory {
+    max_commands: usize,
+    records: VecDeque<CommandRecord>,
+}
+
+impl CommandHistory {
+    pub fn new(max_commands: usize) -> Self {
+        CommandHistory {
+            max_commands,
+            records: VecDeque::new(),
+        }
+    }
+
+    /// Add a completed command to the history, evicting the oldest commands if full
+    ///
+    /// A command that is already recorded for the same topic is replaced,
+    /// as this is the same command cleared once more (e.g. after an agent restart).
+    pub fn record(&mut self, mut record: CommandRecord) {
+        if let Some(pos) = self.records.iter().position(|r| r.topic == record.topic) {
+            if let Some(previous) = self.records.remove(pos) {
+                record.started_at = record.started_at.or(previous.started_at);
+            }
+        }
+        self.push(record)
+    }
+
+    fn push(&mut self, record: CommandRecord) {
+        self.records.push_back(record);
+        while self.records.len() > self.max_commands {
+            self.records.pop_front();
+        }
+    }
+
+    /// Return the commands matching the filters, the most recent first
+    pub fn query(&self, filters: &CommandFilters) -> Vec<CommandRecord> {
+        self.records
+            .iter()
+            .rev()
+            .filter(|record| filters.matches(record))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub enum CommandHistoryRequest {
+    Record(CommandRecord),
+    List(CommandFilters),
+}
+
+#[derive(Debug)]
+pub enum CommandHistoryResponse {
+    Ok,
+    List(Vec<CommandRecord>),
+}
+
+/// Keep the history of the completed commands, persisting it in the agent state directory
+pub struct CommandHistoryServer {
+    history: CommandHistory,
+    repository: AgentStateRepository<VecDeque<CommandRecord>>,
+}
+
+impl CommandHistoryServer {
+    /// Create a command history server, reloading the history persisted by a previous agent run
+    pub async fn load(state_dir: Utf8PathBuf, max_commands: usize) -> Self {
+        let repository = AgentStateRepository::with_state_dir(state_dir, "command-history");
+        let mut history = CommandHistory::new(max_commands);
+        match repository.load().await {
+            Ok(Some(records)) => {
+                for record in records {
+                    history.push(record)
+                }
+            }
+            Ok(None) => {}
+            Err(err) => error!(
+                "Fail to reload the command history from {}: {err}",
+                repository.state_repo_path
+            ),
+        }
+
+        CommandHistoryServer {
+            history,
+            repository,
+        }
+    }
+}
+
+#[async_trait]
+impl Server for CommandHistoryServer {
+    type Request = CommandHistoryRequest;
+    type Response = CommandHistoryResponse;
+
+    fn name(&self) -> &str {
+        "CommandHistoryServer"
+    }
+
+    async fn handle(&mut self, request: CommandHistoryRequest) -> CommandHistoryResponse {
+        match request {
+            CommandHistoryRequest::Record(record) => {
+                self.history.record(record);
+                if let Err(err) = self.repository.store(&self.history.records).await {
+                    error!(
+                        "Fail to persist the command history in {}: {err}",
+                        self.repository.state_repo_path
+                    );
+                }
+                CommandHistoryResponse::Ok
+            }
+            CommandHistoryRequest::List(filters) => {
+                CommandHistoryResponse::List(self.history.query(&filters))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tedge_test_utils::fs::TempTedgeDir;
+    use time::macros::datetime;
+
+    fn record(
+        operation: &str,
+        cmd_id: &str,
+        status: &str,
+        finished_at: OffsetDateTime,
+    ) -> CommandRecord {
+        CommandRecord {
+            topic: format!("te/device/main///cmd/{operation}/{cmd_id}"),
+            entity: EntityTopicId::default_main_device(),
+            operation: operation.to_string(),
+            cmd_id: cmd_id.to_string(),
+            status: status.to_string(),
+            reason: None,
+            started_at: None,
+            finished_at,
+            log_path: None,
+        }
+    }
+
+    fn cmd_ids(records: Vec<CommandRecord>) -> Vec<String> {
+        records.into_iter().map(|r| r.cmd_id).collect()
+    }
+
+    #[test]
+    fn oldest_commands_are_evicted() {
+        let mut history = CommandHistory::new(2);
+        history.record(record(
+            "restart",
+            "1",
+            "successful",
+            datetime!(2025-01-01 10:00 UTC),
+        ));
+        history.record(record(
+            "restart",
+            "2",
+            "failed",
+            datetime!(2025-01-01 11:00 UTC),
+        ));
+        history.record(record(
+            "restart",
+            "3",
+            "successful",
+            datetime!(2025-01-01 12:00 UTC),
+        ));
+
+        assert_eq!(
+            cmd_ids(history.query(&CommandFilters::default())),
+            vec!["3", "2"]
+        );
+    }
+
+    #[test]
+    fn commands_cleared_twice_are_recorded_once() {
+        let mut history = CommandHistory::new(10);
+        let mut first = record(
+            "restart",
+            "1",
+            "successful",
+            datetime!(2025-01-01 10:00 UTC),
+        );
+        first.started_at = Some(datetime!(2025-01-01 09:59 UTC));
+        history.record(first);
+        history.record(record(
+            "restart",
+            "1",
+            "successful",
+            datetime!(2025-01-01 10:05 UTC),
+        ));
+
+        let records = history.query(&CommandFilters::default());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].started_at, Some(datetime!(2025-01-01 09:59 UTC)));
+        assert_eq!(records[0].finished_at, datetime!(2025-01-01 10:05 UTC));
+    }
+
+    #[test]
+    fn filter_commands() {
+        let mut history = CommandHistory::new(10);
+        history.record(record(
+            "restart",
+            "1",
+            "successful",
+            datetime!(2025-01-01 10:00 UTC),
+        ));
+        history.record(record(
+            "software_update",
+            "2",
+            "failed",
+            datetime!(2025-01-01 11:00 UTC),
+        ));
+        history.record(record(
+            "restart",
+            "3",
+            "failed",
+            datetime!(2025-01-01 12:00 UTC),
+        ));
+        let mut child_command = record(
+            "restart",
+            "4",
+            "successful",
+            datetime!(2025-01-01 13:00 UTC),
+        );
+        child_command.entity = EntityTopicId::default_child_device("child").unwrap();
+        history.record(child_command);
+
+        let by_operation = CommandFilters {
+            operation: Some("restart".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cmd_ids(history.query(&by_operation)), vec!["4", "3", "1"]);
+
+        let by_status = CommandFilters {
+            status: Some("failed".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(cmd_ids(history.query(&by_status)), vec!["3", "2"]);
+
+        let by_entity = CommandFilters {
+            entity: Some(EntityTopicId::default_main_device()),
+            ..Default::default()
+        };
+        assert_eq!(cmd_ids(history.query(&by_entity)), vec!["3", "2", "1"]);
+
+        let by_time_range = CommandFilters {
+            from: Some(datetime!(2025-01-01 11:00 UTC)),
+            to: Some(datetime!(2025-01-01 12:30 UTC)),
+            ..Default::default()
+        };
+        assert_eq!(cmd_ids(history.query(&by_time_range)), vec!["3", "2"]);
+    }
+
+    #[tokio::test]
+    async fn history_is_persisted() {
+        let ttd = TempTedgeDir::new();
+        let mut server = CommandHistoryServer::load(ttd.utf8_path_buf(), 10).await;
+        let mut command = record("restart", "1", "failed", datetime!(2025-01-01 10:00 UTC));
+        command.reason = Some("timeout".to_string());
+        command.log_path = Some("/var/log/tedge/agent/workflow-restart-1.log".into());
+        server
+            .handle(CommandHistoryRequest::Record(command.clone()))
+            .await;
+
+        let mut server = CommandHistoryServer::load(ttd.utf8_path_buf(), 10).await;
+        let CommandHistoryResponse::List(records) = server
+            .handle(CommandHistoryRequest::List(CommandFilters::default()))
+            .await
+        else {
+            panic!("Unexpected response")
+        };
+        assert_eq!(records, vec![command]);
+    }
+}
@@ -0,0 +1,347 @@
+use crate::entity_manager::server::EntityStoreRequest;
+use crate::entity_manager::server::EntityStoreResponse;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::time::Duration;
+use tedge_actors::futures::channel::mpsc;
+use tedge_actors::futures::StreamExt;
+use tedge_actors::Actor;
+use tedge_actors::Builder;
+use tedge_actors::ClientMessageBox;
+use tedge_actors::DynSender;
+use tedge_actors::RuntimeError;
+use tedge_actors::RuntimeRequest;
+use tedge_actors::RuntimeRequestSink;
+use tedge_actors::Service;
+use tedge_api::entity::EntityMetadata;
+use tedge_api::entity::EntityType;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_config::models::SecondsOrHumanTime;
+use time::OffsetDateTime;
+use tracing::error;
+
+/// The twin fragment used to flag stale entities
+pub const LIFECYCLE_FRAGMENT: &str = "lifecycle";
+
+/// The event type used to notify the deregistration of expired entities
+pub const ENTITY_REMOVED_EVENT: &str = "entity_removed";
+
+/// The maximum delay between two checks of the entity lifecycle policies
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long an entity can stay without any activity, before being marked stale and then removed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct EntityLifecyclePolicy {
+    /// Delay after the last activity of an entity, after which this entity is marked stale
+    pub stale_after: Duration,
+
+    /// Delay after an entity has been marked stale, after which this entity is deregistered
+    ///
+    /// If not set, stale entities are never deregistered.
+    pub remove_after: Option<Duration>,
+}
+
+impl EntityLifecyclePolicy {
+    pub fn new(stale_after: Duration, remove_after: Option<Duration>) -> Self {
+        EntityLifecyclePolicy {
+            stale_after,
+            remove_after,
+        }
+    }
+
+    /// Build the policy configured for an entity type, if any
+    pub fn from_config(
+        stale_after: Option<&SecondsOrHumanTime>,
+        remove_after: Option<&SecondsOrHumanTime>,
+    ) -> Option<Self> {
+        let stale_after = stale_after?.duration();
+        let remove_after = remove_after.map(SecondsOrHumanTime::duration);
+        Some(EntityLifecyclePolicy::new(stale_after, remove_after))
+    }
+
+    fn removal_delay(&self) -> Option<Duration> {
+        self.remove_after
+            .map(|remove_after| self.stale_after + remove_after)
+    }
+}
+
+/// The lifecycle policies applied to the child devices and services
+///
+/// The main device is never marked stale.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct EntityLifecyclePolicies {
+    pub child_device: Option<EntityLifecyclePolicy>,
+    pub service: Option<EntityLifecyclePolicy>,
+}
+
+impl EntityLifecyclePolicies {
+    pub fn is_enabled(&self) -> bool {
+        self.child_device.is_some() || self.service.is_some()
+    }
+
+    pub fn policy(&self, entity_type: &EntityType) -> Option<&EntityLifecyclePolicy> {
+        match entity_type {
+            EntityType::MainDevice => None,
+            EntityType::ChildDevice => self.child_device.as_ref(),
+            EntityType::Service => self.service.as_ref(),
+        }
+    }
+
+    /// The delay between two checks, short enough to apply the shortest policy on time
+    pub fn check_interval(&self) -> Duration {
+        [self.child_device, self.service]
+            .into_iter()
+            .flatten()
+            .flat_map(|policy| [Some(policy.stale_after), policy.remove_after])
+            .flatten()
+            .filter(|delay| !delay.is_zero())
+            .fold(MAX_CHECK_INTERVAL, Duration::min)
+    }
+}
+
+/// The entities that have reached a new lifecycle stage on a check
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct LifecycleChanges {
+    /// The entities to be marked stale, along their last activity
+    pub stale: Vec<(EntityTopicId, OffsetDateTime)>,
+
+    /// The entities to be deregistered, along their last activity
+    pub expired: Vec<(EntityTopicId, OffsetDateTime)>,
+}
+
+/// Track the last activity of the entities
+#[derive(Debug, Default)]
+pub struct EntityActivity {
+    last_activity: HashMap<EntityTopicId, OffsetDateTime>,
+    stale: HashSet<EntityTopicId>,
+}
+
+impl EntityActivity {
+    /// Record some activity of an entity
+    ///
+    /// Return `true` if this entity was stale and is now active again.
+    pub fn touch(&mut self, topic_id: &EntityTopicId, now: OffsetDateTime) -> bool {
+        self.last_activity.insert(topic_id.clone(), now);
+        self.stale.remove(topic_id)
+    }
+
+    /// Forget a deregistered entity
+    pub fn forget(&mut self, topic_id: &EntityTopicId) {
+        self.last_activity.remove(topic_id);
+        self.stale.remove(topic_id);
+    }
+
+    /// Return the entities that are to be marked stale or deregistered
+    ///
+    /// An entity with no recorded activity is given the full delays from now,
+    /// as this is the case for all the entities restored on start.
+    pub fn check<'a>(
+        &mut self,
+        policies: &EntityLifecyclePolicies,
+        entities: impl IntoIterator<Item = &'a EntityMetadata>,
+        now: OffsetDateTime,
+    ) -> LifecycleChanges {
+        let mut changes = LifecycleChanges::default();
+        for entity in entities {
+            let Some(policy) = policies.policy(&entity.r#type) else {
+                continue;
+            };
+            let topic_id = &entity.topic_id;
+            let last_activity = *self.last_activity.entry(topic_id.clone()).or_insert(now);
+            let idle = now - last_activity;
+
+            if policy
+                .removal_delay()
+                .is_some_and(|removal_delay| idle >= removal_delay)
+            {
+                changes.expired.push((topic_id.clone(), last_activity));
+            } else if idle >= policy.stale_after && self.stale.insert(topic_id.clone()) {
+                changes.stale.push((topic_id.clone(), last_activity));
+            }
+        }
+        changes
+    }
+}
+
+/// Periodically request the entity store to apply the lifecycle policies
+pub struct EntityLifecycleActor {
+    check_interval: Duration,
+    signal_receiver: mpsc::Receiver<RuntimeRequest>,
+    entity_store_handle: ClientMessageBox<EntityStoreRequest, EntityStoreResponse>,
+}
+
+#[async_trait]
+impl Actor for EntityLifecycleActor {
+    fn name(&self) -> &str {
+        "EntityLifecycle"
+    }
+
+    async fn run(mut self) -> Result<(), RuntimeError> {
+        let mut ticks = tokio::time::interval(self.check_interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {
+                    if let Err(err) = self
+                        .entity_store_handle
+                        .await_response(EntityStoreRequest::CheckLifecycle)
+                        .await
+                    {
+                        error!("Fail to check the entity lifecycle policies: {err}");
+                    }
+                }
+                Some(RuntimeRequest::Shutdown) = self.signal_receiver.next() => {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+pub struct EntityLifecycleBuilder {
+    check_interval: Duration,
+    signal_sender: mpsc::Sender<RuntimeRequest>,
+    signal_receiver: mpsc::Receiver<RuntimeRequest>,
+    entity_store_handle: ClientMessageBox<EntityStoreRequest, EntityStoreResponse>,
+}
+
+impl EntityLifecycleBuilder {
+    pub fn new(
+        policies: &EntityLifecyclePolicies,
+        entity_store_service: &mut impl Service<EntityStoreRequest, EntityStoreResponse>,
+    ) -> Self {
+        let (signal_sender, signal_receiver) = mpsc::channel(10);
+        let entity_store_handle = ClientMessageBox::new(entity_store_service);
+        EntityLifecycleBuilder {
+            check_interval: policies.check_interval(),
+            signal_sender,
+            signal_receiver,
+            entity_store_handle,
+        }
+    }
+}
+
+impl RuntimeRequestSink for EntityLifecycleBuilder {
+    fn get_signal_sender(&self) -> DynSender<RuntimeRequest> {
+        Box::new(self.signal_sender.clone())
+    }
+}
+
+impl Builder<EntityLifecycleActor> for EntityLifecycleBuilder {
+    type Error = Infallible;
+
+    fn try_build(self) -> Result<EntityLifecycleActor, Self::Error> {
+        Ok(EntityLifecycleActor {
+            check_interval: self.check_interval,
+            signal_receiver: self.signal_receiver,
+            entity_store_handle: self.entity_store_handle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn child(name: &str) -> EntityMetadata {
+        EntityMetadata::child_device(name.to_string()).unwrap()
+    }
+
+    fn policies() -> EntityLifecyclePolicies {
+        EntityLifecyclePolicies {
+            child_device: Some(EntityLifecyclePolicy::new(
+                Duration::from_secs(3600),
+                Some(Duration::from_secs(24 * 3600)),
+            )),
+            service: None,
+        }
+    }
+
+    #[test]
+    fn idle_entities_are_marked_stale_then_expired() {
+        let policies = policies();
+        let child = child("child0");
+        let start = datetime!(2025-01-01 10:00 UTC);
+        let mut activity = EntityActivity::default();
+        activity.touch(&child.topic_id, start);
+
+        let changes = activity.check(&policies, [&child], datetime!(2025-01-01 10:30 UTC));
+        assert_eq!(changes, LifecycleChanges::default());
+
+        let changes = activity.check(&policies, [&child], datetime!(2025-01-01 11:00 UTC));
+        assert_eq!(changes.stale, vec![(child.topic_id.clone(), start)]);
+        assert!(changes.expired.is_empty());
+
+        // An entity is marked stale only once
+        let changes = activity.check(&policies, [&child], datetime!(2025-01-01 12:00 UTC));
+        assert_eq!(changes, LifecycleChanges::default());
+
+        let changes = activity.check(&policies, [&child], datetime!(2025-01-02 11:00 UTC));
+        assert!(changes.stale.is_empty());
+        assert_eq!(changes.expired, vec![(child.topic_id.clone(), start)]);
+    }
+
+    #[test]
+    fn activity_resets_the_lifecycle() {
+        let policies = policies();
+        let child = child("child0");
+        let mut activity = EntityActivity::default();
+        activity.touch(&child.topic_id, datetime!(2025-01-01 10:00 UTC));
+
+        let changes = activity.check(&policies, [&child], datetime!(2025-01-01 11:00 UTC));
+        assert_eq!(changes.stale.len(), 1);
+
+        let back = datetime!(2025-01-01 11:30 UTC);
+        assert!(activity.touch(&child.topic_id, back));
+        assert!(!activity.touch(&child.topic_id, back));
+
+        let changes = activity.check(&policies, [&child], datetime!(2025-01-02 11:00 UTC));
+        assert_eq!(changes.stale, vec![(child.topic_id.clone(), back)]);
+        assert!(changes.expired.is_empty());
+    }
+
+    #[test]
+    fn entities_without_policy_are_kept() {
+        let policies = policies();
+        let main = EntityMetadata::main_device(None);
+        let service = EntityMetadata::new(
+            "device/main/service/collectd".parse().unwrap(),
+            EntityType::Service,
+        );
+        let mut activity = EntityActivity::default();
+
+        // Entities with no recorded activity are given the full delays from the first check
+        let start = datetime!(2025-01-01 10:00 UTC);
+        let child = child("child0");
+        activity.check(&policies, [&main, &service, &child], start);
+
+        let changes = activity.check(
+            &policies,
+            [&main, &service, &child],
+            datetime!(2025-02-01 10:00 UTC),
+        );
+        assert!(changes.stale.is_empty());
+        assert_eq!(changes.expired, vec![(child.topic_id.clone(), start)]);
+    }
+
+    #[test]
+    fn check_interval_follows_the_shortest_delay() {
+        assert_eq!(
+            EntityLifecyclePolicies::default().check_interval(),
+            MAX_CHECK_INTERVAL
+        );
+        assert_eq!(policies().check_interval(), MAX_CHECK_INTERVAL);
+
+        let policies = EntityLifecyclePolicies {
+            child_device: None,
+            service: Some(EntityLifecyclePolicy::new(
+                Duration::from_secs(300),
+                Some(Duration::from_secs(10)),
+            )),
+        };
+        assert_eq!(policies.check_interval(), Duration::from_secs(10));
+    }
+}
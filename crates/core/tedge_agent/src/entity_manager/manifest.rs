@@ -0,0 +1,326 @@
+use crate::entity_manager::server::EntityStoreRequest;
+use crate::entity_manager::server::EntityStoreResponse;
+use crate::state_repository::error::StateError;
+use crate::state_repository::state::AgentStateRepository;
+use async_trait::async_trait;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::path::PathBuf;
+use tedge_actors::Actor;
+use tedge_actors::Builder;
+use tedge_actors::ClientMessageBox;
+use tedge_actors::DynSender;
+use tedge_actors::MessageReceiver;
+use tedge_actors::MessageSource;
+use tedge_actors::NoMessage;
+use tedge_actors::RuntimeError;
+use tedge_actors::RuntimeRequest;
+use tedge_actors::RuntimeRequestSink;
+use tedge_actors::Service;
+use tedge_actors::SimpleMessageBox;
+use tedge_actors::SimpleMessageBoxBuilder;
+use tedge_api::entity_manifest::EntityManifest;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_file_system_ext::FsWatchEvent;
+use tracing::error;
+use tracing::info;
+
+/// The state file used to persist the topic ids of the entities declared by the manifests
+const DECLARED_ENTITIES_STATE: &str = "entity-manifests";
+
+/// Reconcile the entity store with the entity manifests of a directory,
+/// on start and on any change of these manifests
+pub struct EntityManifestActor {
+    entities_dir: Utf8PathBuf,
+    messages: SimpleMessageBox<FsWatchEvent, NoMessage>,
+    entity_store_handle: ClientMessageBox<EntityStoreRequest, EntityStoreResponse>,
+    repository: AgentStateRepository<BTreeSet<EntityTopicId>>,
+    declared: BTreeSet<EntityTopicId>,
+}
+
+#[async_trait]
+impl Actor for EntityManifestActor {
+    fn name(&self) -> &str {
+        "EntityManifest"
+    }
+
+    async fn run(mut self) -> Result<(), RuntimeError> {
+        self.load_declared_entities().await;
+        self.reconcile().await?;
+
+        while let Some(event) = self.messages.recv().await {
+            if is_manifest_update(&event) {
+                self.reconcile().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl EntityManifestActor {
+    /// Load the entities declared by the manifests on a previous run
+    ///
+    /// These are required to deregister the entities of the manifests removed while the agent was down.
+    async fn load_declared_entities(&mut self) {
+        match self.repository.load().await {
+            Ok(declared) => self.declared = declared.unwrap_or_default(),
+            Err(StateError::LoadingFromFileFailed { source, .. })
+                if source.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => error!(
+                "Fail to reload the previously declared entities from {}: {err}",
+                self.repository.state_repo_path
+            ),
+        }
+    }
+
+    async fn reconcile(&mut self) -> Result<(), RuntimeError> {
+        info!("Loading entity manifests from {}", self.entities_dir);
+        let (manifest, errors) = EntityManifest::load_dir(&self.entities_dir);
+        let declared: BTreeSet<EntityTopicId> = manifest.topic_ids().cloned().collect();
+
+        // On error, the entities of the invalid manifests are kept till these manifests are fixed
+        let removed: Vec<EntityTopicId> = if errors.is_empty() {
+            self.declared.difference(&declared).cloned().collect()
+        } else {
+            for err in errors {
+                error!("{err}");
+            }
+            vec![]
+        };
+        self.declared.retain(|topic_id| !removed.contains(topic_id));
+        self.declared.extend(declared);
+
+        self.entity_store_handle
+            .await_response(EntityStoreRequest::Reconcile(manifest, removed))
+            .await?;
+
+        if let Err(err) = self.repository.store(&self.declared).await {
+            error!(
+                "Fail to persist the declared entities in {}: {err}",
+                self.repository.state_repo_path
+            );
+        }
+        Ok(())
+    }
+}
+
+fn is_manifest_update(event: &FsWatchEvent) -> bool {
+    match event {
+        FsWatchEvent::Modified(path)
+        | FsWatchEvent::FileDeleted(path)
+        | FsWatchEvent::FileCreated(path) => {
+            Utf8Path::from_path(path).is_some_and(EntityManifest::is_manifest_file)
+        }
+        FsWatchEvent::DirectoryDeleted(_) | FsWatchEvent::DirectoryCreated(_) => false,
+    }
+}
+
+pub struct EntityManifestBuilder {
+    entities_dir: Utf8PathBuf,
+    state_dir: Utf8PathBuf,
+    box_builder: SimpleMessageBoxBuilder<FsWatchEvent, NoMessage>,
+    entity_store_handle: ClientMessageBox<EntityStoreRequest, EntityStoreResponse>,
+}
+
+impl EntityManifestBuilder {
+    pub fn new(
+        entities_dir: Utf8PathBuf,
+        state_dir: Utf8PathBuf,
+        fs_notify: &mut impl MessageSource<FsWatchEvent, PathBuf>,
+        entity_store_service: &mut impl Service<EntityStoreRequest, EntityStoreResponse>,
+    ) -> Self {
+        let box_builder = SimpleMessageBoxBuilder::new("EntityManifest", 16);
+        fs_notify.connect_sink(entities_dir.clone().into(), &box_builder);
+        let entity_store_handle = ClientMessageBox::new(entity_store_service);
+        EntityManifestBuilder {
+            entities_dir,
+            state_dir,
+            box_builder,
+            entity_store_handle,
+        }
+    }
+}
+
+impl RuntimeRequestSink for EntityManifestBuilder {
+    fn get_signal_sender(&self) -> DynSender<RuntimeRequest> {
+        self.box_builder.get_signal_sender()
+    }
+}
+
+impl Builder<EntityManifestActor> for EntityManifestBuilder {
+    type Error = Infallible;
+
+    fn try_build(self) -> Result<EntityManifestActor, Self::Error> {
+        let repository =
+            AgentStateRepository::with_state_dir(self.state_dir, DECLARED_ENTITIES_STATE);
+        Ok(EntityManifestActor {
+            entities_dir: self.entities_dir,
+            messages: self.box_builder.build(),
+            entity_store_handle: self.entity_store_handle,
+            repository,
+            declared: BTreeSet::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tedge_actors::Sender;
+    use tedge_actors::ServerMessageBox;
+    use tedge_actors::ServerMessageBoxBuilder;
+    use tedge_test_utils::fs::TempTedgeDir;
+
+    const TEST_TIMEOUT_MS: Duration = Duration::from_millis(5000);
+
+    #[tokio::test]
+    async fn undeclared_entities_are_removed() {
+        let ttd = TempTedgeDir::new();
+        let entities_dir = ttd.dir("entities");
+        entities_dir.file("children.toml").with_raw_content(
+            r#"
+[[entities]]
+"@topic-id" = "device/child1//"
+"@type" = "child-device"
+
+[[entities]]
+"@topic-id" = "device/child2//"
+"@type" = "child-device"
+"#,
+        );
+
+        let (mut fs_events, mut entity_store) = spawn_manifest_actor(&ttd, &entities_dir).await;
+        assert_eq!(
+            reconcile_request(&mut entity_store).await,
+            (
+                vec!["device/child1//".to_string(), "device/child2//".to_string()],
+                vec![]
+            )
+        );
+
+        entities_dir.file("children.toml").delete();
+        entities_dir.file("children.toml").with_raw_content(
+            r#"
+[[entities]]
+"@topic-id" = "device/child2//"
+"@type" = "child-device"
+"#,
+        );
+        fs_events
+            .send(FsWatchEvent::Modified(
+                entities_dir.path().join("children.toml"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(
+            reconcile_request(&mut entity_store).await,
+            (
+                vec!["device/child2//".to_string()],
+                vec!["device/child1//".to_string()]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn entities_of_invalid_manifests_are_kept() {
+        let ttd = TempTedgeDir::new();
+        let entities_dir = ttd.dir("entities");
+        entities_dir.file("children.json").with_raw_content(
+            r#"{"entities": [{"@topic-id": "device/child1//", "@type": "child-device"}]}"#,
+        );
+
+        let (mut fs_events, mut entity_store) = spawn_manifest_actor(&ttd, &entities_dir).await;
+        reconcile_request(&mut entity_store).await;
+
+        entities_dir.file("children.json").delete();
+        entities_dir
+            .file("children.json")
+            .with_raw_content(r#"{"entities": [{"@topic-id": "device/child1//""#);
+        fs_events
+            .send(FsWatchEvent::Modified(
+                entities_dir.path().join("children.json"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(reconcile_request(&mut entity_store).await, (vec![], vec![]));
+    }
+
+    #[tokio::test]
+    async fn entities_removed_while_the_agent_was_down_are_deregistered() {
+        let ttd = TempTedgeDir::new();
+        let entities_dir = ttd.dir("entities");
+        entities_dir.file("children.toml").with_raw_content(
+            r#"
+[[entities]]
+"@topic-id" = "device/child1//"
+"@type" = "child-device"
+"#,
+        );
+
+        let (_, mut entity_store) = spawn_manifest_actor(&ttd, &entities_dir).await;
+        reconcile_request(&mut entity_store).await;
+
+        // The declared entities are persisted once the reconcile request is acknowledged
+        let state_path = ttd.utf8_path().join(DECLARED_ENTITIES_STATE);
+        tokio::time::timeout(TEST_TIMEOUT_MS, async {
+            while !std::fs::read_to_string(&state_path).is_ok_and(|state| !state.is_empty()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the declared entities to be persisted");
+
+        entities_dir.file("children.toml").delete();
+        let (_, mut entity_store) = spawn_manifest_actor(&ttd, &entities_dir).await;
+        assert_eq!(
+            reconcile_request(&mut entity_store).await,
+            (vec![], vec!["device/child1//".to_string()])
+        );
+    }
+
+    async fn spawn_manifest_actor(
+        ttd: &TempTedgeDir,
+        entities_dir: &TempTedgeDir,
+    ) -> (
+        SimpleMessageBox<NoMessage, FsWatchEvent>,
+        ServerMessageBox<EntityStoreRequest, EntityStoreResponse>,
+    ) {
+        let mut fs_notify = SimpleMessageBoxBuilder::<NoMessage, FsWatchEvent>::new("FS", 16);
+        let mut entity_store = ServerMessageBoxBuilder::new("EntityStore", 16);
+        let builder = EntityManifestBuilder::new(
+            entities_dir.utf8_path_buf(),
+            ttd.utf8_path_buf(),
+            &mut fs_notify,
+            &mut entity_store,
+        );
+        let actor = builder.build();
+        tokio::spawn(async move { actor.run().await });
+        (fs_notify.build(), entity_store.build())
+    }
+
+    /// Wait for a reconcile request, returning the declared and removed entities
+    async fn reconcile_request(
+        entity_store: &mut ServerMessageBox<EntityStoreRequest, EntityStoreResponse>,
+    ) -> (Vec<String>, Vec<String>) {
+        let mut request = tokio::time::timeout(TEST_TIMEOUT_MS, entity_store.recv())
+            .await
+            .expect("a reconcile request")
+            .expect("a reconcile request");
+        let EntityStoreRequest::Reconcile(manifest, removed) = request.request else {
+            panic!("Unexpected request: {:?}", request.request)
+        };
+        request
+            .reply_to
+            .send(EntityStoreResponse::Ok)
+            .await
+            .unwrap();
+        (
+            manifest.topic_ids().map(|t| t.to_string()).collect(),
+            removed.iter().map(|t| t.to_string()).collect(),
+        )
+    }
+}
@@ -1,3 +1,5 @@
+pub(crate) mod lifecycle;
+pub(crate) mod manifest;
 pub(crate) mod server;
 
 #[cfg(test)]
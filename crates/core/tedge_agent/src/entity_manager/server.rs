@@ -1,6 +1,11 @@
+use crate::entity_manager::lifecycle::EntityActivity;
+use crate::entity_manager::lifecycle::EntityLifecyclePolicies;
+use crate::entity_manager::lifecycle::ENTITY_REMOVED_EVENT;
+use crate::entity_manager::lifecycle::LIFECYCLE_FRAGMENT;
 use async_trait::async_trait;
 use futures::channel::mpsc;
 use futures::StreamExt as _;
+use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
 use tedge_actors::LoggingSender;
@@ -9,6 +14,7 @@ use tedge_actors::MessageSink;
 use tedge_actors::Sender;
 use tedge_actors::Server;
 use tedge_api::entity::EntityMetadata;
+use tedge_api::entity_manifest::EntityManifest;
 use tedge_api::entity_store;
 use tedge_api::entity_store::EntityRegistrationMessage;
 use tedge_api::entity_store::EntityTwinMessage;
@@ -24,7 +30,10 @@ use tedge_api::EntityStore;
 use tedge_mqtt_ext::MqttMessage;
 use tedge_mqtt_ext::MqttRequest;
 use tedge_mqtt_ext::TopicFilter;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 use tracing::error;
+use tracing::info;
 
 #[derive(Debug)]
 pub enum EntityStoreRequest {
@@ -33,11 +42,15 @@ pub enum EntityStoreRequest {
     Update(EntityTopicId, EntityUpdateMessage),
     Delete(EntityTopicId),
     List(ListFilters),
+    ListPage(ListFilters, Option<EntityTopicId>, usize),
     MqttMessage(MqttMessage),
     GetTwinFragment(EntityTopicId, String),
     SetTwinFragment(EntityTwinMessage),
     GetTwinFragments(EntityTopicId),
     SetTwinFragments(EntityTopicId, Map<String, Value>),
+    Subscribe(mpsc::UnboundedSender<EntityChange>),
+    CheckLifecycle,
+    Reconcile(EntityManifest, Vec<EntityTopicId>),
 }
 
 #[derive(Debug)]
@@ -47,6 +60,7 @@ pub enum EntityStoreResponse {
     Update(Result<EntityMetadata, entity_store::Error>),
     Delete(Vec<EntityMetadata>),
     List(Vec<EntityMetadata>),
+    ListPage(Vec<EntityMetadata>, Option<EntityTopicId>),
     Ok,
     GetTwinFragment(Option<Value>),
     SetTwinFragment(Result<bool, entity_store::Error>),
@@ -54,16 +68,27 @@ pub enum EntityStoreResponse {
     SetTwinFragments(Result<(), entity_store::Error>),
 }
 
+/// A change of the entity store notified to the subscribers
+#[derive(Debug, Clone)]
+pub enum EntityChange {
+    Registered(EntityMetadata),
+    Deregistered(EntityMetadata),
+    TwinUpdated(EntityTwinMessage),
+}
+
 pub struct EntityStoreServer {
     config: EntityStoreServerConfig,
     entity_store: EntityStore,
     mqtt_publisher: LoggingSender<MqttMessage>,
     retain_requests: LoggingSender<(mpsc::UnboundedSender<MqttMessage>, TopicFilter)>,
+    subscribers: Vec<mpsc::UnboundedSender<EntityChange>>,
+    activity: EntityActivity,
 }
 
 pub struct EntityStoreServerConfig {
     pub mqtt_schema: MqttSchema,
     pub entity_auto_register: bool,
+    pub lifecycle_policies: EntityLifecyclePolicies,
 }
 
 impl EntityStoreServerConfig {
@@ -71,8 +96,14 @@ impl EntityStoreServerConfig {
         Self {
             mqtt_schema,
             entity_auto_register,
+            lifecycle_policies: EntityLifecyclePolicies::default(),
         }
     }
+
+    pub fn with_lifecycle_policies(mut self, lifecycle_policies: EntityLifecyclePolicies) -> Self {
+        self.lifecycle_policies = lifecycle_policies;
+        self
+    }
 }
 
 impl EntityStoreServer {
@@ -99,6 +130,8 @@ impl EntityStoreServer {
             entity_store,
             mqtt_publisher,
             retain_requests,
+            subscribers: vec![],
+            activity: EntityActivity::default(),
         }
     }
 
@@ -144,6 +177,15 @@ impl Server for EntityStoreServer {
                 let entities = self.entity_store.list_entity_tree(filters);
                 EntityStoreResponse::List(entities.into_iter().cloned().collect())
             }
+            EntityStoreRequest::ListPage(filters, after, limit) => {
+                let page = self
+                    .entity_store
+                    .list_entity_page(filters, after.as_ref(), limit);
+                EntityStoreResponse::ListPage(
+                    page.entities.into_iter().cloned().collect(),
+                    page.next,
+                )
+            }
             EntityStoreRequest::GetTwinFragment(topic_id, fragment_key) => {
                 let twin = self
                     .entity_store
@@ -166,6 +208,18 @@ impl Server for EntityStoreServer {
                 self.process_mqtt_message(mqtt_message).await;
                 EntityStoreResponse::Ok
             }
+            EntityStoreRequest::Subscribe(subscriber) => {
+                self.subscribers.push(subscriber);
+                EntityStoreResponse::Ok
+            }
+            EntityStoreRequest::CheckLifecycle => {
+                self.check_lifecycle(OffsetDateTime::now_utc()).await;
+                EntityStoreResponse::Ok
+            }
+            EntityStoreRequest::Reconcile(manifest, removed) => {
+                self.reconcile(manifest, removed).await;
+                EntityStoreResponse::Ok
+            }
         }
     }
 }
@@ -173,17 +227,23 @@ impl Server for EntityStoreServer {
 impl EntityStoreServer {
     pub(crate) async fn process_mqtt_message(&mut self, message: MqttMessage) {
         if let Ok((topic_id, channel)) = self.config.mqtt_schema.entity_channel_of(&message.topic) {
+            let is_activity =
+                self.config.lifecycle_policies.is_enabled() && is_activity(&channel, &message);
             if let Channel::EntityMetadata = channel {
-                self.process_entity_registration(topic_id, message.payload_bytes())
+                self.process_entity_registration(topic_id.clone(), message.payload_bytes())
                     .await;
             } else {
                 let res = self
-                    .process_entity_data(topic_id, channel, message.clone())
+                    .process_entity_data(topic_id.clone(), channel, message.clone())
                     .await;
                 if let Err(err) = res {
                     error!("Failed to process entity data message: {message} due to : {err}");
                 }
             }
+            if is_activity {
+                self.record_activity(&topic_id, OffsetDateTime::now_utc())
+                    .await;
+            }
         }
     }
 
@@ -197,6 +257,7 @@ impl EntityStoreServer {
             Ok(entity) => match self.entity_store.update(entity.clone()) {
                 Ok(registered) => {
                     for entity in registered {
+                        self.notify_registration(&entity.reg_message);
                         for (fragment_key, fragment_value) in entity.reg_message.twin_data {
                             self.publish_twin_data(
                                 &entity.reg_message.topic_id,
@@ -233,6 +294,7 @@ impl EntityStoreServer {
         {
             let entities = self.entity_store.auto_register_entity(&topic_id)?;
             for entity in entities {
+                self.notify_registration(&entity);
                 let message = entity
                     .to_mqtt_message(&self.config.mqtt_schema)
                     .with_retain();
@@ -247,7 +309,12 @@ impl EntityStoreServer {
                 serde_json::from_slice(message.payload_bytes())?
             };
             let twin_message = EntityTwinMessage::new(topic_id, fragment_key, fragment_value);
-            self.entity_store.update_twin_fragment(twin_message)?;
+            if self
+                .entity_store
+                .update_twin_fragment(twin_message.clone())?
+            {
+                self.notify(EntityChange::TwinUpdated(twin_message));
+            }
         }
 
         Ok(())
@@ -261,6 +328,7 @@ impl EntityStoreServer {
             .entity_store
             .update_twin_fragment(twin_message.clone())?;
         if updated {
+            self.notify(EntityChange::TwinUpdated(twin_message.clone()));
             self.publish_twin_data(
                 &twin_message.topic_id,
                 twin_message.fragment_key,
@@ -315,6 +383,9 @@ impl EntityStoreServer {
         }
 
         let registered = self.entity_store.update(entity.clone())?;
+        for entity in registered.iter() {
+            self.notify_registration(&entity.reg_message);
+        }
 
         if !registered.is_empty() {
             let message = entity.to_mqtt_message(&self.config.mqtt_schema);
@@ -329,7 +400,9 @@ impl EntityStoreServer {
         update_message: EntityUpdateMessage,
     ) -> Result<&EntityMetadata, entity_store::Error> {
         let entity = self.entity_store.update_entity(topic_id, update_message)?;
+        let updated_entity = EntityChange::Registered(entity.clone());
         let entity_reg_msg: EntityRegistrationMessage = entity.into();
+        self.notify(updated_entity);
         let entity_msg = entity_reg_msg.to_mqtt_message(&self.config.mqtt_schema);
 
         self.publish_message(entity_msg).await;
@@ -339,8 +412,27 @@ impl EntityStoreServer {
 
     async fn deregister_entity(&mut self, topic_id: &EntityTopicId) -> Vec<EntityMetadata> {
         let deleted = self.entity_store.deregister_entity(topic_id);
+        self.clear_deregistered_entities(&deleted).await;
+        deleted
+    }
+
+    async fn deregister_and_persist_entity(
+        &mut self,
+        topic_id: &EntityTopicId,
+    ) -> Result<Vec<EntityMetadata>, entity_store::Error> {
+        let deleted = self.entity_store.deregister_and_persist_entity(topic_id)?;
+        self.clear_deregistered_entities(&deleted).await;
+        Ok(deleted)
+    }
+
+    /// Clear the retained messages of deregistered entities and notify the subscribers
+    async fn clear_deregistered_entities(&mut self, deleted: &[EntityMetadata]) {
         if deleted.is_empty() {
-            return deleted;
+            return;
+        }
+        for entity in deleted.iter() {
+            self.activity.forget(&entity.topic_id);
+            self.notify(EntityChange::Deregistered(entity.clone()));
         }
 
         let mut topics = TopicFilter::empty();
@@ -383,8 +475,150 @@ impl EntityStoreServer {
 
             self.publish_message(clear_entity_msg).await;
         }
+    }
 
-        deleted
+    /// Record the activity of an entity and of its ancestors
+    ///
+    /// A stale entity that is active again is no more flagged as stale.
+    async fn record_activity(&mut self, topic_id: &EntityTopicId, now: OffsetDateTime) {
+        let Ok(ancestors) = self.entity_store.ancestors(topic_id) else {
+            // Unknown entity
+            return;
+        };
+        let mut active_entities = vec![topic_id.clone()];
+        active_entities.extend(ancestors.into_iter().cloned());
+
+        for topic_id in active_entities {
+            if self.activity.touch(&topic_id, now) {
+                info!("Entity {topic_id} is active again");
+                let twin_message =
+                    EntityTwinMessage::new(topic_id, LIFECYCLE_FRAGMENT.to_string(), Value::Null);
+                if let Err(err) = self.set_twin_fragment(twin_message).await {
+                    error!("Failed to clear the stale flag of an active entity: {err}");
+                }
+            }
+        }
+    }
+
+    /// Mark stale or deregister the entities with no recent activity, as per the lifecycle policies
+    ///
+    /// A stale entity is flagged with a `lifecycle` twin fragment.
+    /// The deregistration of an expired entity is notified by an `entity_removed` event
+    /// published on behalf of its parent.
+    pub(crate) async fn check_lifecycle(&mut self, now: OffsetDateTime) {
+        let changes = self.activity.check(
+            &self.config.lifecycle_policies,
+            self.entity_store.list_entity_tree(ListFilters::default()),
+            now,
+        );
+
+        for (topic_id, last_activity) in changes.stale {
+            let last_activity = format_time(last_activity);
+            info!("Entity {topic_id} is stale: no activity since {last_activity}");
+            let lifecycle = json!({
+                "status": "stale",
+                "lastActivity": last_activity,
+            });
+            let twin_message =
+                EntityTwinMessage::new(topic_id, LIFECYCLE_FRAGMENT.to_string(), lifecycle);
+            if let Err(err) = self.set_twin_fragment(twin_message).await {
+                error!("Failed to mark a stale entity: {err}");
+            }
+        }
+
+        for (topic_id, last_activity) in changes.expired {
+            // Skip the entities already removed along an expired ancestor
+            let Some(entity) = self.entity_store.get(&topic_id).cloned() else {
+                continue;
+            };
+            let last_activity = format_time(last_activity);
+            info!("Deregistering entity {topic_id}: no activity since {last_activity}");
+            match self.deregister_and_persist_entity(&topic_id).await {
+                Ok(_) => self.publish_removal(&entity, &last_activity).await,
+                Err(err) => error!("Failed to deregister the expired entity {topic_id}: {err}"),
+            }
+        }
+    }
+
+    /// Register the entities declared by the entity manifests, and deregister those no more declared
+    ///
+    /// The twin fragments declared by the manifests are initial values:
+    /// these are set only when missing, not to override the values updated since.
+    pub(crate) async fn reconcile(
+        &mut self,
+        manifest: EntityManifest,
+        removed: Vec<EntityTopicId>,
+    ) {
+        for topic_id in removed {
+            info!("Deregistering entity {topic_id}: no more declared by the entity manifests");
+            if let Err(err) = self.deregister_and_persist_entity(&topic_id).await {
+                error!("Failed to deregister the undeclared entity {topic_id}: {err}");
+            }
+        }
+
+        for entity in manifest.parents_first().entities {
+            let mut registration = EntityRegistrationMessage::from(entity);
+            let topic_id = registration.topic_id.clone();
+            let missing_fragments: Map<String, Value> = match self.entity_store.get(&topic_id) {
+                None => Map::new(),
+                Some(existing) => std::mem::take(&mut registration.twin_data)
+                    .into_iter()
+                    .filter(|(fragment_key, _)| !existing.twin_data.contains_key(fragment_key))
+                    .collect(),
+            };
+
+            match self.entity_store.update(registration) {
+                Ok(registered) => {
+                    for entity in registered {
+                        self.notify_registration(&entity.reg_message);
+                        let message = entity
+                            .reg_message
+                            .clone()
+                            .to_mqtt_message(&self.config.mqtt_schema);
+                        self.publish_message(message).await;
+                        for (fragment_key, fragment_value) in entity.reg_message.twin_data {
+                            self.publish_twin_data(
+                                &entity.reg_message.topic_id,
+                                fragment_key,
+                                fragment_value,
+                            )
+                            .await;
+                        }
+                    }
+                }
+                Err(err) => {
+                    error!("Failed to register the declared entity {topic_id}: {err}");
+                    continue;
+                }
+            }
+
+            for (fragment_key, fragment_value) in missing_fragments {
+                let twin_message =
+                    EntityTwinMessage::new(topic_id.clone(), fragment_key, fragment_value);
+                if let Err(err) = self.set_twin_fragment(twin_message).await {
+                    error!("Failed to set the declared twin data of {topic_id}: {err}");
+                }
+            }
+        }
+    }
+
+    async fn publish_removal(&mut self, entity: &EntityMetadata, last_activity: &str) {
+        let parent = entity
+            .parent
+            .clone()
+            .unwrap_or_else(|| self.entity_store.main_device().clone());
+        let channel = Channel::Event {
+            event_type: ENTITY_REMOVED_EVENT.to_string(),
+        };
+        let topic = self.config.mqtt_schema.topic_for(&parent, &channel);
+        let payload = json!({
+            "text": format!("Removed {}: no activity since {last_activity}", entity.topic_id),
+            "@topic-id": entity.topic_id,
+            "@type": entity.r#type,
+            "lastActivity": last_activity,
+        });
+        self.publish_message(MqttMessage::new(&topic, payload.to_string()))
+            .await;
     }
 
     async fn set_entity_twin_fragments(
@@ -405,6 +639,7 @@ impl EntityStoreServer {
         // Clear all old twin messages
         for fragment_key in fragments_to_clear.into_iter() {
             let twin_message = EntityTwinMessage::new(topic_id.clone(), fragment_key, Value::Null);
+            self.notify(EntityChange::TwinUpdated(twin_message.clone()));
             let message = twin_message.to_mqtt_message(&self.config.mqtt_schema);
             self.publish_message(message).await;
         }
@@ -417,6 +652,7 @@ impl EntityStoreServer {
             }
             let twin_message =
                 EntityTwinMessage::new(topic_id.clone(), fragment_key, fragment_value);
+            self.notify(EntityChange::TwinUpdated(twin_message.clone()));
 
             let message = twin_message.to_mqtt_message(&self.config.mqtt_schema);
             self.publish_message(message).await;
@@ -424,6 +660,46 @@ impl EntityStoreServer {
 
         Ok(())
     }
+
+    /// Notify the subscribers that an entity has been registered along with its twin data
+    fn notify_registration(&mut self, entity: &EntityRegistrationMessage) {
+        let Some(metadata) = self.entity_store.get(&entity.topic_id).cloned() else {
+            return;
+        };
+        self.notify(EntityChange::Registered(metadata));
+        for (fragment_key, fragment_value) in entity.twin_data.iter() {
+            let twin_message = EntityTwinMessage::new(
+                entity.topic_id.clone(),
+                fragment_key.clone(),
+                fragment_value.clone(),
+            );
+            self.notify(EntityChange::TwinUpdated(twin_message));
+        }
+    }
+
+    /// Notify a change to the subscribers, forgetting those who unsubscribed
+    fn notify(&mut self, change: EntityChange) {
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(change.clone()).is_ok());
+    }
+}
+
+/// Check if a message published by an entity is a sign of activity
+///
+/// Cleared retained messages as well as the lifecycle notifications published by the agent are ignored.
+fn is_activity(channel: &Channel, message: &MqttMessage) -> bool {
+    if message.payload_bytes().is_empty() {
+        return false;
+    }
+    match channel {
+        Channel::EntityTwinData { fragment_key } => fragment_key != LIFECYCLE_FRAGMENT,
+        Channel::Event { event_type } => event_type != ENTITY_REMOVED_EVENT,
+        _ => true,
+    }
+}
+
+fn format_time(time: OffsetDateTime) -> String {
+    time.format(&Rfc3339).unwrap_or_else(|_| time.to_string())
 }
 
 pub fn subscriptions(mqtt_schema: &MqttSchema) -> TopicFilter {
@@ -1,3 +1,7 @@
+use crate::entity_manager::lifecycle::EntityLifecyclePolicies;
+use crate::entity_manager::lifecycle::EntityLifecyclePolicy;
+use crate::entity_manager::server::EntityChange;
+use crate::entity_manager::server::EntityStoreRequest;
 use crate::entity_manager::server::EntityStoreResponse;
 use crate::entity_manager::tests::model::Action;
 use crate::entity_manager::tests::model::Action::AddDevice;
@@ -6,17 +10,21 @@ use crate::entity_manager::tests::model::Command;
 use crate::entity_manager::tests::model::Commands;
 use crate::entity_manager::tests::model::Protocol::HTTP;
 use crate::entity_manager::tests::model::Protocol::MQTT;
+use futures::channel::mpsc;
 use proptest::proptest;
 use serde_json::json;
 use std::collections::HashSet;
+use std::time::Duration;
 use tedge_actors::test_helpers::MessageReceiverExt;
 use tedge_actors::Sender;
 use tedge_actors::Server;
 use tedge_api::entity::EntityMetadata;
 use tedge_api::entity::EntityType;
+use tedge_api::entity_manifest::EntityManifest;
 use tedge_api::mqtt_topics::EntityTopicId;
 use tedge_mqtt_ext::test_helpers::assert_received_contains_str;
 use tedge_mqtt_ext::MqttMessage;
+use time::OffsetDateTime;
 
 #[tokio::test]
 async fn new_entity_store() {
@@ -222,6 +230,245 @@ async fn clear_entity_twin_data() {
     assert_eq!(entity.twin_data.get("x"), None);
 }
 
+#[tokio::test]
+async fn subscribers_are_notified_of_entity_changes() {
+    let handle = entity::server("device-under-test");
+    let mut entity_store = handle.entity_store;
+
+    let (sender, mut receiver) = mpsc::unbounded();
+    entity_store
+        .handle(EntityStoreRequest::Subscribe(sender))
+        .await;
+
+    entity::create_entity(
+        &mut entity_store,
+        "device/child0//",
+        EntityType::ChildDevice,
+        None,
+    )
+    .await
+    .unwrap();
+    entity_store
+        .process_mqtt_message(MqttMessage::from(("te/device/child0///twin/x", "9")).with_retain())
+        .await;
+    entity::delete_entity(&mut entity_store, "device/child0//")
+        .await
+        .unwrap();
+
+    let Ok(EntityChange::Registered(entity)) = receiver.try_recv() else {
+        panic!("Expected a registration")
+    };
+    assert_eq!(entity.topic_id.as_str(), "device/child0//");
+
+    let Ok(EntityChange::TwinUpdated(twin)) = receiver.try_recv() else {
+        panic!("Expected a twin update")
+    };
+    assert_eq!(twin.topic_id.as_str(), "device/child0//");
+    assert_eq!(twin.fragment_key, "x");
+    assert_eq!(twin.fragment_value, json!(9));
+
+    let Ok(EntityChange::Deregistered(entity)) = receiver.try_recv() else {
+        panic!("Expected a deregistration")
+    };
+    assert_eq!(entity.topic_id.as_str(), "device/child0//");
+
+    assert!(receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn idle_entities_are_marked_stale_then_removed() {
+    let policies = EntityLifecyclePolicies {
+        child_device: Some(EntityLifecyclePolicy::new(
+            Duration::from_secs(3600),
+            Some(Duration::from_secs(3600)),
+        )),
+        service: None,
+    };
+    let handle = entity::server_with_lifecycle_policies("device-under-test", policies);
+    let (mut entity_store, mut mqtt_output) = (handle.entity_store, handle.mqtt_output);
+
+    entity::create_entity(
+        &mut entity_store,
+        "device/child0//",
+        EntityType::ChildDevice,
+        None,
+    )
+    .await
+    .unwrap();
+    mqtt_output.skip(1).await; // Skip the registration message
+
+    let start = OffsetDateTime::now_utc();
+    entity_store
+        .process_mqtt_message(MqttMessage::from((
+            "te/device/child0///m/environment",
+            r#"{"temp": 21}"#,
+        )))
+        .await;
+
+    entity_store
+        .check_lifecycle(start + Duration::from_secs(1800))
+        .await;
+    entity_store
+        .check_lifecycle(start + Duration::from_secs(3700))
+        .await;
+    assert_received_contains_str(
+        &mut mqtt_output,
+        [("te/device/child0///twin/lifecycle", r#""status":"stale""#)],
+    )
+    .await;
+    let child = entity::get(&mut entity_store, "device/child0//")
+        .await
+        .unwrap();
+    assert_eq!(
+        child.twin_data.get("lifecycle").unwrap()["status"],
+        json!("stale")
+    );
+
+    entity_store
+        .check_lifecycle(start + Duration::from_secs(7300))
+        .await;
+    mqtt_output
+        .assert_received([
+            MqttMessage::from(("te/device/child0///twin/lifecycle", "")).with_retain(),
+            MqttMessage::from(("te/device/child0//", "")).with_retain(),
+        ])
+        .await;
+    assert_received_contains_str(
+        &mut mqtt_output,
+        [(
+            "te/device/main///e/entity_removed",
+            r#""@topic-id":"device/child0//""#,
+        )],
+    )
+    .await;
+    assert_eq!(
+        entity::get(&mut entity_store, "device/child0//").await,
+        None
+    );
+}
+
+#[tokio::test]
+async fn active_entities_are_no_more_stale() {
+    let policies = EntityLifecyclePolicies {
+        child_device: None,
+        service: Some(EntityLifecyclePolicy::new(Duration::from_secs(60), None)),
+    };
+    let handle = entity::server_with_lifecycle_policies("device-under-test", policies);
+    let (mut entity_store, mut mqtt_output) = (handle.entity_store, handle.mqtt_output);
+
+    entity::create_entity(
+        &mut entity_store,
+        "device/main/service/collectd",
+        EntityType::Service,
+        None,
+    )
+    .await
+    .unwrap();
+    mqtt_output.skip(1).await; // Skip the registration message
+
+    entity_store
+        .check_lifecycle(OffsetDateTime::now_utc())
+        .await;
+    entity_store
+        .check_lifecycle(OffsetDateTime::now_utc() + Duration::from_secs(3600))
+        .await;
+    assert_received_contains_str(
+        &mut mqtt_output,
+        [(
+            "te/device/main/service/collectd/twin/lifecycle",
+            r#""status":"stale""#,
+        )],
+    )
+    .await;
+
+    // The stale flag published by the agent is not an activity
+    entity_store
+        .process_mqtt_message(
+            MqttMessage::from((
+                "te/device/main/service/collectd/twin/lifecycle",
+                r#"{"status":"stale"}"#,
+            ))
+            .with_retain(),
+        )
+        .await;
+    entity_store
+        .process_mqtt_message(
+            MqttMessage::from((
+                "te/device/main/service/collectd/status/health",
+                r#"{"status":"up"}"#,
+            ))
+            .with_retain(),
+        )
+        .await;
+    mqtt_output
+        .assert_received([MqttMessage::from((
+            "te/device/main/service/collectd/twin/lifecycle",
+            "",
+        ))
+        .with_retain()])
+        .await;
+}
+
+#[tokio::test]
+async fn declared_entities_are_reconciled() {
+    let handle = entity::server("device-under-test");
+    let mut entity_store = handle.entity_store;
+
+    let manifest: EntityManifest = serde_json::from_value(json!({
+        "entities": [
+            {"@topic-id": "device/child1/service/collectd", "@type": "service"},
+            {"@topic-id": "device/child1//", "@type": "child-device", "name": "Child 1"},
+        ]
+    }))
+    .unwrap();
+    entity_store.reconcile(manifest.clone(), vec![]).await;
+
+    let child = entity::get(&mut entity_store, "device/child1//")
+        .await
+        .unwrap();
+    assert_eq!(child.twin_data.get("name"), Some(&json!("Child 1")));
+    assert!(
+        entity::get(&mut entity_store, "device/child1/service/collectd")
+            .await
+            .is_some()
+    );
+
+    // The declared twin data are initial values, not overriding those updated since
+    entity_store
+        .process_mqtt_message(
+            MqttMessage::from(("te/device/child1///twin/name", r#""Renamed""#)).with_retain(),
+        )
+        .await;
+    let mut manifest = manifest;
+    manifest.entities[1]
+        .payload
+        .twin_data
+        .insert("location".to_string(), json!("hall"));
+    entity_store.reconcile(manifest, vec![]).await;
+
+    let child = entity::get(&mut entity_store, "device/child1//")
+        .await
+        .unwrap();
+    assert_eq!(child.twin_data.get("name"), Some(&json!("Renamed")));
+    assert_eq!(child.twin_data.get("location"), Some(&json!("hall")));
+
+    // The entities no more declared are removed, along their services
+    entity_store
+        .reconcile(
+            EntityManifest::default(),
+            vec!["device/child1//".parse().unwrap()],
+        )
+        .await;
+    assert_eq!(
+        entity::get(&mut entity_store, "device/child1//").await,
+        None
+    );
+    assert_eq!(
+        entity::get(&mut entity_store, "device/child1/service/collectd").await,
+        None
+    );
+}
+
 proptest! {
     //#![proptest_config(proptest::prelude::ProptestConfig::with_cases(1000))]
     #[test]
@@ -329,6 +576,7 @@ async fn check_registrations_from_user_pov(registrations: Commands) {
 }
 
 mod entity {
+    use crate::entity_manager::lifecycle::EntityLifecyclePolicies;
     use crate::entity_manager::server::EntityStoreRequest;
     use crate::entity_manager::server::EntityStoreResponse;
     use crate::entity_manager::server::EntityStoreServer;
@@ -423,6 +671,13 @@ mod entity {
     }
 
     pub fn server(device_id: &str) -> TestHandle {
+        server_with_lifecycle_policies(device_id, EntityLifecyclePolicies::default())
+    }
+
+    pub fn server_with_lifecycle_policies(
+        device_id: &str,
+        lifecycle_policies: EntityLifecyclePolicies,
+    ) -> TestHandle {
         let mqtt_schema = MqttSchema::default();
         let main_device = EntityRegistrationMessage::main_device(Some(device_id.to_string()));
         let telemetry_cache_size = 0;
@@ -439,7 +694,8 @@ mod entity {
         )
         .unwrap();
 
-        let config = EntityStoreServerConfig::new(mqtt_schema.clone(), entity_auto_register);
+        let config = EntityStoreServerConfig::new(mqtt_schema.clone(), entity_auto_register)
+            .with_lifecycle_policies(lifecycle_policies);
 
         let mqtt_actor = SimpleMessageBoxBuilder::new("MQTT", 64);
         let mut actor_builder = TestMqttActorBuilder {
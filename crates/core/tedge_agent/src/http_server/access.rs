@@ -0,0 +1,728 @@
+//! Access control of the agent HTTP API.
+//!
+//! When enabled, any request must be authenticated either by a client certificate,
+//! the client being identified by the certificate common name, or by a bearer token.
+//! The policy of the authenticated client then defines the entity subtrees
+//! and the file transfer paths this client is granted read or write access to.
+//!
+//! ```toml
+//! [[clients]]
+//! name = "child1"
+//! token_sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//!
+//! [[clients.entities]]
+//! topic_id = "device/child1//"
+//! access = "write"
+//!
+//! [[clients.files]]
+//! prefix = "child1"
+//! access = "read"
+//! ```
+use super::entity_store::requested_topic_id;
+use super::entity_store::HTTP_MAX_PAYLOAD_SIZE;
+use super::server::AgentState;
+use crate::entity_manager::server::EntityStoreRequest;
+use crate::entity_manager::server::EntityStoreResponse;
+use axum::body::Body;
+use axum::extract::Query;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum_tls::TlsData;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use hyper::header::AUTHORIZATION;
+use hyper::header::WWW_AUTHENTICATE;
+use hyper::Method;
+use hyper::StatusCode;
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tedge_actors::ChannelError;
+use tedge_api::entity_manifest::EntityDeclaration;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_utils::fs::atomically_write_file_sync;
+use tedge_utils::fs::AtomFileError;
+use tracing::error;
+use tracing::warn;
+
+/// The kind of access granted to a client, write access implying read access
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// The access policies of all the clients of the agent HTTP API
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessPolicies {
+    #[serde(default)]
+    pub clients: Vec<ClientPolicy>,
+}
+
+/// How a client is authenticated and what this client is granted access to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientPolicy {
+    /// The name of the client, used in the logs
+    pub name: String,
+
+    /// The common name of the client certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+
+    /// The SHA-256 digest of the client bearer token
+    ///
+    /// Only the digest is stored, the token itself being only known by the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_sha256: Option<String>,
+
+    /// The entity subtrees this client is granted access to
+    #[serde(default)]
+    pub entities: Vec<EntityGrant>,
+
+    /// The file transfer paths this client is granted access to
+    #[serde(default)]
+    pub files: Vec<FileGrant>,
+}
+
+/// Access to an entity and all its descendants
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityGrant {
+    pub topic_id: EntityTopicId,
+    pub access: Access,
+}
+
+/// Access to the file transfer paths starting with a prefix
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileGrant {
+    pub prefix: Utf8PathBuf,
+    pub access: Access,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AccessPolicyError {
+    #[error("Failed to read {path}: {source}")]
+    ReadFailed {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid access policy file {path}: {source}")]
+    InvalidToml {
+        path: Utf8PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error(transparent)]
+    SerializationFailed(#[from] toml::ser::Error),
+
+    #[error(transparent)]
+    WriteFailed(#[from] AtomFileError),
+}
+
+/// The SHA-256 digest of a bearer token, as stored in the access policies
+pub fn token_digest(token: &str) -> String {
+    sha256::digest(token)
+}
+
+impl AccessPolicies {
+    /// Load the access policies from a TOML file
+    ///
+    /// A missing file is not an error but defines no clients.
+    pub fn load(path: &Utf8Path) -> Result<Self, AccessPolicyError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(AccessPolicies::default())
+            }
+            Err(source) => {
+                return Err(AccessPolicyError::ReadFailed {
+                    path: path.to_owned(),
+                    source,
+                })
+            }
+        };
+        toml::from_str(&content).map_err(|source| AccessPolicyError::InvalidToml {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// Store the access policies into a TOML file
+    pub fn store(&self, path: &Utf8Path) -> Result<(), AccessPolicyError> {
+        let content = toml::to_string(self)?;
+        atomically_write_file_sync(path, content.as_bytes())?;
+        Ok(())
+    }
+
+    /// Add a client policy, replacing any previous policy with the same name
+    pub fn insert(&mut self, client: ClientPolicy) {
+        match self.clients.iter_mut().find(|c| c.name == client.name) {
+            Some(existing) => *existing = client,
+            None => self.clients.push(client),
+        }
+    }
+
+    /// Find the client authenticated either by a bearer token or by a certificate
+    ///
+    /// A bearer token takes precedence over the certificate of the connection:
+    /// a request with an unknown token is rejected even if the client certificate is known.
+    pub fn authenticate(
+        &self,
+        bearer_token: Option<&str>,
+        common_name: Option<&str>,
+    ) -> Option<&ClientPolicy> {
+        match (bearer_token, common_name) {
+            (Some(token), _) => {
+                let digest = token_digest(token);
+                self.clients
+                    .iter()
+                    .find(|client| client.token_sha256.as_deref() == Some(digest.as_str()))
+            }
+            (None, Some(common_name)) => self
+                .clients
+                .iter()
+                .find(|client| client.certificate.as_deref() == Some(common_name)),
+            (None, None) => None,
+        }
+    }
+}
+
+impl ClientPolicy {
+    /// Check if this client can access an entity, given the lineage of this entity
+    ///
+    /// The lineage starts with the entity itself, followed by its parent, grand-parent and so on.
+    pub fn can_access_entity(&self, lineage: &[EntityTopicId], access: Access) -> bool {
+        self.entities
+            .iter()
+            .any(|grant| grant.access >= access && lineage.contains(&grant.topic_id))
+    }
+
+    /// Check if this client can access a file transfer path
+    pub fn can_access_file(&self, path: &Utf8Path, access: Access) -> bool {
+        self.files
+            .iter()
+            .any(|grant| grant.access >= access && path.starts_with(&grant.prefix))
+    }
+}
+
+/// The access policies enforced by the HTTP server, reloaded on any update of the policy file
+#[derive(Debug, Clone)]
+pub struct HttpAccessControl {
+    policy_file: Utf8PathBuf,
+    main_device: EntityTopicId,
+    cache: Arc<Mutex<Option<CachedPolicies>>>,
+}
+
+/// The policies last loaded, along with the modification time of the policy file
+type CachedPolicies = (SystemTime, Arc<AccessPolicies>);
+
+impl HttpAccessControl {
+    pub fn new(policy_file: Utf8PathBuf, main_device: EntityTopicId) -> Self {
+        HttpAccessControl {
+            policy_file,
+            main_device,
+            cache: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The current access policies
+    ///
+    /// If the policy file cannot be loaded, the previous policies are kept,
+    /// and no client is granted access if none have ever been loaded.
+    async fn policies(&self) -> Arc<AccessPolicies> {
+        let modified = tokio::fs::metadata(&self.policy_file)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        let previous = self.cache.lock().unwrap().clone();
+        if let Some((loaded, policies)) = &previous {
+            if *loaded == modified {
+                return policies.clone();
+            }
+        }
+
+        // The file is read without holding the cache lock
+        let policy_file = self.policy_file.clone();
+        let loaded = tokio::task::spawn_blocking(move || AccessPolicies::load(&policy_file)).await;
+        let policies = match loaded {
+            Ok(Ok(policies)) => Arc::new(policies),
+            Ok(Err(err)) => {
+                error!("{err}");
+                previous.map(|(_, policies)| policies).unwrap_or_default()
+            }
+            Err(err) => {
+                error!("Failed to load the HTTP access policies: {err}");
+                previous.map(|(_, policies)| policies).unwrap_or_default()
+            }
+        };
+        *self.cache.lock().unwrap() = Some((modified, policies.clone()));
+        policies
+    }
+}
+
+/// The resource targeted by a request, as checked against the client policy
+enum Resource {
+    File(Utf8PathBuf),
+    Entity(EntityTopicId),
+    NewEntity(EntityDeclaration),
+    /// A request not related to any known resource, that is denied
+    Unknown,
+}
+
+/// Axum middleware rejecting the requests of unauthenticated clients
+/// and those to resources not granted to the client
+pub(crate) async fn authorize(
+    State(state): State<AgentState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(access_control) = state.access_control.clone() else {
+        return next.run(request).await;
+    };
+
+    let policies = access_control.policies().await;
+    let bearer_token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let common_name = request
+        .extensions()
+        .get::<TlsData>()
+        .and_then(|tls_data| tls_data.common_name.as_deref());
+    let Some(client) = policies.authenticate(bearer_token, common_name) else {
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, "Bearer")],
+            "Authentication required",
+        )
+            .into_response();
+    };
+
+    let access = match *request.method() {
+        Method::GET | Method::HEAD => Access::Read,
+        _ => Access::Write,
+    };
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let (request, resource) = match requested_resource(request, &access_control.main_device).await {
+        Ok(requested) => requested,
+        Err(response) => return response,
+    };
+
+    let granted = match resource {
+        Resource::File(path) => client.can_access_file(&path, access),
+        Resource::Entity(topic_id) => match lineage(&state, topic_id).await {
+            Ok(lineage) => client.can_access_entity(&lineage, access),
+            Err(err) => return internal_error(err),
+        },
+        Resource::NewEntity(entity) => {
+            let parent = entity
+                .parent()
+                .unwrap_or_else(|| access_control.main_device.clone());
+            match lineage(&state, parent).await {
+                Ok(mut lineage) => {
+                    lineage.insert(0, entity.topic_id);
+                    client.can_access_entity(&lineage, access)
+                }
+                Err(err) => return internal_error(err),
+            }
+        }
+        Resource::Unknown => false,
+    };
+
+    if !granted {
+        warn!("HTTP access denied to {}: {method} {path}", client.name);
+        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    }
+    next.run(request).await
+}
+
+/// Determine the resource targeted by a request
+///
+/// The path is percent-decoded as done by the router when extracting the path parameters,
+/// so the resource is checked as seen by the request handlers.
+///
+/// The body of an entity registration request is read to get the topic id and parent of the new entity,
+/// hence the request is returned to be forwarded along the resource.
+async fn requested_resource(
+    request: Request,
+    main_device: &EntityTopicId,
+) -> Result<(Request, Resource), Response> {
+    let method = request.method().clone();
+    let Ok(path) = percent_decode_str(request.uri().path()).decode_utf8() else {
+        return Ok((request, Resource::Unknown));
+    };
+    let path = path.into_owned();
+
+    if let Some(file_path) = path
+        .strip_prefix("/te/v1/files/")
+        .or_else(|| path.strip_prefix("/tedge/file-transfer/"))
+    {
+        // Cleaned as done by the file transfer service, so `a/../b` is checked as `b`
+        let file_path = path_clean::clean(file_path.trim_start_matches('/'));
+        let resource = match Utf8PathBuf::try_from(file_path) {
+            Ok(file_path) => Resource::File(file_path),
+            Err(_) => Resource::Unknown,
+        };
+        return Ok((request, resource));
+    }
+
+    if let Some(entity_path) = path.strip_prefix("/te/v1/entities/") {
+        let resource = match requested_topic_id(entity_path) {
+            Some(topic_id) => Resource::Entity(topic_id),
+            None => Resource::Unknown,
+        };
+        return Ok((request, resource));
+    }
+
+    match (path.as_str(), method) {
+        ("/te/v1/entities", Method::POST) => {
+            let (parts, body) = request.into_parts();
+            let bytes = axum::body::to_bytes(body, HTTP_MAX_PAYLOAD_SIZE)
+                .await
+                .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+            let resource = match serde_json::from_slice::<EntityDeclaration>(&bytes) {
+                Ok(entity) => Resource::NewEntity(entity),
+                Err(_) => Resource::Unknown,
+            };
+            Ok((Request::from_parts(parts, Body::from(bytes)), resource))
+        }
+        ("/te/v1/entities", _) => {
+            // Only the clients granted access to the whole entity tree can list all the entities
+            let root = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+                .ok()
+                .and_then(|Query(params)| params.get("root")?.parse().ok())
+                .unwrap_or_else(|| main_device.clone());
+            Ok((request, Resource::Entity(root)))
+        }
+        ("/te/v1/entity-events", _) | ("/te/v1/commands", _) => {
+            Ok((request, Resource::Entity(main_device.clone())))
+        }
+        _ => Ok((request, Resource::Unknown)),
+    }
+}
+
+/// The lineage of an entity: this entity, followed by its parent, grand-parent and so on
+async fn lineage(
+    state: &AgentState,
+    topic_id: EntityTopicId,
+) -> Result<Vec<EntityTopicId>, ChannelError> {
+    let mut lineage = vec![];
+    let mut current = Some(topic_id);
+    while let Some(topic_id) = current.take() {
+        if lineage.contains(&topic_id) {
+            break;
+        }
+        let response = state
+            .entity_store_handle
+            .clone()
+            .await_response(EntityStoreRequest::Get(topic_id.clone()))
+            .await?;
+        if let EntityStoreResponse::Get(Some(entity)) = response {
+            current = entity.parent;
+        }
+        lineage.push(topic_id);
+    }
+    Ok(lineage)
+}
+
+fn internal_error(err: ChannelError) -> Response {
+    error!("Fail to check HTTP access: {err}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_server::server::router;
+    use tedge_actors::Builder;
+    use tedge_actors::ClientMessageBox;
+    use tedge_actors::MessageReceiver;
+    use tedge_actors::ServerMessageBox;
+    use tedge_actors::ServerMessageBoxBuilder;
+    use tedge_api::entity::EntityMetadata;
+    use tedge_api::path::DataDir;
+    use tedge_test_utils::fs::TempTedgeDir;
+    use tedge_utils::paths::TedgePaths;
+    use tower::Service;
+
+    const POLICIES: &str = r#"
+[[clients]]
+name = "child1"
+token_sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+
+[[clients.entities]]
+topic_id = "device/child1//"
+access = "write"
+
+[[clients.files]]
+prefix = "child1"
+access = "write"
+
+[[clients.files]]
+prefix = "firmware"
+access = "read"
+
+[[clients]]
+name = "mapper"
+certificate = "gateway"
+
+[[clients.entities]]
+topic_id = "device/main//"
+access = "read"
+"#;
+
+    fn policies() -> AccessPolicies {
+        toml::from_str(POLICIES).unwrap()
+    }
+
+    fn topic_ids(ids: &[&str]) -> Vec<EntityTopicId> {
+        ids.iter().map(|id| id.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn clients_are_authenticated_by_token_or_certificate() {
+        let policies = policies();
+
+        let client = policies.authenticate(Some("test"), None).unwrap();
+        assert_eq!(client.name, "child1");
+        let client = policies.authenticate(None, Some("gateway")).unwrap();
+        assert_eq!(client.name, "mapper");
+
+        assert!(policies
+            .authenticate(Some("unknown"), Some("gateway"))
+            .is_none());
+        assert!(policies.authenticate(None, Some("unknown")).is_none());
+        assert!(policies.authenticate(None, None).is_none());
+    }
+
+    #[test]
+    fn entity_access_is_granted_to_subtrees() {
+        let policies = policies();
+        let child1 = &policies.clients[0];
+
+        let service = topic_ids(&[
+            "device/child1/service/collectd",
+            "device/child1//",
+            "device/main//",
+        ]);
+        assert!(child1.can_access_entity(&service, Access::Write));
+        assert!(child1.can_access_entity(&service, Access::Read));
+
+        let other = topic_ids(&["device/child2//", "device/main//"]);
+        assert!(!child1.can_access_entity(&other, Access::Read));
+
+        let mapper = &policies.clients[1];
+        assert!(mapper.can_access_entity(&other, Access::Read));
+        assert!(!mapper.can_access_entity(&other, Access::Write));
+    }
+
+    #[test]
+    fn file_access_is_granted_to_path_prefixes() {
+        let policies = policies();
+        let child1 = &policies.clients[0];
+
+        assert!(child1.can_access_file(Utf8Path::new("child1/logs.txt"), Access::Write));
+        assert!(child1.can_access_file(Utf8Path::new("firmware/v1.bin"), Access::Read));
+        assert!(!child1.can_access_file(Utf8Path::new("firmware/v1.bin"), Access::Write));
+        assert!(!child1.can_access_file(Utf8Path::new("child10/logs.txt"), Access::Read));
+    }
+
+    #[test]
+    fn policies_are_stored_and_reloaded() {
+        let ttd = TempTedgeDir::new();
+        let path = ttd.utf8_path().join("http-access.toml");
+        assert_eq!(
+            AccessPolicies::load(&path).unwrap(),
+            AccessPolicies::default()
+        );
+
+        let mut policies = policies();
+        policies.insert(ClientPolicy {
+            name: "child1".to_string(),
+            certificate: Some("child1".to_string()),
+            token_sha256: None,
+            entities: vec![],
+            files: vec![],
+        });
+        policies.store(&path).unwrap();
+
+        let reloaded = AccessPolicies::load(&path).unwrap();
+        assert_eq!(reloaded, policies);
+        assert_eq!(reloaded.clients.len(), 2);
+        assert_eq!(reloaded.clients[0].certificate.as_deref(), Some("child1"));
+    }
+
+    #[tokio::test]
+    async fn requests_are_checked_against_the_client_policy() {
+        let ttd = TempTedgeDir::new();
+        let (mut app, mut entity_store_box) = setup(&ttd);
+
+        // Mock entity store actor response
+        tokio::spawn(async move {
+            while let Some(mut req) = entity_store_box.recv().await {
+                let response = match req.request {
+                    EntityStoreRequest::Get(topic_id) if topic_id.as_str() == "device/child2//" => {
+                        EntityStoreResponse::Get(Some(
+                            EntityMetadata::child_device("child2".to_string()).unwrap(),
+                        ))
+                    }
+                    EntityStoreRequest::Get(_) => EntityStoreResponse::Get(None),
+                    _ => EntityStoreResponse::Ok,
+                };
+                req.reply_to.send(response).await.unwrap();
+            }
+        });
+
+        let request = |method: Method, uri: &str, token: Option<&str>| {
+            let mut request = hyper::Request::builder().method(method).uri(uri);
+            if let Some(token) = token {
+                request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+            }
+            request.body(Body::empty()).unwrap()
+        };
+
+        let response = app
+            .call(request(Method::GET, "/te/v1/files/child1/logs.txt", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .call(request(
+                Method::GET,
+                "/te/v1/files/child1/logs.txt",
+                Some("unknown"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .call(request(
+                Method::PUT,
+                "/te/v1/files/child1/../firmware/v1.bin",
+                Some("test"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .call(request(
+                Method::PUT,
+                "/te/v1/files/child1/..%2Fchild2%2Fsecret",
+                Some("test"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .call(request(
+                Method::GET,
+                "/te/v1/files/firmware/v1.bin",
+                Some("test"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .call(request(
+                Method::GET,
+                "/te/v1/files/child1%2Flogs.txt",
+                Some("test"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = app
+            .call(request(
+                Method::DELETE,
+                "/te/v1/entities/device/child2//",
+                Some("test"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        for method in [Method::GET, Method::DELETE] {
+            let response = app
+                .call(request(
+                    method,
+                    "/te/v1/entities/device%2Fchild2%2F%2F",
+                    Some("test"),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        let response = app
+            .call(request(Method::GET, "/te/v1/unknown", Some("test")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = app
+            .call(request(Method::GET, "/te/v1/entity-events", Some("test")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let registration = hyper::Request::builder()
+            .method(Method::POST)
+            .uri("/te/v1/entities")
+            .header(AUTHORIZATION, "Bearer test")
+            .header("Content-Type", "application/json")
+            .body(Body::from(
+                r#"{"@topic-id": "device/child11//", "@type": "child-device", "@parent": "device/child2//"}"#,
+            ))
+            .unwrap();
+        let response = app.call(registration).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    fn setup(
+        ttd: &TempTedgeDir,
+    ) -> (
+        axum::Router,
+        ServerMessageBox<EntityStoreRequest, EntityStoreResponse>,
+    ) {
+        let policy_file = ttd.utf8_path().join("http-access.toml");
+        std::fs::write(&policy_file, POLICIES).unwrap();
+        let data_dir: DataDir =
+            TedgePaths::from_root_with_defaults(ttd.utf8_path_buf(), "", "").into();
+        let file_transfer_dir = data_dir.file_transfer_dir();
+
+        let mut entity_store_box = ServerMessageBoxBuilder::new("EntityStoreBox", 16);
+        let entity_store_handle = ClientMessageBox::new(&mut entity_store_box);
+        let mut command_history_box = ServerMessageBoxBuilder::new("CommandHistoryBox", 16);
+        let command_history_handle = ClientMessageBox::new(&mut command_history_box);
+
+        let agent_state = AgentState {
+            file_transfer_dir,
+            data_dir,
+            entity_store_handle,
+            command_history_handle,
+            access_control: Some(HttpAccessControl::new(
+                policy_file,
+                EntityTopicId::default_main_device(),
+            )),
+        };
+
+        (router(agent_state), entity_store_box.build())
+    }
+}
@@ -1,5 +1,8 @@
+use crate::command_history::server::CommandHistoryRequest;
+use crate::command_history::server::CommandHistoryResponse;
 use crate::entity_manager::server::EntityStoreRequest;
 use crate::entity_manager::server::EntityStoreResponse;
+use crate::http_server::access::HttpAccessControl;
 use crate::http_server::error::HttpServerError;
 use crate::http_server::server::http_server;
 use crate::http_server::server::AgentState;
@@ -35,6 +38,8 @@ pub struct HttpServerActor {
     signal_receiver: mpsc::Receiver<RuntimeRequest>,
     listener: TcpListener,
     entity_store_handle: ClientMessageBox<EntityStoreRequest, EntityStoreResponse>,
+    command_history_handle: ClientMessageBox<CommandHistoryRequest, CommandHistoryResponse>,
+    access_control: Option<HttpAccessControl>,
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +52,8 @@ pub(crate) struct HttpServerConfig<CertKeyPath = Utf8PathBuf, CaPath = Utf8PathB
     pub key_path: OptionalConfig<CertKeyPath>,
     pub ca_path: OptionalConfig<CaPath>,
     pub bind_addr: SocketAddr,
+    /// The access policies enforced on the clients, if access control is enabled
+    pub access_control: Option<HttpAccessControl>,
 }
 
 /// HTTP file transfer server is stand-alone.
@@ -61,6 +68,8 @@ impl Actor for HttpServerActor {
             self.file_transfer_dir,
             self.data_dir,
             self.entity_store_handle,
+            self.command_history_handle,
+            self.access_control,
         );
 
         let server = http_server(self.listener, self.rustls_config, agent_state)?;
@@ -86,18 +95,22 @@ pub struct HttpServerBuilder {
     signal_receiver: mpsc::Receiver<RuntimeRequest>,
     listener: TcpListener,
     entity_store_handle: ClientMessageBox<EntityStoreRequest, EntityStoreResponse>,
+    command_history_handle: ClientMessageBox<CommandHistoryRequest, CommandHistoryResponse>,
+    access_control: Option<HttpAccessControl>,
 }
 
 impl HttpServerBuilder {
     pub(crate) async fn try_bind(
         config: HttpServerConfig<impl PemReader, impl TrustStoreLoader>,
         entity_store_service: &mut impl Service<EntityStoreRequest, EntityStoreResponse>,
+        command_history_service: &mut impl Service<CommandHistoryRequest, CommandHistoryResponse>,
     ) -> Result<Self, anyhow::Error> {
         let listener = TcpListener::bind(config.bind_addr)
             .await
             .with_context(|| format!("Binding file-transfer server to {}", config.bind_addr))?;
         let (signal_sender, signal_receiver) = mpsc::channel(10);
         let entity_store_handle = ClientMessageBox::new(entity_store_service);
+        let command_history_handle = ClientMessageBox::new(command_history_service);
 
         Ok(Self {
             rustls_config: load_ssl_config(
@@ -112,6 +125,8 @@ impl HttpServerBuilder {
             signal_receiver,
             listener,
             entity_store_handle,
+            command_history_handle,
+            access_control: config.access_control,
         })
     }
 }
@@ -133,6 +148,8 @@ impl Builder<HttpServerActor> for HttpServerBuilder {
             signal_receiver: self.signal_receiver,
             listener: self.listener,
             entity_store_handle: self.entity_store_handle,
+            command_history_handle: self.command_history_handle,
+            access_control: self.access_control,
         })
     }
 }
@@ -182,10 +199,14 @@ mod tests {
         let ttd = TempTedgeDir::new();
         let (_listener, port_in_use) = create_listener().await?;
         let mut entity_store_service = ServerMessageBoxBuilder::new("EntityStoreBox", 16);
+        let mut command_history_service = ServerMessageBoxBuilder::new("CommandHistoryBox", 16);
 
-        let binding_res =
-            HttpServerBuilder::try_bind(http_config(&ttd, port_in_use), &mut entity_store_service)
-                .await;
+        let binding_res = HttpServerBuilder::try_bind(
+            http_config(&ttd, port_in_use),
+            &mut entity_store_service,
+            &mut command_history_service,
+        )
+        .await;
 
         ensure!(
             binding_res.is_err(),
@@ -258,8 +279,15 @@ mod tests {
             let config = http_config(&temp_dir, 0);
             let (tx, rx) = mpsc::channel(1);
             let mut entity_store_service = ServerMessageBoxBuilder::new("EntityStoreBox", 16);
+            let mut command_history_service = ServerMessageBoxBuilder::new("CommandHistoryBox", 16);
 
-            let port = Self::spawn(config, tx, &mut entity_store_service).await?;
+            let port = Self::spawn(
+                config,
+                tx,
+                &mut entity_store_service,
+                &mut command_history_service,
+            )
+            .await?;
 
             Ok(TestFileTransferService {
                 port,
@@ -300,8 +328,15 @@ mod tests {
             let config = https_config(&temp_dir, &server_cert, trusted_root)?;
             let (tx, rx) = mpsc::channel(1);
             let mut entity_store_service = ServerMessageBoxBuilder::new("EntityStoreBox", 16);
+            let mut command_history_service = ServerMessageBoxBuilder::new("CommandHistoryBox", 16);
 
-            let port = Self::spawn(config, tx, &mut entity_store_service).await?;
+            let port = Self::spawn(
+                config,
+                tx,
+                &mut entity_store_service,
+                &mut command_history_service,
+            )
+            .await?;
 
             Ok(TestFileTransferService {
                 port,
@@ -358,8 +393,11 @@ mod tests {
             config: TestConfig,
             mut error_tx: Sender<RuntimeError>,
             entity_store_service: &mut impl Service<EntityStoreRequest, EntityStoreResponse>,
+            command_history_service: &mut impl Service<CommandHistoryRequest, CommandHistoryResponse>,
         ) -> anyhow::Result<u16> {
-            let builder = HttpServerBuilder::try_bind(config, entity_store_service).await?;
+            let builder =
+                HttpServerBuilder::try_bind(config, entity_store_service, command_history_service)
+                    .await?;
             let port = builder.listener.local_addr()?.port();
             let actor = builder.build();
 
@@ -392,6 +430,7 @@ mod tests {
             key_path: OptionalConfig::empty("http.key_path"),
             ca_path: OptionalConfig::empty("http.ca_path"),
             bind_addr: ([127, 0, 0, 1], bind_port).into(),
+            access_control: None,
         }
     }
 
@@ -422,6 +461,7 @@ mod tests {
                 .map(|c| OptionalConfig::present(InjectedValue(c), "http.ca_path"))
                 .unwrap_or_else(|| OptionalConfig::empty("http.ca_path")),
             bind_addr: ([127, 0, 0, 1], 0).into(),
+            access_control: None,
         })
     }
 }
@@ -0,0 +1,281 @@
+//! This module defines the axum routes and handlers for the command history REST API.
+//! The following endpoint is currently supported:
+//!
+//! - `GET /v1/commands`: Lists the completed commands, the most recent first,
+//!   filtered by `entity`, `operation`, `status` and time range (`from` and `to`).
+use super::server::AgentState;
+use crate::command_history::server::CommandFilters;
+use crate::command_history::server::CommandHistoryRequest;
+use crate::command_history::server::CommandHistoryResponse;
+use crate::command_history::server::CommandRecord;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::response::Response;
+use axum::routing::get;
+use axum::Json;
+use axum::Router;
+use hyper::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use tedge_api::mqtt_topics::TopicIdError;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CommandParams {
+    #[serde(default)]
+    entity: Option<String>,
+    #[serde(default)]
+    operation: Option<String>,
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    from: Option<String>,
+    #[serde(default)]
+    to: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq, Clone)]
+pub enum InputValidationError {
+    #[error(transparent)]
+    InvalidEntityTopic(#[from] TopicIdError),
+    #[error("Invalid `{0}` parameter: {1} is not an RFC 3339 timestamp")]
+    InvalidTimestamp(&'static str, String),
+}
+
+impl TryFrom<CommandParams> for CommandFilters {
+    type Error = InputValidationError;
+
+    fn try_from(params: CommandParams) -> Result<Self, Self::Error> {
+        let entity = params
+            .entity
+            .filter(|v| !v.is_empty())
+            .map(|val| val.parse())
+            .transpose()?;
+        let from = parse_timestamp("from", params.from)?;
+        let to = parse_timestamp("to", params.to)?;
+
+        Ok(Self {
+            entity,
+            operation: params.operation.filter(|v| !v.is_empty()),
+            status: params.status.filter(|v| !v.is_empty()),
+            from,
+            to,
+        })
+    }
+}
+
+fn parse_timestamp(
+    param: &'static str,
+    value: Option<String>,
+) -> Result<Option<OffsetDateTime>, InputValidationError> {
+    value
+        .filter(|v| !v.is_empty())
+        .map(|val| {
+            #[expect(
+                clippy::disallowed_methods,
+                reason = "Not vulnerable to RUSTSEC-2026-0009 as not RFC-2822 format"
+            )]
+            OffsetDateTime::parse(&val, &Rfc3339)
+                .map_err(|_| InputValidationError::InvalidTimestamp(param, val))
+        })
+        .transpose()
+}
+
+#[derive(thiserror::Error, Debug)]
+enum Error {
+    #[allow(clippy::enum_variant_names)]
+    #[error("Failed to query the command history")]
+    ChannelError(#[from] tedge_actors::ChannelError),
+
+    #[error("Received unexpected response from command history")]
+    InvalidCommandHistoryResponse,
+
+    #[error(transparent)]
+    InvalidInput(#[from] InputValidationError),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let status_code = match &self {
+            Error::ChannelError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::InvalidCommandHistoryResponse => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::InvalidInput(_) => StatusCode::BAD_REQUEST,
+        };
+        let error_message = self.to_string();
+
+        (status_code, Json(json!({ "error": error_message }))).into_response()
+    }
+}
+
+pub(crate) fn command_history_router(state: AgentState) -> Router {
+    Router::new()
+        .route("/v1/commands", get(list_commands))
+        .with_state(state)
+}
+
+async fn list_commands(
+    State(state): State<AgentState>,
+    Query(params): Query<CommandParams>,
+) -> Result<Json<Vec<CommandRecord>>, Error> {
+    let filters = params.try_into()?;
+    let response = state
+        .command_history_handle
+        .clone()
+        .await_response(CommandHistoryRequest::List(filters))
+        .await?;
+    let CommandHistoryResponse::List(commands) = response else {
+        return Err(Error::InvalidCommandHistoryResponse);
+    };
+
+    Ok(Json(commands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AgentState;
+    use crate::command_history::server::CommandFilters;
+    use crate::command_history::server::CommandHistoryRequest;
+    use crate::command_history::server::CommandHistoryResponse;
+    use crate::command_history::server::CommandRecord;
+    use crate::http_server::command_history::command_history_router;
+    use assert_json_diff::assert_json_eq;
+    use axum::body::Body;
+    use axum::Router;
+    use http_body_util::BodyExt as _;
+    use hyper::Method;
+    use hyper::Request;
+    use hyper::StatusCode;
+    use serde_json::json;
+    use serde_json::Value;
+    use tedge_actors::Builder;
+    use tedge_actors::ClientMessageBox;
+    use tedge_actors::MessageReceiver;
+    use tedge_actors::ServerMessageBox;
+    use tedge_actors::ServerMessageBoxBuilder;
+    use tedge_api::mqtt_topics::EntityTopicId;
+    use tedge_api::path::DataDir;
+    use tedge_test_utils::fs::TempTedgeDir;
+    use tedge_utils::paths::TedgePaths;
+    use time::macros::datetime;
+    use tower::Service;
+
+    #[tokio::test]
+    async fn list_commands_with_filters() {
+        let TestHandle {
+            mut app,
+            mut command_history_box,
+        } = setup();
+
+        // Mock command history actor response
+        tokio::spawn(async move {
+            if let Some(mut req) = command_history_box.recv().await {
+                if let CommandHistoryRequest::List(filters) = req.request {
+                    assert_eq!(
+                        filters,
+                        CommandFilters {
+                            entity: Some(EntityTopicId::default_child_device("child01").unwrap()),
+                            operation: Some("restart".to_string()),
+                            status: Some("failed".to_string()),
+                            from: Some(datetime!(2025-01-01 10:00 UTC)),
+                            to: None,
+                        }
+                    );
+                    let record = CommandRecord {
+                        topic: "te/device/child01///cmd/restart/c8y-mapper-123".to_string(),
+                        entity: EntityTopicId::default_child_device("child01").unwrap(),
+                        operation: "restart".to_string(),
+                        cmd_id: "c8y-mapper-123".to_string(),
+                        status: "failed".to_string(),
+                        reason: Some("timeout".to_string()),
+                        started_at: Some(datetime!(2025-01-01 10:15 UTC)),
+                        finished_at: datetime!(2025-01-01 10:20 UTC),
+                        log_path: None,
+                    };
+                    req.reply_to
+                        .send(CommandHistoryResponse::List(vec![record]))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("/v1/commands?entity=device/child01//&operation=restart&status=failed&from=2025-01-01T10:00:00Z")
+            .body(Body::empty())
+            .expect("request builder");
+
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let commands: Value = serde_json::from_slice(&body).unwrap();
+        assert_json_eq!(
+            commands,
+            json!([{
+                "topic": "te/device/child01///cmd/restart/c8y-mapper-123",
+                "entity": "device/child01//",
+                "operation": "restart",
+                "cmdId": "c8y-mapper-123",
+                "status": "failed",
+                "reason": "timeout",
+                "startedAt": "2025-01-01T10:15:00Z",
+                "finishedAt": "2025-01-01T10:20:00Z",
+            }])
+        );
+    }
+
+    #[tokio::test]
+    async fn list_commands_with_invalid_time_range() {
+        let TestHandle { mut app, .. } = setup();
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("/v1/commands?to=yesterday")
+            .body(Body::empty())
+            .expect("request builder");
+
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let error: Value = serde_json::from_slice(&body).unwrap();
+        assert_json_eq!(
+            error,
+            json!({"error": "Invalid `to` parameter: yesterday is not an RFC 3339 timestamp"})
+        );
+    }
+
+    struct TestHandle {
+        app: Router,
+        command_history_box: ServerMessageBox<CommandHistoryRequest, CommandHistoryResponse>,
+    }
+
+    fn setup() -> TestHandle {
+        let ttd: TempTedgeDir = TempTedgeDir::new();
+        let data_dir: DataDir =
+            TedgePaths::from_root_with_defaults(ttd.utf8_path_buf(), "", "").into();
+        let file_transfer_dir = data_dir.file_transfer_dir();
+
+        let mut entity_store_box = ServerMessageBoxBuilder::new("EntityStoreBox", 16);
+        let entity_store_handle = ClientMessageBox::new(&mut entity_store_box);
+        let mut command_history_box = ServerMessageBoxBuilder::new("CommandHistoryBox", 16);
+        let command_history_handle = ClientMessageBox::new(&mut command_history_box);
+
+        let agent_state = AgentState {
+            file_transfer_dir,
+            data_dir,
+            entity_store_handle,
+            command_history_handle,
+            access_control: None,
+        };
+        let app: Router = command_history_router(agent_state);
+
+        TestHandle {
+            app,
+            command_history_box: command_history_box.build(),
+        }
+    }
+}
@@ -4,34 +4,44 @@
 //! - `POST /v1/entities`: Registers a new entity.
 //! - `GET /v1/entities/*path`: Retrieves an existing entity.
 //! - `DELETE /v1/entities/*path`: Deregisters an existing entity.
+//! - `GET /v1/entity-events`: Streams entity registrations, deregistrations and twin updates as server-sent events.
 //!
 //! References:
 //!
 //! - https://github.com/thin-edge/thin-edge.io/blob/main/design/decisions/0005-entity-registration-api.md
 use super::server::AgentState;
+use crate::entity_manager::server::EntityChange;
 use crate::entity_manager::server::EntityStoreRequest;
 use crate::entity_manager::server::EntityStoreResponse;
 use axum::extract::DefaultBodyLimit;
 use axum::extract::Path;
 use axum::extract::Query;
 use axum::extract::State;
+use axum::response::sse::Event;
+use axum::response::sse::KeepAlive;
+use axum::response::sse::Sse;
 use axum::response::IntoResponse;
 use axum::response::Response;
 use axum::routing::get;
 use axum::routing::post;
 use axum::Json;
 use axum::Router;
+use futures::channel::mpsc;
+use futures::Stream;
+use futures::StreamExt;
+use hyper::header::HeaderName;
+use hyper::header::HeaderValue;
 use hyper::StatusCode;
 use serde::Deserialize;
 use serde_json::json;
 use serde_json::Map;
 use serde_json::Value;
+use std::collections::HashMap;
 use std::str::FromStr;
-use tedge_api::entity::EntityMetadata;
 use tedge_api::entity::InvalidEntityType;
+use tedge_api::entity_manifest::EntityDeclaration;
 use tedge_api::entity_store;
 use tedge_api::entity_store::EntityRegistrationMessage;
-use tedge_api::entity_store::EntityRegistrationPayload;
 use tedge_api::entity_store::EntityTwinMessage;
 use tedge_api::entity_store::EntityUpdateMessage;
 use tedge_api::entity_store::ListFilters;
@@ -41,6 +51,12 @@ use tedge_api::mqtt_topics::TopicIdError;
 
 pub const HTTP_MAX_PAYLOAD_SIZE: usize = 1048576; // 1 MB
 
+/// The header returning the cursor to the next page of a paginated list of entities
+pub const NEXT_CURSOR_HEADER: HeaderName = HeaderName::from_static("x-next-cursor");
+
+/// The prefix of the query parameters used to filter entities on their twin data, as in `twin.hardware.model=rpi4`
+const TWIN_PARAM_PREFIX: &str = "twin.";
+
 #[derive(Debug, Default, Deserialize)]
 pub struct ListParams {
     #[serde(default)]
@@ -49,6 +65,21 @@ pub struct ListParams {
     parent: Option<String>,
     #[serde(default)]
     r#type: Option<String>,
+    #[serde(default)]
+    external_id: Option<String>,
+    #[serde(default)]
+    after: Option<String>,
+    #[serde(default)]
+    limit: Option<String>,
+    #[serde(flatten)]
+    others: HashMap<String, String>,
+}
+
+/// The pagination requested along a list of entities
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Pagination {
+    after: Option<EntityTopicId>,
+    limit: Option<usize>,
 }
 
 #[derive(Debug, thiserror::Error, PartialEq, Eq, Clone)]
@@ -59,6 +90,32 @@ pub enum InputValidationError {
     InvalidEntityTopic(#[from] TopicIdError),
     #[error("The provided parameters: {0} and {1} are mutually exclusive. Use either one.")]
     IncompatibleParams(String, String),
+    #[error("The limit must be a positive integer, not: {0}")]
+    InvalidLimit(String),
+}
+
+impl TryFrom<&ListParams> for Pagination {
+    type Error = InputValidationError;
+
+    fn try_from(params: &ListParams) -> Result<Self, Self::Error> {
+        let after = params
+            .after
+            .as_ref()
+            .filter(|v| !v.is_empty())
+            .map(|val| val.parse())
+            .transpose()?;
+        let limit = params
+            .limit
+            .as_ref()
+            .filter(|v| !v.is_empty())
+            .map(|val| match val.parse::<usize>() {
+                Ok(limit) if limit > 0 => Ok(limit),
+                _ => Err(InputValidationError::InvalidLimit(val.clone())),
+            })
+            .transpose()?;
+
+        Ok(Self { after, limit })
+    }
 }
 
 impl TryFrom<ListParams> for ListFilters {
@@ -88,11 +145,28 @@ impl TryFrom<ListParams> for ListFilters {
             ));
         }
 
-        Ok(Self {
+        let mut filters = ListFilters {
             root,
             parent,
             r#type,
-        })
+            ..Default::default()
+        };
+        if let Some(pattern) = params.external_id.filter(|v| !v.is_empty()) {
+            filters = filters.external_id(pattern);
+        }
+        let mut twin_params: Vec<_> = params
+            .others
+            .into_iter()
+            .filter_map(|(key, value)| {
+                Some((key.strip_prefix(TWIN_PARAM_PREFIX)?.to_string(), value))
+            })
+            .collect();
+        twin_params.sort();
+        for (path, value) in twin_params {
+            filters = filters.twin(path, value);
+        }
+
+        Ok(filters)
     }
 }
 
@@ -165,6 +239,7 @@ impl IntoResponse for Error {
 pub(crate) fn entity_store_router(state: AgentState) -> Router {
     Router::new()
         .route("/v1/entities", post(register_entity).get(list_entities))
+        .route("/v1/entity-events", get(stream_entity_events))
         .route(
             "/v1/entities/{*path}",
             get(get_resource)
@@ -176,30 +251,9 @@ pub(crate) fn entity_store_router(state: AgentState) -> Router {
         .with_state(state)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
-struct EntityRegistrationHttpPayload {
-    #[serde(rename = "@topic-id")]
-    pub topic_id: EntityTopicId,
-    #[serde(flatten)]
-    others: EntityRegistrationPayload,
-}
-
-impl From<EntityRegistrationHttpPayload> for EntityRegistrationMessage {
-    fn from(value: EntityRegistrationHttpPayload) -> Self {
-        EntityRegistrationMessage {
-            topic_id: value.topic_id,
-            external_id: value.others.external_id,
-            r#type: value.others.r#type,
-            parent: value.others.parent,
-            health_endpoint: value.others.health_endpoint,
-            twin_data: value.others.twin_data,
-        }
-    }
-}
-
 async fn register_entity(
     State(state): State<AgentState>,
-    Json(payload): Json<EntityRegistrationHttpPayload>,
+    Json(payload): Json<EntityDeclaration>,
 ) -> impl IntoResponse {
     let reg_message: EntityRegistrationMessage = payload.into();
     let topic_id = reg_message.topic_id.clone();
@@ -347,6 +401,11 @@ fn parse_path(path: &str) -> Result<(EntityTopicId, Channel), Error> {
     }
 }
 
+/// The topic id of the entity targeted by a request path, if valid
+pub(super) fn requested_topic_id(path: &str) -> Option<EntityTopicId> {
+    parse_path(path).ok().map(|(topic_id, _)| topic_id)
+}
+
 fn topic_id_from_path_segments(
     seg1: &str,
     seg2: Option<&str>,
@@ -421,19 +480,74 @@ async fn deregister_entity(state: AgentState, topic_id: EntityTopicId) -> Result
 async fn list_entities(
     State(state): State<AgentState>,
     Query(params): Query<ListParams>,
-) -> Result<Json<Vec<EntityMetadata>>, Error> {
+) -> Result<Response, Error> {
+    let pagination = Pagination::try_from(&params)?;
     let filters = params.try_into()?;
+    let Some(limit) = pagination.limit else {
+        let response = state
+            .entity_store_handle
+            .clone()
+            .await_response(EntityStoreRequest::List(filters))
+            .await?;
+
+        let EntityStoreResponse::List(entities) = response else {
+            return Err(Error::InvalidEntityStoreResponse);
+        };
+
+        return Ok(Json(entities).into_response());
+    };
+
+    let response = state
+        .entity_store_handle
+        .clone()
+        .await_response(EntityStoreRequest::ListPage(
+            filters,
+            pagination.after,
+            limit,
+        ))
+        .await?;
+
+    let EntityStoreResponse::ListPage(entities, next) = response else {
+        return Err(Error::InvalidEntityStoreResponse);
+    };
+
+    let mut response = Json(entities).into_response();
+    if let Some(cursor) = next.and_then(|next| HeaderValue::from_str(next.as_str()).ok()) {
+        response.headers_mut().insert(NEXT_CURSOR_HEADER, cursor);
+    }
+    Ok(response)
+}
+
+async fn stream_entity_events(
+    State(state): State<AgentState>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, Error> {
+    let (sender, receiver) = mpsc::unbounded();
     let response = state
         .entity_store_handle
         .clone()
-        .await_response(EntityStoreRequest::List(filters))
+        .await_response(EntityStoreRequest::Subscribe(sender))
         .await?;
 
-    let EntityStoreResponse::List(entities) = response else {
+    let EntityStoreResponse::Ok = response else {
         return Err(Error::InvalidEntityStoreResponse);
     };
 
-    Ok(Json(entities))
+    let events = receiver.map(entity_event);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+fn entity_event(change: EntityChange) -> Result<Event, axum::Error> {
+    match change {
+        EntityChange::Registered(entity) => Event::default().event("registered").json_data(entity),
+        EntityChange::Deregistered(entity) => {
+            Event::default().event("deregistered").json_data(entity)
+        }
+        EntityChange::TwinUpdated(twin) => Event::default().event("twin").json_data(json!({
+            "@topic-id": twin.topic_id,
+            "fragment": twin.fragment_key,
+            "value": twin.fragment_value,
+        })),
+    }
 }
 
 async fn get_entity_twin_fragment(
@@ -559,17 +673,23 @@ async fn delete_entity_twin_fragments(
 #[cfg(test)]
 mod tests {
     use super::AgentState;
+    use super::ListParams;
+    use super::Pagination;
+    use super::NEXT_CURSOR_HEADER;
+    use crate::entity_manager::server::EntityChange;
     use crate::entity_manager::server::EntityStoreRequest;
     use crate::entity_manager::server::EntityStoreResponse;
     use crate::http_server::entity_store::entity_store_router;
     use assert_json_diff::assert_json_eq;
     use axum::body::Body;
+    use axum::extract::Query;
     use axum::response::Response;
     use axum::Router;
     use http_body_util::BodyExt as _;
     use hyper::Method;
     use hyper::Request;
     use hyper::StatusCode;
+    use hyper::Uri;
     use serde_json::json;
     use serde_json::Value;
     use std::collections::HashSet;
@@ -581,6 +701,9 @@ mod tests {
     use tedge_api::entity::EntityMetadata;
     use tedge_api::entity::EntityType;
     use tedge_api::entity_store;
+    use tedge_api::entity_store::EntityTwinMessage;
+    use tedge_api::entity_store::ListFilters;
+    use tedge_api::entity_store::TwinFilter;
     use tedge_api::mqtt_topics::EntityTopicId;
     use tedge_api::path::DataDir;
     use tedge_test_utils::fs::TempTedgeDir;
@@ -1128,6 +1251,195 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn entity_list_page() {
+        let TestHandle {
+            mut app,
+            mut entity_store_box,
+        } = setup();
+
+        // Mock entity store actor response
+        tokio::spawn(async move {
+            if let Some(mut req) = entity_store_box.recv().await {
+                if let EntityStoreRequest::ListPage(_, after, limit) = req.request {
+                    assert_eq!(after, Some("device/child01//".parse().unwrap()));
+                    assert_eq!(limit, 2);
+                    req.reply_to
+                        .send(EntityStoreResponse::ListPage(
+                            vec![
+                                EntityMetadata::child_device("child02".to_string()).unwrap(),
+                                EntityMetadata::child_device("child03".to_string()).unwrap(),
+                            ],
+                            Some("device/child03//".parse().unwrap()),
+                        ))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("/v1/entities?type=child-device&after=device/child01//&limit=2")
+            .body(Body::empty())
+            .expect("request builder");
+
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(NEXT_CURSOR_HEADER).unwrap(),
+            "device/child03//"
+        );
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let entities: Vec<EntityMetadata> = serde_json::from_slice(&body).unwrap();
+        let topic_ids = entities
+            .iter()
+            .map(|e| e.topic_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(topic_ids, vec!["device/child02//", "device/child03//"]);
+    }
+
+    #[tokio::test]
+    async fn entity_list_last_page() {
+        let TestHandle {
+            mut app,
+            mut entity_store_box,
+        } = setup();
+
+        // Mock entity store actor response
+        tokio::spawn(async move {
+            if let Some(mut req) = entity_store_box.recv().await {
+                if let EntityStoreRequest::ListPage(_, None, 10) = req.request {
+                    req.reply_to
+                        .send(EntityStoreResponse::ListPage(
+                            vec![EntityMetadata::main_device(None)],
+                            None,
+                        ))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("/v1/entities?limit=10")
+            .body(Body::empty())
+            .expect("request builder");
+
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(NEXT_CURSOR_HEADER).is_none());
+    }
+
+    #[test_case("limit=0")]
+    #[test_case("limit=-1")]
+    #[test_case("limit=ten")]
+    #[tokio::test]
+    async fn entity_list_bad_limit(param: &str) {
+        let TestHandle {
+            mut app,
+            entity_store_box: _, // Not used
+        } = setup();
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(format!("/v1/entities?{param}"))
+            .body(Body::empty())
+            .expect("request builder");
+
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn entity_list_external_id_and_twin_params() {
+        let uri: Uri = "/v1/entities?type=child-device&external_id=gateway:*&twin.hardware.model=rpi4&twin.maintenanceMode=true&other=ignored".parse().unwrap();
+        let Query(params) = Query::<ListParams>::try_from_uri(&uri).unwrap();
+        assert_eq!(
+            Pagination::try_from(&params).unwrap(),
+            Pagination::default()
+        );
+
+        let filters = ListFilters::try_from(params).unwrap();
+        assert_eq!(filters.external_id, Some("gateway:*".to_string()));
+        assert_eq!(
+            filters.twin,
+            vec![
+                TwinFilter {
+                    path: "hardware.model".to_string(),
+                    value: "rpi4".to_string()
+                },
+                TwinFilter {
+                    path: "maintenanceMode".to_string(),
+                    value: "true".to_string()
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn entity_events_stream() {
+        let TestHandle {
+            mut app,
+            mut entity_store_box,
+        } = setup();
+
+        // Mock entity store actor response
+        tokio::spawn(async move {
+            if let Some(mut req) = entity_store_box.recv().await {
+                if let EntityStoreRequest::Subscribe(subscriber) = req.request {
+                    req.reply_to.send(EntityStoreResponse::Ok).await.unwrap();
+                    let child = EntityMetadata::child_device("child0".to_string()).unwrap();
+                    subscriber
+                        .unbounded_send(EntityChange::Registered(child.clone()))
+                        .unwrap();
+                    subscriber
+                        .unbounded_send(EntityChange::TwinUpdated(EntityTwinMessage::new(
+                            child.topic_id.clone(),
+                            "maintenanceMode".to_string(),
+                            json!(true),
+                        )))
+                        .unwrap();
+                    subscriber
+                        .unbounded_send(EntityChange::Deregistered(child))
+                        .unwrap();
+                }
+            }
+        });
+
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri("/v1/entity-events")
+            .body(Body::empty())
+            .expect("request builder");
+
+        let response = app.call(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+
+        // The stream ends when the subscriber is dropped by the mock entity store
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let events = String::from_utf8(body.to_vec()).unwrap();
+        let events = events
+            .split("\n\n")
+            .filter(|event| !event.is_empty())
+            .map(|event| event.lines().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0][0], "event: registered");
+        assert_eq!(events[1][0], "event: twin");
+        assert_eq!(
+            events[1][1],
+            r#"data: {"@topic-id":"device/child0//","fragment":"maintenanceMode","value":true}"#
+        );
+        assert_eq!(events[2][0], "event: deregistered");
+    }
+
     #[tokio::test]
     async fn entity_twin_get() {
         let TestHandle {
@@ -1707,11 +2019,15 @@ mod tests {
 
         let mut entity_store_box = ServerMessageBoxBuilder::new("EntityStoreBox", 16);
         let entity_store_handle = ClientMessageBox::new(&mut entity_store_box);
+        let mut command_history_box = ServerMessageBoxBuilder::new("CommandHistoryBox", 16);
+        let command_history_handle = ClientMessageBox::new(&mut command_history_box);
 
         let agent_state = AgentState {
             file_transfer_dir,
             data_dir,
             entity_store_handle,
+            command_history_handle,
+            access_control: None,
         };
         // TODO: Add a timeout to this router. Attempts to add a tower_http::timer::TimeoutLayer as a layer failed.
         let app: Router = entity_store_router(agent_state);
@@ -1,4 +1,6 @@
+pub mod access;
 pub mod actor;
+mod command_history;
 mod entity_store;
 pub mod error;
 mod file_transfer;
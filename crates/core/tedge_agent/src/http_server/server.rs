@@ -1,9 +1,15 @@
+use super::command_history::command_history_router;
 use super::entity_store::entity_store_router;
 use super::file_transfer::file_transfer_legacy_router;
 use super::file_transfer::file_transfer_router;
+use crate::command_history::server::CommandHistoryRequest;
+use crate::command_history::server::CommandHistoryResponse;
 use crate::entity_manager::server::EntityStoreRequest;
 use crate::entity_manager::server::EntityStoreResponse;
+use crate::http_server::access::authorize;
+use crate::http_server::access::HttpAccessControl;
 use crate::http_server::error::HttpServerError;
+use axum::middleware;
 use axum::Router;
 use futures::future::FutureExt;
 use rustls::ServerConfig;
@@ -19,6 +25,9 @@ pub(crate) struct AgentState {
     pub(crate) file_transfer_dir: ManagedDir,
     pub(crate) data_dir: DataDir,
     pub(crate) entity_store_handle: ClientMessageBox<EntityStoreRequest, EntityStoreResponse>,
+    pub(crate) command_history_handle:
+        ClientMessageBox<CommandHistoryRequest, CommandHistoryResponse>,
+    pub(crate) access_control: Option<HttpAccessControl>,
 }
 
 impl AgentState {
@@ -26,11 +35,15 @@ impl AgentState {
         file_transfer_dir: ManagedDir,
         data_dir: DataDir,
         entity_store_handle: ClientMessageBox<EntityStoreRequest, EntityStoreResponse>,
+        command_history_handle: ClientMessageBox<CommandHistoryRequest, CommandHistoryResponse>,
+        access_control: Option<HttpAccessControl>,
     ) -> Self {
         AgentState {
             file_transfer_dir,
             data_dir,
             entity_store_handle,
+            command_history_handle,
+            access_control,
         }
     }
 }
@@ -55,14 +68,21 @@ pub(crate) fn http_server(
     Ok(server)
 }
 
-fn router(state: AgentState) -> Router {
+pub(crate) fn router(state: AgentState) -> Router {
     let file_transfer_legacy_router =
         file_transfer_legacy_router(state.file_transfer_dir.clone(), state.data_dir.clone());
     let file_transfer_router =
         file_transfer_router(state.file_transfer_dir.clone(), state.data_dir.clone());
-    let entity_store_router = entity_store_router(state);
+    let command_history_router = command_history_router(state.clone());
+    let entity_store_router = entity_store_router(state.clone());
 
     Router::new()
-        .nest("/te", entity_store_router.merge(file_transfer_router))
+        .nest(
+            "/te",
+            entity_store_router
+                .merge(command_history_router)
+                .merge(file_transfer_router),
+        )
         .merge(file_transfer_legacy_router)
+        .layer(middleware::from_fn_with_state(state, authorize))
 }
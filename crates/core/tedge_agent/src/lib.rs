@@ -27,6 +27,7 @@ use tedge_supervisor::UnitKind;
 use tracing::info;
 
 mod agent;
+mod command_history;
 mod device_profile_manager;
 mod entity_manager;
 mod http_server;
@@ -36,6 +37,9 @@ mod software_manager;
 mod state_repository;
 mod twin_manager;
 
+/// The access policies of the agent HTTP API clients, as managed by `tedge http token`
+pub use http_server::access as http_access;
+
 #[derive(Debug, Clone, clap::Parser)]
 #[clap(
 name = clap::crate_name!(),
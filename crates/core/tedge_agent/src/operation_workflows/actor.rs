@@ -1,3 +1,6 @@
+use crate::command_history::server::CommandHistoryRequest;
+use crate::command_history::server::CommandHistoryResponse;
+use crate::command_history::server::CommandRecord;
 use crate::operation_workflows::message_box::CommandDispatcher;
 use crate::operation_workflows::message_box::SyncSignalDispatcher;
 use crate::operation_workflows::persist::WorkflowRepository;
@@ -9,9 +12,11 @@ use serde_json::json;
 use std::collections::HashMap;
 use std::process::Output;
 use std::time::Duration;
+use std::time::Instant;
 use tedge_actors::fan_in_message_type;
 use tedge_actors::Actor;
 use tedge_actors::ClientMessageBox;
+use tedge_actors::CloneSender;
 use tedge_actors::DynSender;
 use tedge_actors::LoggingSender;
 use tedge_actors::MessageReceiver;
@@ -19,6 +24,8 @@ use tedge_actors::RuntimeError;
 use tedge_actors::Sender;
 use tedge_actors::UnboundedLoggingReceiver;
 use tedge_api::mqtt_topics::Channel;
+use tedge_api::mqtt_topics::ChannelFilter;
+use tedge_api::mqtt_topics::EntityFilter;
 use tedge_api::mqtt_topics::EntityTopicError;
 use tedge_api::mqtt_topics::EntityTopicId;
 use tedge_api::mqtt_topics::MqttSchema;
@@ -28,15 +35,18 @@ use tedge_api::workflow::extract_json_output;
 use tedge_api::workflow::log::log_dir::OperationLogs;
 use tedge_api::workflow::CommandBoard;
 use tedge_api::workflow::CommandId;
+use tedge_api::workflow::CommandScheduler;
 use tedge_api::workflow::GenericCommandData;
 use tedge_api::workflow::GenericCommandMetadata;
 use tedge_api::workflow::GenericCommandState;
 use tedge_api::workflow::GenericStateUpdate;
+use tedge_api::workflow::JoinStep;
 use tedge_api::workflow::OperationAction;
 use tedge_api::workflow::OperationName;
 use tedge_api::workflow::OperationStep;
 use tedge_api::workflow::OperationStepRequest;
 use tedge_api::workflow::OperationStepResponse;
+use tedge_api::workflow::TopicName;
 use tedge_api::workflow::WorkflowExecutionError;
 use tedge_api::CommandLog;
 use tedge_downloader_ext::DownloadRequest;
@@ -44,7 +54,12 @@ use tedge_downloader_ext::DownloadResult;
 use tedge_file_system_ext::FsWatchEvent;
 use tedge_mqtt_ext::MqttMessage;
 use tedge_mqtt_ext::QoS;
+use tedge_mqtt_ext::SubscriptionDiff;
+use tedge_mqtt_ext::TopicFilter;
 use tedge_script_ext::Execute;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use time::UtcOffset;
 use tokio::time::sleep;
 use tracing::error;
 use tracing::info;
@@ -53,6 +68,10 @@ use tracing::warn;
 type DownloaderRequest = (String, DownloadRequest);
 type DownloaderResult = (String, DownloadResult);
 
+/// The maximum delay before checking again if a deferred command is due,
+/// so clock adjustments and time zone changes are taken into account
+const MAX_DEFERRAL_DELAY: Duration = Duration::from_secs(600);
+
 /// A generic command state that is published by the [TedgeOperationConverterActor]
 /// to itself for further processing .i.e. after a state update
 #[derive(Debug)]
@@ -76,9 +95,21 @@ pub struct WorkflowActor {
     pub(crate) sync_signal_dispatcher: SyncSignalDispatcher,
     pub(crate) command_sender: DynSender<InternalCommandState>,
     pub(crate) mqtt_publisher: LoggingSender<MqttMessage>,
+    pub(crate) mqtt_subscriptions: LoggingSender<SubscriptionDiff>,
+    /// The topics of the running parallel sub-commands that are not commands of the agent device
+    pub(crate) parallel_task_topics: TopicFilter,
     pub(crate) script_runner: ClientMessageBox<Execute, std::io::Result<Output>>,
     pub(crate) downloader: ClientMessageBox<DownloaderRequest, DownloaderResult>,
     pub(crate) tmp_dir: Utf8PathBuf,
+    pub(crate) scheduler: CommandScheduler,
+    /// The commands which execution is deferred, with the time they are waiting for
+    pub(crate) deferred_commands: HashMap<TopicName, OffsetDateTime>,
+    /// The commands joining parallel sub-commands, with the deadline set by the join timeout
+    pub(crate) joining_commands: HashMap<TopicName, Instant>,
+    pub(crate) command_history:
+        Option<ClientMessageBox<CommandHistoryRequest, CommandHistoryResponse>>,
+    /// The commands under execution, with the time they have been received
+    pub(crate) started_commands: HashMap<TopicName, OffsetDateTime>,
 }
 
 #[async_trait]
@@ -160,12 +191,18 @@ impl WorkflowActor {
     /// but also from *this* actor as all its state transitions are published over MQTT.
     /// Only the former will be actually processed with [Self::process_command_update].
     async fn process_mqtt_message(&mut self, message: MqttMessage) -> Result<(), RuntimeError> {
-        let Ok((_, channel)) = self.mqtt_schema.entity_channel_of(&message.topic) else {
+        let Ok((entity, channel)) = self.mqtt_schema.entity_channel_of(&message.topic) else {
             error!("Unknown topic: {}", message.topic.name);
             return Ok(());
         };
         match channel {
             Channel::Command { operation, cmd_id } => {
+                if GenericCommandState::is_parallel_task_id(&cmd_id) {
+                    self.process_parallel_task_update(&message).await?;
+                }
+                if entity != self.device_topic_id {
+                    return Ok(());
+                }
                 self.process_command_message(message, operation, cmd_id)
                     .await
             }
@@ -220,6 +257,54 @@ impl WorkflowActor {
         Ok(())
     }
 
+    /// Record the new state of a sub-command triggered by a parallel action into the invoking command
+    ///
+    /// The invoking command is resumed, if awaiting the completion of its sub-commands.
+    async fn process_parallel_task_update(
+        &mut self,
+        message: &MqttMessage,
+    ) -> Result<(), RuntimeError> {
+        let Ok(task_state) = GenericCommandState::from_command_message(message) else {
+            return Ok(());
+        };
+        let Some(new_state) = self
+            .workflow_repository
+            .parallel_command_state(&task_state.topic.name)
+            .and_then(|state| state.update_parallel_task(&task_state))
+        else {
+            return Ok(());
+        };
+        let Ok((operation, cmd_id)) = self.extract_command_identifiers(&new_state.topic.name)
+        else {
+            return Ok(());
+        };
+        let mut log_file = self.open_command_log(&new_state, &operation, &cmd_id).await;
+        log_file
+            .log_info(&format!(
+                "=> {} sub-operation {}: {}",
+                task_state.operation().unwrap_or_default(),
+                task_state.topic.name,
+                task_state.status
+            ))
+            .await;
+
+        if let Ok(OperationAction::Join(_)) = self.workflow_repository.get_action(&new_state) {
+            self.publish_command_state(new_state, &mut log_file).await
+        } else {
+            // The sub-commands will be joined later: simply record the progress
+            if let Err(err) = self
+                .workflow_repository
+                .apply_internal_update(new_state.clone())
+            {
+                error!("Fail to persist workflow operation state: {err}");
+            }
+            self.persist_command_board().await?;
+            self.update_parallel_task_subscriptions().await?;
+            self.mqtt_publisher.send(new_state.into_message()).await?;
+            Ok(())
+        }
+    }
+
     async fn process_command_message(
         &mut self,
         message: MqttMessage,
@@ -238,6 +323,10 @@ impl WorkflowActor {
 
         let mut log_file = self.open_command_log(&state, &operation, &cmd_id).await;
 
+        if state.is_failed() && self.deferred_commands.contains_key(&state.topic.name) {
+            return self.cancel_deferred_command(state, &mut log_file).await;
+        }
+
         match self
             .workflow_repository
             .apply_external_update(&operation, state)
@@ -247,6 +336,8 @@ impl WorkflowActor {
             Ok(Some(new_state)) => {
                 self.persist_command_board().await?;
                 if new_state.is_init() {
+                    self.started_commands
+                        .insert(new_state.topic.name.clone(), OffsetDateTime::now_utc());
                     self.process_command_update(new_state.with_log_path(&log_file.path))
                         .await?;
                 }
@@ -288,6 +379,10 @@ impl WorkflowActor {
         }
         let mut log_file = self.open_command_log(&state, &operation, &cmd_id).await;
 
+        if state.is_scheduled() && self.defer_command(&state, &mut log_file).await? {
+            return Ok(());
+        }
+
         let action = match self.workflow_repository.get_action(&state) {
             Ok(action) => action,
             Err(WorkflowExecutionError::UnknownStep { operation, step }) => {
@@ -308,6 +403,7 @@ impl WorkflowActor {
 
         match action {
             OperationAction::Clear => {
+                self.record_completed_command(&state, &log_file).await?;
                 if let Some(invoking_command) =
                     self.workflow_repository.invoking_command_state(&state)
                 {
@@ -615,9 +711,200 @@ impl WorkflowActor {
                 }
                 Ok(())
             }
+            OperationAction::Parallel(sub_operation, items, max_concurrency, input, handlers) => {
+                let next_state = &handlers.on_exec.status;
+                info!(
+                    "Triggering parallel {sub_operation} commands, and moving {operation} operation to {next_state} state"
+                );
+
+                let sub_cmd_input = input.extract_value_from(&state);
+                match state.clone().fan_out(
+                    &self.mqtt_schema,
+                    &self.device_topic_id,
+                    &operation,
+                    &cmd_id,
+                    &sub_operation,
+                    &items,
+                    sub_cmd_input,
+                    max_concurrency,
+                ) {
+                    Ok(new_state) => {
+                        let (new_state, started) = new_state.start_parallel_tasks();
+                        let new_state = new_state.update(handlers.on_exec);
+                        self.publish_command_state(new_state, &mut log_file).await?;
+                        for task in started {
+                            self.mqtt_publisher.send(task.into_message()).await?;
+                        }
+                    }
+                    Err(err) => {
+                        error!("Parallel {sub_operation} commands cannot be triggered: {err}");
+                        let new_state = state.update(GenericStateUpdate::failed(err.to_string()));
+                        self.publish_command_state(new_state, &mut log_file).await?;
+                    }
+                }
+                Ok(())
+            }
+            OperationAction::Join(handlers) => {
+                let topic = state.topic.name.clone();
+                // A join timer might fire after new sub-command updates or once the command moved on:
+                // proceed only from the latest recorded state
+                let Some(state) = self
+                    .workflow_repository
+                    .get_state(&topic)
+                    .filter(|current_state| current_state.status == state.status)
+                    .cloned()
+                else {
+                    self.joining_commands.remove(&topic);
+                    return Ok(());
+                };
+                let step = state.status.clone();
+                if let Some(timeout) = handlers.timeout {
+                    if self.join_timed_out(&state, timeout) {
+                        self.joining_commands.remove(&topic);
+                        warn!("{operation} operation {step}: timeout awaiting sub-operations");
+                        log_file
+                            .log_info("=> timeout awaiting parallel sub-operations")
+                            .await;
+                        let new_state = state.update(handlers.on_timeout);
+                        self.publish_command_state(new_state, &mut log_file).await?;
+                        return Ok(());
+                    }
+                }
+                match state.join_parallel_tasks(&handlers) {
+                    JoinStep::Wait => {
+                        info!(
+                            "{operation} operation {step}: waiting for sub-operations completion"
+                        );
+                        log_file
+                            .log_info("=> parallel sub-operations are still running")
+                            .await;
+                    }
+                    JoinStep::Start(new_state, started) => {
+                        log_file
+                            .log_info(&format!(
+                                "=> starting {} more parallel sub-operations",
+                                started.len()
+                            ))
+                            .await;
+                        self.publish_command_state(new_state, &mut log_file).await?;
+                        for task in started {
+                            self.mqtt_publisher.send(task.into_message()).await?;
+                        }
+                    }
+                    JoinStep::Done(new_state, finished) => {
+                        self.joining_commands.remove(&topic);
+                        log_file
+                            .log_info("=> all parallel sub-operations are finished")
+                            .await;
+                        self.publish_command_state(new_state, &mut log_file).await?;
+                        for task in finished {
+                            self.mqtt_publisher.send(task.into_message()).await?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Check if a command has been awaiting its parallel sub-commands longer than the join timeout
+    ///
+    /// On the first check, a timer is set to resume the command when the timeout expires.
+    fn join_timed_out(&mut self, state: &GenericCommandState, timeout: Duration) -> bool {
+        let now = Instant::now();
+        if let Some(deadline) = self.joining_commands.get(&state.topic.name) {
+            return *deadline <= now;
+        }
+        self.joining_commands
+            .insert(state.topic.name.clone(), now + timeout);
+        let mut command_sender: DynSender<InternalCommandState> =
+            self.command_sender.sender_clone();
+        let command = InternalCommandState(state.clone());
+        tokio::spawn(async move {
+            sleep(timeout).await;
+            let _ = command_sender.send(command).await;
+        });
+        false
+    }
+
+    /// Defer the execution of a scheduled command till its scheduled time or the maintenance window
+    ///
+    /// Return `true` if the command has to wait, a timer being set to resume the command when due.
+    async fn defer_command(
+        &mut self,
+        state: &GenericCommandState,
+        log_file: &mut CommandLog,
+    ) -> Result<bool, RuntimeError> {
+        let topic = &state.topic.name;
+        if self
+            .workflow_repository
+            .get_state(topic)
+            .is_none_or(|current_state| current_state.status != state.status)
+        {
+            // The command has been cancelled or cleared while waiting
+            self.deferred_commands.remove(topic);
+            return Ok(true);
+        }
+
+        let now = local_now().await;
+        match self.scheduler.deferred_until(state, now) {
+            Ok(None) => {
+                if self.deferred_commands.remove(topic).is_some() {
+                    info!("Resuming deferred command {topic}");
+                    log_file.log_info("Resuming deferred command").await;
+                }
+                Ok(false)
+            }
+            Ok(Some(until)) => {
+                if self.deferred_commands.insert(topic.clone(), until) != Some(until) {
+                    let until = until.format(&Rfc3339).unwrap_or_else(|_| until.to_string());
+                    info!("Deferring command {topic} till {until}");
+                    log_file
+                        .log_info(&format!("Deferring command till {until}"))
+                        .await;
+                }
+                let delay = Duration::try_from(until - now)
+                    .unwrap_or_default()
+                    .min(MAX_DEFERRAL_DELAY);
+                let mut command_sender: DynSender<InternalCommandState> =
+                    self.command_sender.sender_clone();
+                let command = InternalCommandState(state.clone());
+                tokio::spawn(async move {
+                    sleep(delay).await;
+                    let _ = command_sender.send(command).await;
+                });
+                Ok(true)
+            }
+            Err(err) => {
+                error!("Command {topic} cannot be scheduled: {err}");
+                let new_state = state.clone().fail_with(err.to_string());
+                self.publish_command_state(new_state, log_file).await?;
+                Ok(true)
+            }
         }
     }
 
+    /// Cancel a deferred command on reception of a failed state for that command
+    async fn cancel_deferred_command(
+        &mut self,
+        state: GenericCommandState,
+        log_file: &mut CommandLog,
+    ) -> Result<(), RuntimeError> {
+        let topic = &state.topic.name;
+        self.deferred_commands.remove(topic);
+        let Some(current_state) = self.workflow_repository.get_state(topic).cloned() else {
+            return Ok(());
+        };
+
+        let reason = state.failure_reason().unwrap_or("Cancelled").to_string();
+        info!("Cancelling deferred command {topic}: {reason}");
+        log_file
+            .log_info(&format!("Cancelling deferred command: {reason}"))
+            .await;
+        let new_state = current_state.fail_with(reason);
+        self.publish_command_state(new_state, log_file).await
+    }
+
     /// Pre-process an update received from a builtin operation actor
     ///
     /// The actual work will be done by [Self::process_command_update].
@@ -659,6 +946,39 @@ impl WorkflowActor {
         self.process_command_update(adapted_state).await
     }
 
+    /// Add a command that reached a terminal state to the command history
+    async fn record_completed_command(
+        &mut self,
+        state: &GenericCommandState,
+        log_file: &CommandLog,
+    ) -> Result<(), RuntimeError> {
+        let started_at = self.started_commands.remove(&state.topic.name);
+        let Some(command_history) = self.command_history.as_mut() else {
+            return Ok(());
+        };
+        let Ok((entity, Channel::Command { operation, cmd_id })) =
+            self.mqtt_schema.entity_channel_of(&state.topic)
+        else {
+            return Ok(());
+        };
+
+        let record = CommandRecord {
+            topic: state.topic.name.clone(),
+            entity,
+            operation: operation.to_string(),
+            cmd_id,
+            status: state.status.clone(),
+            reason: state.failure_reason().map(str::to_string),
+            started_at,
+            finished_at: OffsetDateTime::now_utc(),
+            log_path: Some(log_file.path.clone()),
+        };
+        command_history
+            .await_response(CommandHistoryRequest::Record(record))
+            .await?;
+        Ok(())
+    }
+
     async fn sync_listener_actors(
         &mut self,
         command: &GenericCommandState,
@@ -710,6 +1030,7 @@ impl WorkflowActor {
             error!("Fail to persist workflow operation state: {err}");
         }
         self.persist_command_board().await?;
+        self.update_parallel_task_subscriptions().await?;
         if !new_state.is_cleared() {
             log_file.log_next_step(&new_state.status).await;
             self.command_sender
@@ -720,15 +1041,42 @@ impl WorkflowActor {
         Ok(())
     }
 
+    /// Subscribe to the parallel sub-commands running on other entities than the agent device
+    ///
+    /// The agent only listens to the commands of its own device,
+    /// plus the topics of the sub-commands it started on other entities, till these are finished.
+    async fn update_parallel_task_subscriptions(&mut self) -> Result<(), RuntimeError> {
+        let device_commands = self.mqtt_schema.topics(
+            EntityFilter::Entity(&self.device_topic_id),
+            ChannelFilter::AnyCommand,
+        );
+        let mut topics = TopicFilter::empty();
+        for (_, command) in self.workflow_repository.pending_commands().iter() {
+            for task in command.running_parallel_tasks() {
+                if !device_commands.accept_topic_name(&task) {
+                    topics.add_unchecked(&task);
+                }
+            }
+        }
+
+        let diff = SubscriptionDiff::new(&topics, &self.parallel_task_topics);
+        if !diff.subscribe.is_empty() || !diff.unsubscribe.is_empty() {
+            self.parallel_task_topics = topics;
+            self.mqtt_subscriptions.send(diff).await?;
+        }
+        Ok(())
+    }
+
     /// Reload from disk the current state of the pending command requests
     async fn load_command_board(&mut self) -> Result<(), RuntimeError> {
         match self.state_repository.load().await {
             Ok(Some(pending_commands)) => {
-                for command in self
+                let pending_commands = self
                     .workflow_repository
                     .load_pending_commands(pending_commands)
-                    .await
-                {
+                    .await;
+                self.update_parallel_task_subscriptions().await?;
+                for command in pending_commands {
                     // Make sure the latest state is visible over MQTT
                     self.mqtt_publisher
                         .send(command.clone().into_message())
@@ -781,6 +1129,39 @@ impl WorkflowActor {
     }
 }
 
+/// Return the current time using the local time zone of the device
+///
+/// The offset is given by the `date` command,
+/// as the local offset cannot be soundly read from a multi-threaded process.
+async fn local_now() -> OffsetDateTime {
+    let now = OffsetDateTime::now_utc();
+    match local_utc_offset().await {
+        Some(offset) => now.to_offset(offset),
+        None => {
+            warn!("Fail to get the local time zone: using UTC");
+            now
+        }
+    }
+}
+
+async fn local_utc_offset() -> Option<UtcOffset> {
+    let output = tokio::process::Command::new("date")
+        .arg("+%z")
+        .output()
+        .await
+        .ok()?;
+    let offset = String::from_utf8(output.stdout).ok()?;
+    let offset = offset.trim();
+    let sign = match offset.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let hours: i8 = offset.get(1..3)?.parse().ok()?;
+    let minutes: i8 = offset.get(3..5)?.parse().ok()?;
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
 #[derive(Debug, thiserror::Error)]
 enum CommandTopicError {
     #[error(transparent)]
@@ -1,3 +1,5 @@
+use crate::command_history::server::CommandHistoryRequest;
+use crate::command_history::server::CommandHistoryResponse;
 use crate::operation_workflows::actor::AgentInput;
 use crate::operation_workflows::actor::InternalCommandState;
 use crate::operation_workflows::actor::WorkflowActor;
@@ -18,6 +20,7 @@ use tedge_actors::CloneSender;
 use tedge_actors::DynSender;
 use tedge_actors::LinkError;
 use tedge_actors::LoggingSender;
+use tedge_actors::MappingSender;
 use tedge_actors::MessageSink;
 use tedge_actors::MessageSource;
 use tedge_actors::NoConfig;
@@ -42,7 +45,10 @@ use tedge_api::workflow::SyncOnCommand;
 use tedge_downloader_ext::DownloadRequest;
 use tedge_downloader_ext::DownloadResult;
 use tedge_file_system_ext::FsWatchEvent;
+use tedge_mqtt_ext::DynSubscriptions;
 use tedge_mqtt_ext::MqttMessage;
+use tedge_mqtt_ext::MqttRequest;
+use tedge_mqtt_ext::SubscriptionDiff;
 use tedge_mqtt_ext::TopicFilter;
 use tedge_script_ext::Execute;
 
@@ -57,6 +63,7 @@ pub struct WorkflowActorBuilder {
     sync_signal_dispatcher: SyncSignalDispatcher,
     command_sender: DynSender<InternalCommandState>,
     mqtt_publisher: LoggingSender<MqttMessage>,
+    mqtt_subscriptions: LoggingSender<SubscriptionDiff>,
     script_runner: ClientMessageBox<Execute, std::io::Result<Output>>,
     signal_sender: mpsc::Sender<RuntimeRequest>,
     downloader: ClientMessageBox<DownloaderRequest, DownloaderResult>,
@@ -64,12 +71,14 @@ pub struct WorkflowActorBuilder {
         (OperationType, OperationStep),
         ClientMessageBox<OperationStepRequest, OperationStepResponse>,
     >,
+    command_history: Option<ClientMessageBox<CommandHistoryRequest, CommandHistoryResponse>>,
 }
 
 impl WorkflowActorBuilder {
     pub fn new(
         config: OperationConfig,
-        mqtt_actor: &mut (impl MessageSource<MqttMessage, TopicFilter> + MessageSink<MqttMessage>),
+        mqtt_actor: &mut (impl for<'a> MessageSource<MqttMessage, &'a mut DynSubscriptions>
+                  + MessageSink<MqttRequest>),
         script_runner: &mut impl Service<Execute, std::io::Result<Output>>,
         fs_notify: &mut impl MessageSource<FsWatchEvent, PathBuf>,
         downloader: &mut impl Service<DownloaderRequest, DownloaderResult>,
@@ -89,16 +98,20 @@ impl WorkflowActorBuilder {
 
         let sync_signal_dispatcher = SyncSignalDispatcher::default();
 
-        let mqtt_publisher = mqtt_actor.get_sender();
-        mqtt_actor.connect_sink(
-            Self::subscriptions(
-                &config.mqtt_schema,
-                &config.device_topic_id,
-                &config.service_topic_id,
-            ),
-            &input_sender,
-        );
-        let mqtt_publisher = LoggingSender::new("MqttPublisher".into(), mqtt_publisher);
+        let mut dyn_subscriptions = DynSubscriptions::new(Self::subscriptions(
+            &config.mqtt_schema,
+            &config.device_topic_id,
+            &config.service_topic_id,
+        ));
+        mqtt_actor.connect_sink(&mut dyn_subscriptions, &input_sender);
+        let client_id = dyn_subscriptions.client_id();
+        let mqtt_requests = mqtt_actor.get_sender();
+        let mqtt_subscriptions = Box::new(MappingSender::new(
+            mqtt_requests.sender_clone(),
+            move |diff| Some(MqttRequest::subscribe(client_id, diff)),
+        ));
+        let mqtt_publisher = LoggingSender::new("MqttPublisher".into(), Box::new(mqtt_requests));
+        let mqtt_subscriptions = LoggingSender::new("MqttSubscriptions".into(), mqtt_subscriptions);
 
         let script_runner = ClientMessageBox::new(script_runner);
 
@@ -114,10 +127,12 @@ impl WorkflowActorBuilder {
             sync_signal_dispatcher,
             command_sender,
             mqtt_publisher,
+            mqtt_subscriptions,
             signal_sender,
             script_runner,
             downloader,
             builtin_operation_step_executor: HashMap::new(),
+            command_history: None,
         }
     }
 
@@ -163,6 +178,17 @@ impl WorkflowActorBuilder {
         }
     }
 
+    /// Connect the service recording the commands that reach a terminal state
+    pub fn register_command_history(
+        &mut self,
+        service: &mut impl Service<CommandHistoryRequest, CommandHistoryResponse>,
+    ) {
+        self.command_history = Some(ClientMessageBox::new(service));
+    }
+
+    /// On start, only the commands of the agent device are subscribed to.
+    /// The sub-operations triggered by a parallel action on other entities
+    /// are subscribed to by the actor, only while these sub-operations are running.
     pub fn subscriptions(
         mqtt_schema: &MqttSchema,
         device_topic_id: &EntityTopicId,
@@ -218,10 +244,17 @@ impl Builder<WorkflowActor> for WorkflowActorBuilder {
             builtin_operation_step_executor: self.builtin_operation_step_executor,
             sync_signal_dispatcher: self.sync_signal_dispatcher,
             mqtt_publisher: self.mqtt_publisher,
+            mqtt_subscriptions: self.mqtt_subscriptions,
+            parallel_task_topics: TopicFilter::empty(),
             command_sender: self.command_sender,
             script_runner: self.script_runner,
             downloader: self.downloader,
             tmp_dir: self.config.tmp_dir.root().into(),
+            scheduler: self.config.scheduler,
+            deferred_commands: HashMap::new(),
+            joining_commands: HashMap::new(),
+            command_history: self.command_history,
+            started_commands: HashMap::new(),
         }
     }
 }
@@ -2,6 +2,8 @@ use crate::Capabilities;
 use tedge_api::mqtt_topics::EntityTopicId;
 use tedge_api::mqtt_topics::MqttSchema;
 use tedge_api::workflow::log::log_dir::OperationLogs;
+use tedge_api::workflow::CommandScheduler;
+use tedge_api::workflow::MaintenanceWindow;
 use tedge_config::TEdgeConfig;
 use tedge_utils::paths::ManagedDir;
 use tedge_utils::paths::TedgePaths;
@@ -17,6 +19,7 @@ pub struct OperationConfig {
     pub operations_dir: ManagedDir,
     pub tmp_dir: TedgePaths,
     pub capabilities: Capabilities,
+    pub scheduler: CommandScheduler,
 }
 
 impl OperationConfig {
@@ -33,6 +36,18 @@ impl OperationConfig {
             log_upload: tedge_config.agent.enable.log_upload,
         };
         let log_dir = tedge_config.operation_logs();
+        let maintenance_window = tedge_config
+            .agent
+            .maintenance
+            .window
+            .or_none()
+            .map(|window| window.parse::<MaintenanceWindow>())
+            .transpose()
+            .map_err(anyhow::Error::from)?;
+        let scheduler = CommandScheduler::new(
+            maintenance_window,
+            tedge_config.agent.maintenance.operations.0.clone(),
+        );
 
         Ok(OperationConfig {
             mqtt_schema: MqttSchema::with_root(topic_root),
@@ -44,6 +59,7 @@ impl OperationConfig {
             operations_dir: config_dir.dir("operations")?,
             tmp_dir: tedge_config.tmp_root(),
             capabilities,
+            scheduler,
         })
     }
 }
@@ -439,6 +439,10 @@ impl WorkflowRepository {
         self.workflows.apply_internal_update(new_command_state)
     }
 
+    pub fn get_state(&self, command: &str) -> Option<&GenericCommandState> {
+        self.workflows.get_state(command)
+    }
+
     pub fn get_action(
         &self,
         command_state: &GenericCommandState,
@@ -467,6 +471,10 @@ impl WorkflowRepository {
         self.workflows.sub_command_state(command_state)
     }
 
+    pub fn parallel_command_state(&self, task_topic: &str) -> Option<&GenericCommandState> {
+        self.workflows.parallel_command_state(task_topic)
+    }
+
     pub fn adapt_builtin_response(
         &self,
         command_state: GenericCommandState,
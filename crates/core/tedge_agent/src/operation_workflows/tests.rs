@@ -6,8 +6,10 @@ use crate::software_manager::actor::SoftwareCommand;
 use crate::Capabilities;
 use camino::Utf8Path;
 use serde_json::json;
+use std::collections::HashSet;
 use std::process::Output;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::time::Duration;
 use tedge_actors::test_helpers::MessageReceiverExt;
 use tedge_actors::test_helpers::TimedMessageBox;
@@ -39,6 +41,7 @@ use tedge_api::mqtt_topics::EntityTopicId;
 use tedge_api::mqtt_topics::MqttSchema;
 use tedge_api::mqtt_topics::OperationType;
 use tedge_api::workflow::log::log_dir::OperationLogs;
+use tedge_api::workflow::CommandScheduler;
 use tedge_api::workflow::GenericCommandData;
 use tedge_api::workflow::GenericCommandState;
 use tedge_api::workflow::OperationName;
@@ -51,12 +54,20 @@ use tedge_api::SoftwareUpdateCommand;
 use tedge_downloader_ext::DownloadResponse;
 use tedge_file_system_ext::FsWatchEvent;
 use tedge_mqtt_ext::test_helpers::assert_received_contains_str;
+use tedge_mqtt_ext::ClientId;
+use tedge_mqtt_ext::DynSubscriptions;
 use tedge_mqtt_ext::MqttMessage;
+use tedge_mqtt_ext::MqttRequest;
+use tedge_mqtt_ext::SubscriptionDiff;
+use tedge_mqtt_ext::SubscriptionRequest;
 use tedge_mqtt_ext::Topic;
 use tedge_script_ext::Execute;
 use tedge_test_utils::fs::TempTedgeDir;
 use tedge_utils::paths::TedgePaths;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
 const TEST_TIMEOUT_MS: Duration = Duration::from_millis(3000);
 
@@ -145,6 +156,8 @@ async fn convert_incoming_software_update_request() -> Result<(), DynError> {
                         .try_into()
                         .unwrap(),
                 ),
+                restore_snapshot: false,
+                rollback: None,
             },
         }])
         .await;
@@ -746,10 +759,340 @@ action = "cleanup"
     Ok(())
 }
 
+#[tokio::test]
+async fn software_update_is_deferred_till_its_scheduled_time() -> Result<(), DynError> {
+    let TestHandler {
+        mut mqtt_box,
+        mut software_box,
+        mut actor_handle,
+        ..
+    } = spawn_mqtt_operation_converter("device/main//", vec![]).await?;
+
+    // Request a software update to be executed in one second
+    let start = Instant::now();
+    let scheduled_at = (OffsetDateTime::now_utc() + Duration::from_secs(1)).format(&Rfc3339)?;
+    let mqtt_message = MqttMessage::new(
+        &Topic::new_unchecked("te/device/main///cmd/software_update/123"),
+        json!({"status":"init", "updateList":[], "scheduled_at": scheduled_at}).to_string(),
+    );
+    mqtt_box.send(mqtt_message).await?;
+
+    // The command is pending in the scheduled state
+    recv_command_state_with_status(
+        &mut mqtt_box,
+        &mut actor_handle,
+        "te/device/main///cmd/software_update/123",
+        "scheduled",
+    )
+    .await;
+
+    // The command is only forwarded to the software manager when due
+    let command =
+        recv_or_fail_on_actor_exit(&mut software_box, &mut actor_handle, "software update")
+            .await
+            .expect("software update command expected");
+    assert!(start.elapsed() >= Duration::from_millis(900));
+    assert!(matches!(command, SoftwareCommand::SoftwareUpdateCommand(_)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn deferred_commands_can_be_cancelled() -> Result<(), DynError> {
+    let TestHandler {
+        mut mqtt_box,
+        mut software_box,
+        mut actor_handle,
+        ..
+    } = spawn_mqtt_operation_converter("device/main//", vec![]).await?;
+
+    // Request a software update to be executed in one hour
+    let scheduled_at = (OffsetDateTime::now_utc() + Duration::from_secs(3600)).format(&Rfc3339)?;
+    let mqtt_message = MqttMessage::new(
+        &Topic::new_unchecked("te/device/main///cmd/software_update/123"),
+        json!({"status":"init", "updateList":[], "scheduled_at": scheduled_at}).to_string(),
+    );
+    mqtt_box.send(mqtt_message).await?;
+    recv_command_state_with_status(
+        &mut mqtt_box,
+        &mut actor_handle,
+        "te/device/main///cmd/software_update/123",
+        "scheduled",
+    )
+    .await;
+
+    // Cancel the command
+    let mqtt_message = MqttMessage::new(
+        &Topic::new_unchecked("te/device/main///cmd/software_update/123"),
+        r#"{"status":"failed", "reason":"Cancelled by operator"}"#,
+    );
+    mqtt_box.send(mqtt_message).await?;
+
+    // The command is marked as failed, preserving its original payload
+    let payload = recv_command_state_with_status(
+        &mut mqtt_box,
+        &mut actor_handle,
+        "te/device/main///cmd/software_update/123",
+        "failed",
+    )
+    .await;
+    assert_eq!(payload["reason"], "Cancelled by operator");
+    assert_eq!(payload["scheduled_at"], scheduled_at.as_str());
+
+    // And never executed
+    assert_no_message_or_actor_exit(
+        &mut software_box,
+        &mut actor_handle,
+        "waiting for no software update",
+    )
+    .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn maintenance_operations_are_deferred_till_the_maintenance_window() -> Result<(), DynError> {
+    // A window that is not open today, whatever the local time zone
+    let day = (OffsetDateTime::now_utc().day() + 10) % 28 + 1;
+    let scheduler = CommandScheduler::new(
+        Some(format!("* * {day} * *").parse()?),
+        vec!["software_update".to_string()],
+    );
+    let TestHandler {
+        mut mqtt_box,
+        mut software_box,
+        mut actor_handle,
+        ..
+    } = spawn_mqtt_operation_converter_with_scheduler("device/main//", vec![], scheduler).await?;
+
+    // Operations not related to maintenance are executed straight away
+    let mqtt_message = MqttMessage::new(
+        &Topic::new_unchecked("te/device/main///cmd/software_list/123"),
+        r#"{"status":"init"}"#,
+    );
+    mqtt_box.send(mqtt_message).await?;
+    let command = recv_or_fail_on_actor_exit(&mut software_box, &mut actor_handle, "software list")
+        .await
+        .expect("software list command expected");
+    assert!(matches!(command, SoftwareCommand::SoftwareListCommand(_)));
+
+    // Maintenance operations wait for the window
+    let mqtt_message = MqttMessage::new(
+        &Topic::new_unchecked("te/device/main///cmd/software_update/456"),
+        r#"{"status":"init", "updateList":[]}"#,
+    );
+    mqtt_box.send(mqtt_message).await?;
+    recv_command_state_with_status(
+        &mut mqtt_box,
+        &mut actor_handle,
+        "te/device/main///cmd/software_update/456",
+        "scheduled",
+    )
+    .await;
+    assert_no_message_or_actor_exit(
+        &mut software_box,
+        &mut actor_handle,
+        "waiting for the maintenance window",
+    )
+    .await;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn parallel_sub_operations_are_joined() -> Result<(), DynError> {
+    let workflow = r#"
+operation = "fleet_update"
+
+[init]
+action = "proceed"
+on_success = "fan_out"
+
+[fan_out]
+parallel = "firmware_update"
+items = "${.payload.devices}"
+max_concurrency = 1
+input.url = "${.payload.url}"
+on_exec = "join"
+
+[join]
+action = "join"
+on_success = "successful"
+on_partial = { status = "failed", reason = "Some devices have not been updated" }
+
+[successful]
+action = "cleanup"
+
+[failed]
+action = "cleanup"
+"#;
+
+    let TestHandler {
+        mut mqtt_box,
+        mqtt_subscriptions,
+        mut actor_handle,
+        ..
+    } = spawn_mqtt_operation_converter(
+        "device/main//",
+        vec![("fleet_update.toml".to_string(), workflow.to_string())],
+    )
+    .await?;
+
+    // Trigger a firmware update on two child devices
+    let init_message = MqttMessage::new(
+        &Topic::new_unchecked("te/device/main///cmd/fleet_update/123"),
+        json!({
+            "status": "init",
+            "url": "http://example.com/firmware",
+            "devices": [
+                {"@target": "device/child1//", "name": "robot"},
+                {"@target": "device/child2//", "name": "drone"},
+            ]
+        })
+        .to_string(),
+    );
+    mqtt_box.send(init_message).await?;
+
+    // Only one sub-operation is started at a time
+    let task_1 = "te/device/child1///cmd/firmware_update/par:0:fleet_update:123";
+    let task_2 = "te/device/child2///cmd/firmware_update/par:1:fleet_update:123";
+    let init_1 =
+        recv_command_state_with_status(&mut mqtt_box, &mut actor_handle, task_1, "init").await;
+    assert_eq!(
+        init_1,
+        json!({"status": "init", "url": "http://example.com/firmware", "name": "robot"})
+    );
+
+    mqtt_box
+        .send(MqttMessage::new(
+            &Topic::new_unchecked(task_1),
+            json!({"status": "successful"}).to_string(),
+        ))
+        .await?;
+    recv_command_state_with_status(&mut mqtt_box, &mut actor_handle, task_2, "init").await;
+
+    mqtt_box
+        .send(MqttMessage::new(
+            &Topic::new_unchecked(task_2),
+            json!({"status": "failed", "reason": "Out of battery"}).to_string(),
+        ))
+        .await?;
+
+    // The outcomes of the sub-operations are collected into the invoking command
+    let state = recv_command_state_with_status(
+        &mut mqtt_box,
+        &mut actor_handle,
+        "te/device/main///cmd/fleet_update/123",
+        "failed",
+    )
+    .await;
+    assert_eq!(state["reason"], "Some devices have not been updated");
+    assert_eq!(
+        state["@parallel"]["tasks"][1]["result"],
+        json!({"status": "failed", "reason": "Out of battery"})
+    );
+
+    // The sub-operations are finally cleared
+    for task in [task_1, task_2] {
+        let message = recv_or_fail_on_actor_exit(&mut mqtt_box, &mut actor_handle, "clear message")
+            .await
+            .expect("clear message expected");
+        assert_eq!(message.topic.name, task);
+        assert!(message.payload_bytes().is_empty());
+        assert!(message.retain);
+    }
+
+    // The agent listens to the sub-operations on the child devices, only while running
+    assert_eq!(
+        *mqtt_subscriptions.lock().unwrap(),
+        vec![
+            subscription_request(&[task_1], &[]),
+            subscription_request(&[], &[task_1]),
+            subscription_request(&[task_2], &[]),
+            subscription_request(&[], &[task_2]),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn parallel_sub_operations_join_can_timeout() -> Result<(), DynError> {
+    let workflow = r#"
+operation = "fleet_update"
+
+[init]
+action = "proceed"
+on_success = "fan_out"
+
+[fan_out]
+parallel = "firmware_update"
+items = "${.payload.devices}"
+on_exec = "join"
+
+[join]
+action = "join"
+on_success = "successful"
+timeout_second = 1
+on_timeout = { status = "failed", reason = "Some devices have not reported" }
+
+[successful]
+action = "cleanup"
+
+[failed]
+action = "cleanup"
+"#;
+
+    let TestHandler {
+        mut mqtt_box,
+        mut actor_handle,
+        ..
+    } = spawn_mqtt_operation_converter(
+        "device/main//",
+        vec![("fleet_update.toml".to_string(), workflow.to_string())],
+    )
+    .await?;
+
+    let init_message = MqttMessage::new(
+        &Topic::new_unchecked("te/device/main///cmd/fleet_update/123"),
+        json!({
+            "status": "init",
+            "devices": [
+                {"@target": "device/child1//"},
+                {"@target": "device/child2//"},
+            ]
+        })
+        .to_string(),
+    );
+    mqtt_box.send(init_message).await?;
+
+    let task_1 = "te/device/child1///cmd/firmware_update/par:0:fleet_update:123";
+    recv_command_state_with_status(&mut mqtt_box, &mut actor_handle, task_1, "init").await;
+    mqtt_box
+        .send(MqttMessage::new(
+            &Topic::new_unchecked(task_1),
+            json!({"status": "successful"}).to_string(),
+        ))
+        .await?;
+
+    // The second sub-operation never reports
+    let state = recv_command_state_with_status(
+        &mut mqtt_box,
+        &mut actor_handle,
+        "te/device/main///cmd/fleet_update/123",
+        "failed",
+    )
+    .await;
+    assert_eq!(state["reason"], "Some devices have not reported");
+
+    Ok(())
+}
+
 struct TestHandler {
     tmp_dir: Arc<TempTedgeDir>,
     actor_handle: JoinHandle<Result<(), RuntimeError>>,
     mqtt_box: TimedMessageBox<SimpleMessageBox<MqttMessage, MqttMessage>>,
+    mqtt_subscriptions: Arc<Mutex<Vec<SubscriptionRequest>>>,
     software_box: TimedMessageBox<SimpleMessageBox<SoftwareCommand, SoftwareCommand>>,
     restart_box: TimedMessageBox<SimpleMessageBox<RestartCommand, RestartCommand>>,
     _inotify_box: TimedMessageBox<SimpleMessageBox<NoMessage, FsWatchEvent>>,
@@ -764,13 +1107,28 @@ struct TestHandler {
 async fn spawn_mqtt_operation_converter(
     device_topic_id: &str,
     workflows: Vec<(String, String)>,
+) -> Result<TestHandler, DynError> {
+    spawn_mqtt_operation_converter_with_scheduler(
+        device_topic_id,
+        workflows,
+        CommandScheduler::default(),
+    )
+    .await
+}
+
+async fn spawn_mqtt_operation_converter_with_scheduler(
+    device_topic_id: &str,
+    workflows: Vec<(String, String)>,
+    scheduler: CommandScheduler,
 ) -> Result<TestHandler, DynError> {
     let mut software_builder = SoftwareActor(SimpleMessageBoxBuilder::new("Software", 5));
     let mut restart_builder = RestartActor(SimpleMessageBoxBuilder::new("Restart", 5));
     let mut config_builder = ConfigActorBuilder(SimpleMessageBoxBuilder::new("Config", 5));
 
-    let mut mqtt_builder: SimpleMessageBoxBuilder<MqttMessage, MqttMessage> =
-        SimpleMessageBoxBuilder::new("MQTT", 5);
+    let mut mqtt_builder = MqttActor {
+        messages: SimpleMessageBoxBuilder::new("MQTT", 5),
+        subscriptions: Arc::default(),
+    };
     let mut script_builder: SimpleMessageBoxBuilder<
         RequestEnvelope<Execute, std::io::Result<Output>>,
         NoMessage,
@@ -807,6 +1165,7 @@ async fn spawn_mqtt_operation_converter(
         operations_dir: config_root.dir("operations").unwrap(),
         tmp_dir: TedgePaths::from_root_with_defaults(tmp_path.join(tmp_path), "", ""),
         capabilities: Capabilities::default(),
+        scheduler,
     };
     let mut workflow_actor_builder = WorkflowActorBuilder::new(
         config,
@@ -822,7 +1181,8 @@ async fn spawn_mqtt_operation_converter(
     let config_box = config_builder.0.build().with_timeout(TEST_TIMEOUT_MS);
     let software_box = software_builder.0.build().with_timeout(TEST_TIMEOUT_MS);
     let restart_box = restart_builder.0.build().with_timeout(TEST_TIMEOUT_MS);
-    let mqtt_box = mqtt_builder.build().with_timeout(TEST_TIMEOUT_MS);
+    let mqtt_subscriptions = mqtt_builder.subscriptions.clone();
+    let mqtt_box = mqtt_builder.messages.build().with_timeout(TEST_TIMEOUT_MS);
     let downloader_box = downloade_builder.build().with_timeout(TEST_TIMEOUT_MS);
     let _inotify_box = inotify_builder.build().with_timeout(TEST_TIMEOUT_MS);
 
@@ -838,6 +1198,7 @@ async fn spawn_mqtt_operation_converter(
         tmp_dir,
         actor_handle,
         mqtt_box,
+        mqtt_subscriptions,
         software_box,
         restart_box,
         _inotify_box,
@@ -931,6 +1292,52 @@ async fn assert_no_message_or_actor_exit<T>(
     }
 }
 
+/// A fake MQTT actor forwarding the published messages and recording the subscription requests
+struct MqttActor {
+    messages: SimpleMessageBoxBuilder<MqttMessage, MqttMessage>,
+    subscriptions: Arc<Mutex<Vec<SubscriptionRequest>>>,
+}
+
+impl MessageSource<MqttMessage, &mut DynSubscriptions> for MqttActor {
+    fn connect_sink(
+        &mut self,
+        config: &mut DynSubscriptions,
+        peer: &impl MessageSink<MqttMessage>,
+    ) {
+        config.set_client_id_usize(0);
+        self.messages.connect_sink(NoConfig, peer);
+    }
+}
+
+impl MessageSink<MqttRequest> for MqttActor {
+    fn get_sender(&self) -> DynSender<MqttRequest> {
+        let subscriptions = self.subscriptions.clone();
+        MappingSender::new(self.messages.get_sender(), move |request| match request {
+            MqttRequest::Publish(message) => Some(message),
+            MqttRequest::Subscribe(request) => {
+                subscriptions.lock().unwrap().push(request);
+                None
+            }
+            MqttRequest::RetrieveRetain(_, _) => None,
+        })
+        .into()
+    }
+}
+
+fn subscription_request(subscribe: &[&str], unsubscribe: &[&str]) -> SubscriptionRequest {
+    let diff = SubscriptionDiff {
+        subscribe: subscribe
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<HashSet<_>>(),
+        unsubscribe: unsubscribe.iter().map(|t| t.to_string()).collect(),
+    };
+    match MqttRequest::subscribe(ClientId(0), diff) {
+        MqttRequest::Subscribe(request) => request,
+        _ => unreachable!(),
+    }
+}
+
 // FIXME: find a way to avoid repeating ourselves with fake and actual restart actors
 struct RestartActor(SimpleMessageBoxBuilder<RestartCommand, RestartCommand>);
 
@@ -2,13 +2,19 @@ use crate::agent::TEDGE_AGENT;
 use crate::software_manager::config::SoftwareManagerConfig;
 use crate::software_manager::error::SoftwareManagerError;
 use crate::software_manager::error::SoftwareManagerError::NoPlugins;
+use crate::software_manager::rollback::backup_files;
+use crate::software_manager::rollback::restore_files;
+use crate::software_manager::rollback::FileBackup;
+use crate::software_manager::rollback::SoftwareSnapshotRecord;
 use crate::state_repository::error::StateError;
 use crate::state_repository::state::AgentStateRepository;
 use anyhow::anyhow;
 use async_trait::async_trait;
 use camino::Utf8Path;
+use camino::Utf8PathBuf;
 use plugin_sm::plugin_manager::ExternalPlugins;
 use plugin_sm::plugin_manager::Plugins;
+use plugin_sm::snapshot::SoftwareSnapshot;
 use serde::Deserialize;
 use serde::Serialize;
 use std::process::Command;
@@ -25,6 +31,7 @@ use tedge_actors::SimpleMessageBox;
 use tedge_api::commands::CommandStatus;
 use tedge_api::commands::SoftwareCommandMetadata;
 use tedge_api::commands::SoftwareListCommand;
+use tedge_api::commands::SoftwareRollback;
 use tedge_api::commands::SoftwareUpdateCommand;
 use tedge_api::mqtt_topics::OperationType;
 use tedge_api::workflow::GenericCommandData;
@@ -32,6 +39,7 @@ use tedge_api::workflow::GenericCommandMetadata;
 use tedge_api::workflow::GenericCommandState;
 use tedge_api::CommandLog;
 use tedge_api::Jsonify;
+use tedge_api::LoggedCommand;
 use tedge_api::SoftwareType;
 use tedge_config::TEdgeConfigError;
 use tracing::error;
@@ -80,6 +88,7 @@ impl SoftwareCommand {
 pub struct SoftwareManagerActor {
     config: SoftwareManagerConfig,
     state_repository: AgentStateRepository<SoftwareCommand>,
+    snapshot_repository: AgentStateRepository<SoftwareSnapshotRecord>,
 
     // the Option is necessary to be able to concurrently handle a request,
     // which mutably borrows the sender, and listen on signals, which mutably
@@ -171,11 +180,17 @@ impl SoftwareManagerActor {
             config.config_dir.clone(),
             "software-current-operation",
         );
+        let snapshot_repository = AgentStateRepository::new(
+            config.state_dir.clone(),
+            config.config_dir.clone(),
+            "software-snapshot",
+        );
         let (output_sender, input_receiver) = message_box.into_split();
 
         Self {
             config,
             state_repository,
+            snapshot_repository,
             input_receiver: Some(input_receiver),
             output_sender,
         }
@@ -255,13 +270,55 @@ impl SoftwareManagerActor {
         let executing_response = request.clone().with_status(CommandStatus::Executing);
         self.output_sender.send(executing_response.into()).await?;
 
-        let command_log = request.payload.log_path.clone().map(|path| {
+        let response = if request.payload.restore_snapshot {
+            self.restore_snapshot(request, plugins).await?
+        } else if self.config.rollback.enable {
+            self.update_with_rollback(request, plugins).await?
+        } else {
+            let command_log = Self::command_log(&request);
+            plugins
+                .process(
+                    request,
+                    command_log,
+                    self.config.tmp_dir.root().as_std_path(),
+                )
+                .await
+        };
+        self.output_sender.send(response.into()).await?;
+
+        self.state_repository.clear().await?;
+        Ok(())
+    }
+
+    fn command_log(request: &SoftwareUpdateCommand) -> Option<CommandLog> {
+        request.payload.log_path.clone().map(|path| {
             CommandLog::from_log_path(
                 path,
                 OperationType::SoftwareUpdate.to_string(),
                 request.cmd_id.clone(),
             )
-        });
+        })
+    }
+
+    /// Apply a software update, rolling back to the previous software modules on failure
+    async fn update_with_rollback(
+        &self,
+        request: SoftwareUpdateCommand,
+        plugins: &ExternalPlugins,
+    ) -> Result<SoftwareUpdateCommand, SoftwareManagerError> {
+        let (snapshot, files) = match self.capture_snapshot(&request, plugins).await {
+            Ok(captured) => captured,
+            Err(err) => {
+                let reason = format!("Fail to capture the software snapshot: {err}");
+                if let Some(mut command_log) = Self::command_log(&request) {
+                    command_log.log_error(&reason).await;
+                }
+                return Ok(request.with_error(reason));
+            }
+        };
+
+        let cmd_id = request.cmd_id.clone();
+        let command_log = Self::command_log(&request);
         let response = plugins
             .process(
                 request,
@@ -269,10 +326,195 @@ impl SoftwareManagerActor {
                 self.config.tmp_dir.root().as_std_path(),
             )
             .await;
-        self.output_sender.send(response.into()).await?;
 
-        self.state_repository.clear().await?;
-        Ok(())
+        let cause = match response.status() {
+            CommandStatus::Failed { reason } => Some(reason),
+            CommandStatus::Successful => self.health_check(&response).await.err(),
+            _ => None,
+        };
+        let response = match cause {
+            Some(cause) => {
+                self.rollback(response, cause, &snapshot, &files, plugins)
+                    .await
+            }
+            None => response,
+        };
+
+        // The snapshot of a sub-command is kept till its invoking command completes,
+        // as the latter might request the whole snapshot to be restored
+        if !cmd_id.starts_with("sub:") {
+            self.snapshot_repository.clear().await?;
+        }
+
+        Ok(response)
+    }
+
+    /// Capture the state of the modules impacted by a software update request
+    ///
+    /// Return the snapshot captured for this request along the backup copies of the configuration files,
+    /// the latter being only captured on the first update of a command.
+    async fn capture_snapshot(
+        &self,
+        request: &SoftwareUpdateCommand,
+        plugins: &ExternalPlugins,
+    ) -> Result<(SoftwareSnapshot, Vec<FileBackup>), SoftwareManagerError> {
+        let mut command_log = Self::command_log(request);
+        if let Some(command_log) = command_log.as_mut() {
+            command_log
+                .log_info("Capturing the software modules before update")
+                .await;
+        }
+        let snapshot = plugins.snapshot(request, command_log.as_mut()).await?;
+
+        let (mut record, files) = match self.snapshot_repository.load().await {
+            Ok(Some(record)) if record.cmd_id == request.cmd_id => (record, vec![]),
+            _ => {
+                let files =
+                    backup_files(&self.config.rollback.files, &self.snapshot_backup_dir()).await?;
+                let record = SoftwareSnapshotRecord {
+                    cmd_id: request.cmd_id.clone(),
+                    modules: SoftwareSnapshot::default(),
+                    files: files.clone(),
+                };
+                (record, files)
+            }
+        };
+        record.modules.merge(snapshot.clone());
+        self.snapshot_repository.store(&record).await?;
+
+        Ok((snapshot, files))
+    }
+
+    fn snapshot_backup_dir(&self) -> Utf8PathBuf {
+        self.snapshot_repository
+            .state_repo_path
+            .with_file_name("software-snapshot-files")
+    }
+
+    /// Run the health check command, if any, returning the failure cause
+    async fn health_check(&self, response: &SoftwareUpdateCommand) -> Result<(), String> {
+        let Some(health_check) = &self.config.rollback.health_check else {
+            return Ok(());
+        };
+        let mut command_log = Self::command_log(response);
+        let outcome = match LoggedCommand::new(health_check, self.config.tmp_dir.root()) {
+            Ok(command) => command.execute(command_log.as_mut()).await,
+            Err(err) => Err(err),
+        };
+        match outcome {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => Err(format!("Health check failed with {}", output.status)),
+            Err(err) => Err(format!("Health check cannot be launched: {err}")),
+        }
+    }
+
+    /// Restore the software modules and configuration files captured before a failed update
+    async fn rollback(
+        &self,
+        response: SoftwareUpdateCommand,
+        cause: String,
+        snapshot: &SoftwareSnapshot,
+        files: &[FileBackup],
+        plugins: &ExternalPlugins,
+    ) -> SoftwareUpdateCommand {
+        warn!("Rolling back software update {}: {cause}", response.cmd_id);
+        let mut command_log = Self::command_log(&response);
+        if let Some(command_log) = command_log.as_mut() {
+            command_log
+                .log_info(&format!("Rolling back software update: {cause}"))
+                .await;
+        }
+
+        let mut restore_request =
+            SoftwareUpdateCommand::new(&response.target, response.cmd_id.clone());
+        restore_request.payload.update_list = snapshot.restore_list();
+        let restored = plugins
+            .process(
+                restore_request,
+                command_log,
+                self.config.tmp_dir.root().as_std_path(),
+            )
+            .await;
+        let mut errors = restore_files(files).await;
+        if let CommandStatus::Failed { reason } = restored.status() {
+            errors.insert(0, reason);
+        }
+
+        let (rollback, reason) = if errors.is_empty() {
+            let rollback = SoftwareRollback {
+                cause: cause.clone(),
+                status: "successful".to_string(),
+                reason: None,
+                update_list: restored.payload.update_list,
+            };
+            (rollback, format!("{cause} (rolled back)"))
+        } else {
+            let rollback_error = errors.join(", ");
+            let rollback = SoftwareRollback {
+                cause: cause.clone(),
+                status: "failed".to_string(),
+                reason: Some(rollback_error.clone()),
+                update_list: restored.payload.update_list,
+            };
+            (
+                rollback,
+                format!("{cause} (rollback failed: {rollback_error})"),
+            )
+        };
+
+        let mut command_log = Self::command_log(&response);
+        if let Some(command_log) = command_log.as_mut() {
+            command_log
+                .log_info(&format!("Rollback {}", rollback.status))
+                .await;
+        }
+        let mut response = response.with_error(reason);
+        response.payload.rollback = Some(rollback);
+        response
+    }
+
+    /// Restore the snapshot captured by the previous updates of the same command
+    async fn restore_snapshot(
+        &self,
+        request: SoftwareUpdateCommand,
+        plugins: &ExternalPlugins,
+    ) -> Result<SoftwareUpdateCommand, SoftwareManagerError> {
+        let mut command_log = Self::command_log(&request);
+        let record = match self.snapshot_repository.load().await {
+            Ok(Some(record)) if record.cmd_id == request.cmd_id => record,
+            _ => {
+                if let Some(command_log) = command_log.as_mut() {
+                    command_log
+                        .log_info("No software snapshot to restore")
+                        .await;
+                }
+                return Ok(request.with_status(CommandStatus::Successful));
+            }
+        };
+
+        if let Some(command_log) = command_log.as_mut() {
+            command_log
+                .log_info("Restoring the software modules captured before update")
+                .await;
+        }
+        let mut restore_request = request;
+        restore_request.payload.update_list = record.modules.restore_list();
+        let response = plugins
+            .process(
+                restore_request,
+                command_log,
+                self.config.tmp_dir.root().as_std_path(),
+            )
+            .await;
+        let errors = restore_files(&record.files).await;
+        self.snapshot_repository.clear().await?;
+
+        match response.status() {
+            CommandStatus::Successful if !errors.is_empty() => {
+                Ok(response.with_error(errors.join(", ")))
+            }
+            _ => Ok(response),
+        }
     }
 
     fn detect_self_update() -> Result<(), SoftwareManagerError> {
@@ -14,6 +14,14 @@ pub struct SoftwareManagerConfig {
     pub log_dir: ManagedDir,
     pub default_plugin_type: Option<String>,
     pub sudo: SudoCommandBuilder,
+    pub rollback: RollbackConfig,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RollbackConfig {
+    pub enable: bool,
+    pub health_check: Option<String>,
+    pub files: Vec<String>,
 }
 
 impl SoftwareManagerConfig {
@@ -31,6 +39,17 @@ impl SoftwareManagerConfig {
 
         let device = tedge_config.mqtt.device_topic_id.clone();
 
+        let rollback = RollbackConfig {
+            enable: tedge_config.software.rollback.enable,
+            health_check: tedge_config
+                .software
+                .rollback
+                .health_check
+                .or_none()
+                .cloned(),
+            files: tedge_config.software.rollback.files.0.clone(),
+        };
+
         Ok(SoftwareManagerConfig {
             device,
             tmp_dir: tedge_config.tmp_root(),
@@ -40,6 +59,7 @@ impl SoftwareManagerConfig {
             log_dir: tedge_config.logs_root().dir("agent")?,
             default_plugin_type,
             sudo: SudoCommandBuilder::new(tedge_config),
+            rollback,
         })
     }
 }
@@ -2,6 +2,7 @@ pub mod actor;
 pub mod builder;
 pub mod config;
 pub mod error;
+pub mod rollback;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,150 @@
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use plugin_sm::snapshot::SoftwareSnapshot;
+use serde::Deserialize;
+use serde::Serialize;
+use tokio::fs;
+use tracing::warn;
+
+/// The state of the device captured before software updates, to be restored if these updates fail
+///
+/// The snapshot is shared by all the software updates triggered with the same command id,
+/// so a device profile, which software updates are all sub-commands of the same command,
+/// can be rolled back as a whole.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SoftwareSnapshotRecord {
+    /// The id of the command for which the snapshot has been captured
+    pub cmd_id: String,
+
+    /// The previous state of the software modules impacted by the updates
+    pub modules: SoftwareSnapshot,
+
+    /// The backup copies of the configuration files
+    #[serde(default)]
+    pub files: Vec<FileBackup>,
+}
+
+/// The backup copy of a configuration file
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileBackup {
+    /// The path of the configuration file
+    pub path: Utf8PathBuf,
+
+    /// The path of the backup copy, if the file existed before the update
+    pub backup: Option<Utf8PathBuf>,
+}
+
+/// Copy the given configuration files into the backup directory
+///
+/// Any previous backup is removed.
+pub async fn backup_files(
+    files: &[String],
+    backup_dir: &Utf8Path,
+) -> Result<Vec<FileBackup>, std::io::Error> {
+    if fs::try_exists(backup_dir).await? {
+        fs::remove_dir_all(backup_dir).await?;
+    }
+    if files.is_empty() {
+        return Ok(vec![]);
+    }
+    fs::create_dir_all(backup_dir).await?;
+
+    let mut backups = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        let path = Utf8PathBuf::from(file);
+        let backup = if fs::try_exists(&path).await? {
+            let backup = backup_dir.join(index.to_string());
+            fs::copy(&path, &backup).await?;
+            Some(backup)
+        } else {
+            None
+        };
+        backups.push(FileBackup { path, backup });
+    }
+
+    Ok(backups)
+}
+
+/// Restore the configuration files from their backup copies
+///
+/// A file that didn't exist before the update is removed.
+/// Return the error messages of the files that cannot be restored.
+pub async fn restore_files(backups: &[FileBackup]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for FileBackup { path, backup } in backups {
+        let outcome = match backup {
+            Some(backup) => fs::copy(backup, path).await.map(|_| ()),
+            None => match fs::remove_file(path).await {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                outcome => outcome,
+            },
+        };
+        if let Err(err) = outcome {
+            warn!("Fail to restore {path}: {err}");
+            errors.push(format!("Fail to restore {path}: {err}"));
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tedge_test_utils::fs::TempTedgeDir;
+
+    #[tokio::test]
+    async fn restore_files_from_backup() {
+        let temp_dir = TempTedgeDir::new();
+        let config = temp_dir.file("config.toml").with_raw_content("version = 1");
+        let config_path = config.utf8_path_buf();
+        let new_path = temp_dir.utf8_path().join("new.toml");
+        let backup_dir = temp_dir.utf8_path().join("backup");
+
+        let files = vec![config_path.to_string(), new_path.to_string()];
+        let backups = backup_files(&files, &backup_dir).await.unwrap();
+
+        fs::write(&config_path, "version = 2").await.unwrap();
+        fs::write(&new_path, "created by the update").await.unwrap();
+
+        let errors = restore_files(&backups).await;
+        assert!(errors.is_empty());
+        assert_eq!(
+            fs::read_to_string(&config_path).await.unwrap(),
+            "version = 1"
+        );
+        assert!(!fs::try_exists(&new_path).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn report_files_that_cannot_be_restored() {
+        let temp_dir = TempTedgeDir::new();
+        let lost = temp_dir.file("lost.toml").with_raw_content("version = 1");
+        let kept = temp_dir.file("kept.toml").with_raw_content("version = 1");
+        let backup_dir = temp_dir.utf8_path().join("backup");
+
+        let files = vec![
+            lost.utf8_path_buf().to_string(),
+            kept.utf8_path_buf().to_string(),
+        ];
+        let backups = backup_files(&files, &backup_dir).await.unwrap();
+
+        fs::write(lost.utf8_path_buf(), "version = 2")
+            .await
+            .unwrap();
+        fs::write(kept.utf8_path_buf(), "version = 2")
+            .await
+            .unwrap();
+        fs::remove_file(backups[0].backup.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        // The files that can be restored are restored even if others cannot
+        let errors = restore_files(&backups).await;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with(&format!("Fail to restore {}", lost.utf8_path_buf())));
+        assert_eq!(
+            fs::read_to_string(kept.utf8_path_buf()).await.unwrap(),
+            "version = 1"
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use crate::software_manager::actor::SoftwareCommand;
 use crate::software_manager::builder::SoftwareManagerBuilder;
+use crate::software_manager::config::RollbackConfig;
 use crate::software_manager::config::SoftwareManagerConfig;
+use camino::Utf8PathBuf;
 use serde_json::json;
+use std::os::unix::fs::PermissionsExt;
 use std::time::Duration;
 use tedge_actors::test_helpers::MessageReceiverExt;
 use tedge_actors::test_helpers::TimedMessageBox;
@@ -99,6 +102,8 @@ async fn test_new_software_update_operation() -> Result<(), DynError> {
             update_list: vec![debian_list],
             failures: vec![],
             log_path: None,
+            restore_snapshot: false,
+            rollback: None,
         },
     };
     converter_box.send(command.into()).await?;
@@ -175,15 +180,72 @@ async fn test_new_software_list_operation() -> Result<(), DynError> {
     Ok(())
 }
 
+#[tokio::test]
+async fn rollback_software_update_failing_on_second_module() -> Result<(), DynError> {
+    let temp_dir = TempTedgeDir::new();
+    let installed = create_fake_plugin(&temp_dir, "fake", "app\t1.0\n");
+    let mut converter_box = spawn_software_manager_with_rollback(&temp_dir).await?;
+
+    let response = apply_update(
+        &mut converter_box,
+        "fake",
+        vec![
+            install_module("app", "2.0"),
+            install_module("broken-install", "1.0"),
+        ],
+    )
+    .await?;
+
+    let CommandStatus::Failed { reason } = response.status() else {
+        panic!("Expected a failed update, got {:?}", response.status())
+    };
+    assert!(reason.ends_with("(rolled back)"), "{reason}");
+    let rollback = response.payload.rollback.expect("rollback outcome");
+    assert_eq!(rollback.status, "successful");
+    assert_eq!(rollback.reason, None);
+
+    // The first module has been re-installed with its previous version
+    assert_eq!(std::fs::read_to_string(installed)?, "app\t1.0\n");
+    Ok(())
+}
+
+#[tokio::test]
+async fn report_software_update_rollback_failures() -> Result<(), DynError> {
+    let temp_dir = TempTedgeDir::new();
+    create_fake_plugin(&temp_dir, "fake", "app\t1.0\n");
+    let mut converter_box = spawn_software_manager_with_rollback(&temp_dir).await?;
+
+    // The new module is installed but then fails the rollback as it cannot be removed
+    let response = apply_update(
+        &mut converter_box,
+        "fake",
+        vec![
+            install_module("broken-remove", "1.0"),
+            install_module("broken-install", "1.0"),
+        ],
+    )
+    .await?;
+
+    let CommandStatus::Failed { reason } = response.status() else {
+        panic!("Expected a failed update, got {:?}", response.status())
+    };
+    assert!(reason.contains("(rollback failed: "), "{reason}");
+    let rollback = response.payload.rollback.expect("rollback outcome");
+    assert_eq!(rollback.status, "failed");
+    assert!(rollback.reason.is_some());
+    Ok(())
+}
+
 async fn spawn_software_manager(
     tmp_dir: &TempTedgeDir,
 ) -> Result<TimedMessageBox<SimpleMessageBox<SoftwareCommand, SoftwareCommand>>, DynError> {
-    let mut converter_builder: SimpleMessageBoxBuilder<SoftwareCommand, SoftwareCommand> =
-        SimpleMessageBoxBuilder::new("Converter", 5);
+    spawn_software_manager_with_config(software_manager_config(tmp_dir)).await
+}
 
+fn software_manager_config(tmp_dir: &TempTedgeDir) -> SoftwareManagerConfig {
     let config_root = TedgePaths::from_root_with_defaults(tmp_dir.utf8_path(), "", "");
 
-    let config = SoftwareManagerConfig {
+    SoftwareManagerConfig {
         device: EntityTopicId::default_main_device(),
         tmp_dir: TedgePaths::from_root_with_defaults(tmp_dir.utf8_path(), "", ""),
         config_dir: config_root.clone(),
@@ -192,7 +254,15 @@ async fn spawn_software_manager(
         log_dir: TedgePaths::from_root_with_defaults(tmp_dir.utf8_path(), "", "").root_dir(),
         default_plugin_type: None,
         sudo: SudoCommandBuilder::with_program(SUDO),
-    };
+        rollback: RollbackConfig::default(),
+    }
+}
+
+async fn spawn_software_manager_with_config(
+    config: SoftwareManagerConfig,
+) -> Result<TimedMessageBox<SimpleMessageBox<SoftwareCommand, SoftwareCommand>>, DynError> {
+    let mut converter_builder: SimpleMessageBoxBuilder<SoftwareCommand, SoftwareCommand> =
+        SimpleMessageBoxBuilder::new("Converter", 5);
 
     let mut software_actor_builder = SoftwareManagerBuilder::new(config);
     converter_builder.connect_sink(NoConfig, &software_actor_builder);
@@ -205,3 +275,96 @@ async fn spawn_software_manager(
 
     Ok(converter_box)
 }
+
+async fn spawn_software_manager_with_rollback(
+    tmp_dir: &TempTedgeDir,
+) -> Result<TimedMessageBox<SimpleMessageBox<SoftwareCommand, SoftwareCommand>>, DynError> {
+    tmp_dir.dir(".agent");
+    let mut config = software_manager_config(tmp_dir);
+    config.sudo = SudoCommandBuilder::enabled(false);
+    config.rollback.enable = true;
+    let mut converter_box = spawn_software_manager_with_config(config).await?;
+
+    // Skip the software command metadata
+    converter_box.recv().await;
+    Ok(converter_box)
+}
+
+/// Create a plugin managing the modules listed in a text file
+///
+/// The installation of the `broken-install` module fails,
+/// as does the removal of the `broken-remove` module.
+fn create_fake_plugin(tmp_dir: &TempTedgeDir, name: &str, installed: &str) -> Utf8PathBuf {
+    let installed_path = tmp_dir.utf8_path().join(format!("{name}-installed"));
+    std::fs::write(&installed_path, installed).unwrap();
+
+    let script = format!(
+        r#"#!/bin/sh
+INSTALLED="{installed_path}"
+TAB="$(printf '\t')"
+case "$1" in
+    list) cat "$INSTALLED" ;;
+    install)
+        [ "$2" = broken-install ] && {{ echo "cannot install $2" >&2; exit 2; }}
+        grep -v "^$2$TAB" "$INSTALLED" > "$INSTALLED.new"
+        printf '%s\t%s\n' "$2" "$4" >> "$INSTALLED.new"
+        mv "$INSTALLED.new" "$INSTALLED"
+        ;;
+    remove)
+        [ "$2" = broken-remove ] && {{ echo "cannot remove $2" >&2; exit 2; }}
+        grep -v "^$2$TAB" "$INSTALLED" > "$INSTALLED.new"
+        mv "$INSTALLED.new" "$INSTALLED"
+        ;;
+    update-list) exit 1 ;;
+esac
+"#
+    );
+    let plugin_path = tmp_dir.utf8_path().join("sm-plugins").join(name);
+    std::fs::create_dir_all(plugin_path.parent().unwrap()).unwrap();
+    std::fs::write(&plugin_path, script).unwrap();
+    std::fs::set_permissions(&plugin_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    installed_path
+}
+
+fn install_module(name: &str, version: &str) -> SoftwareModuleItem {
+    SoftwareModuleItem {
+        name: name.into(),
+        version: Some(version.into()),
+        action: Some(SoftwareModuleAction::Install),
+        url: None,
+        reason: None,
+    }
+}
+
+/// Send a software update request, returning the final response
+async fn apply_update(
+    converter_box: &mut TimedMessageBox<SimpleMessageBox<SoftwareCommand, SoftwareCommand>>,
+    plugin_type: &str,
+    modules: Vec<SoftwareModuleItem>,
+) -> Result<SoftwareUpdateCommand, DynError> {
+    let mut command = SoftwareUpdateCommand::new(
+        &EntityTopicId::default_main_device(),
+        "rollback-test".to_string(),
+    );
+    command.payload.update_list = vec![SoftwareRequestResponseSoftwareList {
+        plugin_type: plugin_type.into(),
+        modules,
+        errors: vec![],
+    }];
+    converter_box
+        .send(command.with_status(CommandStatus::Scheduled).into())
+        .await?;
+
+    loop {
+        match converter_box.recv().await {
+            Some(SoftwareCommand::SoftwareUpdateCommand(response))
+                if response.status() != CommandStatus::Executing =>
+            {
+                return Ok(response)
+            }
+            Some(_) => continue,
+            None => return Err("No software update response".into()),
+        }
+    }
+}
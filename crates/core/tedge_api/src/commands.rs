@@ -370,6 +370,31 @@ impl CommandPayload for SoftwareListCommandPayload {
     }
 }
 
+/// Outcome of the rollback of a software update
+#[derive(Debug, Clone, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SoftwareRollback {
+    /// Why the rollback has been triggered
+    pub cause: String,
+
+    /// Either `successful` or `failed`
+    pub status: String,
+
+    /// The reason of the rollback failure, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    /// The updates applied to restore the previous software modules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub update_list: Vec<SoftwareRequestResponseSoftwareList>,
+}
+
+impl SoftwareRollback {
+    pub fn is_successful(&self) -> bool {
+        self.status == "successful"
+    }
+}
+
 /// Sub list of modules grouped by plugin type.
 #[derive(Debug, Clone, Deserialize, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -429,6 +454,15 @@ pub struct SoftwareUpdateCommandPayload {
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub log_path: Option<Utf8PathBuf>,
+
+    /// Request the software modules captured before the previous updates to be restored,
+    /// instead of applying an update list
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub restore_snapshot: bool,
+
+    /// The outcome of the rollback triggered by a failed update, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollback: Option<SoftwareRollback>,
 }
 
 impl Jsonify for SoftwareUpdateCommandPayload {}
@@ -1019,6 +1053,8 @@ mod tests {
             update_list: vec![debian_list, docker_list],
             failures: vec![],
             log_path: None,
+            restore_snapshot: false,
+            rollback: None,
         };
 
         let expected_json = r#"{"status":"init","updateList":[{"type":"debian","modules":[{"name":"debian1","version":"0.0.1","action":"install"},{"name":"debian2","version":"0.0.2","action":"install"}]},{"type":"docker","modules":[{"name":"docker1","version":"0.0.1","url":"test.com","action":"remove"}]}]}"#;
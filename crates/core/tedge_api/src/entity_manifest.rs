@@ -0,0 +1,327 @@
+//! Declarative description of a set of entities, with their metadata and twin data.
+//!
+//! The same format is used for the manifest files loaded by the agent
+//! and for the files produced by `tedge entities export`.
+//! Each entity is described as in an HTTP registration request,
+//! i.e. with a `@topic-id`, a `@type`, an optional `@parent`, `@id` and `@health`,
+//! and any other property being a twin fragment.
+//!
+//! ```toml
+//! [[entities]]
+//! "@topic-id" = "device/child1//"
+//! "@type" = "child-device"
+//! "@id" = "gateway:child1"
+//! name = "Child 1"
+//!
+//! [entities.hardware]
+//! model = "rpi4"
+//! ```
+use crate::entity::EntityMetadata;
+use crate::entity::EntityType;
+use crate::entity_store::EntityRegistrationMessage;
+use crate::entity_store::EntityRegistrationPayload;
+use crate::mqtt_topics::EntityTopicId;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// A set of entities, as declared in a manifest or exported from the entity store
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityManifest {
+    #[serde(default)]
+    pub entities: Vec<EntityDeclaration>,
+}
+
+/// The declaration of an entity, along its twin data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityDeclaration {
+    #[serde(rename = "@topic-id")]
+    pub topic_id: EntityTopicId,
+
+    #[serde(flatten)]
+    pub payload: EntityRegistrationPayload,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ManifestError {
+    #[error("Failed to read {path}: {source}")]
+    ReadFailed {
+        path: Utf8PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid JSON entity manifest {path}: {source}")]
+    InvalidJson {
+        path: Utf8PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[error("Invalid TOML entity manifest {path}: {source}")]
+    InvalidToml {
+        path: Utf8PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("Unsupported entity manifest format {path}: expecting a .toml or .json file")]
+    UnsupportedFormat { path: Utf8PathBuf },
+}
+
+impl EntityManifest {
+    /// Check if a file is an entity manifest, i.e. a TOML or JSON file
+    pub fn is_manifest_file(path: &Utf8Path) -> bool {
+        matches!(path.extension(), Some("toml") | Some("json"))
+    }
+
+    /// Load a TOML or JSON manifest file
+    pub fn load_file(path: &Utf8Path) -> Result<Self, ManifestError> {
+        let content =
+            std::fs::read_to_string(path).map_err(|source| ManifestError::ReadFailed {
+                path: path.to_owned(),
+                source,
+            })?;
+        match path.extension() {
+            Some("toml") => toml::from_str(&content).map_err(|source| ManifestError::InvalidToml {
+                path: path.to_owned(),
+                source,
+            }),
+            Some("json") => {
+                serde_json::from_str(&content).map_err(|source| ManifestError::InvalidJson {
+                    path: path.to_owned(),
+                    source,
+                })
+            }
+            _ => Err(ManifestError::UnsupportedFormat {
+                path: path.to_owned(),
+            }),
+        }
+    }
+
+    /// Load all the manifest files of a directory, in the alphabetical order of their names
+    ///
+    /// Invalid files are skipped, returning the errors along the entities declared by the valid files.
+    /// An entity declared by several files is defined by the last one.
+    pub fn load_dir(dir: &Utf8Path) -> (Self, Vec<ManifestError>) {
+        let mut manifest = EntityManifest::default();
+        let mut errors = vec![];
+
+        let mut files: Vec<Utf8PathBuf> = match dir.read_dir_utf8() {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.into_path())
+                .filter(|path| path.is_file() && EntityManifest::is_manifest_file(path))
+                .collect(),
+            Err(source) => {
+                errors.push(ManifestError::ReadFailed {
+                    path: dir.to_owned(),
+                    source,
+                });
+                vec![]
+            }
+        };
+        files.sort();
+
+        for file in files {
+            match EntityManifest::load_file(&file) {
+                Ok(file_manifest) => manifest.extend(file_manifest),
+                Err(err) => errors.push(err),
+            }
+        }
+
+        (manifest, errors)
+    }
+
+    /// Add the entities of another manifest, replacing those declared by both
+    pub fn extend(&mut self, other: EntityManifest) {
+        for entity in other.entities {
+            match self
+                .entities
+                .iter_mut()
+                .find(|e| e.topic_id == entity.topic_id)
+            {
+                Some(existing) => *existing = entity,
+                None => self.entities.push(entity),
+            }
+        }
+    }
+
+    /// The topic identifiers of the declared entities
+    pub fn topic_ids(&self) -> impl Iterator<Item = &EntityTopicId> {
+        self.entities.iter().map(|entity| &entity.topic_id)
+    }
+
+    /// Sort the entities so parents are declared before their children
+    pub fn parents_first(mut self) -> Self {
+        let parents: HashMap<EntityTopicId, EntityTopicId> = self
+            .entities
+            .iter()
+            .filter_map(|entity| Some((entity.topic_id.clone(), entity.parent()?)))
+            .collect();
+        let depth = |topic_id: &EntityTopicId| {
+            let mut depth = 0;
+            let mut current = topic_id;
+            // Bounded to the number of entities to be safe with cyclic declarations
+            while let Some(parent) = parents.get(current) {
+                depth += 1;
+                current = parent;
+                if depth > parents.len() {
+                    break;
+                }
+            }
+            depth
+        };
+        self.entities
+            .sort_by_cached_key(|entity| (depth(&entity.topic_id), entity.topic_id.clone()));
+        self
+    }
+}
+
+impl EntityDeclaration {
+    /// The parent of this entity, either declared or derived from the default topic scheme
+    pub fn parent(&self) -> Option<EntityTopicId> {
+        self.payload
+            .parent
+            .clone()
+            .or_else(|| match self.payload.r#type {
+                EntityType::MainDevice => None,
+                EntityType::ChildDevice => None,
+                EntityType::Service => self.topic_id.default_service_parent_identifier(),
+            })
+    }
+}
+
+impl From<EntityDeclaration> for EntityRegistrationMessage {
+    fn from(entity: EntityDeclaration) -> Self {
+        EntityRegistrationMessage {
+            topic_id: entity.topic_id,
+            external_id: entity.payload.external_id,
+            r#type: entity.payload.r#type,
+            parent: entity.payload.parent,
+            health_endpoint: entity.payload.health_endpoint,
+            twin_data: entity.payload.twin_data,
+        }
+    }
+}
+
+impl From<EntityMetadata> for EntityDeclaration {
+    fn from(entity: EntityMetadata) -> Self {
+        EntityDeclaration {
+            topic_id: entity.topic_id,
+            payload: EntityRegistrationPayload {
+                external_id: entity.external_id,
+                r#type: entity.r#type,
+                parent: entity.parent,
+                health_endpoint: entity.health_endpoint,
+                twin_data: entity.twin_data,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tedge_test_utils::fs::TempTedgeDir;
+
+    fn topic_ids(manifest: &EntityManifest) -> Vec<&str> {
+        manifest.topic_ids().map(|t| t.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_toml_manifest() {
+        let manifest: EntityManifest = toml::from_str(
+            r#"
+[[entities]]
+"@topic-id" = "device/child1//"
+"@type" = "child-device"
+"@id" = "gateway:child1"
+name = "Child 1"
+
+[entities.hardware]
+model = "rpi4"
+
+[[entities]]
+"@topic-id" = "device/child1/service/collectd"
+"@type" = "service"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            topic_ids(&manifest),
+            vec!["device/child1//", "device/child1/service/collectd"]
+        );
+        let child: EntityRegistrationMessage = manifest.entities[0].clone().into();
+        assert_eq!(child.r#type, EntityType::ChildDevice);
+        assert_eq!(child.external_id, Some("gateway:child1".into()));
+        assert_eq!(child.twin_data.get("name"), Some(&json!("Child 1")));
+        assert_eq!(
+            child.twin_data.get("hardware"),
+            Some(&json!({"model": "rpi4"}))
+        );
+        assert_eq!(
+            manifest.entities[1].parent(),
+            Some("device/child1//".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn sort_parents_first() {
+        let manifest: EntityManifest = serde_json::from_value(json!({
+            "entities": [
+                {"@topic-id": "device/child10//", "@type": "child-device", "@parent": "device/child1//"},
+                {"@topic-id": "device/child1/service/collectd", "@type": "service"},
+                {"@topic-id": "device/child1//", "@type": "child-device"},
+                {"@topic-id": "device/child0//", "@type": "child-device"},
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            topic_ids(&manifest.parents_first()),
+            vec![
+                "device/child0//",
+                "device/child1//",
+                "device/child1/service/collectd",
+                "device/child10//"
+            ]
+        );
+    }
+
+    #[test]
+    fn load_manifest_directory() {
+        let ttd = TempTedgeDir::new();
+        ttd.file("10-children.toml").with_raw_content(
+            r#"
+[[entities]]
+"@topic-id" = "device/child1//"
+"@type" = "child-device"
+location = "hall"
+
+[[entities]]
+"@topic-id" = "device/child2//"
+"@type" = "child-device"
+"#,
+        );
+        ttd.file("20-override.json").with_raw_content(
+            r#"{"entities": [{"@topic-id": "device/child1//", "@type": "child-device", "location": "roof"}]}"#,
+        );
+        ttd.file("30-invalid.json").with_raw_content("not json");
+        ttd.file("README.md").with_raw_content("not a manifest");
+
+        let (manifest, errors) = EntityManifest::load_dir(ttd.utf8_path());
+
+        assert_eq!(
+            topic_ids(&manifest),
+            vec!["device/child1//", "device/child2//"]
+        );
+        assert_eq!(
+            manifest.entities[0].payload.twin_data.get("location"),
+            Some(&json!("roof"))
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], ManifestError::InvalidJson { .. }));
+    }
+}
@@ -484,6 +484,29 @@ impl EntityStore {
     pub fn list_entity_tree(&self, filters: ListFilters) -> Vec<&EntityMetadata> {
         self.entities.list_entity_tree(filters)
     }
+
+    /// List, sorted by topic id, at most `limit` entities matching the filters
+    /// and which topic ids are after the given cursor
+    pub fn list_entity_page(
+        &self,
+        filters: ListFilters,
+        after: Option<&EntityTopicId>,
+        limit: usize,
+    ) -> EntityPage<'_> {
+        let mut entities = self.entities.list_entity_tree(filters);
+        if let Some(after) = after {
+            entities.retain(|entity| &entity.topic_id > after);
+        }
+        entities.sort_by(|a, b| a.topic_id.cmp(&b.topic_id));
+
+        let next = if entities.len() > limit {
+            entities.truncate(limit);
+            entities.last().map(|entity| entity.topic_id.clone())
+        } else {
+            None
+        };
+        EntityPage { entities, next }
+    }
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -491,6 +514,27 @@ pub struct ListFilters {
     pub root: Option<EntityTopicId>,
     pub parent: Option<EntityTopicId>,
     pub r#type: Option<EntityType>,
+
+    /// A pattern the external id of the entities must match, using `*` and `?` wildcards
+    #[serde(default)]
+    pub external_id: Option<String>,
+
+    /// The twin values the entities must have, given by twin fragment paths
+    #[serde(default)]
+    pub twin: Vec<TwinFilter>,
+}
+
+/// Select the entities having a given twin value
+///
+/// The path is a twin fragment key, optionally followed by dot-separated field names,
+/// as in `hardware.model`.
+///
+/// The expected value is given as text and matches either a JSON string with the same content
+/// or any JSON value with the same JSON representation, e.g. `true` or `42`.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct TwinFilter {
+    pub path: String,
+    pub value: String,
 }
 
 impl ListFilters {
@@ -509,6 +553,19 @@ impl ListFilters {
         self
     }
 
+    pub fn external_id(mut self, pattern: impl Into<String>) -> Self {
+        self.external_id = Some(pattern.into());
+        self
+    }
+
+    pub fn twin(mut self, path: impl Into<String>, value: impl Into<String>) -> Self {
+        self.twin.push(TwinFilter {
+            path: path.into(),
+            value: value.into(),
+        });
+        self
+    }
+
     fn matches(&self, metadata: &EntityMetadata) -> bool {
         if let Some(entity_type) = self.r#type.as_ref() {
             if &metadata.r#type != entity_type {
@@ -520,10 +577,78 @@ impl ListFilters {
                 return false;
             }
         }
-        true
+        if let Some(pattern) = self.external_id.as_ref() {
+            let Some(external_id) = metadata.external_id.as_ref() else {
+                return false;
+            };
+            if !wildcard_match(pattern, external_id.as_ref()) {
+                return false;
+            }
+        }
+        self.twin.iter().all(|filter| filter.matches(metadata))
     }
 }
 
+impl TwinFilter {
+    fn matches(&self, metadata: &EntityMetadata) -> bool {
+        let mut path = self.path.split('.');
+        let Some(fragment_key) = path.next() else {
+            return false;
+        };
+        let value = path.fold(metadata.twin_data.get(fragment_key), |value, field| {
+            value.and_then(|value| value.get(field))
+        });
+        match value {
+            None => false,
+            Some(JsonValue::String(text)) => text == &self.value,
+            Some(value) => {
+                serde_json::from_str::<JsonValue>(&self.value).is_ok_and(|v| &v == value)
+            }
+        }
+    }
+}
+
+/// Check if a text matches a pattern where `*` stands for any sequence of characters
+/// and `?` for any single character
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Where to resume on a mismatch: the position of the last `*` and the text position it matched
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// A page of entities, sorted by topic id
+#[derive(Debug)]
+pub struct EntityPage<'a> {
+    pub entities: Vec<&'a EntityMetadata>,
+
+    /// The cursor to get the next page, if any: i.e. the topic id of the last entity of this page
+    pub next: Option<EntityTopicId>,
+}
+
 /// In-memory representation of the entity tree
 struct EntityTree {
     main_device: EntityTopicId,
@@ -1387,6 +1512,167 @@ mod tests {
             .map(|e| e.topic_id.as_str())
             .collect()
     }
+
+    #[test_case(
+        ListFilters::default().external_id("gateway:child2*"),
+        BTreeSet::from([
+            "device/child2//",
+            "device/child20//",
+            "device/child21//",
+            "device/child22//",
+        ]);
+        "external_id_prefix"
+    )]
+    #[test_case(
+        ListFilters::default().external_id("gateway:child2?"),
+        BTreeSet::from([
+            "device/child20//",
+            "device/child21//",
+            "device/child22//",
+        ]);
+        "external_id_single_char_wildcard"
+    )]
+    #[test_case(
+        ListFilters::default().twin("maintenanceMode", "true"),
+        BTreeSet::from([
+            "device/child20//",
+            "device/child22//",
+        ]);
+        "boolean_twin_value"
+    )]
+    #[test_case(
+        ListFilters::default().twin("hardware.model", "rpi4"),
+        BTreeSet::from([
+            "device/child21//",
+            "device/child22//",
+            "device/child3//",
+        ]);
+        "nested_twin_value"
+    )]
+    #[test_case(
+        ListFilters::default()
+            .twin("hardware.model", "rpi4")
+            .twin("maintenanceMode", "true"),
+        BTreeSet::from([
+            "device/child22//",
+        ]);
+        "all_twin_filters_must_match"
+    )]
+    #[test_case(
+        ListFilters::default()
+            .parent("device/child2//".parse().unwrap())
+            .twin("hardware", r#"{"model":"rpi3"}"#),
+        BTreeSet::from([
+            "device/child20//",
+        ]);
+        "object_twin_value"
+    )]
+    fn list_entities_with_external_id_and_twin_filters(
+        filters: ListFilters,
+        expected: BTreeSet<&str>,
+    ) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = new_entity_store(&temp_dir, true);
+
+        register(
+            &mut store,
+            "device/child2//",
+            json!({"@type": "child-device", "@id": "gateway:child2"}),
+        );
+        register(
+            &mut store,
+            "device/child20//",
+            json!({
+                "@type": "child-device",
+                "@parent": "device/child2//",
+                "@id": "gateway:child20",
+                "maintenanceMode": true,
+                "hardware": {"model": "rpi3"},
+            }),
+        );
+        register(
+            &mut store,
+            "device/child21//",
+            json!({
+                "@type": "child-device",
+                "@parent": "device/child2//",
+                "@id": "gateway:child21",
+                "maintenanceMode": false,
+                "hardware": {"model": "rpi4"},
+            }),
+        );
+        register(
+            &mut store,
+            "device/child22//",
+            json!({
+                "@type": "child-device",
+                "@parent": "device/child2//",
+                "@id": "gateway:child22",
+                "maintenanceMode": true,
+                "hardware": {"model": "rpi4"},
+            }),
+        );
+        register(
+            &mut store,
+            "device/child3//",
+            json!({
+                "@type": "child-device",
+                "@id": "other:child3",
+                "hardware": {"model": "rpi4"},
+            }),
+        );
+
+        let entities: BTreeSet<&str> = list_entity_tree_topics(&mut store, filters);
+        assert_eq!(entities, expected);
+    }
+
+    #[test]
+    fn list_entities_page_by_page() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut store = new_entity_store(&temp_dir, true);
+        build_test_entity_tree(&mut store);
+
+        let filters = || ListFilters::default().r#type(EntityType::ChildDevice);
+        let mut pages = vec![];
+        let mut cursor = None;
+        loop {
+            let page = store.list_entity_page(filters(), cursor.as_ref(), 4);
+            pages.push(
+                page.entities
+                    .iter()
+                    .map(|e| e.topic_id.to_string())
+                    .collect::<Vec<_>>(),
+            );
+            match page.next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(
+            pages,
+            vec![
+                vec![
+                    "device/child0//",
+                    "device/child00//",
+                    "device/child000//",
+                    "device/child1//"
+                ],
+                vec![
+                    "device/child2//",
+                    "device/child20//",
+                    "device/child21//",
+                    "device/child210//"
+                ],
+                vec![
+                    "device/child2100//",
+                    "device/child211//",
+                    "device/child22//"
+                ],
+            ]
+        );
+    }
+
     /// Build the test entity tree:
     ///
     /// main
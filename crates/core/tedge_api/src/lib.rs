@@ -2,6 +2,7 @@ pub mod alarm;
 pub mod commands;
 pub mod device_profile;
 pub mod entity;
+pub mod entity_manifest;
 pub mod entity_store;
 pub mod error;
 pub mod event;
@@ -14,6 +15,7 @@ pub mod script;
 mod software;
 pub mod store;
 pub mod substitution;
+pub mod twin;
 pub mod workflow;
 
 pub use commands::CommandStatus;
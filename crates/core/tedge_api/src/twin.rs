@@ -0,0 +1,102 @@
+//! Helpers to synchronize the twin data of the entities with the device twins of a cloud
+//!
+//! The twin data of the main device are mapped onto the device twin of the cloud device,
+//! while those of the other entities are grouped under a name derived from the entity topic id,
+//! e.g. `device:child1` for `device/child1//`.
+use crate::mqtt_topics::EntityTopicId;
+use serde_json::Value;
+
+/// The name under which the twin data of an entity are grouped, the main device having none
+pub fn entity_twin_name(entity: &EntityTopicId) -> Option<String> {
+    if entity.is_default_main_device() {
+        return None;
+    }
+    let name = entity
+        .as_str()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>()
+        .join(":");
+    Some(name)
+}
+
+/// The entity of a twin name, if this is the name of an entity other than the main device
+///
+/// The names which are not derived from a `device/...` topic id are not related to an entity.
+pub fn entity_of_twin_name(name: &str) -> Option<EntityTopicId> {
+    if !name.starts_with("device:") {
+        return None;
+    }
+    name.replace(':', "/").parse().ok()
+}
+
+/// Check that a key can be used as a twin fragment key, i.e. as an MQTT topic segment
+pub fn is_valid_fragment_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains(['/', '+', '#'])
+}
+
+/// Merge a JSON patch into a value, a `null` property of the patch removing the property
+pub fn merge(value: &mut Value, patch: Value) {
+    match (value, patch) {
+        (Value::Object(object), Value::Object(patch)) => {
+            for (key, patch) in patch {
+                if patch.is_null() {
+                    object.remove(&key);
+                } else {
+                    merge(object.entry(key).or_insert(Value::Null), patch);
+                }
+            }
+        }
+        (value, patch) => *value = patch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn twin_names_are_derived_from_entity_topic_ids() {
+        for (topic_id, name) in [
+            ("device/child1//", "device:child1"),
+            (
+                "device/main/service/collectd",
+                "device:main:service:collectd",
+            ),
+        ] {
+            let entity: EntityTopicId = topic_id.parse().unwrap();
+            assert_eq!(entity_twin_name(&entity), Some(name.to_string()));
+            assert_eq!(entity_of_twin_name(name), Some(entity));
+        }
+
+        assert_eq!(
+            entity_twin_name(&EntityTopicId::default_main_device()),
+            None
+        );
+        assert_eq!(entity_of_twin_name("config"), None);
+    }
+
+    #[test]
+    fn fragment_keys_are_checked() {
+        assert!(is_valid_fragment_key("hardware"));
+        assert!(!is_valid_fragment_key(""));
+        assert!(!is_valid_fragment_key("a/b"));
+        assert!(!is_valid_fragment_key("c+"));
+        assert!(!is_valid_fragment_key("#"));
+    }
+
+    #[test]
+    fn patches_are_merged_recursively() {
+        let mut value =
+            json!({"interval": 10, "unit": "s", "log": {"level": "info", "debug": true}});
+        merge(
+            &mut value,
+            json!({"interval": 60, "unit": null, "log": {"debug": null}}),
+        );
+        assert_eq!(value, json!({"interval": 60, "log": {"level": "info"}}));
+
+        merge(&mut value, json!("replaced"));
+        assert_eq!(value, json!("replaced"));
+    }
+}
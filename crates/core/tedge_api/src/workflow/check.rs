@@ -0,0 +1,527 @@
+use crate::mqtt_topics::OperationType;
+use crate::workflow::toml_config::TomlExitHandlers;
+use crate::workflow::toml_config::TomlOperationState;
+use crate::workflow::DefaultHandlers;
+use crate::workflow::OperationAction;
+use crate::workflow::OperationWorkflow;
+use crate::workflow::StateName;
+use crate::workflow::WorkflowDefinitionError;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+/// The keys of the default handlers that can be defined at the workflow level
+const WORKFLOW_HANDLERS: [&str; 10] = [
+    "on_success",
+    "on_error",
+    "on_kill",
+    "on_exit",
+    "timeout_second",
+    "on_timeout",
+    "on_stdout",
+    "on_exec",
+    "on_next",
+    "on_partial",
+];
+
+/// Severity of a workflow issue
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum IssueSeverity {
+    Error,
+    Warning,
+}
+
+/// An issue found while checking a workflow definition
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WorkflowIssue {
+    pub severity: IssueSeverity,
+    pub message: String,
+
+    /// The state where the issue has been found, if any
+    pub state: Option<StateName>,
+
+    /// The key of the state definition where the issue has been found, if any
+    pub key: Option<String>,
+
+    /// The faulty text, if any
+    pub excerpt: Option<String>,
+}
+
+impl WorkflowIssue {
+    fn error(message: impl Into<String>) -> Self {
+        WorkflowIssue {
+            severity: IssueSeverity::Error,
+            message: message.into(),
+            state: None,
+            key: None,
+            excerpt: None,
+        }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        WorkflowIssue {
+            severity: IssueSeverity::Warning,
+            ..WorkflowIssue::error(message)
+        }
+    }
+
+    fn on_state(self, state: &str) -> Self {
+        WorkflowIssue {
+            state: Some(state.to_string()),
+            ..self
+        }
+    }
+
+    fn on_key(self, key: &str) -> Self {
+        WorkflowIssue {
+            key: Some(key.to_string()),
+            ..self
+        }
+    }
+
+    fn with_excerpt(self, excerpt: &str) -> Self {
+        WorkflowIssue {
+            excerpt: Some(excerpt.to_string()),
+            ..self
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == IssueSeverity::Error
+    }
+}
+
+/// Check a workflow definition, as parsed from its TOML file, reporting all the issues found
+///
+/// In contrast to the agent, which rejects a workflow on the first error,
+/// the states are checked one by one and the whole state machine is analyzed for:
+/// - ill-defined states and unknown actions
+/// - transitions to undefined states
+/// - states unreachable from the `init` state
+/// - dead ends, i.e. states from where no terminal state can be reached
+/// - missing `successful` and `failed` terminal states
+/// - invalid `${...}` path expressions
+pub fn check_workflow(definition: &Map<String, Value>) -> Vec<WorkflowIssue> {
+    let mut issues = Vec::new();
+
+    let mut workflow_handlers = Map::new();
+    let mut state_definitions = BTreeMap::new();
+    for (key, value) in definition {
+        if value.is_object() && !WORKFLOW_HANDLERS.contains(&key.as_str()) {
+            state_definitions.insert(key.clone(), value);
+        } else {
+            workflow_handlers.insert(key.clone(), value.clone());
+        }
+    }
+
+    let operation = match definition.get("operation") {
+        Some(Value::String(operation)) => Some(OperationType::from(operation.as_str())),
+        Some(_) => {
+            issues.push(
+                WorkflowIssue::error("The operation name must be a string").on_key("operation"),
+            );
+            None
+        }
+        None => {
+            issues.push(WorkflowIssue::error("Missing operation name"));
+            None
+        }
+    };
+
+    let default_handlers = TomlExitHandlers::deserialize(Value::Object(workflow_handlers))
+        .map_err(|err| err.to_string())
+        .and_then(|handlers| DefaultHandlers::try_from(handlers).map_err(|err| err.to_string()))
+        .unwrap_or_else(|err| {
+            issues.push(WorkflowIssue::error(format!(
+                "Invalid workflow handlers: {err}"
+            )));
+            DefaultHandlers::default()
+        });
+
+    // The states that cannot be analyzed, because ill-defined
+    let mut ill_defined = BTreeSet::new();
+    let mut states = HashMap::new();
+    for (state, definition) in state_definitions.iter() {
+        check_path_expressions(&mut issues, state, None, definition);
+
+        let action = TomlOperationState::deserialize((*definition).clone())
+            .map_err(|err| WorkflowIssue::error(err.to_string()))
+            .and_then(|spec| {
+                OperationAction::try_from((spec, default_handlers.clone()))
+                    .map_err(|err| definition_issue(err, definition))
+            });
+        match action {
+            Ok(action) => {
+                states.insert(state.clone(), action);
+            }
+            Err(issue) => {
+                issues.push(issue.on_state(state));
+                ill_defined.insert(state.clone());
+            }
+        }
+    }
+
+    for terminal in ["successful", "failed"] {
+        if !state_definitions.contains_key(terminal) {
+            issues.push(WorkflowIssue::warning(format!(
+                "Missing `{terminal}` terminal state: a `cleanup` action is implied"
+            )));
+        }
+    }
+
+    // Check the workflow as a whole, unless the init state has already been reported as ill-defined
+    if let Some(operation) = operation.filter(|_| !ill_defined.contains("init")) {
+        if let Err(err) = OperationWorkflow::try_new(operation, default_handlers, states.clone()) {
+            issues.push(definition_issue(err, &Value::Null));
+        }
+    }
+
+    check_transitions(&mut issues, states, &ill_defined);
+    issues
+}
+
+/// Convert a workflow definition error into an issue, extracting the faulty text
+fn definition_issue(err: WorkflowDefinitionError, definition: &Value) -> WorkflowIssue {
+    let issue = WorkflowIssue::error(err.to_string());
+    match err {
+        WorkflowDefinitionError::UnknownAction { action } => {
+            let key = ["action", "operation"]
+                .into_iter()
+                .find(|key| definition.get(key).and_then(Value::as_str) == Some(action.as_str()))
+                .unwrap_or("action");
+            issue.on_key(key).with_excerpt(&action)
+        }
+        WorkflowDefinitionError::InvalidPathExpression(path) => issue.with_excerpt(&path),
+        WorkflowDefinitionError::MissingParallelItems { .. } => issue.on_key("parallel"),
+        WorkflowDefinitionError::InvalidMaxConcurrency { .. } => issue.on_key("max_concurrency"),
+        WorkflowDefinitionError::InvalidBuiltinOperation {
+            builtin_operation, ..
+        } => issue
+            .on_key("operation")
+            .with_excerpt(&format!("builtin:{builtin_operation}")),
+        WorkflowDefinitionError::InvalidAction { state, .. } => issue.on_state(&state),
+        _ => issue,
+    }
+}
+
+/// Check the transitions between states
+fn check_transitions(
+    issues: &mut Vec<WorkflowIssue>,
+    mut states: HashMap<StateName, OperationAction>,
+    ill_defined: &BTreeSet<StateName>,
+) {
+    for terminal in ["successful", "failed"] {
+        if !ill_defined.contains(terminal) {
+            states
+                .entry(terminal.to_string())
+                .or_insert(OperationAction::Clear);
+        }
+    }
+    let is_defined = |state: &str| states.contains_key(state) || ill_defined.contains(state);
+
+    let mut names: Vec<&StateName> = states.keys().collect();
+    names.sort();
+    for state in names.iter() {
+        for (handler, target) in states[*state].next_states() {
+            if !is_defined(&target) {
+                let key = handler.split('.').next().unwrap_or(&handler);
+                issues.push(
+                    WorkflowIssue::error(format!("Transition to an undefined state: `{target}`"))
+                        .on_state(state)
+                        .on_key(key)
+                        .with_excerpt(&target),
+                );
+            }
+        }
+    }
+
+    // States which next states are unknown
+    let is_opaque = |state: &str| {
+        ill_defined.contains(state)
+            || states
+                .get(state)
+                .is_some_and(OperationAction::next_state_from_stdout)
+    };
+
+    // Forward analysis from the init state
+    let mut reachable = BTreeSet::new();
+    let mut opaque_reached = false;
+    let mut queue = VecDeque::from(["init".to_string()]);
+    while let Some(state) = queue.pop_front() {
+        if !is_defined(&state) || !reachable.insert(state.clone()) {
+            continue;
+        }
+        opaque_reached |= is_opaque(&state);
+        if let Some(action) = states.get(&state) {
+            queue.extend(action.next_states().into_iter().map(|(_, target)| target));
+        }
+    }
+
+    if !opaque_reached && reachable.contains("init") {
+        for state in names.iter() {
+            if !reachable.contains(*state) && !["successful", "failed"].contains(&state.as_str()) {
+                issues.push(
+                    WorkflowIssue::warning(
+                        "Unreachable state: no transition leads to this state from `init`",
+                    )
+                    .on_state(state),
+                );
+            }
+        }
+        if !reachable.contains("successful") {
+            issues.push(WorkflowIssue::error(
+                "The `successful` terminal state cannot be reached from `init`",
+            ));
+        }
+    }
+
+    // Backward analysis from the terminal states,
+    // assuming a terminal state can be reached from any state which next states are unknown
+    let mut live: BTreeSet<&str> = names
+        .iter()
+        .map(|state| state.as_str())
+        .filter(|state| states[*state] == OperationAction::Clear || is_opaque(state))
+        .chain(ill_defined.iter().map(|state| state.as_str()))
+        .collect();
+    loop {
+        let more: Vec<&str> = names
+            .iter()
+            .map(|state| state.as_str())
+            .filter(|state| !live.contains(state))
+            .filter(|state| {
+                is_opaque(state)
+                    || states[*state]
+                        .next_states()
+                        .iter()
+                        .any(|(_, target)| live.contains(target.as_str()))
+            })
+            .collect();
+        if more.is_empty() {
+            break;
+        }
+        live.extend(more);
+    }
+    for state in names.iter() {
+        if reachable.contains(*state) && !live.contains(state.as_str()) {
+            issues.push(
+                WorkflowIssue::error("Dead end: no terminal state can be reached from this state")
+                    .on_state(state),
+            );
+        }
+    }
+}
+
+/// Check all the `${...}` path expressions used in a state definition
+fn check_path_expressions(
+    issues: &mut Vec<WorkflowIssue>,
+    state: &str,
+    key: Option<&str>,
+    value: &Value,
+) {
+    match value {
+        Value::String(text) => {
+            for expression in invalid_path_expressions(text) {
+                let issue = WorkflowIssue::error(format!(
+                    "Invalid path expression: `{expression}`, expecting `${{.payload.<path>}}` or `${{.topic.<field>}}`"
+                ))
+                .on_state(state)
+                .with_excerpt(&expression);
+                issues.push(match key {
+                    Some(key) => issue.on_key(key),
+                    None => issue,
+                })
+            }
+        }
+        Value::Array(values) => {
+            for value in values {
+                check_path_expressions(issues, state, key, value)
+            }
+        }
+        Value::Object(values) => {
+            for (sub_key, value) in values {
+                check_path_expressions(issues, state, Some(key.unwrap_or(sub_key)), value)
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Extract from a text the path expressions which cannot be resolved against a command state
+///
+/// Only the expressions referring to the command state are checked,
+/// i.e. `${.some.path}`, `${payload.some.path}` or `${topic.some.path}`,
+/// the other expressions being possibly shell variables, as `${HOME}`.
+fn invalid_path_expressions(text: &str) -> Vec<String> {
+    let mut invalid = Vec::new();
+    let mut remaining = text;
+    while let Some(start) = remaining.find("${") {
+        let expression = &remaining[start..];
+        let (expression, path) = match expression.find('}') {
+            Some(end) => (&expression[..=end], Some(&expression[2..end])),
+            None => (expression, None),
+        };
+        remaining = &remaining[start + expression.len()..];
+
+        match path {
+            Some(path) if is_valid_path(path) => {}
+            Some(path)
+                if !path.starts_with('.')
+                    && !path.starts_with("payload")
+                    && !path.starts_with("topic") => {}
+            None if !expression.starts_with("${.") => {}
+            _ => invalid.push(expression.to_string()),
+        }
+    }
+    invalid
+}
+
+/// Check that a path can be resolved against a command state, using [crate::substitution::Record::extract_value]
+fn is_valid_path(path: &str) -> bool {
+    match path {
+        "." | ".topic" | ".topic.root_prefix" | ".topic.target" | ".topic.operation"
+        | ".topic.cmd_id" | ".payload" => true,
+        path => path.strip_prefix(".payload.").is_some_and(|fields| {
+            fields
+                .split('.')
+                .all(|field| !field.is_empty() && !field.contains(char::is_whitespace))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn check(definition: Value) -> Vec<WorkflowIssue> {
+        check_workflow(definition.as_object().unwrap())
+    }
+
+    fn messages(issues: &[WorkflowIssue]) -> Vec<(Option<&str>, &str)> {
+        issues
+            .iter()
+            .map(|issue| (issue.state.as_deref(), issue.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn a_well_defined_workflow_has_no_issues() {
+        let issues = check(json!({
+            "operation": "check",
+            "init": { "action": "proceed", "on_success": "executing" },
+            "executing": {
+                "script": "/some/script.sh ${.payload.x} ${.topic.target}",
+                "on_success": "successful",
+                "on_error": { "status": "failed", "reason": "script failed" }
+            },
+            "successful": { "action": "cleanup" },
+            "failed": { "action": "cleanup" },
+        }));
+        assert_eq!(issues, vec![]);
+    }
+
+    #[test]
+    fn report_unknown_actions_and_undefined_states() {
+        let issues = check(json!({
+            "operation": "check",
+            "init": { "action": "proceed", "on_success": "executing" },
+            "executing": { "action": "do-something", "on_success": "successful" },
+            "next": { "script": "/some/script.sh", "on_success": "unknown" },
+            "successful": { "action": "cleanup" },
+            "failed": { "action": "cleanup" },
+        }));
+        let executing = issues
+            .iter()
+            .find(|issue| issue.state.as_deref() == Some("executing"))
+            .unwrap();
+        assert_eq!(executing.key.as_deref(), Some("action"));
+        assert_eq!(executing.excerpt.as_deref(), Some("do-something"));
+
+        let next = issues
+            .iter()
+            .find(|issue| issue.excerpt.as_deref() == Some("unknown"))
+            .unwrap();
+        assert_eq!(next.state.as_deref(), Some("next"));
+        assert_eq!(next.key.as_deref(), Some("on_success"));
+    }
+
+    #[test]
+    fn report_unreachable_states_and_dead_ends() {
+        let issues = check(json!({
+            "operation": "check",
+            "init": { "action": "proceed", "on_success": "executing" },
+            "executing": { "action": "proceed", "on_success": "loop" },
+            "loop": { "action": "proceed", "on_success": "executing" },
+            "orphan": { "action": "proceed", "on_success": "successful" },
+            "successful": { "action": "cleanup" },
+            "failed": { "action": "cleanup" },
+        }));
+        let messages = messages(&issues);
+        assert!(messages.contains(&(
+            Some("orphan"),
+            "Unreachable state: no transition leads to this state from `init`"
+        )));
+        assert!(messages.contains(&(
+            None,
+            "The `successful` terminal state cannot be reached from `init`"
+        )));
+        for state in ["init", "executing", "loop"] {
+            assert!(messages.contains(&(
+                Some(state),
+                "Dead end: no terminal state can be reached from this state"
+            )));
+        }
+    }
+
+    #[test]
+    fn report_missing_terminal_states() {
+        let issues = check(json!({
+            "operation": "check",
+            "init": { "action": "proceed", "on_success": "successful" },
+        }));
+        assert_eq!(
+            messages(&issues),
+            vec![
+                (
+                    None,
+                    "Missing `successful` terminal state: a `cleanup` action is implied"
+                ),
+                (
+                    None,
+                    "Missing `failed` terminal state: a `cleanup` action is implied"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_invalid_path_expressions() {
+        let issues = check(json!({
+            "operation": "check",
+            "init": {
+                "script": "/some/script.sh ${.payload.x} ${payload.y} ${.topic.unknown} ${HOME}",
+                "input": { "z": "${.payload..z}" },
+                "on_success": "successful",
+            },
+            "successful": { "action": "cleanup" },
+            "failed": { "action": "cleanup" },
+        }));
+        let mut excerpts: Vec<_> = issues
+            .iter()
+            .map(|issue| (issue.key.as_deref(), issue.excerpt.as_deref()))
+            .collect();
+        excerpts.sort();
+        assert_eq!(
+            excerpts,
+            vec![
+                (Some("input"), Some("${.payload..z}")),
+                (Some("script"), Some("${.topic.unknown}")),
+                (Some("script"), Some("${payload.y}")),
+            ]
+        );
+    }
+}
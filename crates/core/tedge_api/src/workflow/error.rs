@@ -21,6 +21,12 @@ pub enum WorkflowDefinitionError {
     #[error("The provided target {0} is not a valid path expression")]
     InvalidPathExpression(String),
 
+    #[error("No items are provided for the parallel `{operation}` sub-operations")]
+    MissingParallelItems { operation: String },
+
+    #[error("The maximum number of concurrent `{operation}` sub-operations must be positive")]
+    InvalidMaxConcurrency { operation: String },
+
     #[error("The `builtin:{builtin_operation}` cannot be invoked from `{main_operation}`, but only from `{builtin_operation}`")]
     InvalidBuiltinOperation {
         main_operation: String,
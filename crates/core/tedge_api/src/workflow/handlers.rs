@@ -1,5 +1,6 @@
 use crate::workflow::GenericStateUpdate;
 use crate::workflow::ScriptDefinitionError;
+use crate::workflow::StateName;
 use serde_json::json;
 use serde_json::Value;
 use std::cmp::max;
@@ -83,6 +84,46 @@ impl ExitHandlers {
         })
     }
 
+    /// The states to which a command can move on the script outcome, along with the handler names
+    ///
+    /// Any unexpected exit code moves the command to the `failed` state, if not handled by `on_error`.
+    pub fn next_states(&self) -> Vec<(String, StateName)> {
+        let mut states = Vec::new();
+        if let Some(update) = &self.on_success {
+            states.push(("on_success".to_string(), update.status.clone()));
+        }
+        for state in self.on_stdout.iter() {
+            states.push(("on_stdout".to_string(), state.clone()));
+        }
+        for (from, to, update) in self.on_exit.iter() {
+            if *from == 0 {
+                // Already given by on_success
+                continue;
+            }
+            let codes = if from == to {
+                from.to_string()
+            } else {
+                format!("{from}-{to}")
+            };
+            states.push((format!("on_exit.{codes}"), update.status.clone()));
+        }
+        let on_error = self
+            .on_error
+            .as_ref()
+            .map(|update| update.status.clone())
+            .unwrap_or_else(|| "failed".to_string());
+        states.push(("on_error".to_string(), on_error));
+        if let Some(update) = &self.on_kill {
+            states.push(("on_kill".to_string(), update.status.clone()));
+        }
+        states
+    }
+
+    /// Return true if the next state is freely chosen by the script, returning a status on stdout
+    pub fn next_state_from_stdout(&self) -> bool {
+        self.on_success.is_none() && self.on_stdout.is_empty()
+    }
+
     pub fn state_update(
         &self,
         program: &str,
@@ -179,11 +220,7 @@ impl ExitHandlers {
         })
     }
 
-    pub(crate) fn state_update_on_result(
-        &self,
-        action: &str,
-        result: Result<Value, String>,
-    ) -> Value {
+    pub(crate) fn state_update_on_result(&self, action: &str, result: Result<Value, String>) -> Value {
         match result {
             Ok(json) => self
                 .on_success
@@ -329,6 +366,16 @@ impl IterateHandlers {
     }
 }
 
+/// Define state transition on the joint outcome of parallel sub-operations
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct JoinHandlers {
+    pub timeout: Option<Duration>,
+    pub on_success: GenericStateUpdate,
+    pub on_partial: GenericStateUpdate,
+    pub on_error: GenericStateUpdate,
+    pub on_timeout: GenericStateUpdate,
+}
+
 /// Define default handlers for all state of an operation workflow
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct DefaultHandlers {
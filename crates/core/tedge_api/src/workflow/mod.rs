@@ -1,7 +1,11 @@
+pub mod check;
 pub mod error;
 pub mod handlers;
 pub mod log;
 mod on_disk;
+pub mod parallel;
+pub mod schedule;
+pub mod simulation;
 pub mod state;
 pub mod supervisor;
 mod toml_config;
@@ -11,10 +15,13 @@ use crate::mqtt_topics::MqttSchema;
 use crate::mqtt_topics::OperationType;
 use crate::script::ShellScript;
 use crate::substitution::Record;
+pub use check::*;
 pub use error::*;
 pub use handlers::*;
 use mqtt_channel::MqttMessage;
 use mqtt_channel::QoS;
+pub use parallel::*;
+pub use schedule::*;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::json;
@@ -205,6 +212,41 @@ pub enum OperationAction {
     /// on_error = "failed"
     /// ```
     Iterate(JsonPath, IterateHandlers),
+
+    /// Trigger a sub-operation for each item of the specified array in the state payload,
+    /// and move to the next state from where these sub-operations will be joined.
+    ///
+    /// Each item must be a JSON object whose fields are added to the sub-operation init payload.
+    /// The optional `@target` field of an item gives the topic id of the entity
+    /// on which the sub-operation has to be executed, the current entity being used by default.
+    /// At most `max_concurrency` sub-operations are running at the same time.
+    ///
+    /// ```toml
+    /// parallel = "firmware_update"
+    /// items = "${.payload.devices}"
+    /// max_concurrency = 5
+    /// input.url = "${.payload.url}"
+    /// on_exec = "awaiting_sub_operations"
+    /// ```
+    Parallel(
+        OperationName,
+        JsonPath,
+        Option<usize>,
+        StateExcerpt,
+        ExecHandlers,
+    ),
+
+    /// Await the completion of the sub-operations triggered by a parallel action
+    ///
+    /// The outcomes of the sub-operations are collected into the command state.
+    ///
+    /// ```toml
+    /// action = "join"
+    /// on_success = "<state>"
+    /// on_partial = "<state>"
+    /// on_error = "<state>"
+    /// ```
+    Join(JoinHandlers),
 }
 
 impl Display for OperationAction {
@@ -237,6 +279,10 @@ impl Display for OperationAction {
             OperationAction::Iterate(json_path, _) => {
                 format!("iterate over {json_path}").to_string()
             }
+            OperationAction::Parallel(operation, json_path, _, _, _) => {
+                format!("execute {operation} as parallel sub-operations over {json_path}")
+            }
+            OperationAction::Join(_) => "await parallel sub-operations completion".to_string(),
         };
         f.write_str(&str)
     }
@@ -427,10 +473,77 @@ impl OperationAction {
                     handlers.clone(),
                 )
             }
+            OperationAction::Parallel(operation_expr, json_path, max, input, handlers) => {
+                let operation = state.inject_values_into_template(operation_expr);
+                OperationAction::Parallel(
+                    operation,
+                    json_path.clone(),
+                    *max,
+                    input.clone(),
+                    handlers.clone(),
+                )
+            }
             _ => self.clone(),
         }
     }
 
+    /// The states to which a command can move from this action, along with the handler names
+    pub fn next_states(&self) -> Vec<(String, StateName)> {
+        fn on_exec(handlers: &ExecHandlers) -> Vec<(String, StateName)> {
+            vec![("on_exec".to_string(), handlers.on_exec.status.clone())]
+        }
+        fn on_completion(handlers: &AwaitHandlers) -> Vec<(String, StateName)> {
+            let mut states = vec![
+                ("on_success".to_string(), handlers.on_success.status.clone()),
+                ("on_error".to_string(), handlers.on_error.status.clone()),
+            ];
+            if handlers.timeout.is_some() {
+                states.push(("on_timeout".to_string(), handlers.on_timeout.status.clone()));
+            }
+            states
+        }
+
+        match self {
+            OperationAction::MoveTo(update) => {
+                vec![("on_success".to_string(), update.status.clone())]
+            }
+            OperationAction::BuiltIn(exec_handlers, await_handlers) => {
+                let mut states = on_exec(exec_handlers);
+                states.extend(on_completion(await_handlers));
+                states
+            }
+            OperationAction::AwaitingAgentRestart(handlers)
+            | OperationAction::AwaitOperationCompletion(handlers, _) => on_completion(handlers),
+            OperationAction::RestartAgent(handlers)
+            | OperationAction::BgScript(_, handlers)
+            | OperationAction::Operation(_, _, _, handlers)
+            | OperationAction::BuiltInOperation(_, handlers)
+            | OperationAction::Parallel(_, _, _, _, handlers) => on_exec(handlers),
+            OperationAction::Script(_, handlers)
+            | OperationAction::Download(_, handlers)
+            | OperationAction::BuiltInOperationStep(_, _, _, handlers) => handlers.next_states(),
+            OperationAction::Clear => vec![],
+            OperationAction::Iterate(_, handlers) => vec![
+                ("on_next".to_string(), handlers.on_next.status.clone()),
+                ("on_success".to_string(), handlers.on_success.status.clone()),
+                ("on_error".to_string(), handlers.on_error.status.clone()),
+            ],
+            OperationAction::Join(handlers) => vec![
+                ("on_success".to_string(), handlers.on_success.status.clone()),
+                ("on_partial".to_string(), handlers.on_partial.status.clone()),
+                ("on_error".to_string(), handlers.on_error.status.clone()),
+            ],
+        }
+    }
+
+    /// Return true if the next state is freely chosen by a script
+    pub fn next_state_from_stdout(&self) -> bool {
+        match self {
+            OperationAction::Script(_, handlers) => handlers.next_state_from_stdout(),
+            _ => false,
+        }
+    }
+
     pub fn process_iterate(
         state: GenericCommandState,
         json_path: &str,
@@ -0,0 +1,597 @@
+use crate::mqtt_topics::Channel;
+use crate::mqtt_topics::EntityTopicId;
+use crate::mqtt_topics::MqttSchema;
+use crate::mqtt_topics::OperationType;
+use crate::substitution::Record;
+use crate::workflow::GenericCommandState;
+use crate::workflow::GenericStateUpdate;
+use crate::workflow::JoinHandlers;
+use crate::workflow::OperationName;
+use mqtt_channel::Topic;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+
+/// The property of a command state where are recorded the sub-commands started by a parallel action
+const PARALLEL: &str = "@parallel";
+
+/// The property of a parallel item telling on which entity the sub-command has to be executed
+const TARGET: &str = "@target";
+
+/// The status of a sub-command that has not been started yet
+const PENDING: &str = "pending";
+
+/// The sub-commands started by a parallel action, as recorded in the invoking command state
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ParallelTasks {
+    /// The operation of the sub-commands
+    pub operation: OperationName,
+
+    /// The maximum number of sub-commands running concurrently, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
+
+    pub tasks: Vec<ParallelTask>,
+}
+
+/// A sub-command started by a parallel action
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ParallelTask {
+    /// The command topic of the sub-command
+    pub topic: String,
+
+    /// The latest known status of the sub-command, `pending` till started
+    pub status: String,
+
+    /// The init payload of the sub-command, till started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init: Option<Value>,
+
+    /// The final payload of the sub-command, once finished
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+}
+
+impl ParallelTask {
+    pub(crate) fn is_pending(&self) -> bool {
+        self.status == PENDING
+    }
+
+    pub(crate) fn is_successful(&self) -> bool {
+        self.status == "successful"
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.is_successful() || self.status == "failed"
+    }
+}
+
+/// What has to be done by a join step
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum JoinStep {
+    /// Some sub-commands are still running: nothing to do
+    Wait,
+
+    /// New sub-commands have to be started, the invoking command state being updated accordingly
+    Start(GenericCommandState, Vec<GenericCommandState>),
+
+    /// All the sub-commands are finished and have to be cleared,
+    /// the invoking command moving to its next state
+    Done(GenericCommandState, Vec<GenericCommandState>),
+}
+
+#[derive(thiserror::Error, Debug, Eq, PartialEq)]
+pub enum ParallelError {
+    #[error("No array found at {0}")]
+    InvalidItems(String),
+
+    #[error("Invalid parallel item #{index}: {reason}")]
+    InvalidItem { index: usize, reason: String },
+}
+
+impl GenericCommandState {
+    /// Check if a command id is the id of a sub-command started by a parallel action
+    pub fn is_parallel_task_id(cmd_id: &str) -> bool {
+        cmd_id.starts_with("par:")
+    }
+
+    fn parallel_task_id(index: usize, operation: &OperationType, cmd_id: &str) -> String {
+        format!("par:{index}:{operation}:{cmd_id}")
+    }
+
+    /// Return the `{operation}:{cmd_id}` key of a command that started parallel sub-commands
+    pub(crate) fn parallel_command_key(&self) -> Option<String> {
+        self.payload.get(PARALLEL)?;
+        Some(format!("{}:{}", self.operation()?, self.cmd_id()?))
+    }
+
+    /// Return the `{operation}:{cmd_id}` key of the command that started a parallel sub-command
+    pub(crate) fn parallel_task_command_key(task_topic: &str) -> Option<&str> {
+        let task_id = task_topic.rsplit('/').next()?;
+        let (_, key) = task_id.strip_prefix("par:")?.split_once(':')?;
+        Some(key)
+    }
+
+    /// Prepare a sub-command for each item of the array at the given path,
+    /// recording these sub-commands as pending in the command state.
+    ///
+    /// The init state of each sub-command is made of the given input and of the item fields,
+    /// the `@target` field, if any, telling on which entity the sub-command is executed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn fan_out(
+        self,
+        schema: &MqttSchema,
+        entity: &EntityTopicId,
+        operation: &OperationType,
+        cmd_id: &str,
+        sub_operation: &str,
+        items_path: &str,
+        input: Value,
+        max_concurrency: Option<usize>,
+    ) -> Result<Self, ParallelError> {
+        let Some(items) = self
+            .extract_value(items_path)
+            .and_then(|v| v.as_array().cloned())
+        else {
+            return Err(ParallelError::InvalidItems(items_path.to_string()));
+        };
+
+        let mut tasks = Vec::with_capacity(items.len());
+        for (index, item) in items.into_iter().enumerate() {
+            let Value::Object(mut item) = item else {
+                return Err(ParallelError::InvalidItem {
+                    index,
+                    reason: "not a JSON object".to_string(),
+                });
+            };
+            let target = match item.remove(TARGET) {
+                None => entity.clone(),
+                Some(Value::String(target)) => {
+                    target.parse().map_err(|err| ParallelError::InvalidItem {
+                        index,
+                        reason: format!("invalid {TARGET}: {err}"),
+                    })?
+                }
+                Some(_) => {
+                    return Err(ParallelError::InvalidItem {
+                        index,
+                        reason: format!("{TARGET} is not a string"),
+                    })
+                }
+            };
+            let topic = schema.topic_for(
+                &target,
+                &Channel::Command {
+                    operation: OperationType::Custom(sub_operation.to_string()),
+                    cmd_id: Self::parallel_task_id(index, operation, cmd_id),
+                },
+            );
+
+            let mut init = GenericStateUpdate::init_payload();
+            for values in [&input, &Value::Object(item)] {
+                if let (Some(init), Some(values)) = (init.as_object_mut(), values.as_object()) {
+                    init.extend(values.clone());
+                }
+            }
+            init["status"] = json!("init");
+
+            tasks.push(ParallelTask {
+                topic: topic.name,
+                status: PENDING.to_string(),
+                init: Some(init),
+                result: None,
+            });
+        }
+
+        Ok(self.with_parallel_tasks(&ParallelTasks {
+            operation: sub_operation.to_string(),
+            max_concurrency,
+            tasks,
+        }))
+    }
+
+    /// Return the sub-commands started by a parallel action, if any
+    pub fn parallel_tasks(&self) -> Option<ParallelTasks> {
+        self.payload
+            .get(PARALLEL)
+            .and_then(|tasks| serde_json::from_value(tasks.clone()).ok())
+    }
+
+    fn with_parallel_tasks(self, tasks: &ParallelTasks) -> Self {
+        self.update_with_json(json!({ PARALLEL: tasks }))
+    }
+
+    /// Return the topics of the sub-commands started by this command and not finished yet
+    pub fn running_parallel_tasks(&self) -> Vec<String> {
+        self.parallel_tasks()
+            .map(|parallel| {
+                parallel
+                    .tasks
+                    .into_iter()
+                    .filter(|task| !task.is_pending() && !task.is_finished())
+                    .map(|task| task.topic)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Check if the given command topic is the topic of a sub-command started by this command
+    pub fn has_parallel_task(&self, topic: &str) -> bool {
+        self.payload
+            .pointer(&format!("/{PARALLEL}/tasks"))
+            .and_then(|tasks| tasks.as_array())
+            .is_some_and(|tasks| tasks.iter().any(|task| task["topic"] == topic))
+    }
+
+    /// Start as many pending sub-commands as allowed by the concurrency limit
+    ///
+    /// Return the updated command state along with the init states of the started sub-commands.
+    pub fn start_parallel_tasks(self) -> (Self, Vec<GenericCommandState>) {
+        let Some(mut parallel) = self.parallel_tasks() else {
+            return (self, vec![]);
+        };
+
+        let running = parallel
+            .tasks
+            .iter()
+            .filter(|task| !task.is_pending() && !task.is_finished())
+            .count();
+        let mut available = parallel
+            .max_concurrency
+            .map_or(usize::MAX, |max| max.saturating_sub(running));
+
+        let mut started = Vec::new();
+        for task in parallel.tasks.iter_mut().filter(|task| task.is_pending()) {
+            if available == 0 {
+                break;
+            }
+            available -= 1;
+            let init = task
+                .init
+                .take()
+                .unwrap_or_else(GenericStateUpdate::init_payload);
+            task.status = "init".to_string();
+            started.push(GenericCommandState::new(
+                Topic::new_unchecked(&task.topic),
+                "init".to_string(),
+                init,
+            ));
+        }
+
+        if started.is_empty() {
+            (self, started)
+        } else {
+            (self.with_parallel_tasks(&parallel), started)
+        }
+    }
+
+    /// Record the new state of a sub-command started by this command
+    ///
+    /// Return the updated command state, if the status of the sub-command has changed.
+    pub fn update_parallel_task(&self, task_state: &GenericCommandState) -> Option<Self> {
+        if task_state.is_cleared() {
+            return None;
+        }
+        let mut parallel = self.parallel_tasks()?;
+        let task = parallel
+            .tasks
+            .iter_mut()
+            .find(|task| task.topic == task_state.topic.name && !task.is_finished())?;
+        if task.status == task_state.status {
+            return None;
+        }
+
+        task.status = task_state.status.clone();
+        if task.is_finished() {
+            task.result = Some(task_state.payload.clone());
+        }
+        Some(self.clone().with_parallel_tasks(&parallel))
+    }
+
+    /// Determine what has to be done to join the sub-commands started by a parallel action
+    ///
+    /// Once all the sub-commands are finished, the command moves to:
+    /// - `on_success` if all the sub-commands are successful,
+    /// - `on_error` if all the sub-commands failed,
+    /// - `on_partial` if some sub-commands failed while others were successful.
+    pub fn join_parallel_tasks(self, handlers: &JoinHandlers) -> JoinStep {
+        let Some(parallel) = self.parallel_tasks() else {
+            let reason = "No parallel sub-operations to join".to_string();
+            return JoinStep::Done(self.update(GenericStateUpdate::failed(reason)), vec![]);
+        };
+
+        if !parallel.tasks.iter().all(ParallelTask::is_finished) {
+            return match self.start_parallel_tasks() {
+                (_, started) if started.is_empty() => JoinStep::Wait,
+                (new_state, started) => JoinStep::Start(new_state, started),
+            };
+        }
+
+        let count = parallel.tasks.len();
+        let failed = parallel
+            .tasks
+            .iter()
+            .filter(|task| !task.is_successful())
+            .count();
+        let operation = &parallel.operation;
+        let update = if failed == 0 {
+            handlers.on_success.clone()
+        } else if failed < count {
+            handlers.on_partial.clone()
+        } else {
+            let mut on_error = handlers.on_error.clone();
+            on_error.reason.get_or_insert_with(|| {
+                format!("All the {count} {operation} sub-operations failed")
+            });
+            on_error
+        };
+
+        let cleared = parallel
+            .tasks
+            .iter()
+            .map(|task| {
+                GenericCommandState::new(
+                    Topic::new_unchecked(&task.topic),
+                    task.status.clone(),
+                    json!({}),
+                )
+                .clear()
+            })
+            .collect();
+        JoinStep::Done(self.update(update), cleared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mqtt_channel::MqttMessage;
+
+    #[test]
+    fn fan_out_one_sub_command_per_item() {
+        let state = command(json!({
+            "status": "fan_out",
+            "devices": [
+                {"@target": "device/child1//", "name": "robot"},
+                {"name": "local"},
+            ]
+        }));
+
+        let state = state
+            .fan_out(
+                &MqttSchema::default(),
+                &EntityTopicId::default_main_device(),
+                &OperationType::Custom("fleet_update".to_string()),
+                "123",
+                "firmware_update",
+                ".payload.devices",
+                json!({"url": "http://example.com/firmware"}),
+                Some(1),
+            )
+            .unwrap();
+        let parallel = state.parallel_tasks().unwrap();
+        assert_eq!(parallel.operation, "firmware_update");
+        assert_eq!(
+            parallel.tasks,
+            vec![
+                ParallelTask {
+                    topic: "te/device/child1///cmd/firmware_update/par:0:fleet_update:123"
+                        .to_string(),
+                    status: "pending".to_string(),
+                    init: Some(json!({
+                        "status": "init",
+                        "url": "http://example.com/firmware",
+                        "name": "robot"
+                    })),
+                    result: None,
+                },
+                ParallelTask {
+                    topic: "te/device/main///cmd/firmware_update/par:1:fleet_update:123"
+                        .to_string(),
+                    status: "pending".to_string(),
+                    init: Some(json!({
+                        "status": "init",
+                        "url": "http://example.com/firmware",
+                        "name": "local"
+                    })),
+                    result: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fan_out_rejects_invalid_items() {
+        for payload in [
+            json!({"status": "fan_out", "devices": "not an array"}),
+            json!({"status": "fan_out", "devices": [42]}),
+            json!({"status": "fan_out", "devices": [{"@target": "not/an/entity/topic/id"}]}),
+        ] {
+            let result = command(payload).fan_out(
+                &MqttSchema::default(),
+                &EntityTopicId::default_main_device(),
+                &OperationType::Custom("fleet_update".to_string()),
+                "123",
+                "firmware_update",
+                ".payload.devices",
+                json!({}),
+                None,
+            );
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn sub_commands_are_started_within_the_concurrency_limit() {
+        let state = command(json!({
+            "status": "fan_out",
+            "devices": [{}, {}, {}]
+        }));
+        let (state, started) = fan_out(state, Some(2)).start_parallel_tasks();
+        assert_eq!(started.len(), 2);
+        assert_eq!(
+            started[0].topic.name,
+            "te/device/main///cmd/firmware_update/par:0:fleet_update:123"
+        );
+        assert!(started[0].is_init());
+
+        // No more sub-commands are started till one is finished
+        let state = state.update(GenericStateUpdate {
+            status: "join".to_string(),
+            reason: None,
+        });
+        let state = state.update_parallel_task(&task(0, "executing")).unwrap();
+        assert_eq!(
+            state.clone().join_parallel_tasks(&join_handlers()),
+            JoinStep::Wait
+        );
+
+        let state = state.update_parallel_task(&task(0, "successful")).unwrap();
+        match state.join_parallel_tasks(&join_handlers()) {
+            JoinStep::Start(_, started) => {
+                assert_eq!(started.len(), 1);
+                assert_eq!(
+                    started[0].topic.name,
+                    "te/device/main///cmd/firmware_update/par:2:fleet_update:123"
+                );
+            }
+            other => panic!("Unexpected join step: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn join_outcome_depends_on_sub_command_outcomes() {
+        let state = command(json!({
+            "status": "fan_out",
+            "devices": [{}, {}]
+        }));
+        let (state, _) = fan_out(state, None).start_parallel_tasks();
+        let state = state.update_parallel_task(&task(0, "successful")).unwrap();
+
+        // All successful
+        let all_successful = state.update_parallel_task(&task(1, "successful")).unwrap();
+        match all_successful.join_parallel_tasks(&join_handlers()) {
+            JoinStep::Done(new_state, cleared) => {
+                assert_eq!(new_state.status, "successful");
+                assert_eq!(cleared.len(), 2);
+                assert!(cleared.iter().all(|task| task.is_cleared()));
+                let parallel = new_state.parallel_tasks().unwrap();
+                assert_eq!(
+                    parallel.tasks[1].result,
+                    Some(json!({"status": "successful"}))
+                );
+            }
+            other => panic!("Unexpected join step: {other:?}"),
+        }
+
+        // Partially successful
+        let partial = state.update_parallel_task(&task(1, "failed")).unwrap();
+        match partial.join_parallel_tasks(&join_handlers()) {
+            JoinStep::Done(new_state, _) => assert_eq!(new_state.status, "partial"),
+            other => panic!("Unexpected join step: {other:?}"),
+        }
+
+        // All failed
+        let state = command(json!({"status": "fan_out", "devices": [{}]}));
+        let (state, _) = fan_out(state, None).start_parallel_tasks();
+        let all_failed = state.update_parallel_task(&task(0, "failed")).unwrap();
+        match all_failed.join_parallel_tasks(&join_handlers()) {
+            JoinStep::Done(new_state, _) => {
+                assert_eq!(new_state.status, "failed");
+                assert_eq!(
+                    new_state.failure_reason(),
+                    Some("All the 1 firmware_update sub-operations failed")
+                );
+            }
+            other => panic!("Unexpected join step: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn sub_commands_refer_to_the_key_of_their_invoking_command() {
+        let state = command(json!({
+            "status": "fan_out",
+            "devices": [{"@target": "device/child1//"}, {}]
+        }));
+        assert_eq!(state.parallel_command_key(), None);
+
+        let (state, started) = fan_out(state, None).start_parallel_tasks();
+        let key = state.parallel_command_key();
+        assert_eq!(key.as_deref(), Some("fleet_update:123"));
+        for task in started {
+            assert_eq!(
+                GenericCommandState::parallel_task_command_key(&task.topic.name),
+                key.as_deref()
+            );
+        }
+        assert_eq!(
+            state.running_parallel_tasks(),
+            vec![
+                "te/device/child1///cmd/firmware_update/par:0:fleet_update:123",
+                "te/device/main///cmd/firmware_update/par:1:fleet_update:123",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_status_changes_of_known_sub_commands_are_recorded() {
+        let state = command(json!({
+            "status": "fan_out",
+            "devices": [{}]
+        }));
+        let (state, _) = fan_out(state, None).start_parallel_tasks();
+        assert!(
+            state.has_parallel_task("te/device/main///cmd/firmware_update/par:0:fleet_update:123")
+        );
+
+        assert!(state.update_parallel_task(&task(0, "init")).is_none());
+        assert!(state.update_parallel_task(&task(1, "executing")).is_none());
+
+        let cleared = task(0, "successful").clear();
+        assert!(state.update_parallel_task(&cleared).is_none());
+    }
+
+    fn command(payload: Value) -> GenericCommandState {
+        GenericCommandState::from_command_message(&MqttMessage::new(
+            &Topic::new_unchecked("te/device/main///cmd/fleet_update/123"),
+            payload.to_string(),
+        ))
+        .unwrap()
+    }
+
+    fn fan_out(state: GenericCommandState, max_concurrency: Option<usize>) -> GenericCommandState {
+        state
+            .fan_out(
+                &MqttSchema::default(),
+                &EntityTopicId::default_main_device(),
+                &OperationType::Custom("fleet_update".to_string()),
+                "123",
+                "firmware_update",
+                ".payload.devices",
+                json!({}),
+                max_concurrency,
+            )
+            .unwrap()
+    }
+
+    fn task(index: usize, status: &str) -> GenericCommandState {
+        GenericCommandState::from_command_message(&MqttMessage::new(
+            &Topic::new_unchecked(&format!(
+                "te/device/main///cmd/firmware_update/par:{index}:fleet_update:123"
+            )),
+            json!({"status": status}).to_string(),
+        ))
+        .unwrap()
+    }
+
+    fn join_handlers() -> JoinHandlers {
+        JoinHandlers {
+            timeout: None,
+            on_success: GenericStateUpdate::successful(),
+            on_partial: "partial".into(),
+            on_error: GenericStateUpdate::unknown_error(),
+            on_timeout: GenericStateUpdate::timeout(),
+        }
+    }
+}
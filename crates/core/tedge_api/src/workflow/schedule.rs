@@ -0,0 +1,383 @@
+use crate::workflow::GenericCommandState;
+use crate::workflow::OperationName;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::str::FromStr;
+use time::format_description::well_known::Rfc3339;
+use time::Duration;
+use time::OffsetDateTime;
+use time::Time;
+
+/// The command property used to request the execution of a command at a given time
+pub const SCHEDULED_AT: &str = "scheduled_at";
+
+/// A maintenance window, given as a cron-like expression
+///
+/// The expression is made of five fields: minute, hour, day of month, month and day of week.
+/// Each field is either `*`, a value, a range `a-b`, a list `a,b,c` or a step `*/n`, `a-b/n` or `a/n`.
+/// The days of week range from 0 (Sunday) to 7 (Sunday again).
+///
+/// The window is open during any minute matched by the expression.
+/// For instance, `* 2-4 * * 6,0` is open from 02:00 to 04:59 on Saturdays and Sundays.
+///
+/// As with cron, when both the day of month and the day of week are restricted,
+/// a day is matched when either field matches.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MaintenanceWindow {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+#[derive(thiserror::Error, Debug, Eq, PartialEq)]
+pub enum ScheduleError {
+    #[error("Invalid maintenance window {expression:?}: expecting 5 fields (minute hour day month weekday)")]
+    InvalidFieldCount { expression: String },
+
+    #[error("Invalid maintenance window {field:?} field: {reason}")]
+    InvalidField { field: String, reason: String },
+
+    #[error("The maintenance window {expression:?} never opens")]
+    NeverOpens { expression: String },
+
+    #[error("Invalid scheduled_at time {time:?}: expecting an RFC 3339 timestamp")]
+    InvalidScheduledTime { time: String },
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = ScheduleError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(ScheduleError::InvalidFieldCount {
+                expression: expression.to_string(),
+            });
+        };
+
+        let any_day = days.starts_with('*');
+        let any_weekday = weekdays.starts_with('*');
+        let weekdays = parse_field(weekdays, 0, 7)?;
+        Ok(MaintenanceWindow {
+            expression: fields.join(" "),
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            // Sunday is either 0 or 7
+            weekdays: (weekdays | (weekdays >> 7)) & 0x7f,
+            any_day,
+            any_weekday,
+        })
+    }
+}
+
+impl Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl MaintenanceWindow {
+    /// Check if the window is open at the given time
+    pub fn contains(&self, time: OffsetDateTime) -> bool {
+        self.day_matches(time)
+            && is_set(self.hours, time.hour())
+            && is_set(self.minutes, time.minute())
+    }
+
+    /// Return the time when the window next opens, i.e. the given time if the window is open
+    ///
+    /// The time is computed using the offset of the given time.
+    pub fn next_opening(&self, time: OffsetDateTime) -> Result<OffsetDateTime, ScheduleError> {
+        if self.contains(time) {
+            return Ok(time);
+        }
+
+        // Any date is matched within 4 years, or never (as for a 30th of February)
+        let horizon = time + Duration::days(4 * 366);
+        let mut next = time.replace_time(Time::from_hms(time.hour(), time.minute(), 0).unwrap())
+            + Duration::MINUTE;
+        while next < horizon {
+            if !self.day_matches(next) {
+                next = next.replace_time(Time::MIDNIGHT) + Duration::DAY;
+            } else if !is_set(self.hours, next.hour()) {
+                next =
+                    next.replace_time(Time::from_hms(next.hour(), 0, 0).unwrap()) + Duration::HOUR;
+            } else if !is_set(self.minutes, next.minute()) {
+                next += Duration::MINUTE;
+            } else {
+                return Ok(next);
+            }
+        }
+
+        Err(ScheduleError::NeverOpens {
+            expression: self.expression.clone(),
+        })
+    }
+
+    fn day_matches(&self, time: OffsetDateTime) -> bool {
+        if !is_set(self.months, u8::from(time.month())) {
+            return false;
+        }
+        let day = is_set(self.days, time.day());
+        let weekday = is_set(self.weekdays, time.weekday().number_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+fn is_set(bits: u64, value: u8) -> bool {
+    bits & (1 << value) != 0
+}
+
+fn parse_field(field: &str, min: u8, max: u8) -> Result<u64, ScheduleError> {
+    let invalid = |reason: String| ScheduleError::InvalidField {
+        field: field.to_string(),
+        reason,
+    };
+    let parse_value = |value: &str| match value.parse::<u8>() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        _ => Err(invalid(format!(
+            "expecting a value between {min} and {max}, found {value:?}"
+        ))),
+    };
+
+    let mut bits = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            None => (item, None),
+            Some((range, step)) => match step.parse::<u8>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(invalid(format!("invalid step {step:?}"))),
+            },
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            None => {
+                let start = parse_value(range)?;
+                (start, if step.is_some() { max } else { start })
+            }
+            Some((start, end)) => (parse_value(start)?, parse_value(end)?),
+        };
+        if start > end {
+            return Err(invalid(format!("empty range {range:?}")));
+        }
+        for value in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// Decide when the commands moved to the `scheduled` state can be executed
+///
+/// - A command with a `scheduled_at` property is deferred till that time.
+/// - A command for one of the gated operations is deferred till the maintenance window opens.
+/// - Any other command is executed straight away.
+#[derive(Clone, Debug, Default)]
+pub struct CommandScheduler {
+    window: Option<MaintenanceWindow>,
+    operations: Vec<OperationName>,
+}
+
+impl CommandScheduler {
+    pub fn new(window: Option<MaintenanceWindow>, operations: Vec<OperationName>) -> Self {
+        CommandScheduler { window, operations }
+    }
+
+    /// Return the time till which the execution of a command has to be deferred, if any
+    pub fn deferred_until(
+        &self,
+        command: &GenericCommandState,
+        now: OffsetDateTime,
+    ) -> Result<Option<OffsetDateTime>, ScheduleError> {
+        if !command.is_scheduled() {
+            return Ok(None);
+        }
+
+        if let Some(time) = command.scheduled_at()? {
+            return Ok((time > now).then_some(time));
+        }
+
+        let Some(window) = &self.window else {
+            return Ok(None);
+        };
+        let gated = command
+            .operation()
+            .is_some_and(|operation| self.operations.contains(&operation));
+        if !gated || window.contains(now) {
+            return Ok(None);
+        }
+        window.next_opening(now).map(Some)
+    }
+}
+
+impl GenericCommandState {
+    /// Return the time at which the command has been requested to be executed, if any
+    pub fn scheduled_at(&self) -> Result<Option<OffsetDateTime>, ScheduleError> {
+        let Some(time) = self.get_text_property(SCHEDULED_AT) else {
+            return Ok(None);
+        };
+        #[expect(
+            clippy::disallowed_methods,
+            reason = "Not vulnerable to RUSTSEC-2026-0009 as not RFC-2822 format"
+        )]
+        OffsetDateTime::parse(time, &Rfc3339)
+            .map(Some)
+            .map_err(|_| ScheduleError::InvalidScheduledTime {
+                time: time.to_string(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mqtt_channel::MqttMessage;
+    use mqtt_channel::Topic;
+    use time::macros::datetime;
+
+    #[test]
+    fn parse_maintenance_windows() {
+        let window: MaintenanceWindow = "*/15 2-4 * * 6,0".parse().unwrap();
+        assert_eq!(window.to_string(), "*/15 2-4 * * 6,0");
+
+        for invalid in [
+            "* * * *",
+            "60 * * * *",
+            "* 5-2 * * *",
+            "*/0 * * * *",
+            "* * 0 * *",
+            "* * * jan *",
+        ] {
+            assert!(
+                invalid.parse::<MaintenanceWindow>().is_err(),
+                "{invalid} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn the_window_is_open_on_matching_minutes() {
+        // 2025-03-01 is a Saturday
+        let window: MaintenanceWindow = "*/15 2-4 * * 6,7".parse().unwrap();
+        assert!(window.contains(datetime!(2025-03-01 02:00 UTC)));
+        assert!(window.contains(datetime!(2025-03-02 04:45:30 UTC)));
+        assert!(!window.contains(datetime!(2025-03-01 02:10 UTC)));
+        assert!(!window.contains(datetime!(2025-03-01 05:00 UTC)));
+        assert!(!window.contains(datetime!(2025-03-03 02:00 UTC)));
+    }
+
+    #[test]
+    fn day_of_month_and_day_of_week_are_alternatives_when_both_restricted() {
+        // The 1st of the month or any Monday
+        let window: MaintenanceWindow = "* * 1 * 1".parse().unwrap();
+        assert!(window.contains(datetime!(2025-03-01 12:00 UTC)));
+        assert!(window.contains(datetime!(2025-03-03 12:00 UTC)));
+        assert!(!window.contains(datetime!(2025-03-04 12:00 UTC)));
+
+        // The 1st of the month only
+        let window: MaintenanceWindow = "* * 1 * *".parse().unwrap();
+        assert!(!window.contains(datetime!(2025-03-03 12:00 UTC)));
+    }
+
+    #[test]
+    fn compute_the_next_opening_of_a_window() {
+        let window: MaintenanceWindow = "30 2 * * 0".parse().unwrap();
+        assert_eq!(
+            window.next_opening(datetime!(2025-03-01 10:12:34 +01:00)),
+            Ok(datetime!(2025-03-02 02:30 +01:00))
+        );
+        assert_eq!(
+            window.next_opening(datetime!(2025-03-02 02:30:15 +01:00)),
+            Ok(datetime!(2025-03-02 02:30:15 +01:00))
+        );
+        assert_eq!(
+            window.next_opening(datetime!(2025-03-02 02:31 +01:00)),
+            Ok(datetime!(2025-03-09 02:30 +01:00))
+        );
+
+        let window: MaintenanceWindow = "0 0 30 2 *".parse().unwrap();
+        assert!(window
+            .next_opening(datetime!(2025-03-01 00:00 UTC))
+            .is_err());
+    }
+
+    #[test]
+    fn gated_operations_are_deferred_till_the_maintenance_window() {
+        let scheduler = CommandScheduler::new(
+            Some("* 2 * * *".parse().unwrap()),
+            vec!["software_update".to_string()],
+        );
+        let now = datetime!(2025-03-01 10:00 UTC);
+
+        let update = command("software_update", r#"{"status":"scheduled"}"#);
+        assert_eq!(
+            scheduler.deferred_until(&update, now),
+            Ok(Some(datetime!(2025-03-02 02:00 UTC)))
+        );
+        assert_eq!(
+            scheduler.deferred_until(&update, datetime!(2025-03-02 02:10 UTC)),
+            Ok(None)
+        );
+
+        let init = command("software_update", r#"{"status":"init"}"#);
+        assert_eq!(scheduler.deferred_until(&init, now), Ok(None));
+
+        let list = command("software_list", r#"{"status":"scheduled"}"#);
+        assert_eq!(scheduler.deferred_until(&list, now), Ok(None));
+    }
+
+    #[test]
+    fn commands_are_deferred_till_their_scheduled_time() {
+        let scheduler = CommandScheduler::new(
+            Some("* 2 * * *".parse().unwrap()),
+            vec!["software_update".to_string()],
+        );
+        let now = datetime!(2025-03-01 10:00 UTC);
+
+        let update = command(
+            "software_update",
+            r#"{"status":"scheduled", "scheduled_at":"2025-03-01T12:00:00+01:00"}"#,
+        );
+        assert_eq!(
+            scheduler.deferred_until(&update, now),
+            Ok(Some(datetime!(2025-03-01 11:00 UTC)))
+        );
+        assert_eq!(
+            scheduler.deferred_until(&update, datetime!(2025-03-01 11:00 UTC)),
+            Ok(None)
+        );
+
+        let restart = command(
+            "restart",
+            r#"{"status":"scheduled", "scheduled_at":"2025-03-01T12:00:00Z"}"#,
+        );
+        assert_eq!(
+            CommandScheduler::default().deferred_until(&restart, now),
+            Ok(Some(datetime!(2025-03-01 12:00 UTC)))
+        );
+
+        let invalid = command(
+            "restart",
+            r#"{"status":"scheduled", "scheduled_at":"tomorrow"}"#,
+        );
+        assert!(scheduler.deferred_until(&invalid, now).is_err());
+    }
+
+    fn command(operation: &str, payload: &str) -> GenericCommandState {
+        GenericCommandState::from_command_message(&MqttMessage::new(
+            &Topic::new_unchecked(&format!("te/device/main///cmd/{operation}/123")),
+            payload,
+        ))
+        .unwrap()
+    }
+}
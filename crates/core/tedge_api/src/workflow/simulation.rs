@@ -0,0 +1,802 @@
+use crate::mqtt_topics::Channel;
+use crate::mqtt_topics::EntityTopicId;
+use crate::mqtt_topics::MqttSchema;
+use crate::mqtt_topics::OperationType;
+use crate::workflow::extract_json_output;
+use crate::workflow::GenericCommandState;
+use crate::workflow::GenericStateUpdate;
+use crate::workflow::JoinStep;
+use crate::workflow::OperationAction;
+use crate::workflow::OperationWorkflow;
+use crate::workflow::StateName;
+use crate::workflow::WorkflowExecutionError;
+use crate::workflow::WorkflowRegistrationError;
+use crate::workflow::WorkflowSource;
+use crate::workflow::WorkflowSupervisor;
+use mqtt_channel::Topic;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::process::Output;
+
+/// The workflow version used to simulate a command
+const SIMULATION_VERSION: &str = "simulation";
+
+/// The outcome of a step, as mocked by a simulation
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MockedOutcome {
+    /// The exit code of a script, 0 by default
+    #[serde(default)]
+    pub exit_code: i32,
+
+    /// The signal killing a script, if any
+    #[serde(default)]
+    pub signal: Option<i32>,
+
+    /// The standard output of a script
+    #[serde(default)]
+    pub stdout: String,
+
+    /// The error returned by a builtin action or a sub-operation, if any
+    #[serde(default)]
+    pub error: Option<String>,
+
+    /// The output returned by a builtin action or a sub-operation
+    #[serde(default)]
+    pub output: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MockedSteps {
+    One(MockedOutcome),
+    Many(Vec<MockedOutcome>),
+}
+
+/// The mocked outcomes of the steps of a workflow, indexed by state name
+///
+/// When a state is visited several times, the outcomes are consumed in order,
+/// the last one being repeated.
+/// A step with no mocked outcome is successful.
+#[derive(Debug, Default)]
+pub struct MockedOutcomes {
+    outcomes: HashMap<StateName, VecDeque<MockedOutcome>>,
+}
+
+impl<'de> Deserialize<'de> for MockedOutcomes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let steps = HashMap::<StateName, MockedSteps>::deserialize(deserializer)?;
+        let outcomes = steps
+            .into_iter()
+            .map(|(state, steps)| match steps {
+                MockedSteps::One(outcome) => (state, VecDeque::from([outcome])),
+                MockedSteps::Many(outcomes) => (state, VecDeque::from(outcomes)),
+            })
+            .collect();
+        Ok(MockedOutcomes { outcomes })
+    }
+}
+
+impl MockedOutcomes {
+    /// Return the mocked outcome for the next visit of the given state, if any
+    fn next(&mut self, state: &str) -> Option<MockedOutcome> {
+        let outcomes = self.outcomes.get_mut(state)?;
+        if outcomes.len() > 1 {
+            outcomes.pop_front()
+        } else {
+            outcomes.front().cloned()
+        }
+    }
+}
+
+impl MockedOutcome {
+    fn script_output(&self) -> Output {
+        let status = match self.signal {
+            Some(signal) => ExitStatus::from_raw(signal & 0x7f),
+            None => ExitStatus::from_raw((self.exit_code & 0xff) << 8),
+        };
+        Output {
+            status,
+            stdout: self.stdout.as_bytes().to_vec(),
+            stderr: vec![],
+        }
+    }
+
+    fn script_outcome(&self) -> String {
+        match self.signal {
+            Some(signal) => format!("killed by signal {signal}"),
+            None => format!("exit code {}", self.exit_code),
+        }
+    }
+
+    fn result(&self, default: Value) -> Result<Value, String> {
+        match &self.error {
+            Some(error) => Err(error.clone()),
+            None => Ok(self.output.clone().unwrap_or(default)),
+        }
+    }
+
+    fn result_description(&self) -> String {
+        match &self.error {
+            Some(error) => format!("failed with: {error}"),
+            None => "successful".to_string(),
+        }
+    }
+}
+
+/// A state transition observed along a simulation
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SimulatedTransition {
+    /// The state from where the command moved
+    pub from: StateName,
+
+    /// The state to where the command moved
+    pub to: StateName,
+
+    /// The action taken in the `from` state
+    pub action: String,
+
+    /// What has been mocked to simulate the action
+    pub details: Vec<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SimulationError {
+    #[error(transparent)]
+    WorkflowRegistrationError(#[from] WorkflowRegistrationError),
+
+    #[error(transparent)]
+    WorkflowExecutionError(#[from] WorkflowExecutionError),
+
+    #[error("The command is stuck in the `{state}` state: no action is defined for this state")]
+    StuckCommand { state: StateName },
+
+    #[error("The command is still running after {max_steps} steps")]
+    TooManySteps { max_steps: usize },
+}
+
+/// A sub-command or builtin operation triggered by the simulated command
+struct PendingSubCommand {
+    /// The init state of the sub-command, if not a builtin operation
+    init_state: Option<GenericCommandState>,
+
+    /// The mocked outcome of the state that triggered the sub-command
+    outcome: Option<MockedOutcome>,
+}
+
+/// Simulate the execution of a command along its operation workflow
+///
+/// The command states are managed by a [WorkflowSupervisor] as done by the agent,
+/// but no processes are run and nothing is published over MQTT:
+/// the outcomes of scripts, downloads, builtin actions and sub-operations are mocked.
+pub struct WorkflowSimulation {
+    supervisor: WorkflowSupervisor,
+    operation: OperationType,
+    outcomes: MockedOutcomes,
+    schema: MqttSchema,
+    device: EntityTopicId,
+    pending: Option<PendingSubCommand>,
+    transitions: Vec<SimulatedTransition>,
+}
+
+impl WorkflowSimulation {
+    pub fn try_new(
+        workflow: OperationWorkflow,
+        outcomes: MockedOutcomes,
+    ) -> Result<Self, SimulationError> {
+        let operation = workflow.operation.clone();
+        let mut supervisor = WorkflowSupervisor::default();
+        supervisor.register_custom_workflow(
+            WorkflowSource::UserDefined(SIMULATION_VERSION.to_string()),
+            workflow,
+        )?;
+
+        Ok(WorkflowSimulation {
+            supervisor,
+            operation,
+            outcomes,
+            schema: MqttSchema::default(),
+            device: EntityTopicId::default_main_device(),
+            pending: None,
+            transitions: vec![],
+        })
+    }
+
+    /// The init state of a command to be simulated
+    pub fn init_state(&self, cmd_id: &str, payload: Value) -> GenericCommandState {
+        let topic = self.schema.topic_for(
+            &self.device,
+            &Channel::Command {
+                operation: self.operation.clone(),
+                cmd_id: cmd_id.to_string(),
+            },
+        );
+        let payload = match payload {
+            Value::Object(_) => payload,
+            _ => json!({}),
+        };
+        GenericCommandState::new(topic, "init".to_string(), payload)
+    }
+
+    /// Run the simulation till the command reaches a terminal state
+    ///
+    /// Return the final state of the command, the transitions being available with [Self::transitions].
+    pub fn run(
+        &mut self,
+        init_state: GenericCommandState,
+        max_steps: usize,
+    ) -> Result<GenericCommandState, SimulationError> {
+        let Some(mut state) = self
+            .supervisor
+            .apply_external_update(&self.operation, init_state)?
+        else {
+            return Err(WorkflowExecutionError::MissingVersion.into());
+        };
+
+        for _ in 0..max_steps {
+            let action = match self.supervisor.get_action(&state) {
+                Ok(action) => action,
+                Err(WorkflowExecutionError::UnknownStep { step, .. }) => {
+                    return Err(SimulationError::StuckCommand { state: step })
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if action == OperationAction::Clear {
+                return Ok(state);
+            }
+
+            let from = state.status.clone();
+            let description = action.to_string();
+            let (new_state, details) = self.process(state, action);
+            self.transitions.push(SimulatedTransition {
+                from,
+                to: new_state.status.clone(),
+                action: description,
+                details,
+            });
+            self.supervisor.apply_internal_update(new_state.clone())?;
+            state = new_state;
+        }
+
+        Err(SimulationError::TooManySteps { max_steps })
+    }
+
+    /// The state transitions observed so far
+    pub fn transitions(&self) -> &[SimulatedTransition] {
+        &self.transitions
+    }
+
+    /// Simulate an action, returning the new state along with what has been mocked
+    fn process(
+        &mut self,
+        state: GenericCommandState,
+        action: OperationAction,
+    ) -> (GenericCommandState, Vec<String>) {
+        let step = state.status.clone();
+        let operation = self.operation.to_string();
+        let cmd_id = state.cmd_id().unwrap_or_default();
+
+        match action {
+            OperationAction::Clear => (state, vec![]),
+
+            OperationAction::MoveTo(next_step) => (state.move_to(next_step), vec![]),
+
+            OperationAction::BuiltIn(_, _) if state.is_scheduled() => {
+                let new_state =
+                    action.adapt_builtin_response(state.update(GenericStateUpdate::executing()));
+                (new_state, vec!["builtin operation started".to_string()])
+            }
+
+            OperationAction::BuiltIn(_, _) => {
+                let outcome = self.outcomes.next(&step).unwrap_or_default();
+                let details = vec![format!(
+                    "builtin operation {}",
+                    outcome.result_description()
+                )];
+                let new_state = action.adapt_builtin_response(builtin_result(state, &outcome));
+                (new_state, details)
+            }
+
+            OperationAction::BuiltInOperation(ref builtin_operation, ref handlers) => {
+                self.pending = Some(PendingSubCommand {
+                    init_state: None,
+                    outcome: self.outcomes.next(&step),
+                });
+                let new_state = state.update(handlers.on_exec.clone());
+                (
+                    new_state,
+                    vec![format!("builtin:{builtin_operation} operation started")],
+                )
+            }
+
+            OperationAction::AwaitOperationCompletion(ref handlers, ref output_excerpt) => {
+                let pending = self.pending.take();
+                let outcome = self
+                    .outcomes
+                    .next(&step)
+                    .or(pending.as_ref().and_then(|pending| pending.outcome.clone()))
+                    .unwrap_or_default();
+                match pending.and_then(|pending| pending.init_state) {
+                    Some(sub_state) => {
+                        let sub_operation = sub_state.operation().unwrap_or_default();
+                        let details = vec![format!(
+                            "{sub_operation} sub-operation {}",
+                            outcome.result_description()
+                        )];
+                        let sub_state = match &outcome.error {
+                            Some(error) => {
+                                sub_state.update(GenericStateUpdate::failed(error.clone()))
+                            }
+                            None => sub_state
+                                .update_with_json(outcome.output.clone().unwrap_or(json!({})))
+                                .update(GenericStateUpdate::successful()),
+                        };
+                        let new_state = if sub_state.is_successful() {
+                            let sub_cmd_output = output_excerpt.extract_value_from(&sub_state);
+                            state
+                                .update_with_json(sub_cmd_output)
+                                .update(handlers.on_success.clone())
+                        } else {
+                            state.update(handlers.on_error.clone())
+                        };
+                        (new_state, details)
+                    }
+                    None => {
+                        let details = vec![format!(
+                            "builtin operation {}",
+                            outcome.result_description()
+                        )];
+                        let new_state =
+                            action.adapt_builtin_response(builtin_result(state, &outcome));
+                        (new_state, details)
+                    }
+                }
+            }
+
+            OperationAction::AwaitingAgentRestart(handlers) => {
+                match self.outcomes.next(&step).and_then(|outcome| outcome.error) {
+                    None => (
+                        state.update(handlers.on_success),
+                        vec!["agent restarted".to_string()],
+                    ),
+                    Some(error) => (
+                        state.update(handlers.on_timeout),
+                        vec![format!("agent not restarted: {error}")],
+                    ),
+                }
+            }
+
+            OperationAction::RestartAgent(handlers) => (
+                state.update(handlers.on_exec),
+                vec!["agent restart requested".to_string()],
+            ),
+
+            OperationAction::Script(script, handlers) => {
+                let outcome = self.outcomes.next(&step).unwrap_or_default();
+                let details = vec![format!("script {}", outcome.script_outcome())];
+                let new_state = state.update_with_script_output(
+                    script.command,
+                    Ok(outcome.script_output()),
+                    handlers,
+                );
+                (new_state, details)
+            }
+
+            OperationAction::BgScript(_, handlers) => (
+                state.update(handlers.on_exec),
+                vec!["background script not awaited".to_string()],
+            ),
+
+            OperationAction::Download(input_excerpt, handlers) => {
+                let outcome = self.outcomes.next(&step).unwrap_or_default();
+                let input = input_excerpt.extract_value_from(&state);
+                let url = GenericCommandState::extract_text_property(&input, "url")
+                    .or_else(|| state.get_text_property("tedgeUrl"))
+                    .or_else(|| state.get_text_property("remoteUrl"))
+                    .map(str::to_string);
+                let (result, details) = match url {
+                    None => (
+                        Err("No valid URL found in input.url, tedgeUrl, or remoteUrl".to_string()),
+                        vec!["no URL to download".to_string()],
+                    ),
+                    Some(url) => {
+                        let downloaded_path = format!("/tmp/{operation}_{cmd_id}");
+                        (
+                            outcome.result(json!({ "downloadedPath": downloaded_path })),
+                            vec![format!(
+                                "download of {url} {}",
+                                outcome.result_description()
+                            )],
+                        )
+                    }
+                };
+                let json_update = handlers.state_update_on_result("download", result);
+                (state.update_with_json(json_update), details)
+            }
+
+            OperationAction::BuiltInOperationStep(
+                operation_name,
+                operation_step,
+                input_excerpt,
+                handlers,
+            ) => {
+                let outcome = self.outcomes.next(&step).unwrap_or_default();
+                let action = format!("builtin:{operation_name}:{operation_step}");
+                let details = vec![format!("{action} {}", outcome.result_description())];
+                let input = input_excerpt.extract_value_from(&state);
+                let state = state.update_with_json(input);
+                let json_update =
+                    handlers.state_update_on_result(&action, outcome.result(json!({})));
+                (state.update_with_json(json_update), details)
+            }
+
+            OperationAction::Operation(sub_operation, input_script, input_excerpt, handlers) => {
+                let outcome = self.outcomes.next(&step);
+                let mut details = vec![format!("{sub_operation} sub-operation started")];
+                let generated_init_state = match input_script {
+                    None => GenericStateUpdate::empty_payload(),
+                    Some(script) => {
+                        let output = outcome.clone().unwrap_or_default().script_output();
+                        match extract_json_output(&script.command, Ok(output)) {
+                            Ok(init_state) => init_state,
+                            Err(reason) => {
+                                let new_state = state.update(GenericStateUpdate::failed(reason));
+                                return (new_state, vec!["input script failed".to_string()]);
+                            }
+                        }
+                    }
+                };
+
+                let sub_cmd_input = input_excerpt.extract_value_from(&state);
+                let sub_cmd_init_state = GenericCommandState::sub_command_init_state(
+                    &self.schema,
+                    &self.device,
+                    self.operation.clone(),
+                    cmd_id,
+                    sub_operation,
+                )
+                .update_with_json(generated_init_state)
+                .update_with_json(sub_cmd_input)
+                .update_with_json(GenericStateUpdate::init_payload());
+                details.push(format!(
+                    "sub-operation init payload: {}",
+                    sub_cmd_init_state.payload
+                ));
+
+                self.pending = Some(PendingSubCommand {
+                    init_state: Some(sub_cmd_init_state),
+                    outcome,
+                });
+                (state.update(handlers.on_exec), details)
+            }
+
+            OperationAction::Iterate(target_json_path, handlers) => {
+                match OperationAction::process_iterate(
+                    state.clone(),
+                    &target_json_path,
+                    handlers.clone(),
+                ) {
+                    Ok(new_state) => (new_state, vec![]),
+                    Err(err) => (
+                        state.update(handlers.on_error),
+                        vec![format!("iteration failed: {err}")],
+                    ),
+                }
+            }
+
+            OperationAction::Parallel(sub_operation, items, max_concurrency, input, handlers) => {
+                let sub_cmd_input = input.extract_value_from(&state);
+                match state.clone().fan_out(
+                    &self.schema,
+                    &self.device,
+                    &self.operation,
+                    &cmd_id,
+                    &sub_operation,
+                    &items,
+                    sub_cmd_input,
+                    max_concurrency,
+                ) {
+                    Ok(new_state) => {
+                        let (new_state, started) = new_state.start_parallel_tasks();
+                        let details = vec![format!(
+                            "{} {sub_operation} sub-operations started",
+                            started.len()
+                        )];
+                        (new_state.update(handlers.on_exec), details)
+                    }
+                    Err(err) => (
+                        state.update(GenericStateUpdate::failed(err.to_string())),
+                        vec![format!(
+                            "parallel sub-operations cannot be triggered: {err}"
+                        )],
+                    ),
+                }
+            }
+
+            OperationAction::Join(handlers) => {
+                let mut state = state;
+                let mut details = vec![];
+                let running: Vec<_> = state
+                    .parallel_tasks()
+                    .map(|parallel| parallel.tasks)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|task| !task.is_pending() && !task.is_finished())
+                    .collect();
+                for task in running {
+                    let outcome = self.outcomes.next(&step).unwrap_or_default();
+                    let task_state = GenericCommandState::new(
+                        Topic::new_unchecked(&task.topic),
+                        task.status.clone(),
+                        json!({}),
+                    );
+                    let task_state = match &outcome.error {
+                        Some(error) => task_state.update(GenericStateUpdate::failed(error.clone())),
+                        None => task_state
+                            .update_with_json(outcome.output.clone().unwrap_or(json!({})))
+                            .update(GenericStateUpdate::successful()),
+                    };
+                    details.push(format!(
+                        "{} sub-operation {}",
+                        task.topic,
+                        outcome.result_description()
+                    ));
+                    if let Some(new_state) = state.update_parallel_task(&task_state) {
+                        state = new_state;
+                    }
+                }
+
+                match state.clone().join_parallel_tasks(&handlers) {
+                    JoinStep::Wait => (state, details),
+                    JoinStep::Start(new_state, started) => {
+                        details.push(format!("{} more sub-operations started", started.len()));
+                        (new_state, details)
+                    }
+                    JoinStep::Done(new_state, _) => (new_state, details),
+                }
+            }
+        }
+    }
+}
+
+/// The state returned by a builtin operation, given its mocked outcome
+fn builtin_result(state: GenericCommandState, outcome: &MockedOutcome) -> GenericCommandState {
+    match &outcome.error {
+        Some(error) => state.update(GenericStateUpdate::failed(error.clone())),
+        None => state
+            .update_with_json(outcome.output.clone().unwrap_or(json!({})))
+            .update(GenericStateUpdate::successful()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulate(
+        workflow: &str,
+        outcomes: &str,
+        payload: Value,
+    ) -> (GenericCommandState, Vec<(String, String)>) {
+        let workflow: OperationWorkflow = toml::from_str(workflow).unwrap();
+        let outcomes: MockedOutcomes = toml::from_str(outcomes).unwrap();
+        let mut simulation = WorkflowSimulation::try_new(workflow, outcomes).unwrap();
+        let init_state = simulation.init_state("123", payload);
+        let final_state = simulation.run(init_state, 100).unwrap();
+        let transitions = simulation
+            .transitions()
+            .iter()
+            .map(|t| (t.from.clone(), t.to.clone()))
+            .collect();
+        (final_state, transitions)
+    }
+
+    fn path(states: &[&str]) -> Vec<(String, String)> {
+        states
+            .windows(2)
+            .map(|w| (w[0].to_string(), w[1].to_string()))
+            .collect()
+    }
+
+    const FIRMWARE_UPDATE: &str = r#"
+operation = "firmware_update"
+
+[init]
+action = "proceed"
+on_success = "executing"
+
+[executing]
+script = "/usr/bin/firmware_handler.sh plan"
+on_success = "download"
+
+[download]
+action = "download"
+input.url = "${.payload.remoteUrl}"
+on_success = "install"
+
+[install]
+script = "/usr/bin/firmware_handler.sh install ${.payload.downloadedPath}"
+on_success = "successful"
+on_exit.1 = { status = "failed", reason = "install failed" }
+on_error = "rollback"
+
+[rollback]
+script = "/usr/bin/firmware_handler.sh rollback"
+on_success = "failed"
+
+[successful]
+action = "cleanup"
+
+[failed]
+action = "cleanup"
+"#;
+
+    #[test]
+    fn steps_are_successful_by_default() {
+        let (state, transitions) = simulate(
+            FIRMWARE_UPDATE,
+            "",
+            json!({"remoteUrl": "https://example.com/firmware.bin"}),
+        );
+        assert_eq!(
+            transitions,
+            path(&["init", "executing", "download", "install", "successful"])
+        );
+        assert_eq!(
+            state.payload["downloadedPath"],
+            json!("/tmp/firmware_update_123")
+        );
+    }
+
+    #[test]
+    fn script_exit_codes_are_mocked() {
+        let (state, transitions) = simulate(
+            FIRMWARE_UPDATE,
+            r#"install.exit_code = 1"#,
+            json!({"remoteUrl": "https://example.com/firmware.bin"}),
+        );
+        assert_eq!(
+            transitions,
+            path(&["init", "executing", "download", "install", "failed"])
+        );
+        assert_eq!(state.payload["reason"], json!("install failed"));
+
+        let (_, transitions) = simulate(
+            FIRMWARE_UPDATE,
+            r#"install.exit_code = 5"#,
+            json!({"remoteUrl": "https://example.com/firmware.bin"}),
+        );
+        assert_eq!(
+            transitions,
+            path(&[
+                "init",
+                "executing",
+                "download",
+                "install",
+                "rollback",
+                "failed"
+            ])
+        );
+    }
+
+    #[test]
+    fn script_outputs_are_mocked() {
+        let workflow = r#"
+operation = "check"
+
+[init]
+script = "/some/script.sh"
+
+[next]
+action = "proceed"
+on_success = "successful"
+"#;
+        let outcomes = r#"
+[init]
+stdout = """
+:::begin-tedge:::
+{"status": "next", "x": 42}
+:::end-tedge:::
+"""
+"#;
+        let (state, transitions) = simulate(workflow, outcomes, json!({}));
+        assert_eq!(transitions, path(&["init", "next", "successful"]));
+        assert_eq!(state.payload["x"], json!(42));
+    }
+
+    #[test]
+    fn download_errors_are_mocked() {
+        let (state, transitions) = simulate(
+            FIRMWARE_UPDATE,
+            r#"download.error = "connection refused""#,
+            json!({"remoteUrl": "https://example.com/firmware.bin"}),
+        );
+        assert_eq!(
+            transitions,
+            path(&["init", "executing", "download", "failed"])
+        );
+        assert_eq!(
+            state.payload["reason"],
+            json!("builtin 'download' action failed with: connection refused")
+        );
+    }
+
+    #[test]
+    fn sub_operation_outcomes_are_mocked() {
+        let workflow = r#"
+operation = "main"
+
+[init]
+operation = "sub"
+input.x = "${.payload.x}"
+on_exec = "awaiting"
+
+[awaiting]
+action = "await-operation-completion"
+on_success = "successful"
+on_error = { status = "failed", reason = "sub-operation failed" }
+output.y = "${.payload.y}"
+"#;
+        let (state, transitions) = simulate(workflow, r#"awaiting.output.y = 2"#, json!({"x": 1}));
+        assert_eq!(transitions, path(&["init", "awaiting", "successful"]));
+        assert_eq!(state.payload["y"], json!(2));
+
+        let (state, transitions) = simulate(workflow, r#"init.error = "oops""#, json!({"x": 1}));
+        assert_eq!(transitions, path(&["init", "awaiting", "failed"]));
+        assert_eq!(state.payload["reason"], json!("sub-operation failed"));
+    }
+
+    #[test]
+    fn repeated_steps_consume_outcomes_in_order() {
+        let workflow = r#"
+operation = "retry"
+
+[init]
+script = "/some/script.sh"
+on_success = "successful"
+on_error = "init"
+"#;
+        let outcomes = r#"
+[[init]]
+exit_code = 1
+
+[[init]]
+exit_code = 1
+
+[[init]]
+exit_code = 0
+"#;
+        let (_, transitions) = simulate(workflow, outcomes, json!({}));
+        assert_eq!(transitions, path(&["init", "init", "init", "successful"]));
+    }
+
+    #[test]
+    fn endless_loops_are_detected() {
+        let workflow: OperationWorkflow = toml::from_str(
+            r#"
+operation = "loop"
+
+[init]
+action = "proceed"
+on_success = "init"
+"#,
+        )
+        .unwrap();
+        let mut simulation =
+            WorkflowSimulation::try_new(workflow, MockedOutcomes::default()).unwrap();
+        let init_state = simulation.init_state("123", json!({}));
+        assert!(matches!(
+            simulation.run(init_state, 10),
+            Err(SimulationError::TooManySteps { max_steps: 10 })
+        ));
+    }
+}
@@ -426,6 +426,10 @@ impl GenericCommandState {
         self.status.as_str() == INIT
     }
 
+    pub fn is_scheduled(&self) -> bool {
+        self.status.as_str() == SCHEDULED
+    }
+
     pub fn is_executing(&self) -> bool {
         self.status.as_str() == EXECUTING
     }
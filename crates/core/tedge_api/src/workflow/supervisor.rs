@@ -256,6 +256,11 @@ impl WorkflowSupervisor {
             .lookup_sub_command(command_state.command_topic())
     }
 
+    /// Return the state of the command which triggered a sub-command as part of a parallel action, if any
+    pub fn parallel_command_state(&self, task_topic: &str) -> Option<&GenericCommandState> {
+        self.commands.lookup_parallel_command(task_topic)
+    }
+
     /// Return the state of the root command which execution leads to the execution of a leaf-command
     ///
     /// Return None, if the given command is not a sub-command
@@ -449,6 +454,11 @@ pub struct CommandBoard {
     /// TODO: use the timestamp to mark faulty any request making no progress
     #[serde(flatten)]
     commands: HashMap<TopicName, (Timestamp, GenericCommandState)>,
+
+    /// The topics of the commands that started parallel sub-commands,
+    /// indexed by the operation and command id these sub-commands refer to
+    #[serde(skip)]
+    parallel_commands: HashMap<String, TopicName>,
 }
 
 pub type TopicName = String;
@@ -456,7 +466,18 @@ pub type Timestamp = time::OffsetDateTime;
 
 impl CommandBoard {
     pub fn new(commands: HashMap<TopicName, (Timestamp, GenericCommandState)>) -> Self {
-        CommandBoard { commands }
+        let parallel_commands = commands
+            .iter()
+            .filter_map(|(topic, (_, command))| {
+                command
+                    .parallel_command_key()
+                    .map(|key| (key, topic.clone()))
+            })
+            .collect();
+        CommandBoard {
+            commands,
+            parallel_commands,
+        }
     }
 
     pub fn get_state(&self, command: &str) -> Option<&(Timestamp, GenericCommandState)> {
@@ -472,6 +493,16 @@ impl CommandBoard {
             .map(|(_, command)| command)
     }
 
+    /// Return the command that triggered a sub-command as part of a parallel action, if any
+    pub fn lookup_parallel_command(&self, task_topic: &str) -> Option<&GenericCommandState> {
+        let key = GenericCommandState::parallel_task_command_key(task_topic)?;
+        let topic = self.parallel_commands.get(key)?;
+        self.commands
+            .get(topic)
+            .map(|(_, command)| command)
+            .filter(|command| command.has_parallel_task(task_topic))
+    }
+
     /// Iterate over the pending commands
     pub fn iter(&self) -> impl Iterator<Item = &(Timestamp, GenericCommandState)> {
         self.commands.values()
@@ -494,6 +525,7 @@ impl CommandBoard {
                 topic: new_command.topic.name,
             }),
             None => {
+                self.index_parallel_command(&new_command);
                 let timestamp = time::OffsetDateTime::now_utc();
                 self.commands
                     .insert(new_command.topic.name.clone(), (timestamp, new_command));
@@ -516,6 +548,10 @@ impl CommandBoard {
             Some((timestamp, command_state)) => {
                 *timestamp = time::OffsetDateTime::now_utc();
                 *command_state = updated_command;
+                if let Some(key) = command_state.parallel_command_key() {
+                    self.parallel_commands
+                        .insert(key, command_state.topic.name.clone());
+                }
                 Ok(())
             }
         }
@@ -523,7 +559,18 @@ impl CommandBoard {
 
     /// Remove from the board an operation request
     pub fn remove(&mut self, topic_name: &String) {
-        self.commands.remove(topic_name);
+        if let Some((_, command)) = self.commands.remove(topic_name) {
+            if let Some(key) = command.parallel_command_key() {
+                self.parallel_commands.remove(&key);
+            }
+        }
+    }
+
+    fn index_parallel_command(&mut self, command: &GenericCommandState) {
+        if let Some(key) = command.parallel_command_key() {
+            self.parallel_commands
+                .insert(key, command.topic.name.clone());
+        }
     }
 }
 
@@ -8,6 +8,7 @@ use crate::workflow::ExitHandlers;
 use crate::workflow::GenericCommandState;
 use crate::workflow::GenericStateUpdate;
 use crate::workflow::IterateHandlers;
+use crate::workflow::JoinHandlers;
 use crate::workflow::OperationAction;
 use crate::workflow::OperationWorkflow;
 use crate::workflow::ScriptDefinitionError;
@@ -65,6 +66,14 @@ pub struct TomlOperationState {
     /// Values to be extracted from the sub-operation final state
     #[serde(default)]
     pub output: Option<Value>,
+
+    /// Array of items, one per sub-operation to be triggered by a parallel action
+    #[serde(default)]
+    pub items: Option<String>,
+
+    /// Maximum number of sub-operations running concurrently for a parallel action
+    #[serde(default)]
+    pub max_concurrency: Option<usize>,
 }
 
 /// User-friendly representation of an [OperationAction]
@@ -76,6 +85,7 @@ pub enum TomlOperationAction {
     Action(String),
     Operation(String),
     Iterate(String),
+    Parallel(String),
 }
 
 impl Default for TomlOperationAction {
@@ -161,6 +171,26 @@ impl TryFrom<(TomlOperationState, DefaultHandlers)> for OperationAction {
                 };
                 Ok(OperationAction::Iterate(json_path.to_string(), handlers))
             }
+            TomlOperationAction::Parallel(operation) => {
+                let handlers = ExecHandlers::try_from((input.handlers, defaults))?;
+                let Some(items) = input.items else {
+                    return Err(WorkflowDefinitionError::MissingParallelItems { operation });
+                };
+                let Some(json_path) = GenericCommandState::extract_path(&items) else {
+                    return Err(WorkflowDefinitionError::InvalidPathExpression(items));
+                };
+                if input.max_concurrency == Some(0) {
+                    return Err(WorkflowDefinitionError::InvalidMaxConcurrency { operation });
+                }
+                let cmd_input = input.input.try_into()?;
+                Ok(OperationAction::Parallel(
+                    operation,
+                    json_path.to_string(),
+                    input.max_concurrency,
+                    cmd_input,
+                    handlers,
+                ))
+            }
             TomlOperationAction::Action(command) => match command.as_str() {
                 "cleanup" => Ok(OperationAction::Clear),
                 "proceed" => {
@@ -186,6 +216,10 @@ impl TryFrom<(TomlOperationState, DefaultHandlers)> for OperationAction {
                         handlers, cmd_output,
                     ))
                 }
+                "join" => {
+                    let handlers = JoinHandlers::try_from((input.handlers, defaults))?;
+                    Ok(OperationAction::Join(handlers))
+                }
                 "builtin" => {
                     let exec_handlers = ExecHandlers::try_from((
                         input.handlers.clone(),
@@ -280,6 +314,9 @@ pub struct TomlExitHandlers {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     on_next: Option<TomlStateUpdate>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    on_partial: Option<TomlStateUpdate>,
 }
 
 impl TryFrom<TomlExitHandlers> for ExitHandlers {
@@ -444,6 +481,40 @@ impl TryFrom<(TomlExitHandlers, DefaultHandlers)> for IterateHandlers {
     }
 }
 
+impl TryFrom<(TomlExitHandlers, DefaultHandlers)> for JoinHandlers {
+    type Error = ScriptDefinitionError;
+
+    fn try_from(
+        (value, default): (TomlExitHandlers, DefaultHandlers),
+    ) -> Result<Self, Self::Error> {
+        let timeout = value
+            .timeout_second
+            .map(Duration::from_secs)
+            .or(default.timeout);
+        let on_success: GenericStateUpdate = value
+            .on_success
+            .map(|u| u.into())
+            .ok_or(ScriptDefinitionError::MissingOnSuccessHandler)?;
+        let on_error: GenericStateUpdate =
+            value.on_error.map(|u| u.into()).unwrap_or(default.on_error);
+        let on_partial = value
+            .on_partial
+            .map(|u| u.into())
+            .unwrap_or_else(|| on_error.clone());
+        let on_timeout = value
+            .on_timeout
+            .map(|u| u.into())
+            .unwrap_or(default.on_timeout);
+        Ok(JoinHandlers {
+            timeout,
+            on_success,
+            on_partial,
+            on_error,
+            on_timeout,
+        })
+    }
+}
+
 impl TryFrom<TomlExitHandlers> for DefaultHandlers {
     type Error = ScriptDefinitionError;
 
@@ -571,6 +642,7 @@ on_kill = { status = "failed", reason = "killed"}         # next status when kil
                 on_stdout: Vec::new(),
                 on_exec: None,
                 on_next: None,
+                on_partial: None,
             }
         )
     }
@@ -849,6 +921,134 @@ on_error = "failed"
         assert_matches!(res, Err(WorkflowDefinitionError::InvalidPathExpression(_)));
     }
 
+    #[test]
+    fn parse_parallel_and_join_toml() {
+        let file = r#"
+operation = "fleet_update"
+
+[init]
+action = "proceed"
+on_success = "fan_out"
+
+[fan_out]
+parallel = "firmware_update"
+items = "${.payload.devices}"
+max_concurrency = 5
+input.url = "${.payload.url}"
+on_exec = "join"
+
+[join]
+action = "join"
+on_success = "successful"
+on_partial = { status = "failed", reason = "Some devices have not been updated" }
+
+[successful]
+action = "cleanup"
+
+[failed]
+action = "cleanup"
+"#;
+        let input: TomlOperationWorkflow = toml::from_str(file).unwrap();
+        let workflow = OperationWorkflow::try_from(input).unwrap();
+
+        match workflow.states.get("fan_out").unwrap() {
+            OperationAction::Parallel(operation, items, max_concurrency, input, handlers) => {
+                assert_eq!(operation, "firmware_update");
+                assert_eq!(items, ".payload.devices");
+                assert_eq!(max_concurrency, &Some(5));
+                assert_eq!(
+                    input,
+                    &StateExcerpt::ExcerptMap(HashMap::from([(
+                        "url".to_string(),
+                        StateExcerpt::PathExpr(".payload.url".to_string())
+                    )]))
+                );
+                assert_eq!(handlers.on_exec, "join".into());
+            }
+            other => panic!("Expected parallel action, but got {other}"),
+        }
+
+        match workflow.states.get("join").unwrap() {
+            OperationAction::Join(JoinHandlers {
+                timeout,
+                on_success,
+                on_partial,
+                on_error,
+                on_timeout,
+            }) => {
+                assert_eq!(timeout, &None);
+                assert_eq!(on_success, &"successful".into());
+                assert_eq!(
+                    on_partial,
+                    &GenericStateUpdate {
+                        status: "failed".to_string(),
+                        reason: Some("Some devices have not been updated".to_string())
+                    }
+                );
+                assert_eq!(on_error, &GenericStateUpdate::unknown_error());
+                assert_eq!(on_timeout, &GenericStateUpdate::timeout());
+            }
+            other => panic!("Expected join action, but got {other}"),
+        }
+    }
+
+    #[test]
+    fn join_on_partial_defaults_to_on_error() {
+        let file = r#"
+action = "join"
+on_success = "successful"
+on_error = "rollback"
+"#;
+        let input: TomlOperationState = toml::from_str(file).unwrap();
+        let action = OperationAction::try_from(input).unwrap();
+        assert_matches!(action, OperationAction::Join(JoinHandlers { on_partial, .. }) if on_partial == "rollback".into());
+    }
+
+    #[test]
+    fn parse_join_timeout() {
+        let file = r#"
+action = "join"
+on_success = "successful"
+timeout_second = 600
+on_timeout = { status = "failed", reason = "Some devices have not reported" }
+"#;
+        let input: TomlOperationState = toml::from_str(file).unwrap();
+        let action = OperationAction::try_from(input).unwrap();
+        assert_matches!(
+            action,
+            OperationAction::Join(JoinHandlers { timeout, on_timeout, .. })
+            if timeout == Some(Duration::from_secs(600))
+            && on_timeout.reason.as_deref() == Some("Some devices have not reported")
+        );
+    }
+
+    #[test]
+    fn parallel_parse_fails_without_items() {
+        let file = r#"
+parallel = "firmware_update"
+on_exec = "join"
+"#;
+        let input: TomlOperationState = toml::from_str(file).unwrap();
+        let res = OperationAction::try_from(input);
+        assert_matches!(res, Err(WorkflowDefinitionError::MissingParallelItems { operation }) if operation == *"firmware_update");
+    }
+
+    #[test]
+    fn parallel_parse_fails_with_zero_max_concurrency() {
+        let file = r#"
+parallel = "firmware_update"
+items = "${.payload.devices}"
+max_concurrency = 0
+on_exec = "join"
+"#;
+        let input: TomlOperationState = toml::from_str(file).unwrap();
+        let res = OperationAction::try_from(input);
+        assert_matches!(
+            res,
+            Err(WorkflowDefinitionError::InvalidMaxConcurrency { .. })
+        );
+    }
+
     #[test]
     fn parse_download_action() {
         let file = r#"
@@ -13,6 +13,7 @@ use tedge_config::tedge_toml::ProfileName;
 use tedge_config::TEdgeConfig;
 use tedge_file_system_ext::FsWatchActorBuilder;
 use tedge_flows::FlowsMapperBuilder;
+use tedge_http_ext::HttpActor;
 use tedge_mqtt_bridge::load_bridge_rules_from_directory;
 use tedge_mqtt_bridge::persist_bridge_config_file;
 use tedge_mqtt_bridge::rumqttc::Transport;
@@ -99,7 +100,7 @@ impl TEdgeComponent for AwsMapper {
         let mapper_dir = self.mapper_dir(config_dir);
         let mut flows = crate::mapper_flow_registry(&tedge_config, &mapper_dir).await?;
         aws_converter.persist_builtin_flow(&mut flows).await?;
-        let service_config = flows_config(&tedge_config, &aws_mapper_name)?;
+        let service_config = flows_config(&tedge_config, &aws_mapper_name, &mapper_dir)?;
 
         let mut fs_actor = FsWatchActorBuilder::new();
         let mut cmd_watcher_actor = WatchActorBuilder::new();
@@ -108,8 +109,11 @@ impl TEdgeComponent for AwsMapper {
         flows_mapper.connect(&mut mqtt_actor);
         flows_mapper.connect_fs(&mut fs_actor);
         flows_mapper.connect_cmd(&mut cmd_watcher_actor);
+        let mut http_actor = HttpActor::new(tedge_config.http.client_tls_config()?).builder();
+        flows_mapper.connect_http(&mut http_actor);
 
         runtime.spawn(flows_mapper).await?;
+        runtime.spawn(http_actor).await?;
         runtime.spawn(fs_actor).await?;
         runtime.spawn(cmd_watcher_actor).await?;
         runtime.spawn(mqtt_actor).await?;
@@ -234,6 +238,13 @@ mod tests {
             "$aws/things/test-device-id/shadow/#"
         ));
 
+        // Jobs (bidirectional)
+        assert!(has_local_subscription(&rules, "aws/jobs/#"));
+        assert!(has_remote_subscription(
+            &rules,
+            "$aws/things/test-device-id/jobs/#"
+        ));
+
         // Connection check
         assert!(has_local_subscription(&rules, "aws/test-connection"));
         assert!(has_remote_subscription(
@@ -8,14 +8,17 @@ use az_mapper_ext::AzureConverter;
 use tedge_actors::Runtime;
 use tedge_api::mqtt_topics::MqttSchema;
 use tedge_api::service_health_topic;
+use tedge_config::models::HTTPS_PORT;
 use tedge_config::tedge_toml::mapper_config::AzMapperSpecificConfig;
 use tedge_config::tedge_toml::ProfileName;
 use tedge_config::TEdgeConfig;
 use tedge_file_system_ext::FsWatchActorBuilder;
 use tedge_flows::FlowsMapperBuilder;
+use tedge_http_ext::HttpActor;
 use tedge_mqtt_bridge::load_bridge_rules_from_directory;
 use tedge_mqtt_bridge::persist_bridge_config_file;
 use tedge_mqtt_bridge::rumqttc::Transport;
+use tedge_mqtt_bridge::use_websocket;
 use tedge_mqtt_bridge::AuthMethod;
 use tedge_mqtt_bridge::BridgeConfig;
 use tedge_mqtt_bridge::MqttBridgeActorBuilder;
@@ -56,17 +59,20 @@ impl TEdgeComponent for AzureMapper {
             let remote_clientid = az_config.device.id()?;
             let rules = bridge_rules(&tedge_config, self.profile.as_ref()).await?;
 
-            let mut cloud_config = tedge_mqtt_bridge::MqttOptions::new(
-                &remote_clientid,
-                az_config.url().or_config_not_set()?.to_string(),
-                8883,
-            );
+            let az_url = az_config.url().or_config_not_set()?;
+            let websocket = az_config.cloud_specific.bridge.websocket;
+            let mut cloud_config = if websocket {
+                tedge_mqtt_bridge::MqttOptions::new(
+                    &remote_clientid,
+                    format!("wss://{az_url}:{HTTPS_PORT}/$iothub/websocket"),
+                    HTTPS_PORT,
+                )
+            } else {
+                tedge_mqtt_bridge::MqttOptions::new(&remote_clientid, az_url.to_string(), 8883)
+            };
             cloud_config.set_clean_session(false);
             cloud_config.set_credentials(
-                format!(
-                    "{}/{remote_clientid}/?api-version=2018-06-30",
-                    az_config.url().or_config_not_set()?
-                ),
+                format!("{az_url}/{remote_clientid}/?api-version=2018-06-30"),
                 "",
             );
             cloud_config.set_keep_alive(az_config.bridge.keepalive_interval.duration());
@@ -75,6 +81,9 @@ impl TEdgeComponent for AzureMapper {
                 .mqtt_client_config_rustls(&az_config)
                 .context("Failed to create MQTT TLS config")?;
             cloud_config.set_transport(Transport::tls_with_config(tls_config.into()));
+            if websocket {
+                use_websocket(&mut cloud_config)?;
+            }
 
             configure_proxy(&tedge_config, &mut cloud_config)?;
 
@@ -103,11 +112,12 @@ impl TEdgeComponent for AzureMapper {
             prefix,
             az_config.mapper.mqtt.max_payload_size.0,
             az_config.topics.to_string(),
+            az_config.cloud_specific.mapper.c2d_topic.clone(),
         );
         let mapper_dir = self.mapper_dir(config_dir);
         let mut flows = crate::mapper_flow_registry(&tedge_config, &mapper_dir).await?;
         az_converter.persist_builtin_flow(&mut flows).await?;
-        let service_config = flows_config(&tedge_config, &az_mapper_name)?;
+        let service_config = flows_config(&tedge_config, &az_mapper_name, &mapper_dir)?;
         let mut fs_actor = FsWatchActorBuilder::new();
         let mut cmd_watcher_actor = WatchActorBuilder::new();
 
@@ -115,8 +125,11 @@ impl TEdgeComponent for AzureMapper {
         flows_mapper.connect(&mut mqtt_actor);
         flows_mapper.connect_fs(&mut fs_actor);
         flows_mapper.connect_cmd(&mut cmd_watcher_actor);
+        let mut http_actor = HttpActor::new(tedge_config.http.client_tls_config()?).builder();
+        flows_mapper.connect_http(&mut http_actor);
 
         runtime.spawn(flows_mapper).await?;
+        runtime.spawn(http_actor).await?;
         runtime.spawn(fs_actor).await?;
         runtime.spawn(cmd_watcher_actor).await?;
         runtime.spawn(mqtt_actor).await?;
@@ -240,7 +253,14 @@ mod tests {
 
         // Digital twin
         assert!(has_local_subscription(&rules, "az/twin/GET/#"));
-        assert!(has_local_subscription(&rules, "az/twin/PATCH/#"));
+        assert!(has_local_subscription(
+            &rules,
+            "az/twin/PATCH/properties/reported/#"
+        ));
+        assert!(has_remote_subscription(
+            &rules,
+            "$iothub/twin/PATCH/properties/desired/#"
+        ));
         assert!(has_remote_subscription(&rules, "$iothub/twin/res/#"));
     }
 
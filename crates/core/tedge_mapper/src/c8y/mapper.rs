@@ -2,6 +2,7 @@ use crate::core::component::TEdgeComponent;
 use crate::core::mapper::start_basic_actors;
 use crate::core::mqtt::configure_proxy;
 use crate::flows_config;
+use anyhow::bail;
 use anyhow::Context;
 use async_trait::async_trait;
 use c8y_api::http_proxy::read_c8y_credentials;
@@ -15,6 +16,7 @@ use mqtt_channel::Config;
 use tedge_actors::Runtime;
 use tedge_api::entity::EntityExternalId;
 use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_config::models::HTTPS_PORT;
 use tedge_config::models::MQTT_CORE_TLS_PORT;
 use tedge_config::models::MQTT_SERVICE_TLS_PORT;
 use tedge_config::tedge_toml::mapper_config;
@@ -32,6 +34,7 @@ use tedge_mqtt_bridge::rumqttc::LastWill;
 use tedge_mqtt_bridge::rumqttc::Publish;
 use tedge_mqtt_bridge::rumqttc::Transport;
 use tedge_mqtt_bridge::use_credentials;
+use tedge_mqtt_bridge::use_websocket;
 use tedge_mqtt_bridge::AuthMethod;
 use tedge_mqtt_bridge::BridgeConfig;
 use tedge_mqtt_bridge::MqttBridgeActorBuilder;
@@ -168,12 +171,13 @@ impl TEdgeComponent for CumulocityMapper {
         let mapper_dir = self.mapper_dir(cfg_dir);
         let mut flows = crate::mapper_flow_registry(&tedge_config, &mapper_dir).await?;
         c8y_mapper_actor.persist_builtin_flows(&mut flows).await?;
-        let service_config = flows_config(&tedge_config, &c8y_mapper_name)?;
+        let service_config = flows_config(&tedge_config, &c8y_mapper_name, &mapper_dir)?;
 
         let mut flows_mapper = FlowsMapperBuilder::try_new(flows, service_config).await?;
         flows_mapper.connect(&mut mqtt_actor);
         flows_mapper.connect_fs(&mut fs_watch_actor);
         flows_mapper.connect_cmd(&mut cmd_watcher_actor);
+        flows_mapper.connect_http(&mut http_actor);
         c8y_mapper_actor.set_flow_context(flows_mapper.context_handle());
 
         runtime.spawn(flows_mapper).await?;
@@ -250,16 +254,24 @@ async fn mqtt_bridge_config(
     let use_mqtt_service = c8y_config.cloud_specific.mqtt_service.enabled;
     let bridge_config = bridge_rules(tedge_config, cloud_profile, auth_method).await?;
 
+    let websocket = c8y_config.cloud_specific.bridge.websocket;
+    if websocket && use_mqtt_service {
+        bail!("`c8y.bridge.websocket` cannot be used together with `c8y.mqtt_service.enabled`");
+    }
+
     let c8y = &c8y_config.mqtt().or_config_not_set()?;
     let mut c8y_port = c8y.port().into();
     if use_mqtt_service && c8y_port == MQTT_CORE_TLS_PORT {
         c8y_port = MQTT_SERVICE_TLS_PORT;
     }
-    let mut cloud_config = tedge_mqtt_bridge::MqttOptions::new(
-        c8y_config.device.id()?,
-        c8y.host().to_string(),
-        c8y_port,
-    );
+    let broker_addr = if websocket {
+        c8y_port = HTTPS_PORT;
+        format!("wss://{}:{c8y_port}/mqtt", c8y.host())
+    } else {
+        c8y.host().to_string()
+    };
+    let mut cloud_config =
+        tedge_mqtt_bridge::MqttOptions::new(c8y_config.device.id()?, broker_addr, c8y_port);
     // Cumulocity tells us not to not set clean session to false, so don't
     // https://cumulocity.com/docs/device-integration/mqtt/#mqtt-clean-session
     cloud_config.set_clean_session(true);
@@ -283,6 +295,9 @@ async fn mqtt_bridge_config(
             )?;
         }
     }
+    if websocket {
+        use_websocket(&mut cloud_config)?;
+    }
 
     let main_device_xid: EntityExternalId = c8y_config.device.id()?.into();
     let service_type = &tedge_config.service.ty;
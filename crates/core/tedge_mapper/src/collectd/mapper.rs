@@ -1,14 +1,21 @@
 use crate::core::component::TEdgeComponent;
 use crate::core::mapper::start_basic_actors;
+use anyhow::bail;
+use anyhow::Context;
 use async_trait::async_trait;
 use batcher::BatchingActorBuilder;
 use collectd_ext::actor::CollectdActorBuilder;
+use collectd_ext::network::NetworkInput;
+use collectd_ext::network::NetworkSecurity;
+use collectd_ext::network::SecurityLevel;
 use mqtt_channel::QoS;
 use mqtt_channel::Topic;
 use mqtt_channel::TopicFilter;
+use std::net::SocketAddr;
 use tedge_actors::MessageSink;
 use tedge_actors::NoConfig;
 use tedge_actors::Runtime;
+use tedge_config::models::CollectdSecurityLevel;
 use tedge_config::TEdgeConfig;
 use tedge_utils::paths::TedgePaths;
 
@@ -26,6 +33,42 @@ impl CollectdMapper {
     fn output_topic() -> Topic {
         Topic::new_unchecked(COLLECTD_OUTPUT_TOPIC)
     }
+
+    async fn network_input(tedge_config: &TEdgeConfig) -> anyhow::Result<Option<NetworkInput>> {
+        let config = &tedge_config.collectd.network;
+        if !config.enable {
+            return Ok(None);
+        }
+
+        let level = match config.security_level {
+            CollectdSecurityLevel::None => SecurityLevel::None,
+            CollectdSecurityLevel::Sign => SecurityLevel::Sign,
+            CollectdSecurityLevel::Encrypt => SecurityLevel::Encrypt,
+        };
+        let users = match config.auth_file.or_none() {
+            Some(auth_file) => {
+                let content = tokio::fs::read_to_string(auth_file)
+                    .await
+                    .with_context(|| format!("Failed to read collectd auth file {auth_file}"))?;
+                NetworkSecurity::parse_auth_file(&content)
+            }
+            None => {
+                // Otherwise, all the signed and encrypted packets would be rejected
+                if level != SecurityLevel::None {
+                    bail!(
+                        "collectd.network.auth_file must be set when collectd.network.security_level is '{}'",
+                        config.security_level
+                    );
+                }
+                Default::default()
+            }
+        };
+
+        Ok(Some(NetworkInput {
+            address: SocketAddr::new(config.bind.address, config.bind.port),
+            security: NetworkSecurity { level, users },
+        }))
+    }
 }
 
 #[async_trait]
@@ -43,6 +86,9 @@ impl TEdgeComponent for CollectdMapper {
 
         let mut batching_actor = BatchingActorBuilder::default();
         let mut collectd_actor = CollectdActorBuilder::new(input_topic);
+        if let Some(network_input) = CollectdMapper::network_input(&tedge_config).await? {
+            collectd_actor = collectd_actor.with_network_input(network_input);
+        }
 
         collectd_actor.add_input(&mut mqtt_actor);
         batching_actor.connect_source(NoConfig, &mut collectd_actor);
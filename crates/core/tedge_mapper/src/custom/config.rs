@@ -9,12 +9,11 @@
 use anyhow::Context;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
+use tedge_config::models::BrokerUrl;
 use tedge_config::models::CloudType;
-use tedge_config::models::HostPort;
 use tedge_config::models::MqttPayloadLimit;
 use tedge_config::models::SecondsOrHumanTime;
 use tedge_config::models::MQTT_MAX_PAYLOAD_SIZE;
-use tedge_config::models::MQTT_TLS_PORT;
 
 /// Authentication method for the cloud broker connection.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
@@ -58,8 +57,9 @@ pub struct CustomMapperConfig {
     pub table: toml::Table,
     /// Cloud integration type, if this mapper delegates to a built-in cloud integration.
     pub cloud_type: Option<CloudType>,
-    /// Cloud broker URL in `{host}:{port}` format (port defaults to 8883).
-    pub url: Option<HostPort<MQTT_TLS_PORT>>,
+    /// Cloud broker URL, either in `{host}:{port}` format (port defaults to 8883),
+    /// or as a `wss://{host}[:{port}]/{path}` URL for MQTT over WebSocket (port defaults to 443).
+    pub url: Option<BrokerUrl>,
     /// Device identity and TLS certificate settings.
     pub device: Option<DeviceConfig>,
     /// MQTT bridge settings (keepalive, clean session).
@@ -145,7 +145,7 @@ fn default_max_payload_size() -> MqttPayloadLimit {
 #[derive(Debug, serde::Deserialize)]
 struct RawConfig {
     cloud_type: Option<CloudType>,
-    url: Option<HostPort<MQTT_TLS_PORT>>,
+    url: Option<BrokerUrl>,
     device: Option<DeviceConfig>,
     #[serde(default)]
     bridge: BridgeConfig,
@@ -448,6 +448,30 @@ url = "mqtt.example.com"
         assert_eq!(config.url.unwrap().port().0, 8883);
     }
 
+    #[tokio::test]
+    async fn parses_websocket_url() {
+        let ttd = TempTedgeDir::new();
+        let mapper_dir = ttd.utf8_path().join("mappers/websocket");
+        tokio::fs::create_dir_all(&mapper_dir).await.unwrap();
+
+        tokio::fs::write(
+            mapper_dir.join("mapper.toml"),
+            r#"
+url = "wss://mqtt.example.com/mqtt"
+"#,
+        )
+        .await
+        .unwrap();
+
+        let config = load_mapper_config(&mapper_dir).await.unwrap().unwrap();
+        let url = config.url.unwrap();
+        assert_eq!(url.port().0, 443);
+        assert_eq!(
+            url.websocket_url().as_deref(),
+            Some("wss://mqtt.example.com:443/mqtt")
+        );
+    }
+
     #[tokio::test]
     async fn parses_full_schema() {
         let ttd = TempTedgeDir::new();
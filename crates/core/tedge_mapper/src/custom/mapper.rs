@@ -29,10 +29,12 @@ use tedge_config::TEdgeConfig;
 use tedge_file_system_ext::FsWatchActorBuilder;
 use tedge_flows::FlowsMapperBuilder;
 use tedge_flows::FlowsMapperConfig;
+use tedge_http_ext::HttpActor;
 use tedge_mqtt_bridge::config_toml::AuthMethod;
 use tedge_mqtt_bridge::load_bridge_rules_from_directory;
 use tedge_mqtt_bridge::rumqttc::Transport;
 use tedge_mqtt_bridge::use_credentials;
+use tedge_mqtt_bridge::use_websocket;
 use tedge_mqtt_bridge::MqttBridgeActorBuilder;
 use tedge_mqtt_bridge::MqttOptions;
 use tedge_utils::paths::ManagedDir;
@@ -156,9 +158,9 @@ async fn list_available_mappers(mappers_root: &Utf8Path) -> Vec<String> {
 ///
 /// Accepts an already-resolved [`EffectiveMapperConfig`] (produced by
 /// [`resolve_effective_config`]) and handles only MQTT wiring: TLS setup or
-/// password credentials, client ID, keepalive, and proxy. Resolution of cert/key
-/// paths, auth method, and device identity is done upstream in
-/// `resolve_effective_config`.
+/// password credentials, client ID, keepalive, WebSocket transport, and proxy.
+/// Resolution of cert/key paths, auth method, and device identity is done
+/// upstream in `resolve_effective_config`.
 ///
 /// Returns `(MqttOptions, AuthMethod)` so the caller can pass `AuthMethod` to
 /// `load_bridge_rules_from_directory`.
@@ -172,7 +174,10 @@ pub async fn build_cloud_mqtt_options(
         format!("'{mapper_dir}/mapper.toml' is missing a 'url' field required for the MQTT bridge")
     })?;
 
-    let mut cloud_config = MqttOptions::new(service_name, url.host().to_string(), url.port().0);
+    let broker_addr = url
+        .websocket_url()
+        .unwrap_or_else(|| url.host().to_string());
+    let mut cloud_config = MqttOptions::new(service_name, broker_addr, url.port().0);
     cloud_config.set_clean_session(config.bridge.clean_session);
     if let Some(interval) = &config.bridge.keepalive_interval {
         cloud_config.set_keep_alive(interval.duration());
@@ -181,12 +186,20 @@ pub async fn build_cloud_mqtt_options(
     let tls_enabled = match config.bridge.tls.enable {
         BridgeTlsEnable::True => true,
         BridgeTlsEnable::False => false,
+        BridgeTlsEnable::Auto if url.is_websocket() => true,
         BridgeTlsEnable::Auto => match url.port().0 {
             1883 => false,
             _ => true, // 8883 and any other port default to TLS on
         },
     };
 
+    if !tls_enabled && url.is_websocket() {
+        anyhow::bail!(
+            "MQTT over WebSocket requires TLS, but TLS is disabled for this mapper. \
+             Either set `[bridge]\ntls = \"on\"` in mapper.toml, or use a `host:port` url."
+        );
+    }
+
     let ca_path = &config.root_cert_path.value;
 
     if !tls_enabled && config.effective_auth.value == AuthMethod::Certificate {
@@ -263,6 +276,9 @@ pub async fn build_cloud_mqtt_options(
         })?;
     cloud_config.set_client_id(device_id);
 
+    if url.is_websocket() {
+        use_websocket(&mut cloud_config)?;
+    }
     configure_proxy(tedge_config, &mut cloud_config)?;
 
     Ok((cloud_config, config.effective_auth.value))
@@ -297,7 +313,8 @@ async fn build_flows_actors(
         stats_config.on_message,
         stats_config.on_interval,
         stats_config.on_startup,
-    );
+    )
+    .with_context_store(crate::context_store_config(tedge_config, mapper_dir.path()));
 
     let flows = crate::mapper_flow_registry(tedge_config, mapper_dir).await?;
     let fs_actor = FsWatchActorBuilder::new();
@@ -386,8 +403,11 @@ impl TEdgeComponent for CustomMapper {
         flows_mapper.connect(&mut mqtt_actor);
         flows_mapper.connect_fs(&mut fs_actor);
         flows_mapper.connect_cmd(&mut cmd_watcher_actor);
+        let mut http_actor = HttpActor::new(tedge_config.http.client_tls_config()?).builder();
+        flows_mapper.connect_http(&mut http_actor);
 
         runtime.spawn(flows_mapper).await?;
+        runtime.spawn(http_actor).await?;
         runtime.spawn(fs_actor).await?;
         runtime.spawn(cmd_watcher_actor).await?;
 
@@ -1200,5 +1220,86 @@ AwEHoUQDQgAEdklRDw9+AAMRbpNMWJutKe4QO/tUlvrBR2swUYN9onxXdKNjJ/k3\n\
                 );
             }
         }
+
+        mod websocket {
+            use super::*;
+
+            #[tokio::test]
+            async fn wss_url_with_cert_auth_connects_over_websocket() {
+                let ttd = TempTedgeDir::new();
+                let mapper_dir = ttd.utf8_path().join("mappers/testmapper");
+                let (cert, key) = write_cert(ttd.utf8_path()).await;
+                let tedge_config = TEdgeConfig::load_toml_str(&format!(
+                    "device.cert_path = \"{cert}\"\ndevice.key_path = \"{key}\"\n"
+                ));
+                let config = make_config(Some("wss://mqtt.example.com/mqtt"));
+                let effective = resolve(&config, &tedge_config).await;
+
+                let (config, _) =
+                    build_cloud_mqtt_options(&effective, "svc", &mapper_dir, &tedge_config)
+                        .await
+                        .unwrap();
+
+                assert!(
+                    matches!(
+                        config.transport(),
+                        tedge_mqtt_bridge::rumqttc::Transport::Wss(..)
+                    ),
+                    "Transport should be WSS",
+                );
+                assert_eq!(
+                    config.broker_address(),
+                    ("wss://mqtt.example.com:443/mqtt".to_owned(), 443)
+                );
+            }
+
+            #[tokio::test]
+            async fn wss_url_with_password_auth_connects_over_websocket() {
+                let ttd = TempTedgeDir::new();
+                let mapper_dir = ttd.utf8_path().join("mappers/testmapper");
+                let creds_path = ttd.utf8_path().join("creds.toml");
+                write_creds(&creds_path).await;
+                let tedge_config = TEdgeConfig::load_toml_str("device.id = \"test-device\"");
+                let mut config = make_config(Some("wss://mqtt.example.com:8443/mqtt"));
+                config.credentials_path = Some(creds_path);
+                let effective = resolve(&config, &tedge_config).await;
+
+                let (config, _) =
+                    build_cloud_mqtt_options(&effective, "svc", &mapper_dir, &tedge_config)
+                        .await
+                        .unwrap();
+
+                assert!(
+                    matches!(
+                        config.transport(),
+                        tedge_mqtt_bridge::rumqttc::Transport::Wss(..)
+                    ),
+                    "Transport should be WSS",
+                );
+                assert!(config.credentials().is_some());
+            }
+
+            #[tokio::test]
+            async fn wss_url_with_tls_off_fails() {
+                let ttd = TempTedgeDir::new();
+                let mapper_dir = ttd.utf8_path().join("mappers/testmapper");
+                let creds_path = ttd.utf8_path().join("creds.toml");
+                write_creds(&creds_path).await;
+                let tedge_config = TEdgeConfig::load_toml_str("device.id = \"test-device\"");
+                let mut config = make_config(Some("wss://mqtt.example.com/mqtt"));
+                config.bridge.tls.enable = BridgeTlsEnable::False;
+                config.credentials_path = Some(creds_path);
+                let effective = resolve(&config, &tedge_config).await;
+
+                let err = build_cloud_mqtt_options(&effective, "svc", &mapper_dir, &tedge_config)
+                    .await
+                    .unwrap_err();
+                assert_eq!(
+                    err.to_string(),
+                    "MQTT over WebSocket requires TLS, but TLS is disabled for this mapper. \
+                     Either set `[bridge]\ntls = \"on\"` in mapper.toml, or use a `host:port` url."
+                );
+            }
+        }
     }
 }
@@ -11,10 +11,9 @@ use camino::Utf8Path;
 use camino::Utf8PathBuf;
 use certificate::PemCertificate;
 use tedge_config::cli::format_config_set_cmd;
-use tedge_config::models::HostPort;
+use tedge_config::models::BrokerUrl;
 use tedge_config::models::MqttPayloadLimit;
 use tedge_config::models::SecondsOrHumanTime;
-use tedge_config::models::MQTT_TLS_PORT;
 use tedge_config::tedge_toml::Cloud;
 use tedge_config::TEdgeConfig;
 use tedge_flows::MapperParams;
@@ -151,7 +150,7 @@ pub struct EffectiveMapperConfig {
     /// appear as `null` rather than being omitted as they would be in TOML serialisation).
     schema: serde_json::Value,
     /// Cloud broker URL (from `mapper.toml`), if configured.
-    pub url: Option<Sourced<HostPort<MQTT_TLS_PORT>>>,
+    pub url: Option<Sourced<BrokerUrl>>,
     /// Effective MQTT client ID. `None` when cert auth is in use but the cert is
     /// unreadable.
     pub device_id: Option<Sourced<String>>,
@@ -455,7 +454,7 @@ impl MapperParams for EffectiveMapperConfig {
 /// such as `device.cert_path` are always reachable in the serialised schema.
 #[derive(serde::Serialize)]
 struct CustomMapperSchema<'a> {
-    url: Option<&'a HostPort<MQTT_TLS_PORT>>,
+    url: Option<&'a BrokerUrl>,
     device: DeviceSchema<'a>,
     bridge: BridgeSchema<'a>,
     auth_method: AuthMethodConfig,
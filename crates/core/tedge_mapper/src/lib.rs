@@ -10,6 +10,7 @@ use crate::custom::mapper::CustomMapper;
 use anyhow::bail;
 use anyhow::Context;
 use camino::Utf8Path;
+use camino::Utf8PathBuf;
 use clap::Parser;
 use flockfile::check_another_instance_is_not_running;
 use flockfile::Flockfile;
@@ -26,6 +27,8 @@ use tedge_config::tedge_toml::ProfileName;
 use tedge_config::TEdgeConfig;
 use tedge_flows::BaseFlowRegistry;
 use tedge_flows::ConnectedFlowRegistry;
+use tedge_flows::ContextStoreConfig;
+use tedge_flows::DeadLetterStoreConfig;
 use tedge_flows::FlowRegistryExt;
 use tedge_flows::FlowsMapperConfig;
 use tedge_flows::UpdateFlowRegistryError;
@@ -391,12 +394,14 @@ pub fn mapper_dir(
 pub(crate) fn flows_config(
     tedge_config: &TEdgeConfig,
     mapper_name: &str,
+    mapper_dir: &ManagedDir,
 ) -> Result<FlowsMapperConfig, anyhow::Error> {
     let te = tedge_config.mqtt.topic_root.as_str();
     let service_topic_id = EntityTopicId::default_main_service(mapper_name)?;
 
     let stats_config = &tedge_config.flows.stats;
     let mem_config = &tedge_config.flows.memory;
+    let wasm_config = &tedge_config.flows.wasm;
     let flows_config = FlowsMapperConfig::new(
         &format!("{te}/{service_topic_id}"),
         stats_config.interval.duration(),
@@ -407,10 +412,55 @@ pub(crate) fn flows_config(
     .with_js_config(
         mem_config.heap_size as usize,
         mem_config.stack_size as usize,
-    );
+    )
+    .with_wasm_limits(wasm_config.memory_size as usize, wasm_config.fuel as u64)
+    .with_context_store(context_store_config(tedge_config, mapper_dir.path()))
+    .with_dead_letter_store(dead_letter_store_config(tedge_config, mapper_dir.path()));
     Ok(flows_config)
 }
 
+pub(crate) fn context_store_config(
+    tedge_config: &TEdgeConfig,
+    mapper_dir: &Utf8Path,
+) -> ContextStoreConfig {
+    let context_config = &tedge_config.flows.context;
+    ContextStoreConfig {
+        path: flows_context_path(tedge_config, mapper_dir),
+        snapshot_interval: context_config.snapshot_interval.duration(),
+        max_entries: context_config.max_entries as usize,
+    }
+}
+
+/// The file where a mapper persists the values of its flow contexts
+pub fn flows_context_path(tedge_config: &TEdgeConfig, mapper_dir: &Utf8Path) -> Utf8PathBuf {
+    let mapper = mapper_dir.file_name().unwrap_or("local");
+    tedge_config
+        .data
+        .path
+        .join("flows-context")
+        .join(format!("{mapper}.json"))
+}
+
+pub(crate) fn dead_letter_store_config(
+    tedge_config: &TEdgeConfig,
+    mapper_dir: &Utf8Path,
+) -> DeadLetterStoreConfig {
+    DeadLetterStoreConfig {
+        path: flows_errors_path(tedge_config, mapper_dir),
+        max_entries: tedge_config.flows.errors.max_entries as usize,
+    }
+}
+
+/// The file where a mapper keeps the messages its flows failed to process
+pub fn flows_errors_path(tedge_config: &TEdgeConfig, mapper_dir: &Utf8Path) -> Utf8PathBuf {
+    let mapper = mapper_dir.file_name().unwrap_or("local");
+    tedge_config
+        .data
+        .path
+        .join("flows-errors")
+        .join(format!("{mapper}.jsonl"))
+}
+
 fn load_builtin_transformers(flows: &mut impl FlowRegistryExt) {
     #[cfg(feature = "c8y")]
     c8y_mapper_ext::load_builtin_transformers(flows);
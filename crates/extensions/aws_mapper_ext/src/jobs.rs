@@ -0,0 +1,615 @@
+//! Translate AWS IoT Jobs into thin-edge commands, reporting the progress of these commands back to AWS.
+//!
+//! The mapper gets the next pending job of the thing from `$aws/things/{thing}/jobs/notify-next`
+//! (locally `aws/jobs/notify-next`), and creates the matching thin-edge command,
+//! using the `operation` property of the job document as the command type
+//! and the other properties as the command parameters.
+//! The command is created for the main device unless the job document specifies another `@topic-id`.
+//! Only the operations listed by the `operations` config of the step are mapped,
+//! the flow only listening to the commands of these operations.
+//!
+//! ```json
+//! {
+//!   "operation": "firmware_update",
+//!   "@topic-id": "device/child1//",
+//!   "name": "core-image-tedge",
+//!   "version": "1.0.1",
+//!   "remoteUrl": "https://example.com/core-image-tedge.wic.bz2"
+//! }
+//! ```
+//!
+//! The job execution is marked `IN_PROGRESS` as soon as the command is processed by the agent,
+//! then `SUCCEEDED` or `FAILED` when the command reaches a final state, the command being then cleared.
+use crate::retained;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tedge_api::mqtt_topics::Channel;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_api::mqtt_topics::OperationType;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+/// The property of a job document giving the type of the command to be created
+const OPERATION_PROPERTY: &str = "operation";
+
+/// The property of a job document giving the topic id of the target entity
+const TARGET_PROPERTY: &str = "@topic-id";
+
+/// The operations mapped by default from AWS jobs to thin-edge commands
+pub(crate) const DEFAULT_OPERATIONS: [&str; 7] = [
+    "restart",
+    "software_update",
+    "config_snapshot",
+    "config_update",
+    "log_upload",
+    "firmware_update",
+    "device_profile",
+];
+
+#[derive(Clone)]
+pub struct AwsJobs {
+    prefix: String,
+    mqtt_schema: MqttSchema,
+    /// The operations which commands can be created from jobs
+    operations: Vec<String>,
+    /// The jobs with a pending command, along the status reported to AWS
+    jobs: HashMap<String, JobStatus>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum JobStatus {
+    Queued,
+    InProgress,
+}
+
+/// A notification of the next pending job, as published on `notify-next` or in response to `$next/get`
+#[derive(Debug, Deserialize)]
+struct NextJobNotification {
+    #[serde(default)]
+    execution: Option<JobExecution>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JobExecution {
+    job_id: String,
+    #[serde(default)]
+    job_document: Value,
+}
+
+/// The state of a thin-edge command, as published by the agent
+#[derive(Debug, Deserialize)]
+struct CommandState {
+    status: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+impl Default for AwsJobs {
+    fn default() -> Self {
+        AwsJobs {
+            prefix: "aws".to_string(),
+            mqtt_schema: MqttSchema::default(),
+            operations: DEFAULT_OPERATIONS.map(str::to_string).to_vec(),
+            jobs: HashMap::new(),
+        }
+    }
+}
+
+impl tedge_flows::Transformer for AwsJobs {
+    fn name(&self) -> &str {
+        "aws-jobs"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(prefix) = config.string_property("prefix") {
+            self.prefix = prefix.to_owned();
+        }
+        if let Some(topic_root) = config.string_property("topic_root") {
+            self.mqtt_schema = MqttSchema::with_root(topic_root.to_owned());
+        }
+        if let Some(operations) = config.strings_property("operations") {
+            if let Some(invalid) = operations.iter().find(|op| !is_valid_operation(op)) {
+                return Err(ConfigError::IncorrectSetting(format!(
+                    "Invalid operation name: {invalid:?}"
+                )));
+            }
+            self.operations = operations.into_iter().map(str::to_owned).collect();
+        }
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if message.topic == self.notify_next_topic() || message.topic == self.get_next_topic() {
+            return self.on_next_job(message);
+        }
+
+        match self.mqtt_schema.entity_channel_of(&message.topic) {
+            Ok((target, Channel::Command { operation, cmd_id }))
+                if self.is_mapped_operation(&operation.to_string()) =>
+            {
+                match self.job_id(&cmd_id).map(str::to_owned) {
+                    Some(job_id) => self.on_command_update(target, operation, job_id, message),
+                    None => Ok(vec![]),
+                }
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn has_startup(&self) -> bool {
+        true
+    }
+
+    fn on_startup(
+        &mut self,
+        _timestamp: SystemTime,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        // Jobs queued while the mapper was down are not notified, hence explicitly requested
+        Ok(vec![Message::new(
+            format!("{}/jobs/$next/get", self.prefix),
+            "{}",
+        )])
+    }
+}
+
+impl AwsJobs {
+    fn notify_next_topic(&self) -> String {
+        format!("{}/jobs/notify-next", self.prefix)
+    }
+
+    fn get_next_topic(&self) -> String {
+        format!("{}/jobs/$next/get/accepted", self.prefix)
+    }
+
+    fn cmd_id(&self, job_id: &str) -> String {
+        format!("{}-{job_id}", self.prefix)
+    }
+
+    fn is_mapped_operation(&self, operation: &str) -> bool {
+        self.operations.iter().any(|op| op == operation)
+    }
+
+    fn job_id<'a>(&self, cmd_id: &'a str) -> Option<&'a str> {
+        cmd_id.strip_prefix(&self.prefix)?.strip_prefix('-')
+    }
+
+    fn on_next_job(&mut self, message: &Message) -> Result<Vec<Message>, FlowError> {
+        let notification: NextJobNotification = serde_json::from_slice(&message.payload)
+            .map_err(|err| FlowError::UnsupportedMessage(format!("Invalid AWS job: {err}")))?;
+        let Some(execution) = notification.execution else {
+            return Ok(vec![]);
+        };
+        if self.jobs.contains_key(&execution.job_id) {
+            return Ok(vec![]);
+        }
+
+        match self.command_request(&execution) {
+            Ok(command) => {
+                self.jobs.insert(execution.job_id, JobStatus::Queued);
+                Ok(vec![command])
+            }
+            Err(reason) => Ok(vec![self.job_update(
+                &execution.job_id,
+                "FAILED",
+                Some(&reason),
+            )]),
+        }
+    }
+
+    /// Build the thin-edge command request matching a job
+    fn command_request(&self, execution: &JobExecution) -> Result<Message, String> {
+        let Value::Object(document) = &execution.job_document else {
+            return Err("The job document is not a JSON object".to_string());
+        };
+        let mut parameters = document.clone();
+        let operation = match parameters.remove(OPERATION_PROPERTY) {
+            Some(Value::String(operation)) if is_valid_operation(&operation) => operation,
+            _ => {
+                return Err(format!(
+                    "The job document has no valid '{OPERATION_PROPERTY}' property"
+                ))
+            }
+        };
+        if !self.is_mapped_operation(&operation) {
+            return Err(format!(
+                "The '{operation}' operation is not mapped from AWS jobs (expected one of: {})",
+                self.operations.join(", ")
+            ));
+        }
+        let target = match parameters.remove(TARGET_PROPERTY) {
+            None => EntityTopicId::default_main_device(),
+            Some(Value::String(topic_id)) => topic_id
+                .parse()
+                .map_err(|err| format!("Invalid '{TARGET_PROPERTY}': {err}"))?,
+            Some(_) => return Err(format!("Invalid '{TARGET_PROPERTY}': not a string")),
+        };
+        parameters.insert("status".to_string(), json!("init"));
+
+        let channel = Channel::Command {
+            operation: operation.parse().unwrap(), // Infallible
+            cmd_id: self.cmd_id(&execution.job_id),
+        };
+        let topic = self.mqtt_schema.topic_for(&target, &channel);
+        Ok(retained(topic.name, Value::Object(parameters).to_string()))
+    }
+
+    fn on_command_update(
+        &mut self,
+        target: EntityTopicId,
+        operation: OperationType,
+        job_id: String,
+        message: &Message,
+    ) -> Result<Vec<Message>, FlowError> {
+        if message.payload.is_empty() {
+            self.jobs.remove(&job_id);
+            return Ok(vec![]);
+        }
+        let state: CommandState = serde_json::from_slice(&message.payload).map_err(|err| {
+            FlowError::UnsupportedMessage(format!("Invalid {operation} command state: {err}"))
+        })?;
+
+        match state.status.as_str() {
+            "init" => {
+                // Possibly a command created before a restart of the mapper
+                self.jobs.entry(job_id).or_insert(JobStatus::Queued);
+                Ok(vec![])
+            }
+            "successful" => {
+                self.jobs.remove(&job_id);
+                Ok(vec![
+                    self.job_update(&job_id, "SUCCEEDED", None),
+                    self.clear_command(&target, operation, &job_id),
+                ])
+            }
+            "failed" => {
+                self.jobs.remove(&job_id);
+                let reason = state
+                    .reason
+                    .unwrap_or_else(|| format!("The {operation} command failed"));
+                Ok(vec![
+                    self.job_update(&job_id, "FAILED", Some(&reason)),
+                    self.clear_command(&target, operation, &job_id),
+                ])
+            }
+            _ => {
+                if self.jobs.insert(job_id.clone(), JobStatus::InProgress)
+                    == Some(JobStatus::InProgress)
+                {
+                    return Ok(vec![]);
+                }
+                Ok(vec![self.job_update(&job_id, "IN_PROGRESS", None)])
+            }
+        }
+    }
+
+    /// Report the status of a job execution
+    fn job_update(&self, job_id: &str, status: &str, reason: Option<&str>) -> Message {
+        let mut update = Map::new();
+        update.insert("status".to_string(), json!(status));
+        if let Some(reason) = reason {
+            update.insert("statusDetails".to_string(), json!({ "reason": reason }));
+        }
+        Message::new(
+            format!("{}/jobs/{job_id}/update", self.prefix),
+            Value::Object(update).to_string(),
+        )
+    }
+
+    fn clear_command(
+        &self,
+        target: &EntityTopicId,
+        operation: OperationType,
+        job_id: &str,
+    ) -> Message {
+        let channel = Channel::Command {
+            operation,
+            cmd_id: self.cmd_id(job_id),
+        };
+        let topic = self.mqtt_schema.topic_for(target, &channel);
+        retained(topic.name, "")
+    }
+}
+
+fn is_valid_operation(operation: &str) -> bool {
+    !operation.is_empty() && !operation.contains(['/', '+', '#'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::is_retained;
+    use crate::test_helpers::on_message;
+    use crate::test_helpers::published;
+    use tedge_flows::Transformer;
+
+    #[test]
+    fn job_documents_are_mapped_to_commands_on_the_main_device_by_default() {
+        let mut jobs = AwsJobs::default();
+
+        let output = next_job(
+            &mut jobs,
+            json!({"jobId": "job-1", "jobDocument": {
+                "operation": "software_update",
+                "updateList": [{"type": "apt", "modules": [{"name": "nodered", "action": "install"}]}]
+            }}),
+        );
+
+        assert_eq!(
+            published(&output),
+            vec![(
+                "te/device/main///cmd/software_update/aws-job-1".to_string(),
+                json!({
+                    "status": "init",
+                    "updateList": [{"type": "apt", "modules": [{"name": "nodered", "action": "install"}]}]
+                })
+            )]
+        );
+        assert!(is_retained(&output[0]));
+    }
+
+    #[test]
+    fn job_documents_can_target_child_devices_and_services() {
+        let mut jobs = AwsJobs::default();
+
+        let output = next_job(
+            &mut jobs,
+            json!({"jobId": "job-2", "jobDocument": {"operation": "restart", "@topic-id": "device/child1/service/app"}}),
+        );
+
+        assert_eq!(
+            published(&output),
+            vec![(
+                "te/device/child1/service/app/cmd/restart/aws-job-2".to_string(),
+                json!({"status": "init"})
+            )]
+        );
+    }
+
+    #[test]
+    fn topics_are_built_from_the_configured_prefix_and_topic_root() {
+        let mut jobs = AwsJobs::default();
+        jobs.set_config(json!({"prefix": "aws-eu", "topic_root": "tedge"}).into())
+            .unwrap();
+
+        let startup = jobs
+            .on_startup(SystemTime::now(), &FlowContextHandle::default())
+            .unwrap();
+        assert_eq!(startup[0].topic, "aws-eu/jobs/$next/get");
+
+        let job = json!({"execution": {"jobId": "job-3", "jobDocument": {"operation": "restart"}}});
+        let output = on_message(&mut jobs, "aws-eu/jobs/notify-next", job);
+        assert_eq!(
+            output[0].topic,
+            "tedge/device/main///cmd/restart/aws-eu-job-3"
+        );
+
+        let output = on_message(
+            &mut jobs,
+            "tedge/device/main///cmd/restart/aws-eu-job-3",
+            json!({"status": "successful"}),
+        );
+        assert_eq!(output[0].topic, "aws-eu/jobs/job-3/update");
+    }
+
+    #[test]
+    fn command_progress_is_reported_to_aws_jobs() {
+        let mut jobs = AwsJobs::default();
+        let cmd_topic = "te/device/main///cmd/restart/aws-job-4";
+        next_job(
+            &mut jobs,
+            json!({"jobId": "job-4", "jobDocument": {"operation": "restart"}}),
+        );
+
+        // The command request itself is not reported
+        let output = on_message(&mut jobs, cmd_topic, json!({"status": "init"}));
+        assert!(output.is_empty());
+
+        let output = on_message(&mut jobs, cmd_topic, json!({"status": "executing"}));
+        assert_eq!(
+            published(&output),
+            vec![(
+                "aws/jobs/job-4/update".to_string(),
+                json!({"status": "IN_PROGRESS"})
+            )]
+        );
+
+        // IN_PROGRESS is reported only once
+        let output = on_message(&mut jobs, cmd_topic, json!({"status": "restarting"}));
+        assert!(output.is_empty());
+
+        let output = on_message(&mut jobs, cmd_topic, json!({"status": "successful"}));
+        assert_eq!(
+            published(&output),
+            vec![
+                (
+                    "aws/jobs/job-4/update".to_string(),
+                    json!({"status": "SUCCEEDED"})
+                ),
+                (cmd_topic.to_string(), Value::Null),
+            ]
+        );
+        assert!(is_retained(&output[1]));
+        assert!(output[1].payload.is_empty());
+    }
+
+    #[test]
+    fn failed_commands_are_reported_with_their_reason() {
+        let mut jobs = AwsJobs::default();
+
+        let output = on_message(
+            &mut jobs,
+            "te/device/main///cmd/firmware_update/aws-job-5",
+            json!({"status": "failed", "reason": "Checksum mismatch"}),
+        );
+        assert_eq!(
+            published(&output)[0],
+            (
+                "aws/jobs/job-5/update".to_string(),
+                json!({"status": "FAILED", "statusDetails": {"reason": "Checksum mismatch"}})
+            )
+        );
+
+        let output = on_message(
+            &mut jobs,
+            "te/device/main///cmd/firmware_update/aws-job-6",
+            json!({"status": "failed"}),
+        );
+        assert_eq!(
+            published(&output)[0].1,
+            json!({"status": "FAILED", "statusDetails": {"reason": "The firmware_update command failed"}})
+        );
+    }
+
+    #[test]
+    fn a_job_is_processed_again_once_its_command_is_cleared() {
+        let mut jobs = AwsJobs::default();
+        let job = json!({"jobId": "job-7", "jobDocument": {"operation": "restart"}});
+
+        assert_eq!(next_job(&mut jobs, job.clone()).len(), 1);
+        assert!(next_job(&mut jobs, job.clone()).is_empty());
+
+        on_message(&mut jobs, "te/device/main///cmd/restart/aws-job-7", "");
+        assert_eq!(next_job(&mut jobs, job).len(), 1);
+    }
+
+    #[test]
+    fn invalid_job_documents_are_rejected() {
+        let cases = [
+            (json!("restart"), "The job document is not a JSON object"),
+            (
+                json!({"name": "no operation"}),
+                "The job document has no valid 'operation' property",
+            ),
+            (
+                json!({"operation": "restart/now"}),
+                "The job document has no valid 'operation' property",
+            ),
+            (
+                json!({"operation": 42}),
+                "The job document has no valid 'operation' property",
+            ),
+            (
+                json!({"operation": "restart", "@topic-id": ["device/child1//"]}),
+                "Invalid '@topic-id': not a string",
+            ),
+        ];
+        for (document, reason) in cases {
+            let mut jobs = AwsJobs::default();
+            let output = next_job(
+                &mut jobs,
+                json!({"jobId": "job-8", "jobDocument": document}),
+            );
+            assert_eq!(
+                published(&output),
+                vec![(
+                    "aws/jobs/job-8/update".to_string(),
+                    json!({"status": "FAILED", "statusDetails": {"reason": reason}})
+                )],
+                "{document}"
+            );
+        }
+
+        let mut jobs = AwsJobs::default();
+        let output = next_job(
+            &mut jobs,
+            json!({"jobId": "job-9", "jobDocument": {"operation": "restart", "@topic-id": "device/child1/service/app/1"}}),
+        );
+        assert_eq!(output[0].topic, "aws/jobs/job-9/update");
+        assert!(published(&output)[0].1["statusDetails"]["reason"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid '@topic-id'"));
+    }
+
+    #[test]
+    fn only_the_configured_operations_are_mapped() {
+        let mut jobs = AwsJobs::default();
+        jobs.set_config(
+            JsonValue::from_value(json!({"operations": ["restart", "custom_operation"]})).unwrap(),
+        )
+        .unwrap();
+
+        let output = next_job(
+            &mut jobs,
+            json!({"jobId": "job-11", "jobDocument": {"operation": "custom_operation"}}),
+        );
+        assert_eq!(
+            published(&output),
+            vec![(
+                "te/device/main///cmd/custom_operation/aws-job-11".to_string(),
+                json!({"status": "init"})
+            )]
+        );
+
+        let output = next_job(
+            &mut jobs,
+            json!({"jobId": "job-12", "jobDocument": {"operation": "software_update"}}),
+        );
+        assert_eq!(
+            published(&output),
+            vec![(
+                "aws/jobs/job-12/update".to_string(),
+                json!({"status": "FAILED", "statusDetails": {"reason": "The 'software_update' operation is not mapped from AWS jobs (expected one of: restart, custom_operation)"}})
+            )]
+        );
+
+        let output = on_message(
+            &mut jobs,
+            "te/device/main///cmd/software_update/aws-job-13",
+            json!({"status": "successful"}),
+        );
+        assert!(output.is_empty());
+
+        assert!(jobs
+            .set_config(JsonValue::from_value(json!({"operations": ["cmd/+"]})).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let mut jobs = AwsJobs::default();
+
+        let notification = Message::new("aws/jobs/notify-next", "not json");
+        assert!(matches!(
+            jobs.on_message(
+                SystemTime::now(),
+                &notification,
+                &FlowContextHandle::default()
+            ),
+            Err(FlowError::UnsupportedMessage(_))
+        ));
+
+        let command = Message::new("te/device/main///cmd/restart/aws-job-10", "{}");
+        assert!(matches!(
+            jobs.on_message(SystemTime::now(), &command, &FlowContextHandle::default()),
+            Err(FlowError::UnsupportedMessage(_))
+        ));
+
+        // No more pending jobs
+        let output = on_message(&mut jobs, "aws/jobs/notify-next", json!({"timestamp": 1}));
+        assert!(output.is_empty());
+    }
+
+    fn next_job(jobs: &mut AwsJobs, execution: Value) -> Vec<Message> {
+        on_message(
+            jobs,
+            "aws/jobs/notify-next",
+            json!({"execution": execution}),
+        )
+    }
+}
@@ -8,13 +8,24 @@ use tedge_flows::FlowError;
 use tedge_flows::FlowRegistryExt;
 use tedge_flows::JsonValue;
 use tedge_flows::Message;
+use tedge_flows::Transport;
 use tedge_flows::UpdateFlowRegistryError;
+use tedge_mqtt_ext::QoS;
 use tedge_mqtt_ext::Topic;
 use tedge_utils::timestamp::TimeFormat;
 
+mod jobs;
+mod shadow;
+#[cfg(test)]
+mod test_helpers;
+
+pub use jobs::AwsJobs;
+pub use shadow::AwsShadow;
+
 pub struct AwsConverter {
     input_topics: String,
     topic_prefix: TopicPrefix,
+    topic_root: String,
     errors_topic: Topic,
     size_threshold: usize,
     add_timestamp: bool,
@@ -35,6 +46,7 @@ impl AwsConverter {
         AwsConverter {
             input_topics,
             topic_prefix,
+            topic_root: mqtt_schema.root.clone(),
             errors_topic,
             size_threshold,
             add_timestamp,
@@ -48,6 +60,12 @@ impl AwsConverter {
     ) -> Result<(), UpdateFlowRegistryError> {
         flows
             .persist_builtin_flow("mea", self.builtin_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("jobs", self.jobs_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("shadow", self.shadow_flow().as_str())
             .await
     }
 
@@ -82,6 +100,76 @@ errors.mqtt.topic = "{errors_topic}"
             version = env!("CARGO_PKG_VERSION"),
         )
     }
+
+    /// The flow translating AWS IoT Jobs into thin-edge commands
+    ///
+    /// Only the commands of the operations mapped from AWS jobs are received back.
+    fn jobs_flow(&self) -> String {
+        let command_topics: String = jobs::DEFAULT_OPERATIONS
+            .iter()
+            .map(|operation| format!(r#", "{}/+/+/+/+/cmd/{operation}/+""#, self.topic_root))
+            .collect();
+        let operations = jobs::DEFAULT_OPERATIONS
+            .iter()
+            .map(|operation| format!(r#""{operation}""#))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = ["{topic_prefix}/jobs/notify-next", "{topic_prefix}/jobs/$next/get/accepted"{command_topics}]
+
+# The commands created for the jobs are received back to track their progress
+expect_loop = true
+
+steps = [
+    {{ builtin = "aws-jobs", config = {{ prefix = "{topic_prefix}", topic_root = "{topic_root}", operations = [{operations}] }} }},
+]
+
+errors.mqtt.topic = "{errors_topic}"
+"#,
+            topic_prefix = self.topic_prefix,
+            topic_root = self.topic_root,
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
+
+    /// The flow synchronizing the AWS device shadows with the entity twin data
+    fn shadow_flow(&self) -> String {
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = ["{topic_root}/+/+/+/+", "{topic_root}/+/+/+/+/twin/+", "{topic_prefix}/shadow/update/delta", "{topic_prefix}/shadow/get/accepted", "{topic_prefix}/shadow/name/+/update/delta", "{topic_prefix}/shadow/name/+/get/accepted"]
+
+# The twin data updated from the desired state are received back to be reported
+expect_loop = true
+
+steps = [
+    {{ builtin = "aws-shadow", config = {{ prefix = "{topic_prefix}", topic_root = "{topic_root}" }} }},
+]
+
+# The versions of the latest deltas applied to the shadows are persisted across restarts
+context.persist_mapper_keys = ["{topic_prefix}/shadow/"]
+
+errors.mqtt.topic = "{errors_topic}"
+"#,
+            topic_prefix = self.topic_prefix,
+            topic_root = self.topic_root,
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
+}
+
+/// Build a message to be published with the retain flag set
+fn retained(topic: String, payload: impl Into<Vec<u8>>) -> Message {
+    let mut message = Message::new(topic, payload);
+    message.transport = Some(Transport::Mqtt {
+        qos: QoS::AtLeastOnce,
+        retain: true,
+    });
+    message
 }
 
 // We need to reduce the number of levels in the topic because AWS IoT only supports topics with 7
@@ -145,6 +233,8 @@ impl tedge_flows::Transformer for SetAwsTopic {
 
 pub fn load_builtin_transformers(flows: &mut impl FlowRegistryExt) {
     flows.register_builtin(SetAwsTopic::default());
+    flows.register_builtin(AwsJobs::default());
+    flows.register_builtin(AwsShadow::default());
 }
 
 #[cfg(test)]
@@ -744,6 +834,257 @@ mod tests {
         assert_eq!(res[0], expected_msg);
     }
 
+    #[tokio::test]
+    async fn aws_jobs_are_translated_into_commands() {
+        let mut converter = create_test_converter(false, None, None).await;
+
+        let job = json!({
+            "timestamp": 1700000000,
+            "execution": {
+                "jobId": "job-42",
+                "thingName": "my-device",
+                "status": "QUEUED",
+                "versionNumber": 1,
+                "executionNumber": 1,
+                "jobDocument": {
+                    "operation": "firmware_update",
+                    "@topic-id": "device/child1//",
+                    "name": "core-image-tedge",
+                    "version": "1.0.1"
+                }
+            }
+        });
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("aws/jobs/notify-next"),
+                job.to_string(),
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(output.len(), 1);
+        assert_eq!(
+            output[0].topic.name,
+            "te/device/child1///cmd/firmware_update/aws-job-42"
+        );
+        assert!(output[0].retain);
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output[0].payload_str().unwrap()).unwrap(),
+            json!({"status": "init", "name": "core-image-tedge", "version": "1.0.1"})
+        );
+
+        // The same job notified twice is processed only once
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("aws/jobs/notify-next"),
+                job.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn command_states_are_reported_to_aws_jobs() {
+        let mut converter = create_test_converter(false, None, None).await;
+        let cmd_topic = Topic::new_unchecked("te/device/main///cmd/restart/aws-job-7");
+
+        let output = converter
+            .convert(&MqttMessage::new(&cmd_topic, r#"{"status":"executing"}"#))
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic.name, "aws/jobs/job-7/update");
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output[0].payload_str().unwrap()).unwrap(),
+            json!({"status": "IN_PROGRESS"})
+        );
+
+        // Intermediate states are not reported
+        let output = converter
+            .convert(&MqttMessage::new(&cmd_topic, r#"{"status":"restarting"}"#))
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+
+        let output = converter
+            .convert(&MqttMessage::new(&cmd_topic, r#"{"status":"successful"}"#))
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].topic.name, "aws/jobs/job-7/update");
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output[0].payload_str().unwrap()).unwrap(),
+            json!({"status": "SUCCEEDED"})
+        );
+        assert_eq!(output[1].topic, cmd_topic);
+        assert!(output[1].retain);
+        assert!(output[1].payload_bytes().is_empty());
+
+        // Commands not created from AWS jobs are ignored
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("te/device/main///cmd/restart/c8y-mapper-123"),
+                r#"{"status":"successful"}"#,
+            ))
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+
+        // Only the commands of the operations mapped from AWS jobs are listened to
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("te/device/main///cmd/custom_operation/aws-job-10"),
+                r#"{"status":"successful"}"#,
+            ))
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_commands_and_invalid_jobs_are_reported_as_failed() {
+        let mut converter = create_test_converter(false, None, None).await;
+
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("te/device/main///cmd/software_update/aws-job-8"),
+                r#"{"status":"failed", "reason":"Package not found"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output[0].payload_str().unwrap()).unwrap(),
+            json!({"status": "FAILED", "statusDetails": {"reason": "Package not found"}})
+        );
+
+        let job = json!({"execution": {"jobId": "job-9", "jobDocument": {"name": "no operation"}}});
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("aws/jobs/$next/get/accepted"),
+                job.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic.name, "aws/jobs/job-9/update");
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output[0].payload_str().unwrap()).unwrap(),
+            json!({"status": "FAILED", "statusDetails": {"reason": "The job document has no valid 'operation' property"}})
+        );
+    }
+
+    #[tokio::test]
+    async fn twin_data_is_reported_to_device_shadows() {
+        let mut converter = create_test_converter(false, None, None).await;
+
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("te/device/main///twin/hardware"),
+                r#"{"model":"rpi4"}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic.name, "aws/shadow/update");
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output[0].payload_str().unwrap()).unwrap(),
+            json!({"state": {"reported": {"hardware": {"model": "rpi4"}}}})
+        );
+
+        // The twin data of a child device are reported to a named shadow
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("te/device/child1///twin/hardware"),
+                "",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(output[0].topic.name, "aws/shadow/name/device:child1/update");
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output[0].payload_str().unwrap()).unwrap(),
+            json!({"state": {"reported": {"hardware": null}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn shadow_deltas_are_applied_onto_twin_data() {
+        let mut converter = create_test_converter(false, None, None).await;
+
+        converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("te/device/child1///twin/settings"),
+                r#"{"interval":10,"mode":"eco"}"#,
+            ))
+            .await
+            .unwrap();
+
+        // Only the properties to be updated are given by the delta
+        let delta = json!({"version": 5, "timestamp": 1700000000, "state": {"settings": {"interval": 60}, "location": "hall"}});
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("aws/shadow/name/device:child1/update/delta"),
+                delta.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].topic.name, "te/device/child1///twin/location");
+        assert!(output[0].retain);
+        assert_eq!(output[0].payload_str().unwrap(), r#""hall""#);
+        assert_eq!(output[1].topic.name, "te/device/child1///twin/settings");
+        assert_json_eq!(
+            serde_json::from_str::<serde_json::Value>(output[1].payload_str().unwrap()).unwrap(),
+            json!({"interval": 60, "mode": "eco"})
+        );
+
+        // Outdated deltas are ignored
+        let delta = json!({"version": 4, "state": {"location": "garage"}});
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("aws/shadow/name/device:child1/update/delta"),
+                delta.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+
+        // Named shadows not related to an entity are ignored
+        let delta = json!({"version": 1, "state": {"location": "garage"}});
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("aws/shadow/name/settings/update/delta"),
+                delta.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn pending_deltas_are_applied_on_start() {
+        let mut converter = create_test_converter(false, None, None).await;
+
+        let document = json!({
+            "version": 12,
+            "state": {
+                "desired": {"location": "hall", "owner": "ops"},
+                "reported": {"owner": "ops"},
+                "delta": {"location": "hall"}
+            }
+        });
+        let output = converter
+            .convert(&MqttMessage::new(
+                &Topic::new_unchecked("aws/shadow/get/accepted"),
+                document.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic.name, "te/device/main///twin/location");
+        assert_eq!(output[0].payload_str().unwrap(), r#""hall""#);
+    }
+
     async fn create_test_converter(
         add_timestamp: bool,
         size_threshold: Option<u32>,
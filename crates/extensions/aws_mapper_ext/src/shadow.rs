@@ -0,0 +1,570 @@
+//! Synchronize the AWS IoT device shadows with the twin data of the thin-edge entities.
+//!
+//! The twin fragments of the main device (`te/device/main///twin/{key}`) are reported
+//! to the classic shadow of the thing (locally `aws/shadow/update`),
+//! while those of the other entities are reported to a named shadow (locally `aws/shadow/name/{name}/update`),
+//! the name of the shadow being derived from the entity topic id, e.g. `device:child1` for `device/child1//`.
+//!
+//! Conversely, the `delta` between the `desired` and `reported` states of a shadow
+//! is applied onto the twin fragments of the matching entity,
+//! the new twin values being then reported back to clear the delta.
+//!
+//! Each delta is applied only once and in order: a delta with a version
+//! older than or equal to the version of the latest delta applied to the same shadow is ignored.
+//! The version of the latest delta applied to a shadow is stored in the mapper context,
+//! under the key `{prefix}/shadow/version` or `{prefix}/shadow/name/{name}/version`,
+//! so it can be persisted across mapper restarts.
+//!
+//! The desired state updated while the mapper was down is not notified, hence explicitly requested:
+//! on startup for the classic shadow and the named shadows with a persisted version,
+//! and on registration for the named shadows of the other entities.
+use crate::retained;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::time::SystemTime;
+use tedge_api::mqtt_topics::Channel;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_api::twin::entity_of_twin_name;
+use tedge_api::twin::entity_twin_name;
+use tedge_api::twin::is_valid_fragment_key;
+use tedge_api::twin::merge;
+use tedge_flows::parse_json_payload;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+#[derive(Clone)]
+pub struct AwsShadow {
+    prefix: String,
+    mqtt_schema: MqttSchema,
+    /// The twin data of the entities, as last published or applied
+    twins: HashMap<EntityTopicId, Map<String, Value>>,
+    /// The named shadows already requested
+    requested: HashSet<String>,
+}
+
+/// A delta notification, as published on `update/delta`
+#[derive(Debug, Deserialize)]
+struct ShadowDelta {
+    version: u64,
+    #[serde(default)]
+    state: Map<String, Value>,
+}
+
+/// A shadow document, as published on `get/accepted`
+#[derive(Debug, Deserialize)]
+struct ShadowDocument {
+    version: u64,
+    #[serde(default)]
+    state: ShadowState,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ShadowState {
+    #[serde(default)]
+    delta: Map<String, Value>,
+}
+
+impl Default for AwsShadow {
+    fn default() -> Self {
+        AwsShadow {
+            prefix: "aws".to_string(),
+            mqtt_schema: MqttSchema::default(),
+            twins: HashMap::new(),
+            requested: HashSet::new(),
+        }
+    }
+}
+
+impl tedge_flows::Transformer for AwsShadow {
+    fn name(&self) -> &str {
+        "aws-shadow"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(prefix) = config.string_property("prefix") {
+            self.prefix = prefix.to_owned();
+        }
+        if let Some(topic_root) = config.string_property("topic_root") {
+            self.mqtt_schema = MqttSchema::with_root(topic_root.to_owned());
+        }
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if let Some((shadow, action)) = self.shadow_of(&message.topic) {
+            return match action {
+                "update/delta" => {
+                    let delta: ShadowDelta = parse_json_payload(&message.payload, "shadow delta")?;
+                    self.apply_delta(context, shadow, delta.version, delta.state)
+                }
+                "get/accepted" => {
+                    let document: ShadowDocument =
+                        parse_json_payload(&message.payload, "shadow document")?;
+                    self.apply_delta(context, shadow, document.version, document.state.delta)
+                }
+                _ => Ok(vec![]),
+            };
+        }
+
+        match self.mqtt_schema.entity_channel_of(&message.topic) {
+            Ok((entity, Channel::EntityTwinData { fragment_key })) => {
+                self.on_twin_update(entity, fragment_key, &message.payload)
+            }
+            Ok((entity, Channel::EntityMetadata)) if !message.payload.is_empty() => {
+                match entity_twin_name(&entity) {
+                    Some(name) => Ok(self.request_named_shadow(name)),
+                    None => Ok(vec![]),
+                }
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn has_startup(&self) -> bool {
+        true
+    }
+
+    fn on_startup(
+        &mut self,
+        _timestamp: SystemTime,
+        context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let mut messages = vec![Message::new(self.shadow_topic(&None, "get"), "{}")];
+        let named_shadows = format!("{}/shadow/name/", self.prefix);
+        for key in context.get_keys() {
+            if let Some(name) = key
+                .strip_prefix(&named_shadows)
+                .and_then(|key| key.strip_suffix("/version"))
+            {
+                messages.extend(self.request_named_shadow(name.to_string()));
+            }
+        }
+        Ok(messages)
+    }
+}
+
+impl AwsShadow {
+    /// Split a shadow topic into the shadow name and the action
+    fn shadow_of<'a>(&self, topic: &'a str) -> Option<(Option<String>, &'a str)> {
+        let path = topic.strip_prefix(&self.prefix)?.strip_prefix("/shadow/")?;
+        match path.strip_prefix("name/") {
+            Some(named) => {
+                let (name, action) = named.split_once('/')?;
+                Some((Some(name.to_string()), action))
+            }
+            None => Some((None, path)),
+        }
+    }
+
+    fn shadow_topic(&self, shadow: &Option<String>, action: &str) -> String {
+        match shadow {
+            None => format!("{}/shadow/{action}", self.prefix),
+            Some(name) => format!("{}/shadow/name/{name}/{action}", self.prefix),
+        }
+    }
+
+    /// Request a named shadow document, unless already requested
+    fn request_named_shadow(&mut self, name: String) -> Vec<Message> {
+        if !self.requested.insert(name.clone()) {
+            return vec![];
+        }
+        vec![Message::new(self.shadow_topic(&Some(name), "get"), "{}")]
+    }
+
+    /// The key of the mapper context value holding the version of the latest delta applied to a shadow
+    fn version_key(&self, shadow: &Option<String>) -> String {
+        self.shadow_topic(shadow, "version")
+    }
+
+    /// The entity of a shadow, if any
+    ///
+    /// Named shadows which name is not derived from a `device/...` topic id are left untouched.
+    fn entity_of(&self, shadow: &Option<String>) -> Option<EntityTopicId> {
+        match shadow {
+            None => Some(EntityTopicId::default_main_device()),
+            Some(name) => entity_of_twin_name(name),
+        }
+    }
+
+    /// Report a twin fragment to the entity shadow
+    fn on_twin_update(
+        &mut self,
+        entity: EntityTopicId,
+        fragment_key: String,
+        payload: &[u8],
+    ) -> Result<Vec<Message>, FlowError> {
+        let twin = self.twins.entry(entity.clone()).or_default();
+        let value = if payload.is_empty() {
+            twin.remove(&fragment_key);
+            Value::Null
+        } else {
+            let value: Value = parse_json_payload(payload, "twin value")?;
+            twin.insert(fragment_key.clone(), value.clone());
+            value
+        };
+
+        let shadow = entity_twin_name(&entity);
+        let update = json!({ "state": { "reported": { fragment_key: value } } });
+        Ok(vec![Message::new(
+            self.shadow_topic(&shadow, "update"),
+            update.to_string(),
+        )])
+    }
+
+    /// Apply the delta between the desired and reported states of a shadow onto the twin data of the entity
+    fn apply_delta(
+        &mut self,
+        context: &FlowContextHandle,
+        shadow: Option<String>,
+        version: u64,
+        delta: Map<String, Value>,
+    ) -> Result<Vec<Message>, FlowError> {
+        let Some(entity) = self.entity_of(&shadow) else {
+            return Ok(vec![]);
+        };
+        let version_key = self.version_key(&shadow);
+        if context
+            .get_value(&version_key)
+            .into_value::<u64>()
+            .is_ok_and(|applied| version <= applied)
+        {
+            return Ok(vec![]);
+        }
+        context.set_value(&version_key, json!(version).into());
+
+        let twin = self.twins.entry(entity.clone()).or_default();
+        let mut messages = vec![];
+        for (fragment_key, patch) in delta {
+            if !is_valid_fragment_key(&fragment_key) {
+                continue;
+            }
+            let channel = Channel::EntityTwinData {
+                fragment_key: fragment_key.clone(),
+            };
+            let topic = self.mqtt_schema.topic_for(&entity, &channel);
+            if patch.is_null() {
+                twin.remove(&fragment_key);
+                messages.push(retained(topic.name, ""));
+                continue;
+            }
+
+            // A delta only contains the properties of an object that differ from the reported ones
+            let mut value = twin.get(&fragment_key).cloned().unwrap_or(Value::Null);
+            merge(&mut value, patch);
+            messages.push(retained(topic.name, value.to_string()));
+            twin.insert(fragment_key, value);
+        }
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::is_retained;
+    use crate::test_helpers::on_message_with_context;
+    use crate::test_helpers::published;
+    use tedge_flows::Transformer;
+
+    #[test]
+    fn main_device_twin_data_is_reported_to_the_classic_shadow() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "te/device/main///twin/maintenance",
+            json!({"enabled": true}),
+        );
+        assert_eq!(
+            published(&output),
+            vec![(
+                "aws/shadow/update".to_string(),
+                json!({"state": {"reported": {"maintenance": {"enabled": true}}}})
+            )]
+        );
+    }
+
+    #[test]
+    fn child_twin_data_is_reported_to_a_named_shadow() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "te/device/child1/service/app/twin/version",
+            json!("1.2.3"),
+        );
+        assert_eq!(
+            published(&output),
+            vec![(
+                "aws/shadow/name/device:child1:service:app/update".to_string(),
+                json!({"state": {"reported": {"version": "1.2.3"}}})
+            )]
+        );
+    }
+
+    #[test]
+    fn cleared_twin_data_is_removed_from_the_reported_state() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+
+        let cleared = Message::new("te/device/child1///twin/location", "");
+        let output = shadow
+            .on_message(SystemTime::now(), &cleared, &context)
+            .unwrap();
+        assert_eq!(
+            published(&output),
+            vec![(
+                "aws/shadow/name/device:child1/update".to_string(),
+                json!({"state": {"reported": {"location": null}}})
+            )]
+        );
+    }
+
+    #[test]
+    fn desired_deltas_are_applied_onto_the_twin_data() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+        on_message_with_context(
+            &mut shadow,
+            &context,
+            "te/device/main///twin/config",
+            json!({"interval": 10, "unit": "s", "debug": true}),
+        );
+
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "aws/shadow/update/delta",
+            json!({"version": 3, "state": {"config": {"interval": 60, "debug": null}, "label": "pump"}}),
+        );
+        assert_eq!(
+            published(&output),
+            vec![
+                (
+                    "te/device/main///twin/config".to_string(),
+                    json!({"interval": 60, "unit": "s"})
+                ),
+                ("te/device/main///twin/label".to_string(), json!("pump")),
+            ]
+        );
+        assert!(output.iter().all(is_retained));
+    }
+
+    #[test]
+    fn desired_null_values_clear_the_twin_fragments() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+        on_message_with_context(
+            &mut shadow,
+            &context,
+            "te/device/child1///twin/label",
+            json!("pump"),
+        );
+
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "aws/shadow/name/device:child1/update/delta",
+            json!({"version": 1, "state": {"label": null}}),
+        );
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic, "te/device/child1///twin/label");
+        assert!(output[0].payload.is_empty());
+        assert!(is_retained(&output[0]));
+    }
+
+    #[test]
+    fn the_desired_state_is_requested_and_applied_on_startup() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+        shadow
+            .set_config(json!({"prefix": "aws-eu", "topic_root": "tedge"}).into())
+            .unwrap();
+
+        let startup = shadow.on_startup(SystemTime::now(), &context).unwrap();
+        assert_eq!(startup[0].topic, "aws-eu/shadow/get");
+
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "aws-eu/shadow/get/accepted",
+            json!({
+                "version": 12,
+                "state": {
+                    "desired": {"label": "pump", "firmware": "1.0"},
+                    "reported": {"firmware": "1.0"},
+                    "delta": {"label": "pump"}
+                }
+            }),
+        );
+        assert_eq!(
+            published(&output),
+            vec![("tedge/device/main///twin/label".to_string(), json!("pump"))]
+        );
+    }
+
+    #[test]
+    fn outdated_and_replayed_deltas_are_ignored() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+        let mut delta = |topic: &str, version: u64, label: &str| {
+            let delta = json!({"version": version, "state": {"label": label}});
+            on_message_with_context(&mut shadow, &context, topic, delta)
+        };
+
+        assert_eq!(delta("aws/shadow/update/delta", 5, "v5").len(), 1);
+        assert!(delta("aws/shadow/update/delta", 5, "v5").is_empty());
+        assert!(delta("aws/shadow/update/delta", 4, "v4").is_empty());
+        assert!(delta("aws/shadow/get/accepted", 3, "v3").is_empty());
+
+        let output = delta("aws/shadow/update/delta", 6, "v6");
+        assert_eq!(published(&output)[0].1, json!("v6"));
+
+        // Versions are tracked independently for each shadow
+        let output = delta("aws/shadow/name/device:child1/update/delta", 1, "child");
+        assert_eq!(
+            published(&output),
+            vec![("te/device/child1///twin/label".to_string(), json!("child"))]
+        );
+    }
+
+    #[test]
+    fn applied_versions_are_kept_in_the_mapper_context() {
+        let context = FlowContextHandle::default();
+        let delta = |version: u64| json!({"version": version, "state": {"label": "pump"}});
+
+        let mut shadow = AwsShadow::default();
+        on_message_with_context(&mut shadow, &context, "aws/shadow/update/delta", delta(7));
+        on_message_with_context(
+            &mut shadow,
+            &context,
+            "aws/shadow/name/device:child1/update/delta",
+            delta(3),
+        );
+        assert_eq!(context.get_value("aws/shadow/version"), json!(7).into());
+        assert_eq!(
+            context.get_value("aws/shadow/name/device:child1/version"),
+            json!(3).into()
+        );
+
+        // Once restarted, the mapper doesn't re-apply the deltas received before
+        let mut restarted = AwsShadow::default();
+        let startup = restarted.on_startup(SystemTime::now(), &context).unwrap();
+        assert_eq!(
+            topics(&startup),
+            vec!["aws/shadow/get", "aws/shadow/name/device:child1/get"]
+        );
+        let topic = "aws/shadow/update/delta";
+        assert!(on_message_with_context(&mut restarted, &context, topic, delta(7)).is_empty());
+        assert_eq!(
+            on_message_with_context(&mut restarted, &context, topic, delta(8)).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn the_named_shadows_of_registered_entities_are_requested() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+        let registration = json!({"@type": "child-device"});
+
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "te/device/child1//",
+            registration.clone(),
+        );
+        assert_eq!(topics(&output), vec!["aws/shadow/name/device:child1/get"]);
+
+        // A shadow is requested only once
+        let output =
+            on_message_with_context(&mut shadow, &context, "te/device/child1//", registration);
+        assert!(output.is_empty());
+
+        // The classic shadow is requested on startup, not on registration of the main device
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "te/device/main//",
+            json!({"@type": "device"}),
+        );
+        assert!(output.is_empty());
+
+        // Deregistered entities are ignored
+        let deregistration = Message::new("te/device/child2//", "");
+        let output = shadow
+            .on_message(SystemTime::now(), &deregistration, &context)
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn unrelated_shadows_and_invalid_fragments_are_ignored() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "aws/shadow/name/config/update/delta",
+            json!({"version": 1, "state": {"label": "pump"}}),
+        );
+        assert!(output.is_empty());
+
+        let output = on_message_with_context(
+            &mut shadow,
+            &context,
+            "aws/shadow/update/delta",
+            json!({"version": 1, "state": {"a/b": 1, "c+": 2, "ok": 3}}),
+        );
+        assert_eq!(
+            published(&output),
+            vec![("te/device/main///twin/ok".to_string(), json!(3))]
+        );
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let mut shadow = AwsShadow::default();
+        let context = FlowContextHandle::default();
+
+        for (topic, payload) in [
+            ("aws/shadow/update/delta", r#"{"state": {}}"#),
+            ("aws/shadow/get/accepted", "not json"),
+            ("te/device/main///twin/label", "not json"),
+        ] {
+            let message = Message::new(topic, payload);
+            assert!(
+                matches!(
+                    shadow.on_message(SystemTime::now(), &message, &context),
+                    Err(FlowError::UnsupportedMessage(_))
+                ),
+                "{topic}: {payload}"
+            );
+        }
+    }
+
+    fn topics(messages: &[Message]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|message| message.topic.as_str())
+            .collect()
+    }
+}
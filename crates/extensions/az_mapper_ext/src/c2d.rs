@@ -0,0 +1,96 @@
+//! Republish the cloud-to-device messages received from Azure IoT Hub on a local topic.
+//!
+//! The messages are received on `az/messages/devicebound/{properties}`,
+//! where `{properties}` is the URL-encoded property bag of the message,
+//! and are republished unchanged on the configured `topic`.
+//! No message is republished when no topic is configured.
+use std::time::SystemTime;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+#[derive(Clone, Default)]
+pub struct AzureC2d {
+    topic: Option<String>,
+}
+
+impl tedge_flows::Transformer for AzureC2d {
+    fn name(&self) -> &str {
+        "az-c2d"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        self.topic = config
+            .string_property("topic")
+            .filter(|topic| !topic.is_empty())
+            .map(str::to_owned);
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let Some(topic) = &self.topic else {
+            return Ok(vec![]);
+        };
+        let mut message = message.clone();
+        message.topic = topic.clone();
+        message.transport = None;
+        Ok(vec![message])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tedge_flows::Transformer;
+    use tedge_flows::Transport;
+    use tedge_mqtt_ext::QoS;
+
+    #[test]
+    fn messages_are_republished_unchanged_on_the_configured_topic() {
+        let mut c2d = AzureC2d::default();
+        c2d.set_config(json!({"topic": "c2d/messages"}).into())
+            .unwrap();
+
+        let mut message = Message::new(
+            "az/messages/devicebound/%24.to=%2Fdevices%2Fmy-device%2Fmessages%2Fdevicebound&kind=alert",
+            r#"{"text":"hello"}"#,
+        );
+        message.transport = Some(Transport::Mqtt {
+            qos: QoS::AtLeastOnce,
+            retain: false,
+        });
+        let output = c2d
+            .on_message(SystemTime::now(), &message, &FlowContextHandle::default())
+            .unwrap();
+
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic, "c2d/messages");
+        assert_eq!(output[0].payload, br#"{"text":"hello"}"#);
+        assert!(output[0].transport.is_none());
+    }
+
+    #[test]
+    fn messages_are_dropped_without_configured_topic() {
+        let message = Message::new("az/messages/devicebound/", "hello");
+
+        let mut c2d = AzureC2d::default();
+        let output = c2d
+            .on_message(SystemTime::now(), &message, &FlowContextHandle::default())
+            .unwrap();
+        assert!(output.is_empty());
+
+        c2d.set_config(json!({"topic": ""}).into()).unwrap();
+        let output = c2d
+            .on_message(SystemTime::now(), &message, &FlowContextHandle::default())
+            .unwrap();
+        assert!(output.is_empty());
+    }
+}
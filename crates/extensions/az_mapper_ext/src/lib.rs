@@ -6,9 +6,23 @@ use tedge_flows::FlowRegistryExt;
 use tedge_flows::UpdateFlowRegistryError;
 use tedge_mqtt_ext::Topic;
 
+mod c2d;
+mod methods;
+mod operations;
+#[cfg(test)]
+mod test_helpers;
+mod twin;
+
+pub use c2d::AzureC2d;
+pub use methods::AzureMethods;
+pub use twin::AzureTwin;
+
 pub struct AzureConverter {
     input_topics: String,
     output_topic: Topic,
+    topic_prefix: TopicPrefix,
+    topic_root: String,
+    c2d_topic: Option<String>,
     errors_topic: Topic,
     add_timestamp: bool,
     time_format: TimeFormat,
@@ -23,6 +37,7 @@ impl AzureConverter {
         topic_prefix: &TopicPrefix,
         max_payload_size: u32,
         input_topics: String,
+        c2d_topic: Option<String>,
     ) -> Self {
         let output_topic = Topic::new_unchecked(&format!("{topic_prefix}/messages/events/"));
         let errors_topic = mqtt_schema.error_topic();
@@ -30,6 +45,9 @@ impl AzureConverter {
         AzureConverter {
             input_topics,
             output_topic,
+            topic_prefix: topic_prefix.clone(),
+            topic_root: mqtt_schema.root.clone(),
+            c2d_topic,
             errors_topic,
             add_timestamp,
             time_format,
@@ -43,6 +61,15 @@ impl AzureConverter {
     ) -> Result<(), UpdateFlowRegistryError> {
         flows
             .persist_builtin_flow("mea", self.builtin_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("twin", self.twin_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("methods", self.methods_flow().as_str())
+            .await?;
+        flows
+            .persist_builtin_flow("c2d", self.c2d_flow().as_str())
             .await
     }
 
@@ -77,7 +104,370 @@ errors.mqtt.topic = "{errors_topic}"
             version = env!("CARGO_PKG_VERSION"),
         )
     }
+
+    /// The flow synchronizing the device twin with the entity twin data
+    fn twin_flow(&self) -> String {
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = ["{topic_root}/+/+/+/+/twin/+", "{topic_root}/+/+/+/+/cmd/+", "{topic_root}/+/+/+/+/cmd/+/+", "{topic_prefix}/twin/PATCH/properties/desired/#", "{topic_prefix}/twin/res/#"]
+
+# The twin data and commands updated from the desired properties are received back to be reported
+expect_loop = true
+
+steps = [
+    {{ builtin = "az-twin", config = {{ prefix = "{topic_prefix}", topic_root = "{topic_root}" }} }},
+]
+
+errors.mqtt.topic = "{errors_topic}"
+"#,
+            topic_prefix = self.topic_prefix,
+            topic_root = self.topic_root,
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
+
+    /// The flow translating direct methods into thin-edge commands
+    fn methods_flow(&self) -> String {
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = ["{topic_prefix}/methods/POST/#", "{topic_root}/+/+/+/+/cmd/+", "{topic_root}/+/+/+/+/cmd/+/+"]
+
+# The commands created for the methods are received back to respond once completed
+expect_loop = true
+
+steps = [
+    {{ builtin = "az-methods", config = {{ prefix = "{topic_prefix}", topic_root = "{topic_root}" }} }},
+]
+
+errors.mqtt.topic = "{errors_topic}"
+"#,
+            topic_prefix = self.topic_prefix,
+            topic_root = self.topic_root,
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
+
+    /// The flow republishing cloud-to-device messages on a local topic
+    fn c2d_flow(&self) -> String {
+        format!(
+            r#"version = "{version}"
+
+input.mqtt.topics = ["{topic_prefix}/messages/devicebound/#"]
+
+steps = [
+    {{ builtin = "az-c2d", config = {{ topic = "{c2d_topic}" }} }},
+]
+
+errors.mqtt.topic = "{errors_topic}"
+"#,
+            topic_prefix = self.topic_prefix,
+            c2d_topic = self.c2d_topic.as_deref().unwrap_or_default(),
+            errors_topic = self.errors_topic,
+            version = env!("CARGO_PKG_VERSION"),
+        )
+    }
+}
+
+pub fn load_builtin_transformers(flows: &mut impl FlowRegistryExt) {
+    flows.register_builtin(AzureTwin::default());
+    flows.register_builtin(AzureMethods::default());
+    flows.register_builtin(AzureC2d::default());
 }
-pub fn load_builtin_transformers(_flows: &mut impl FlowRegistryExt) {
-    // nothing specific for az
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_json_diff::*;
+    use camino::Utf8PathBuf;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+    use tedge_flows::FlowError;
+    use tedge_flows::FlowResult;
+    use tedge_flows::Message;
+    use tedge_flows::MessageProcessor;
+    use tedge_flows::SourceTag;
+    use tedge_mqtt_ext::MqttMessage;
+    use tedge_utils::paths::TedgePaths;
+
+    static TE_MEA_TOPICS: &str =
+        r#"["te/+/+/+/+/m/+", "te/+/+/+/+/e/+", "te/+/+/+/+/a/+", "te/+/+/+/+/status/health"]"#;
+
+    #[tokio::test]
+    async fn twin_data_is_reported_as_twin_properties() {
+        let mut converter = create_test_converter(None).await;
+
+        let output = converter
+            .convert("te/device/main///twin/hardware", r#"{"model":"rpi4"}"#)
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(
+            output[0].topic.name,
+            "az/twin/PATCH/properties/reported/?$rid=1"
+        );
+        assert_json_eq!(payload(&output[0]), json!({"hardware": {"model": "rpi4"}}));
+
+        // The twin data of a child device are grouped under a property named after the child
+        let output = converter
+            .convert("te/device/child1///twin/hardware", "")
+            .await
+            .unwrap();
+        assert_eq!(
+            output[0].topic.name,
+            "az/twin/PATCH/properties/reported/?$rid=2"
+        );
+        assert_json_eq!(
+            payload(&output[0]),
+            json!({"device:child1": {"hardware": null}})
+        );
+    }
+
+    #[tokio::test]
+    async fn desired_properties_are_applied_onto_entities() {
+        let mut converter = create_test_converter(None).await;
+        converter
+            .convert("te/device/main///cmd/firmware_update", "{}")
+            .await
+            .unwrap();
+
+        let desired = json!({
+            "location": "hall",
+            "firmware_update": {"name": "core-image-tedge", "version": "1.0.1"},
+            "$version": 3
+        });
+        let output = converter
+            .convert(
+                "az/twin/PATCH/properties/desired/?$version=3",
+                &desired.to_string(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 3);
+        assert_eq!(
+            output[0].topic.name,
+            "te/device/main///cmd/firmware_update/az-desired-3"
+        );
+        assert!(output[0].retain);
+        assert_json_eq!(
+            payload(&output[0]),
+            json!({"status": "init", "name": "core-image-tedge", "version": "1.0.1"})
+        );
+        assert_eq!(output[1].topic.name, "te/device/main///twin/location");
+        assert_eq!(output[1].payload_str().unwrap(), r#""hall""#);
+        assert_json_eq!(payload(&output[2]), json!({"desiredVersion": 3}));
+
+        // The command state is reported under the desired property
+        let output = converter
+            .convert(
+                "te/device/main///cmd/firmware_update/az-desired-3",
+                r#"{"status":"successful","name":"core-image-tedge","version":"1.0.1"}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 2);
+        assert_json_eq!(
+            payload(&output[0]),
+            json!({"firmware_update": {"status": "successful", "name": "core-image-tedge", "version": "1.0.1"}})
+        );
+        assert_eq!(
+            output[1].topic.name,
+            "te/device/main///cmd/firmware_update/az-desired-3"
+        );
+        assert!(output[1].payload_bytes().is_empty());
+
+        // Outdated desired properties are ignored
+        let desired = json!({"location": "garage", "$version": 2});
+        let output = converter
+            .convert(
+                "az/twin/PATCH/properties/desired/?$version=2",
+                &desired.to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[tokio::test]
+    async fn desired_properties_already_applied_are_skipped_on_start() {
+        let mut converter = create_test_converter(None).await;
+        converter
+            .convert("te/device/child1///cmd/firmware_update", "{}")
+            .await
+            .unwrap();
+
+        let twin = json!({
+            "desired": {
+                "device:child1": {"firmware_update": {"name": "fw", "version": "2.0"}},
+                "$version": 7
+            },
+            "reported": {
+                "device:child1": {"firmware_update": {"status": "successful", "name": "fw", "version": "2.0"}},
+                "desiredVersion": 6
+            }
+        });
+        let output = converter
+            .convert("az/twin/res/200/?$rid=twin-get", &twin.to_string())
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_json_eq!(payload(&output[0]), json!({"desiredVersion": 7}));
+    }
+
+    #[tokio::test]
+    async fn direct_methods_are_translated_into_commands() {
+        let mut converter = create_test_converter(None).await;
+        converter
+            .convert("te/device/child1///cmd/restart", "{}")
+            .await
+            .unwrap();
+
+        let output = converter
+            .convert(
+                "az/methods/POST/restart/?$rid=7",
+                r#"{"@topic-id": "device/child1//"}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(
+            output[0].topic.name,
+            "te/device/child1///cmd/restart/az-method-7"
+        );
+        assert_json_eq!(payload(&output[0]), json!({"status": "init"}));
+
+        let output = converter
+            .convert(
+                "te/device/child1///cmd/restart/az-method-7",
+                r#"{"status":"restarting"}"#,
+            )
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+
+        let output = converter
+            .convert(
+                "te/device/child1///cmd/restart/az-method-7",
+                r#"{"status":"failed","reason":"Timeout"}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].topic.name, "az/methods/res/500/?$rid=7");
+        assert_json_eq!(payload(&output[0]), json!({"reason": "Timeout"}));
+        assert_eq!(
+            output[1].topic.name,
+            "te/device/child1///cmd/restart/az-method-7"
+        );
+        assert!(output[1].payload_bytes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unsupported_direct_methods_are_rejected() {
+        let mut converter = create_test_converter(None).await;
+
+        let output = converter
+            .convert("az/methods/POST/restart/?$rid=8", "{}")
+            .await
+            .unwrap();
+        assert_eq!(output[0].topic.name, "az/methods/res/404/?$rid=8");
+
+        let output = converter
+            .convert("az/methods/POST/restart/?$rid=9", "[1,2,3]")
+            .await
+            .unwrap();
+        assert_eq!(output[0].topic.name, "az/methods/res/400/?$rid=9");
+    }
+
+    #[tokio::test]
+    async fn cloud_to_device_messages_are_republished_on_the_configured_topic() {
+        let mut converter = create_test_converter(Some("c2d/messages")).await;
+        let output = converter
+            .convert(
+                "az/messages/devicebound/%24.to=%2Fdevices%2Fmy-device",
+                "hello",
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic.name, "c2d/messages");
+        assert_eq!(output[0].payload_str().unwrap(), "hello");
+
+        let mut converter = create_test_converter(None).await;
+        let output = converter
+            .convert(
+                "az/messages/devicebound/%24.to=%2Fdevices%2Fmy-device",
+                "hello",
+            )
+            .await
+            .unwrap();
+        assert!(output.is_empty());
+    }
+
+    fn payload(message: &MqttMessage) -> serde_json::Value {
+        serde_json::from_str(message.payload_str().unwrap()).unwrap()
+    }
+
+    async fn create_test_converter(c2d_topic: Option<&str>) -> AzureFlows {
+        let converter = AzureConverter::new(
+            false,
+            &MqttSchema::default(),
+            TimeFormat::Unix,
+            &TopicPrefix::try_from("az").unwrap(),
+            262144,
+            TE_MEA_TOPICS.to_string(),
+            c2d_topic.map(str::to_owned),
+        );
+        let temp_dir = tempfile::TempDir::new().expect("Failed to create temp dir");
+        let flows_dir = Utf8PathBuf::from_path_buf(temp_dir.path().to_path_buf()).unwrap();
+        let managed_dir = TedgePaths::from_root_with_defaults(&flows_dir, "", "").root_dir();
+        let mut flows = ConnectedFlowRegistry::new(HashMap::new(), managed_dir).unwrap();
+        load_builtin_transformers(&mut flows);
+        converter.persist_builtin_flow(&mut flows).await.unwrap();
+
+        let mut runtime = MessageProcessor::with_default(flows).await.unwrap();
+        runtime.load_all_flows().await;
+
+        AzureFlows {
+            runtime,
+            _flows_dir: temp_dir,
+        }
+    }
+
+    struct AzureFlows {
+        runtime: MessageProcessor<ConnectedFlowRegistry>,
+        _flows_dir: tempfile::TempDir,
+    }
+
+    impl AzureFlows {
+        async fn convert(
+            &mut self,
+            topic: &str,
+            payload: &str,
+        ) -> Result<Vec<MqttMessage>, FlowError> {
+            let message = Message::new(topic, payload);
+            let results = self
+                .runtime
+                .on_message(SystemTime::now(), &SourceTag::Mqtt, &message)
+                .await;
+
+            let mut output = vec![];
+            for result in results {
+                match result {
+                    FlowResult::Ok { messages, .. } => output.extend(messages),
+                    FlowResult::Err { error, .. } => return Err(error),
+                }
+            }
+
+            let mut messages = vec![];
+            for message in output {
+                messages.push(MqttMessage::try_from(message)?);
+            }
+            Ok(messages)
+        }
+    }
 }
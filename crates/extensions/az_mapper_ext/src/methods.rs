@@ -0,0 +1,384 @@
+//! Translate Azure IoT Hub direct methods into thin-edge commands.
+//!
+//! A direct method invoked on the device (locally `az/methods/POST/{method}/?$rid={rid}`)
+//! creates a thin-edge command, using the method name as the command type
+//! and the properties of the method payload as the command parameters.
+//! The command is created for the main device unless the payload specifies another `@topic-id`.
+//!
+//! The method response (locally `az/methods/res/{status}/?$rid={rid}`) is sent when the command reaches a final state:
+//! - `200` with the final state of the command, if successful
+//! - `500` with the failure reason, if failed.
+//!
+//! A method is immediately rejected with a `400` status if its payload is invalid,
+//! and with a `404` status if the target entity doesn't support the operation.
+use crate::operations::clear_command;
+use crate::operations::command_request;
+use crate::operations::is_valid_topic_segment;
+use crate::operations::Capabilities;
+use crate::operations::CommandState;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::time::SystemTime;
+use tedge_api::mqtt_topics::Channel;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_api::mqtt_topics::OperationType;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+/// The property of a method payload giving the topic id of the target entity
+const TARGET_PROPERTY: &str = "@topic-id";
+
+#[derive(Clone)]
+pub struct AzureMethods {
+    prefix: String,
+    mqtt_schema: MqttSchema,
+    capabilities: Capabilities,
+}
+
+impl Default for AzureMethods {
+    fn default() -> Self {
+        AzureMethods {
+            prefix: "az".to_string(),
+            mqtt_schema: MqttSchema::default(),
+            capabilities: Capabilities::default(),
+        }
+    }
+}
+
+impl tedge_flows::Transformer for AzureMethods {
+    fn name(&self) -> &str {
+        "az-methods"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(prefix) = config.string_property("prefix") {
+            self.prefix = prefix.to_owned();
+        }
+        if let Some(topic_root) = config.string_property("topic_root") {
+            self.mqtt_schema = MqttSchema::with_root(topic_root.to_owned());
+        }
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if let Some((method, rid)) = self.method_of(&message.topic) {
+            let (method, rid) = (method.to_owned(), rid.to_owned());
+            return Ok(vec![self.on_method(method, rid, &message.payload)]);
+        }
+
+        match self.mqtt_schema.entity_channel_of(&message.topic) {
+            Ok((target, Channel::CommandMetadata { operation })) => {
+                self.capabilities
+                    .update(target, operation, &message.payload);
+                Ok(vec![])
+            }
+            Ok((target, Channel::Command { operation, cmd_id })) => {
+                match self.request_id(&cmd_id).map(str::to_owned) {
+                    Some(rid) => self.on_command_update(target, operation, rid, &message.payload),
+                    None => Ok(vec![]),
+                }
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+impl AzureMethods {
+    /// Extract the method name and the request id from a method invocation topic
+    fn method_of<'a>(&self, topic: &'a str) -> Option<(&'a str, &'a str)> {
+        let (method, query) = topic
+            .strip_prefix(&self.prefix)?
+            .strip_prefix("/methods/POST/")?
+            .split_once("/?")?;
+        let rid = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("$rid="))?;
+        Some((method, rid))
+    }
+
+    fn cmd_id(&self, rid: &str) -> String {
+        format!("{}-method-{rid}", self.prefix)
+    }
+
+    fn request_id<'a>(&self, cmd_id: &'a str) -> Option<&'a str> {
+        cmd_id.strip_prefix(&self.prefix)?.strip_prefix("-method-")
+    }
+
+    fn on_method(&self, method: String, rid: String, payload: &[u8]) -> Message {
+        if !is_valid_topic_segment(&method) || !is_valid_topic_segment(&rid) {
+            return self.method_response(&rid, 400, error_payload("Invalid method name"));
+        }
+        match self.command_target(payload) {
+            Ok((target, parameters)) => {
+                let operation: OperationType = method.as_str().into();
+                if !self.capabilities.supports(&target, &operation) {
+                    let reason = format!("{target} doesn't support the {operation} operation");
+                    return self.method_response(&rid, 404, error_payload(&reason));
+                }
+                command_request(
+                    &self.mqtt_schema,
+                    &target,
+                    operation,
+                    self.cmd_id(&rid),
+                    parameters,
+                )
+            }
+            Err(reason) => self.method_response(&rid, 400, error_payload(&reason)),
+        }
+    }
+
+    /// Extract the target entity and the command parameters from a method payload
+    fn command_target(
+        &self,
+        payload: &[u8],
+    ) -> Result<(EntityTopicId, Map<String, Value>), String> {
+        let mut parameters = if payload.is_empty() {
+            Map::new()
+        } else {
+            match serde_json::from_slice(payload) {
+                Ok(Value::Object(parameters)) => parameters,
+                Ok(Value::Null) => Map::new(),
+                Ok(_) => return Err("The method payload is not a JSON object".to_string()),
+                Err(err) => return Err(format!("Invalid method payload: {err}")),
+            }
+        };
+        let target = match parameters.remove(TARGET_PROPERTY) {
+            None => EntityTopicId::default_main_device(),
+            Some(Value::String(topic_id)) => topic_id
+                .parse()
+                .map_err(|err| format!("Invalid '{TARGET_PROPERTY}': {err}"))?,
+            Some(_) => return Err(format!("Invalid '{TARGET_PROPERTY}': not a string")),
+        };
+        Ok((target, parameters))
+    }
+
+    fn on_command_update(
+        &self,
+        target: EntityTopicId,
+        operation: OperationType,
+        rid: String,
+        payload: &[u8],
+    ) -> Result<Vec<Message>, FlowError> {
+        if payload.is_empty() {
+            return Ok(vec![]);
+        }
+        let state = CommandState::parse(&operation, payload)?;
+        if !state.is_final() {
+            return Ok(vec![]);
+        }
+
+        let response = if state.status == "successful" {
+            self.method_response(&rid, 200, payload.to_vec())
+        } else {
+            let reason = state
+                .reason
+                .unwrap_or_else(|| format!("The {operation} command failed"));
+            self.method_response(&rid, 500, error_payload(&reason))
+        };
+        let cmd_id = self.cmd_id(&rid);
+        Ok(vec![
+            response,
+            clear_command(&self.mqtt_schema, &target, operation, cmd_id),
+        ])
+    }
+
+    fn method_response(&self, rid: &str, status: u16, payload: Vec<u8>) -> Message {
+        Message::new(
+            format!("{}/methods/res/{status}/?$rid={rid}", self.prefix),
+            payload,
+        )
+    }
+}
+
+fn error_payload(reason: &str) -> Vec<u8> {
+    json!({ "reason": reason }).to_string().into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::on_message;
+    use crate::test_helpers::published;
+    use tedge_flows::Transformer;
+
+    #[test]
+    fn methods_supported_by_the_target_create_commands() {
+        let mut methods = methods_supporting(&[("device/main//", "restart")]);
+
+        let output = on_message(&mut methods, "az/methods/POST/restart/?$rid=1", "");
+        assert_eq!(
+            published(&output),
+            vec![(
+                "te/device/main///cmd/restart/az-method-1".to_string(),
+                json!({"status": "init"})
+            )]
+        );
+
+        let mut methods = methods_supporting(&[("device/child1//", "software_update")]);
+        let output = on_message(
+            &mut methods,
+            "az/methods/POST/software_update/?$rid=2&other=x",
+            r#"{"@topic-id": "device/child1//", "updateList": []}"#,
+        );
+        assert_eq!(
+            published(&output),
+            vec![(
+                "te/device/child1///cmd/software_update/az-method-2".to_string(),
+                json!({"status": "init", "updateList": []})
+            )]
+        );
+    }
+
+    #[test]
+    fn successful_commands_are_responded_with_their_final_state() {
+        let mut methods = AzureMethods::default();
+        let cmd_topic = "te/device/main///cmd/restart/az-method-3";
+
+        assert!(on_message(&mut methods, cmd_topic, r#"{"status":"executing"}"#).is_empty());
+
+        let output = on_message(
+            &mut methods,
+            cmd_topic,
+            r#"{"status":"successful","delay":5}"#,
+        );
+        assert_eq!(
+            published(&output),
+            vec![
+                (
+                    "az/methods/res/200/?$rid=3".to_string(),
+                    json!({"status": "successful", "delay": 5})
+                ),
+                (cmd_topic.to_string(), Value::Null),
+            ]
+        );
+        assert!(output[1].payload.is_empty());
+
+        // Once cleared, the command is not responded to anymore
+        assert!(on_message(&mut methods, cmd_topic, "").is_empty());
+    }
+
+    #[test]
+    fn failed_commands_are_responded_with_the_failure_reason() {
+        let mut methods = AzureMethods::default();
+
+        let output = on_message(
+            &mut methods,
+            "te/device/main///cmd/restart/az-method-4",
+            r#"{"status":"failed","reason":"Timeout"}"#,
+        );
+        assert_eq!(
+            published(&output)[0],
+            (
+                "az/methods/res/500/?$rid=4".to_string(),
+                json!({"reason": "Timeout"})
+            )
+        );
+
+        let output = on_message(
+            &mut methods,
+            "te/device/main///cmd/restart/az-method-5",
+            r#"{"status":"failed"}"#,
+        );
+        assert_eq!(
+            published(&output)[0],
+            (
+                "az/methods/res/500/?$rid=5".to_string(),
+                json!({"reason": "The restart command failed"})
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_method_payloads_are_rejected() {
+        let mut methods = methods_supporting(&[("device/main//", "restart")]);
+
+        for (payload, reason) in [
+            ("[1,2,3]", "The method payload is not a JSON object"),
+            (r#"{"@topic-id": 42}"#, "Invalid '@topic-id': not a string"),
+        ] {
+            let output = on_message(&mut methods, "az/methods/POST/restart/?$rid=6", payload);
+            assert_eq!(
+                published(&output),
+                vec![(
+                    "az/methods/res/400/?$rid=6".to_string(),
+                    json!({"reason": reason})
+                )],
+                "{payload}"
+            );
+        }
+
+        let output = on_message(
+            &mut methods,
+            "az/methods/POST/restart/?$rid=7",
+            "{ not json",
+        );
+        assert_eq!(output[0].topic, "az/methods/res/400/?$rid=7");
+        assert!(published(&output)[0].1["reason"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid method payload"));
+    }
+
+    #[test]
+    fn unsupported_methods_are_rejected() {
+        let mut methods = methods_supporting(&[("device/child1//", "restart")]);
+
+        let output = on_message(&mut methods, "az/methods/POST/restart/?$rid=8", "{}");
+        assert_eq!(
+            published(&output),
+            vec![(
+                "az/methods/res/404/?$rid=8".to_string(),
+                json!({"reason": "device/main// doesn't support the restart operation"})
+            )]
+        );
+
+        let output = on_message(&mut methods, "az/methods/POST/+/?$rid=9", "{}");
+        assert_eq!(
+            published(&output),
+            vec![(
+                "az/methods/res/400/?$rid=9".to_string(),
+                json!({"reason": "Invalid method name"})
+            )]
+        );
+    }
+
+    #[test]
+    fn commands_not_created_for_methods_are_ignored() {
+        let mut methods = AzureMethods::default();
+
+        for cmd_id in ["c8y-mapper-1", "az-desired-2", "aws-job-3"] {
+            let topic = format!("te/device/main///cmd/restart/{cmd_id}");
+            assert!(on_message(&mut methods, &topic, r#"{"status":"successful"}"#).is_empty());
+        }
+    }
+
+    #[test]
+    fn malformed_command_states_are_rejected() {
+        let mut methods = AzureMethods::default();
+        let message = Message::new("te/device/main///cmd/restart/az-method-10", "not json");
+
+        assert!(matches!(
+            methods.on_message(SystemTime::now(), &message, &FlowContextHandle::default()),
+            Err(FlowError::UnsupportedMessage(_))
+        ));
+    }
+
+    fn methods_supporting(operations: &[(&str, &str)]) -> AzureMethods {
+        let mut methods = AzureMethods::default();
+        for (topic_id, operation) in operations {
+            let topic = format!("te/{topic_id}/cmd/{operation}");
+            assert!(on_message(&mut methods, &topic, "{}").is_empty());
+        }
+        methods
+    }
+}
@@ -0,0 +1,182 @@
+//! Helpers shared by the flows creating thin-edge commands on behalf of Azure IoT Hub
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use tedge_api::mqtt_topics::Channel;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_api::mqtt_topics::OperationType;
+use tedge_flows::FlowError;
+use tedge_flows::Message;
+use tedge_flows::Transport;
+use tedge_mqtt_ext::QoS;
+
+/// The operations supported by each entity, as advertised by the command metadata
+#[derive(Clone, Default)]
+pub(crate) struct Capabilities {
+    operations: HashMap<EntityTopicId, HashSet<OperationType>>,
+}
+
+impl Capabilities {
+    /// Update the capabilities from a command metadata message
+    pub fn update(&mut self, entity: EntityTopicId, operation: OperationType, payload: &[u8]) {
+        let operations = self.operations.entry(entity).or_default();
+        if payload.is_empty() {
+            operations.remove(&operation);
+        } else {
+            operations.insert(operation);
+        }
+    }
+
+    pub fn supports(&self, entity: &EntityTopicId, operation: &OperationType) -> bool {
+        self.operations
+            .get(entity)
+            .is_some_and(|operations| operations.contains(operation))
+    }
+}
+
+/// The state of a thin-edge command, as published by the agent
+#[derive(Debug, Deserialize)]
+pub(crate) struct CommandState {
+    pub status: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl CommandState {
+    pub fn parse(operation: &OperationType, payload: &[u8]) -> Result<Self, FlowError> {
+        serde_json::from_slice(payload).map_err(|err| {
+            FlowError::UnsupportedMessage(format!("Invalid {operation} command state: {err}"))
+        })
+    }
+
+    pub fn is_final(&self) -> bool {
+        self.status == "successful" || self.status == "failed"
+    }
+}
+
+/// Build the request of a new command
+pub(crate) fn command_request(
+    mqtt_schema: &MqttSchema,
+    target: &EntityTopicId,
+    operation: OperationType,
+    cmd_id: String,
+    mut parameters: Map<String, Value>,
+) -> Message {
+    parameters.insert("status".to_string(), json!("init"));
+    let channel = Channel::Command { operation, cmd_id };
+    let topic = mqtt_schema.topic_for(target, &channel);
+    retained(topic.name, Value::Object(parameters).to_string())
+}
+
+/// Clear a command that reached a final state
+pub(crate) fn clear_command(
+    mqtt_schema: &MqttSchema,
+    target: &EntityTopicId,
+    operation: OperationType,
+    cmd_id: String,
+) -> Message {
+    let channel = Channel::Command { operation, cmd_id };
+    let topic = mqtt_schema.topic_for(target, &channel);
+    retained(topic.name, "")
+}
+
+/// Check that a name can be used as an MQTT topic segment
+pub(crate) fn is_valid_topic_segment(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['/', '+', '#'])
+}
+
+/// Build a message to be published with the retain flag set
+pub(crate) fn retained(topic: String, payload: impl Into<Vec<u8>>) -> Message {
+    let mut message = Message::new(topic, payload);
+    message.transport = Some(Transport::Mqtt {
+        qos: QoS::AtLeastOnce,
+        retain: true,
+    });
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::is_retained;
+
+    #[test]
+    fn capabilities_are_updated_from_command_metadata() {
+        let mut capabilities = Capabilities::default();
+        let child: EntityTopicId = "device/child1//".parse().unwrap();
+        let restart: OperationType = "restart".into();
+
+        capabilities.update(child.clone(), restart.clone(), b"{}");
+        assert!(capabilities.supports(&child, &restart));
+        assert!(!capabilities.supports(&EntityTopicId::default_main_device(), &restart));
+        assert!(!capabilities.supports(&child, &"firmware_update".into()));
+
+        capabilities.update(child.clone(), restart.clone(), b"");
+        assert!(!capabilities.supports(&child, &restart));
+    }
+
+    #[test]
+    fn command_states_are_parsed() {
+        let operation: OperationType = "restart".into();
+
+        let state = CommandState::parse(&operation, br#"{"status":"executing"}"#).unwrap();
+        assert!(!state.is_final());
+
+        let state =
+            CommandState::parse(&operation, br#"{"status":"failed","reason":"Timeout"}"#).unwrap();
+        assert!(state.is_final());
+        assert_eq!(state.reason.as_deref(), Some("Timeout"));
+
+        assert!(matches!(
+            CommandState::parse(&operation, br#"{"reason":"no status"}"#),
+            Err(FlowError::UnsupportedMessage(reason)) if reason.starts_with("Invalid restart command state")
+        ));
+    }
+
+    #[test]
+    fn command_requests_and_clearing_messages_are_retained() {
+        let mqtt_schema = MqttSchema::with_root("tedge".to_string());
+        let target: EntityTopicId = "device/child1//".parse().unwrap();
+        let parameters = Map::from_iter([("name".to_string(), json!("fw"))]);
+
+        let request = command_request(
+            &mqtt_schema,
+            &target,
+            "firmware_update".into(),
+            "az-method-1".to_string(),
+            parameters,
+        );
+        assert_eq!(
+            request.topic,
+            "tedge/device/child1///cmd/firmware_update/az-method-1"
+        );
+        assert_eq!(
+            serde_json::from_slice::<Value>(&request.payload).unwrap(),
+            json!({"status": "init", "name": "fw"})
+        );
+        assert!(is_retained(&request));
+
+        let clear = clear_command(
+            &mqtt_schema,
+            &target,
+            "firmware_update".into(),
+            "az-method-1".to_string(),
+        );
+        assert_eq!(clear.topic, request.topic);
+        assert!(clear.payload.is_empty());
+        assert!(is_retained(&clear));
+    }
+
+    #[test]
+    fn topic_segments_are_checked() {
+        assert!(is_valid_topic_segment("firmware_update"));
+        assert!(!is_valid_topic_segment(""));
+        assert!(!is_valid_topic_segment("a/b"));
+        assert!(!is_valid_topic_segment("+"));
+        assert!(!is_valid_topic_segment("#"));
+    }
+}
@@ -0,0 +1,37 @@
+//! Helpers shared by the unit tests of the builtin transformers
+use serde_json::Value;
+use std::time::SystemTime;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::Message;
+use tedge_flows::Transformer;
+use tedge_flows::Transport;
+
+/// Process a message with a fresh context, the transformer being expected to succeed
+pub(crate) fn on_message(
+    transformer: &mut impl Transformer,
+    topic: &str,
+    payload: impl ToString,
+) -> Vec<Message> {
+    let message = Message::new(topic, payload.to_string());
+    transformer
+        .on_message(SystemTime::now(), &message, &FlowContextHandle::default())
+        .unwrap()
+}
+
+/// The topics and JSON payloads of the published messages, `null` standing for an empty or non-JSON payload
+pub(crate) fn published(messages: &[Message]) -> Vec<(String, Value)> {
+    messages
+        .iter()
+        .map(|message| {
+            let payload = serde_json::from_slice(&message.payload).unwrap_or(Value::Null);
+            (message.topic.clone(), payload)
+        })
+        .collect()
+}
+
+pub(crate) fn is_retained(message: &Message) -> bool {
+    matches!(
+        message.transport,
+        Some(Transport::Mqtt { retain: true, .. })
+    )
+}
@@ -0,0 +1,556 @@
+//! Synchronize the Azure IoT Hub device twin with the twin data of the thin-edge entities.
+//!
+//! The twin fragments of the main device (`te/device/main///twin/{key}`) are sent as reported properties
+//! (locally `az/twin/PATCH/properties/reported/?$rid={rid}`),
+//! while those of the other entities are grouped under a reported property named after the entity topic id,
+//! e.g. `device:child1` for `device/child1//`.
+//!
+//! Conversely, the desired properties (locally `az/twin/PATCH/properties/desired/?$version={version}`)
+//! are applied onto the entities:
+//! - a desired property named after an operation supported by the entity creates a command of this type,
+//!   the state of this command being then reported under the same property name,
+//! - any other desired property is applied onto the twin fragment of the same name.
+//!
+//! The version of the latest desired properties applied is reported as `desiredVersion`,
+//! so outdated desired properties are ignored, even after a restart of the mapper.
+use crate::operations::clear_command;
+use crate::operations::command_request;
+use crate::operations::retained;
+use crate::operations::Capabilities;
+use crate::operations::CommandState;
+use serde::Deserialize;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::SystemTime;
+use tedge_api::mqtt_topics::Channel;
+use tedge_api::mqtt_topics::EntityTopicId;
+use tedge_api::mqtt_topics::MqttSchema;
+use tedge_api::mqtt_topics::OperationType;
+use tedge_api::twin::entity_of_twin_name;
+use tedge_api::twin::entity_twin_name;
+use tedge_api::twin::is_valid_fragment_key;
+use tedge_api::twin::merge;
+use tedge_flows::parse_json_payload;
+use tedge_flows::ConfigError;
+use tedge_flows::FlowContextHandle;
+use tedge_flows::FlowError;
+use tedge_flows::JsonValue;
+use tedge_flows::Message;
+
+/// The reported property used to persist the version of the latest desired properties applied
+const DESIRED_VERSION_PROPERTY: &str = "desiredVersion";
+
+/// The request id used to get the whole device twin
+const GET_TWIN_REQUEST_ID: &str = "twin-get";
+
+#[derive(Clone)]
+pub struct AzureTwin {
+    prefix: String,
+    mqtt_schema: MqttSchema,
+    capabilities: Capabilities,
+    /// The twin data of the entities, as last published or applied
+    twins: HashMap<EntityTopicId, Map<String, Value>>,
+    /// The version of the latest desired properties applied
+    desired_version: Option<u64>,
+    /// The request id of the next reported properties update
+    next_request_id: u64,
+}
+
+/// The whole device twin, as returned on `twin/res/200`
+#[derive(Debug, Deserialize)]
+struct DeviceTwin {
+    #[serde(default)]
+    desired: Map<String, Value>,
+    #[serde(default)]
+    reported: Map<String, Value>,
+}
+
+impl Default for AzureTwin {
+    fn default() -> Self {
+        AzureTwin {
+            prefix: "az".to_string(),
+            mqtt_schema: MqttSchema::default(),
+            capabilities: Capabilities::default(),
+            twins: HashMap::new(),
+            desired_version: None,
+            next_request_id: 1,
+        }
+    }
+}
+
+impl tedge_flows::Transformer for AzureTwin {
+    fn name(&self) -> &str {
+        "az-twin"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(prefix) = config.string_property("prefix") {
+            self.prefix = prefix.to_owned();
+        }
+        if let Some(topic_root) = config.string_property("topic_root") {
+            self.mqtt_schema = MqttSchema::with_root(topic_root.to_owned());
+        }
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        if message.topic.starts_with(&self.desired_topic()) {
+            let desired = parse_json_payload(&message.payload, "desired properties")?;
+            return self.on_desired(desired, &Map::new());
+        }
+        if message.topic == self.get_twin_response_topic() {
+            let twin: DeviceTwin = parse_json_payload(&message.payload, "device twin")?;
+            return self.on_desired(twin.desired, &twin.reported);
+        }
+
+        match self.mqtt_schema.entity_channel_of(&message.topic) {
+            Ok((entity, Channel::EntityTwinData { fragment_key })) => {
+                self.on_twin_update(entity, fragment_key, &message.payload)
+            }
+            Ok((entity, Channel::CommandMetadata { operation })) => {
+                self.capabilities
+                    .update(entity, operation, &message.payload);
+                Ok(vec![])
+            }
+            Ok((entity, Channel::Command { operation, cmd_id })) if self.is_desired(&cmd_id) => {
+                self.on_command_update(entity, operation, cmd_id, &message.payload)
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn has_startup(&self) -> bool {
+        true
+    }
+
+    fn on_startup(
+        &mut self,
+        _timestamp: SystemTime,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        // The desired properties updated while the mapper was down are not notified, hence explicitly requested
+        Ok(vec![Message::new(
+            format!("{}/twin/GET/?$rid={GET_TWIN_REQUEST_ID}", self.prefix),
+            "",
+        )])
+    }
+}
+
+impl AzureTwin {
+    fn desired_topic(&self) -> String {
+        format!("{}/twin/PATCH/properties/desired/", self.prefix)
+    }
+
+    fn get_twin_response_topic(&self) -> String {
+        format!("{}/twin/res/200/?$rid={GET_TWIN_REQUEST_ID}", self.prefix)
+    }
+
+    fn cmd_id(&self, version: u64) -> String {
+        format!("{}-desired-{version}", self.prefix)
+    }
+
+    fn is_desired(&self, cmd_id: &str) -> bool {
+        cmd_id
+            .strip_prefix(&self.prefix)
+            .is_some_and(|suffix| suffix.starts_with("-desired-"))
+    }
+
+    /// Send a patch of reported properties, possibly for a child entity
+    fn report(&mut self, entity: &EntityTopicId, properties: Map<String, Value>) -> Message {
+        let patch = match entity_twin_name(entity) {
+            None => Value::Object(properties),
+            Some(name) => json!({ name: properties }),
+        };
+        let rid = self.next_request_id;
+        self.next_request_id += 1;
+        Message::new(
+            format!("{}/twin/PATCH/properties/reported/?$rid={rid}", self.prefix),
+            patch.to_string(),
+        )
+    }
+
+    fn on_twin_update(
+        &mut self,
+        entity: EntityTopicId,
+        fragment_key: String,
+        payload: &[u8],
+    ) -> Result<Vec<Message>, FlowError> {
+        let twin = self.twins.entry(entity.clone()).or_default();
+        let value = if payload.is_empty() {
+            twin.remove(&fragment_key);
+            Value::Null
+        } else {
+            let value: Value = parse_json_payload(payload, "twin value")?;
+            twin.insert(fragment_key.clone(), value.clone());
+            value
+        };
+
+        let properties = Map::from_iter([(fragment_key, value)]);
+        Ok(vec![self.report(&entity, properties)])
+    }
+
+    /// Apply desired properties, be they a patch or the whole desired state
+    ///
+    /// The reported properties are only used to skip the commands already successfully executed.
+    fn on_desired(
+        &mut self,
+        mut desired: Map<String, Value>,
+        reported: &Map<String, Value>,
+    ) -> Result<Vec<Message>, FlowError> {
+        let Some(version) = desired.remove("$version").and_then(|v| v.as_u64()) else {
+            return Err(FlowError::UnsupportedMessage(
+                "Desired properties without $version".to_string(),
+            ));
+        };
+        let reported_version = reported
+            .get(DESIRED_VERSION_PROPERTY)
+            .and_then(Value::as_u64);
+        if self
+            .desired_version
+            .max(reported_version)
+            .is_some_and(|applied| version <= applied)
+        {
+            return Ok(vec![]);
+        }
+        self.desired_version = Some(version);
+
+        let main_device = EntityTopicId::default_main_device();
+        let mut main_properties = Map::new();
+        let mut messages = vec![];
+        for (key, value) in desired {
+            match entity_of_twin_name(&key) {
+                Some(entity) => {
+                    let Value::Object(properties) = value else {
+                        continue;
+                    };
+                    let reported = match reported.get(&key) {
+                        Some(Value::Object(reported)) => reported.clone(),
+                        _ => Map::new(),
+                    };
+                    messages.extend(self.apply_desired(&entity, version, properties, &reported));
+                }
+                None => {
+                    main_properties.insert(key, value);
+                }
+            }
+        }
+        messages.extend(self.apply_desired(&main_device, version, main_properties, reported));
+
+        let properties = Map::from_iter([(DESIRED_VERSION_PROPERTY.to_string(), json!(version))]);
+        messages.push(self.report(&main_device, properties));
+        Ok(messages)
+    }
+
+    /// Apply the desired properties of an entity, creating commands or updating twin fragments
+    fn apply_desired(
+        &mut self,
+        entity: &EntityTopicId,
+        version: u64,
+        properties: Map<String, Value>,
+        reported: &Map<String, Value>,
+    ) -> Vec<Message> {
+        let mut messages = vec![];
+        for (key, value) in properties {
+            if key.starts_with('$') || !is_valid_fragment_key(&key) {
+                continue;
+            }
+
+            let operation: OperationType = key.as_str().into();
+            if self.capabilities.supports(entity, &operation) {
+                if let Value::Object(parameters) = value {
+                    if !is_already_executed(reported.get(&key), &parameters) {
+                        let cmd_id = self.cmd_id(version);
+                        messages.push(command_request(
+                            &self.mqtt_schema,
+                            entity,
+                            operation,
+                            cmd_id,
+                            parameters,
+                        ));
+                    }
+                    continue;
+                }
+            }
+
+            let twin = self.twins.entry(entity.clone()).or_default();
+            let channel = Channel::EntityTwinData {
+                fragment_key: key.clone(),
+            };
+            let topic = self.mqtt_schema.topic_for(entity, &channel);
+            if value.is_null() {
+                twin.remove(&key);
+                messages.push(retained(topic.name, ""));
+                continue;
+            }
+
+            // A patch only contains the properties of an object that have been updated
+            let mut twin_value = twin.get(&key).cloned().unwrap_or(Value::Null);
+            merge(&mut twin_value, value);
+            messages.push(retained(topic.name, twin_value.to_string()));
+            twin.insert(key, twin_value);
+        }
+        messages
+    }
+
+    /// Report the state of a command created from a desired property
+    fn on_command_update(
+        &mut self,
+        entity: EntityTopicId,
+        operation: OperationType,
+        cmd_id: String,
+        payload: &[u8],
+    ) -> Result<Vec<Message>, FlowError> {
+        if payload.is_empty() {
+            return Ok(vec![]);
+        }
+        let state = CommandState::parse(&operation, payload)?;
+        let value: Value = parse_json_payload(payload, "command state")?;
+        let properties = Map::from_iter([(operation.to_string(), value)]);
+        let mut messages = vec![self.report(&entity, properties)];
+        if state.is_final() {
+            messages.push(clear_command(&self.mqtt_schema, &entity, operation, cmd_id));
+        }
+        Ok(messages)
+    }
+}
+
+/// Check if a command has already been successfully executed with the same parameters
+fn is_already_executed(reported: Option<&Value>, parameters: &Map<String, Value>) -> bool {
+    let Some(Value::Object(reported)) = reported else {
+        return false;
+    };
+    reported.get("status") == Some(&json!("successful"))
+        && parameters
+            .iter()
+            .all(|(key, value)| reported.get(key) == Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::is_retained;
+    use crate::test_helpers::on_message;
+    use crate::test_helpers::published;
+    use tedge_flows::Transformer;
+
+    #[test]
+    fn twin_data_is_sent_as_reported_properties() {
+        let mut twin = AzureTwin::default();
+
+        let output = on_message(&mut twin, "te/device/main///twin/location", r#""hall""#);
+        assert_eq!(
+            published(&output),
+            vec![(
+                "az/twin/PATCH/properties/reported/?$rid=1".to_string(),
+                json!({"location": "hall"})
+            )]
+        );
+
+        let output = on_message(
+            &mut twin,
+            "te/device/child1/service/app/twin/version",
+            r#""1.2""#,
+        );
+        assert_eq!(
+            published(&output),
+            vec![(
+                "az/twin/PATCH/properties/reported/?$rid=2".to_string(),
+                json!({"device:child1:service:app": {"version": "1.2"}})
+            )]
+        );
+    }
+
+    #[test]
+    fn desired_property_patches_are_merged_into_the_twin_data() {
+        let mut twin = AzureTwin::default();
+        on_message(
+            &mut twin,
+            "te/device/main///twin/config",
+            r#"{"interval": 10, "unit": "s", "debug": true}"#,
+        );
+
+        let output = desired(
+            &mut twin,
+            json!({"config": {"interval": 60, "debug": null}, "$version": 2}),
+        );
+        assert_eq!(
+            published(&output),
+            vec![
+                (
+                    "te/device/main///twin/config".to_string(),
+                    json!({"interval": 60, "unit": "s"})
+                ),
+                (
+                    "az/twin/PATCH/properties/reported/?$rid=2".to_string(),
+                    json!({"desiredVersion": 2})
+                ),
+            ]
+        );
+        assert!(is_retained(&output[0]));
+    }
+
+    #[test]
+    fn desired_properties_of_child_entities_are_applied_onto_these_entities() {
+        let mut twin = AzureTwin::default();
+
+        let output = desired(
+            &mut twin,
+            json!({
+                "device:child1": {"label": "pump", "$metadata": {}, "a/b": 1},
+                "device:child2": "not an object",
+                "$version": 3
+            }),
+        );
+        assert_eq!(
+            published(&output),
+            vec![
+                ("te/device/child1///twin/label".to_string(), json!("pump")),
+                (
+                    "az/twin/PATCH/properties/reported/?$rid=1".to_string(),
+                    json!({"desiredVersion": 3})
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn desired_null_properties_clear_the_twin_fragments() {
+        let mut twin = AzureTwin::default();
+
+        let output = desired(&mut twin, json!({"label": null, "$version": 1}));
+        assert_eq!(output[0].topic, "te/device/main///twin/label");
+        assert!(output[0].payload.is_empty());
+        assert!(is_retained(&output[0]));
+    }
+
+    #[test]
+    fn desired_operations_create_commands_which_states_are_reported() {
+        let mut twin = AzureTwin::default();
+        on_message(&mut twin, "te/device/child1///cmd/firmware_update", "{}");
+
+        let output = desired(
+            &mut twin,
+            json!({"device:child1": {"firmware_update": {"name": "fw", "version": "2.0"}}, "$version": 4}),
+        );
+        let cmd_topic = "te/device/child1///cmd/firmware_update/az-desired-4";
+        assert_eq!(
+            published(&output)[0],
+            (
+                cmd_topic.to_string(),
+                json!({"status": "init", "name": "fw", "version": "2.0"})
+            )
+        );
+        assert!(is_retained(&output[0]));
+
+        let output = on_message(&mut twin, cmd_topic, r#"{"status":"executing"}"#);
+        assert_eq!(
+            published(&output),
+            vec![(
+                "az/twin/PATCH/properties/reported/?$rid=2".to_string(),
+                json!({"device:child1": {"firmware_update": {"status": "executing"}}})
+            )]
+        );
+
+        let output = on_message(&mut twin, cmd_topic, r#"{"status":"successful"}"#);
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[1].topic, cmd_topic);
+        assert!(output[1].payload.is_empty());
+
+        // Commands not created from desired properties are ignored
+        let output = on_message(
+            &mut twin,
+            "te/device/child1///cmd/firmware_update/az-method-5",
+            r#"{"status":"successful"}"#,
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn outdated_desired_properties_are_ignored() {
+        let mut twin = AzureTwin::default();
+
+        assert_eq!(
+            desired(&mut twin, json!({"label": "a", "$version": 5})).len(),
+            2
+        );
+        assert!(desired(&mut twin, json!({"label": "b", "$version": 5})).is_empty());
+        assert!(desired(&mut twin, json!({"label": "c", "$version": 4})).is_empty());
+        assert_eq!(
+            desired(&mut twin, json!({"label": "d", "$version": 6})).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn the_whole_twin_is_requested_and_applied_on_startup() {
+        let mut twin = AzureTwin::default();
+        twin.set_config(json!({"prefix": "az-eu", "topic_root": "tedge"}).into())
+            .unwrap();
+        on_message(&mut twin, "tedge/device/main///cmd/restart", "{}");
+
+        let startup = twin
+            .on_startup(SystemTime::now(), &FlowContextHandle::default())
+            .unwrap();
+        assert_eq!(startup[0].topic, "az-eu/twin/GET/?$rid=twin-get");
+
+        // The desired properties already applied before a restart are skipped
+        let output = on_message(
+            &mut twin,
+            "az-eu/twin/res/200/?$rid=twin-get",
+            json!({
+                "desired": {"label": "pump", "$version": 8},
+                "reported": {"desiredVersion": 8}
+            }),
+        );
+        assert!(output.is_empty());
+
+        // As the commands already executed with the same parameters
+        let output = on_message(
+            &mut twin,
+            "az-eu/twin/res/200/?$rid=twin-get",
+            json!({
+                "desired": {"label": "pump", "restart": {"delay": 5}, "$version": 9},
+                "reported": {"restart": {"status": "successful", "delay": 5}, "desiredVersion": 8}
+            }),
+        );
+        assert_eq!(
+            published(&output),
+            vec![
+                ("tedge/device/main///twin/label".to_string(), json!("pump")),
+                (
+                    "az-eu/twin/PATCH/properties/reported/?$rid=1".to_string(),
+                    json!({"desiredVersion": 9})
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_desired_properties_are_rejected() {
+        let mut twin = AzureTwin::default();
+
+        for payload in [r#"{"label": "no version"}"#, "not json"] {
+            let message = Message::new("az/twin/PATCH/properties/desired/?$version=1", payload);
+            assert!(
+                matches!(
+                    twin.on_message(SystemTime::now(), &message, &FlowContextHandle::default()),
+                    Err(FlowError::UnsupportedMessage(_))
+                ),
+                "{payload}"
+            );
+        }
+    }
+
+    fn desired(twin: &mut AzureTwin, properties: Value) -> Vec<Message> {
+        let version = properties["$version"].as_u64().unwrap();
+        let topic = format!("az/twin/PATCH/properties/desired/?$version={version}");
+        on_message(twin, &topic, properties)
+    }
+}
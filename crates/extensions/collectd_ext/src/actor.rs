@@ -1,6 +1,11 @@
 use crate::collectd::CollectdMessage;
+use crate::network::parse_packet;
+use crate::network::NetworkInput;
 use async_trait::async_trait;
 use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
 use tedge_actors::Actor;
 use tedge_actors::Builder;
 use tedge_actors::DynSender;
@@ -16,11 +21,34 @@ use tedge_actors::SimpleMessageBox;
 use tedge_actors::SimpleMessageBoxBuilder;
 use tedge_mqtt_ext::MqttMessage;
 use tedge_mqtt_ext::TopicFilter;
+use tokio::net::UdpSocket;
+use tracing::debug;
 use tracing::error;
+use tracing::info;
+use tracing::warn;
 
-/// An actor that collects measurements from collectd over MQTT
+/// The largest UDP payload, collectd packets being 1452 bytes by default
+const MAX_PACKET_SIZE: usize = 65507;
+
+/// Minimum delay between two warnings about invalid collectd packets
+const INVALID_PACKET_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An actor that collects measurements from collectd over MQTT,
+/// and optionally over UDP using the binary protocol of the collectd network plugin
 pub struct CollectdActor {
     messages: SimpleMessageBox<MqttMessage, CollectdMessage>,
+    network_input: Option<NetworkInput>,
+    invalid_packets: InvalidPackets,
+}
+
+/// The invalid packets received since the last warning
+///
+/// Any host can send packets to the collectd port,
+/// hence invalid packets are only reported from time to time.
+#[derive(Default)]
+struct InvalidPackets {
+    count: usize,
+    last_warning: Option<Instant>,
 }
 
 #[async_trait]
@@ -30,15 +58,82 @@ impl Actor for CollectdActor {
     }
 
     async fn run(mut self) -> Result<(), RuntimeError> {
-        while let Some(message) = self.messages.recv().await {
-            match CollectdMessage::parse_from(&message) {
-                Ok(collectd_message) => {
-                    for msg in collectd_message {
-                        self.messages.send(msg).await?
+        let Some(network_input) = self.network_input.take() else {
+            while let Some(message) = self.messages.recv().await {
+                self.process_mqtt_message(message).await?;
+            }
+            return Ok(());
+        };
+
+        let socket = UdpSocket::bind(network_input.address)
+            .await
+            .map_err(Box::new)?;
+        info!(
+            "Listening for collectd packets on udp://{}",
+            network_input.address
+        );
+        let mut packet = vec![0; MAX_PACKET_SIZE];
+        loop {
+            tokio::select! {
+                message = self.messages.recv() => match message {
+                    Some(message) => self.process_mqtt_message(message).await?,
+                    None => break,
+                },
+                received = socket.recv_from(&mut packet) => match received {
+                    Ok((size, sender)) => {
+                        self.process_packet(&packet[..size], sender, &network_input).await?
                     }
+                    Err(err) => error!("Error while receiving a collectd packet: {err}"),
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CollectdActor {
+    async fn process_mqtt_message(&mut self, message: MqttMessage) -> Result<(), RuntimeError> {
+        match CollectdMessage::parse_from(&message) {
+            Ok(collectd_message) => {
+                for msg in collectd_message {
+                    self.messages.send(msg).await?
+                }
+            }
+            Err(err) => {
+                error!("Error while decoding a collectd message: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    async fn process_packet(
+        &mut self,
+        packet: &[u8],
+        sender: SocketAddr,
+        network_input: &NetworkInput,
+    ) -> Result<(), RuntimeError> {
+        match parse_packet(packet, &network_input.security) {
+            Ok(collectd_message) => {
+                for msg in collectd_message {
+                    self.messages.send(msg).await?
                 }
-                Err(err) => {
-                    error!("Error while decoding a collectd message: {}", err);
+            }
+            Err(err) => {
+                debug!("Error while decoding a collectd packet from {sender}: {err}");
+                self.invalid_packets.count += 1;
+                if self
+                    .invalid_packets
+                    .last_warning
+                    .is_none_or(|last| last.elapsed() >= INVALID_PACKET_WARNING_INTERVAL)
+                {
+                    warn!(
+                        "Ignored {} invalid collectd packet(s), the last one from {sender}: {err}",
+                        self.invalid_packets.count
+                    );
+                    self.invalid_packets = InvalidPackets {
+                        count: 0,
+                        last_warning: Some(Instant::now()),
+                    };
                 }
             }
         }
@@ -49,6 +144,7 @@ impl Actor for CollectdActor {
 pub struct CollectdActorBuilder {
     topics: TopicFilter,
     message_box: SimpleMessageBoxBuilder<MqttMessage, CollectdMessage>,
+    network_input: Option<NetworkInput>,
 }
 
 impl CollectdActorBuilder {
@@ -56,9 +152,16 @@ impl CollectdActorBuilder {
         CollectdActorBuilder {
             topics,
             message_box: SimpleMessageBoxBuilder::new("Collectd", 16),
+            network_input: None,
         }
     }
 
+    /// Also receive the measurements sent over UDP by the collectd network plugin
+    pub fn with_network_input(mut self, network_input: NetworkInput) -> Self {
+        self.network_input = Some(network_input);
+        self
+    }
+
     pub fn add_input(&mut self, source: &mut impl MessageSource<MqttMessage, TopicFilter>) {
         source.connect_sink(self.topics.clone(), &self.message_box)
     }
@@ -86,6 +189,85 @@ impl Builder<CollectdActor> for CollectdActorBuilder {
     fn build(self) -> CollectdActor {
         CollectdActor {
             messages: self.message_box.build(),
+            network_input: self.network_input,
+            invalid_packets: InvalidPackets::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::tests::hex;
+    use crate::network::tests::PLAIN_PACKET;
+    use crate::network::NetworkSecurity;
+    use tedge_actors::NoMessage;
+    use tokio::time::timeout;
+
+    const TEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn measurements_received_over_udp_are_forwarded() {
+        let address = free_udp_address();
+        let mut builder =
+            CollectdActorBuilder::new(TopicFilter::empty()).with_network_input(NetworkInput {
+                address,
+                security: NetworkSecurity::default(),
+            });
+        let output = SimpleMessageBoxBuilder::<CollectdMessage, NoMessage>::new("Output", 16);
+        builder.connect_sink(NoConfig, &output);
+        let mut output = output.build();
+        // Keep the MQTT input open, as the actor stops once its inputs are closed
+        let _mqtt_input = builder.message_box.get_sender();
+        tokio::spawn(builder.build().run());
+
+        let collectd = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        collectd
+            .send_to(b"not a collectd packet", address)
+            .await
+            .unwrap();
+
+        // The packet is sent till the actor is listening
+        let message = timeout(TEST_TIMEOUT, async {
+            loop {
+                collectd.send_to(&hex(PLAIN_PACKET), address).await.unwrap();
+                if let Ok(Some(message)) = timeout(Duration::from_millis(100), output.recv()).await
+                {
+                    return message;
+                }
+            }
+        })
+        .await
+        .expect("a measurement forwarded by the actor");
+
+        assert_eq!(message.metric_group_key, "cpu-0");
+        assert_eq!(message.metric_key, "percent-idle");
+        assert_eq!(message.metric_value, 97.5);
+    }
+
+    #[tokio::test]
+    async fn invalid_packets_are_reported_at_most_once_per_interval() {
+        let mut actor = CollectdActorBuilder::new(TopicFilter::empty()).build();
+        let network_input = NetworkInput {
+            address: free_udp_address(),
+            security: NetworkSecurity::default(),
+        };
+        let sender = "127.0.0.1:25826".parse().unwrap();
+
+        for _ in 0..3 {
+            actor
+                .process_packet(b"not a collectd packet", sender, &network_input)
+                .await
+                .unwrap();
+        }
+
+        // Only the first invalid packet has been reported, the others being counted
+        assert!(actor.invalid_packets.last_warning.is_some());
+        assert_eq!(actor.invalid_packets.count, 2);
+    }
+
+    fn free_udp_address() -> SocketAddr {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.local_addr().unwrap()
+    }
+}
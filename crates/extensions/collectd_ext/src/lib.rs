@@ -3,3 +3,4 @@ pub mod batcher;
 pub mod collectd;
 pub mod converter;
 pub mod error;
+pub mod network;
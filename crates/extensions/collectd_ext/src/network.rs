@@ -0,0 +1,630 @@
+//! Decoding of the collectd binary network protocol.
+//!
+//! The network plugin of collectd sends the measurements over UDP using a [binary protocol]:
+//! a packet is a sequence of parts, each made of a type, a length and a value.
+//! The parts giving the plugin, the type and the time of a measurement
+//! are followed by a values part, which is turned into [CollectdMessage]s
+//! as a measurement published by the MQTT write plugin of collectd would be.
+//!
+//! The parts of a packet can be signed (HMAC-SHA-256) or encrypted (AES-256-OFB)
+//! with the password of a user known by both ends.
+//!
+//! [binary protocol]: https://github.com/collectd/collectd/wiki/Binary-protocol
+use crate::collectd::CollectdMessage;
+use aes::Aes256;
+use ofb::cipher::KeyIvInit;
+use ofb::cipher::StreamCipher;
+use ofb::Ofb;
+use ring::digest;
+use ring::hmac;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use time::Duration;
+use time::OffsetDateTime;
+use tracing::warn;
+
+const TYPE_HOST: u16 = 0x0000;
+const TYPE_TIME: u16 = 0x0001;
+const TYPE_PLUGIN: u16 = 0x0002;
+const TYPE_PLUGIN_INSTANCE: u16 = 0x0003;
+const TYPE_TYPE: u16 = 0x0004;
+const TYPE_TYPE_INSTANCE: u16 = 0x0005;
+const TYPE_VALUES: u16 = 0x0006;
+const TYPE_TIME_HR: u16 = 0x0008;
+const TYPE_SIGN_SHA256: u16 = 0x0200;
+const TYPE_ENCR_AES256: u16 = 0x0210;
+
+const DS_TYPE_COUNTER: u8 = 0;
+const DS_TYPE_GAUGE: u8 = 1;
+const DS_TYPE_DERIVE: u8 = 2;
+const DS_TYPE_ABSOLUTE: u8 = 3;
+
+const PART_HEADER_SIZE: usize = 4;
+const SHA256_SIZE: usize = 32;
+const SHA1_SIZE: usize = 20;
+const AES_IV_SIZE: usize = 16;
+
+/// The minimum protection required on the measurements received over the network
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SecurityLevel {
+    /// Accept signed, encrypted and plain measurements
+    #[default]
+    None,
+    /// Accept only signed or encrypted measurements
+    Sign,
+    /// Accept only encrypted measurements
+    Encrypt,
+}
+
+/// The UDP address on which collectd packets are received, and how these packets are secured
+#[derive(Debug, Clone)]
+pub struct NetworkInput {
+    pub address: SocketAddr,
+    pub security: NetworkSecurity,
+}
+
+/// The security settings applied to the packets received from collectd
+#[derive(Debug, Clone, Default)]
+pub struct NetworkSecurity {
+    pub level: SecurityLevel,
+    /// The passwords of the users allowed to sign or encrypt packets
+    pub users: HashMap<String, String>,
+}
+
+impl NetworkSecurity {
+    /// Parse an authentication file, as used by the collectd network plugin
+    ///
+    /// Each line gives the password of a user, as in `user: password`.
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse_auth_file(content: &str) -> HashMap<String, String> {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(user, password)| (user.trim().to_string(), password.trim().to_string()))
+            .collect()
+    }
+}
+
+#[derive(thiserror::Error, Debug, Eq, PartialEq)]
+pub enum CollectdPacketError {
+    #[error("Truncated collectd packet")]
+    TruncatedPacket,
+
+    #[error("Invalid part of type {part_type:#06x} in collectd packet: {reason}")]
+    InvalidPart {
+        part_type: u16,
+        reason: &'static str,
+    },
+
+    #[error("Unknown collectd user: {0}")]
+    UnknownUser(String),
+
+    #[error("Invalid signature of collectd packet sent by user: {0}")]
+    InvalidSignature(String),
+
+    #[error("Failed to decrypt collectd packet sent by user: {0}")]
+    DecryptionFailed(String),
+}
+
+/// Decode the measurements of a collectd network packet
+pub fn parse_packet(
+    packet: &[u8],
+    security: &NetworkSecurity,
+) -> Result<Vec<CollectdMessage>, CollectdPacketError> {
+    let mut messages = Vec::new();
+    parse_parts(packet, SecurityLevel::None, security, &mut messages)?;
+    Ok(messages)
+}
+
+/// The plugin, type and time of the next values part
+#[derive(Default)]
+struct ValueList {
+    plugin: String,
+    plugin_instance: String,
+    type_name: String,
+    type_instance: String,
+    time: Option<OffsetDateTime>,
+}
+
+/// Decode a sequence of parts, given the protection already applied to these parts
+fn parse_parts(
+    mut buffer: &[u8],
+    mut protection: SecurityLevel,
+    security: &NetworkSecurity,
+    messages: &mut Vec<CollectdMessage>,
+) -> Result<(), CollectdPacketError> {
+    let mut value_list = ValueList::default();
+
+    while !buffer.is_empty() {
+        let (part_type, part, rest) = split_part(buffer)?;
+        match part_type {
+            TYPE_HOST => {
+                // As for the MQTT write plugin, the measurements are attributed to the main device
+                parse_string(part_type, part)?;
+            }
+            TYPE_PLUGIN => value_list.plugin = parse_string(part_type, part)?,
+            TYPE_PLUGIN_INSTANCE => value_list.plugin_instance = parse_string(part_type, part)?,
+            TYPE_TYPE => value_list.type_name = parse_string(part_type, part)?,
+            TYPE_TYPE_INSTANCE => value_list.type_instance = parse_string(part_type, part)?,
+            TYPE_TIME => {
+                let seconds = parse_number(part_type, part)?;
+                value_list.time = Some(timestamp(seconds << 30, part_type)?);
+            }
+            TYPE_TIME_HR => {
+                let time = parse_number(part_type, part)?;
+                value_list.time = Some(timestamp(time, part_type)?);
+            }
+            TYPE_VALUES => {
+                if protection < security.level {
+                    warn!(
+                        "Ignoring collectd measurements of {}/{}: the packet is not {}",
+                        value_list.plugin,
+                        value_list.type_name,
+                        if security.level == SecurityLevel::Sign {
+                            "signed"
+                        } else {
+                            "encrypted"
+                        }
+                    );
+                } else {
+                    let values = parse_values(part_type, part)?;
+                    messages.extend(value_list.messages(part_type, values)?);
+                }
+            }
+            // The signature covers all the parts following the signature part
+            TYPE_SIGN_SHA256 => {
+                let verified = verify_signature(part, rest, security)?;
+                if verified {
+                    protection = protection.max(SecurityLevel::Sign);
+                }
+            }
+            TYPE_ENCR_AES256 => {
+                let payload = decrypt(part, security)?;
+                parse_parts(&payload, SecurityLevel::Encrypt, security, messages)?;
+            }
+            _ => {
+                // Intervals, notifications and unknown parts are ignored
+            }
+        }
+        buffer = rest;
+    }
+
+    Ok(())
+}
+
+/// Split the next part of a buffer, returning the type of the part, its content and the remaining buffer
+fn split_part(buffer: &[u8]) -> Result<(u16, &[u8], &[u8]), CollectdPacketError> {
+    if buffer.len() < PART_HEADER_SIZE {
+        return Err(CollectdPacketError::TruncatedPacket);
+    }
+    let part_type = u16::from_be_bytes([buffer[0], buffer[1]]);
+    let part_length = u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+    if part_length < PART_HEADER_SIZE || part_length > buffer.len() {
+        return Err(CollectdPacketError::TruncatedPacket);
+    }
+    Ok((
+        part_type,
+        &buffer[PART_HEADER_SIZE..part_length],
+        &buffer[part_length..],
+    ))
+}
+
+fn parse_string(part_type: u16, part: &[u8]) -> Result<String, CollectdPacketError> {
+    let Some((0, string)) = part.split_last() else {
+        return Err(invalid_part(part_type, "string not null-terminated"));
+    };
+    String::from_utf8(string.to_vec()).map_err(|_| invalid_part(part_type, "non UTF-8 string"))
+}
+
+fn parse_number(part_type: u16, part: &[u8]) -> Result<u64, CollectdPacketError> {
+    let bytes = part
+        .try_into()
+        .map_err(|_| invalid_part(part_type, "expected a 64-bit number"))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Convert a collectd time, given in units of 2^-30 seconds
+fn timestamp(time: u64, part_type: u16) -> Result<OffsetDateTime, CollectdPacketError> {
+    let seconds = (time >> 30) as i64;
+    let nanoseconds = ((time & 0x3fff_ffff) * 1_000_000_000) >> 30;
+    OffsetDateTime::from_unix_timestamp(seconds)
+        .map(|time| time + Duration::nanoseconds(nanoseconds as i64))
+        .map_err(|_| invalid_part(part_type, "time out of range"))
+}
+
+fn parse_values(part_type: u16, part: &[u8]) -> Result<Vec<f64>, CollectdPacketError> {
+    let Some((count, part)) = part.split_first_chunk::<2>() else {
+        return Err(invalid_part(part_type, "missing number of values"));
+    };
+    let count = u16::from_be_bytes(*count) as usize;
+    if part.len() != count * 9 {
+        return Err(invalid_part(part_type, "unexpected number of values"));
+    }
+
+    let (types, values) = part.split_at(count);
+    types
+        .iter()
+        .zip(values.chunks_exact(8))
+        .map(|(data_source_type, value)| {
+            let value: [u8; 8] = value.try_into().unwrap();
+            match *data_source_type {
+                DS_TYPE_GAUGE => Ok(f64::from_le_bytes(value)),
+                DS_TYPE_COUNTER | DS_TYPE_ABSOLUTE => Ok(u64::from_be_bytes(value) as f64),
+                DS_TYPE_DERIVE => Ok(i64::from_be_bytes(value) as f64),
+                _ => Err(invalid_part(part_type, "unknown data source type")),
+            }
+        })
+        .collect()
+}
+
+impl ValueList {
+    /// The measurements for the given values,
+    /// named as `<plugin>-<plugin-instance>` / `<type>-<type-instance>` as by the MQTT write plugin
+    fn messages(
+        &self,
+        part_type: u16,
+        values: Vec<f64>,
+    ) -> Result<Vec<CollectdMessage>, CollectdPacketError> {
+        if self.plugin.is_empty() || self.type_name.is_empty() {
+            return Err(invalid_part(
+                part_type,
+                "values received before plugin and type",
+            ));
+        }
+        let metric_group_key = with_instance(&self.plugin, &self.plugin_instance);
+        let metric_key = with_instance(&self.type_name, &self.type_instance);
+        let timestamp = self.time.unwrap_or_else(OffsetDateTime::now_utc);
+
+        let num_measurements = values.len();
+        Ok(values
+            .into_iter()
+            .enumerate()
+            .map(|(i, metric_value)| CollectdMessage {
+                metric_group_key: metric_group_key.clone(),
+                // If there are multiple values, then create unique keys metric_key_val1, metric_key_val2 etc.
+                metric_key: if num_measurements > 1 {
+                    format!("{}_val{}", metric_key, i + 1)
+                } else {
+                    metric_key.clone()
+                },
+                timestamp,
+                metric_value,
+            })
+            .collect())
+    }
+}
+
+fn with_instance(name: &str, instance: &str) -> String {
+    if instance.is_empty() {
+        name.to_string()
+    } else {
+        format!("{name}-{instance}")
+    }
+}
+
+/// Check the signature of the parts following a signature part
+///
+/// Returns `false` if the signature can't be checked,
+/// because no users are configured and signed packets are not required.
+fn verify_signature(
+    part: &[u8],
+    signed_parts: &[u8],
+    security: &NetworkSecurity,
+) -> Result<bool, CollectdPacketError> {
+    if part.len() < SHA256_SIZE {
+        return Err(invalid_part(TYPE_SIGN_SHA256, "truncated signature"));
+    }
+    let (signature, username) = part.split_at(SHA256_SIZE);
+    let username = String::from_utf8_lossy(username).to_string();
+    if security.users.is_empty() && security.level == SecurityLevel::None {
+        return Ok(false);
+    }
+    let Some(password) = security.users.get(&username) else {
+        return Err(CollectdPacketError::UnknownUser(username));
+    };
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, password.as_bytes());
+    let signed_data = [username.as_bytes(), signed_parts].concat();
+    hmac::verify(&key, &signed_data, signature)
+        .map_err(|_| CollectdPacketError::InvalidSignature(username))?;
+    Ok(true)
+}
+
+/// Decrypt the parts embedded in an encrypted part
+fn decrypt(part: &[u8], security: &NetworkSecurity) -> Result<Vec<u8>, CollectdPacketError> {
+    let Some((username_length, part)) = part.split_first_chunk::<2>() else {
+        return Err(invalid_part(TYPE_ENCR_AES256, "missing username"));
+    };
+    let username_length = u16::from_be_bytes(*username_length) as usize;
+    if part.len() < username_length + AES_IV_SIZE + SHA1_SIZE {
+        return Err(invalid_part(TYPE_ENCR_AES256, "truncated encrypted data"));
+    }
+    let (username, part) = part.split_at(username_length);
+    let (iv, encrypted) = part.split_at(AES_IV_SIZE);
+    let username = String::from_utf8_lossy(username).to_string();
+    let Some(password) = security.users.get(&username) else {
+        return Err(CollectdPacketError::UnknownUser(username));
+    };
+
+    let key = digest::digest(&digest::SHA256, password.as_bytes());
+    let mut cipher = Ofb::<Aes256>::new_from_slices(key.as_ref(), iv)
+        .map_err(|_| CollectdPacketError::DecryptionFailed(username.clone()))?;
+    let mut decrypted = encrypted.to_vec();
+    cipher.apply_keystream(&mut decrypted);
+
+    // The decrypted data starts with the SHA-1 hash of the embedded parts
+    let payload = decrypted.split_off(SHA1_SIZE);
+    let hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, &payload);
+    if hash.as_ref() != decrypted.as_slice() {
+        return Err(CollectdPacketError::DecryptionFailed(username));
+    }
+    Ok(payload)
+}
+
+fn invalid_part(part_type: u16, reason: &'static str) -> CollectdPacketError {
+    CollectdPacketError::InvalidPart { part_type, reason }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use time::macros::datetime;
+
+    /// A packet giving the idle cpu percentage, as sent by collectd
+    pub(crate) const PLAIN_PACKET: &str = "0000000b646576696365000008000c15554dc06000000000020008637075000003000630000004000c70657263656e74000005000969646c65000006000f0001010000000000605840";
+
+    /// The same packet signed by user `tedge` with password `secret`
+    const SIGNED_PACKET: &str = "02000029a934e621ad56010938c0c2fc8fc0d05f31ace15f64d21294b726889913d8c41174656467650000000b646576696365000008000c15554dc06000000000020008637075000003000630000004000c70657263656e74000005000969646c65000006000f0001010000000000605840";
+
+    /// The same packet encrypted by user `tedge` with password `secret`
+    const ENCRYPTED_PACKET: &str = "0210007800057465646765000102030405060708090a0b0c0d0e0f0f9e87caf28f3350ef39f3e39097af8bb2d0e071805ac9380f62413e9a1b8727980eefd8f9832a6af2e98ba7427ca281c4d37c1f19c25df3cfbf2e75ab89b15056875cfba138b7f2959bca1074730d072183cf788c591261a78b84aaee";
+
+    pub(crate) fn hex(packet: &str) -> Vec<u8> {
+        (0..packet.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&packet[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn string_part(part_type: u16, value: &str) -> Vec<u8> {
+        let length = (PART_HEADER_SIZE + value.len() + 1) as u16;
+        [
+            &part_type.to_be_bytes()[..],
+            &length.to_be_bytes(),
+            value.as_bytes(),
+            &[0],
+        ]
+        .concat()
+    }
+
+    fn number_part(part_type: u16, value: u64) -> Vec<u8> {
+        [
+            &part_type.to_be_bytes()[..],
+            &12u16.to_be_bytes(),
+            &value.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    fn values_part(values: &[(u8, [u8; 8])]) -> Vec<u8> {
+        let length = (PART_HEADER_SIZE + 2 + 9 * values.len()) as u16;
+        let mut part = [
+            &TYPE_VALUES.to_be_bytes()[..],
+            &length.to_be_bytes(),
+            &(values.len() as u16).to_be_bytes(),
+        ]
+        .concat();
+        part.extend(values.iter().map(|(data_source_type, _)| data_source_type));
+        part.extend(values.iter().flat_map(|(_, value)| value));
+        part
+    }
+
+    fn users() -> HashMap<String, String> {
+        HashMap::from([("tedge".to_string(), "secret".to_string())])
+    }
+
+    fn assert_idle_cpu(messages: Vec<CollectdMessage>) {
+        assert_eq!(messages.len(), 1);
+        let message = &messages[0];
+        assert_eq!(message.metric_group_key, "cpu-0");
+        assert_eq!(message.metric_key, "percent-idle");
+        assert_eq!(message.metric_value, 97.5);
+        assert_eq!(message.timestamp, datetime!(2015-05-15 0:00:01.5 UTC));
+    }
+
+    #[test]
+    fn parse_plain_packet() {
+        let messages = parse_packet(&hex(PLAIN_PACKET), &NetworkSecurity::default()).unwrap();
+
+        assert_idle_cpu(messages);
+    }
+
+    #[test]
+    fn parse_multi_valued_measurement() {
+        let packet = [
+            string_part(TYPE_HOST, "device"),
+            number_part(TYPE_TIME, 123456789),
+            string_part(TYPE_PLUGIN, "interface"),
+            string_part(TYPE_TYPE, "if_octets"),
+            values_part(&[
+                (DS_TYPE_DERIVE, 1234i64.to_be_bytes()),
+                (DS_TYPE_COUNTER, 5678u64.to_be_bytes()),
+            ]),
+            string_part(TYPE_PLUGIN, "memory"),
+            string_part(TYPE_TYPE, "memory"),
+            string_part(TYPE_TYPE_INSTANCE, "used"),
+            values_part(&[(DS_TYPE_GAUGE, 42.0f64.to_le_bytes())]),
+        ]
+        .concat();
+
+        let messages = parse_packet(&packet, &NetworkSecurity::default()).unwrap();
+
+        let measurements: Vec<_> = messages
+            .iter()
+            .map(|m| {
+                (
+                    m.metric_group_key.as_str(),
+                    m.metric_key.as_str(),
+                    m.metric_value,
+                )
+            })
+            .collect();
+        assert_eq!(
+            measurements,
+            vec![
+                ("interface", "if_octets_val1", 1234.0),
+                ("interface", "if_octets_val2", 5678.0),
+                ("memory", "memory-used", 42.0),
+            ]
+        );
+        assert!(messages
+            .iter()
+            .all(|m| m.timestamp == datetime!(1973-11-29 21:33:09.0 UTC)));
+    }
+
+    #[test]
+    fn parse_signed_packet() {
+        let security = NetworkSecurity {
+            level: SecurityLevel::Sign,
+            users: users(),
+        };
+
+        let messages = parse_packet(&hex(SIGNED_PACKET), &security).unwrap();
+
+        assert_idle_cpu(messages);
+    }
+
+    #[test]
+    fn reject_tampered_signed_packet() {
+        let security = NetworkSecurity {
+            level: SecurityLevel::Sign,
+            users: users(),
+        };
+        let mut packet = hex(SIGNED_PACKET);
+        *packet.last_mut().unwrap() = 0x41;
+
+        let result = parse_packet(&packet, &security);
+
+        assert_eq!(
+            result.unwrap_err(),
+            CollectdPacketError::InvalidSignature("tedge".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_encrypted_packet() {
+        let security = NetworkSecurity {
+            level: SecurityLevel::Encrypt,
+            users: users(),
+        };
+
+        let messages = parse_packet(&hex(ENCRYPTED_PACKET), &security).unwrap();
+
+        assert_idle_cpu(messages);
+    }
+
+    #[test]
+    fn reject_packet_encrypted_with_another_password() {
+        let security = NetworkSecurity {
+            level: SecurityLevel::Encrypt,
+            users: HashMap::from([("tedge".to_string(), "other-secret".to_string())]),
+        };
+
+        let result = parse_packet(&hex(ENCRYPTED_PACKET), &security);
+
+        assert_eq!(
+            result.unwrap_err(),
+            CollectdPacketError::DecryptionFailed("tedge".to_string())
+        );
+    }
+
+    #[test]
+    fn reject_packet_from_unknown_user() {
+        let security = NetworkSecurity {
+            level: SecurityLevel::Sign,
+            users: HashMap::from([("admin".to_string(), "secret".to_string())]),
+        };
+
+        let result = parse_packet(&hex(SIGNED_PACKET), &security);
+
+        assert_eq!(
+            result.unwrap_err(),
+            CollectdPacketError::UnknownUser("tedge".to_string())
+        );
+    }
+
+    #[test]
+    fn ignore_measurements_below_the_security_level() {
+        let security = NetworkSecurity {
+            level: SecurityLevel::Encrypt,
+            users: users(),
+        };
+
+        assert!(parse_packet(&hex(PLAIN_PACKET), &security)
+            .unwrap()
+            .is_empty());
+        assert!(parse_packet(&hex(SIGNED_PACKET), &security)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn signature_is_not_checked_when_not_required() {
+        let mut packet = hex(SIGNED_PACKET);
+        *packet.last_mut().unwrap() = 0x41;
+
+        let messages = parse_packet(&packet, &NetworkSecurity::default()).unwrap();
+
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn reject_truncated_packet() {
+        let packet = hex(PLAIN_PACKET);
+
+        let result = parse_packet(&packet[..packet.len() - 1], &NetworkSecurity::default());
+
+        assert_matches!(result, Err(CollectdPacketError::TruncatedPacket));
+    }
+
+    #[test]
+    fn reject_values_without_plugin() {
+        let packet = [
+            number_part(TYPE_TIME, 123456789),
+            values_part(&[(DS_TYPE_GAUGE, 42.0f64.to_le_bytes())]),
+        ]
+        .concat();
+
+        let result = parse_packet(&packet, &NetworkSecurity::default());
+
+        assert_matches!(
+            result,
+            Err(CollectdPacketError::InvalidPart {
+                part_type: TYPE_VALUES,
+                ..
+            })
+        );
+    }
+
+    #[test]
+    fn parse_auth_file() {
+        let users = NetworkSecurity::parse_auth_file(
+            r#"
+            # collectd users
+            tedge: secret
+            device-01:p@ss:word
+            "#,
+        );
+
+        assert_eq!(
+            users,
+            HashMap::from([
+                ("tedge".to_string(), "secret".to_string()),
+                ("device-01".to_string(), "p@ss:word".to_string()),
+            ])
+        );
+    }
+}
@@ -1,10 +1,15 @@
 use crate::connected_flow::watch_request_topic;
 use crate::connected_flow::ConnectedFlowRegistry;
+use crate::context_store::ContextStore;
+use crate::dead_letters::DeadLetterStore;
 use crate::flow::FlowError;
 use crate::flow::FlowOutput;
 use crate::flow::FlowResult;
 use crate::flow::Message;
 use crate::flow::SourceTag;
+use crate::http_input::WebhookMessage;
+use crate::http_input::WebhookServers;
+use crate::http_output::HttpPublisher;
 use crate::params::is_params_file;
 use crate::registry::FlowRegistryExt;
 use crate::registry::RegistrationStatus;
@@ -52,19 +57,28 @@ pub struct FlowsMapper {
     processor: MessageProcessor<ConnectedFlowRegistry>,
     next_dump: Instant,
     deferred_tick: bool,
+    webhooks: WebhookServers,
+    http: HttpPublisher,
+    context_store: Option<ContextStore>,
+    dead_letters: Option<DeadLetterStore>,
 }
 
 impl FlowsMapper {
-    pub fn new(
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
         config: FlowsMapperConfig,
         messages: SimpleMessageBox<InputMessage, SubscriptionDiff>,
         mqtt_sender: DynSender<MqttMessage>,
         watch_request_sender: DynSender<WatchRequest>,
         subscriptions: TopicFilter,
         processor: MessageProcessor<ConnectedFlowRegistry>,
+        webhooks: WebhookServers,
+        http: HttpPublisher,
     ) -> Self {
         let watched_commands = HashSet::new();
         let next_dump = Instant::now() + config.stats_dump_interval;
+        let context_store = config.context_store.clone().map(ContextStore::new);
+        let dead_letters = config.dead_letters.clone().map(DeadLetterStore::new);
         FlowsMapper {
             config,
             messages,
@@ -75,6 +89,10 @@ impl FlowsMapper {
             processor,
             next_dump,
             deferred_tick: false,
+            webhooks,
+            http,
+            context_store,
+            dead_letters,
         }
     }
 }
@@ -87,6 +105,14 @@ impl Actor for FlowsMapper {
 
     async fn run(mut self) -> Result<(), RuntimeError> {
         self.send_updated_subscriptions().await?;
+        if let Some(context_store) = &mut self.context_store {
+            context_store
+                .restore(&self.processor.context_handle())
+                .await;
+        }
+        if let Some(dead_letters) = &mut self.dead_letters {
+            dead_letters.restore().await;
+        }
         self.notify_flows_status().await?;
         self.on_startup().await?;
 
@@ -99,6 +125,13 @@ impl Actor for FlowsMapper {
                 InputMessage::Tick(_) => {
                     self.on_source_poll().await?;
                     self.on_interval().await?;
+                    self.http.flush_expired(Instant::now());
+                    if let Some(context_store) = &mut self.context_store {
+                        context_store
+                            .on_interval(&self.processor.context_handle(), Instant::now())
+                            .await;
+                    }
+                    self.replay_failed_messages().await?;
                 }
                 InputMessage::MqttMessage(message) => {
                     let source = SourceTag::Mqtt;
@@ -107,6 +140,9 @@ impl Actor for FlowsMapper {
                 InputMessage::WatchEvent(event) => {
                     self.on_input_event(event).await?;
                 }
+                InputMessage::WebhookMessage(WebhookMessage { flow, message }) => {
+                    self.on_webhook_message(&flow, message).await?;
+                }
                 InputMessage::FsWatchEvent(event) => {
                     self.handle_fs_event(event).await?;
                     self.on_startup().await?;
@@ -114,6 +150,11 @@ impl Actor for FlowsMapper {
             }
         }
 
+        if let Some(context_store) = &mut self.context_store {
+            context_store
+                .snapshot(&self.processor.context_handle())
+                .await;
+        }
         Ok(())
     }
 }
@@ -124,6 +165,18 @@ impl FlowsMapper {
             .processor
             .next_interval_deadline()
             .map_or(self.next_dump, |deadline| min(deadline, self.next_dump));
+        let deadline = self
+            .http
+            .next_deadline()
+            .map_or(deadline, |batch_deadline| min(batch_deadline, deadline));
+        let deadline = self
+            .context_store
+            .as_ref()
+            .map_or(deadline, |store| min(store.next_deadline(), deadline));
+        let deadline = self
+            .dead_letters
+            .as_ref()
+            .map_or(deadline, |store| min(store.next_deadline(), deadline));
 
         tokio::select! {
             message = self.messages.recv() => {
@@ -141,6 +194,8 @@ impl FlowsMapper {
         for watch_request in self.update_watched_commands() {
             self.watch_request_sender.send(watch_request).await?;
         }
+        self.webhooks.update(self.processor.registry.flows()).await;
+        self.processor.update_context_persistence();
         Ok(())
     }
 
@@ -361,6 +416,22 @@ impl FlowsMapper {
         Ok(())
     }
 
+    async fn on_webhook_message(
+        &mut self,
+        flow_path: &Utf8Path,
+        message: Message,
+    ) -> Result<(), RuntimeError> {
+        let timestamp = SystemTime::now();
+        if let Some(result) = self
+            .processor
+            .on_flow_input(flow_path, timestamp, &message)
+            .await
+        {
+            self.publish_result(result).await?;
+        }
+        Ok(())
+    }
+
     async fn on_input_error(
         &mut self,
         watch_topic: &str,
@@ -415,6 +486,28 @@ impl FlowsMapper {
         Ok(())
     }
 
+    /// Process again the failed messages marked for replay by `tedge flows errors replay`
+    async fn replay_failed_messages(&mut self) -> Result<(), RuntimeError> {
+        let Some(dead_letters) = &mut self.dead_letters else {
+            return Ok(());
+        };
+        for letter in dead_letters.take_replays(Instant::now()).await {
+            let flow = Utf8PathBuf::from(letter.flow);
+            let timestamp = SystemTime::now();
+            match self
+                .processor
+                .on_flow_input(&flow, timestamp, &letter.message)
+                .await
+            {
+                Some(result) => self.publish_result(result).await?,
+                None => {
+                    warn!(target: "flows", "Cannot replay failed message #{}: unknown flow {flow}", letter.id)
+                }
+            }
+        }
+        Ok(())
+    }
+
     async fn publish_result(&mut self, result: FlowResult) -> Result<(), RuntimeError> {
         match result {
             FlowResult::Ok {
@@ -426,7 +519,13 @@ impl FlowsMapper {
                 flow,
                 error,
                 output,
-            } => self.publish_error(&flow, error, &output).await,
+                failed,
+            } => {
+                if let (Some(dead_letters), Some(failed)) = (&mut self.dead_letters, failed) {
+                    dead_letters.record(&flow, failed, &error).await;
+                }
+                self.publish_error(&flow, error, &output).await
+            }
         }
     }
 
@@ -472,6 +571,7 @@ impl FlowsMapper {
                     error!(target: "flows", "{flow}: cannot flush {path}: {err}");
                 }
             }
+            FlowOutput::Http(output) => self.http.publish(flow, output, messages),
         }
         Ok(())
     }
@@ -567,7 +667,7 @@ impl FlowsMapper {
     }
 
     async fn on_file_updated(&mut self, path: &Utf8Path) -> Result<(), RuntimeError> {
-        if matches!(path.extension(), Some("js" | "ts" | "mjs")) {
+        if matches!(path.extension(), Some("js" | "ts" | "mjs" | "wasm")) {
             let reloaded_flows = self.processor.reload_script(path).await;
             self.send_updated_subscriptions().await?;
             self.update_all_flow_status(reloaded_flows).await?;
@@ -588,7 +688,7 @@ impl FlowsMapper {
             return Ok(());
         }
 
-        if matches!(path.extension(), Some("js" | "ts" | "mjs")) {
+        if matches!(path.extension(), Some("js" | "ts" | "mjs" | "wasm")) {
             self.processor.remove_script(path).await;
         } else if path.extension() == Some("toml") {
             self.processor.remove_flow(path).await;
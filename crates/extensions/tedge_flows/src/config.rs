@@ -1,13 +1,24 @@
 use crate::flow::Flow;
 use crate::flow::FlowInput;
 use crate::flow::FlowOutput;
+use crate::http_input::WebhookInput;
+use crate::http_output::HttpMethod;
+use crate::http_output::HttpOutput;
+use crate::js_lib::kv_store::ContextPersistence;
 use crate::js_runtime::JsRuntime;
 use crate::js_script::JsScript;
+use crate::modbus::ModbusRegister;
+use crate::modbus::ModbusServer;
+use crate::modbus::SerialParity;
+use crate::modbus::DEFAULT_MODBUS_PORT;
 use crate::params::is_params_file;
 use crate::params::MapperParams;
 use crate::params::Params;
 use crate::steps::FlowStep;
 use crate::transformers::BuiltinTransformers;
+#[cfg(feature = "wasm")]
+#[cfg(feature = "wasm")]
+use crate::wasm_step::WasmStep;
 use crate::LoadError;
 use camino::Utf8Component;
 use camino::Utf8Path;
@@ -16,8 +27,11 @@ use glob::glob;
 use serde::Deserialize;
 use serde_json::Map;
 use serde_json::Value;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
 use std::time::Duration;
 use tedge_mqtt_ext::Topic;
 use tedge_mqtt_ext::TopicFilter;
@@ -49,6 +63,27 @@ pub struct FlowConfig {
     /// If true, output messages that match the input filter are not dropped
     #[serde(default)]
     expect_loop: bool,
+
+    /// The context values to be persisted across mapper restarts
+    #[serde(default)]
+    context: ContextConfig,
+}
+
+#[derive(Deserialize, Default)]
+#[cfg_attr(test, derive(Debug, Eq, PartialEq))]
+pub struct ContextConfig {
+    /// Persist the values of the flow context and of the contexts of its steps
+    #[serde(default)]
+    persist: bool,
+
+    /// Persist the values of the mapper context with a key starting with one of these prefixes
+    #[serde(default)]
+    persist_mapper_keys: Vec<String>,
+
+    /// Delay after which a persisted value expires, unless updated
+    #[serde(default)]
+    #[serde(deserialize_with = "parse_human_interval")]
+    ttl: Option<IntervalConfig>,
 }
 
 #[derive(Deserialize)]
@@ -73,6 +108,9 @@ pub enum StepSpec {
 
     #[serde(rename = "script")]
     JavaScript(Utf8PathBuf),
+
+    #[serde(rename = "wasm")]
+    WebAssembly(Utf8PathBuf),
 }
 
 #[derive(Clone, Deserialize, Default)]
@@ -86,6 +124,15 @@ pub struct InputConfig {
 
     #[serde(default, deserialize_with = "deserialize_one_or_many")]
     process: Vec<ProcessInputConfig>,
+
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    modbus: Vec<ModbusInputConfig>,
+
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    serial: Vec<SerialInputConfig>,
+
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    http: Vec<HttpInputConfig>,
 }
 
 #[derive(Clone, Deserialize)]
@@ -120,6 +167,90 @@ pub struct ProcessInputConfig {
     interval: Option<IntervalConfig>,
 }
 
+#[derive(Clone, Deserialize)]
+#[cfg_attr(test, derive(Debug, Eq, PartialEq))]
+pub struct ModbusInputConfig {
+    /// Host of a Modbus TCP server, unless a serial `device` is given for Modbus RTU
+    host: Option<String>,
+
+    #[serde(default = "default_modbus_port")]
+    port: u16,
+
+    /// Serial port of a Modbus RTU server
+    device: Option<Utf8PathBuf>,
+
+    #[serde(default = "default_baud_rate")]
+    baud_rate: u32,
+
+    #[serde(default)]
+    parity: SerialParity,
+
+    #[serde(default = "default_modbus_unit")]
+    unit: u8,
+
+    /// Default to the server address
+    topic: Option<String>,
+
+    #[serde(default)]
+    #[serde(deserialize_with = "parse_human_interval")]
+    interval: Option<IntervalConfig>,
+
+    registers: Vec<ModbusRegister>,
+}
+
+#[derive(Clone, Deserialize)]
+#[cfg_attr(test, derive(Debug, Eq, PartialEq))]
+pub struct SerialInputConfig {
+    device: Utf8PathBuf,
+
+    #[serde(default = "default_baud_rate")]
+    baud_rate: u32,
+
+    #[serde(default)]
+    parity: SerialParity,
+
+    /// Default to device
+    topic: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[cfg_attr(test, derive(Debug, Eq, PartialEq))]
+pub struct HttpInputConfig {
+    /// Local address on which the webhook is listening, e.g. `127.0.0.1:8080`
+    ///
+    /// Given only a port, the webhook listens on the loopback interface.
+    address: String,
+
+    #[serde(default = "default_http_path")]
+    path: String,
+
+    /// Default to path
+    topic: Option<String>,
+
+    /// Certificate and private key, if the webhook is served over HTTPS
+    cert_path: Option<Utf8PathBuf>,
+    key_path: Option<Utf8PathBuf>,
+
+    /// Bearer token the requests must be authorized with, if any
+    token: Option<String>,
+}
+
+fn default_modbus_port() -> u16 {
+    DEFAULT_MODBUS_PORT
+}
+
+fn default_modbus_unit() -> u8 {
+    1
+}
+
+fn default_baud_rate() -> u32 {
+    9600
+}
+
+fn default_http_path() -> String {
+    "/".to_string()
+}
+
 #[derive(Deserialize)]
 #[cfg_attr(test, derive(Debug, Eq, PartialEq))]
 pub enum OutputConfig {
@@ -128,6 +259,45 @@ pub enum OutputConfig {
 
     #[serde(rename = "file")]
     File { path: Utf8PathBuf },
+
+    #[serde(rename = "http")]
+    Http(HttpOutputConfig),
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Debug, Eq, PartialEq))]
+pub struct HttpOutputConfig {
+    url: String,
+
+    #[serde(default)]
+    method: HttpMethod,
+
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+
+    #[serde(default = "default_http_retries")]
+    retries: u32,
+
+    /// Default to 1 second
+    #[serde(default)]
+    #[serde(deserialize_with = "parse_human_interval")]
+    backoff: Option<IntervalConfig>,
+
+    #[serde(default = "default_http_batch_size")]
+    batch_size: usize,
+
+    /// Default to 1 second
+    #[serde(default)]
+    #[serde(deserialize_with = "parse_human_interval")]
+    batch_timeout: Option<IntervalConfig>,
+}
+
+fn default_http_retries() -> u32 {
+    3
+}
+
+fn default_http_batch_size() -> usize {
+    1
 }
 
 #[derive(Clone)]
@@ -157,6 +327,12 @@ pub enum ConfigError {
     #[error("Not a valid interval duration: {0}")]
     IncorrectInterval(String),
 
+    #[error("Not a valid input configuration: {0}")]
+    IncorrectInput(String),
+
+    #[error("Not a valid output configuration: {0}")]
+    IncorrectOutput(String),
+
     #[error("Flow '{name}' defines an infinite loop: the output topic '{output_topic}' matches input filter '{input_filter}'")]
     MqttInfiniteLoop {
         name: String,
@@ -287,6 +463,13 @@ impl FlowConfig {
             step.substitute_params(params)?;
         }
 
+        for prefix in self.context.persist_mapper_keys.iter_mut() {
+            *prefix = params.substitute_inner_paths(prefix);
+        }
+        if let Some(ttl) = self.context.ttl.take() {
+            self.context.ttl = Some(ttl.substitute_params(params)?);
+        }
+
         Ok(FlowConfig {
             input: self.input.substitute_params(params)?,
             output: self.output.substitute_params(params)?,
@@ -297,8 +480,12 @@ impl FlowConfig {
 
     pub fn from_step(script: Utf8PathBuf) -> Self {
         let input_topic = "#".to_string();
+        let step = match script.extension() {
+            Some("wasm") => StepSpec::WebAssembly(script),
+            _ => StepSpec::JavaScript(script),
+        };
         let step = StepConfig {
-            step: StepSpec::JavaScript(script),
+            step,
             config: Map::new(),
             interval: None,
         };
@@ -318,6 +505,7 @@ impl FlowConfig {
             steps: vec![step],
             output: default_output(),
             errors: default_errors(),
+            context: ContextConfig::default(),
         }
     }
 
@@ -332,6 +520,7 @@ impl FlowConfig {
         let input = self.input.into_flow_inputs(source_dir)?;
         let output = self.output.try_into()?;
         let errors = self.errors.try_into()?;
+        let context = self.context.try_into()?;
         let mut steps = vec![];
         for (i, step) in self.steps.into_iter().enumerate() {
             let step = step
@@ -366,6 +555,7 @@ impl FlowConfig {
             errors,
             source,
             expect_loop: self.expect_loop,
+            context,
         })
     }
 }
@@ -423,6 +613,7 @@ impl StepConfig {
             StepSpec::Transformer(name) => {
                 Self::instantiate_builtin(rs_transformers, flow, name, index)?
             }
+            StepSpec::WebAssembly(path) => Self::compile_wasm(js_runtime, flow, path, index)?,
         };
         let config = if self.config.is_empty() {
             None
@@ -441,6 +632,44 @@ impl StepConfig {
         path: &Utf8Path,
         index: usize,
     ) -> Result<FlowStep, ConfigError> {
+        let path = Self::step_path(flow, path);
+        let module_name = FlowStep::instance_name(flow, &path, index);
+        let mut script = JsScript::new(module_name, flow.to_owned(), path);
+        js_runtime.load_script(&mut script).await?;
+        Ok(FlowStep::new_script(script))
+    }
+
+    #[cfg(feature = "wasm")]
+    fn compile_wasm(
+        js_runtime: &JsRuntime,
+        flow: &Utf8Path,
+        path: &Utf8Path,
+        index: usize,
+    ) -> Result<FlowStep, ConfigError> {
+        let path = Self::step_path(flow, path);
+        let module_name = FlowStep::instance_name(flow, &path, index);
+        let step = WasmStep::load(
+            module_name,
+            flow.to_owned(),
+            path,
+            js_runtime.context_handle(),
+            js_runtime.wasm_config(),
+        )?;
+        Ok(FlowStep::new_wasm(step))
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    fn compile_wasm(
+        _js_runtime: &JsRuntime,
+        _flow: &Utf8Path,
+        _path: &Utf8Path,
+        _index: usize,
+    ) -> Result<FlowStep, ConfigError> {
+        Err(LoadError::WasmNotSupported.into())
+    }
+
+    /// Resolve the path to a step source file, relative to the flow definition if not absolute
+    fn step_path(flow: &Utf8Path, path: &Utf8Path) -> Utf8PathBuf {
         let path = if path.is_absolute() {
             path.to_owned()
         } else {
@@ -449,13 +678,8 @@ impl StepConfig {
                 .map(|parent| parent.join(path))
                 .unwrap_or_else(|| path.to_owned())
         };
-        let path = path
-            .canonicalize_utf8()
-            .unwrap_or_else(|_| path.to_path_buf());
-        let module_name = FlowStep::instance_name(flow, &path, index);
-        let mut script = JsScript::new(module_name, flow.to_owned(), path);
-        js_runtime.load_script(&mut script).await?;
-        Ok(FlowStep::new_script(script))
+        path.canonicalize_utf8()
+            .unwrap_or_else(|_| path.to_path_buf())
     }
 
     fn instantiate_builtin(
@@ -512,6 +736,49 @@ impl InputConfig {
                     })
                 })
                 .collect::<Result<_, ConfigError>>()?,
+            modbus: self
+                .modbus
+                .into_iter()
+                .map(|input| {
+                    Ok(ModbusInputConfig {
+                        host: input.host.map(|h| params.substitute_inner_paths(&h)),
+                        device: input
+                            .device
+                            .map(|d| params.substitute_inner_paths(d.as_str()).into()),
+                        topic: input.topic.map(|t| params.substitute_inner_paths(&t)),
+                        interval: input
+                            .interval
+                            .map(|i| i.substitute_params(params))
+                            .transpose()?,
+                        ..input
+                    })
+                })
+                .collect::<Result<_, ConfigError>>()?,
+            serial: self
+                .serial
+                .into_iter()
+                .map(|input| SerialInputConfig {
+                    device: params.substitute_inner_paths(input.device.as_str()).into(),
+                    topic: input.topic.map(|t| params.substitute_inner_paths(&t)),
+                    ..input
+                })
+                .collect(),
+            http: self
+                .http
+                .into_iter()
+                .map(|input| HttpInputConfig {
+                    address: params.substitute_inner_paths(&input.address),
+                    path: params.substitute_inner_paths(&input.path),
+                    topic: input.topic.map(|t| params.substitute_inner_paths(&t)),
+                    cert_path: input
+                        .cert_path
+                        .map(|p| params.substitute_inner_paths(p.as_str()).into()),
+                    key_path: input
+                        .key_path
+                        .map(|p| params.substitute_inner_paths(p.as_str()).into()),
+                    token: input.token.map(|t| params.substitute_inner_paths(&t)),
+                })
+                .collect(),
         })
     }
 }
@@ -607,12 +874,118 @@ impl InputConfig {
             inputs.push(input);
         }
 
+        for ModbusInputConfig {
+            host,
+            port,
+            device,
+            baud_rate,
+            parity,
+            unit,
+            topic,
+            interval,
+            registers,
+        } in self.modbus
+        {
+            let server = match (host, device) {
+                (Some(host), None) => ModbusServer::Tcp { host, port },
+                (None, Some(device)) => ModbusServer::Rtu {
+                    device,
+                    baud_rate,
+                    parity,
+                },
+                _ => {
+                    return Err(ConfigError::IncorrectInput(
+                        "A Modbus input must be given either a TCP host or a serial device"
+                            .to_string(),
+                    ))
+                }
+            };
+            let interval = match interval.map(|i| i.duration()) {
+                Some(Ok(interval)) if !interval.is_zero() => interval,
+                Some(Err(e)) => return Err(e),
+                _ => {
+                    return Err(ConfigError::IncorrectInput(format!(
+                        "The Modbus input from {server} must be polled at a non-zero interval"
+                    )))
+                }
+            };
+            if registers.is_empty() {
+                return Err(ConfigError::IncorrectInput(format!(
+                    "The Modbus input from {server} has no registers to read"
+                )));
+            }
+            inputs.push(FlowInput::PollModbus {
+                topic: topic.unwrap_or_else(|| server.to_string()),
+                server,
+                unit,
+                registers,
+                interval,
+            });
+        }
+
+        for SerialInputConfig {
+            device,
+            baud_rate,
+            parity,
+            topic,
+        } in self.serial
+        {
+            let topic = topic.unwrap_or_else(|| device.clone().to_string());
+            inputs.push(FlowInput::StreamSerial {
+                topic,
+                device,
+                baud_rate,
+                parity,
+            });
+        }
+
+        for HttpInputConfig {
+            address,
+            path,
+            topic,
+            cert_path,
+            key_path,
+            token,
+        } in self.http
+        {
+            let address: SocketAddr = match address.parse::<u16>() {
+                Ok(port) => SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+                Err(_) => address.parse().map_err(|_| {
+                    ConfigError::IncorrectInput(format!(
+                        "The HTTP input address must be a port or an IP address and port, not: {address}"
+                    ))
+                })?,
+            };
+            if !path.starts_with('/') {
+                return Err(ConfigError::IncorrectInput(format!(
+                    "The HTTP input path must start with a '/', not: {path}"
+                )));
+            }
+            let tls = match (cert_path, key_path) {
+                (Some(cert_path), Some(key_path)) => Some((cert_path, key_path)),
+                (None, None) => None,
+                _ => {
+                    return Err(ConfigError::IncorrectInput(
+                        "An HTTPS input must be given both a certificate and a private key"
+                            .to_string(),
+                    ))
+                }
+            };
+            inputs.push(FlowInput::Http(WebhookInput {
+                topic: topic.unwrap_or_else(|| path.clone()),
+                address,
+                path,
+                tls,
+                token,
+            }));
+        }
+
         Ok(inputs)
     }
 }
 
 impl OutputConfig {
-    fn substitute_params(self, params: &Params<&dyn MapperParams>) -> Result<Self, LoadError> {
+    fn substitute_params(self, params: &Params<&dyn MapperParams>) -> Result<Self, ConfigError> {
         match self {
             OutputConfig::Mqtt { topic } => Ok(OutputConfig::Mqtt {
                 topic: topic.map(|t| params.substitute_inner_paths(&t)),
@@ -620,6 +993,23 @@ impl OutputConfig {
             OutputConfig::File { path } => Ok(OutputConfig::File {
                 path: params.substitute_inner_paths(path.as_str()).into(),
             }),
+            OutputConfig::Http(output) => Ok(OutputConfig::Http(HttpOutputConfig {
+                url: params.substitute_inner_paths(&output.url),
+                headers: output
+                    .headers
+                    .into_iter()
+                    .map(|(name, value)| (name, params.substitute_inner_paths(&value)))
+                    .collect(),
+                backoff: output
+                    .backoff
+                    .map(|i| i.substitute_params(params))
+                    .transpose()?,
+                batch_timeout: output
+                    .batch_timeout
+                    .map(|i| i.substitute_params(params))
+                    .transpose()?,
+                ..output
+            })),
         }
     }
 }
@@ -633,6 +1023,49 @@ impl TryFrom<OutputConfig> for FlowOutput {
                 topic: topic.map(into_topic).transpose()?,
             },
             OutputConfig::File { path } => FlowOutput::File { path },
+            OutputConfig::Http(output) => FlowOutput::Http(output.try_into()?),
+        })
+    }
+}
+
+impl TryFrom<HttpOutputConfig> for HttpOutput {
+    type Error = ConfigError;
+
+    fn try_from(output: HttpOutputConfig) -> Result<Self, Self::Error> {
+        if !output.url.starts_with("http://") && !output.url.starts_with("https://") {
+            return Err(ConfigError::IncorrectOutput(format!(
+                "The HTTP output URL must start with http:// or https://, not: {}",
+                output.url
+            )));
+        }
+        if output.batch_size == 0 {
+            return Err(ConfigError::IncorrectOutput(
+                "The HTTP output batch size must be at least 1".to_string(),
+            ));
+        }
+        let one_second = Duration::from_secs(1);
+        Ok(HttpOutput {
+            url: output.url,
+            method: output.method,
+            headers: output.headers.into_iter().collect(),
+            retries: output.retries,
+            backoff: output.backoff.map_or(Ok(one_second), |i| i.duration())?,
+            batch_size: output.batch_size,
+            batch_timeout: output
+                .batch_timeout
+                .map_or(Ok(one_second), |i| i.duration())?,
+        })
+    }
+}
+
+impl TryFrom<ContextConfig> for ContextPersistence {
+    type Error = ConfigError;
+
+    fn try_from(config: ContextConfig) -> Result<Self, Self::Error> {
+        Ok(ContextPersistence {
+            flow: config.persist,
+            mapper_keys: config.persist_mapper_keys,
+            ttl: config.ttl.map(|ttl| ttl.duration()).transpose()?,
         })
     }
 }
@@ -741,6 +1174,9 @@ fn detect_loop(
 mod tests {
     use super::*;
     use crate::empty_mapper_params;
+    use crate::modbus::DataType;
+    use crate::modbus::Endianness;
+    use crate::modbus::RegisterTable;
     use camino::Utf8PathBuf;
     use serde_json::json;
     use std::time::Duration;
@@ -816,6 +1252,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn steps_are_declared_as_builtin_script_or_wasm() {
+        let flow_toml = r#"
+input.mqtt.topics = ["te/+/+/+/+/m/+"]
+steps = [
+    { builtin = "add-timestamp" },
+    { script = "te_to_c8y.js" },
+    { wasm = "filter.wasm", config = { threshold = 42 } },
+]
+"#;
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let steps: Vec<_> = flow.steps.into_iter().map(|step| step.step).collect();
+        assert_eq!(
+            steps,
+            vec![
+                StepSpec::Transformer("add-timestamp".to_string()),
+                StepSpec::JavaScript("te_to_c8y.js".into()),
+                StepSpec::WebAssembly("filter.wasm".into()),
+            ]
+        );
+    }
+
     #[test]
     fn detect_loop_when_output_topic_matches_input_filter() {
         let input = FlowInput::Mqtt {
@@ -1398,4 +1856,273 @@ topic = "te/device/main///e/"
             }]
         )
     }
+
+    #[test]
+    fn modbus_and_serial_inputs_can_be_deserialized() {
+        let flow_toml = r#"
+        [input.modbus]
+        host = "192.168.1.10"
+        interval = "10s"
+        topic = "plc"
+
+        [[input.modbus.registers]]
+        name = "temperature"
+        address = 0
+        type = "int16"
+        scale = 0.1
+
+        [[input.modbus.registers]]
+        name = "energy"
+        address = 10
+        table = "input"
+        type = "uint32"
+        word_order = "little"
+
+        [input.serial]
+        device = "/dev/ttyUSB0"
+        baud_rate = 115200
+        parity = "even"
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let input = flow
+            .input
+            .into_flow_inputs(Utf8Path::new("/flows"))
+            .unwrap();
+        assert_eq!(
+            input,
+            vec![
+                FlowInput::PollModbus {
+                    topic: "plc".into(),
+                    server: ModbusServer::Tcp {
+                        host: "192.168.1.10".into(),
+                        port: 502,
+                    },
+                    unit: 1,
+                    registers: vec![
+                        ModbusRegister {
+                            name: "temperature".into(),
+                            address: 0,
+                            table: RegisterTable::Holding,
+                            data_type: DataType::Int16,
+                            byte_order: Endianness::Big,
+                            word_order: Endianness::Big,
+                            scale: serde_json::Number::from_f64(0.1),
+                        },
+                        ModbusRegister {
+                            name: "energy".into(),
+                            address: 10,
+                            table: RegisterTable::Input,
+                            data_type: DataType::Uint32,
+                            byte_order: Endianness::Big,
+                            word_order: Endianness::Little,
+                            scale: None,
+                        },
+                    ],
+                    interval: Duration::from_secs(10),
+                },
+                FlowInput::StreamSerial {
+                    topic: "/dev/ttyUSB0".into(),
+                    device: Utf8PathBuf::from("/dev/ttyUSB0"),
+                    baud_rate: 115200,
+                    parity: SerialParity::Even,
+                },
+            ]
+        )
+    }
+
+    #[test]
+    fn modbus_rtu_input_is_read_from_a_serial_device() {
+        let flow_toml = r#"
+        [input.modbus]
+        device = "/dev/ttyS1"
+        unit = 7
+        interval = "1m"
+        registers = [{ name = "level", address = 3 }]
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let input = flow
+            .input
+            .into_flow_inputs(Utf8Path::new("/flows"))
+            .unwrap();
+        assert!(matches!(
+            &input[..],
+            [FlowInput::PollModbus {
+                topic,
+                server: ModbusServer::Rtu {
+                    baud_rate: 9600,
+                    parity: SerialParity::None,
+                    ..
+                },
+                unit: 7,
+                ..
+            }] if topic == "/dev/ttyS1"
+        ));
+    }
+
+    #[test_case(r#"interval = "10s""#; "without host nor device")]
+    #[test_case(r#"host = "plc"
+        device = "/dev/ttyS1"
+        interval = "10s""#; "with both host and device")]
+    #[test_case(r#"host = "plc""#; "without interval")]
+    fn invalid_modbus_inputs_are_rejected(settings: &str) {
+        let flow_toml = format!(
+            r#"
+        [input.modbus]
+        {settings}
+        registers = [{{ name = "level", address = 3 }}]
+        "#
+        );
+
+        let flow: FlowConfig = toml::from_str(&flow_toml).unwrap();
+        let result = flow.input.into_flow_inputs(Utf8Path::new("/flows"));
+        assert!(matches!(result, Err(ConfigError::IncorrectInput(_))));
+    }
+
+    #[test]
+    fn http_input_can_be_deserialized() {
+        let flow_toml = r#"
+        [[input.http]]
+        address = "127.0.0.1:8080"
+        path = "/alarms"
+
+        [[input.http]]
+        address = "0.0.0.0:8443"
+        topic = "webhook"
+        cert_path = "/etc/tedge/device-certs/webhook.crt"
+        key_path = "/etc/tedge/device-certs/webhook.key"
+        token = "secret"
+
+        [[input.http]]
+        address = "8081"
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let input = flow
+            .input
+            .into_flow_inputs(Utf8Path::new("/flows"))
+            .unwrap();
+        assert_eq!(
+            input,
+            vec![
+                FlowInput::Http(WebhookInput {
+                    topic: "/alarms".into(),
+                    address: "127.0.0.1:8080".parse().unwrap(),
+                    path: "/alarms".into(),
+                    tls: None,
+                    token: None,
+                }),
+                FlowInput::Http(WebhookInput {
+                    topic: "webhook".into(),
+                    address: "0.0.0.0:8443".parse().unwrap(),
+                    path: "/".into(),
+                    tls: Some((
+                        "/etc/tedge/device-certs/webhook.crt".into(),
+                        "/etc/tedge/device-certs/webhook.key".into()
+                    )),
+                    token: Some("secret".into()),
+                }),
+                FlowInput::Http(WebhookInput {
+                    topic: "/".into(),
+                    address: "127.0.0.1:8081".parse().unwrap(),
+                    path: "/".into(),
+                    tls: None,
+                    token: None,
+                }),
+            ]
+        )
+    }
+
+    #[test_case(r#"address = "localhost""#; "without port")]
+    #[test_case(r#"address = "127.0.0.1:8080"
+        path = "alarms""#; "with a relative path")]
+    #[test_case(r#"address = "127.0.0.1:8443"
+        cert_path = "/etc/tedge/device-certs/webhook.crt""#; "without private key")]
+    fn invalid_http_inputs_are_rejected(settings: &str) {
+        let flow_toml = format!(
+            r#"
+        [input.http]
+        {settings}
+        "#
+        );
+
+        let flow: FlowConfig = toml::from_str(&flow_toml).unwrap();
+        let result = flow.input.into_flow_inputs(Utf8Path::new("/flows"));
+        assert!(matches!(result, Err(ConfigError::IncorrectInput(_))));
+    }
+
+    #[test]
+    fn http_output_can_be_deserialized() {
+        let flow_toml = r#"
+        input.mqtt.topics = ["te/+/+/+/+/m/+"]
+
+        [output.http]
+        url = "https://example.com/api/measurements"
+        method = "PUT"
+        headers = { Authorization = "Bearer ${config.token}" }
+        batch_size = 50
+        batch_timeout = "5s"
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let output: FlowOutput = flow.output.try_into().unwrap();
+        assert_eq!(
+            output,
+            FlowOutput::Http(HttpOutput {
+                url: "https://example.com/api/measurements".into(),
+                method: HttpMethod::Put,
+                headers: vec![("Authorization".into(), "Bearer ${config.token}".into())],
+                retries: 3,
+                backoff: Duration::from_secs(1),
+                batch_size: 50,
+                batch_timeout: Duration::from_secs(5),
+            })
+        )
+    }
+
+    #[test_case(r#"url = "example.com/api""#; "without scheme")]
+    #[test_case(r#"url = "http://example.com/api"
+        batch_size = 0"#; "with empty batches")]
+    fn invalid_http_outputs_are_rejected(settings: &str) {
+        let flow_toml = format!(
+            r#"
+        input.mqtt.topics = ["te/+/+/+/+/m/+"]
+
+        [output.http]
+        {settings}
+        "#
+        );
+
+        let flow: FlowConfig = toml::from_str(&flow_toml).unwrap();
+        let result = FlowOutput::try_from(flow.output);
+        assert!(matches!(result, Err(ConfigError::IncorrectOutput(_))));
+    }
+
+    #[test]
+    fn context_persistence_can_be_configured() {
+        let flow_toml = r#"
+        input.mqtt.topics = ["te/+/+/+/+/m/+"]
+
+        [context]
+        persist = true
+        persist_mapper_keys = ["counters/", "last/"]
+        ttl = "1d"
+        "#;
+
+        let flow: FlowConfig = toml::from_str(flow_toml).unwrap();
+        let context: ContextPersistence = flow.context.try_into().unwrap();
+        assert_eq!(
+            context,
+            ContextPersistence {
+                flow: true,
+                mapper_keys: vec!["counters/".to_string(), "last/".to_string()],
+                ttl: Some(Duration::from_secs(86400)),
+            }
+        );
+
+        let flow: FlowConfig = toml::from_str(r#"input.mqtt.topics = ["te/+/+/+/+/m/+"]"#).unwrap();
+        let context: ContextPersistence = flow.context.try_into().unwrap();
+        assert!(!context.is_enabled());
+    }
 }
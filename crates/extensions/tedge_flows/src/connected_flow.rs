@@ -8,7 +8,9 @@ use crate::input_source::CommandPollingSource;
 use crate::input_source::CommandStreamingSource;
 use crate::input_source::FilePollingSource;
 use crate::input_source::FileStreamingSource;
+use crate::input_source::ModbusPollingSource;
 use crate::input_source::PollingSource;
+use crate::input_source::SerialStreamingSource;
 use crate::input_source::StreamingSource;
 use crate::params::MapperParams;
 use crate::registry::FlowRegistry;
@@ -237,6 +239,7 @@ pub(crate) fn watch_request_topic(request: &WatchRequest) -> &str {
     match request {
         WatchRequest::WatchFile { topic, .. }
         | WatchRequest::WatchCommand { topic, .. }
+        | WatchRequest::WatchSerial { topic, .. }
         | WatchRequest::UnWatch { topic } => topic,
     }
 }
@@ -255,6 +258,15 @@ fn streaming_source(flow_name: String, input: FlowInput) -> Option<Box<dyn Strea
             flow_name, topic, command, cwd,
         ))),
 
+        FlowInput::StreamSerial {
+            topic,
+            device,
+            baud_rate,
+            parity,
+        } => Some(Box::new(SerialStreamingSource::new(
+            flow_name, topic, device, baud_rate, parity,
+        ))),
+
         _ => None,
     }
 }
@@ -276,6 +288,16 @@ fn polling_source(input: FlowInput) -> Option<Box<dyn PollingSource>> {
             topic, command, cwd, interval,
         ))),
 
+        FlowInput::PollModbus {
+            topic,
+            server,
+            unit,
+            registers,
+            interval,
+        } => Some(Box::new(ModbusPollingSource::new(
+            topic, server, unit, registers, interval,
+        ))),
+
         _ => None,
     }
 }
@@ -284,6 +306,7 @@ fn polling_source(input: FlowInput) -> Option<Box<dyn PollingSource>> {
 mod tests {
     use super::*;
     use crate::flow::FlowOutput;
+    use crate::js_lib::kv_store::ContextPersistence;
     use camino::Utf8PathBuf;
 
     #[test]
@@ -310,6 +333,7 @@ mod tests {
             errors: FlowOutput::Mqtt { topic: None },
             source: Utf8PathBuf::from("/flows/test.toml"),
             expect_loop: false,
+            context: ContextPersistence::default(),
         };
 
         let connected = ConnectedFlow::new(flow);
@@ -358,6 +382,7 @@ mod tests {
             errors: FlowOutput::Mqtt { topic: None },
             source: Utf8PathBuf::from("/flows/test.toml"),
             expect_loop: false,
+            context: ContextPersistence::default(),
         };
 
         let connected = ConnectedFlow::new(flow);
@@ -392,6 +417,7 @@ mod tests {
             errors: FlowOutput::Mqtt { topic: None },
             source: Utf8PathBuf::from("/flows/test.toml"),
             expect_loop: false,
+            context: ContextPersistence::default(),
         };
 
         let connected = ConnectedFlow::new(flow);
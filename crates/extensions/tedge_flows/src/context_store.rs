@@ -0,0 +1,372 @@
+use crate::js_lib::kv_store::FlowContextHandle;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use serde::Deserialize;
+use serde::Serialize;
+use std::time::Duration;
+use std::time::SystemTime;
+use tedge_utils::fs::atomically_write_file_async;
+use tedge_utils::fs::AtomFileError;
+use tokio::time::Instant;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// Where and how often the persisted values of the flow contexts are saved
+#[derive(Clone, Debug)]
+pub struct ContextStoreConfig {
+    /// The snapshot file
+    pub path: Utf8PathBuf,
+
+    /// The delay between two snapshots
+    pub snapshot_interval: Duration,
+
+    /// The maximum number of values persisted, the least recently updated values being dropped first
+    pub max_entries: usize,
+}
+
+/// A value persisted by a flow context
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ContextEntry {
+    pub context: ContextScope,
+    pub key: String,
+    pub value: serde_json::Value,
+
+    /// Unix timestamp of the last update of the value
+    pub updated_at: u64,
+
+    /// Unix timestamp after which the value is discarded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// The context a persisted value belongs to
+#[derive(Clone, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextScope {
+    /// The context shared by all the flows of the mapper
+    Mapper,
+
+    /// The context of a flow, given by the path of its definition
+    Flow(String),
+
+    /// The context of a flow step
+    Script(String),
+}
+
+impl ContextEntry {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= unix_secs(now))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ContextStoreError {
+    #[error("Cannot read {path}: {error}")]
+    ReadError {
+        path: Utf8PathBuf,
+        error: std::io::Error,
+    },
+
+    #[error("Cannot parse {path}: {error}")]
+    ParseError {
+        path: Utf8PathBuf,
+        error: serde_json::Error,
+    },
+
+    #[error(transparent)]
+    WriteError(#[from] AtomFileError),
+}
+
+/// Read the values persisted in a snapshot file, if any
+pub async fn read_context_snapshot(
+    path: &Utf8Path,
+) -> Result<Vec<ContextEntry>, ContextStoreError> {
+    let content = match tokio::fs::read(path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => {
+            return Err(ContextStoreError::ReadError {
+                path: path.to_owned(),
+                error,
+            })
+        }
+    };
+    serde_json::from_slice(&content).map_err(|error| ContextStoreError::ParseError {
+        path: path.to_owned(),
+        error,
+    })
+}
+
+/// Replace the content of a snapshot file
+pub async fn write_context_snapshot(
+    path: &Utf8Path,
+    entries: &[ContextEntry],
+) -> Result<(), ContextStoreError> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|error| ContextStoreError::ReadError {
+                path: dir.to_owned(),
+                error,
+            })?;
+    }
+    let content =
+        serde_json::to_vec_pretty(entries).map_err(|error| ContextStoreError::ParseError {
+            path: path.to_owned(),
+            error,
+        })?;
+    atomically_write_file_async(path, &content).await?;
+    Ok(())
+}
+
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Saves periodically the persisted values of the flow contexts
+pub(crate) struct ContextStore {
+    config: ContextStoreConfig,
+    next_snapshot: Instant,
+
+    /// The modification time of the snapshot file, when last read or written by the mapper
+    last_modified: Option<SystemTime>,
+}
+
+impl ContextStore {
+    pub fn new(config: ContextStoreConfig) -> Self {
+        let next_snapshot = Instant::now() + config.snapshot_interval;
+        ContextStore {
+            config,
+            next_snapshot,
+            last_modified: None,
+        }
+    }
+
+    pub fn next_deadline(&self) -> Instant {
+        self.next_snapshot
+    }
+
+    /// Restore the values persisted before the mapper has been restarted
+    pub async fn restore(&mut self, context: &FlowContextHandle) {
+        let path = &self.config.path;
+        match read_context_snapshot(path).await {
+            Ok(entries) => {
+                info!(target: "flows", "Restoring {} context values from {path}", entries.len());
+                context.restore(entries, SystemTime::now());
+            }
+            Err(err) => error!(target: "flows", "Cannot restore context values: {err}"),
+        }
+        self.last_modified = self.modified().await;
+    }
+
+    /// Save the persisted values, if a snapshot is due
+    pub async fn on_interval(&mut self, context: &FlowContextHandle, now: Instant) {
+        if self.next_snapshot <= now {
+            self.next_snapshot = now + self.config.snapshot_interval;
+            self.snapshot(context).await;
+        }
+    }
+
+    pub async fn snapshot(&mut self, context: &FlowContextHandle) {
+        let path = &self.config.path;
+        let now = SystemTime::now();
+
+        // The snapshot might have been updated by `tedge flows context clear` since last saved
+        if self.modified().await != self.last_modified {
+            match read_context_snapshot(path).await {
+                Ok(entries) => {
+                    info!(target: "flows", "Reloading context values updated in {path}");
+                    context.replace_persisted(entries, now)
+                }
+                Err(err) => error!(target: "flows", "Cannot reload context values: {err}"),
+            }
+        }
+
+        context.purge_expired(now);
+        let mut entries = context.snapshot(now);
+        let max_entries = self.config.max_entries;
+        if entries.len() > max_entries {
+            warn!(
+                target: "flows",
+                "Too many context values to persist: only the {max_entries} most recently updated are saved out of {}",
+                entries.len()
+            );
+            entries.sort_by_key(|entry| std::cmp::Reverse(entry.updated_at));
+            entries.truncate(max_entries);
+        }
+
+        if let Err(err) = write_context_snapshot(path, &entries).await {
+            error!(target: "flows", "Cannot save context values: {err}");
+        }
+        self.last_modified = self.modified().await;
+    }
+
+    async fn modified(&self) -> Option<SystemTime> {
+        let metadata = tokio::fs::metadata(&self.config.path).await.ok()?;
+        metadata.modified().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::js_lib::kv_store::ContextPersistence;
+    use crate::js_lib::kv_store::FlowContext;
+    use crate::JsonValue;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    const FLOW: &str = "/flows/counter.toml";
+
+    #[tokio::test]
+    async fn persisted_values_are_restored_after_a_restart() {
+        let dir = TempDir::new().unwrap();
+        let context = context_with_persistence(ContextPersistence {
+            flow: true,
+            mapper_keys: vec!["counters/".to_string()],
+            ttl: None,
+        });
+        context.insert(&FlowContext::flow(FLOW), "count", json!(42));
+        context.insert(
+            &FlowContext::script(&format!("{FLOW}|0|count.js")),
+            "last",
+            json!("x"),
+        );
+        context.insert(&FlowContext::Mapper, "counters/total", json!(7));
+        context.insert(&FlowContext::Mapper, "volatile", json!(1));
+        context.insert(&FlowContext::flow("/flows/other.toml"), "count", json!(1));
+
+        let mut store = ContextStore::new(config(&dir));
+        store.snapshot(&context).await;
+
+        let restarted = FlowContextHandle::default();
+        let mut store = ContextStore::new(config(&dir));
+        store.restore(&restarted).await;
+        assert_eq!(
+            restarted.get(&FlowContext::flow(FLOW), "count"),
+            JsonValue::from(json!(42))
+        );
+        assert_eq!(
+            restarted.get(&FlowContext::script(&format!("{FLOW}|0|count.js")), "last"),
+            JsonValue::from(json!("x"))
+        );
+        assert_eq!(
+            restarted.get(&FlowContext::Mapper, "counters/total"),
+            JsonValue::from(json!(7))
+        );
+        assert_eq!(
+            restarted.get(&FlowContext::Mapper, "volatile"),
+            JsonValue::Null
+        );
+        assert_eq!(
+            restarted.get(&FlowContext::flow("/flows/other.toml"), "count"),
+            JsonValue::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_values_are_neither_returned_nor_restored() {
+        let context = context_with_persistence(ContextPersistence {
+            flow: true,
+            mapper_keys: vec![],
+            ttl: Some(Duration::from_secs(60)),
+        });
+        context.insert(&FlowContext::flow(FLOW), "count", json!(42));
+        let mut entries = context.snapshot(SystemTime::now());
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].expires_at.is_some());
+
+        let later = SystemTime::now() + Duration::from_secs(61);
+        assert!(context.snapshot(later).is_empty());
+
+        entries[0].expires_at = Some(unix_secs(SystemTime::now()) - 1);
+        let restarted = FlowContextHandle::default();
+        restarted.restore(entries, SystemTime::now());
+        assert_eq!(
+            restarted.get(&FlowContext::flow(FLOW), "count"),
+            JsonValue::Null
+        );
+    }
+
+    #[tokio::test]
+    async fn only_the_most_recently_updated_values_are_persisted_beyond_the_size_cap() {
+        let dir = TempDir::new().unwrap();
+        let context = context_with_persistence(ContextPersistence {
+            flow: true,
+            mapper_keys: vec![],
+            ttl: None,
+        });
+        let now = unix_secs(SystemTime::now());
+        let entries = (0..5)
+            .map(|i| ContextEntry {
+                context: ContextScope::Flow(FLOW.to_string()),
+                key: format!("key-{i}"),
+                value: json!(i),
+                updated_at: now - 10 + i,
+                expires_at: None,
+            })
+            .collect();
+        context.restore(entries, SystemTime::now());
+
+        let mut store = ContextStore::new(ContextStoreConfig {
+            max_entries: 2,
+            ..config(&dir)
+        });
+        store.snapshot(&context).await;
+
+        let saved = read_context_snapshot(&store.config.path).await.unwrap();
+        let keys: Vec<_> = saved.iter().map(|entry| entry.key.as_str()).collect();
+        assert_eq!(keys, vec!["key-4", "key-3"]);
+    }
+
+    #[tokio::test]
+    async fn values_cleared_from_the_snapshot_are_removed_from_the_running_mapper() {
+        let dir = TempDir::new().unwrap();
+        let context = context_with_persistence(ContextPersistence {
+            flow: true,
+            mapper_keys: vec![],
+            ttl: None,
+        });
+        context.insert(&FlowContext::flow(FLOW), "count", json!(42));
+        context.insert(&FlowContext::flow(FLOW), "total", json!(1000));
+        let mut store = ContextStore::new(config(&dir));
+        store.snapshot(&context).await;
+
+        // As done by `tedge flows context clear --key count`
+        let path = store.config.path.clone();
+        let mut entries = read_context_snapshot(&path).await.unwrap();
+        entries.retain(|entry| entry.key != "count");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        write_context_snapshot(&path, &entries).await.unwrap();
+
+        store.snapshot(&context).await;
+        assert_eq!(
+            context.get(&FlowContext::flow(FLOW), "count"),
+            JsonValue::Null
+        );
+        assert_eq!(
+            context.get(&FlowContext::flow(FLOW), "total"),
+            JsonValue::from(json!(1000))
+        );
+    }
+
+    fn context_with_persistence(persistence: ContextPersistence) -> FlowContextHandle {
+        let context = FlowContextHandle::default();
+        context.set_persistence(vec![(FLOW.to_string(), persistence)]);
+        context
+    }
+
+    fn config(dir: &TempDir) -> ContextStoreConfig {
+        ContextStoreConfig {
+            path: Utf8Path::from_path(dir.path())
+                .unwrap()
+                .join("flows-context/local.json"),
+            snapshot_interval: Duration::from_secs(60),
+            max_entries: 100,
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use crate::context_store::unix_secs;
+use crate::flow::FailedMessage;
+use crate::flow::FlowError;
+use crate::flow::Message;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use nix::fcntl::Flock;
+use nix::fcntl::FlockArg;
+use serde::Deserialize;
+use serde::Serialize;
+use std::time::Duration;
+use std::time::SystemTime;
+use tedge_utils::fs::atomically_write_file_async;
+use tedge_utils::fs::AtomFileError;
+use tokio::io::AsyncWriteExt;
+use tokio::time::Instant;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// The delay between two checks for failed messages to be replayed
+const REPLAY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where and how many failed messages are kept for replay
+#[derive(Clone, Debug)]
+pub struct DeadLetterStoreConfig {
+    /// The file where the failed messages are stored, one JSON object per line
+    pub path: Utf8PathBuf,
+
+    /// The maximum number of failed messages kept, the oldest being dropped first
+    pub max_entries: usize,
+}
+
+/// An input message that a flow failed to process
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct DeadLetter {
+    /// Identifier of the failed message, unique for a mapper
+    pub id: u64,
+
+    /// The path to the definition of the flow
+    pub flow: String,
+
+    /// The source of the step that failed
+    pub step: String,
+
+    pub error: String,
+
+    /// Unix timestamp of the failure
+    pub failed_at: u64,
+
+    pub message: Message,
+
+    /// Set to have the message processed again by the flow
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub replay: bool,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DeadLetterStoreError {
+    #[error("Cannot read {path}: {error}")]
+    ReadError {
+        path: Utf8PathBuf,
+        error: std::io::Error,
+    },
+
+    #[error("Cannot parse {path}: {error}")]
+    ParseError {
+        path: Utf8PathBuf,
+        error: serde_json::Error,
+    },
+
+    #[error(transparent)]
+    WriteError(#[from] AtomFileError),
+}
+
+/// Read the failed messages stored in a file, if any
+pub async fn read_dead_letters(path: &Utf8Path) -> Result<Vec<DeadLetter>, DeadLetterStoreError> {
+    let content = match tokio::fs::read(path).await {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => {
+            return Err(DeadLetterStoreError::ReadError {
+                path: path.to_owned(),
+                error,
+            })
+        }
+    };
+    serde_json::Deserializer::from_slice(&content)
+        .into_iter()
+        .collect::<Result<_, _>>()
+        .map_err(|error| DeadLetterStoreError::ParseError {
+            path: path.to_owned(),
+            error,
+        })
+}
+
+/// Update the failed messages stored in a file
+///
+/// The file is locked till updated, so no message recorded meanwhile by a running mapper is lost.
+pub async fn update_dead_letters<T>(
+    path: &Utf8Path,
+    update: impl FnOnce(&mut Vec<DeadLetter>) -> T,
+) -> Result<T, DeadLetterStoreError> {
+    let _lock = lock_dead_letters(path).await?;
+    let mut letters = read_dead_letters(path).await?;
+    let unchanged = letters.clone();
+    let result = update(&mut letters);
+    if letters != unchanged {
+        write_dead_letters(path, &letters).await?;
+    }
+    Ok(result)
+}
+
+/// Take an exclusive lock on a file of failed messages
+///
+/// The lock is taken on a sibling `.lock` file, the file of messages being replaced on update.
+async fn lock_dead_letters(path: &Utf8Path) -> Result<Flock<std::fs::File>, DeadLetterStoreError> {
+    create_parent_dir(path).await?;
+    let lock_path = path.with_extension("lock");
+    let lock_error = |error| DeadLetterStoreError::ReadError {
+        path: lock_path.clone(),
+        error,
+    };
+    let file = std::fs::File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(lock_error)?;
+    tokio::task::spawn_blocking(move || {
+        Flock::lock(file, FlockArg::LockExclusive).map_err(|(_, err)| std::io::Error::from(err))
+    })
+    .await
+    .map_err(|err| lock_error(std::io::Error::other(err)))?
+    .map_err(lock_error)
+}
+
+async fn write_dead_letters(
+    path: &Utf8Path,
+    letters: &[DeadLetter],
+) -> Result<(), DeadLetterStoreError> {
+    let mut content = Vec::new();
+    for letter in letters {
+        content.extend(to_json_line(path, letter)?);
+    }
+    atomically_write_file_async(path, &content).await?;
+    Ok(())
+}
+
+async fn append_dead_letter(
+    path: &Utf8Path,
+    letter: &DeadLetter,
+) -> Result<(), DeadLetterStoreError> {
+    let _lock = lock_dead_letters(path).await?;
+    let line = to_json_line(path, letter)?;
+    let read_error = |error| DeadLetterStoreError::ReadError {
+        path: path.to_owned(),
+        error,
+    };
+    let mut file = tokio::fs::File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .map_err(read_error)?;
+    file.write_all(&line).await.map_err(read_error)?;
+    file.flush().await.map_err(read_error)
+}
+
+async fn create_parent_dir(path: &Utf8Path) -> Result<(), DeadLetterStoreError> {
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|error| DeadLetterStoreError::ReadError {
+                path: dir.to_owned(),
+                error,
+            })?;
+    }
+    Ok(())
+}
+
+fn to_json_line(path: &Utf8Path, letter: &DeadLetter) -> Result<Vec<u8>, DeadLetterStoreError> {
+    let mut line =
+        serde_json::to_vec(letter).map_err(|error| DeadLetterStoreError::ParseError {
+            path: path.to_owned(),
+            error,
+        })?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Keeps the messages that the flows failed to process, and hands back those marked for replay
+pub(crate) struct DeadLetterStore {
+    config: DeadLetterStoreConfig,
+    next_id: u64,
+    count: usize,
+    next_check: Instant,
+
+    /// The modification time of the store file, when last read or written by the mapper
+    last_modified: Option<SystemTime>,
+}
+
+impl DeadLetterStore {
+    pub fn new(config: DeadLetterStoreConfig) -> Self {
+        DeadLetterStore {
+            config,
+            next_id: 1,
+            count: 0,
+            next_check: Instant::now() + REPLAY_CHECK_INTERVAL,
+            last_modified: None,
+        }
+    }
+
+    pub fn next_deadline(&self) -> Instant {
+        self.next_check
+    }
+
+    /// Restore the state of the store after a restart
+    ///
+    /// The messages marked for replay while the mapper was stopped are replayed on the first check.
+    pub async fn restore(&mut self) {
+        match read_dead_letters(&self.config.path).await {
+            Ok(letters) => self.update_counters(&letters),
+            Err(err) => error!(target: "flows", "Cannot restore failed messages: {err}"),
+        }
+    }
+
+    /// Record a message that a flow failed to process
+    pub async fn record(&mut self, flow: &Utf8Path, failed: FailedMessage, error: &FlowError) {
+        let max_entries = self.config.max_entries;
+        if max_entries == 0 {
+            return;
+        }
+
+        let letter = DeadLetter {
+            id: self.next_id,
+            flow: flow.to_string(),
+            step: failed.step,
+            error: error.to_string(),
+            failed_at: unix_secs(SystemTime::now()),
+            message: failed.message,
+            replay: false,
+        };
+        self.next_id += 1;
+
+        // The store might have been updated by `tedge flows errors` since last checked
+        let externally_modified = self.modified().await != self.last_modified;
+        let path = &self.config.path;
+        let result = if self.count < max_entries {
+            self.count += 1;
+            append_dead_letter(path, &letter).await
+        } else {
+            self.trim_and_append(letter).await
+        };
+        if let Err(err) = result {
+            error!(target: "flows", "Cannot store failed message: {err}");
+        }
+        if !externally_modified {
+            self.last_modified = self.modified().await;
+        }
+    }
+
+    async fn trim_and_append(&mut self, letter: DeadLetter) -> Result<(), DeadLetterStoreError> {
+        let max_entries = self.config.max_entries;
+        self.count = update_dead_letters(&self.config.path, |letters| {
+            letters.push(letter);
+            if letters.len() > max_entries {
+                warn!(
+                    target: "flows",
+                    "Too many failed messages: dropping the {} oldest",
+                    letters.len() - max_entries
+                );
+                letters.drain(..letters.len() - max_entries);
+            }
+            letters.len()
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Remove from the store and return the messages marked for replay, if a check is due
+    pub async fn take_replays(&mut self, now: Instant) -> Vec<DeadLetter> {
+        if self.next_check > now {
+            return vec![];
+        }
+        self.next_check = now + REPLAY_CHECK_INTERVAL;
+
+        let modified = self.modified().await;
+        if modified == self.last_modified {
+            return vec![];
+        }
+
+        let path = self.config.path.clone();
+        let replays = update_dead_letters(&path, |letters| {
+            let (replays, kept): (Vec<_>, Vec<_>) = std::mem::take(letters)
+                .into_iter()
+                .partition(|letter| letter.replay);
+            self.update_counters(&kept);
+            *letters = kept;
+            replays
+        })
+        .await;
+        self.last_modified = self.modified().await;
+        match replays {
+            Ok(replays) => {
+                if !replays.is_empty() {
+                    info!(target: "flows", "Replaying {} failed messages", replays.len());
+                }
+                replays
+            }
+            Err(err) => {
+                error!(target: "flows", "Cannot check failed messages to replay: {err}");
+                vec![]
+            }
+        }
+    }
+
+    fn update_counters(&mut self, letters: &[DeadLetter]) {
+        self.count = letters.len();
+        if let Some(max_id) = letters.iter().map(|letter| letter.id).max() {
+            self.next_id = self.next_id.max(max_id + 1);
+        }
+    }
+
+    async fn modified(&self) -> Option<SystemTime> {
+        let metadata = tokio::fs::metadata(&self.config.path).await.ok()?;
+        metadata.modified().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const FLOW: &str = "/flows/measurements.toml";
+    const STEP: &str = "/flows/measurements.js";
+
+    #[tokio::test]
+    async fn failed_messages_are_recorded_with_their_flow_step_and_error() {
+        let dir = TempDir::new().unwrap();
+        let mut store = DeadLetterStore::new(config(&dir, 10));
+        store.restore().await;
+
+        store
+            .record(Utf8Path::new(FLOW), failed("a"), &error())
+            .await;
+        store
+            .record(Utf8Path::new(FLOW), failed("b"), &error())
+            .await;
+
+        let letters = read_dead_letters(&store.config.path).await.unwrap();
+        assert_eq!(letters.len(), 2);
+        assert_eq!(letters[0].id, 1);
+        assert_eq!(letters[0].flow, FLOW);
+        assert_eq!(letters[0].step, STEP);
+        assert_eq!(
+            letters[0].error,
+            "Input message cannot be processed: not a number"
+        );
+        assert_eq!(letters[0].message, Message::new("sensors/temp", "a"));
+        assert_eq!(letters[1].id, 2);
+        assert!(!letters[1].replay);
+    }
+
+    #[tokio::test]
+    async fn only_the_most_recent_failed_messages_are_kept() {
+        let dir = TempDir::new().unwrap();
+        let mut store = DeadLetterStore::new(config(&dir, 2));
+        store.restore().await;
+
+        for payload in ["a", "b", "c", "d"] {
+            store
+                .record(Utf8Path::new(FLOW), failed(payload), &error())
+                .await;
+        }
+
+        let letters = read_dead_letters(&store.config.path).await.unwrap();
+        let ids: Vec<_> = letters.iter().map(|letter| letter.id).collect();
+        assert_eq!(ids, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn ids_are_not_reused_after_a_restart() {
+        let dir = TempDir::new().unwrap();
+        let mut store = DeadLetterStore::new(config(&dir, 10));
+        store.restore().await;
+        store
+            .record(Utf8Path::new(FLOW), failed("a"), &error())
+            .await;
+
+        let mut restarted = DeadLetterStore::new(config(&dir, 10));
+        restarted.restore().await;
+        restarted
+            .record(Utf8Path::new(FLOW), failed("b"), &error())
+            .await;
+
+        let letters = read_dead_letters(&store.config.path).await.unwrap();
+        let ids: Vec<_> = letters.iter().map(|letter| letter.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn messages_marked_for_replay_are_handed_back_and_removed() {
+        let dir = TempDir::new().unwrap();
+        let mut store = DeadLetterStore::new(config(&dir, 10));
+        store.restore().await;
+        for payload in ["a", "b", "c"] {
+            store
+                .record(Utf8Path::new(FLOW), failed(payload), &error())
+                .await;
+        }
+        assert!(store.take_replays(store.next_deadline()).await.is_empty());
+
+        // As done by `tedge flows errors replay --id 2`
+        let path = store.config.path.clone();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        update_dead_letters(&path, |letters| letters[1].replay = true)
+            .await
+            .unwrap();
+
+        let replays = store.take_replays(store.next_deadline()).await;
+        assert_eq!(replays.len(), 1);
+        assert_eq!(replays[0].message, Message::new("sensors/temp", "b"));
+
+        let letters = read_dead_letters(&path).await.unwrap();
+        let ids: Vec<_> = letters.iter().map(|letter| letter.id).collect();
+        assert_eq!(ids, vec![1, 3]);
+        assert!(store.take_replays(store.next_deadline()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_messages_are_not_recorded_while_the_store_is_updated() {
+        let dir = TempDir::new().unwrap();
+        let mut store = DeadLetterStore::new(config(&dir, 10));
+        store.restore().await;
+        let path = store.config.path.clone();
+
+        // As done by `tedge flows errors purge`, while the mapper records a new failed message
+        let lock = lock_dead_letters(&path).await.unwrap();
+        let recording = tokio::spawn(async move {
+            store
+                .record(Utf8Path::new(FLOW), failed("a"), &error())
+                .await;
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!recording.is_finished());
+        drop(lock);
+        recording.await.unwrap();
+
+        let letters = read_dead_letters(&path).await.unwrap();
+        assert_eq!(letters.len(), 1);
+    }
+
+    fn config(dir: &TempDir, max_entries: usize) -> DeadLetterStoreConfig {
+        DeadLetterStoreConfig {
+            path: Utf8Path::from_path(dir.path())
+                .unwrap()
+                .join("flows-errors/local.jsonl"),
+            max_entries,
+        }
+    }
+
+    fn failed(payload: &str) -> FailedMessage {
+        FailedMessage {
+            message: Message::new("sensors/temp", payload),
+            step: STEP.to_string(),
+        }
+    }
+
+    fn error() -> FlowError {
+        FlowError::UnsupportedMessage("not a number".to_string())
+    }
+}
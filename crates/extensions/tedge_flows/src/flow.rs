@@ -1,10 +1,17 @@
+use crate::http_input::WebhookInput;
+use crate::http_output::HttpOutput;
 use crate::input_source::PollingSourceError;
+use crate::js_lib::kv_store::ContextPersistence;
 use crate::js_runtime::JsRuntime;
+use crate::modbus::ModbusRegister;
+use crate::modbus::ModbusServer;
+use crate::modbus::SerialParity;
 use crate::stats::Counter;
 use crate::steps::FlowStep;
 use crate::FlowContextUpdate;
 use crate::LoadError;
 use camino::Utf8PathBuf;
+use serde::Deserialize;
 use serde_json::json;
 use serde_json::Value;
 use std::fmt::Display;
@@ -54,6 +61,9 @@ pub struct Flow {
 
     /// Whether to allow output messages to loop back to the input
     pub expect_loop: bool,
+
+    /// The context values to be persisted across mapper restarts
+    pub context: ContextPersistence,
 }
 
 pub enum SourceTag {
@@ -93,12 +103,28 @@ pub enum FlowInput {
         command: String,
         cwd: Utf8PathBuf,
     },
+    PollModbus {
+        topic: String,
+        server: ModbusServer,
+        unit: u8,
+        registers: Vec<ModbusRegister>,
+        interval: Duration,
+    },
+    StreamSerial {
+        topic: String,
+        device: Utf8PathBuf,
+        baud_rate: u32,
+        parity: SerialParity,
+    },
+    Http(WebhookInput),
 }
 
 #[derive(Clone)]
+#[cfg_attr(test, derive(Debug, PartialEq, Eq))]
 pub enum FlowOutput {
     Mqtt { topic: Option<Topic> },
     File { path: Utf8PathBuf },
+    Http(HttpOutput),
 }
 
 /// The final outcome of a sequence of transformations applied by a flow to a message
@@ -112,9 +138,20 @@ pub enum FlowResult {
         flow: Utf8PathBuf,
         error: FlowError,
         output: FlowOutput,
+
+        /// The input message that failed to be processed, if any
+        failed: Option<FailedMessage>,
     },
 }
 
+/// An input message that a flow failed to process
+pub struct FailedMessage {
+    pub message: Message,
+
+    /// The source of the step that failed to process the message
+    pub step: String,
+}
+
 impl FlowResult {
     pub fn is_err(&self) -> bool {
         match self {
@@ -159,6 +196,9 @@ pub enum FlowError {
     #[error("No messages can be processed due to an incorrect setting: {0}")]
     IncorrectSetting(String),
 
+    #[error("Step execution failed: {0}")]
+    StepFailed(String),
+
     #[error(transparent)]
     PollingSourceError(#[from] PollingSourceError),
 
@@ -169,6 +209,15 @@ pub enum FlowError {
     Anyhow(#[from] anyhow::Error),
 }
 
+/// Parse a JSON payload, the `what` description being used to report an invalid payload
+pub fn parse_json_payload<'a, T: Deserialize<'a>>(
+    payload: &'a [u8],
+    what: &str,
+) -> Result<T, FlowError> {
+    serde_json::from_slice(payload)
+        .map_err(|err| FlowError::UnsupportedMessage(format!("Invalid {what}: {err}")))
+}
+
 impl AsRef<Flow> for Flow {
     fn as_ref(&self) -> &Flow {
         self
@@ -209,13 +258,24 @@ impl Flow {
         let result = self
             .on_message_steps(js_runtime, stats, timestamp, message)
             .await;
-        match &result {
+        match result {
             Ok(messages) => {
                 stats.flow_on_message_done(self.name(), started_at, messages.len());
+                self.publish(Ok(messages))
+            }
+            Err((step, error)) => {
+                stats.flow_on_message_failed(self.name());
+                FlowResult::Err {
+                    flow: self.source.clone(),
+                    error,
+                    output: self.errors.clone(),
+                    failed: Some(FailedMessage {
+                        message: message.clone(),
+                        step,
+                    }),
+                }
             }
-            Err(_) => stats.flow_on_message_failed(self.name()),
         }
-        self.publish(result)
     }
 
     async fn on_message_steps(
@@ -224,7 +284,7 @@ impl Flow {
         stats: &mut Counter,
         timestamp: SystemTime,
         message: &Message,
-    ) -> Result<Vec<Message>, FlowError> {
+    ) -> Result<Vec<Message>, (String, FlowError)> {
         let mut messages = vec![message.clone()];
         for step in self.steps.iter_mut() {
             let js = step.source().to_string();
@@ -238,7 +298,7 @@ impl Flow {
                     }
                     Err(_) => stats.flow_step_failed(&js, "onMessage"),
                 }
-                transformed_messages.extend(step_output?);
+                transformed_messages.extend(step_output.map_err(|error| (js.clone(), error))?);
             }
             messages = transformed_messages;
         }
@@ -450,6 +510,7 @@ impl Flow {
                 flow: self.source.clone(),
                 error,
                 output: self.errors.clone(),
+                failed: None,
             },
         }
     }
@@ -505,6 +566,15 @@ impl Display for FlowInput {
             FlowInput::StreamCommand { command, .. } => {
                 write!(f, "Streaming command: {command}")
             }
+            FlowInput::PollModbus { server, .. } => {
+                write!(f, "Polling Modbus server: {server}")
+            }
+            FlowInput::StreamSerial { device, .. } => {
+                write!(f, "Streaming serial port: {device}")
+            }
+            FlowInput::Http(webhook) => {
+                write!(f, "HTTP webhook: {}{}", webhook.address, webhook.path)
+            }
         }
     }
 }
@@ -523,14 +593,19 @@ impl FlowInput {
             FlowInput::PollFile { topic, .. }
             | FlowInput::PollCommand { topic, .. }
             | FlowInput::StreamFile { topic, .. }
-            | FlowInput::StreamCommand { topic, .. } => Some(topic),
+            | FlowInput::StreamCommand { topic, .. }
+            | FlowInput::PollModbus { topic, .. }
+            | FlowInput::StreamSerial { topic, .. }
+            | FlowInput::Http(WebhookInput { topic, .. }) => Some(topic),
         }
     }
 
     pub fn is_streaming(&self) -> bool {
         matches!(
             self,
-            FlowInput::StreamFile { .. } | FlowInput::StreamCommand { .. }
+            FlowInput::StreamFile { .. }
+                | FlowInput::StreamCommand { .. }
+                | FlowInput::StreamSerial { .. }
         )
     }
 
@@ -540,7 +615,10 @@ impl FlowInput {
             FlowInput::PollFile { topic, .. }
             | FlowInput::PollCommand { topic, .. }
             | FlowInput::StreamFile { topic, .. }
-            | FlowInput::StreamCommand { topic, .. } => topic == &message.topic,
+            | FlowInput::StreamCommand { topic, .. }
+            | FlowInput::PollModbus { topic, .. }
+            | FlowInput::StreamSerial { topic, .. }
+            | FlowInput::Http(WebhookInput { topic, .. }) => topic == &message.topic,
         }
     }
 }
@@ -679,6 +757,8 @@ pub fn error_from_js(err: LoadError) -> FlowError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::js_runtime::JsRuntimeConfig;
+    use crate::transformers::BuiltinTransformers;
     use camino::Utf8PathBuf;
 
     #[test]
@@ -736,6 +816,43 @@ mod tests {
         assert_eq!(messages[1].topic, "te/other");
     }
 
+    #[tokio::test]
+    async fn failed_input_messages_are_returned_along_the_failing_step() {
+        let input = FlowInput::Mqtt {
+            topics: TopicFilter::new_unchecked("te/+"),
+        };
+        let output = FlowOutput::Mqtt { topic: None };
+        let mut flow = test_flow(input, output, false);
+        let step = FlowStep::new_transformer(
+            "test.toml|0|limit-payload-size".to_string(),
+            BuiltinTransformers::default()
+                .new_instance("limit-payload-size")
+                .unwrap(),
+        )
+        .with_config(Some(json!({"max_size": 16})))
+        .unwrap();
+        flow.steps.push(step);
+
+        let js_runtime = JsRuntime::try_new(JsRuntimeConfig::default(), Default::default())
+            .await
+            .unwrap();
+        let mut stats = Counter::default();
+        let message = Message::new("te/foo", "a payload too large to be forwarded");
+        let result = flow
+            .on_message(&js_runtime, &mut stats, SystemTime::now(), &message)
+            .await;
+
+        let FlowResult::Err {
+            failed: Some(failed),
+            ..
+        } = result
+        else {
+            panic!("Expected a failed message");
+        };
+        assert_eq!(failed.message, message);
+        assert_eq!(failed.step, "limit-payload-size");
+    }
+
     fn test_flow(input: FlowInput, output: FlowOutput, expect_loop: bool) -> Flow {
         Flow {
             name: "test-flow".to_string(),
@@ -748,6 +865,7 @@ mod tests {
             errors: FlowOutput::Mqtt { topic: None },
             source: Utf8PathBuf::from("test.toml"),
             expect_loop,
+            context: ContextPersistence::default(),
         }
     }
 }
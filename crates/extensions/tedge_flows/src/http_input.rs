@@ -0,0 +1,354 @@
+use crate::connected_flow::ConnectedFlow;
+use crate::flow::FlowInput;
+use crate::flow::Message;
+use anyhow::Context;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::routing::post;
+use axum::Router;
+use camino::Utf8PathBuf;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use subtle::ConstantTimeEq;
+use tedge_actors::DynSender;
+use tedge_actors::Sender;
+use tokio::task::JoinHandle;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// A message received by the webhook of a flow
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WebhookMessage {
+    /// The flow to which the message is addressed
+    pub flow: Utf8PathBuf,
+
+    pub message: Message,
+}
+
+/// The configuration of the webhook of a flow, to receive messages over HTTP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebhookInput {
+    pub topic: String,
+    pub address: SocketAddr,
+    pub path: String,
+
+    /// The certificate and private key files, if the webhook is served over HTTPS
+    pub tls: Option<(Utf8PathBuf, Utf8PathBuf)>,
+
+    /// The bearer token expected in the `Authorization` header of the requests, if any
+    pub token: Option<String>,
+}
+
+/// The HTTP servers listening for the webhook requests of the flows
+///
+/// Each HTTP input of a flow is served by a dedicated server,
+/// which is started and stopped as the flows are added, updated and removed.
+pub(crate) struct WebhookServers {
+    sender: DynSender<WebhookMessage>,
+    servers: HashMap<String, (WebhookInput, JoinHandle<()>)>,
+}
+
+impl WebhookServers {
+    pub fn new(sender: DynSender<WebhookMessage>) -> Self {
+        WebhookServers {
+            sender,
+            servers: HashMap::new(),
+        }
+    }
+
+    /// Start the servers of new or updated flows, and stop those of flows that have been removed or updated
+    pub async fn update<'a>(&mut self, flows: impl Iterator<Item = &'a ConnectedFlow>) {
+        let mut inputs = HashMap::new();
+        for flow in flows {
+            for (index, input) in flow.flow.input.iter().enumerate() {
+                if let FlowInput::Http(webhook) = input {
+                    let key = format!("{}#input-{index}", flow.source_path());
+                    inputs.insert(key, (flow.source_path().to_owned(), webhook.clone()));
+                }
+            }
+        }
+
+        let stopped: Vec<_> = self
+            .servers
+            .extract_if(|key, (webhook, _)| {
+                !inputs.get(key).is_some_and(|(_, input)| input == webhook)
+            })
+            .collect();
+        for (_, (webhook, server)) in stopped {
+            info!(target: "flows", "Stopping webhook on {}{}", webhook.address, webhook.path);
+            server.abort();
+            // The listener must be closed before an updated server is started on the same address
+            let _ = server.await;
+        }
+
+        for (key, (flow, webhook)) in inputs {
+            if self.servers.contains_key(&key) {
+                continue;
+            }
+            match self.start(flow, &webhook) {
+                Ok(server) => {
+                    info!(target: "flows", "Starting webhook on {}{}", webhook.address, webhook.path);
+                    if webhook.token.is_none() && !webhook.address.ip().is_loopback() {
+                        warn!(target: "flows", "The webhook on {}{} accepts unauthenticated requests from the network: consider setting a token", webhook.address, webhook.path);
+                    }
+                    self.servers.insert(key, (webhook, server));
+                }
+                Err(err) => {
+                    error!(target: "flows", "Cannot start webhook on {}{}: {err:#}", webhook.address, webhook.path);
+                }
+            }
+        }
+    }
+
+    fn start(&self, flow: Utf8PathBuf, webhook: &WebhookInput) -> anyhow::Result<JoinHandle<()>> {
+        let state = WebhookState {
+            flow,
+            topic: webhook.topic.clone(),
+            token: webhook.token.clone(),
+            sender: self.sender.sender_clone(),
+        };
+        let app = Router::new()
+            .route(&webhook.path, post(on_request).put(on_request))
+            .with_state(state);
+
+        let listener = TcpListener::bind(webhook.address)
+            .with_context(|| format!("binding to {}", webhook.address))?;
+        let server: BoxFuture<'static, std::io::Result<()>> = match &webhook.tls {
+            None => axum_server::from_tcp(listener)
+                .serve(app.into_make_service())
+                .boxed(),
+            Some((cert_path, key_path)) => {
+                let cert = axum_tls::load_cert(cert_path)?;
+                let key = axum_tls::load_pkey(key_path)?;
+                let server_config = axum_tls::ssl_config(cert, key, None)?;
+                axum_tls::start_tls_server(listener, server_config, app).boxed()
+            }
+        };
+
+        let address = webhook.address;
+        Ok(tokio::spawn(async move {
+            if let Err(err) = server.await {
+                error!(target: "flows", "Webhook server on {address} failed: {err}");
+            }
+        }))
+    }
+}
+
+impl Drop for WebhookServers {
+    fn drop(&mut self) {
+        for (_, server) in self.servers.values() {
+            server.abort();
+        }
+    }
+}
+
+struct WebhookState {
+    flow: Utf8PathBuf,
+    topic: String,
+    token: Option<String>,
+    sender: DynSender<WebhookMessage>,
+}
+
+impl Clone for WebhookState {
+    fn clone(&self) -> Self {
+        WebhookState {
+            flow: self.flow.clone(),
+            topic: self.topic.clone(),
+            token: self.token.clone(),
+            sender: self.sender.sender_clone(),
+        }
+    }
+}
+
+async fn on_request(
+    State(mut state): State<WebhookState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    if let Some(token) = &state.token {
+        let authorization = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        // Compared in constant time, not to leak the token through response timings
+        let authorized =
+            authorization.is_some_and(|value| bool::from(value.as_bytes().ct_eq(token.as_bytes())));
+        if !authorized {
+            return StatusCode::UNAUTHORIZED;
+        }
+    }
+
+    let message = WebhookMessage {
+        flow: state.flow,
+        message: Message::new(state.topic, body.to_vec()),
+    };
+    match state.sender.send(message).await {
+        Ok(()) => StatusCode::ACCEPTED,
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::Flow;
+    use crate::flow::FlowOutput;
+    use crate::js_lib::kv_store::ContextPersistence;
+    use std::time::Duration;
+    use tedge_actors::test_helpers::MessageReceiverExt;
+    use tedge_actors::Builder;
+    use tedge_actors::MessageReceiver;
+    use tedge_actors::MessageSink;
+    use tedge_actors::NoMessage;
+    use tedge_actors::SimpleMessageBox;
+    use tedge_actors::SimpleMessageBoxBuilder;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn webhook_requests_are_forwarded_as_flow_messages() {
+        let address = free_local_address();
+        let (mut servers, mut messages) = webhook_servers();
+        let flow = webhook_flow(address);
+        servers.update(std::iter::once(&flow)).await;
+
+        let response = http_post(address, "/hook", "", r#"{"temperature":21.5}"#).await;
+        assert!(response.starts_with("HTTP/1.1 202"), "{response}");
+
+        let received = messages.recv().await.unwrap();
+        assert_eq!(received.flow, Utf8PathBuf::from("/flows/webhook.toml"));
+        assert_eq!(
+            received.message,
+            Message::new("webhook", r#"{"temperature":21.5}"#)
+        );
+    }
+
+    #[tokio::test]
+    async fn webhook_servers_are_stopped_along_their_flow() {
+        let address = free_local_address();
+        let (mut servers, _messages) = webhook_servers();
+        let flow = webhook_flow(address);
+        servers.update(std::iter::once(&flow)).await;
+        assert_eq!(servers.servers.len(), 1);
+
+        servers.update(std::iter::empty()).await;
+        assert!(servers.servers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn webhook_still_answers_once_its_flow_is_edited() {
+        let address = free_local_address();
+        let (mut servers, mut messages) = webhook_servers();
+        let flow = webhook_flow(address);
+        servers.update(std::iter::once(&flow)).await;
+
+        // The server is restarted on the same address
+        let edited = flow_with_webhook(WebhookInput {
+            path: "/edited".into(),
+            ..webhook_input(address)
+        });
+        servers.update(std::iter::once(&edited)).await;
+        assert_eq!(servers.servers.len(), 1);
+
+        let response = http_post(address, "/edited", "", "hello").await;
+        assert!(response.starts_with("HTTP/1.1 202"), "{response}");
+        let received = messages.recv().await.unwrap();
+        assert_eq!(received.message, Message::new("webhook", "hello"));
+    }
+
+    #[tokio::test]
+    async fn webhook_requests_must_be_authorized_with_the_token() {
+        let address = free_local_address();
+        let (mut servers, mut messages) = webhook_servers();
+        let flow = flow_with_webhook(WebhookInput {
+            token: Some("secret".into()),
+            ..webhook_input(address)
+        });
+        servers.update(std::iter::once(&flow)).await;
+
+        let response = http_post(address, "/hook", "", "anonymous").await;
+        assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+        let response = http_post(
+            address,
+            "/hook",
+            "Authorization: Bearer not-the-token\r\n",
+            "forged",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 401"), "{response}");
+
+        let response = http_post(
+            address,
+            "/hook",
+            "Authorization: Bearer secret\r\n",
+            "authorized",
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 202"), "{response}");
+        let received = messages.recv().await.unwrap();
+        assert_eq!(received.message, Message::new("webhook", "authorized"));
+    }
+
+    fn webhook_servers() -> (WebhookServers, impl MessageReceiver<WebhookMessage>) {
+        let messages: SimpleMessageBoxBuilder<WebhookMessage, NoMessage> =
+            SimpleMessageBoxBuilder::new("Flows", 16);
+        let servers = WebhookServers::new(messages.get_sender());
+        let messages: SimpleMessageBox<WebhookMessage, NoMessage> = messages.build();
+        (servers, messages.with_timeout(Duration::from_secs(1)))
+    }
+
+    fn webhook_flow(address: SocketAddr) -> ConnectedFlow {
+        flow_with_webhook(webhook_input(address))
+    }
+
+    fn webhook_input(address: SocketAddr) -> WebhookInput {
+        WebhookInput {
+            topic: "webhook".into(),
+            address,
+            path: "/hook".into(),
+            tls: None,
+            token: None,
+        }
+    }
+
+    fn flow_with_webhook(webhook: WebhookInput) -> ConnectedFlow {
+        ConnectedFlow::new(Flow {
+            name: "webhook".into(),
+            version: None,
+            description: None,
+            tags: None,
+            input: vec![FlowInput::Http(webhook)],
+            steps: vec![],
+            output: FlowOutput::Mqtt { topic: None },
+            errors: FlowOutput::Mqtt { topic: None },
+            source: Utf8PathBuf::from("/flows/webhook.toml"),
+            expect_loop: false,
+            context: ContextPersistence::default(),
+        })
+    }
+
+    fn free_local_address() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    async fn http_post(address: SocketAddr, path: &str, headers: &str, body: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!(
+            "POST {path} HTTP/1.1\r\nHost: {address}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+}
@@ -0,0 +1,392 @@
+use crate::flow::Message;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+use tedge_actors::ClientMessageBox;
+use tedge_http_ext::HttpBytes;
+use tedge_http_ext::HttpError;
+use tedge_http_ext::HttpRequest;
+use tedge_http_ext::HttpRequestBuilder;
+use tedge_http_ext::HttpResult;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::error;
+use tracing::warn;
+
+/// The maximum number of requests queued for an HTTP endpoint, beyond which messages are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// The maximum delay between two attempts to send a request
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// An HTTP endpoint to which flow output messages are sent
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpOutput {
+    pub url: String,
+    pub method: HttpMethod,
+    pub headers: Vec<(String, String)>,
+
+    /// How many times a failed request is retried
+    pub retries: u32,
+
+    /// The delay before the first retry, doubled on each subsequent retry
+    pub backoff: Duration,
+
+    /// The maximum number of messages sent in a single request
+    pub batch_size: usize,
+
+    /// How long a message can be held back, waiting for a batch to be complete
+    pub batch_timeout: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HttpMethod {
+    #[default]
+    Post,
+    Put,
+}
+
+impl HttpOutput {
+    fn request(&self, body: HttpBytes) -> Result<HttpRequest, HttpError> {
+        let mut request = match self.method {
+            HttpMethod::Post => HttpRequestBuilder::post(self.url.as_str()),
+            HttpMethod::Put => HttpRequestBuilder::put(self.url.as_str()),
+        };
+        if !self
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        {
+            request = request.header("content-type", "application/json");
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        request.bytes(body).build()
+    }
+}
+
+/// Sends the messages published by the flows with an HTTP output
+///
+/// The requests to each endpoint are sent in order by a background task,
+/// so slow or failing endpoints don't block the flows.
+pub(crate) struct HttpPublisher {
+    client: Option<ClientMessageBox<HttpRequest, HttpResult>>,
+    endpoints: HashMap<(Utf8PathBuf, String), Endpoint>,
+}
+
+struct Endpoint {
+    output: HttpOutput,
+    queue: mpsc::Sender<Vec<u8>>,
+    batch: Vec<Vec<u8>>,
+    deadline: Option<Instant>,
+}
+
+impl HttpPublisher {
+    pub fn new(client: Option<ClientMessageBox<HttpRequest, HttpResult>>) -> Self {
+        HttpPublisher {
+            client,
+            endpoints: HashMap::new(),
+        }
+    }
+
+    pub fn publish(&mut self, flow: &Utf8Path, output: &HttpOutput, messages: Vec<Message>) {
+        let Some(client) = &self.client else {
+            error!(target: "flows", "{flow}: cannot send messages to {}: no HTTP client", output.url);
+            return;
+        };
+
+        let key = (flow.to_path_buf(), output.url.clone());
+        if !matches!(self.endpoints.get(&key), Some(endpoint) if &endpoint.output == output) {
+            // The flow is new or has been updated: the former endpoint task stops once its queue is drained
+            let endpoint = Endpoint::spawn(flow, output.clone(), client.clone());
+            if let Some(mut former) = self.endpoints.insert(key.clone(), endpoint) {
+                former.flush(flow);
+            }
+        }
+        let endpoint = self.endpoints.get_mut(&key).unwrap();
+
+        let now = Instant::now();
+        for message in messages {
+            endpoint.push(flow, message.payload, now);
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.endpoints.values().filter_map(|e| e.deadline).min()
+    }
+
+    /// Send the pending batches which have been waiting for too long
+    pub fn flush_expired(&mut self, now: Instant) {
+        for ((flow, _), endpoint) in self.endpoints.iter_mut() {
+            if endpoint.deadline.is_some_and(|deadline| deadline <= now) {
+                endpoint.flush(flow);
+            }
+        }
+    }
+}
+
+impl Endpoint {
+    fn spawn(
+        flow: &Utf8Path,
+        output: HttpOutput,
+        client: ClientMessageBox<HttpRequest, HttpResult>,
+    ) -> Self {
+        let (queue, requests) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(send_requests(
+            flow.to_path_buf(),
+            output.clone(),
+            client,
+            requests,
+        ));
+        Endpoint {
+            output,
+            queue,
+            batch: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    fn push(&mut self, flow: &Utf8Path, payload: Vec<u8>, now: Instant) {
+        if self.output.batch_size <= 1 {
+            self.enqueue(flow, payload);
+            return;
+        }
+
+        if self.batch.is_empty() {
+            self.deadline = Some(now + self.output.batch_timeout);
+        }
+        self.batch.push(payload);
+        if self.batch.len() >= self.output.batch_size {
+            self.flush(flow);
+        }
+    }
+
+    fn flush(&mut self, flow: &Utf8Path) {
+        self.deadline = None;
+        if !self.batch.is_empty() {
+            let body = batch_body(std::mem::take(&mut self.batch));
+            self.enqueue(flow, body);
+        }
+    }
+
+    fn enqueue(&mut self, flow: &Utf8Path, body: Vec<u8>) {
+        if self.queue.try_send(body).is_err() {
+            error!(target: "flows", "{flow}: dropping message for {}: too many pending requests", self.output.url);
+        }
+    }
+}
+
+/// Combine the payloads of a batch into a JSON array, non-JSON payloads being included as strings
+fn batch_body(payloads: Vec<Vec<u8>>) -> Vec<u8> {
+    let values: Vec<Value> = payloads
+        .into_iter()
+        .map(|payload| {
+            serde_json::from_slice(&payload)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&payload).into_owned()))
+        })
+        .collect();
+    Value::from(values).to_string().into_bytes()
+}
+
+async fn send_requests(
+    flow: Utf8PathBuf,
+    output: HttpOutput,
+    mut client: ClientMessageBox<HttpRequest, HttpResult>,
+    mut requests: mpsc::Receiver<Vec<u8>>,
+) {
+    while let Some(body) = requests.recv().await {
+        if let Err(err) = send_with_retries(&mut client, &output, body.into()).await {
+            error!(target: "flows", "{flow}: cannot send message to {}: {err}", output.url);
+        }
+    }
+}
+
+async fn send_with_retries(
+    client: &mut ClientMessageBox<HttpRequest, HttpResult>,
+    output: &HttpOutput,
+    body: HttpBytes,
+) -> Result<(), String> {
+    let mut backoff = output.backoff;
+    let mut attempt = 0;
+    loop {
+        let error = match send(client, output, body.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(SendError::Permanent(error)) => return Err(error),
+            Err(SendError::Transient(error)) => error,
+        };
+        if attempt >= output.retries {
+            return Err(error);
+        }
+        attempt += 1;
+        warn!(target: "flows", "Request to {} failed: {error}. Retrying in {backoff:?}", output.url);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+enum SendError {
+    /// The request might succeed if sent again
+    Transient(String),
+
+    /// The request is rejected and must not be sent again
+    Permanent(String),
+}
+
+async fn send(
+    client: &mut ClientMessageBox<HttpRequest, HttpResult>,
+    output: &HttpOutput,
+    body: HttpBytes,
+) -> Result<(), SendError> {
+    let request = output
+        .request(body)
+        .map_err(|err| SendError::Permanent(err.to_string()))?;
+    let response = client
+        .await_response(request)
+        .await
+        .map_err(|err| SendError::Permanent(err.to_string()))?
+        .map_err(|err| SendError::Transient(err.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else if status.is_server_error() || status.as_u16() == 429 {
+        Err(SendError::Transient(format!("HTTP status {status}")))
+    } else {
+        Err(SendError::Permanent(format!("HTTP status {status}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tedge_actors::test_helpers::FakeServerBox;
+    use tedge_actors::test_helpers::MessageReceiverExt;
+    use tedge_actors::Builder;
+    use tedge_actors::MessageReceiver;
+    use tedge_actors::Sender;
+    use tedge_http_ext::test_helpers::HttpRequestExt;
+    use tedge_http_ext::test_helpers::HttpResponseBuilder;
+
+    #[test]
+    fn batches_are_sent_as_json_arrays() {
+        let body = batch_body(vec![
+            br#"{"temperature":21.5}"#.to_vec(),
+            b"not json".to_vec(),
+        ]);
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body, json!([{"temperature": 21.5}, "not json"]));
+    }
+
+    #[test]
+    fn requests_are_sent_with_the_configured_method_and_headers() {
+        let mut output = output(1);
+        output.method = HttpMethod::Put;
+        output.headers = vec![
+            ("Content-Type".to_string(), "text/plain".to_string()),
+            ("Authorization".to_string(), "Bearer secret".to_string()),
+        ];
+
+        let request = output.request("hello".into()).unwrap();
+        assert_eq!(request.method().as_str(), "PUT");
+        assert_eq!(request.uri().to_string(), "http://127.0.0.1:8080/telemetry");
+        assert_eq!(request.headers()["content-type"], "text/plain");
+        assert_eq!(request.headers()["authorization"], "Bearer secret");
+    }
+
+    #[tokio::test]
+    async fn messages_are_batched_up_to_the_batch_size() {
+        let mut server = FakeServerBox::<HttpRequest, HttpResult>::builder();
+        let mut publisher = HttpPublisher::new(Some(ClientMessageBox::new(&mut server)));
+        let mut server = server.build().with_timeout(Duration::from_secs(1));
+
+        let flow = Utf8Path::new("/flows/http.toml");
+        let output = output(2);
+        publisher.publish(flow, &output, vec![Message::new("t", "1")]);
+        assert!(publisher.next_deadline().is_some());
+        publisher.publish(flow, &output, vec![Message::new("t", "2")]);
+        assert!(publisher.next_deadline().is_none());
+
+        let request = server.recv().await.unwrap();
+        let body: Value = request.json().await.unwrap();
+        assert_eq!(body, json!([1, 2]));
+    }
+
+    #[tokio::test]
+    async fn pending_batches_are_sent_on_timeout() {
+        let mut server = FakeServerBox::<HttpRequest, HttpResult>::builder();
+        let mut publisher = HttpPublisher::new(Some(ClientMessageBox::new(&mut server)));
+        let mut server = server.build().with_timeout(Duration::from_secs(1));
+
+        let flow = Utf8Path::new("/flows/http.toml");
+        publisher.publish(flow, &output(10), vec![Message::new("t", "1")]);
+        let deadline = publisher.next_deadline().unwrap();
+        publisher.flush_expired(deadline - Duration::from_millis(1));
+        assert!(publisher.next_deadline().is_some());
+        publisher.flush_expired(deadline);
+        assert!(publisher.next_deadline().is_none());
+
+        let request = server.recv().await.unwrap();
+        let body: Value = request.json().await.unwrap();
+        assert_eq!(body, json!([1]));
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_retried() {
+        let mut server = FakeServerBox::<HttpRequest, HttpResult>::builder();
+        let mut client = ClientMessageBox::new(&mut server);
+        let mut server = server.build();
+        let output = output(1);
+
+        let sending =
+            tokio::spawn(
+                async move { send_with_retries(&mut client, &output, "hello".into()).await },
+            );
+
+        server.recv().await.unwrap();
+        let unavailable = HttpResponseBuilder::new().status(503).build();
+        server.send(unavailable).await.unwrap();
+
+        server.recv().await.unwrap();
+        let ok = HttpResponseBuilder::new().status(200).build();
+        server.send(ok).await.unwrap();
+
+        assert!(sending.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_are_not_retried() {
+        let mut server = FakeServerBox::<HttpRequest, HttpResult>::builder();
+        let mut client = ClientMessageBox::new(&mut server);
+        let mut server = server.build();
+        let output = output(1);
+
+        let sending =
+            tokio::spawn(
+                async move { send_with_retries(&mut client, &output, "hello".into()).await },
+            );
+
+        server.recv().await.unwrap();
+        let bad_request = HttpResponseBuilder::new().status(400).build();
+        server.send(bad_request).await.unwrap();
+
+        assert!(sending.await.unwrap().is_err());
+    }
+
+    fn output(batch_size: usize) -> HttpOutput {
+        HttpOutput {
+            url: "http://127.0.0.1:8080/telemetry".to_string(),
+            method: HttpMethod::Post,
+            headers: vec![],
+            retries: 3,
+            backoff: Duration::from_millis(10),
+            batch_size,
+            batch_timeout: Duration::from_secs(1),
+        }
+    }
+}
@@ -1,4 +1,8 @@
 use crate::flow::Message;
+use crate::modbus::ModbusClient;
+use crate::modbus::ModbusRegister;
+use crate::modbus::ModbusServer;
+use crate::modbus::SerialParity;
 use crate::next_deadline_after;
 use async_trait::async_trait;
 use camino::Utf8PathBuf;
@@ -188,6 +192,103 @@ impl StreamingSource for FileStreamingSource {
     }
 }
 
+pub struct ModbusPollingSource {
+    topic: String,
+    server: String,
+    client: ModbusClient,
+    registers: Vec<ModbusRegister>,
+    poll: PollInterval,
+}
+
+impl ModbusPollingSource {
+    pub fn new(
+        topic: String,
+        server: ModbusServer,
+        unit: u8,
+        registers: Vec<ModbusRegister>,
+        interval: Duration,
+    ) -> Self {
+        ModbusPollingSource {
+            topic,
+            server: server.to_string(),
+            client: ModbusClient::new(server, unit),
+            registers,
+            poll: PollInterval::new(interval),
+        }
+    }
+}
+
+#[async_trait]
+impl PollingSource for ModbusPollingSource {
+    async fn poll(&mut self, timestamp: SystemTime) -> Result<Vec<Message>, PollingSourceError> {
+        let values = self.client.read(&self.registers).await.map_err(|err| {
+            PollingSourceError::CannotPoll {
+                resource: self.server.clone(),
+                error: err.to_string(),
+            }
+        })?;
+        let payload = serde_json::Value::Object(values).to_string();
+        Ok(vec![Message::with_timestamp(
+            self.topic.clone(),
+            payload,
+            timestamp,
+        )])
+    }
+
+    fn next_deadline(&self) -> Instant {
+        self.poll.next_deadline
+    }
+
+    fn is_ready(&self, now: Instant) -> bool {
+        self.poll.is_ready(now)
+    }
+
+    fn update_after_poll(&mut self, now: Instant) {
+        self.poll.update_after_poll(now);
+    }
+}
+
+pub struct SerialStreamingSource {
+    flow: String,
+    topic: String,
+    device: Utf8PathBuf,
+    baud_rate: u32,
+    parity: SerialParity,
+}
+
+impl SerialStreamingSource {
+    pub fn new(
+        flow: String,
+        topic: String,
+        device: Utf8PathBuf,
+        baud_rate: u32,
+        parity: SerialParity,
+    ) -> Self {
+        SerialStreamingSource {
+            flow,
+            topic,
+            device,
+            baud_rate,
+            parity,
+        }
+    }
+}
+
+impl StreamingSource for SerialStreamingSource {
+    fn watch_request(&self) -> Option<WatchRequest> {
+        Some(WatchRequest::WatchSerial {
+            topic: self.flow.clone(),
+            device: self.device.clone(),
+            baud_rate: self.baud_rate,
+            parity: self.parity.into(),
+        })
+    }
+
+    fn input_topic(&self) -> &str {
+        &self.topic
+    }
+}
+
 struct PollInterval {
     polling_interval: Duration,
     next_deadline: Instant,
@@ -1,3 +1,6 @@
+use crate::context_store::unix_secs;
+use crate::context_store::ContextEntry;
+use crate::context_store::ContextScope;
 use crate::js_value::JsonValue;
 use rquickjs::class::Trace;
 use rquickjs::Ctx;
@@ -11,6 +14,8 @@ use std::collections::HashMap;
 use std::ops::Deref;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
 
 #[derive(Clone, Debug, Default, JsLifetime)]
 pub struct FlowContextHandle {
@@ -22,6 +27,31 @@ struct LayeredKVStore {
     global: BTreeMap<String, JsonValue>,
     scoped: HashMap<FlowContext, BTreeMap<String, JsonValue>>,
     updates: Vec<FlowContextUpdate>,
+
+    /// When each value has been last updated
+    updated_at: HashMap<FlowContext, HashMap<String, SystemTime>>,
+
+    /// The values to be persisted, as requested by each flow
+    persistence: Vec<(String, ContextPersistence)>,
+}
+
+/// The context values a flow requests to be persisted across mapper restarts
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContextPersistence {
+    /// Persist the values of the flow context and of the contexts of its steps
+    pub flow: bool,
+
+    /// Persist the values of the mapper context with a key starting with one of these prefixes
+    pub mapper_keys: Vec<String>,
+
+    /// Delay after which a persisted value expires, unless updated
+    pub ttl: Option<Duration>,
+}
+
+impl ContextPersistence {
+    pub fn is_enabled(&self) -> bool {
+        self.flow || !self.mapper_keys.is_empty()
+    }
 }
 
 #[derive(Debug)]
@@ -87,6 +117,50 @@ impl FlowContextHandle {
         data.remove(context, key);
     }
 
+    /// Set the context values to be persisted, as requested by the flows
+    pub(crate) fn set_persistence(&self, persistence: Vec<(String, ContextPersistence)>) {
+        self.handle.lock().unwrap().persistence = persistence;
+    }
+
+    /// Collect the values to be persisted
+    pub(crate) fn snapshot(&self, now: SystemTime) -> Vec<ContextEntry> {
+        self.handle.lock().unwrap().snapshot(now)
+    }
+
+    /// Restore values persisted before a restart, ignoring those that have expired since
+    pub(crate) fn restore(&self, entries: Vec<ContextEntry>, now: SystemTime) {
+        let mut data = self.handle.lock().unwrap();
+        for entry in entries {
+            data.restore(entry, now);
+        }
+    }
+
+    /// Replace all the persisted values by those of a snapshot
+    ///
+    /// This is used to take into account a snapshot that has been updated by the user.
+    pub(crate) fn replace_persisted(&self, entries: Vec<ContextEntry>, now: SystemTime) {
+        let mut data = self.handle.lock().unwrap();
+        for (context, key) in data.persisted_keys() {
+            data.remove(&context, &key);
+        }
+        for entry in entries {
+            data.restore(entry, now);
+        }
+    }
+
+    /// Remove the persisted values which have expired
+    pub(crate) fn purge_expired(&self, now: SystemTime) {
+        let mut data = self.handle.lock().unwrap();
+        let expired: Vec<_> = data
+            .persisted_keys()
+            .into_iter()
+            .filter(|(context, key)| data.is_expired(context, key, now))
+            .collect();
+        for (context, key) in expired {
+            data.remove(&context, &key);
+        }
+    }
+
     pub(crate) fn drain_updates(&mut self) -> Vec<FlowContextUpdate> {
         let mut data = self.handle.lock().unwrap();
         data.updates.drain(..).collect()
@@ -130,6 +204,9 @@ impl LayeredKVStore {
     }
 
     fn get(&self, context: &FlowContext, key: &str) -> JsonValue {
+        if self.is_expired(context, key, SystemTime::now()) {
+            return JsonValue::Null;
+        }
         match self.context(context) {
             None => JsonValue::Null,
             Some(map) => map.get_value(key),
@@ -144,14 +221,27 @@ impl LayeredKVStore {
     }
 
     fn keys(&self, context: &FlowContext) -> Vec<String> {
+        let now = SystemTime::now();
         match self.context(context) {
             None => vec![],
-            Some(map) => map.get_keys(),
+            Some(map) => map
+                .get_keys()
+                .into_iter()
+                .filter(|key| !self.is_expired(context, key, now))
+                .collect(),
         }
     }
 
     fn insert(&mut self, context: &FlowContext, key: &str, value: JsonValue) {
+        self.insert_at(context, key, value, SystemTime::now())
+    }
+
+    fn insert_at(&mut self, context: &FlowContext, key: &str, value: JsonValue, now: SystemTime) {
         self.entry(context).set_value(key, value);
+        self.updated_at
+            .entry(context.clone())
+            .or_default()
+            .insert(key.to_string(), now);
         if context.is_global() {
             self.updates.push(FlowContextUpdate::Inserted {
                 key: key.to_string(),
@@ -168,6 +258,91 @@ impl LayeredKVStore {
                 });
             }
         }
+        if let Some(updated_at) = self.updated_at.get_mut(context) {
+            updated_at.remove(key);
+        }
+    }
+
+    /// The persistence requested for a value, if any
+    fn persistence(&self, context: &FlowContext, key: &str) -> Option<&ContextPersistence> {
+        self.persistence.iter().find_map(|(flow, persistence)| {
+            let persisted = match context {
+                FlowContext::Mapper => persistence
+                    .mapper_keys
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix.as_str())),
+                FlowContext::Flow(name) => persistence.flow && name == flow,
+                FlowContext::Script(name) => {
+                    persistence.flow
+                        && name
+                            .strip_prefix(flow.as_str())
+                            .is_some_and(|step| step.starts_with('|'))
+                }
+            };
+            persisted.then_some(persistence)
+        })
+    }
+
+    fn expires_at(&self, context: &FlowContext, key: &str) -> Option<SystemTime> {
+        let ttl = self.persistence(context, key)?.ttl?;
+        let updated_at = self.updated_at.get(context)?.get(key)?;
+        Some(*updated_at + ttl)
+    }
+
+    fn is_expired(&self, context: &FlowContext, key: &str, now: SystemTime) -> bool {
+        self.expires_at(context, key)
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+
+    fn values(&self) -> impl Iterator<Item = (FlowContext, &String, &JsonValue)> {
+        let global = self
+            .global
+            .iter()
+            .map(|(key, value)| (FlowContext::Mapper, key, value));
+        let scoped = self.scoped.iter().flat_map(|(context, map)| {
+            map.iter()
+                .map(move |(key, value)| (context.clone(), key, value))
+        });
+        global.chain(scoped)
+    }
+
+    fn persisted_keys(&self) -> Vec<(FlowContext, String)> {
+        self.values()
+            .filter(|(context, key, _)| self.persistence(context, key).is_some())
+            .map(|(context, key, _)| (context, key.clone()))
+            .collect()
+    }
+
+    fn snapshot(&self, now: SystemTime) -> Vec<ContextEntry> {
+        self.values()
+            .filter(|(context, key, _)| {
+                self.persistence(context, key).is_some() && !self.is_expired(context, key, now)
+            })
+            .map(|(context, key, value)| {
+                let updated_at = self
+                    .updated_at
+                    .get(&context)
+                    .and_then(|updated_at| updated_at.get(key))
+                    .copied()
+                    .unwrap_or(now);
+                ContextEntry {
+                    expires_at: self.expires_at(&context, key).map(unix_secs),
+                    context: context.into(),
+                    key: key.clone(),
+                    value: value.clone().into(),
+                    updated_at: unix_secs(updated_at),
+                }
+            })
+            .collect()
+    }
+
+    fn restore(&mut self, entry: ContextEntry, now: SystemTime) {
+        if entry.is_expired(now) {
+            return;
+        }
+        let context = entry.context.into();
+        let updated_at = SystemTime::UNIX_EPOCH + Duration::from_secs(entry.updated_at);
+        self.insert_at(&context, &entry.key, entry.value.into(), updated_at);
     }
 }
 
@@ -216,6 +391,26 @@ impl<'js> FlowContext {
     }
 }
 
+impl From<FlowContext> for ContextScope {
+    fn from(context: FlowContext) -> Self {
+        match context {
+            FlowContext::Mapper => ContextScope::Mapper,
+            FlowContext::Flow(name) => ContextScope::Flow(name),
+            FlowContext::Script(name) => ContextScope::Script(name),
+        }
+    }
+}
+
+impl From<ContextScope> for FlowContext {
+    fn from(scope: ContextScope) -> Self {
+        match scope {
+            ContextScope::Mapper => FlowContext::Mapper,
+            ContextScope::Flow(name) => FlowContext::Flow(name),
+            ContextScope::Script(name) => FlowContext::Script(name),
+        }
+    }
+}
+
 impl KVStore for BTreeMap<String, JsonValue> {
     fn get_value(&self, key: &str) -> JsonValue {
         self.get(key).cloned().unwrap_or(JsonValue::Null)
@@ -2,6 +2,7 @@ use crate::js_lib;
 use crate::js_lib::kv_store::FlowContextHandle;
 use crate::js_script::JsScript;
 use crate::js_value::JsonValue;
+use crate::wasm_config::WasmConfig;
 use crate::LoadError;
 use camino::Utf8Path;
 use rquickjs::module::Evaluated;
@@ -22,6 +23,7 @@ pub struct JsRuntime {
     worker: mpsc::Sender<JsRequest>,
     module_sources: HashMap<String, Vec<u8>>,
     config: JsRuntimeConfig,
+    wasm_config: WasmConfig,
 }
 
 #[derive(Clone)]
@@ -66,6 +68,7 @@ impl JsRuntime {
             worker,
             module_sources,
             config,
+            wasm_config: WasmConfig::default(),
         })
     }
 
@@ -86,6 +89,19 @@ impl JsRuntime {
         self.store.clone()
     }
 
+    pub fn config(&self) -> &JsRuntimeConfig {
+        &self.config
+    }
+
+    /// The limits applied to the WebAssembly steps loaded along the scripts
+    pub fn wasm_config(&self) -> WasmConfig {
+        self.wasm_config
+    }
+
+    pub fn set_wasm_config(&mut self, wasm_config: WasmConfig) {
+        self.wasm_config = wasm_config;
+    }
+
     pub async fn load_script(&mut self, script: &mut JsScript) -> Result<(), LoadError> {
         let exports = self
             .load_file(script.module_name.to_owned(), script.path())
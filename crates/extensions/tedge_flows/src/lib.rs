@@ -1,25 +1,50 @@
 mod actor;
 mod config;
 mod connected_flow;
+mod context_store;
+mod dead_letters;
 mod flow;
+mod http_input;
+mod http_output;
 mod input_source;
 mod js_lib;
 mod js_runtime;
 mod js_script;
 mod js_value;
+mod modbus;
 mod params;
 mod registry;
 mod runtime;
 mod stats;
 mod steps;
 mod transformers;
+mod wasm_config;
+#[cfg(feature = "wasm")]
+mod wasm_step;
 
 use crate::actor::FlowsMapper;
 pub use crate::config::derive_flow_name;
 pub use crate::config::ConfigError;
 pub use crate::config::FlowConfig;
 pub use crate::connected_flow::ConnectedFlowRegistry;
+pub use crate::context_store::read_context_snapshot;
+pub use crate::context_store::write_context_snapshot;
+pub use crate::context_store::ContextEntry;
+pub use crate::context_store::ContextScope;
+pub use crate::context_store::ContextStoreConfig;
+pub use crate::context_store::ContextStoreError;
+pub use crate::dead_letters::read_dead_letters;
+pub use crate::dead_letters::update_dead_letters;
+pub use crate::dead_letters::DeadLetter;
+pub use crate::dead_letters::DeadLetterStoreConfig;
+pub use crate::dead_letters::DeadLetterStoreError;
 pub use crate::flow::*;
+pub use crate::http_input::WebhookInput;
+pub use crate::http_input::WebhookMessage;
+use crate::http_input::WebhookServers;
+pub use crate::http_output::HttpMethod;
+pub use crate::http_output::HttpOutput;
+use crate::http_output::HttpPublisher;
 pub use crate::params::empty_mapper_params;
 pub use crate::params::MapperParams;
 pub use crate::registry::BaseFlowRegistry;
@@ -30,6 +55,7 @@ use crate::stats::MqttStatsPublisher;
 use crate::stats::StatsFilter;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
+pub use js_lib::kv_store::ContextPersistence;
 pub use js_lib::kv_store::FlowContextHandle;
 pub use js_lib::kv_store::FlowContextUpdate;
 pub use js_runtime::JsRuntimeConfig;
@@ -39,16 +65,20 @@ use std::path::PathBuf;
 use std::time::Duration;
 use tedge_actors::fan_in_message_type;
 use tedge_actors::Builder;
+use tedge_actors::ClientMessageBox;
 use tedge_actors::CloneSender;
 use tedge_actors::DynSender;
 use tedge_actors::MessageSink;
 use tedge_actors::MessageSource;
 use tedge_actors::NoConfig;
 use tedge_actors::NullSender;
+use tedge_actors::RequestEnvelope;
 use tedge_actors::RuntimeRequest;
 use tedge_actors::RuntimeRequestSink;
 use tedge_actors::SimpleMessageBoxBuilder;
 use tedge_file_system_ext::FsWatchEvent;
+use tedge_http_ext::HttpRequest;
+use tedge_http_ext::HttpResult;
 use tedge_mqtt_ext::DynSubscriptions;
 use tedge_mqtt_ext::MqttMessage;
 use tedge_mqtt_ext::MqttRequest;
@@ -60,7 +90,10 @@ use tedge_watch_ext::WatchActorBuilder;
 use tedge_watch_ext::WatchEvent;
 use tedge_watch_ext::WatchRequest;
 use tokio::time::Instant;
+pub use transformers::BuiltinTransformers;
 pub use transformers::Transformer;
+pub use transformers::TransformerChain;
+pub use wasm_config::WasmConfig;
 
 pub struct FlowsMapperConfig {
     pub(crate) status_topic: Topic,
@@ -68,6 +101,9 @@ pub struct FlowsMapperConfig {
     pub(crate) stats_dump_interval: Duration,
     pub(crate) stats_filter: StatsFilter,
     pub(crate) js_config: JsRuntimeConfig,
+    pub(crate) wasm_config: WasmConfig,
+    pub(crate) context_store: Option<ContextStoreConfig>,
+    pub(crate) dead_letters: Option<DeadLetterStoreConfig>,
 }
 
 impl Default for FlowsMapperConfig {
@@ -112,6 +148,9 @@ impl FlowsMapperConfig {
                 publish_on_startup_stats,
             },
             js_config: JsRuntimeConfig::default(),
+            wasm_config: WasmConfig::default(),
+            context_store: None,
+            dead_letters: None,
         }
     }
 
@@ -123,9 +162,33 @@ impl FlowsMapperConfig {
         };
         FlowsMapperConfig { js_config, ..self }
     }
+
+    pub fn with_wasm_limits(self, memory_size: usize, fuel: u64) -> Self {
+        let wasm_config = WasmConfig { memory_size, fuel };
+        FlowsMapperConfig {
+            wasm_config,
+            ..self
+        }
+    }
+
+    /// Persist the flow context values requested by the flows
+    pub fn with_context_store(self, context_store: ContextStoreConfig) -> Self {
+        FlowsMapperConfig {
+            context_store: Some(context_store),
+            ..self
+        }
+    }
+
+    /// Keep the messages that the flows failed to process, so these can be replayed
+    pub fn with_dead_letter_store(self, dead_letters: DeadLetterStoreConfig) -> Self {
+        FlowsMapperConfig {
+            dead_letters: Some(dead_letters),
+            ..self
+        }
+    }
 }
 
-fan_in_message_type!(InputMessage[MqttMessage, WatchEvent, FsWatchEvent, Tick]: Clone, Debug, Eq, PartialEq);
+fan_in_message_type!(InputMessage[MqttMessage, WatchEvent, FsWatchEvent, WebhookMessage, Tick]: Clone, Debug, Eq, PartialEq);
 
 pub fn flows_dir(mapper_dir: &Utf8Path) -> Utf8PathBuf {
     mapper_dir.join("flows")
@@ -159,6 +222,7 @@ pub struct FlowsMapperBuilder {
     message_box: SimpleMessageBoxBuilder<InputMessage, SubscriptionDiff>,
     mqtt_sender: DynSender<MqttMessage>,
     watch_request_sender: DynSender<WatchRequest>,
+    http: Option<ClientMessageBox<HttpRequest, HttpResult>>,
     processor: MessageProcessor<ConnectedFlowRegistry>,
 }
 
@@ -169,7 +233,9 @@ impl FlowsMapperBuilder {
     ) -> Result<Self, LoadError> {
         let context = FlowContextHandle::default();
         let mut processor =
-            MessageProcessor::with_context(registry, config.js_config.clone(), context).await?;
+            MessageProcessor::with_context(registry, config.js_config.clone(), context)
+                .await?
+                .with_wasm_config(config.wasm_config);
         let message_box = SimpleMessageBoxBuilder::new("TedgeFlows", 16);
         let mqtt_sender = NullSender.into();
         let watch_request_sender = NullSender.into();
@@ -181,6 +247,7 @@ impl FlowsMapperBuilder {
             message_box,
             mqtt_sender,
             watch_request_sender,
+            http: None,
             processor,
         })
     }
@@ -217,6 +284,14 @@ impl FlowsMapperBuilder {
         cmd.connect(self);
     }
 
+    /// Connect the HTTP client used by the flows with an HTTP output
+    pub fn connect_http(
+        &mut self,
+        http: &mut impl MessageSink<RequestEnvelope<HttpRequest, HttpResult>>,
+    ) {
+        self.http = Some(ClientMessageBox::new(http));
+    }
+
     fn topics(&self) -> TopicFilter {
         self.processor.subscriptions()
     }
@@ -249,6 +324,8 @@ impl Builder<FlowsMapper> for FlowsMapperBuilder {
 
     fn build(self) -> FlowsMapper {
         let subscriptions = self.topics();
+        let webhooks = WebhookServers::new(self.message_box.get_sender().sender_clone());
+        let http = HttpPublisher::new(self.http);
         FlowsMapper::new(
             self.config,
             self.message_box.build(),
@@ -256,6 +333,8 @@ impl Builder<FlowsMapper> for FlowsMapperBuilder {
             self.watch_request_sender,
             subscriptions,
             self.processor,
+            webhooks,
+            http,
         )
     }
 }
@@ -294,6 +373,14 @@ pub enum LoadError {
 
     #[error("Maximum processing time exceeded")]
     Timeout,
+
+    #[error("WebAssembly Error: {0:#}")]
+    WasmError(anyhow::Error),
+
+    #[error(
+        "WebAssembly steps are not supported: thin-edge has been built without the wasm feature"
+    )]
+    WasmNotSupported,
 }
 
 impl LoadError {
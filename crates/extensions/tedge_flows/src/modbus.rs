@@ -0,0 +1,692 @@
+//! A minimal Modbus client, reading holding and input registers over TCP or RTU
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Number;
+use serde_json::Value;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio_serial::DataBits;
+use tokio_serial::SerialPortBuilderExt;
+use tokio_serial::SerialStream;
+use tokio_serial::StopBits;
+
+/// The maximum number of registers that can be read by a single request
+const MAX_REGISTERS_PER_READ: u32 = 125;
+
+/// How long to wait for a response before giving up
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub const DEFAULT_MODBUS_PORT: u16 = 502;
+
+/// The server from which registers are read
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModbusServer {
+    /// A Modbus TCP server
+    Tcp { host: String, port: u16 },
+
+    /// A Modbus RTU server, connected to a serial port
+    Rtu {
+        device: Utf8PathBuf,
+        baud_rate: u32,
+        parity: SerialParity,
+    },
+}
+
+/// The parity bit of the frames sent over a serial port
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SerialParity {
+    #[default]
+    None,
+    Even,
+    Odd,
+}
+
+impl From<SerialParity> for tokio_serial::Parity {
+    fn from(parity: SerialParity) -> Self {
+        match parity {
+            SerialParity::None => tokio_serial::Parity::None,
+            SerialParity::Even => tokio_serial::Parity::Even,
+            SerialParity::Odd => tokio_serial::Parity::Odd,
+        }
+    }
+}
+
+impl Display for ModbusServer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModbusServer::Tcp { host, port } => write!(f, "{host}:{port}"),
+            ModbusServer::Rtu { device, .. } => write!(f, "{device}"),
+        }
+    }
+}
+
+/// A value read from one or several consecutive registers
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct ModbusRegister {
+    /// The property name of the value in the messages
+    pub name: String,
+
+    /// The address of the first register
+    pub address: u16,
+
+    #[serde(default)]
+    pub table: RegisterTable,
+
+    #[serde(rename = "type", default)]
+    pub data_type: DataType,
+
+    /// The order of the two bytes of each register
+    #[serde(default)]
+    pub byte_order: Endianness,
+
+    /// The order of the registers of a value spanning several registers
+    #[serde(default)]
+    pub word_order: Endianness,
+
+    /// A factor applied to the raw value
+    pub scale: Option<Number>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterTable {
+    #[default]
+    Holding,
+    Input,
+}
+
+impl RegisterTable {
+    fn function_code(self) -> u8 {
+        match self {
+            RegisterTable::Holding => 0x03,
+            RegisterTable::Input => 0x04,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DataType {
+    Int16,
+    #[default]
+    Uint16,
+    Int32,
+    Uint32,
+    Int64,
+    Uint64,
+    Float32,
+    Float64,
+}
+
+impl DataType {
+    /// The number of 16-bit registers holding a value of this type
+    pub fn register_count(self) -> u16 {
+        match self {
+            DataType::Int16 | DataType::Uint16 => 1,
+            DataType::Int32 | DataType::Uint32 | DataType::Float32 => 2,
+            DataType::Int64 | DataType::Uint64 | DataType::Float64 => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Endianness {
+    #[default]
+    Big,
+    Little,
+}
+
+impl ModbusRegister {
+    /// Decode the value of this register, given the content of the registers it spans
+    pub fn decode(&self, words: &[u16]) -> Value {
+        let mut words = words.to_vec();
+        if self.word_order == Endianness::Little {
+            words.reverse();
+        }
+        let mut bytes = Vec::with_capacity(words.len() * 2);
+        for word in words {
+            match self.byte_order {
+                Endianness::Big => bytes.extend_from_slice(&word.to_be_bytes()),
+                Endianness::Little => bytes.extend_from_slice(&word.to_le_bytes()),
+            }
+        }
+
+        let value: Number = match self.data_type {
+            DataType::Int16 => i16::from_be_bytes([bytes[0], bytes[1]]).into(),
+            DataType::Uint16 => u16::from_be_bytes([bytes[0], bytes[1]]).into(),
+            DataType::Int32 => i32::from_be_bytes(be_bytes(&bytes)).into(),
+            DataType::Uint32 => u32::from_be_bytes(be_bytes(&bytes)).into(),
+            DataType::Int64 => i64::from_be_bytes(be_bytes(&bytes)).into(),
+            DataType::Uint64 => u64::from_be_bytes(be_bytes(&bytes)).into(),
+            DataType::Float32 => {
+                match Number::from_f64(f32::from_be_bytes(be_bytes(&bytes)) as f64) {
+                    Some(value) => value,
+                    None => return Value::Null,
+                }
+            }
+            DataType::Float64 => match Number::from_f64(f64::from_be_bytes(be_bytes(&bytes))) {
+                Some(value) => value,
+                None => return Value::Null,
+            },
+        };
+
+        match self.scale.as_ref().and_then(Number::as_f64) {
+            None => Value::Number(value),
+            Some(scale) => value
+                .as_f64()
+                .and_then(|value| Number::from_f64(value * scale))
+                .map_or(Value::Null, Value::Number),
+        }
+    }
+}
+
+fn be_bytes<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    bytes[..N].try_into().expect("as many bytes as registers")
+}
+
+/// A range of consecutive registers read by a single request
+#[derive(Debug, PartialEq, Eq)]
+struct ReadBlock {
+    table: RegisterTable,
+    address: u16,
+    count: u16,
+}
+
+impl ReadBlock {
+    fn contains(&self, register: &ModbusRegister) -> bool {
+        let start = register.address as u32;
+        let end = start + register.data_type.register_count() as u32;
+        register.table == self.table
+            && start >= self.address as u32
+            && end <= self.address as u32 + self.count as u32
+    }
+}
+
+/// Group the registers into as few read requests as possible
+fn read_blocks(registers: &[ModbusRegister]) -> Vec<ReadBlock> {
+    let mut ranges: Vec<_> = registers
+        .iter()
+        .map(|r| {
+            let start = r.address as u32;
+            (r.table, start, start + r.data_type.register_count() as u32)
+        })
+        .collect();
+    ranges.sort();
+
+    let mut blocks: Vec<ReadBlock> = Vec::new();
+    for (table, start, end) in ranges {
+        if let Some(block) = blocks.last_mut() {
+            let block_start = block.address as u32;
+            let block_end = block_start + block.count as u32;
+            if block.table == table
+                && start <= block_end
+                && end - block_start <= MAX_REGISTERS_PER_READ
+            {
+                block.count = (end.max(block_end) - block_start) as u16;
+                continue;
+            }
+        }
+        blocks.push(ReadBlock {
+            table,
+            address: start as u16,
+            count: (end - start) as u16,
+        });
+    }
+    blocks
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ModbusError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("No response received within {0:?}")]
+    Timeout(Duration),
+
+    #[error("The server responded with exception code {0}")]
+    Exception(u8),
+
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+
+    #[error("Cannot open serial port {0}")]
+    SerialPort(String),
+}
+
+/// A connection to a Modbus server, established on demand and kept open across reads
+pub struct ModbusClient {
+    server: ModbusServer,
+    unit: u8,
+    connection: Option<Connection>,
+    transaction_id: u16,
+}
+
+enum Connection {
+    Tcp(TcpStream),
+    Rtu(SerialStream),
+}
+
+impl ModbusClient {
+    pub fn new(server: ModbusServer, unit: u8) -> Self {
+        ModbusClient {
+            server,
+            unit,
+            connection: None,
+            transaction_id: 0,
+        }
+    }
+
+    /// Read the given registers, returning their decoded values indexed by register name
+    pub async fn read(
+        &mut self,
+        registers: &[ModbusRegister],
+    ) -> Result<Map<String, Value>, ModbusError> {
+        let mut blocks = Vec::new();
+        for block in read_blocks(registers) {
+            let words = self.read_block(&block).await?;
+            blocks.push((block, words));
+        }
+
+        let mut values = Map::new();
+        for register in registers {
+            let Some((block, words)) = blocks.iter().find(|(block, _)| block.contains(register))
+            else {
+                continue;
+            };
+            let offset = (register.address - block.address) as usize;
+            let count = register.data_type.register_count() as usize;
+            values.insert(
+                register.name.clone(),
+                register.decode(&words[offset..offset + count]),
+            );
+        }
+        Ok(values)
+    }
+
+    async fn read_block(&mut self, block: &ReadBlock) -> Result<Vec<u16>, ModbusError> {
+        let result = tokio::time::timeout(RESPONSE_TIMEOUT, self.request(block))
+            .await
+            .unwrap_or(Err(ModbusError::Timeout(RESPONSE_TIMEOUT)));
+        if result.is_err() {
+            // The connection is possibly out of sync with the server
+            self.connection = None;
+        }
+        result
+    }
+
+    async fn request(&mut self, block: &ReadBlock) -> Result<Vec<u16>, ModbusError> {
+        let function = block.table.function_code();
+        let mut pdu = vec![function];
+        pdu.extend_from_slice(&block.address.to_be_bytes());
+        pdu.extend_from_slice(&block.count.to_be_bytes());
+
+        self.transaction_id = self.transaction_id.wrapping_add(1);
+        let transaction_id = self.transaction_id;
+        let unit = self.unit;
+        let response = match self.connection().await? {
+            Connection::Tcp(stream) => tcp_request(stream, transaction_id, unit, &pdu).await?,
+            Connection::Rtu(port) => rtu_request(port, unit, &pdu).await?,
+        };
+
+        parse_read_response(function, block.count, &response)
+    }
+
+    async fn connection(&mut self) -> Result<&mut Connection, ModbusError> {
+        if self.connection.is_none() {
+            let connection = match &self.server {
+                ModbusServer::Tcp { host, port } => {
+                    Connection::Tcp(TcpStream::connect((host.as_str(), *port)).await?)
+                }
+                ModbusServer::Rtu {
+                    device,
+                    baud_rate,
+                    parity,
+                } => Connection::Rtu(open_serial_port(device, *baud_rate, *parity)?),
+            };
+            self.connection = Some(connection);
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+}
+
+/// Open a serial port using 8 data bits and 1 stop bit, as specified for Modbus RTU
+fn open_serial_port(
+    device: &Utf8Path,
+    baud_rate: u32,
+    parity: SerialParity,
+) -> Result<SerialStream, ModbusError> {
+    tokio_serial::new(device.as_str(), baud_rate)
+        .data_bits(DataBits::Eight)
+        .parity(parity.into())
+        .stop_bits(StopBits::One)
+        .timeout(RESPONSE_TIMEOUT)
+        .open_native_async()
+        .map_err(|err| ModbusError::SerialPort(format!("{device}: {err}")))
+}
+
+/// Send a request over Modbus TCP, returning the response PDU
+async fn tcp_request<S>(
+    stream: &mut S,
+    transaction_id: u16,
+    unit: u8,
+    pdu: &[u8],
+) -> Result<Vec<u8>, ModbusError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut frame = Vec::with_capacity(7 + pdu.len());
+    frame.extend_from_slice(&transaction_id.to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes());
+    frame.push(unit);
+    frame.extend_from_slice(pdu);
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+
+    let mut header = [0u8; 7];
+    stream.read_exact(&mut header).await?;
+    let response_id = u16::from_be_bytes([header[0], header[1]]);
+    let protocol_id = u16::from_be_bytes([header[2], header[3]]);
+    let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+    if response_id != transaction_id || protocol_id != 0 || length < 2 {
+        return Err(ModbusError::InvalidResponse(format!(
+            "unexpected MBAP header {header:02x?}"
+        )));
+    }
+
+    let mut response = vec![0u8; length - 1];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
+
+/// Send a request over Modbus RTU, returning the response PDU
+async fn rtu_request<S>(port: &mut S, unit: u8, pdu: &[u8]) -> Result<Vec<u8>, ModbusError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut frame = Vec::with_capacity(3 + pdu.len());
+    frame.push(unit);
+    frame.extend_from_slice(pdu);
+    frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+    port.write_all(&frame).await?;
+    port.flush().await?;
+
+    // Read the unit and function code, then the remaining bytes given the kind of response
+    let mut response = vec![0u8; 3];
+    port.read_exact(&mut response).await?;
+    let remaining = if response[1] & 0x80 != 0 {
+        2
+    } else {
+        response[2] as usize + 2
+    };
+    let mut tail = vec![0u8; remaining];
+    port.read_exact(&mut tail).await?;
+    response.extend_from_slice(&tail);
+
+    let (content, crc) = response.split_at(response.len() - 2);
+    if crc16(content).to_le_bytes() != crc {
+        return Err(ModbusError::InvalidResponse("CRC mismatch".to_string()));
+    }
+    if content[0] != unit {
+        return Err(ModbusError::InvalidResponse(format!(
+            "response from unit {} instead of {unit}",
+            content[0]
+        )));
+    }
+    Ok(content[1..].to_vec())
+}
+
+fn parse_read_response(function: u8, count: u16, pdu: &[u8]) -> Result<Vec<u16>, ModbusError> {
+    match pdu {
+        [code, exception, ..] if *code == function | 0x80 => {
+            Err(ModbusError::Exception(*exception))
+        }
+        [code, byte_count, data @ ..] if *code == function => {
+            let expected = count as usize * 2;
+            if *byte_count as usize != expected || data.len() != expected {
+                return Err(ModbusError::InvalidResponse(format!(
+                    "expected {expected} bytes of register data, got {}",
+                    data.len()
+                )));
+            }
+            Ok(data
+                .chunks_exact(2)
+                .map(|word| u16::from_be_bytes([word[0], word[1]]))
+                .collect())
+        }
+        _ => Err(ModbusError::InvalidResponse(format!(
+            "unexpected PDU {pdu:02x?}"
+        ))),
+    }
+}
+
+/// The CRC-16 used by Modbus RTU frames
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for byte in bytes {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0xA001;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn computes_rtu_crc() {
+        let frame = [0x01, 0x03, 0x00, 0x00, 0x00, 0x0A];
+        assert_eq!(crc16(&frame).to_le_bytes(), [0xC5, 0xCD]);
+    }
+
+    #[test]
+    fn decodes_signed_and_unsigned_integers() {
+        assert_eq!(register(DataType::Int16).decode(&[0xFFFE]), json!(-2));
+        assert_eq!(register(DataType::Uint16).decode(&[0xFFFE]), json!(65534));
+        assert_eq!(
+            register(DataType::Uint32).decode(&[0x0001, 0x0002]),
+            json!(65538)
+        );
+        assert_eq!(
+            register(DataType::Int32).decode(&[0xFFFF, 0xFFFF]),
+            json!(-1)
+        );
+    }
+
+    #[test]
+    fn decodes_values_according_to_byte_and_word_order() {
+        let mut uint32 = register(DataType::Uint32);
+        uint32.word_order = Endianness::Little;
+        assert_eq!(uint32.decode(&[0x0002, 0x0001]), json!(65538));
+
+        let mut uint16 = register(DataType::Uint16);
+        uint16.byte_order = Endianness::Little;
+        assert_eq!(uint16.decode(&[0x0100]), json!(1));
+
+        // 21.5 as a float32 is 0x41AC0000
+        let mut float32 = register(DataType::Float32);
+        assert_eq!(float32.decode(&[0x41AC, 0x0000]), json!(21.5));
+        float32.word_order = Endianness::Little;
+        assert_eq!(float32.decode(&[0x0000, 0x41AC]), json!(21.5));
+        float32.byte_order = Endianness::Little;
+        assert_eq!(float32.decode(&[0x0000, 0xAC41]), json!(21.5));
+    }
+
+    #[test]
+    fn scales_raw_values() {
+        let mut temperature = register(DataType::Int16);
+        temperature.scale = Some(Number::from_f64(0.5).unwrap());
+        assert_eq!(temperature.decode(&[0xFFFB]), json!(-2.5));
+    }
+
+    #[test]
+    fn groups_consecutive_registers_into_a_single_read() {
+        let registers = vec![
+            at(RegisterTable::Holding, 10, DataType::Float32),
+            at(RegisterTable::Holding, 0, DataType::Uint16),
+            at(RegisterTable::Holding, 1, DataType::Uint32),
+            at(RegisterTable::Input, 3, DataType::Int16),
+            at(RegisterTable::Holding, 200, DataType::Uint16),
+        ];
+
+        assert_eq!(
+            read_blocks(&registers),
+            vec![
+                ReadBlock {
+                    table: RegisterTable::Holding,
+                    address: 0,
+                    count: 3
+                },
+                ReadBlock {
+                    table: RegisterTable::Holding,
+                    address: 10,
+                    count: 2
+                },
+                ReadBlock {
+                    table: RegisterTable::Holding,
+                    address: 200,
+                    count: 1
+                },
+                ReadBlock {
+                    table: RegisterTable::Input,
+                    address: 3,
+                    count: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_exception_responses() {
+        assert!(matches!(
+            parse_read_response(0x03, 2, &[0x83, 0x02]),
+            Err(ModbusError::Exception(2))
+        ));
+        assert!(matches!(
+            parse_read_response(0x03, 2, &[0x03, 0x02, 0x00, 0x01]),
+            Err(ModbusError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reads_registers_from_a_modbus_tcp_server() {
+        let mut holding = [0u16; 16];
+        holding[0] = 0xFFFB;
+        holding[1] = 0x41AC;
+        holding[2] = 0x0000;
+        holding[8] = 0x0001;
+        holding[9] = 0x0002;
+        let port = spawn_simulator(holding).await;
+
+        let mut temperature = at(RegisterTable::Holding, 0, DataType::Int16);
+        temperature.name = "temperature".to_string();
+        temperature.scale = Some(Number::from_f64(0.1).unwrap());
+        let mut pressure = at(RegisterTable::Holding, 1, DataType::Float32);
+        pressure.name = "pressure".to_string();
+        let mut counter = at(RegisterTable::Holding, 8, DataType::Uint32);
+        counter.name = "counter".to_string();
+
+        let server = ModbusServer::Tcp {
+            host: "127.0.0.1".to_string(),
+            port,
+        };
+        let mut client = ModbusClient::new(server, 1);
+        let values = client
+            .read(&[temperature, pressure, counter])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Value::Object(values),
+            json!({"temperature": -0.5, "pressure": 21.5, "counter": 65538})
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_reads_beyond_the_server_registers() {
+        let port = spawn_simulator([0u16; 16]).await;
+        let server = ModbusServer::Tcp {
+            host: "127.0.0.1".to_string(),
+            port,
+        };
+        let mut client = ModbusClient::new(server, 1);
+
+        let result = client
+            .read(&[at(RegisterTable::Holding, 100, DataType::Uint16)])
+            .await;
+        assert!(matches!(result, Err(ModbusError::Exception(2))));
+    }
+
+    /// A Modbus TCP server exposing the given holding registers
+    async fn spawn_simulator(holding: [u16; 16]) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            loop {
+                let mut header = [0u8; 7];
+                if stream.read_exact(&mut header).await.is_err() {
+                    return;
+                }
+                let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+                let mut pdu = vec![0u8; length - 1];
+                stream.read_exact(&mut pdu).await.unwrap();
+
+                let address = u16::from_be_bytes([pdu[1], pdu[2]]) as usize;
+                let count = u16::from_be_bytes([pdu[3], pdu[4]]) as usize;
+                let response = match holding.get(address..address + count) {
+                    Some(words) if pdu[0] == 0x03 => {
+                        let mut response = vec![0x03, (count * 2) as u8];
+                        for word in words {
+                            response.extend_from_slice(&word.to_be_bytes());
+                        }
+                        response
+                    }
+                    _ => vec![pdu[0] | 0x80, 0x02],
+                };
+
+                let mut frame = header[0..4].to_vec();
+                frame.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+                frame.push(header[6]);
+                frame.extend_from_slice(&response);
+                stream.write_all(&frame).await.unwrap();
+            }
+        });
+        port
+    }
+
+    fn register(data_type: DataType) -> ModbusRegister {
+        at(RegisterTable::Holding, 0, data_type)
+    }
+
+    fn at(table: RegisterTable, address: u16, data_type: DataType) -> ModbusRegister {
+        ModbusRegister {
+            name: format!("{table:?}-{address}"),
+            address,
+            table,
+            data_type,
+            byte_order: Endianness::Big,
+            word_order: Endianness::Big,
+            scale: None,
+        }
+    }
+}
@@ -8,6 +8,7 @@ use crate::registry::FlowRegistryExt;
 use crate::stats::Counter;
 use crate::stats::StatsFilter;
 use crate::stats::StatsPublisher;
+use crate::wasm_config::WasmConfig;
 use crate::LoadError;
 use camino::Utf8Path;
 use camino::Utf8PathBuf;
@@ -43,6 +44,12 @@ impl<Registry: FlowRegistryExt + Send> MessageProcessor<Registry> {
         })
     }
 
+    /// Set the limits applied to the WebAssembly steps, before loading the flows
+    pub fn with_wasm_config(mut self, wasm_config: WasmConfig) -> Self {
+        self.js_runtime.set_wasm_config(wasm_config);
+        self
+    }
+
     pub fn context_handle(&self) -> FlowContextHandle {
         self.js_runtime.context_handle()
     }
@@ -77,6 +84,18 @@ impl<Registry: FlowRegistryExt + Send> MessageProcessor<Registry> {
         topics
     }
 
+    /// Update the context values to be persisted, as requested by the flows
+    pub fn update_context_persistence(&self) {
+        let persistence = self
+            .registry
+            .flows()
+            .map(|flow| flow.as_ref())
+            .filter(|flow| flow.context.is_enabled())
+            .map(|flow| (flow.source.to_string(), flow.context.clone()))
+            .collect();
+        self.context_handle().set_persistence(persistence);
+    }
+
     /// Get the next deadline for interval execution across all scripts
     /// Returns None if no scripts have intervals configured
     pub fn next_interval_deadline(&self) -> Option<tokio::time::Instant> {
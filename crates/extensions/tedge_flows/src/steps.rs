@@ -4,6 +4,8 @@ use crate::js_script::JsScript;
 use crate::js_value::JsonValue;
 use crate::next_deadline_after;
 use crate::transformers::Transformer;
+#[cfg(feature = "wasm")]
+use crate::wasm_step::WasmStep;
 use crate::FlowContextUpdate;
 use crate::FlowError;
 use crate::LoadError;
@@ -29,6 +31,8 @@ pub struct FlowStep {
 pub enum StepHandler {
     JsScript(JsScript, JsonValue),
     Transformer(String, Box<dyn Transformer>),
+    #[cfg(feature = "wasm")]
+    Wasm(WasmStep, JsonValue),
 }
 
 impl FlowStep {
@@ -60,6 +64,17 @@ impl FlowStep {
         }
     }
 
+    #[cfg(feature = "wasm")]
+    pub fn new_wasm(step: WasmStep) -> Self {
+        let config = JsonValue::default();
+        FlowStep {
+            handler: StepHandler::Wasm(step, config),
+            interval: Duration::ZERO,
+            next_execution: None,
+            started_at: None,
+        }
+    }
+
     pub fn with_config(mut self, config: Option<serde_json::Value>) -> Result<Self, ConfigError> {
         if let Some(config) = config {
             self.handler.set_config(JsonValue::from(config))?
@@ -71,6 +86,8 @@ impl FlowStep {
         let is_periodic = match &self.handler {
             StepHandler::JsScript(script, _) => script.is_periodic,
             StepHandler::Transformer(_, builtin) => builtin.is_periodic(),
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(step, _) => step.is_periodic,
         };
         if !is_periodic && interval.is_some() {
             tracing::warn!(target: "flows", "Script with no 'onInterval' function: {}; but configured with an 'interval' in {flow}", self.source());
@@ -88,6 +105,8 @@ impl FlowStep {
         let has_startup = match &self.handler {
             StepHandler::JsScript(script, _) => script.has_startup,
             StepHandler::Transformer(_, transformer) => transformer.has_startup(),
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(step, _) => step.has_startup,
         };
 
         if !has_startup {
@@ -103,6 +122,8 @@ impl FlowStep {
         match &self.handler {
             StepHandler::JsScript(script, _) => script.path.as_str(),
             StepHandler::Transformer(_, builtin) => builtin.name(),
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(step, _) => step.path.as_str(),
         }
     }
 
@@ -111,6 +132,8 @@ impl FlowStep {
         match &self.handler {
             StepHandler::JsScript(script, _) => Some(&script.path),
             StepHandler::Transformer(_, _) => None,
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(step, _) => Some(step.path()),
         }
     }
 
@@ -118,10 +141,24 @@ impl FlowStep {
         match &self.handler {
             StepHandler::JsScript(script, _) => &script.module_name,
             StepHandler::Transformer(instance_name, _) => instance_name,
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(step, _) => &step.module_name,
         }
     }
 
     pub async fn load_script(&mut self, js: &mut JsRuntime) -> Result<(), LoadError> {
+        #[cfg(feature = "wasm")]
+        if let StepHandler::Wasm(step, _) = &mut self.handler {
+            step.reload()?;
+
+            // after reload trigger onStartup again
+            if step.has_startup {
+                self.started_at = None;
+            } else {
+                self.started_at = Some(Instant::now());
+            }
+            self.init_next_execution();
+        }
         if let StepHandler::JsScript(script, _) = &mut self.handler {
             js.load_script(script).await?;
             // FIXME: there is bug here when the updated version adds an on_interval method
@@ -188,6 +225,8 @@ impl FlowStep {
             StepHandler::Transformer(_, builtin) => {
                 builtin.on_message(timestamp, message, &js.context_handle())
             }
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(step, config) => step.on_message(timestamp, message, config),
         }
     }
 
@@ -208,6 +247,8 @@ impl FlowStep {
             StepHandler::Transformer(_, builtin) => {
                 builtin.on_interval(timestamp, &js.context_handle())
             }
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(step, config) => step.on_interval(timestamp, config),
         }
     }
 
@@ -233,6 +274,8 @@ impl FlowStep {
             StepHandler::Transformer(_, builtin) => {
                 builtin.on_startup(timestamp, &js.context_handle())
             }
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(step, config) => step.on_startup(timestamp, config),
         };
 
         // after onStartup is finished, make sure it's not run again and schedule onInterval
@@ -250,6 +293,8 @@ impl FlowStep {
     ) -> Result<Vec<Message>, FlowError> {
         match &mut self.handler {
             StepHandler::JsScript(_, _) => Ok(vec![]),
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(_, _) => Ok(vec![]),
             StepHandler::Transformer(_, builtin) => {
                 builtin.on_context_update(timestamp, update, &js.context_handle())
             }
@@ -261,6 +306,8 @@ impl StepHandler {
     pub fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
         match self {
             StepHandler::JsScript(_, c) => *c = config,
+            #[cfg(feature = "wasm")]
+            StepHandler::Wasm(_, c) => *c = config,
             StepHandler::Transformer(_, builtin) => {
                 builtin.set_config(config)?;
             }
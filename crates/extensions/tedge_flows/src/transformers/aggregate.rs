@@ -0,0 +1,489 @@
+use crate::ConfigError;
+use crate::FlowContextHandle;
+use crate::FlowError;
+use crate::JsonValue;
+use crate::Message;
+use crate::Transformer;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::SystemTime;
+use tedge_utils::timestamp::TimeFormat;
+use time::OffsetDateTime;
+
+/// Compute statistics over the `te` measurements received during a time window.
+///
+/// Each numeric value of a measurement is a series, identified by the message topic,
+/// the measurement group if any, and the measurement name.
+/// For each series and each configured function, the aggregated measurement published
+/// at the end of a window holds a `<name>_<function>` value (e.g. `temperature_avg`).
+///
+/// - Windows are aligned on the processing time, with a window closed every `slide` period.
+///   When `slide` is not configured, windows are tumbling: `slide` equals `window`.
+/// - Aggregated measurements are published on the topic of the source measurements,
+///   with the end of the window as `time`.
+/// - The source measurements are consumed, and any message that is not a JSON object is forwarded unchanged.
+#[derive(Clone)]
+pub struct Aggregate {
+    window: Duration,
+    slide: Duration,
+    functions: Vec<Function>,
+
+    /// End time of the next window to be closed, if any sample has been received
+    next_window_end: Option<SystemTime>,
+
+    /// Samples per topic and series
+    samples: BTreeMap<String, BTreeMap<Series, VecDeque<(SystemTime, f64)>>>,
+}
+
+/// A measurement series of a topic: the measurement group, if any, and name
+type Series = (Option<String>, String);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Function {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Count,
+    Last,
+    StdDev,
+}
+
+impl Default for Aggregate {
+    fn default() -> Self {
+        Aggregate {
+            window: Duration::from_secs(60),
+            slide: Duration::from_secs(60),
+            functions: vec![Function::Min, Function::Max, Function::Avg],
+            next_window_end: None,
+            samples: BTreeMap::new(),
+        }
+    }
+}
+
+impl Transformer for Aggregate {
+    fn name(&self) -> &str {
+        "aggregate"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        if let Some(window) = config.string_property("window") {
+            self.window = parse_duration("window", window)?;
+        }
+        self.slide = match config.string_property("slide") {
+            Some(slide) => parse_duration("slide", slide)?,
+            None => self.window,
+        };
+        if self.slide > self.window {
+            return Err(ConfigError::IncorrectSetting(format!(
+                "Invalid slide: {} is longer than the window {}",
+                humantime::format_duration(self.slide),
+                humantime::format_duration(self.window)
+            )));
+        }
+        if let Some(functions) = config.strings_property("functions") {
+            self.functions = functions
+                .into_iter()
+                .map(Function::try_from)
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let Ok(Value::Object(payload)) = serde_json::from_slice(&message.payload) else {
+            return Ok(vec![message.clone()]);
+        };
+
+        let aggregated = self.close_windows(timestamp);
+
+        self.next_window_end
+            .get_or_insert_with(|| next_multiple(timestamp, self.slide));
+        let topic_samples = self.samples.entry(message.topic.clone()).or_default();
+        for (series, value) in numeric_values(payload) {
+            topic_samples
+                .entry(series)
+                .or_default()
+                .push_back((timestamp, value));
+        }
+        if topic_samples.is_empty() {
+            self.samples.remove(&message.topic);
+        }
+        if self.samples.is_empty() {
+            self.next_window_end = None;
+        }
+
+        Ok(aggregated)
+    }
+
+    fn is_periodic(&self) -> bool {
+        true
+    }
+
+    fn on_interval(
+        &mut self,
+        timestamp: SystemTime,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        Ok(self.close_windows(timestamp))
+    }
+}
+
+impl Aggregate {
+    /// Close all the windows ending before the given time
+    fn close_windows(&mut self, timestamp: SystemTime) -> Vec<Message> {
+        let mut messages = vec![];
+        while let Some(window_end) = self.next_window_end {
+            if window_end > timestamp {
+                break;
+            }
+            messages.extend(self.close_window(window_end));
+            self.next_window_end = if self.samples.is_empty() {
+                None
+            } else {
+                Some(window_end + self.slide)
+            };
+        }
+        messages
+    }
+
+    /// Publish the statistics of the window ending at the given time
+    /// and drop the samples which are not part of the next window
+    fn close_window(&mut self, window_end: SystemTime) -> Vec<Message> {
+        let window_start = window_end.checked_sub(self.window);
+        let next_window_start = (window_end + self.slide).checked_sub(self.window);
+        let is_in = |time: SystemTime, start: Option<SystemTime>, end: SystemTime| {
+            start.is_none_or(|start| start <= time) && time < end
+        };
+
+        let mut messages = vec![];
+        for (topic, topic_samples) in self.samples.iter_mut() {
+            let mut payload = Map::new();
+            for ((group, name), samples) in topic_samples.iter_mut() {
+                let values: Vec<f64> = samples
+                    .iter()
+                    .filter(|(time, _)| is_in(*time, window_start, window_end))
+                    .map(|(_, value)| *value)
+                    .collect();
+                if !values.is_empty() {
+                    let target = match group {
+                        None => Some(&mut payload),
+                        Some(group) => payload
+                            .entry(group.clone())
+                            .or_insert_with(|| Value::Object(Map::new()))
+                            .as_object_mut(),
+                    };
+                    if let Some(target) = target {
+                        for function in self.functions.iter() {
+                            if let Some(value) = function.apply(&values) {
+                                target.insert(format!("{name}_{}", function.name()), value.into());
+                            }
+                        }
+                    }
+                }
+                samples.retain(|(time, _)| {
+                    next_window_start.is_none_or(|start| start <= *time) || window_end <= *time
+                });
+            }
+            topic_samples.retain(|_, samples| !samples.is_empty());
+
+            if !payload.is_empty() {
+                if let Ok(time) = TimeFormat::Unix.to_json(OffsetDateTime::from(window_end)) {
+                    payload.insert("time".to_string(), time);
+                }
+                messages.push(Message::new(
+                    topic.clone(),
+                    Value::Object(payload).to_string(),
+                ));
+            }
+        }
+        self.samples.retain(|_, samples| !samples.is_empty());
+        messages
+    }
+}
+
+impl Function {
+    fn name(&self) -> &'static str {
+        match self {
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Avg => "avg",
+            Function::Sum => "sum",
+            Function::Count => "count",
+            Function::Last => "last",
+            Function::StdDev => "stddev",
+        }
+    }
+
+    /// Apply the function to a non-empty list of values
+    fn apply(&self, values: &[f64]) -> Option<f64> {
+        let count = values.len() as f64;
+        let sum = values.iter().sum::<f64>();
+        match self {
+            Function::Min => values.iter().copied().reduce(f64::min),
+            Function::Max => values.iter().copied().reduce(f64::max),
+            Function::Avg => Some(sum / count),
+            Function::Sum => Some(sum),
+            Function::Count => Some(count),
+            Function::Last => values.last().copied(),
+            Function::StdDev => {
+                let avg = sum / count;
+                let variance = values.iter().map(|v| (v - avg).powi(2)).sum::<f64>() / count;
+                Some(variance.sqrt())
+            }
+        }
+    }
+}
+
+impl TryFrom<&str> for Function {
+    type Error = ConfigError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "min" => Ok(Function::Min),
+            "max" => Ok(Function::Max),
+            "avg" => Ok(Function::Avg),
+            "sum" => Ok(Function::Sum),
+            "count" => Ok(Function::Count),
+            "last" => Ok(Function::Last),
+            "stddev" => Ok(Function::StdDev),
+            _ => Err(ConfigError::IncorrectSetting(format!(
+                "Invalid function: {value}, expecting one of min, max, avg, sum, count, last or stddev"
+            ))),
+        }
+    }
+}
+
+pub(super) fn parse_duration(setting: &str, value: &str) -> Result<Duration, ConfigError> {
+    match humantime::parse_duration(value) {
+        Ok(duration) if !duration.is_zero() => Ok(duration),
+        _ => Err(ConfigError::IncorrectSetting(format!(
+            "Invalid {setting}: not a duration: {value}"
+        ))),
+    }
+}
+
+/// The first multiple of the period, since the epoch, that is after the given time
+fn next_multiple(time: SystemTime, period: Duration) -> SystemTime {
+    let elapsed = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let period_millis = period.as_millis().max(1);
+    let next = (elapsed / period_millis + 1) * period_millis;
+    SystemTime::UNIX_EPOCH + Duration::from_millis(next as u64)
+}
+
+/// The numeric values of a measurement, ignoring the `time` and any non-numeric values
+fn numeric_values(payload: Map<String, Value>) -> Vec<(Series, f64)> {
+    let mut values = vec![];
+    for (key, value) in payload {
+        match value {
+            Value::Number(number) if key != "time" => {
+                if let Some(value) = number.as_f64() {
+                    values.push(((None, key), value));
+                }
+            }
+            Value::Object(group) => {
+                for (name, value) in group {
+                    if let Some(value) = value.as_f64() {
+                        values.push(((Some(key.clone()), name), value));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn statistics_are_published_at_the_end_of_a_tumbling_window() {
+        let context = FlowContextHandle::default();
+        let mut aggregate = aggregate(json!({
+            "window": "10s",
+            "functions": ["min", "max", "avg", "sum", "count", "last", "stddev"],
+        }));
+
+        let start = at(1000);
+        for (delay, temperature) in [(1, 20.0), (3, 24.0), (5, 22.0), (7, 26.0)] {
+            let message = measurement(json!({"temperature": temperature}));
+            assert!(aggregate
+                .on_message(start + secs(delay), &message, &context)
+                .unwrap()
+                .is_empty());
+        }
+
+        assert!(aggregate
+            .on_interval(start + secs(9), &context)
+            .unwrap()
+            .is_empty());
+
+        let messages = aggregate.on_interval(start + secs(10), &context).unwrap();
+        assert_eq!(
+            payloads(messages),
+            vec![json!({
+                "time": 1010.0,
+                "temperature_min": 20.0,
+                "temperature_max": 26.0,
+                "temperature_avg": 23.0,
+                "temperature_sum": 92.0,
+                "temperature_count": 4.0,
+                "temperature_last": 26.0,
+                "temperature_stddev": 5.0_f64.sqrt(),
+            })]
+        );
+
+        // The next window starts empty
+        assert!(aggregate
+            .on_interval(start + secs(20), &context)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn sliding_windows_overlap() {
+        let context = FlowContextHandle::default();
+        let mut aggregate = aggregate(json!({
+            "window": "10s",
+            "slide": "5s",
+            "functions": ["count", "avg"],
+        }));
+
+        let start = at(1000);
+        let mut messages = vec![];
+        for (delay, temperature) in [(1, 10.0), (6, 20.0), (11, 30.0)] {
+            let message = measurement(json!({"temperature": temperature}));
+            messages.extend(
+                aggregate
+                    .on_message(start + secs(delay), &message, &context)
+                    .unwrap(),
+            );
+        }
+
+        messages.extend(aggregate.on_interval(start + secs(15), &context).unwrap());
+        assert_eq!(
+            payloads(messages),
+            vec![
+                json!({"time": 1005.0, "temperature_count": 1.0, "temperature_avg": 10.0}),
+                json!({"time": 1010.0, "temperature_count": 2.0, "temperature_avg": 15.0}),
+                json!({"time": 1015.0, "temperature_count": 2.0, "temperature_avg": 25.0}),
+            ]
+        );
+    }
+
+    #[test]
+    fn each_series_is_aggregated_independently() {
+        let context = FlowContextHandle::default();
+        let mut aggregate = aggregate(json!({"window": "10s", "functions": ["max"]}));
+
+        let start = at(1000);
+        for payload in [
+            json!({"time": "2025-01-01T00:00:00Z", "temperature": 20.0, "env": {"humidity": 40}}),
+            json!({"temperature": 24.0, "env": {"humidity": 60}, "status": "ok"}),
+        ] {
+            aggregate
+                .on_message(start + secs(1), &measurement(payload), &context)
+                .unwrap();
+        }
+        let other = Message::new("te/device/child///m/env", r#"{"temperature": 5}"#);
+        aggregate
+            .on_message(start + secs(2), &other, &context)
+            .unwrap();
+
+        let messages = aggregate.on_interval(start + secs(10), &context).unwrap();
+        let topics: Vec<_> = messages.iter().map(|m| m.topic.clone()).collect();
+        assert_eq!(
+            topics,
+            vec!["te/device/child///m/env", "te/device/main///m/env"]
+        );
+        assert_eq!(
+            payloads(messages),
+            vec![
+                json!({"time": 1010.0, "temperature_max": 5.0}),
+                json!({"time": 1010.0, "temperature_max": 24.0, "env": {"humidity_max": 60.0}}),
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_are_closed_by_late_messages_when_no_interval_has_been_triggered() {
+        let context = FlowContextHandle::default();
+        let mut aggregate = aggregate(json!({"window": "10s", "functions": ["last"]}));
+
+        let start = at(1000);
+        let message = measurement(json!({"temperature": 20.0}));
+        aggregate
+            .on_message(start + secs(1), &message, &context)
+            .unwrap();
+
+        let message = measurement(json!({"temperature": 30.0}));
+        let messages = aggregate
+            .on_message(start + secs(12), &message, &context)
+            .unwrap();
+        assert_eq!(
+            payloads(messages),
+            vec![json!({"time": 1010.0, "temperature_last": 20.0})]
+        );
+    }
+
+    #[test]
+    fn non_json_messages_are_forwarded() {
+        let context = FlowContextHandle::default();
+        let mut aggregate = Aggregate::default();
+        let message = Message::new("te/device/main///m/env", "not a measurement");
+        assert_eq!(
+            aggregate.on_message(at(1000), &message, &context).unwrap(),
+            vec![message]
+        );
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        for config in [
+            json!({"window": "not a duration"}),
+            json!({"window": "10s", "slide": "1m"}),
+            json!({"functions": ["median"]}),
+        ] {
+            assert!(Aggregate::default().set_config(config.into()).is_err());
+        }
+    }
+
+    fn aggregate(config: Value) -> Aggregate {
+        let mut aggregate = Aggregate::default();
+        aggregate.set_config(config.into()).unwrap();
+        aggregate
+    }
+
+    fn measurement(payload: Value) -> Message {
+        Message::new("te/device/main///m/env", payload.to_string())
+    }
+
+    fn payloads(messages: Vec<Message>) -> Vec<Value> {
+        messages
+            .into_iter()
+            .map(|message| serde_json::from_slice(&message.payload).unwrap())
+            .collect()
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+}
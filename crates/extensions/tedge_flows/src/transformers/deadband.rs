@@ -0,0 +1,283 @@
+use crate::transformers::aggregate::parse_duration;
+use crate::ConfigError;
+use crate::FlowContextHandle;
+use crate::FlowError;
+use crate::JsonValue;
+use crate::Message;
+use crate::Transformer;
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::SystemTime;
+
+/// Report `te` measurement values only when they have significantly changed.
+///
+/// Each value of a measurement is a series, identified by the message topic,
+/// the measurement group if any, and the measurement name.
+/// A numeric value is reported when it differs from the last reported value of its series
+/// by more than the `absolute` threshold or by more than `percent` of the last reported value.
+/// With no threshold, a value is reported on any change.
+///
+/// - Non-numeric values are reported when changed.
+/// - With a `max_interval`, a value is reported anyway when the last report of its series is older.
+/// - The values which are not reported are removed from the measurement,
+///   and the measurement is dropped when no value is left, except its `time`.
+/// - Any message that is not a JSON object is forwarded unchanged.
+#[derive(Clone, Default)]
+pub struct Deadband {
+    absolute: Option<f64>,
+    percent: Option<f64>,
+    max_interval: Option<Duration>,
+
+    /// The last reported value and time, per topic and series
+    reported: HashMap<(String, Option<String>, String), (Value, SystemTime)>,
+}
+
+impl Transformer for Deadband {
+    fn name(&self) -> &str {
+        "deadband"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        self.absolute = threshold(&config, "absolute")?;
+        self.percent = threshold(&config, "percent")?;
+        self.max_interval = config
+            .string_property("max_interval")
+            .map(|max_interval| parse_duration("max_interval", max_interval))
+            .transpose()?;
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let Ok(Value::Object(payload)) = serde_json::from_slice(&message.payload) else {
+            return Ok(vec![message.clone()]);
+        };
+
+        let mut reported = Map::new();
+        let mut time = None;
+        for (key, value) in payload {
+            match value {
+                Value::Object(group) => {
+                    let group: Map<String, Value> = group
+                        .into_iter()
+                        .filter(|(name, value)| {
+                            self.is_reported(&message.topic, Some(&key), name, value, timestamp)
+                        })
+                        .collect();
+                    if !group.is_empty() {
+                        reported.insert(key, Value::Object(group));
+                    }
+                }
+                value if key == "time" => time = Some(value),
+                value => {
+                    if self.is_reported(&message.topic, None, &key, &value, timestamp) {
+                        reported.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        if reported.is_empty() {
+            return Ok(vec![]);
+        }
+        if let Some(time) = time {
+            reported.insert("time".to_string(), time);
+        }
+        Ok(vec![Message {
+            payload: Value::Object(reported).to_string().into_bytes(),
+            ..message.clone()
+        }])
+    }
+}
+
+impl Deadband {
+    /// Check if a value has to be reported, recording it as the last reported value if so
+    fn is_reported(
+        &mut self,
+        topic: &str,
+        group: Option<&str>,
+        name: &str,
+        value: &Value,
+        timestamp: SystemTime,
+    ) -> bool {
+        let series = (topic.to_owned(), group.map(str::to_owned), name.to_owned());
+        let is_reported = match self.reported.get(&series) {
+            None => true,
+            Some((last_value, last_time)) => {
+                self.max_interval.is_some_and(|max_interval| {
+                    timestamp
+                        .duration_since(*last_time)
+                        .is_ok_and(|elapsed| elapsed >= max_interval)
+                }) || self.has_changed(last_value, value)
+            }
+        };
+        if is_reported {
+            self.reported.insert(series, (value.clone(), timestamp));
+        }
+        is_reported
+    }
+
+    fn has_changed(&self, last_value: &Value, value: &Value) -> bool {
+        let (Some(last_value), Some(value)) = (last_value.as_f64(), value.as_f64()) else {
+            return last_value != value;
+        };
+        let delta = (value - last_value).abs();
+        match (self.absolute, self.percent) {
+            (None, None) => delta > 0.0,
+            (absolute, percent) => {
+                absolute.is_some_and(|absolute| delta > absolute)
+                    || percent.is_some_and(|percent| delta > last_value.abs() * percent / 100.0)
+            }
+        }
+    }
+}
+
+fn threshold(config: &JsonValue, setting: &str) -> Result<Option<f64>, ConfigError> {
+    match config.number_property(setting).map(|n| n.as_f64()) {
+        None => Ok(None),
+        Some(Some(threshold)) if threshold >= 0.0 => Ok(Some(threshold)),
+        Some(_) => Err(ConfigError::IncorrectSetting(format!(
+            "Invalid {setting}: expecting a positive number"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn values_are_reported_only_when_changed_by_more_than_the_absolute_threshold() {
+        let mut deadband = deadband(json!({"absolute": 0.5}));
+
+        assert_eq!(
+            filter(&mut deadband, 0, json!({"temperature": 20.0})),
+            Some(json!({"temperature": 20.0}))
+        );
+        assert_eq!(filter(&mut deadband, 1, json!({"temperature": 20.4})), None);
+        assert_eq!(filter(&mut deadband, 2, json!({"temperature": 19.6})), None);
+        assert_eq!(
+            filter(&mut deadband, 3, json!({"temperature": 20.6})),
+            Some(json!({"temperature": 20.6}))
+        );
+        // The threshold applies to the last reported value
+        assert_eq!(filter(&mut deadband, 4, json!({"temperature": 20.2})), None);
+    }
+
+    #[test]
+    fn values_are_reported_only_when_changed_by_more_than_the_percentage_threshold() {
+        let mut deadband = deadband(json!({"percent": 10}));
+
+        assert!(filter(&mut deadband, 0, json!({"pressure": 1000})).is_some());
+        assert_eq!(filter(&mut deadband, 1, json!({"pressure": 1090})), None);
+        assert_eq!(filter(&mut deadband, 2, json!({"pressure": 901})), None);
+        assert_eq!(
+            filter(&mut deadband, 3, json!({"pressure": 1101})),
+            Some(json!({"pressure": 1101}))
+        );
+    }
+
+    #[test]
+    fn without_threshold_values_are_reported_on_change() {
+        let mut deadband = Deadband::default();
+
+        assert!(filter(&mut deadband, 0, json!({"speed": 3, "state": "on"})).is_some());
+        assert_eq!(
+            filter(&mut deadband, 1, json!({"speed": 3, "state": "on"})),
+            None
+        );
+        assert_eq!(
+            filter(&mut deadband, 2, json!({"speed": 3, "state": "off"})),
+            Some(json!({"state": "off"}))
+        );
+    }
+
+    #[test]
+    fn each_series_is_filtered_independently() {
+        let mut deadband = deadband(json!({"absolute": 1}));
+
+        assert!(filter(
+            &mut deadband,
+            0,
+            json!({"time": "2025-01-01T00:00:00Z", "temperature": 20, "env": {"humidity": 40, "pressure": 1000}})
+        )
+        .is_some());
+        assert_eq!(
+            filter(
+                &mut deadband,
+                1,
+                json!({"time": "2025-01-01T00:00:01Z", "temperature": 20.5, "env": {"humidity": 45, "pressure": 1000}})
+            ),
+            Some(json!({"time": "2025-01-01T00:00:01Z", "env": {"humidity": 45}}))
+        );
+        assert_eq!(
+            filter(
+                &mut deadband,
+                2,
+                json!({"time": "2025-01-01T00:00:02Z", "temperature": 20.5})
+            ),
+            None
+        );
+
+        // Series are distinct across topics
+        let message = Message::new("te/device/child///m/env", r#"{"temperature":20.5}"#);
+        assert_eq!(
+            deadband
+                .on_message(at(3), &message, &FlowContextHandle::default())
+                .unwrap(),
+            vec![message]
+        );
+    }
+
+    #[test]
+    fn unchanged_values_are_reported_after_the_max_interval() {
+        let mut deadband = deadband(json!({"absolute": 1, "max_interval": "10s"}));
+
+        assert!(filter(&mut deadband, 0, json!({"temperature": 20})).is_some());
+        assert_eq!(filter(&mut deadband, 9, json!({"temperature": 20})), None);
+        assert_eq!(
+            filter(&mut deadband, 10, json!({"temperature": 20})),
+            Some(json!({"temperature": 20}))
+        );
+        assert_eq!(filter(&mut deadband, 11, json!({"temperature": 20})), None);
+    }
+
+    #[test]
+    fn invalid_configurations_are_rejected() {
+        for config in [
+            json!({"absolute": -1}),
+            json!({"percent": -5}),
+            json!({"max_interval": "sometimes"}),
+        ] {
+            assert!(Deadband::default().set_config(config.into()).is_err());
+        }
+    }
+
+    fn deadband(config: Value) -> Deadband {
+        let mut deadband = Deadband::default();
+        deadband.set_config(config.into()).unwrap();
+        deadband
+    }
+
+    fn filter(deadband: &mut Deadband, secs: u64, payload: Value) -> Option<Value> {
+        let message = Message::new("te/device/main///m/env", payload.to_string());
+        let mut messages = deadband
+            .on_message(at(secs), &message, &FlowContextHandle::default())
+            .unwrap();
+        assert!(messages.len() <= 1);
+        messages
+            .pop()
+            .map(|message| serde_json::from_slice(&message.payload).unwrap())
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+}
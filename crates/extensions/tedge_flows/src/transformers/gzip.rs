@@ -0,0 +1,174 @@
+use crate::config::ConfigError;
+use crate::js_value::JsonValue;
+use crate::transformers::Transformer;
+use crate::FlowContextHandle;
+use crate::FlowError;
+use crate::Message;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Read;
+use std::io::Write;
+use std::time::SystemTime;
+
+#[derive(Clone, Default)]
+pub struct GzipCompress {
+    level: Option<u32>,
+}
+
+impl Transformer for GzipCompress {
+    fn name(&self) -> &str {
+        "gzip-compress"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        self.level = match config.number_property("level").map(|n| n.as_u64()) {
+            None => None,
+            Some(Some(level)) if level <= 9 => Some(level as u32),
+            Some(_) => {
+                return Err(ConfigError::IncorrectSetting(format!(
+                    "The compression level of {} step must be between 0 and 9",
+                    self.name()
+                )))
+            }
+        };
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let level = self.level.map(Compression::new).unwrap_or_default();
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        let compressed = encoder
+            .write_all(&message.payload)
+            .and_then(|()| encoder.finish())
+            .map_err(|err| {
+                FlowError::UnsupportedMessage(format!("Failed to compress payload: {err}"))
+            })?;
+
+        let mut transformed_message = message.clone();
+        transformed_message.payload = compressed;
+        Ok(vec![transformed_message])
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct GzipDecompress {
+    max_size: Option<u64>,
+}
+
+impl Transformer for GzipDecompress {
+    fn name(&self) -> &str {
+        "gzip-decompress"
+    }
+
+    fn set_config(&mut self, config: JsonValue) -> Result<(), ConfigError> {
+        self.max_size = config.number_property("max_size").and_then(|n| n.as_u64());
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        // Read one byte more than the limit, to detect payloads expanding beyond it
+        let limit = self.max_size.map_or(u64::MAX, |max_size| max_size + 1);
+        let mut decompressed = Vec::new();
+        GzDecoder::new(message.payload.as_slice())
+            .take(limit)
+            .read_to_end(&mut decompressed)
+            .map_err(|err| {
+                FlowError::UnsupportedMessage(format!("Failed to decompress payload: {err}"))
+            })?;
+        if let Some(max_size) = self.max_size {
+            if decompressed.len() as u64 > max_size {
+                return Err(FlowError::UnsupportedMessage(format!(
+                    "Decompressed payload is too large >{max_size}"
+                )));
+            }
+        }
+
+        let mut transformed_message = message.clone();
+        transformed_message.payload = decompressed;
+        Ok(vec![transformed_message])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn decompressing_restores_the_compressed_payload() {
+        let message = Message::new("topic", r#"{"temperature":21.5}"#);
+        let mut compress = GzipCompress::default();
+        let mut decompress = GzipDecompress::default();
+
+        let compressed = forward(&mut compress, &message).unwrap();
+        assert_ne!(compressed[0].payload, message.payload);
+        assert_eq!(compressed[0].topic, message.topic);
+
+        let decompressed = forward(&mut decompress, &compressed[0]).unwrap();
+        assert_eq!(decompressed, vec![message]);
+    }
+
+    #[test]
+    fn compression_reduces_repetitive_payloads() {
+        let message = Message::new("topic", vec![b'x'; 4096]);
+        let mut compress = GzipCompress::default();
+        compress.set_config(json!({"level": 9}).into()).unwrap();
+
+        let compressed = forward(&mut compress, &message).unwrap();
+        assert!(compressed[0].payload.len() < 100);
+    }
+
+    #[test]
+    fn rejects_invalid_compression_level() {
+        let mut compress = GzipCompress::default();
+        assert!(compress.set_config(json!({"level": 10}).into()).is_err());
+    }
+
+    #[test]
+    fn rejects_payloads_which_are_not_compressed() {
+        let message = Message::new("topic", "not gzip");
+        let mut decompress = GzipDecompress::default();
+
+        assert!(matches!(
+            forward(&mut decompress, &message),
+            Err(FlowError::UnsupportedMessage(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_payloads_decompressing_beyond_the_limit() {
+        let message = Message::new("topic", vec![b'x'; 4096]);
+        let compressed = forward(&mut GzipCompress::default(), &message).unwrap();
+        let mut decompress = GzipDecompress::default();
+        decompress
+            .set_config(json!({"max_size": 1024}).into())
+            .unwrap();
+
+        assert!(matches!(
+            forward(&mut decompress, &compressed[0]),
+            Err(FlowError::UnsupportedMessage(_))
+        ));
+    }
+
+    fn forward(
+        transformer: &mut impl Transformer,
+        message: &Message,
+    ) -> Result<Vec<Message>, FlowError> {
+        transformer.on_message(
+            SystemTime::UNIX_EPOCH,
+            message,
+            &FlowContextHandle::default(),
+        )
+    }
+}
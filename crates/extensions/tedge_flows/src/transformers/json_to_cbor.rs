@@ -0,0 +1,84 @@
+use crate::config::ConfigError;
+use crate::js_value::JsonValue;
+use crate::transformers::Transformer;
+use crate::FlowContextHandle;
+use crate::FlowError;
+use crate::Message;
+use std::time::SystemTime;
+
+#[derive(Clone, Default)]
+pub struct JsonToCbor;
+
+impl Transformer for JsonToCbor {
+    fn name(&self) -> &str {
+        "json-to-cbor"
+    }
+
+    fn set_config(&mut self, _config: JsonValue) -> Result<(), ConfigError> {
+        Ok(())
+    }
+
+    fn on_message(
+        &mut self,
+        _timestamp: SystemTime,
+        message: &Message,
+        _context: &FlowContextHandle,
+    ) -> Result<Vec<Message>, FlowError> {
+        let json: serde_json::Value = serde_json::from_slice(&message.payload).map_err(|err| {
+            FlowError::UnsupportedMessage(format!("Payload is not valid JSON: {err}"))
+        })?;
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&json, &mut cbor).map_err(|err| {
+            FlowError::UnsupportedMessage(format!("Failed to encode payload as CBOR: {err}"))
+        })?;
+
+        let mut transformed_message = message.clone();
+        transformed_message.payload = cbor;
+        Ok(vec![transformed_message])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn encodes_json_payload_as_cbor() {
+        let message = Message::new("topic", r#"{"temperature":21.5,"unit":"C"}"#);
+
+        let output = forward(&message).unwrap();
+
+        let decoded: serde_json::Value =
+            ciborium::from_reader(output[0].payload.as_slice()).unwrap();
+        assert_eq!(decoded, json!({"temperature": 21.5, "unit": "C"}));
+        assert_eq!(output[0].topic, message.topic);
+    }
+
+    #[test]
+    fn cbor_is_more_compact_than_json() {
+        let message = Message::new("topic", r#"{"values":[1,2,3,4,5,6,7,8,9,10]}"#);
+
+        let output = forward(&message).unwrap();
+
+        assert!(output[0].payload.len() < message.payload.len());
+    }
+
+    #[test]
+    fn rejects_payloads_which_are_not_json() {
+        let message = Message::new("topic", "not json");
+
+        assert!(matches!(
+            forward(&message),
+            Err(FlowError::UnsupportedMessage(_))
+        ));
+    }
+
+    fn forward(message: &Message) -> Result<Vec<Message>, FlowError> {
+        JsonToCbor.on_message(
+            SystemTime::UNIX_EPOCH,
+            message,
+            &FlowContextHandle::default(),
+        )
+    }
+}
@@ -9,8 +9,12 @@ use std::collections::HashMap;
 use std::time::SystemTime;
 
 mod add_timestamp;
+mod aggregate;
+mod deadband;
 mod group_measurements;
+mod gzip;
 mod ignore_topics;
+mod json_to_cbor;
 mod limit_payload_size;
 mod set_topic;
 mod skip_mosquitto_health_status;
@@ -82,7 +86,12 @@ impl Default for BuiltinTransformers {
             transformers: HashMap::default(),
         };
         transformers.register(add_timestamp::AddTimestamp::default());
+        transformers.register(aggregate::Aggregate::default());
+        transformers.register(deadband::Deadband::default());
         transformers.register(group_measurements::GroupMeasurements::default());
+        transformers.register(gzip::GzipCompress::default());
+        transformers.register(gzip::GzipDecompress::default());
+        transformers.register(json_to_cbor::JsonToCbor);
         transformers.register(limit_payload_size::LimitPayloadSize::default());
         transformers.register(ignore_topics::IgnoreTopics::default());
         transformers.register(set_topic::SetTopic::default());
@@ -110,6 +119,51 @@ impl BuiltinTransformers {
     }
 }
 
+/// A sequence of builtin transformers applied to messages outside of any flow
+///
+/// Contrary to the steps of a flow, these transformers are not scheduled:
+/// they are only given the messages to transform, and share a context of their own.
+pub struct TransformerChain {
+    steps: Vec<Box<dyn Transformer>>,
+    context: FlowContextHandle,
+}
+
+impl TransformerChain {
+    /// Instantiates and configures the given builtin transformers, in order
+    pub fn try_new<'a>(
+        transformers: &BuiltinTransformers,
+        steps: impl IntoIterator<Item = (&'a str, JsonValue)>,
+    ) -> Result<Self, ConfigError> {
+        let mut chain = Vec::new();
+        for (name, config) in steps {
+            let mut step = transformers.new_instance(name)?;
+            step.set_config(config)?;
+            chain.push(step);
+        }
+        Ok(TransformerChain {
+            steps: chain,
+            context: FlowContextHandle::default(),
+        })
+    }
+
+    /// Transform a message into zero, one or more messages, applying the steps in turn
+    pub fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: Message,
+    ) -> Result<Vec<Message>, FlowError> {
+        let mut messages = vec![message];
+        for step in self.steps.iter_mut() {
+            let mut transformed_messages = vec![];
+            for message in messages.iter() {
+                transformed_messages.extend(step.on_message(timestamp, message, &self.context)?);
+            }
+            messages = transformed_messages;
+        }
+        Ok(messages)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -331,6 +385,42 @@ builtin = "onstartup-builtin"
         );
     }
 
+    #[test]
+    fn chaining_builtin_transformers() {
+        let transformers = BuiltinTransformers::new();
+        let mut chain = TransformerChain::try_new(
+            &transformers,
+            [
+                (
+                    "limit-payload-size",
+                    json!({"max_size": 64, "discard": true}).into(),
+                ),
+                ("json-to-cbor", JsonValue::default()),
+                ("gzip-compress", JsonValue::default()),
+            ],
+        )
+        .unwrap();
+        let datetime = SystemTime::UNIX_EPOCH;
+
+        let input = Message::new("te/device/main///m/", r#"{"temperature":21.5}"#);
+        let output = chain.on_message(datetime, input).unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].topic, "te/device/main///m/");
+        assert_ne!(output[0].payload, br#"{"temperature":21.5}"#);
+
+        let oversized = Message::new("te/device/main///m/", vec![b' '; 128]);
+        assert_eq!(chain.on_message(datetime, oversized).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn chaining_unknown_transformer() {
+        let transformers = BuiltinTransformers::new();
+        let error = TransformerChain::try_new(&transformers, [("unknown", JsonValue::default())])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Builtin transformer not found: unknown");
+    }
+
     async fn step_instance(
         transformers: &BuiltinTransformers,
         config: &str,
@@ -0,0 +1,18 @@
+/// The limits applied to the WebAssembly steps
+#[derive(Clone, Copy, Debug)]
+pub struct WasmConfig {
+    /// The maximum number of bytes of memory allocated to a step instance
+    pub memory_size: usize,
+
+    /// The fuel given to a step for each call, bounding the number of instructions executed
+    pub fuel: u64,
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        WasmConfig {
+            memory_size: 16 * 1024 * 1024,
+            fuel: 100_000_000,
+        }
+    }
+}
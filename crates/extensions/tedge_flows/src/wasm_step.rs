@@ -0,0 +1,447 @@
+use crate::flow::FlowError;
+use crate::flow::Message;
+use crate::js_lib::kv_store::FlowContext;
+use crate::js_lib::kv_store::FlowContextHandle;
+use crate::js_value::JsonValue;
+use crate::wasm_config::WasmConfig;
+use crate::LoadError;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::SystemTime;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+use wasmtime::component::Component;
+use wasmtime::component::ComponentType;
+use wasmtime::component::Instance;
+use wasmtime::component::Lift;
+use wasmtime::component::Linker;
+use wasmtime::component::Lower;
+use wasmtime::component::TypedFunc;
+use wasmtime::Engine;
+use wasmtime::Store;
+use wasmtime::StoreLimits;
+use wasmtime::StoreLimitsBuilder;
+
+type StepResult = (Result<Vec<WasmMessage>, String>,);
+type OnMessage = TypedFunc<(u64, WasmMessage, String), StepResult>;
+type OnTime = TypedFunc<(u64, String), StepResult>;
+
+/// A flow step implemented by a WebAssembly component
+///
+/// The component has to implement the `step` world defined by `wit/flows.wit`.
+/// Each step is given its own instance, with limited memory,
+/// and a limited amount of fuel for each call, bounding the number of instructions executed.
+pub struct WasmStep {
+    pub module_name: String,
+    pub flow: Utf8PathBuf,
+    pub path: Utf8PathBuf,
+    pub is_periodic: bool,
+    pub has_startup: bool,
+    limits: WasmConfig,
+    component: Component,
+    store: Store<WasmState>,
+    on_message: OnMessage,
+    on_interval: Option<OnTime>,
+    on_startup: Option<OnTime>,
+}
+
+/// The state of a component instance, giving access to the flow context
+struct WasmState {
+    context: FlowContextHandle,
+    flow: String,
+    step: String,
+    limits: StoreLimits,
+}
+
+#[derive(ComponentType, Lift, Lower)]
+#[component(record)]
+struct WasmMessage {
+    topic: String,
+    payload: Vec<u8>,
+    timestamp: Option<u64>,
+}
+
+#[derive(ComponentType, Lift, Lower, Clone, Copy)]
+#[component(enum)]
+#[repr(u8)]
+#[allow(dead_code)] // The variants are only built when lifting the arguments of the host functions
+enum Scope {
+    #[component(name = "mapper")]
+    Mapper,
+    #[component(name = "flow")]
+    Flow,
+    #[component(name = "step")]
+    Step,
+}
+
+impl WasmStep {
+    /// Compile and instantiate a WebAssembly component
+    pub fn load(
+        module_name: String,
+        flow: Utf8PathBuf,
+        path: Utf8PathBuf,
+        context: FlowContextHandle,
+        limits: WasmConfig,
+    ) -> Result<Self, LoadError> {
+        let component = Component::from_file(engine()?, &path)
+            .map_err(|err| LoadError::WasmError(err.context(format!("loading {path}"))))?;
+        let step = Self::instantiate(module_name, flow, path, component, context, limits)?;
+        info!(target: "flows", "Loaded WebAssembly step {}", step.path);
+        Ok(step)
+    }
+
+    fn instantiate(
+        module_name: String,
+        flow: Utf8PathBuf,
+        path: Utf8PathBuf,
+        component: Component,
+        context: FlowContextHandle,
+        limits: WasmConfig,
+    ) -> Result<Self, LoadError> {
+        let state = WasmState {
+            context,
+            flow: flow.to_string(),
+            step: module_name.clone(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(limits.memory_size)
+                .instances(1)
+                .build(),
+        };
+        let mut store = Store::new(engine()?, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(limits.fuel).map_err(LoadError::WasmError)?;
+
+        let instance = linker()?
+            .instantiate(&mut store, &component)
+            .map_err(|err| LoadError::WasmError(err.context(format!("instantiating {path}"))))?;
+        let on_message = instance
+            .get_typed_func(&mut store, "on-message")
+            .map_err(|err| LoadError::WasmError(err.context(format!("on-message in {path}"))))?;
+        let on_interval = Self::optional_func(&instance, &mut store, &path, "on-interval")?;
+        let on_startup = Self::optional_func(&instance, &mut store, &path, "on-startup")?;
+
+        Ok(WasmStep {
+            module_name,
+            flow,
+            path,
+            is_periodic: on_interval.is_some(),
+            has_startup: on_startup.is_some(),
+            limits,
+            component,
+            store,
+            on_message,
+            on_interval,
+            on_startup,
+        })
+    }
+
+    /// Compile and instantiate again the component, after an update of its source
+    ///
+    /// Any state held by the previous instance is lost.
+    pub fn reload(&mut self) -> Result<(), LoadError> {
+        *self = WasmStep::load(
+            self.module_name.clone(),
+            self.flow.clone(),
+            self.path.clone(),
+            self.store.data().context.clone(),
+            self.limits,
+        )?;
+        Ok(())
+    }
+
+    /// Instantiate again the component, after a trap
+    fn restart(&mut self) -> Result<(), LoadError> {
+        *self = WasmStep::instantiate(
+            self.module_name.clone(),
+            self.flow.clone(),
+            self.path.clone(),
+            self.component.clone(),
+            self.store.data().context.clone(),
+            self.limits,
+        )?;
+        Ok(())
+    }
+
+    fn optional_func(
+        instance: &Instance,
+        store: &mut Store<WasmState>,
+        path: &Utf8Path,
+        name: &str,
+    ) -> Result<Option<OnTime>, LoadError> {
+        if instance.get_func(&mut *store, name).is_none() {
+            return Ok(None);
+        }
+        let func = instance
+            .get_typed_func(&mut *store, name)
+            .map_err(|err| LoadError::WasmError(err.context(format!("{name} in {path}"))))?;
+        Ok(Some(func))
+    }
+
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+
+    /// Transform an input message into zero, one or more output messages
+    pub fn on_message(
+        &mut self,
+        timestamp: SystemTime,
+        message: &Message,
+        config: &JsonValue,
+    ) -> Result<Vec<Message>, FlowError> {
+        debug!(target: "flows", "{}: on-message({timestamp:?}, {message})", &self.module_name);
+        let input = WasmMessage {
+            topic: message.topic.clone(),
+            payload: message.payload.clone(),
+            timestamp: Some(epoch_millis(message.timestamp.unwrap_or(timestamp))),
+        };
+        let func = self.on_message;
+        self.call("on-message", |store| {
+            let output = func.call(&mut *store, (epoch_millis(timestamp), input, json(config)))?;
+            func.post_return(store)?;
+            Ok(output)
+        })
+    }
+
+    /// Trigger the on-interval function of the component, if any
+    pub fn on_interval(
+        &mut self,
+        timestamp: SystemTime,
+        config: &JsonValue,
+    ) -> Result<Vec<Message>, FlowError> {
+        let Some(func) = self.on_interval else {
+            return Ok(vec![]);
+        };
+        debug!(target: "flows", "{}: on-interval({timestamp:?})", self.module_name);
+        self.call("on-interval", |store| {
+            let output = func.call(&mut *store, (epoch_millis(timestamp), json(config)))?;
+            func.post_return(store)?;
+            Ok(output)
+        })
+    }
+
+    /// Trigger the on-startup function of the component, if any
+    pub fn on_startup(
+        &mut self,
+        timestamp: SystemTime,
+        config: &JsonValue,
+    ) -> Result<Vec<Message>, FlowError> {
+        let Some(func) = self.on_startup else {
+            return Ok(vec![]);
+        };
+        debug!(target: "flows", "{}: on-startup()", self.module_name);
+        self.call("on-startup", |store| {
+            let output = func.call(&mut *store, (epoch_millis(timestamp), json(config)))?;
+            func.post_return(store)?;
+            Ok(output)
+        })
+    }
+
+    /// Call a function of the component, after a refill of its fuel
+    ///
+    /// A component instance cannot be used after a trap (e.g. when out of fuel or memory),
+    /// hence a new instance is created to process the next messages.
+    fn call(
+        &mut self,
+        function: &str,
+        call: impl FnOnce(&mut Store<WasmState>) -> wasmtime::Result<StepResult>,
+    ) -> Result<Vec<Message>, FlowError> {
+        self.store
+            .set_fuel(self.limits.fuel)
+            .map_err(|err| error(&self.module_name, function, err))?;
+        match call(&mut self.store) {
+            Ok((Ok(messages),)) => Ok(messages.into_iter().map(Message::from).collect()),
+            Ok((Err(message),)) => Err(FlowError::UnsupportedMessage(message)),
+            Err(err) => {
+                let err = error(&self.module_name, function, err);
+                if let Err(reload_err) = self.restart() {
+                    warn!(target: "flows", "Cannot restart WebAssembly step {}: {reload_err}", self.path);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+impl WasmState {
+    fn context(&self, scope: Scope) -> FlowContext {
+        match scope {
+            Scope::Mapper => FlowContext::Mapper,
+            Scope::Flow => FlowContext::flow(&self.flow),
+            Scope::Step => FlowContext::script(&self.step),
+        }
+    }
+}
+
+impl From<WasmMessage> for Message {
+    fn from(message: WasmMessage) -> Self {
+        let mut output = Message::new(message.topic, message.payload);
+        output.timestamp = message
+            .timestamp
+            .map(|millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis));
+        output
+    }
+}
+
+/// The engine shared by all the WebAssembly steps
+fn engine() -> Result<&'static Engine, LoadError> {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    if let Some(engine) = ENGINE.get() {
+        return Ok(engine);
+    }
+    let mut config = wasmtime::Config::new();
+    config.wasm_component_model(true);
+    config.consume_fuel(true);
+    let engine = Engine::new(&config).map_err(LoadError::WasmError)?;
+    Ok(ENGINE.get_or_init(|| engine))
+}
+
+/// The host functions provided to the WebAssembly steps
+fn linker() -> Result<Linker<WasmState>, LoadError> {
+    let mut linker = Linker::new(engine()?);
+    let mut root = linker.root();
+    root.func_wrap("context-get", |store, (scope, key): (Scope, String)| {
+        let state: &WasmState = store.data();
+        let value = state.context.get(&state.context(scope), &key);
+        let value = match serde_json::Value::from(value) {
+            serde_json::Value::Null => None,
+            value => Some(value.to_string()),
+        };
+        Ok((value,))
+    })
+    .map_err(LoadError::WasmError)?;
+    root.func_wrap(
+        "context-set",
+        |store, (scope, key, value): (Scope, String, Option<String>)| {
+            let state: &WasmState = store.data();
+            let context = state.context(scope);
+            match value {
+                None => state.context.remove(&context, &key),
+                Some(value) => {
+                    let value: serde_json::Value = serde_json::from_str(&value)?;
+                    state.context.insert(&context, &key, value)
+                }
+            }
+            Ok(())
+        },
+    )
+    .map_err(LoadError::WasmError)?;
+    root.func_wrap("context-keys", |store, (scope,): (Scope,)| {
+        let state: &WasmState = store.data();
+        Ok((state.context.keys(&state.context(scope)),))
+    })
+    .map_err(LoadError::WasmError)?;
+    root.func_wrap("log", |store, (message,): (String,)| {
+        let state: &WasmState = store.data();
+        info!(target: "flows", "{}: {message}", state.step);
+        Ok(())
+    })
+    .map_err(LoadError::WasmError)?;
+    Ok(linker)
+}
+
+fn json(config: &JsonValue) -> String {
+    serde_json::Value::from(config.clone()).to_string()
+}
+
+fn epoch_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn error(module_name: &str, function: &str, err: wasmtime::Error) -> FlowError {
+    FlowError::StepFailed(format!("{module_name}: {function} failed: {err:#}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    /// A component returning the input message with the step config as payload,
+    /// and looping forever on interval, as compiled from `tests/data/echo_config.wat`
+    const ECHO_CONFIG: &[u8] = include_bytes!("../tests/data/echo_config.wasm");
+
+    #[test]
+    fn wasm_steps_are_given_the_messages_and_step_config() {
+        let dir = TempDir::new().unwrap();
+        let mut step = load_step(&dir, ECHO_CONFIG);
+        assert!(step.is_periodic);
+        assert!(!step.has_startup);
+
+        let config = JsonValue::from(json!({"threshold": 42}));
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1763050414123);
+        let input = Message::new("te/device/main///m/", "hello");
+        let output = step.on_message(time, &input, &config).unwrap();
+        assert_eq!(
+            output,
+            vec![Message::with_timestamp(
+                "te/device/main///m/",
+                r#"{"threshold":42}"#,
+                time
+            )]
+        );
+    }
+
+    #[test]
+    fn wasm_steps_are_restarted_when_out_of_fuel() {
+        let dir = TempDir::new().unwrap();
+        let mut step = load_step(&dir, ECHO_CONFIG);
+        let config = JsonValue::default();
+
+        let error = step.on_interval(SystemTime::now(), &config).unwrap_err();
+        assert!(matches!(error, FlowError::StepFailed(_)), "{error:?}");
+        assert!(error.to_string().contains("all fuel consumed"), "{error}");
+
+        let input = Message::new("te/device/main///m/", "hello");
+        assert_eq!(
+            step.on_message(SystemTime::now(), &input, &config)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn loading_an_invalid_component() {
+        let dir = TempDir::new().unwrap();
+        let path = Utf8Path::from_path(dir.path())
+            .unwrap()
+            .join("invalid.wasm");
+        std::fs::write(&path, "not a component").unwrap();
+        let error = WasmStep::load(
+            "flow|0|invalid.wasm".to_string(),
+            "flow.toml".into(),
+            path,
+            FlowContextHandle::default(),
+            limits(),
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("invalid.wasm"), "{error}");
+    }
+
+    fn load_step(dir: &TempDir, component: &[u8]) -> WasmStep {
+        let path = Utf8Path::from_path(dir.path()).unwrap().join("step.wasm");
+        std::fs::write(&path, component).unwrap();
+        WasmStep::load(
+            "flow.toml|0|step.wasm".to_string(),
+            "flow.toml".into(),
+            path,
+            FlowContextHandle::default(),
+            limits(),
+        )
+        .unwrap()
+    }
+
+    fn limits() -> WasmConfig {
+        WasmConfig {
+            memory_size: 1024 * 1024,
+            fuel: 1_000_000,
+        }
+    }
+}
@@ -0,0 +1,41 @@
+;; A component returning the input message with the step config as payload,
+;; and looping forever on interval
+(component
+  (core module $m
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 1024))
+    (func (export "cabi_realloc") (param i32 i32 i32 i32) (result i32)
+      (local $ptr i32)
+      (local.set $ptr (global.get $heap))
+      (global.set $heap (i32.add (local.get $ptr) (i32.add (local.get 3) (i32.const 8))))
+      (local.get $ptr))
+    (func (export "on-message")
+      (param $time i64) (param $topic i32) (param $topic_len i32) (param $payload i32) (param $payload_len i32)
+      (param $ts_some i32) (param $ts i64) (param $config i32) (param $config_len i32) (result i32)
+      (i32.store (i32.const 64) (local.get $topic))
+      (i32.store (i32.const 68) (local.get $topic_len))
+      (i32.store (i32.const 72) (local.get $config))
+      (i32.store (i32.const 76) (local.get $config_len))
+      (i32.store8 (i32.const 80) (local.get $ts_some))
+      (i64.store (i32.const 88) (local.get $ts))
+      (i32.store8 (i32.const 16) (i32.const 0))
+      (i32.store (i32.const 20) (i32.const 64))
+      (i32.store (i32.const 24) (i32.const 1))
+      (i32.const 16))
+    (func (export "on-interval") (param i64 i32 i32) (result i32)
+      (loop $forever (br $forever))
+      (unreachable))
+  )
+  (core instance $i (instantiate $m))
+  (alias core export $i "memory" (core memory $mem))
+  (alias core export $i "cabi_realloc" (core func $realloc))
+  (type $message' (record (field "topic" string) (field "payload" (list u8)) (field "timestamp" (option u64))))
+  (export $message "message" (type $message'))
+  (type $output (result (list $message) (error string)))
+  (func $on-message (param "time" u64) (param "message" $message) (param "config" string) (result $output)
+    (canon lift (core func $i "on-message") (memory $mem) (realloc $realloc)))
+  (func $on-interval (param "time" u64) (param "config" string) (result $output)
+    (canon lift (core func $i "on-interval") (memory $mem) (realloc $realloc)))
+  (export "on-message" (func $on-message))
+  (export "on-interval" (func $on-interval))
+)
@@ -159,6 +159,12 @@ impl HttpRequestBuilder {
         let body = Ok(content.into());
         HttpRequestBuilder { body, ..self }
     }
+
+    /// Send raw bytes as body
+    pub fn bytes(self, content: impl Into<Bytes>) -> Self {
+        let body = Ok(Full::new(content.into()).map_err(infallible).boxed());
+        HttpRequestBuilder { body, ..self }
+    }
 }
 
 #[async_trait]
@@ -3,6 +3,10 @@ use crate::config_toml::ExpandError;
 use crate::config_toml::ExpandedBridgeRule;
 use crate::config_toml::MapperConfigLookup;
 use crate::config_toml::NonExpansionReason;
+use crate::config_toml::OverflowPolicy;
+use crate::config_toml::RateLimit;
+use crate::config_toml::RetainPolicy;
+use crate::config_toml::TransformStep;
 use crate::topics::matches_ignore_dollar_prefix;
 use crate::topics::TopicConverter;
 use crate::AuthMethod;
@@ -14,6 +18,7 @@ use ariadne::Source;
 use camino::Utf8Path;
 use certificate::parse_root_certificate::create_tls_config;
 use certificate::parse_root_certificate::create_tls_config_without_client_cert;
+use mqtt_channel::QoS;
 use rumqttc::valid_filter;
 use rumqttc::valid_topic;
 use rumqttc::MqttOptions;
@@ -23,6 +28,9 @@ use std::path::Path;
 use tedge_config::tedge_toml::CloudConfig;
 use tedge_config::tedge_toml::ProfileName;
 use tedge_config::TEdgeConfig;
+use tedge_flows::BuiltinTransformers;
+use tedge_flows::JsonValue;
+use tedge_flows::TransformerChain;
 
 pub fn use_key_and_cert(
     config: &mut MqttOptions,
@@ -49,6 +57,22 @@ pub fn use_credentials(
     Ok(())
 }
 
+/// Tunnels an MQTT over TLS connection through a secure WebSocket
+///
+/// The TLS settings already set on `config`, whichever the authentication method, are reused
+/// as is for the WebSocket. Hence, this has to be called once the TLS transport is configured,
+/// on an [MqttOptions] created with the full `wss://` URL of the broker as address.
+pub fn use_websocket(config: &mut MqttOptions) -> anyhow::Result<()> {
+    match config.transport() {
+        Transport::Tls(tls_config) => {
+            config.set_transport(Transport::Wss(tls_config));
+            Ok(())
+        }
+        Transport::Wss(_) => Ok(()),
+        _ => anyhow::bail!("MQTT over WebSocket requires a TLS connection to the broker"),
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct BridgeConfig {
     local_to_remote: Vec<BridgeRule>,
@@ -84,6 +108,11 @@ pub struct BridgeRule {
     topic_filter: Cow<'static, str>,
     prefix_to_remove: Cow<'static, str>,
     prefix_to_add: Cow<'static, str>,
+    on_overflow: OverflowPolicy,
+    transform: Vec<TransformStep>,
+    qos: Option<QoS>,
+    retain: RetainPolicy,
+    max_rate: Option<RateLimit>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -104,6 +133,12 @@ pub enum InvalidBridgeRule {
 
     #[error("Invalid bridge config template")]
     Template,
+
+    #[error("Invalid transform for the bridge rule {topic_filter:?}: {reason}")]
+    InvalidTransform {
+        topic_filter: String,
+        reason: String,
+    },
 }
 
 fn validate_topic(topic: &str) -> Result<(), InvalidBridgeRule> {
@@ -131,6 +166,11 @@ impl BridgeRule {
             topic_filter: prefix_to_remove.clone() + base_topic_filter.clone(),
             prefix_to_remove,
             prefix_to_add,
+            on_overflow: OverflowPolicy::default(),
+            transform: Vec::new(),
+            qos: None,
+            retain: RetainPolicy::default(),
+            max_rate: None,
         };
 
         validate_topic(&r.prefix_to_add)?;
@@ -169,6 +209,81 @@ impl BridgeRule {
     pub fn prefix_to_remove(&self) -> &str {
         &self.prefix_to_remove
     }
+
+    /// What to discard when a message matching this rule is queued and the queue is full
+    pub fn on_overflow(&self) -> OverflowPolicy {
+        self.on_overflow
+    }
+
+    pub fn with_overflow_policy(self, on_overflow: OverflowPolicy) -> Self {
+        Self {
+            on_overflow,
+            ..self
+        }
+    }
+
+    /// The builtin transformers applied in turn to the messages matching this rule
+    pub fn transform(&self) -> &[TransformStep] {
+        &self.transform
+    }
+
+    pub fn with_transform(self, transform: Vec<TransformStep>) -> Self {
+        Self { transform, ..self }
+    }
+
+    /// The QoS used to forward the messages matching this rule, if not the QoS they were received with
+    pub fn qos(&self) -> Option<QoS> {
+        self.qos
+    }
+
+    pub fn with_qos(self, qos: QoS) -> Self {
+        Self {
+            qos: Some(qos),
+            ..self
+        }
+    }
+
+    /// How the retain flag of the messages matching this rule is set when forwarded
+    pub fn retain(&self) -> RetainPolicy {
+        self.retain
+    }
+
+    pub fn with_retain_policy(self, retain: RetainPolicy) -> Self {
+        Self { retain, ..self }
+    }
+
+    /// The maximum rate at which the messages matching this rule are forwarded, if any
+    pub fn max_rate(&self) -> Option<&RateLimit> {
+        self.max_rate.as_ref()
+    }
+
+    pub fn with_max_rate(self, max_rate: RateLimit) -> Self {
+        Self {
+            max_rate: Some(max_rate),
+            ..self
+        }
+    }
+
+    /// Instantiates the transformers applied to the messages matching this rule, if any
+    pub fn transformer_chain(&self) -> Result<Option<TransformerChain>, InvalidBridgeRule> {
+        if self.transform.is_empty() {
+            return Ok(None);
+        }
+
+        let invalid_transform = |reason: String| InvalidBridgeRule::InvalidTransform {
+            topic_filter: self.topic_filter.to_string(),
+            reason,
+        };
+        let mut steps = Vec::with_capacity(self.transform.len());
+        for step in &self.transform {
+            let config = serde_json::to_value(&step.config)
+                .map_err(|err| invalid_transform(err.to_string()))?;
+            steps.push((step.builtin.as_str(), JsonValue::from(config)));
+        }
+        TransformerChain::try_new(&BuiltinTransformers::new(), steps)
+            .map(Some)
+            .map_err(|err| invalid_transform(err.to_string()))
+    }
 }
 
 impl BridgeConfig {
@@ -258,6 +373,13 @@ impl BridgeConfig {
         rules: Vec<ExpandedBridgeRule>,
     ) -> Result<(), InvalidBridgeRule> {
         for rule in rules {
+            let first_local = self.local_to_remote.len();
+            let first_remote = self.remote_to_local.len();
+            let on_overflow = rule.on_overflow;
+            let transform = rule.transform;
+            let qos = rule.qos;
+            let retain = rule.retain;
+            let max_rate = rule.max_rate;
             match rule.direction {
                 Direction::Outbound => {
                     self.forward_from_local(rule.topic, rule.local_prefix, rule.remote_prefix)?;
@@ -273,6 +395,18 @@ impl BridgeConfig {
                     )?;
                 }
             }
+            for created in self.local_to_remote[first_local..]
+                .iter_mut()
+                .chain(self.remote_to_local[first_remote..].iter_mut())
+            {
+                created.on_overflow = on_overflow;
+                created.transform = transform.clone();
+                created.qos = qos;
+                created.retain = retain;
+                created.max_rate = max_rate.clone();
+                // Reject unknown transformers and invalid settings upfront, not on the first message
+                created.transformer_chain()?;
+            }
         }
         Ok(())
     }
@@ -402,6 +536,41 @@ mod tests {
         }
     }
 
+    mod use_websocket {
+        use super::use_credentials;
+        use super::use_websocket;
+        use rumqttc::MqttOptions;
+        use rumqttc::Transport;
+
+        #[test]
+        fn reuses_the_tls_configuration() {
+            let mut opts = MqttOptions::new("dummy-device", "wss://example.com:443/mqtt", 443);
+            let ca_cert = rcgen::generate_simple_self_signed(["dummy-ca".into()]).unwrap();
+            let ttd = tedge_test_utils::fs::TempTedgeDir::new();
+            let root_cert_path = ttd.path().join("ca.pem");
+            std::fs::write(&root_cert_path, ca_cert.cert.pem()).unwrap();
+            use_credentials(&mut opts, &root_cert_path, "user".into(), "pass".into()).unwrap();
+
+            use_websocket(&mut opts).unwrap();
+
+            let Transport::Wss(tls) = opts.transport() else {
+                panic!("Transport should be type WSS")
+            };
+            assert!(matches!(tls, rumqttc::TlsConfiguration::Rustls(_)));
+            assert_eq!(
+                opts.credentials(),
+                Some(rumqttc::Login::new("user", "pass"))
+            );
+        }
+
+        #[test]
+        fn requires_tls() {
+            let mut opts = MqttOptions::new("dummy-device", "wss://example.com:443/mqtt", 443);
+
+            assert!(use_websocket(&mut opts).is_err());
+        }
+    }
+
     mod bridge_rule {
         use super::*;
 
@@ -518,7 +687,7 @@ mod tests {
             let err = BridgeRule::try_new("".into(), "".into(), "a/".into()).unwrap_err();
             assert_eq!(
                 err.to_string(),
-                r#"BridgeRule { topic_filter: "", prefix_to_remove: "", prefix_to_add: "a/" } is not a valid rule, at least one of the topic filter or both prefixes must be non-empty"#
+                r#"BridgeRule { topic_filter: "", prefix_to_remove: "", prefix_to_add: "a/", on_overflow: DropOldest, transform: [], qos: None, retain: Keep, max_rate: None } is not a valid rule, at least one of the topic filter or both prefixes must be non-empty"#
             )
         }
 
@@ -527,9 +696,49 @@ mod tests {
             let err = BridgeRule::try_new("".into(), "a/".into(), "".into()).unwrap_err();
             assert_eq!(
                 err.to_string(),
-                r#"BridgeRule { topic_filter: "", prefix_to_remove: "a/", prefix_to_add: "" } is not a valid rule, at least one of the topic filter or both prefixes must be non-empty"#
+                r#"BridgeRule { topic_filter: "", prefix_to_remove: "a/", prefix_to_add: "", on_overflow: DropOldest, transform: [], qos: None, retain: Keep, max_rate: None } is not a valid rule, at least one of the topic filter or both prefixes must be non-empty"#
             )
         }
+
+        #[test]
+        fn has_no_transformer_chain_by_default() {
+            let rule = BridgeRule::try_new("a/topic".into(), "".into(), "".into()).unwrap();
+            assert!(rule.transformer_chain().unwrap().is_none());
+        }
+
+        #[test]
+        fn instantiates_builtin_transformers() {
+            let rule = BridgeRule::try_new("a/topic".into(), "".into(), "".into())
+                .unwrap()
+                .with_transform(vec![transform_step("gzip-compress", "level = 9")]);
+            assert!(rule.transformer_chain().unwrap().is_some());
+        }
+
+        #[test]
+        fn rejects_unknown_transformers() {
+            let rule = BridgeRule::try_new("a/topic".into(), "".into(), "".into())
+                .unwrap()
+                .with_transform(vec![transform_step("unknown", "")]);
+            assert_eq!(
+                rule.transformer_chain().err().unwrap().to_string(),
+                r#"Invalid transform for the bridge rule "a/topic": Builtin transformer not found: unknown"#
+            );
+        }
+
+        #[test]
+        fn rejects_invalid_transformer_settings() {
+            let rule = BridgeRule::try_new("a/topic".into(), "".into(), "".into())
+                .unwrap()
+                .with_transform(vec![transform_step("gzip-compress", "level = 42")]);
+            assert!(rule.transformer_chain().is_err());
+        }
+
+        fn transform_step(builtin: &str, config: &str) -> TransformStep {
+            TransformStep {
+                builtin: builtin.to_owned(),
+                config: toml::from_str(config).unwrap(),
+            }
+        }
     }
 
     mod topic_converter {
@@ -10,12 +10,15 @@ pub use mapper_config::MapperKeyResult;
 pub use mapper_config::TableMapperLookup;
 pub use mapper_config::WalkResult;
 
+use mqtt_channel::QoS;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_spanned::Spanned;
 use std::fmt;
 use std::marker::PhantomData;
+use std::num::NonZeroU32;
 use std::str::FromStr;
+use tedge_config::models::SecondsOrHumanTime;
 use tedge_config::models::TemplatesSet;
 use tedge_config::tedge_toml::ConfigNotSet;
 use tedge_config::tedge_toml::ParseKeyError;
@@ -57,6 +60,11 @@ pub struct ExpandedBridgeRule {
     pub remote_prefix: String,
     pub direction: Direction,
     pub topic: String,
+    pub on_overflow: OverflowPolicy,
+    pub transform: Vec<TransformStep>,
+    pub qos: Option<QoS>,
+    pub retain: RetainPolicy,
+    pub max_rate: Option<RateLimit>,
 }
 
 #[derive(Debug)]
@@ -254,6 +262,11 @@ impl PersistedBridgeConfig {
                 local_prefix: final_local_prefix,
                 remote_prefix: final_remote_prefix,
                 direction: rule.direction,
+                on_overflow: rule.on_overflow,
+                transform: rule.transform.clone(),
+                qos: rule.qos,
+                retain: rule.retain,
+                max_rate: rule.max_rate.clone(),
                 topic: expand_spanned(
                     &rule.topic,
                     static_cfg(),
@@ -373,6 +386,11 @@ impl PersistedBridgeConfig {
                     local_prefix: final_local_prefix.clone(),
                     remote_prefix: final_remote_prefix.clone(),
                     direction: template.direction,
+                    on_overflow: template.on_overflow,
+                    transform: template.transform.clone(),
+                    qos: template.qos,
+                    retain: template.retain,
+                    max_rate: template.max_rate.clone(),
                     topic: expand_spanned(
                         &template.topic,
                         template_config,
@@ -419,6 +437,15 @@ struct StaticBridgeRule {
     direction: Direction,
     topic: Spanned<Template>,
     r#if: Option<Spanned<String>>,
+    #[serde(default)]
+    on_overflow: OverflowPolicy,
+    #[serde(default)]
+    transform: Vec<TransformStep>,
+    #[serde(default, with = "optional_qos")]
+    qos: Option<QoS>,
+    #[serde(default)]
+    retain: RetainPolicy,
+    max_rate: Option<RateLimit>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -430,6 +457,15 @@ struct TemplateBridgeRule {
     remote_prefix: Option<Spanned<Template>>,
     direction: Direction,
     r#if: Option<Spanned<String>>,
+    #[serde(default)]
+    on_overflow: OverflowPolicy,
+    #[serde(default)]
+    transform: Vec<TransformStep>,
+    #[serde(default, with = "optional_qos")]
+    qos: Option<QoS>,
+    #[serde(default)]
+    retain: RetainPolicy,
+    max_rate: Option<RateLimit>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
@@ -440,6 +476,107 @@ pub enum Direction {
     Bidirectional,
 }
 
+/// What to discard when the store-and-forward queue of a bridge direction is full
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discard the oldest queued messages to make room for the new one
+    #[default]
+    DropOldest,
+    /// Keep the queued messages and discard the new one
+    DropNewest,
+}
+
+/// How the retain flag of the messages forwarded by a rule is set
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RetainPolicy {
+    /// Forward the retain flag as received
+    #[default]
+    Keep,
+    /// Forward the messages as not retained
+    Strip,
+    /// Forward the messages as retained
+    Force,
+}
+
+impl RetainPolicy {
+    pub fn apply(self, retain: bool) -> bool {
+        match self {
+            RetainPolicy::Keep => retain,
+            RetainPolicy::Strip => false,
+            RetainPolicy::Force => true,
+        }
+    }
+}
+
+/// A limit on the rate of the messages forwarded by a rule
+///
+/// The limit is enforced by a token bucket, holding up to `burst` tokens
+/// and refilled with `messages` tokens per `per` interval.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// The number of messages allowed per interval
+    pub messages: NonZeroU32,
+    /// The interval over which the messages are allowed, e.g. `1m`
+    pub per: SecondsOrHumanTime,
+    /// The number of messages which can be forwarded in a row, `messages` if not set
+    pub burst: Option<NonZeroU32>,
+    #[serde(default)]
+    pub on_limit: OnRateLimit,
+}
+
+/// What to do with the messages exceeding the rate limit of a rule
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnRateLimit {
+    /// Discard the messages exceeding the limit
+    #[default]
+    Drop,
+    /// Hold back the latest message of each topic exceeding the limit, until it can be forwarded,
+    /// discarding the messages it supersedes
+    Coalesce,
+}
+
+mod optional_qos {
+    use mqtt_channel::QoS;
+    use serde::de::Error;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serialize;
+    use serde::Serializer;
+
+    pub fn serialize<S: Serializer>(qos: &Option<QoS>, serializer: S) -> Result<S::Ok, S::Error> {
+        qos.map(|qos| qos as u8).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<QoS>, D::Error> {
+        Option::<u8>::deserialize(deserializer)?
+            .map(|qos| match qos {
+                0 => Ok(QoS::AtMostOnce),
+                1 => Ok(QoS::AtLeastOnce),
+                2 => Ok(QoS::ExactlyOnce),
+                _ => Err(D::Error::custom(format!(
+                    "invalid QoS {qos}, expected 0, 1 or 2"
+                ))),
+            })
+            .transpose()
+    }
+}
+
+/// A builtin flow transformer applied to the payload of the messages forwarded by a rule
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TransformStep {
+    /// The name of the builtin transformer, e.g. `gzip-compress`
+    pub builtin: String,
+    #[serde(default)]
+    pub config: toml::Table,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) enum Condition {
     AuthMethod(AuthMethod),
@@ -930,6 +1067,140 @@ mod tests {
             ));
         }
 
+        #[test]
+        fn deserializes_overflow_policies() {
+            let toml = r#"
+                [[rule]]
+                topic = "default/topic"
+                direction = "outbound"
+
+                [[rule]]
+                topic = "newest/topic"
+                direction = "outbound"
+                on_overflow = "drop_newest"
+
+                [[template_rule]]
+                for = ["a", "b"]
+                topic = "${item}"
+                direction = "outbound"
+                on_overflow = "drop_oldest"
+            "#;
+
+            let config: PersistedBridgeConfig = toml::from_str(toml).unwrap();
+            assert_eq!(
+                config.rules[0].get_ref().on_overflow,
+                OverflowPolicy::DropOldest
+            );
+            assert_eq!(
+                config.rules[1].get_ref().on_overflow,
+                OverflowPolicy::DropNewest
+            );
+            assert_eq!(
+                config.template_rules[0].get_ref().on_overflow,
+                OverflowPolicy::DropOldest
+            );
+        }
+
+        #[test]
+        fn deserializes_transform_steps() {
+            let toml = r#"
+                [[rule]]
+                topic = "plain/topic"
+                direction = "outbound"
+
+                [[rule]]
+                topic = "compressed/topic"
+                direction = "outbound"
+                transform = [
+                    { builtin = "json-to-cbor" },
+                    { builtin = "limit-payload-size", config = { max_size = 1024, discard = true } },
+                ]
+            "#;
+
+            let config: PersistedBridgeConfig = toml::from_str(toml).unwrap();
+            assert_eq!(config.rules[0].get_ref().transform, vec![]);
+            let transform = &config.rules[1].get_ref().transform;
+            assert_eq!(transform.len(), 2);
+            assert_eq!(transform[0].builtin, "json-to-cbor");
+            assert!(transform[0].config.is_empty());
+            assert_eq!(transform[1].builtin, "limit-payload-size");
+            assert_eq!(transform[1].config["max_size"].as_integer(), Some(1024));
+        }
+
+        #[test]
+        fn deserializes_qos_retain_and_rate_limits() {
+            let toml = r#"
+                [[rule]]
+                topic = "default/topic"
+                direction = "outbound"
+
+                [[rule]]
+                topic = "chatty/topic"
+                direction = "outbound"
+                qos = 0
+                retain = "strip"
+                max_rate = { messages = 10, per = "1m", burst = 20, on_limit = "coalesce" }
+
+                [[template_rule]]
+                for = ["a", "b"]
+                topic = "${item}"
+                direction = "inbound"
+                qos = 2
+                retain = "force"
+                max_rate = { messages = 1, per = 5 }
+            "#;
+
+            let config: PersistedBridgeConfig = toml::from_str(toml).unwrap();
+            let default = config.rules[0].get_ref();
+            assert_eq!(default.qos, None);
+            assert_eq!(default.retain, RetainPolicy::Keep);
+            assert_eq!(default.max_rate, None);
+
+            let chatty = config.rules[1].get_ref();
+            assert_eq!(chatty.qos, Some(QoS::AtMostOnce));
+            assert_eq!(chatty.retain, RetainPolicy::Strip);
+            let max_rate = chatty.max_rate.as_ref().unwrap();
+            assert_eq!(max_rate.messages.get(), 10);
+            assert_eq!(max_rate.per.duration(), std::time::Duration::from_secs(60));
+            assert_eq!(max_rate.burst.map(|burst| burst.get()), Some(20));
+            assert_eq!(max_rate.on_limit, OnRateLimit::Coalesce);
+
+            let template = config.template_rules[0].get_ref();
+            assert_eq!(template.qos, Some(QoS::ExactlyOnce));
+            assert_eq!(template.retain, RetainPolicy::Force);
+            let max_rate = template.max_rate.as_ref().unwrap();
+            assert_eq!(max_rate.per.duration(), std::time::Duration::from_secs(5));
+            assert_eq!(max_rate.burst, None);
+            assert_eq!(max_rate.on_limit, OnRateLimit::Drop);
+        }
+
+        #[test]
+        fn rejects_invalid_qos() {
+            let toml = r#"
+                [[rule]]
+                topic = "some/topic"
+                direction = "outbound"
+                qos = 3
+            "#;
+
+            let err = toml::from_str::<PersistedBridgeConfig>(toml).unwrap_err();
+            assert!(err
+                .to_string()
+                .contains("invalid QoS 3, expected 0, 1 or 2"));
+        }
+
+        #[test]
+        fn rejects_zero_rate_limit() {
+            let toml = r#"
+                [[rule]]
+                topic = "some/topic"
+                direction = "outbound"
+                max_rate = { messages = 0, per = "1s" }
+            "#;
+
+            assert!(toml::from_str::<PersistedBridgeConfig>(toml).is_err());
+        }
+
         #[test]
         fn rejects_unknown_fields() {
             let toml = r#"
@@ -8,17 +8,30 @@ use rumqttc::Event;
 use rumqttc::Incoming;
 use rumqttc::Publish;
 use rumqttc::QoS;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
+/// How often the queue depths are checked for changes to be published
+const QUEUE_DEPTH_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
 /// A tool for monitoring and publishing the health of the two bridge halves
 ///
 /// When [Self::monitor] runs, this will watch the status of the bridge halves, and notify the
 /// relevant MQTT topic about the overall health.
+///
+/// When store-and-forward is enabled, the health message also reports the number of messages
+/// queued for each broker, e.g. `{"status":"down","queued":{"cloud":42}}`.
 pub struct BridgeHealthMonitor {
     topic: String,
     rx_status: mpsc::Receiver<(&'static str, Status)>,
     companion_bridge_half: BridgeMessageSender,
+    queue_depths: Vec<(&'static str, Arc<AtomicUsize>)>,
 }
 
 impl BridgeHealthMonitor {
@@ -33,23 +46,48 @@ impl BridgeHealthMonitor {
                 topic,
                 rx_status,
                 companion_bridge_half: bridge_half.clone_sender(),
+                queue_depths: Vec::new(),
             },
         )
     }
 
+    /// Reports the depth of the given store-and-forward queues, named after their target broker
+    pub(crate) fn with_queue_depths(
+        self,
+        queue_depths: Vec<(&'static str, Arc<AtomicUsize>)>,
+    ) -> Self {
+        Self {
+            queue_depths,
+            ..self
+        }
+    }
+
     pub async fn monitor(mut self) -> ! {
         let mut statuses = HashMap::from([("local", None), ("cloud", None)]);
         let mut last_status = None;
+        let mut last_depths = self.current_queue_depths();
+        let mut depth_check = tokio::time::interval(QUEUE_DEPTH_REPORT_INTERVAL);
         loop {
-            let (name, status) = self.rx_status.recv().await.unwrap();
-            *statuses.entry(name).or_insert(Some(status)) = Some(status);
+            tokio::select! {
+                update = self.rx_status.recv() => {
+                    let (name, status) = update.unwrap();
+                    *statuses.entry(name).or_insert(Some(status)) = Some(status);
+                }
+                _ = depth_check.tick(), if !self.queue_depths.is_empty() => {}
+            }
 
             let status = statuses.values().fold(Some(Status::Up), overall_status);
-            if last_status != status {
+            let depths = self.current_queue_depths();
+            if let Some(current) = status.filter(|_| last_status != status || last_depths != depths)
+            {
                 last_status = status;
+                last_depths = depths;
 
-                let mut health_msg =
-                    Publish::new(&self.topic, QoS::AtLeastOnce, status.unwrap().json());
+                let mut health_msg = Publish::new(
+                    &self.topic,
+                    QoS::AtLeastOnce,
+                    health_payload(current, &last_depths),
+                );
                 health_msg.retain = true;
 
                 // Publish the health message over MQTT, but with no duplicate for the companion
@@ -58,6 +96,30 @@ impl BridgeHealthMonitor {
             }
         }
     }
+
+    fn current_queue_depths(&self) -> Vec<(&'static str, usize)> {
+        self.queue_depths
+            .iter()
+            .map(|(name, depth)| (*name, depth.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+/// The payload of a health message
+#[derive(Serialize)]
+struct HealthPayload<'a> {
+    status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queued: Option<BTreeMap<&'a str, usize>>,
+}
+
+/// Builds the health message payload, adding the queue depths if any
+fn health_payload(status: Status, queue_depths: &[(&'static str, usize)]) -> String {
+    let payload = HealthPayload {
+        status,
+        queued: (!queue_depths.is_empty()).then(|| queue_depths.iter().copied().collect()),
+    };
+    serde_json::to_string(&payload).expect("a health payload can always be serialized")
 }
 
 type NotificationRes = Result<Event, ConnectionError>;
@@ -102,3 +164,21 @@ impl BridgeHealth {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_payload_without_queues() {
+        assert_eq!(health_payload(Status::Up, &[]), r#"{"status":"up"}"#);
+    }
+
+    #[test]
+    fn health_payload_with_queue_depths() {
+        assert_eq!(
+            health_payload(Status::Down, &[("local", 0), ("cloud", 42)]),
+            r#"{"status":"down","queued":{"cloud":42,"local":0}}"#
+        );
+    }
+}
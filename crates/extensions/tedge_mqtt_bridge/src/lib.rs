@@ -2,6 +2,8 @@ mod backoff;
 pub mod config;
 pub mod config_toml;
 pub mod persist;
+pub mod queue;
+mod rate_limit;
 #[cfg(test)]
 mod test_helpers;
 mod topics;
@@ -35,6 +37,7 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 use tedge_actors::Actor;
 use tedge_actors::Builder;
 use tedge_actors::DynSender;
@@ -58,8 +61,15 @@ pub use mqtt_channel::QoS;
 pub use mqtt_channel::Topic;
 use tedge_config::tedge_toml::TEdgeConfigReaderMqttBridgeReconnectPolicy;
 use tedge_config::TEdgeConfig;
+use tedge_flows::TransformerChain;
 
 use crate::backoff::CustomBackoff;
+use crate::config_toml::OverflowPolicy;
+use crate::queue::DiskQueue;
+use crate::queue::QueueConfig;
+use crate::queue::Queued;
+use crate::rate_limit::Admission;
+use crate::rate_limit::RateLimiter;
 use crate::topics::matches_ignore_dollar_prefix;
 use crate::topics::TopicConverter;
 pub use config::*;
@@ -178,12 +188,31 @@ impl MqttBridgeActorBuilder {
             .map(|t| SubscribeFilter::new(t.to_owned(), QoS::AtLeastOnce))
             .collect();
 
+        // Messages are queued on disk, per target broker, while that broker is unreachable
+        let queue_dir = tedge_config
+            .data
+            .path
+            .join("bridge-queue")
+            .join(service_name);
+        let cloud_queue = open_queue(tedge_config, queue_dir.join("cloud")).await;
+        let local_queue = open_queue(tedge_config, queue_dir.join("local")).await;
+        let queue_depths: Vec<_> = [("cloud", &cloud_queue), ("local", &local_queue)]
+            .into_iter()
+            .filter_map(|(name, queue)| Some((name, queue.as_ref()?.depth())))
+            .collect();
+
         let ([cloud_target, local_target], [cloud_gate_controller, local_gate_controller]) =
-            bidirectional_channel(cloud_client.clone(), local_client.clone(), in_flight.into());
+            bidirectional_channel(
+                cloud_client.clone(),
+                local_client.clone(),
+                in_flight.into(),
+                [cloud_queue, local_queue],
+            );
         let [(convert_local, bidir_local), (convert_cloud, bidir_cloud)] =
             rules.converters_and_bidirectional_topic_filters();
         let (tx_status, monitor) =
             BridgeHealthMonitor::new(health_topic.name.clone(), &local_target);
+        let monitor = monitor.with_queue_depths(queue_depths);
         let cloud_tx = cloud_target.clone_sender();
         let local_tx = local_target.clone_sender();
         let monitor_task = tokio::spawn(
@@ -251,10 +280,29 @@ impl MqttBridgeActorBuilder {
     }
 }
 
+/// Opens the store-and-forward queue for one bridge direction, if enabled
+///
+/// A queue that cannot be opened is reported and the bridge runs without it,
+/// holding messages in memory as when store-and-forward is disabled.
+async fn open_queue(tedge_config: &TEdgeConfig, dir: camino::Utf8PathBuf) -> Option<DiskQueue> {
+    let config = QueueConfig::from_tedge_config(tedge_config, dir)?;
+    let dir = config.dir.clone();
+    match DiskQueue::open(config).await {
+        Ok(queue) => Some(queue),
+        Err(err) => {
+            tracing::error!(
+                "Failed to open the bridge message queue {dir}, messages will not be persisted: {err}"
+            );
+            None
+        }
+    }
+}
+
 fn bidirectional_channel<Client: MqttClient + 'static>(
     cloud_client: Client,
     local_client: Client,
     buffer: usize,
+    [cloud_queue, local_queue]: [Option<DiskQueue>; 2],
 ) -> (
     [BridgeAsyncClient<Client>; 2],
     [SubscriptionGateController; 2],
@@ -268,8 +316,20 @@ fn bidirectional_channel<Client: MqttClient + 'static>(
     let (local_gate, local_gate_controller) = SubscriptionGate::new();
     (
         [
-            BridgeAsyncClient::new(cloud_client, tx_first, rx_second, cloud_gate),
-            BridgeAsyncClient::new(local_client, tx_second, rx_first, local_gate),
+            BridgeAsyncClient::with_store(
+                cloud_client.clone(),
+                tx_first,
+                rx_second,
+                cloud_gate,
+                cloud_queue.map(|queue| StoreAndForward::new("cloud", queue, local_client.clone())),
+            ),
+            BridgeAsyncClient::with_store(
+                local_client,
+                tx_second,
+                rx_first,
+                local_gate,
+                local_queue.map(|queue| StoreAndForward::new("local", queue, cloud_client)),
+            ),
         ],
         [cloud_gate_controller, local_gate_controller],
     )
@@ -340,6 +400,9 @@ enum BridgeMessage {
     BridgePub {
         target_topic: String,
         publish: Publish,
+        /// The QoS to publish the message with, possibly not the QoS it was received with
+        qos: QoS,
+        on_overflow: OverflowPolicy,
     },
 
     /// A message to be acknowledged on the target
@@ -351,6 +414,15 @@ enum BridgeMessage {
     ///
     /// This message has not to be acknowledged, as not received by the bridge.
     Pub { publish: Publish },
+
+    /// An additional message produced by the transformers of a bridge rule
+    ///
+    /// As a message generated by the bridge, this message has not to be acknowledged.
+    /// However, this message is queued as any message forwarded by the rule.
+    ExtraPub {
+        publish: Publish,
+        on_overflow: OverflowPolicy,
+    },
 }
 
 /// Wraps the target of an half bridge with a channel to its half bridge companion.
@@ -387,11 +459,23 @@ impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
         self.sender.clone()
     }
 
+    #[cfg(test)]
     fn new(
         target: Client,
         tx: mpsc::Sender<Option<(String, Publish)>>,
         rx: mpsc::Receiver<Option<(String, Publish)>>,
         gate: SubscriptionGate,
+    ) -> Self {
+        Self::with_store(target, tx, rx, gate, None::<StoreAndForward<Client>>)
+    }
+
+    /// Creates the client, persisting the messages to disk while the target is unreachable
+    fn with_store<Source: MqttAck + Send + Sync + 'static>(
+        target: Client,
+        tx: mpsc::Sender<Option<(String, Publish)>>,
+        rx: mpsc::Receiver<Option<(String, Publish)>>,
+        gate: SubscriptionGate,
+        store: Option<StoreAndForward<Source>>,
     ) -> Self {
         let (unbounded_tx, unbounded_rx) = mpsc::unbounded_channel();
         let companion_bridge_half = BridgeAsyncClient {
@@ -402,12 +486,19 @@ impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
             acknowledged: Arc::new(AtomicUsize::new(0)),
             gate,
         };
-        companion_bridge_half.spawn_publisher(tx, unbounded_rx);
+        companion_bridge_half.spawn_publisher(tx, unbounded_rx, store);
         companion_bridge_half
     }
 
+    #[cfg(test)]
     fn publish(&mut self, target_topic: String, publish: Publish) {
-        self.sender.publish(target_topic, publish)
+        let qos = publish.qos;
+        self.sender
+            .publish(target_topic, publish, qos, OverflowPolicy::default())
+    }
+
+    fn forward(&mut self, forward: Forward, on_overflow: OverflowPolicy) {
+        self.sender.forward(forward, on_overflow)
     }
 
     fn ack(&mut self, publish: Publish) {
@@ -422,10 +513,11 @@ impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
         self.acknowledged.load(Ordering::Relaxed)
     }
 
-    fn spawn_publisher(
+    fn spawn_publisher<Source: MqttAck + Send + Sync + 'static>(
         &self,
         tx: mpsc::Sender<Option<(String, Publish)>>,
         mut unbounded_rx: mpsc::UnboundedReceiver<BridgeMessage>,
+        mut store: Option<StoreAndForward<Source>>,
     ) {
         let target = self.target.clone();
         let published = self.published.clone();
@@ -438,8 +530,15 @@ impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
                 // order and flushed once the gate opens. Every publish goes through the
                 // buffer, so they are always forwarded in arrival order. Acks are never
                 // gated.
+                //
+                // With store-and-forward enabled, the bridged publishes are persisted to
+                // disk instead of being held in memory, and the persisted messages are
+                // replayed before anything else when the gate opens.
                 let mut buffer: VecDeque<BridgeMessage> = VecDeque::new();
                 loop {
+                    if let Some(store) = store.as_mut().filter(|_| gate.is_open()) {
+                        store.replay(&target, &tx, &published, &gate).await;
+                    }
                     if gate.is_open() {
                         // Flush queued publishes in order before handling anything new
                         while let Some(message) = buffer.pop_front() {
@@ -458,6 +557,20 @@ impl<Client: MqttClient + 'static> BridgeAsyncClient<Client> {
                                 target.ack(&publish).await.unwrap();
                                 acknowledged.fetch_add(1, Ordering::Relaxed);
                             }
+                            // Bridged publishes are persisted while the target is unreachable,
+                            // or while older persisted messages are still to be replayed
+                            Some(
+                                message @ (BridgeMessage::BridgePub { .. }
+                                | BridgeMessage::ExtraPub { .. }),
+                            ) => match &mut store {
+                                Some(store) if !gate.is_open() || !store.is_empty() => {
+                                    store.spill(&mut buffer).await;
+                                    if let Err(message) = store.hold(message).await {
+                                        buffer.push_back(message);
+                                    }
+                                }
+                                _ => buffer.push_back(message),
+                            },
                             // Publishes are queued and flushed at the top of the loop
                             Some(message) => buffer.push_back(message),
                         }
@@ -481,16 +594,18 @@ async fn publish_to_target<Client: MqttClient>(
         BridgeMessage::BridgePub {
             target_topic,
             publish,
+            qos,
+            ..
         } => {
             let duplicate = (target_topic.clone(), publish.clone());
             tx.send(Some(duplicate)).await.unwrap();
             target
-                .publish(target_topic, publish.qos, publish.retain, publish.payload)
+                .publish(target_topic, qos, publish.retain, publish.payload)
                 .await
                 .unwrap();
             published.fetch_add(1, Ordering::Relaxed);
         }
-        BridgeMessage::Pub { publish } => {
+        BridgeMessage::Pub { publish } | BridgeMessage::ExtraPub { publish, .. } => {
             tx.send(None).await.unwrap();
             target
                 .publish(publish.topic, publish.qos, publish.retain, publish.payload)
@@ -504,6 +619,18 @@ async fn publish_to_target<Client: MqttClient>(
     }
 }
 
+/// The messages to be forwarded in place of a received message
+struct Forward {
+    /// The received message, to be acknowledged once forwarded
+    publish: Publish,
+
+    /// The QoS to publish the messages with
+    qos: QoS,
+
+    /// The topics and payloads of the messages to be forwarded
+    messages: Vec<(String, Bytes)>,
+}
+
 #[derive(Clone)]
 struct BridgeMessageSender {
     unbounded_tx: mpsc::UnboundedSender<BridgeMessage>,
@@ -516,15 +643,55 @@ impl BridgeMessageSender {
             .unwrap()
     }
 
-    fn publish(&mut self, target_topic: String, publish: Publish) {
+    fn extra_publish(&mut self, publish: Publish, on_overflow: OverflowPolicy) {
+        self.unbounded_tx
+            .send(BridgeMessage::ExtraPub {
+                publish,
+                on_overflow,
+            })
+            .unwrap()
+    }
+
+    fn publish(
+        &mut self,
+        target_topic: String,
+        publish: Publish,
+        qos: QoS,
+        on_overflow: OverflowPolicy,
+    ) {
         self.unbounded_tx
             .send(BridgeMessage::BridgePub {
                 target_topic,
                 publish,
+                qos,
+                on_overflow,
             })
             .unwrap()
     }
 
+    /// Publishes the messages to be forwarded in place of a received message
+    ///
+    /// Only the last one is tracked, to acknowledge the received message once delivered.
+    /// The others are queued along, so they are not lost while the target is unreachable.
+    fn forward(&mut self, forward: Forward, on_overflow: OverflowPolicy) {
+        let Forward {
+            publish,
+            qos,
+            mut messages,
+        } = forward;
+        let Some((target_topic, payload)) = messages.pop() else {
+            return;
+        };
+        for (topic, payload) in messages {
+            let mut extra = Publish::new(topic, qos, payload);
+            extra.retain = publish.retain;
+            self.extra_publish(extra, on_overflow);
+        }
+        let mut publish = publish;
+        publish.payload = payload;
+        self.publish(target_topic, publish, qos, on_overflow)
+    }
+
     fn ack(&mut self, publish: Publish) {
         self.unbounded_tx
             .send(BridgeMessage::BridgeAck { publish })
@@ -532,6 +699,142 @@ impl BridgeMessageSender {
     }
 }
 
+/// Persists the bridged messages while the target broker is unreachable
+///
+/// A message is acknowledged to its source broker as soon as it is persisted, since the
+/// queue is then responsible for it. This is also the case when the message has to be
+/// discarded because the queue is full. The persisted messages are replayed as messages
+/// generated by the bridge, with no acknowledgement to forward once published.
+///
+/// As replayed messages are not tracked by the [MessageLoopBreaker], queueing is best
+/// suited to rules forwarding messages in a single direction.
+struct StoreAndForward<Source> {
+    name: &'static str,
+    queue: DiskQueue,
+    source: Source,
+}
+
+impl<Source: MqttAck + Send + Sync> StoreAndForward<Source> {
+    fn new(name: &'static str, queue: DiskQueue, source: Source) -> Self {
+        if !queue.is_empty() {
+            log_event!(
+                name,
+                "{} queued messages will be replayed once connected",
+                queue.len()
+            );
+        }
+        StoreAndForward {
+            name,
+            queue,
+            source,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Persists a bridged publish and acknowledges it to its source
+    ///
+    /// Returns the message unchanged if it cannot be persisted, so it can be held in memory.
+    async fn hold(&mut self, message: BridgeMessage) -> Result<(), BridgeMessage> {
+        let name = self.name;
+        let (queued, on_overflow, received) = match &message {
+            BridgeMessage::BridgePub {
+                target_topic,
+                publish,
+                qos,
+                on_overflow,
+            } => {
+                let mut queued = Publish::new(target_topic, *qos, publish.payload.clone());
+                queued.retain = publish.retain;
+                (queued, *on_overflow, Some(publish))
+            }
+            // Extra messages produced by transformers have no source message to acknowledge
+            BridgeMessage::ExtraPub {
+                publish,
+                on_overflow,
+            } => (publish.clone(), *on_overflow, None),
+            _ => return Err(message),
+        };
+
+        let target_topic = &queued.topic;
+        match self.queue.push(&queued, on_overflow).await {
+            Ok(Queued::Stored { evicted: 0 }) => {}
+            Ok(Queued::Stored { evicted }) => {
+                log_event!(warn: name, "Message queue is full, discarded the {evicted} oldest messages");
+            }
+            Ok(Queued::Discarded) => {
+                log_event!(warn: name, "Message queue is full, discarding message on topic {target_topic}");
+            }
+            Err(err) => {
+                log_event!(error: name, "Failed to queue message on topic {target_topic}: {err}");
+                return Err(message);
+            }
+        }
+
+        if let Some(publish) = received {
+            self.source.ack(publish).await.unwrap();
+        }
+        Ok(())
+    }
+
+    /// Moves to the queue any bridged publish still held in memory, preserving their order
+    async fn spill(&mut self, buffer: &mut VecDeque<BridgeMessage>) {
+        if !buffer.iter().any(|message| {
+            matches!(
+                message,
+                BridgeMessage::BridgePub { .. } | BridgeMessage::ExtraPub { .. }
+            )
+        }) {
+            return;
+        }
+        let mut kept = VecDeque::new();
+        for message in buffer.drain(..) {
+            let message = match message {
+                message @ (BridgeMessage::BridgePub { .. } | BridgeMessage::ExtraPub { .. }) => {
+                    self.hold(message).await
+                }
+                message => Err(message),
+            };
+            if let Err(message) = message {
+                kept.push_back(message);
+            }
+        }
+        *buffer = kept;
+    }
+
+    /// Publishes the queued messages in order, for as long as the gate stays open
+    async fn replay<Client: MqttClient>(
+        &mut self,
+        target: &Client,
+        tx: &mpsc::Sender<Option<(String, Publish)>>,
+        published: &AtomicUsize,
+        gate: &SubscriptionGate,
+    ) {
+        let name = self.name;
+        let mut replayed = 0;
+        while gate.is_open() {
+            match self.queue.front().await {
+                Ok(Some(publish)) => {
+                    publish_to_target(target, tx, published, BridgeMessage::Pub { publish }).await;
+                    replayed += 1;
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    log_event!(error: name, "Discarding queued message that cannot be read: {err}");
+                }
+            }
+            if let Err(err) = self.queue.remove_front().await {
+                log_event!(error: name, "Failed to remove replayed message from the queue: {err}");
+            }
+        }
+        if replayed > 0 {
+            log_event!(name, "Replayed {replayed} queued messages");
+        }
+    }
+}
+
 /// Forward messages received from `recv_event_loop` to `target`
 ///
 /// The result of running this function constitutes half the MQTT bridge, hence the name.
@@ -659,6 +962,16 @@ async fn half_bridge(
     let mut bridge_health = BridgeHealth::new(name, tx_health);
     let mut loop_breaker =
         MessageLoopBreaker::new(recv_client.clone(), bidirectional_topic_filters);
+    let mut transforms: Vec<_> = transformer
+        .0
+        .iter()
+        .map(BridgeRule::transformer_chain)
+        .collect();
+    let rate_limiters: Vec<Option<RateLimiter<Forward>>> = transformer
+        .0
+        .iter()
+        .map(|rule| rule.max_rate().map(RateLimiter::new))
+        .collect();
 
     let mut received = 0; // Count of messages received by this half-bridge
     let mut published = 0; // Count of messages published (by the companion)
@@ -775,24 +1088,98 @@ async fn half_bridge(
             // Forward messages from event loop to target
             Event::Incoming(Incoming::Publish(publish)) => {
                 if let Some(publish) = loop_breaker.ensure_not_looped(publish).await {
-                    if let Some(topic) = transformer.convert_topic(&publish.topic) {
-                        let wire_size = mqtt_channel::publish_packet_size(
-                            topic.as_ref(),
-                            publish.qos,
-                            publish.payload.len(),
-                        );
-                        if let Some(limit) = max_payload_size.filter(|&limit| wire_size > limit) {
-                            // The message is too large to ever be accepted by the cloud broker.
-                            // Acknowledge it locally so it is not redelivered, and drop it rather
-                            // than let it block the cloud connection.
-                            log_event!(
-                                warn: name,
-                                "Dropping cloud-bound message on topic {topic}: packet size {wire_size} B exceeds the configured limit of {limit} B"
-                            );
-                            recv_client.ack(&publish).await.unwrap()
-                        } else {
-                            received += 1;
-                            target.publish(topic.to_string(), publish);
+                    if let Some((topic, index)) = transformer.convert(&publish.topic) {
+                        let target_topic = topic.into_owned();
+                        let rule = &transformer.0[index];
+                        let qos = rule.qos().unwrap_or(publish.qos);
+                        let on_overflow = rule.on_overflow();
+                        let mut publish = publish;
+                        publish.retain = rule.retain().apply(publish.retain);
+                        if qos == QoS::AtMostOnce && publish.qos != QoS::AtMostOnce {
+                            // No acknowledgement will be received for a message forwarded with QoS 0.
+                            // Acknowledge the message right away, so it is not redelivered.
+                            recv_client.ack(&publish).await.unwrap();
+                            publish.qos = QoS::AtMostOnce;
+                            publish.pkid = 0;
+                        }
+
+                        let mut messages =
+                            match transform(&mut transforms[index], target_topic, &publish) {
+                                Ok(messages) => messages,
+                                Err(err) => {
+                                    log_event!(
+                                        warn: name,
+                                        "Dropping message on topic {}: {err}",
+                                        publish.topic
+                                    );
+                                    Vec::new()
+                                }
+                            };
+                        messages.retain(|(topic, payload)| {
+                            let wire_size =
+                                mqtt_channel::publish_packet_size(topic, qos, payload.len());
+                            match max_payload_size.filter(|&limit| wire_size > limit) {
+                                // The message is too large to ever be accepted by the cloud broker.
+                                // Drop it rather than let it block the cloud connection.
+                                Some(limit) => {
+                                    log_event!(
+                                        warn: name,
+                                        "Dropping cloud-bound message on topic {topic}: packet size {wire_size} B exceeds the configured limit of {limit} B"
+                                    );
+                                    false
+                                }
+                                None => true,
+                            }
+                        });
+                        if messages.is_empty() {
+                            // Nothing is left to forward.
+                            // Acknowledge the message locally so it is not redelivered,
+                            // unless already done when downgraded to QoS 0.
+                            if publish.qos != QoS::AtMostOnce {
+                                recv_client.ack(&publish).await.unwrap();
+                            }
+                            continue;
+                        }
+
+                        received += 1;
+                        let forward = Forward {
+                            publish,
+                            qos,
+                            messages,
+                        };
+                        let Some(rate_limiter) = &rate_limiters[index] else {
+                            target.forward(forward, on_overflow);
+                            continue;
+                        };
+                        let topic = forward.publish.topic.clone();
+                        match rate_limiter.admit(&topic, forward, Instant::now()) {
+                            Admission::Forward(forward) => target.forward(forward, on_overflow),
+                            Admission::Discard(forward) => {
+                                log_event!(
+                                    debug: name,
+                                    "Dropping message on topic {}: rate limit exceeded",
+                                    forward.publish.topic
+                                );
+                                if forward.publish.qos != QoS::AtMostOnce {
+                                    recv_client.ack(&forward.publish).await.unwrap();
+                                }
+                            }
+                            Admission::Held {
+                                superseded,
+                                release_in,
+                            } => {
+                                if let Some(superseded) = superseded
+                                    .filter(|superseded| superseded.publish.qos != QoS::AtMostOnce)
+                                {
+                                    recv_client.ack(&superseded.publish).await.unwrap();
+                                }
+                                if let Some(delay) = release_in {
+                                    let mut sender = target.clone_sender();
+                                    rate_limiter.schedule_release(delay, move |forward| {
+                                        sender.forward(forward, on_overflow)
+                                    });
+                                }
+                            }
                         }
                     } else {
                         // Being not forwarded to this bridge target
@@ -881,6 +1268,34 @@ async fn half_bridge(
     }
 }
 
+/// Applies the transformers of a bridge rule to a message to be forwarded on the target topic
+///
+/// Returns the topics and payloads of the messages to be forwarded instead.
+fn transform(
+    chain: &mut Result<Option<TransformerChain>, InvalidBridgeRule>,
+    target_topic: String,
+    publish: &Publish,
+) -> Result<Vec<(String, Bytes)>, String> {
+    let chain = match chain {
+        Ok(Some(chain)) => chain,
+        Ok(None) => return Ok(vec![(target_topic, publish.payload.clone())]),
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let mut message = tedge_flows::Message::new(target_topic, publish.payload.to_vec());
+    message.transport = Some(tedge_flows::Transport::Mqtt {
+        qos: publish.qos,
+        retain: publish.retain,
+    });
+    let messages = chain
+        .on_message(SystemTime::now(), message)
+        .map_err(|err| err.to_string())?;
+    Ok(messages
+        .into_iter()
+        .map(|message| (message.topic, Bytes::from(message.payload)))
+        .collect())
+}
+
 #[async_trait::async_trait]
 trait MqttEvents: Send {
     async fn poll(&mut self) -> Result<Event, ConnectionError>;
@@ -917,7 +1332,8 @@ impl MqttClient for LoggingAsyncClient {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
 enum Status {
     Up,
     Down,
@@ -1377,6 +1793,12 @@ mod tests {
         use tokio::sync::mpsc::error::TryRecvError;
         use tokio::task::JoinHandle;
 
+        macro_rules! bridge_rule {
+            ($base:literal -$remove:literal +$add:literal) => {
+                BridgeRule::try_new($base.into(), $remove.into(), $add.into()).unwrap()
+            };
+        }
+
         #[tokio::test]
         async fn subscribes_after_conn_ack() {
             let events = [inc!(connack)];
@@ -1455,6 +1877,26 @@ mod tests {
             );
         }
 
+        #[tokio::test]
+        async fn over_limit_message_downgraded_to_qos_0_is_acked_once() {
+            let big_msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, vec![b'x'; 100]);
+            let rule = bridge_rule!("s/us" - "c8y/" + "").with_qos(QoS::AtMostOnce);
+
+            let bridge = Bridge::default()
+                .with_local_events([inc!(publish(big_msg))])
+                .with_cloud_events([inc!(connack)])
+                .with_local_rules(vec![rule])
+                .with_max_payload_size(50)
+                .process_all_events()
+                .await;
+
+            assert_eq!(
+                bridge.local_client.next_action().unwrap(),
+                Action::Ack(big_msg)
+            );
+            assert!(bridge.local_client.next_action().is_err());
+        }
+
         #[tokio::test]
         async fn over_limit_cloud_to_local_message_is_forwarded_unchanged() {
             let big_msg = Publish::new("s/ds", QoS::AtLeastOnce, vec![b'x'; 100]);
@@ -1814,6 +2256,263 @@ mod tests {
             assert!(actions.contains(&Action::Publish(expected_forwarded)));
         }
 
+        mod transforming_payloads {
+            use super::*;
+            use crate::config_toml::TransformStep;
+
+            #[tokio::test]
+            async fn applies_the_transformers_of_the_matching_rule() {
+                let incoming_msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, "payload");
+                let rule = bridge_rule!("s/us" - "c8y/" + "").with_transform(vec![
+                    transform_step("gzip-compress", ""),
+                    transform_step("gzip-decompress", ""),
+                    transform_step("set-topic", r#"topic = "s/uc/custom""#),
+                ]);
+
+                let bridge = Bridge::default()
+                    .with_local_events([inc!(publish(incoming_msg))])
+                    .with_cloud_events([inc!(connack)])
+                    .with_local_rules(vec![rule])
+                    .process_all_events()
+                    .await;
+
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/uc/custom", QoS::AtLeastOnce, "payload"))
+                )
+            }
+
+            #[tokio::test]
+            async fn acknowledges_messages_discarded_by_transformers() {
+                let big_msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, vec![b'x'; 100]);
+                let small_msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, "payload");
+                let events = [inc!(publish(big_msg)), inc!(publish(small_msg))];
+                let rule = bridge_rule!("s/us" - "c8y/" + "").with_transform(vec![transform_step(
+                    "limit-payload-size",
+                    "max_size = 50\ndiscard = true",
+                )]);
+
+                let bridge = Bridge::default()
+                    .with_local_events(events)
+                    .with_cloud_events([inc!(connack)])
+                    .with_local_rules(vec![rule])
+                    .process_all_events()
+                    .await;
+
+                assert_eq!(
+                    bridge.local_client.next_action().unwrap(),
+                    Action::Ack(big_msg)
+                );
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "payload"))
+                );
+            }
+
+            #[tokio::test]
+            async fn acknowledges_messages_which_cannot_be_transformed() {
+                let msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, "not compressed");
+                let rule = bridge_rule!("s/us" - "c8y/" + "")
+                    .with_transform(vec![transform_step("gzip-decompress", "")]);
+
+                let bridge = Bridge::default()
+                    .with_local_events([inc!(publish(msg))])
+                    .with_cloud_events([inc!(connack)])
+                    .with_local_rules(vec![rule])
+                    .process_all_events()
+                    .await;
+
+                assert_eq!(bridge.local_client.next_action().unwrap(), Action::Ack(msg));
+                assert!(bridge.cloud_client.next_action().is_err());
+            }
+
+            fn transform_step(builtin: &str, config: &str) -> TransformStep {
+                TransformStep {
+                    builtin: builtin.to_owned(),
+                    config: toml::from_str(config).unwrap(),
+                }
+            }
+        }
+
+        mod rule_settings {
+            use super::*;
+            use crate::config_toml::OnRateLimit;
+            use crate::config_toml::RateLimit;
+            use crate::config_toml::RetainPolicy;
+            use std::num::NonZeroU32;
+
+            #[tokio::test]
+            async fn overrides_the_qos_and_retain_flag_of_forwarded_messages() {
+                let incoming_msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, "payload");
+                let rule = bridge_rule!("s/us" - "c8y/" + "")
+                    .with_qos(QoS::ExactlyOnce)
+                    .with_retain_policy(RetainPolicy::Force);
+
+                let bridge = Bridge::default()
+                    .with_local_events([inc!(publish(incoming_msg))])
+                    .with_cloud_events([inc!(connack)])
+                    .with_local_rules(vec![rule])
+                    .process_all_events()
+                    .await;
+
+                let mut outgoing_msg = Publish::new("s/us", QoS::ExactlyOnce, "payload");
+                outgoing_msg.retain = true;
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(outgoing_msg)
+                )
+            }
+
+            #[tokio::test]
+            async fn acknowledges_messages_downgraded_to_qos_0_on_receipt() {
+                let incoming_msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, "payload");
+                let rule = bridge_rule!("s/us" - "c8y/" + "").with_qos(QoS::AtMostOnce);
+
+                let bridge = Bridge::default()
+                    .with_local_events([inc!(publish(incoming_msg))])
+                    .with_cloud_events([inc!(connack)])
+                    .with_local_rules(vec![rule])
+                    .process_all_events()
+                    .await;
+
+                assert_eq!(
+                    bridge.local_client.next_action().unwrap(),
+                    Action::Ack(incoming_msg)
+                );
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/us", QoS::AtMostOnce, "payload"))
+                );
+            }
+
+            #[tokio::test]
+            async fn drops_messages_over_the_rate_limit() {
+                let first = Publish::new("c8y/s/us", QoS::AtLeastOnce, "first");
+                let second = Publish::new("c8y/s/us", QoS::AtLeastOnce, "second");
+                let third = Publish::new("c8y/s/us", QoS::AtLeastOnce, "third");
+                let events = [
+                    inc!(publish(first)),
+                    inc!(publish(second)),
+                    inc!(publish(third)),
+                ];
+                let rule = bridge_rule!("s/us" - "c8y/" + "").with_max_rate(rate_limit(
+                    1,
+                    "1m",
+                    OnRateLimit::Drop,
+                ));
+
+                let bridge = Bridge::default()
+                    .with_local_events(events)
+                    .with_cloud_events([inc!(connack)])
+                    .with_local_rules(vec![rule])
+                    .process_all_events()
+                    .await;
+
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "first"))
+                );
+                assert!(bridge.cloud_client.next_action().is_err());
+                assert_eq!(
+                    bridge.local_client.next_action().unwrap(),
+                    Action::Ack(second)
+                );
+                assert_eq!(
+                    bridge.local_client.next_action().unwrap(),
+                    Action::Ack(third)
+                );
+            }
+
+            #[tokio::test]
+            async fn coalesces_messages_over_the_rate_limit_to_the_latest() {
+                let first = Publish::new("c8y/s/us", QoS::AtLeastOnce, "first");
+                let second = Publish::new("c8y/s/us", QoS::AtLeastOnce, "second");
+                let third = Publish::new("c8y/s/us", QoS::AtLeastOnce, "third");
+                let events = [
+                    inc!(publish(first)),
+                    inc!(publish(second)),
+                    inc!(publish(third)),
+                ];
+                let rule = bridge_rule!("s/us" - "c8y/" + "").with_max_rate(rate_limit(
+                    1,
+                    "200ms",
+                    OnRateLimit::Coalesce,
+                ));
+
+                let bridge = Bridge::default()
+                    .with_local_events(events)
+                    .with_cloud_events([inc!(connack)])
+                    .with_local_rules(vec![rule])
+                    .process_all_events()
+                    .await;
+
+                // The second message is superseded by the third, held back till the limit allows it
+                assert_eq!(
+                    bridge.local_client.next_action().unwrap(),
+                    Action::Ack(second)
+                );
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "first"))
+                );
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "third"))
+                );
+                assert!(bridge.cloud_client.next_action().is_err());
+            }
+
+            #[tokio::test]
+            async fn coalesces_messages_per_topic() {
+                let first = Publish::new("c8y/s/us/child1", QoS::AtLeastOnce, "first");
+                let second = Publish::new("c8y/s/us/child1", QoS::AtLeastOnce, "second");
+                let third = Publish::new("c8y/s/us/child2", QoS::AtLeastOnce, "third");
+                let events = [
+                    inc!(publish(first)),
+                    inc!(publish(second)),
+                    inc!(publish(third)),
+                ];
+                let rule = bridge_rule!("s/us/#" - "c8y/" + "").with_max_rate(rate_limit(
+                    1,
+                    "100ms",
+                    OnRateLimit::Coalesce,
+                ));
+
+                let bridge = Bridge::default()
+                    .with_local_events(events)
+                    .with_cloud_events([inc!(connack)])
+                    .with_local_rules(vec![rule])
+                    .process_all_events()
+                    .await;
+
+                // Messages on distinct topics do not supersede each other
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                assert!(bridge.local_client.next_action().is_err());
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/us/child1", QoS::AtLeastOnce, "first"))
+                );
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/us/child1", QoS::AtLeastOnce, "second"))
+                );
+                assert_eq!(
+                    bridge.cloud_client.next_action().unwrap(),
+                    Action::Publish(Publish::new("s/us/child2", QoS::AtLeastOnce, "third"))
+                );
+            }
+
+            fn rate_limit(messages: u32, per: &str, on_limit: OnRateLimit) -> RateLimit {
+                RateLimit {
+                    messages: NonZeroU32::new(messages).unwrap(),
+                    per: per.parse().unwrap(),
+                    burst: None,
+                    on_limit,
+                }
+            }
+        }
+
         mod holding_publishes_until_subscribed {
             use super::*;
 
@@ -1968,6 +2667,154 @@ mod tests {
             }
         }
 
+        mod store_and_forward {
+            use super::*;
+            use crate::queue::QueueConfig;
+            use tedge_test_utils::fs::TempTedgeDir;
+
+            #[tokio::test]
+            async fn persists_and_acknowledges_messages_while_not_subscribed() {
+                let ttd = TempTedgeDir::new();
+                let mut publisher = store_and_forward_publisher(&ttd).await;
+                let msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, "payload");
+
+                publisher.client.publish("s/us".to_string(), msg.clone());
+
+                // The message is released at the source once persisted, but not forwarded
+                assert_eq!(next_action(&publisher.source).await, Action::Ack(msg));
+                assert!(publisher.target.next_action().is_err());
+
+                // Once subscribed, the persisted message is forwarded to the target
+                publisher.controller.open();
+                assert_eq!(
+                    next_action(&publisher.target).await,
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "payload"))
+                );
+            }
+
+            #[tokio::test]
+            async fn replays_persisted_messages_before_new_ones() {
+                let ttd = TempTedgeDir::new();
+                let mut publisher = store_and_forward_publisher(&ttd).await;
+                let queued = Publish::new("c8y/s/us", QoS::AtLeastOnce, "queued");
+                let fresh = Publish::new("c8y/s/us", QoS::AtLeastOnce, "fresh");
+
+                publisher.client.publish("s/us".to_string(), queued.clone());
+                assert_eq!(next_action(&publisher.source).await, Action::Ack(queued));
+
+                publisher.controller.open();
+                publisher.client.publish("s/us".to_string(), fresh);
+
+                assert_eq!(
+                    next_action(&publisher.target).await,
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "queued"))
+                );
+                assert_eq!(
+                    next_action(&publisher.target).await,
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "fresh"))
+                );
+            }
+
+            #[tokio::test]
+            async fn replays_messages_persisted_before_a_restart() {
+                let ttd = TempTedgeDir::new();
+                let msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, "payload");
+                {
+                    let mut publisher = store_and_forward_publisher(&ttd).await;
+                    publisher.client.publish("s/us".to_string(), msg.clone());
+                    assert_eq!(next_action(&publisher.source).await, Action::Ack(msg));
+                }
+
+                let publisher = store_and_forward_publisher(&ttd).await;
+                publisher.controller.open();
+                assert_eq!(
+                    next_action(&publisher.target).await,
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "payload"))
+                );
+            }
+
+            #[tokio::test]
+            async fn persists_the_extra_messages_produced_by_transformers() {
+                let ttd = TempTedgeDir::new();
+                let mut publisher = store_and_forward_publisher(&ttd).await;
+                let msg = Publish::new("c8y/s/us", QoS::AtLeastOnce, "payload");
+                let forward = Forward {
+                    publish: msg,
+                    qos: QoS::AtLeastOnce,
+                    messages: vec![
+                        ("s/us/extra".to_string(), Bytes::from("extra")),
+                        ("s/us".to_string(), Bytes::from("payload")),
+                    ],
+                };
+
+                publisher.client.forward(forward, OverflowPolicy::default());
+
+                // Only the received message is acknowledged, once both messages are persisted
+                assert!(matches!(
+                    next_action(&publisher.source).await,
+                    Action::Ack(_)
+                ));
+                assert!(publisher.source.next_action().is_err());
+                assert!(publisher.target.next_action().is_err());
+
+                // Once subscribed, both messages are forwarded in order
+                publisher.controller.open();
+                assert_eq!(
+                    next_action(&publisher.target).await,
+                    Action::Publish(Publish::new("s/us/extra", QoS::AtLeastOnce, "extra"))
+                );
+                assert_eq!(
+                    next_action(&publisher.target).await,
+                    Action::Publish(Publish::new("s/us", QoS::AtLeastOnce, "payload"))
+                );
+            }
+
+            struct StoreAndForwardPublisher {
+                client: BridgeAsyncClient<ActionLogger>,
+                controller: SubscriptionGateController,
+                target: ActionLogger,
+                source: ActionLogger,
+                _companion_rx: mpsc::Receiver<Option<(String, Publish)>>,
+            }
+
+            async fn store_and_forward_publisher(ttd: &TempTedgeDir) -> StoreAndForwardPublisher {
+                let target = ActionLogger::default();
+                let source = ActionLogger::default();
+                let (gate, controller) = SubscriptionGate::new();
+                let (tx, companion_rx) = mpsc::channel(10);
+                let (_unused_tx, rx) = mpsc::channel(10);
+                let queue = DiskQueue::open(QueueConfig {
+                    dir: camino::Utf8PathBuf::from_path_buf(ttd.path().join("queue")).unwrap(),
+                    max_messages: 100,
+                    max_size: 1024 * 1024,
+                    max_age: Duration::from_secs(3600),
+                })
+                .await
+                .unwrap();
+                let store = StoreAndForward::new("cloud", queue, source.clone());
+                let client =
+                    BridgeAsyncClient::with_store(target.clone(), tx, rx, gate, Some(store));
+                StoreAndForwardPublisher {
+                    client,
+                    controller,
+                    target,
+                    source,
+                    _companion_rx: companion_rx,
+                }
+            }
+
+            /// Waits for the next action to be logged, giving time to the queue file operations
+            async fn next_action(logger: &ActionLogger) -> Action {
+                for _ in 0..500 {
+                    if let Ok(action) = logger.next_action() {
+                        return action;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                panic!("expected an action to be logged but none appeared");
+            }
+        }
+
         mod forwarding_respects_subscription_state {
             use super::*;
 
@@ -2278,12 +3125,6 @@ mod tests {
             rx_health: mpsc::Receiver<(&'static str, Status)>,
         }
 
-        macro_rules! bridge_rule {
-            ($base:literal -$remove:literal +$add:literal) => {
-                BridgeRule::try_new($base.into(), $remove.into(), $add.into()).unwrap()
-            };
-        }
-
         impl Default for Bridge<FixedEventStream, FixedEventStream, ActionLogger, ActionLogger> {
             fn default() -> Self {
                 Self {
@@ -2325,6 +3166,13 @@ mod tests {
                 }
             }
 
+            fn with_local_rules(self, rules: Vec<BridgeRule>) -> Self {
+                Self {
+                    local_topic_converter: TopicConverter(rules),
+                    ..self
+                }
+            }
+
             fn with_cloud_reconnect_message(self, message: Option<Publish>) -> Self {
                 Self {
                     cloud_reconnect_message: message,
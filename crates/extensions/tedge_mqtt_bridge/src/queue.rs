@@ -0,0 +1,491 @@
+//! Disk-backed store-and-forward queue for the built-in bridge
+//!
+//! While the destination broker of a bridge direction is unreachable, the messages to be
+//! forwarded are written to a queue directory, one file per message, and acknowledged to
+//! the source broker. Once the connection is re-established, the queued messages are
+//! replayed in order, before any newer message.
+//!
+//! Each file is named after the message sequence number, so the queue order can be
+//! rebuilt from the directory listing when the bridge restarts.
+use crate::config_toml::OverflowPolicy;
+use bytes::Bytes;
+use camino::Utf8Path;
+use camino::Utf8PathBuf;
+use rumqttc::Publish;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use tedge_config::TEdgeConfig;
+
+const MESSAGE_EXTENSION: &str = "msg";
+
+/// Size of the fixed part of a queued message: timestamp, QoS, retain flag and topic length
+const HEADER_LEN: usize = 8 + 1 + 1 + 4;
+
+/// The limits applied to the queue of one bridge direction
+#[derive(Clone, Debug)]
+pub struct QueueConfig {
+    /// The directory where the queued messages are stored
+    pub dir: Utf8PathBuf,
+
+    /// The maximum number of queued messages, 0 meaning that no message is stored
+    pub max_messages: usize,
+
+    /// The maximum size in bytes of the queued messages
+    pub max_size: u64,
+
+    /// How long a queued message is kept before being discarded
+    pub max_age: Duration,
+}
+
+impl QueueConfig {
+    /// Reads the queue settings of the `mqtt.bridge.queue` table
+    ///
+    /// Returns `None` if store-and-forward is disabled.
+    pub fn from_tedge_config(tedge_config: &TEdgeConfig, dir: Utf8PathBuf) -> Option<Self> {
+        let queue = &tedge_config.mqtt.bridge.queue;
+        queue.enable.then(|| QueueConfig {
+            dir,
+            max_messages: queue.max_messages as usize,
+            max_size: queue.max_size.into(),
+            max_age: queue.max_age.duration(),
+        })
+    }
+}
+
+/// The result of queueing a message
+#[derive(Debug, PartialEq, Eq)]
+pub enum Queued {
+    /// The message has been persisted, possibly evicting older messages
+    Stored { evicted: usize },
+
+    /// The queue is full and the message has been discarded
+    Discarded,
+}
+
+struct QueueEntry {
+    seq: u64,
+    size: u64,
+    queued_at: SystemTime,
+}
+
+/// A persistent FIFO of MQTT messages with size and age limits
+pub struct DiskQueue {
+    config: QueueConfig,
+    entries: VecDeque<QueueEntry>,
+    next_seq: u64,
+    size: u64,
+    depth: Arc<AtomicUsize>,
+}
+
+impl DiskQueue {
+    /// Opens the queue stored in `config.dir`, creating the directory if needed
+    ///
+    /// Messages left by a previous run are kept and will be replayed first.
+    pub async fn open(config: QueueConfig) -> io::Result<Self> {
+        tokio::fs::create_dir_all(&config.dir).await?;
+
+        let mut entries = Vec::new();
+        let mut read_dir = tokio::fs::read_dir(&config.dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            let Some(path) = Utf8Path::from_path(&path) else {
+                continue;
+            };
+            if path.extension() != Some(MESSAGE_EXTENSION) {
+                continue;
+            }
+            let Some(seq) = path.file_stem().and_then(|stem| stem.parse::<u64>().ok()) else {
+                continue;
+            };
+            match read_message(path).await {
+                Ok((queued_at, _)) => {
+                    let size = entry.metadata().await?.len();
+                    entries.push(QueueEntry {
+                        seq,
+                        size,
+                        queued_at,
+                    });
+                }
+                Err(err) => {
+                    tracing::warn!("Discarding unreadable queued message {path}: {err}");
+                    let _ = tokio::fs::remove_file(path).await;
+                }
+            }
+        }
+        entries.sort_by_key(|entry| entry.seq);
+
+        let next_seq = entries.last().map_or(0, |entry| entry.seq + 1);
+        let size = entries.iter().map(|entry| entry.size).sum();
+        let depth = Arc::new(AtomicUsize::new(entries.len()));
+        Ok(DiskQueue {
+            config,
+            entries: entries.into(),
+            next_seq,
+            size,
+            depth,
+        })
+    }
+
+    /// The number of messages currently queued
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// A handle on the queue depth, updated as messages are queued and replayed
+    pub fn depth(&self) -> Arc<AtomicUsize> {
+        self.depth.clone()
+    }
+
+    /// Persists a message at the end of the queue
+    ///
+    /// When the queue is full, the overflow policy tells whether the oldest messages
+    /// are evicted to make room, or the new message is discarded.
+    pub async fn push(
+        &mut self,
+        publish: &Publish,
+        on_overflow: OverflowPolicy,
+    ) -> io::Result<Queued> {
+        self.expire_old_messages().await?;
+
+        let now = SystemTime::now();
+        let encoded = encode_message(publish, now);
+        let size = encoded.len() as u64;
+        if self.config.max_messages == 0 || size > self.config.max_size {
+            return Ok(Queued::Discarded);
+        }
+
+        let mut evicted = 0;
+        while self.is_full_for(size) {
+            match on_overflow {
+                OverflowPolicy::DropOldest => {
+                    self.remove_front().await?;
+                    evicted += 1;
+                }
+                OverflowPolicy::DropNewest => return Ok(Queued::Discarded),
+            }
+        }
+
+        let seq = self.next_seq;
+        let path = self.message_path(seq);
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, &encoded).await?;
+        tokio::fs::rename(&tmp_path, &path).await?;
+
+        self.next_seq += 1;
+        self.size += size;
+        self.entries.push_back(QueueEntry {
+            seq,
+            size,
+            queued_at: now,
+        });
+        self.update_depth();
+        Ok(Queued::Stored { evicted })
+    }
+
+    /// Reads the oldest queued message, without removing it from the queue
+    ///
+    /// Messages older than the configured maximum age are discarded on the way.
+    pub async fn front(&mut self) -> io::Result<Option<Publish>> {
+        self.expire_old_messages().await?;
+        while let Some(entry) = self.entries.front() {
+            match read_message(&self.message_path(entry.seq)).await {
+                Ok((_, publish)) => return Ok(Some(publish)),
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    tracing::warn!("Discarding unreadable queued message: {err}");
+                    self.remove_front().await?;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Removes the oldest queued message
+    pub async fn remove_front(&mut self) -> io::Result<()> {
+        if let Some(entry) = self.entries.pop_front() {
+            self.size -= entry.size;
+            self.update_depth();
+            match tokio::fs::remove_file(self.message_path(entry.seq)).await {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    async fn expire_old_messages(&mut self) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut expired = 0;
+        while self.entries.front().is_some_and(|entry| {
+            now.duration_since(entry.queued_at)
+                .is_ok_and(|age| age > self.config.max_age)
+        }) {
+            self.remove_front().await?;
+            expired += 1;
+        }
+        if expired > 0 {
+            tracing::warn!(
+                "Discarded {expired} queued messages older than {:?}",
+                self.config.max_age
+            );
+        }
+        Ok(())
+    }
+
+    fn is_full_for(&self, size: u64) -> bool {
+        !self.entries.is_empty()
+            && (self.entries.len() >= self.config.max_messages
+                || self.size + size > self.config.max_size)
+    }
+
+    fn message_path(&self, seq: u64) -> Utf8PathBuf {
+        self.config
+            .dir
+            .join(format!("{seq:020}.{MESSAGE_EXTENSION}"))
+    }
+
+    fn update_depth(&self) {
+        self.depth.store(self.entries.len(), Ordering::Relaxed);
+    }
+}
+
+fn encode_message(publish: &Publish, queued_at: SystemTime) -> Vec<u8> {
+    let timestamp = queued_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let topic = publish.topic.as_bytes();
+    let mut encoded = Vec::with_capacity(HEADER_LEN + topic.len() + publish.payload.len());
+    encoded.extend_from_slice(&timestamp.to_be_bytes());
+    encoded.push(publish.qos as u8);
+    encoded.push(publish.retain as u8);
+    encoded.extend_from_slice(&(topic.len() as u32).to_be_bytes());
+    encoded.extend_from_slice(topic);
+    encoded.extend_from_slice(&publish.payload);
+    encoded
+}
+
+fn decode_message(encoded: &[u8]) -> io::Result<(SystemTime, Publish)> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_owned());
+    if encoded.len() < HEADER_LEN {
+        return Err(invalid("truncated message header"));
+    }
+    let (header, rest) = encoded.split_at(HEADER_LEN);
+    let timestamp = u64::from_be_bytes(header[0..8].try_into().unwrap());
+    let qos = rumqttc::qos(header[8]).map_err(|_| invalid("invalid QoS"))?;
+    let retain = header[9] != 0;
+    let topic_len = u32::from_be_bytes(header[10..14].try_into().unwrap()) as usize;
+    if rest.len() < topic_len {
+        return Err(invalid("truncated message topic"));
+    }
+    let (topic, payload) = rest.split_at(topic_len);
+    let topic = std::str::from_utf8(topic).map_err(|_| invalid("invalid topic"))?;
+
+    let mut publish = Publish::new(topic, qos, Bytes::copy_from_slice(payload));
+    publish.retain = retain;
+    Ok((UNIX_EPOCH + Duration::from_millis(timestamp), publish))
+}
+
+async fn read_message(path: &Utf8Path) -> io::Result<(SystemTime, Publish)> {
+    decode_message(&tokio::fs::read(path).await?)
+}
+
+impl std::fmt::Debug for DiskQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DiskQueue")
+            .field("dir", &self.config.dir)
+            .field("len", &self.entries.len())
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::QoS;
+    use tedge_test_utils::fs::TempTedgeDir;
+
+    fn config(ttd: &TempTedgeDir, max_messages: usize) -> QueueConfig {
+        QueueConfig {
+            dir: Utf8PathBuf::from_path_buf(ttd.path().join("queue")).unwrap(),
+            max_messages,
+            max_size: 1024 * 1024,
+            max_age: Duration::from_secs(3600),
+        }
+    }
+
+    fn message(payload: &'static str) -> Publish {
+        Publish::new("s/us", QoS::AtLeastOnce, payload)
+    }
+
+    async fn drain(queue: &mut DiskQueue) -> Vec<Publish> {
+        let mut messages = Vec::new();
+        while let Some(publish) = queue.front().await.unwrap() {
+            messages.push(publish);
+            queue.remove_front().await.unwrap();
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn replays_messages_in_order() {
+        let ttd = TempTedgeDir::new();
+        let mut queue = DiskQueue::open(config(&ttd, 10)).await.unwrap();
+
+        for payload in ["1", "2", "3"] {
+            queue
+                .push(&message(payload), OverflowPolicy::DropOldest)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(queue.depth().load(Ordering::Relaxed), 3);
+        assert_eq!(
+            drain(&mut queue).await,
+            vec![message("1"), message("2"), message("3")]
+        );
+        assert!(queue.is_empty());
+        assert_eq!(queue.depth().load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn preserves_qos_and_retain_flag() {
+        let ttd = TempTedgeDir::new();
+        let mut queue = DiskQueue::open(config(&ttd, 10)).await.unwrap();
+        let mut retained = Publish::new("a/topic", QoS::ExactlyOnce, vec![0u8, 1, 2]);
+        retained.retain = true;
+
+        queue
+            .push(&retained, OverflowPolicy::DropOldest)
+            .await
+            .unwrap();
+
+        assert_eq!(drain(&mut queue).await, vec![retained]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_evicts_the_oldest_messages_when_full() {
+        let ttd = TempTedgeDir::new();
+        let mut queue = DiskQueue::open(config(&ttd, 2)).await.unwrap();
+
+        for payload in ["1", "2"] {
+            queue
+                .push(&message(payload), OverflowPolicy::DropOldest)
+                .await
+                .unwrap();
+        }
+        let queued = queue
+            .push(&message("3"), OverflowPolicy::DropOldest)
+            .await
+            .unwrap();
+
+        assert_eq!(queued, Queued::Stored { evicted: 1 });
+        assert_eq!(drain(&mut queue).await, vec![message("2"), message("3")]);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_discards_the_new_message_when_full() {
+        let ttd = TempTedgeDir::new();
+        let mut queue = DiskQueue::open(config(&ttd, 2)).await.unwrap();
+
+        for payload in ["1", "2"] {
+            queue
+                .push(&message(payload), OverflowPolicy::DropOldest)
+                .await
+                .unwrap();
+        }
+        let queued = queue
+            .push(&message("3"), OverflowPolicy::DropNewest)
+            .await
+            .unwrap();
+
+        assert_eq!(queued, Queued::Discarded);
+        assert_eq!(drain(&mut queue).await, vec![message("1"), message("2")]);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_stored_when_max_messages_is_zero() {
+        let ttd = TempTedgeDir::new();
+        let mut queue = DiskQueue::open(config(&ttd, 0)).await.unwrap();
+
+        let queued = queue
+            .push(&message("1"), OverflowPolicy::DropOldest)
+            .await
+            .unwrap();
+
+        assert_eq!(queued, Queued::Discarded);
+        assert!(queue.is_empty());
+        assert_eq!(drain(&mut queue).await, vec![]);
+    }
+
+    #[tokio::test]
+    async fn size_limit_is_enforced() {
+        let ttd = TempTedgeDir::new();
+        let mut config = config(&ttd, 10);
+        let message_size = encode_message(&message("1"), SystemTime::now()).len() as u64;
+        config.max_size = 2 * message_size;
+        let mut queue = DiskQueue::open(config).await.unwrap();
+
+        for payload in ["1", "2", "3"] {
+            queue
+                .push(&message(payload), OverflowPolicy::DropOldest)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(drain(&mut queue).await, vec![message("2"), message("3")]);
+    }
+
+    #[tokio::test]
+    async fn expired_messages_are_not_replayed() {
+        let ttd = TempTedgeDir::new();
+        let mut config = config(&ttd, 10);
+        config.max_age = Duration::ZERO;
+        let mut queue = DiskQueue::open(config).await.unwrap();
+
+        queue
+            .push(&message("1"), OverflowPolicy::DropOldest)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        assert_eq!(queue.front().await.unwrap(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn queued_messages_survive_a_restart() {
+        let ttd = TempTedgeDir::new();
+        let mut queue = DiskQueue::open(config(&ttd, 10)).await.unwrap();
+        for payload in ["1", "2"] {
+            queue
+                .push(&message(payload), OverflowPolicy::DropOldest)
+                .await
+                .unwrap();
+        }
+        drop(queue);
+
+        let mut queue = DiskQueue::open(config(&ttd, 10)).await.unwrap();
+        assert_eq!(queue.len(), 2);
+        queue
+            .push(&message("3"), OverflowPolicy::DropOldest)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            drain(&mut queue).await,
+            vec![message("1"), message("2"), message("3")]
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use crate::config_toml::OnRateLimit;
+use crate::config_toml::RateLimit;
+use std::collections::VecDeque;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use tracing::Instrument;
+
+/// A token bucket, refilled at a constant rate up to its burst size
+///
+/// Rather than counting tokens, the bucket tracks the time at which it will be full again,
+/// i.e. the theoretical arrival time of the next message were the messages evenly spaced.
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    /// The time to refill a single token
+    emission_interval: Duration,
+
+    /// How far ahead of an even schedule the messages can be, i.e. the time to refill all the tokens but one
+    burst_tolerance: Duration,
+
+    /// The time at which the bucket is full again, if not already
+    full_at: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub fn new(limit: &RateLimit) -> Self {
+        let messages = limit.messages.get();
+        let burst = limit.burst.map_or(messages, NonZeroU32::get);
+        let emission_interval = limit.per.duration() / messages;
+        TokenBucket {
+            emission_interval,
+            burst_tolerance: emission_interval
+                .checked_mul(burst - 1)
+                .unwrap_or(Duration::MAX),
+            full_at: None,
+        }
+    }
+
+    /// Takes a token if one is available, returning otherwise how long to wait for one
+    pub fn try_acquire(&mut self, now: Instant) -> Result<(), Duration> {
+        let full_at = self.full_at.map_or(now, |full_at| full_at.max(now));
+        let wait = full_at
+            .saturating_duration_since(now)
+            .saturating_sub(self.burst_tolerance);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        self.full_at = Some(full_at + self.emission_interval);
+        Ok(())
+    }
+}
+
+/// The outcome of a message submitted to a [RateLimiter]
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Admission<T> {
+    /// The message is within the limit and can be forwarded
+    Forward(T),
+
+    /// The message exceeds the limit and has to be discarded
+    Discard(T),
+
+    /// The message exceeds the limit and is held back, till it can be forwarded
+    Held {
+        /// The message previously held back for the same topic, superseded by the new one and to be discarded
+        superseded: Option<T>,
+
+        /// The delay after which the held message can be released, unless a release is already scheduled
+        release_in: Option<Duration>,
+    },
+}
+
+/// Limits the rate of the messages forwarded by a bridge rule
+///
+/// Depending on the [OnRateLimit] policy, the messages exceeding the limit are either discarded,
+/// or coalesced: only the latest one of each topic being held back and forwarded once the limit allows it.
+/// The limit itself applies to all the topics of the rule.
+pub(crate) struct RateLimiter<T> {
+    on_limit: OnRateLimit,
+    state: Arc<Mutex<RateLimiterState<T>>>,
+}
+
+struct RateLimiterState<T> {
+    bucket: TokenBucket,
+    held: VecDeque<(String, T)>,
+    release_scheduled: bool,
+}
+
+impl<T> Clone for RateLimiter<T> {
+    fn clone(&self) -> Self {
+        RateLimiter {
+            on_limit: self.on_limit,
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T: Send + 'static> RateLimiter<T> {
+    pub fn new(limit: &RateLimit) -> Self {
+        RateLimiter {
+            on_limit: limit.on_limit,
+            state: Arc::new(Mutex::new(RateLimiterState {
+                bucket: TokenBucket::new(limit),
+                held: VecDeque::new(),
+                release_scheduled: false,
+            })),
+        }
+    }
+
+    pub fn admit(&self, topic: &str, message: T, now: Instant) -> Admission<T> {
+        let mut state = self.state.lock().unwrap();
+        if state.release_scheduled {
+            // A new message must not overtake the ones held back,
+            // superseding the one held back for the same topic if any
+            let superseded = match state.held.iter_mut().find(|(held, _)| held == topic) {
+                Some((_, held)) => Some(std::mem::replace(held, message)),
+                None => {
+                    state.held.push_back((topic.to_owned(), message));
+                    None
+                }
+            };
+            return Admission::Held {
+                superseded,
+                release_in: None,
+            };
+        }
+
+        match state.bucket.try_acquire(now) {
+            Ok(()) => Admission::Forward(message),
+            Err(_) if self.on_limit == OnRateLimit::Drop => Admission::Discard(message),
+            Err(wait) => {
+                state.held.push_back((topic.to_owned(), message));
+                state.release_scheduled = true;
+                Admission::Held {
+                    superseded: None,
+                    release_in: Some(wait),
+                }
+            }
+        }
+    }
+
+    /// Takes the oldest message held back, if the limit now allows it to be forwarded
+    ///
+    /// Returns how long to wait still if the limit is not yet over.
+    pub fn release(&self, now: Instant) -> Result<Option<T>, Duration> {
+        let mut state = self.state.lock().unwrap();
+        if state.held.is_empty() {
+            state.release_scheduled = false;
+            return Ok(None);
+        }
+        state.bucket.try_acquire(now)?;
+        Ok(state.held.pop_front().map(|(_, message)| message))
+    }
+
+    /// Forwards the messages held back, as soon as the limit allows it
+    pub fn schedule_release(&self, delay: Duration, mut forward: impl FnMut(T) + Send + 'static) {
+        let limiter = self.clone();
+        tokio::spawn(
+            async move {
+                let mut delay = delay;
+                loop {
+                    tokio::time::sleep(delay).await;
+                    match limiter.release(Instant::now()) {
+                        Ok(Some(message)) => {
+                            forward(message);
+                            delay = Duration::ZERO;
+                        }
+                        Ok(None) => return,
+                        Err(wait) => delay = wait,
+                    }
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst_of_messages() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(&limit(10, "10s", Some(3), OnRateLimit::Drop));
+
+        assert_eq!(bucket.try_acquire(start), Ok(()));
+        assert_eq!(bucket.try_acquire(start), Ok(()));
+        assert_eq!(bucket.try_acquire(start), Ok(()));
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn bucket_is_refilled_at_a_constant_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(&limit(10, "10s", Some(1), OnRateLimit::Drop));
+
+        assert_eq!(bucket.try_acquire(start), Ok(()));
+        assert_eq!(
+            bucket.try_acquire(start + Duration::from_millis(400)),
+            Err(Duration::from_millis(600))
+        );
+        assert_eq!(bucket.try_acquire(start + Duration::from_secs(1)), Ok(()));
+        assert_eq!(bucket.try_acquire(start + Duration::from_secs(2)), Ok(()));
+    }
+
+    #[test]
+    fn bucket_is_not_refilled_beyond_its_burst_size() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(&limit(1, "1s", Some(2), OnRateLimit::Drop));
+        let later = start + Duration::from_secs(60);
+
+        assert_eq!(bucket.try_acquire(later), Ok(()));
+        assert_eq!(bucket.try_acquire(later), Ok(()));
+        assert!(bucket.try_acquire(later).is_err());
+    }
+
+    #[test]
+    fn burst_defaults_to_the_number_of_messages_per_interval() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(&limit(5, "1m", None, OnRateLimit::Drop));
+
+        for _ in 0..5 {
+            assert_eq!(bucket.try_acquire(start), Ok(()));
+        }
+        assert_eq!(bucket.try_acquire(start), Err(Duration::from_secs(12)));
+    }
+
+    #[test]
+    fn limiter_discards_messages_over_the_limit() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(&limit(1, "1s", None, OnRateLimit::Drop));
+
+        assert_eq!(
+            limiter.admit("topic", "first", start),
+            Admission::Forward("first")
+        );
+        assert_eq!(
+            limiter.admit("topic", "second", start),
+            Admission::Discard("second")
+        );
+        assert_eq!(
+            limiter.admit("topic", "third", start + Duration::from_secs(1)),
+            Admission::Forward("third")
+        );
+    }
+
+    #[test]
+    fn limiter_coalesces_messages_over_the_limit_to_the_latest() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(&limit(1, "1s", None, OnRateLimit::Coalesce));
+
+        assert_eq!(
+            limiter.admit("topic", "first", start),
+            Admission::Forward("first")
+        );
+        assert_eq!(
+            limiter.admit("topic", "second", start),
+            Admission::Held {
+                superseded: None,
+                release_in: Some(Duration::from_secs(1))
+            }
+        );
+        assert_eq!(
+            limiter.admit("topic", "third", start + Duration::from_millis(500)),
+            Admission::Held {
+                superseded: Some("second"),
+                release_in: None
+            }
+        );
+
+        let later = start + Duration::from_millis(800);
+        assert_eq!(limiter.release(later), Err(Duration::from_millis(200)));
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.release(later), Ok(Some("third")));
+        assert_eq!(limiter.release(later), Ok(None));
+        assert_eq!(
+            limiter.admit("topic", "fourth", later),
+            Admission::Held {
+                superseded: None,
+                release_in: Some(Duration::from_secs(1))
+            }
+        );
+    }
+
+    #[test]
+    fn limiter_coalesces_messages_per_topic() {
+        let start = Instant::now();
+        let limiter = RateLimiter::new(&limit(1, "1s", None, OnRateLimit::Coalesce));
+
+        assert_eq!(limiter.admit("a", "a1", start), Admission::Forward("a1"));
+        assert_eq!(
+            limiter.admit("a", "a2", start),
+            Admission::Held {
+                superseded: None,
+                release_in: Some(Duration::from_secs(1))
+            }
+        );
+        assert_eq!(
+            limiter.admit("b", "b1", start),
+            Admission::Held {
+                superseded: None,
+                release_in: None
+            }
+        );
+        assert_eq!(
+            limiter.admit("a", "a3", start),
+            Admission::Held {
+                superseded: Some("a2"),
+                release_in: None
+            }
+        );
+
+        let later = start + Duration::from_secs(1);
+        assert_eq!(limiter.release(later), Ok(Some("a3")));
+        assert_eq!(limiter.release(later), Err(Duration::from_secs(1)));
+        let later = start + Duration::from_secs(2);
+        assert_eq!(limiter.release(later), Ok(Some("b1")));
+        assert_eq!(limiter.release(later), Ok(None));
+    }
+
+    fn limit(messages: u32, per: &str, burst: Option<u32>, on_limit: OnRateLimit) -> RateLimit {
+        RateLimit {
+            messages: NonZeroU32::new(messages).unwrap(),
+            per: per.parse().unwrap(),
+            burst: burst.and_then(NonZeroU32::new),
+            on_limit,
+        }
+    }
+}
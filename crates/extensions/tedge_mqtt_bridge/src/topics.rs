@@ -15,10 +15,17 @@ pub fn matches_ignore_dollar_prefix(topic: &str, filter: &str) -> bool {
 pub struct TopicConverter(pub Vec<BridgeRule>);
 
 impl TopicConverter {
+    #[cfg(test)]
     pub fn convert_topic<'a>(&'a self, topic: &'a str) -> Option<Cow<'a, str>> {
+        self.convert(topic).map(|(converted, _)| converted)
+    }
+
+    /// Converts the topic, also returning the index of the rule that matched it
+    pub fn convert<'a>(&self, topic: &'a str) -> Option<(Cow<'a, str>, usize)> {
         self.0
             .iter()
-            .find_map(|rule| rule.apply(topic))
+            .enumerate()
+            .find_map(|(index, rule)| rule.apply(topic).map(|converted| (converted, index)))
             .or_else(|| {
                 warn!("Failed to convert {topic:?}");
                 None
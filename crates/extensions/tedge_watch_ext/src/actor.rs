@@ -21,6 +21,12 @@ use tokio::process::Child;
 use tokio::process::ChildStderr;
 use tokio::process::ChildStdout;
 use tokio::process::Command;
+use tokio::task::AbortHandle;
+use tokio::task::JoinSet;
+use tokio_serial::DataBits;
+use tokio_serial::Parity;
+use tokio_serial::SerialPortBuilderExt;
+use tokio_serial::StopBits;
 
 type ClientId = u32;
 type Topic = String;
@@ -29,6 +35,10 @@ type CommandLine = String;
 pub struct Watcher {
     /// The collection of commands watched by each client
     processes: HashMap<(ClientId, Topic), (CommandLine, Child)>,
+    /// The serial ports read by each client
+    serial_ports: HashMap<(ClientId, Topic), AbortHandle>,
+    /// The tasks reading the serial ports, removed from `serial_ports` once done
+    serial_readers: JoinSet<()>,
     /// The channels to send events to clients identified by their slot
     event_senders: Vec<DynSender<WatchEvent>>,
     /// Channel used to send requests on behalf of a client
@@ -44,30 +54,23 @@ impl Actor for Watcher {
     }
 
     async fn run(mut self) -> Result<(), RuntimeError> {
-        while let Some((client, request)) = self.request_receiver.recv().await {
-            let topic = match &request {
-                WatchRequest::WatchFile { topic, .. }
-                | WatchRequest::WatchCommand { topic, .. }
-                | WatchRequest::UnWatch { topic } => topic.clone(),
-            };
-            let result = match request {
-                WatchRequest::WatchFile { topic, file } => {
-                    self.watch_file(client, topic, file).await
+        loop {
+            tokio::select! {
+                Some(reader) = self.serial_readers.join_next_with_id() => {
+                    let id = match reader {
+                        Ok((id, ())) => id,
+                        Err(err) => err.id(),
+                    };
+                    self.serial_ports.retain(|_, reader| reader.id() != id);
+                }
+                request = self.request_receiver.recv() => {
+                    let Some((client, request)) = request else {
+                        return Ok(());
+                    };
+                    self.handle_request(client, request).await?;
                 }
-                WatchRequest::WatchCommand {
-                    topic,
-                    command,
-                    cwd,
-                } => self.watch_command(client, topic, command, cwd).await,
-                WatchRequest::UnWatch { topic } => self.unwatch(client, topic).await,
-            };
-            if let Err(error) = result {
-                self.client_sender(client)
-                    .send(WatchEvent::Error { topic, error })
-                    .await?;
             }
         }
-        Ok(())
     }
 }
 
@@ -79,12 +82,48 @@ impl Watcher {
     ) -> Self {
         Watcher {
             processes: HashMap::new(),
+            serial_ports: HashMap::new(),
+            serial_readers: JoinSet::new(),
             event_senders,
             request_sender,
             request_receiver,
         }
     }
 
+    async fn handle_request(
+        &mut self,
+        client: ClientId,
+        request: WatchRequest,
+    ) -> Result<(), RuntimeError> {
+        let topic = match &request {
+            WatchRequest::WatchFile { topic, .. }
+            | WatchRequest::WatchCommand { topic, .. }
+            | WatchRequest::WatchSerial { topic, .. }
+            | WatchRequest::UnWatch { topic } => topic.clone(),
+        };
+        let result = match request {
+            WatchRequest::WatchFile { topic, file } => self.watch_file(client, topic, file).await,
+            WatchRequest::WatchCommand {
+                topic,
+                command,
+                cwd,
+            } => self.watch_command(client, topic, command, cwd).await,
+            WatchRequest::WatchSerial {
+                topic,
+                device,
+                baud_rate,
+                parity,
+            } => self.watch_serial(client, topic, device, baud_rate, parity),
+            WatchRequest::UnWatch { topic } => self.unwatch(client, topic).await,
+        };
+        if let Err(error) = result {
+            self.client_sender(client)
+                .send(WatchEvent::Error { topic, error })
+                .await?;
+        }
+        Ok(())
+    }
+
     pub async fn watch_file(
         &mut self,
         client: u32,
@@ -117,6 +156,59 @@ impl Watcher {
         Ok(())
     }
 
+    /// Read the lines received on a serial port, configured with the given baud rate and parity
+    pub fn watch_serial(
+        &mut self,
+        client: u32,
+        topic: Topic,
+        device: Utf8PathBuf,
+        baud_rate: u32,
+        parity: Parity,
+    ) -> Result<(), WatchError> {
+        let port = tokio_serial::new(device.as_str(), baud_rate)
+            .data_bits(DataBits::Eight)
+            .parity(parity)
+            .stop_bits(StopBits::One)
+            .open_native_async()
+            .map_err(|err| WatchError::SerialPortFailed {
+                device: device.to_string(),
+                error: err.to_string(),
+            })?;
+
+        let mut event_sender = self.client_sender(client);
+        let reader_topic = topic.clone();
+        let reader = self.serial_readers.spawn(async move {
+            let mut reader = BufReader::new(port);
+            let mut bytes = Vec::new();
+            while let Ok(n) = reader.read_until(b'\n', &mut bytes).await {
+                if n == 0 {
+                    break;
+                }
+                let event = match String::from_utf8(std::mem::take(&mut bytes)) {
+                    Ok(line) => WatchEvent::StdoutLine {
+                        topic: reader_topic.clone(),
+                        line: line.trim_end_matches(['\r', '\n']).to_string(),
+                    },
+                    Err(err) => WatchEvent::StderrLine {
+                        topic: reader_topic.clone(),
+                        line: format!("Skipping invalid line read from {device}: {err}"),
+                    },
+                };
+                let _ = event_sender.send(event).await;
+            }
+            let _ = event_sender
+                .send(WatchEvent::EndOfStream {
+                    topic: reader_topic,
+                })
+                .await;
+        });
+
+        if let Some(previous) = self.serial_ports.insert((client, topic), reader) {
+            previous.abort();
+        }
+        Ok(())
+    }
+
     fn client_sender(&self, client: u32) -> DynSender<WatchEvent> {
         self.event_senders
             .get(client as usize)
@@ -168,6 +260,9 @@ impl Watcher {
     }
 
     pub async fn unwatch(&mut self, client: u32, topic: Topic) -> Result<(), WatchError> {
+        if let Some(reader) = self.serial_ports.remove(&(client, topic.clone())) {
+            reader.abort();
+        }
         if let Some((command, mut child)) = self.processes.remove(&(client, topic)) {
             if let Ok(Some(status)) = child.try_wait() {
                 return check_status(&command, status);
@@ -26,6 +26,13 @@ pub enum WatchRequest {
         command: String,
         cwd: Utf8PathBuf,
     },
+    /// Read a serial port line by line
+    WatchSerial {
+        topic: String,
+        device: Utf8PathBuf,
+        baud_rate: u32,
+        parity: Parity,
+    },
     UnWatch {
         topic: String,
     },
@@ -55,9 +62,13 @@ pub enum WatchError {
 
     #[error("Failed to kill `{command}`: {error}")]
     TerminationFailed { command: String, error: String },
+
+    #[error("Failed to open serial port {device}: {error}")]
+    SerialPortFailed { device: String, error: String },
 }
 
 pub use actor::command_output;
+pub use tokio_serial::Parity;
 
 pub struct WatchActorBuilder {
     request_box: SimpleMessageBoxBuilder<(u32, WatchRequest), NoMessage>,
@@ -1,4 +1,5 @@
 use crate::WatchActorBuilder;
+use crate::WatchError;
 use crate::WatchEvent;
 use crate::WatchRequest;
 use camino::Utf8PathBuf;
@@ -8,6 +9,10 @@ use tedge_actors::MessageReceiver;
 use tedge_actors::Sender;
 use tedge_actors::SimpleMessageBox;
 use tedge_actors::SimpleMessageBoxBuilder;
+use tokio::io::AsyncWriteExt;
+use tokio_serial::Parity;
+use tokio_serial::SerialPort;
+use tokio_serial::SerialStream;
 
 #[tokio::test]
 async fn reading_process_stdout() {
@@ -89,6 +94,116 @@ async fn serving_independent_clients() {
     assert_eq!(&topic, "seq");
 }
 
+#[tokio::test]
+async fn reading_serial_port_lines() {
+    let mut actor = launch_watcher(1).pop().unwrap();
+
+    // A pseudo terminal stands for the serial device, the test writing on the other end
+    let (mut remote, port) = SerialStream::pair().unwrap();
+    let device = Utf8PathBuf::from(port.name().unwrap());
+    drop(port);
+
+    actor
+        .send(WatchRequest::WatchSerial {
+            topic: "gps".to_string(),
+            device,
+            baud_rate: 115200,
+            parity: Parity::None,
+        })
+        .await
+        .unwrap();
+
+    // Lines sent before the port is opened by the watcher might be lost
+    let msg = loop {
+        remote.write_all(b"$GPGGA,123519\r\n").await.unwrap();
+        if let Ok(msg) =
+            tokio::time::timeout(std::time::Duration::from_millis(100), actor.recv()).await
+        {
+            break msg;
+        }
+    };
+    let Some(WatchEvent::StdoutLine { topic, line }) = msg else {
+        panic!("Expecting line from serial port, got: {:?}", msg);
+    };
+    assert_eq!(&topic, "gps");
+    assert_eq!(line, "$GPGGA,123519");
+}
+
+#[tokio::test]
+async fn skipping_invalid_serial_port_lines() {
+    let mut actor = launch_watcher(1).pop().unwrap();
+    let (mut remote, port) = SerialStream::pair().unwrap();
+    let device = Utf8PathBuf::from(port.name().unwrap());
+    drop(port);
+
+    actor
+        .send(WatchRequest::WatchSerial {
+            topic: "gps".to_string(),
+            device,
+            baud_rate: 115200,
+            parity: Parity::None,
+        })
+        .await
+        .unwrap();
+
+    // Wait for the port to be opened by the watcher
+    loop {
+        remote.write_all(b"ready\r\n").await.unwrap();
+        if tokio::time::timeout(std::time::Duration::from_millis(100), actor.recv())
+            .await
+            .is_ok()
+        {
+            break;
+        }
+    }
+    while let Ok(Some(WatchEvent::StdoutLine { .. })) =
+        tokio::time::timeout(std::time::Duration::from_millis(100), actor.recv()).await
+    {}
+
+    remote
+        .write_all(b"\xff\xfe\r\n$GPGGA,123519\r\n")
+        .await
+        .unwrap();
+
+    let msg = actor.recv().await;
+    let Some(WatchEvent::StderrLine { topic, .. }) = msg else {
+        panic!("Expecting invalid line to be reported, got: {:?}", msg);
+    };
+    assert_eq!(&topic, "gps");
+    let msg = actor.recv().await;
+    let Some(WatchEvent::StdoutLine { topic, line }) = msg else {
+        panic!("Expecting line from serial port, got: {:?}", msg);
+    };
+    assert_eq!(&topic, "gps");
+    assert_eq!(line, "$GPGGA,123519");
+}
+
+#[tokio::test]
+async fn reporting_serial_port_errors() {
+    let mut actor = launch_watcher(1).pop().unwrap();
+
+    actor
+        .send(WatchRequest::WatchSerial {
+            topic: "gps".to_string(),
+            device: Utf8PathBuf::from("/dev/does-not-exist"),
+            baud_rate: 9600,
+            parity: Parity::Even,
+        })
+        .await
+        .unwrap();
+
+    let msg = actor.recv().await;
+    let Some(WatchEvent::Error {
+        topic,
+        error: WatchError::SerialPortFailed { device, .. },
+    }) = msg
+    else {
+        panic!("Expecting serial port error, got: {:?}", msg);
+    };
+    assert_eq!(&topic, "gps");
+    assert_eq!(device, "/dev/does-not-exist");
+}
+
 fn launch_watcher(client_count: u32) -> Vec<SimpleMessageBox<WatchEvent, WatchRequest>> {
     let mut watcher = WatchActorBuilder::new();
     let clients = (0..=client_count)